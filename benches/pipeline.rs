@@ -0,0 +1,153 @@
+//! Benchmarks of the parsing of the mails and of the mail broker, to validate
+//! the refactorings of the hot paths.
+//!
+//! Run them with `cargo bench --features bench`. The modules are compiled from
+//! the sources of the binary, the crate having no library.
+
+// Only a part of the modules is used here, and their tests are not built, the
+// benchmarks being compiled with `cfg(test)` but without the test harness
+#![allow(dead_code, unused_imports)]
+
+use std::time::Duration;
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    task,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use ulid::Ulid;
+
+use crate::{
+    encoding::decode_string,
+    error::MailcatcherError,
+    mail::{
+        broker::{MailEvt, MailTank, Removal},
+        store::MemoryStore,
+        Mail,
+    },
+};
+
+/// Decode encoded string
+#[path = "../src/encoding.rs"]
+mod encoding;
+/// Errors of the crate
+#[path = "../src/error.rs"]
+mod error;
+/// Mail representation/gestion
+#[path = "../src/mail/mod.rs"]
+mod mail;
+
+/// Helpers used by the tests of the modules, outside of the test functions
+#[cfg(test)]
+mod test {
+    /// Result type of the tests, accepting any error
+    pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// The logs are not displayed while benchmarking
+    pub const fn log_init() {}
+}
+
+/// Result type commonly used in this crate
+type Result<T> = std::result::Result<T, MailcatcherError>;
+
+/// Type alias for stream channel
+type Channel<T> = (Sender<T>, Receiver<T>);
+
+/// Multipart mail, with encoded headers, a quoted-printable part and an
+/// attachment
+const MAIL: &str = "From: =?UTF-8?Q?Andr=C3=A9?= <andre@example.org>\r
+To: Bob <bob@example.net>\r
+Subject: =?UTF-8?B?UmFwcG9ydCBkZSBsYSBzZW1haW5lIOKAlCDDqXTDqQ==?=\r
+Date: Tue, 16 Feb 2021 10:00:00 +0100\r
+Message-ID: <report-42@example.org>\r
+MIME-Version: 1.0\r
+Content-Type: multipart/mixed; boundary=\"outer\"\r
+\r
+--outer\r
+Content-Type: multipart/alternative; boundary=\"inner\"\r
+\r
+--inner\r
+Content-Type: text/plain; charset=utf-8\r
+Content-Transfer-Encoding: quoted-printable\r
+\r
+Voici le rapport de la semaine, avec les chiffres de l'=C3=A9t=C3=A9.\r
+--inner\r
+Content-Type: text/html; charset=utf-8\r
+\r
+<p>Voici le <b>rapport</b> de la semaine.</p>\r
+--inner--\r
+--outer\r
+Content-Type: application/pdf; name=\"report.pdf\"\r
+Content-Disposition: attachment; filename=\"report.pdf\"\r
+Content-Transfer-Encoding: base64\r
+\r
+JVBERi0xLjQKJcOkw7zDtsOfCjIgMCBvYmoKPDwvTGVuZ3RoIDMgMCBSL0ZpbHRlci9GbGF0ZURl\r
+--outer--\r
+";
+
+/// Parsing of a whole mail
+fn mail_new(c: &mut Criterion) {
+    let to: Vec<String> = vec!["bob@example.net".to_owned()];
+    let _ = c.bench_function("Mail::new", |b| {
+        b.iter(|| Mail::new("andre@example.org", &to, MAIL));
+    });
+}
+
+/// Decoding of the encoded words of the headers
+fn header_decoding(c: &mut Criterion) {
+    let _ = c.bench_function("decode_string", |b| {
+        b.iter(|| {
+            decode_string(
+                "=?UTF-8?B?UmFwcG9ydCBkZSBsYSBzZW1haW5lIOKAlCDDqXTDqQ==?= \
+                 =?ISO-8859-1?Q?pour_l'=E9quipe?=",
+            )
+        });
+    });
+}
+
+/// Round trip through the event loop of the mail broker: insertion of a mail,
+/// retrieval of it, then removal
+fn broker_loop(c: &mut Criterion) {
+    let (sender, receiver): Channel<MailEvt> = channel::unbounded();
+    let broker: MailTank = MailTank::new(receiver, Box::new(MemoryStore::default()), None);
+    let _broker_task = task::spawn(broker.process());
+
+    let to: Vec<String> = vec!["bob@example.net".to_owned()];
+    let _ = c.bench_function("MailTank::process", |b| {
+        b.iter_batched(
+            || Mail::new("andre@example.org", &to, MAIL),
+            |mail| {
+                task::block_on(async {
+                    let id: Ulid = mail.get_id();
+                    let (s, r): Channel<Option<Mail>> = channel::bounded(1);
+                    let (s_removal, r_removal): Channel<Removal> = channel::bounded(1);
+                    sender
+                        .send(MailEvt::NewMail(Box::new(mail)))
+                        .await
+                        .expect("broker running");
+                    sender
+                        .send(MailEvt::GetMail(s, id))
+                        .await
+                        .expect("broker running");
+                    let _mail = r.recv().await.expect("mail retrieved");
+                    // The tank is kept at the same size
+                    sender
+                        .send(MailEvt::Remove(s_removal, id))
+                        .await
+                        .expect("broker running");
+                    r_removal.recv().await.expect("mail removed")
+                })
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    sender.close();
+}
+
+criterion_group! {
+    name = benches;
+    // The caches and the allocator are warmed up before the measures
+    config = Criterion::default().warm_up_time(Duration::from_secs(2));
+    targets = mail_new, header_decoding, broker_loop
+}
+criterion_main!(benches);
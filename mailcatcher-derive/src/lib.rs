@@ -8,26 +8,84 @@ use std::{env, path::Path};
 use quote::quote;
 use syn::{Data, DeriveInput, Fields, Ident, Lit, Meta};
 
+// Names of the sub-folders of {folder}, like the themes of the assets
+fn sub_folders(folder_path: &str) -> Vec<String> {
+    let mut folders: Vec<String> = std::fs::read_dir(folder_path)
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_str().unwrap().to_owned())
+        .collect();
+    folders.sort();
+    folders
+}
+
 // Release build
 //
 // Include files contents inside the generated binary,
 // compressed with deflate
 #[cfg(not(debug_assertions))]
 fn generate_assets(ident: &Ident, folder_path: String) -> TokenStream {
+    let mut match_values = Vec::new();
+    let mut modified_values = Vec::new();
+    embed_folder(&folder_path, "", &mut match_values, &mut modified_values);
+    let folders = sub_folders(&folder_path);
+
+    {
+        quote! {
+            impl #ident {
+                pub fn get(file_path: &str) -> Option<std::borrow::Cow<'static, [u8]>> {
+                    match file_path {
+                        #(#match_values)*
+                        _ => None
+                    }
+                }
+
+                pub fn modif(file_path: &str) -> Option<std::borrow::Cow<'static, str>> {
+                    match file_path {
+                        #(#modified_values)*
+                        _ => None
+                    }
+                }
+
+                pub fn folders() -> &'static [&'static str] {
+                    &[#(#folders),*]
+                }
+            }
+        }
+    }
+    .into()
+}
+
+// Add the files of {folder} and of its sub-folders, named after their path
+// relative to {folder} like "dark/theme.css"
+#[cfg(not(debug_assertions))]
+fn embed_folder(
+    folder_path: &str,
+    prefix: &str,
+    match_values: &mut Vec<proc_macro2::TokenStream>,
+    modified_values: &mut Vec<proc_macro2::TokenStream>,
+) {
     use chrono::{DateTime, Utc};
     use miniz_oxide::deflate::compress_to_vec;
     use proc_macro2::Span;
     use std::{fs, time::SystemTime};
     use syn::LitByteStr;
 
-    let mut match_values = Vec::new();
-    let mut modified_values = Vec::new();
-
     // For each file in {folder}
-    for entry in fs::read_dir(folder_path.clone()).unwrap() {
+    for entry in fs::read_dir(folder_path).unwrap() {
         if let Ok(entry) = entry {
-            // Only the filename
-            let rel_path = entry.file_name().to_str().unwrap().to_owned();
+            // Only the filename, after the sub-folders
+            let rel_path = format!("{}{}", prefix, entry.file_name().to_str().unwrap());
+            if entry.path().is_dir() {
+                embed_folder(
+                    entry.path().to_str().unwrap(),
+                    &format!("{}/", rel_path),
+                    match_values,
+                    modified_values,
+                );
+                continue;
+            }
             // Filename with absolute path
             let full_path = std::fs::canonicalize(entry.path())
                 .unwrap()
@@ -67,27 +125,6 @@ fn generate_assets(ident: &Ident, folder_path: String) -> TokenStream {
             });
         }
     }
-
-    {
-        quote! {
-            impl #ident {
-                pub fn get(file_path: &str) -> Option<std::borrow::Cow<'static, [u8]>> {
-                    match file_path {
-                        #(#match_values)*
-                        _ => None
-                    }
-                }
-
-                pub fn modif(file_path: &str) -> Option<std::borrow::Cow<'static, str>> {
-                    match file_path {
-                        #(#modified_values)*
-                        _ => None
-                    }
-                }
-            }
-        }
-    }
-    .into()
 }
 
 // Debug build
@@ -96,6 +133,8 @@ fn generate_assets(ident: &Ident, folder_path: String) -> TokenStream {
 // content is not stored but reread everytime
 #[cfg(debug_assertions)]
 fn generate_assets(ident: &Ident, folder_path: String) -> TokenStream {
+    let folders = sub_folders(&folder_path);
+
     {
         quote! {
             impl #ident {
@@ -131,6 +170,10 @@ fn generate_assets(ident: &Ident, folder_path: String) -> TokenStream {
                         Err(_e) => None,
                     }
                 }
+
+                pub fn folders() -> &'static [&'static str] {
+                    &[#(#folders),*]
+                }
             }
         }
     }
@@ -0,0 +1,126 @@
+use tide::{
+    http::Method,
+    prelude::{json, Deserialize},
+};
+
+use crate::{
+    error::MailcatcherError,
+    utils::{local_request, Output},
+};
+
+/// Information about the running catcher, as given by the `/api/info` route,
+/// with the fields printed as text
+#[derive(Debug, Deserialize)]
+struct Info {
+    /// Version of the catcher
+    version: String,
+    /// SMTP side
+    smtp: Endpoint,
+    /// HTTP side
+    http: Endpoint,
+}
+
+/// Where a server listens on
+#[derive(Debug, Deserialize)]
+struct Endpoint {
+    /// Addresses listened on
+    addresses: Vec<String>,
+}
+
+/// Check that an instance is listening on the HTTP `port`, like in a CI step
+/// waiting for it, with the `GET /api/info` route
+///
+/// The exit code is not 0 if no instance replied. With the JSON `output`, the
+/// document of the route is printed with `running`, or the failure.
+#[allow(clippy::print_stdout)]
+pub async fn check(port: u16, output: Output) -> crate::Result<()> {
+    let info: crate::Result<serde_json::Value> =
+        match local_request(port, Method::Get, "/api/info", None).await {
+            Ok(mut response) => response
+                .body_json()
+                .await
+                .map_err(|e| MailcatcherError::http(e.to_string())),
+            Err(e) => Err(e),
+        };
+
+    println!("{}", render(&info, output));
+    info.map(|_| ())
+}
+
+/// Outcome of the check printed in the `output` format
+fn render(info: &crate::Result<serde_json::Value>, output: Output) -> String {
+    match *info {
+        Ok(ref document) => match output {
+            Output::Text => serde_json::from_value(document.clone()).map_or_else(
+                |_| "MailCatcher running".to_owned(),
+                |running: Info| {
+                    format!(
+                        "MailCatcher {} running, SMTP on {}, HTTP on {}",
+                        running.version,
+                        running.smtp.addresses.join(" "),
+                        running.http.addresses.join(" ")
+                    )
+                },
+            ),
+            Output::Json => {
+                let mut running: serde_json::Value = document.clone();
+                if let Some(fields) = running.as_object_mut() {
+                    let _ = fields.insert("running".to_owned(), json!(true));
+                }
+                running.to_string()
+            }
+        },
+        Err(ref e) => match output {
+            Output::Text => format!("MailCatcher not running: {}", e),
+            Output::Json => json!({
+                "running": false,
+                "error": e.to_string(),
+            })
+            .to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn rendered_check() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let info: crate::Result<serde_json::Value> = Ok(json!({
+            "version": "0.1.0",
+            "started": 1_605_000_000,
+            "smtp": {"port": 1025, "addresses": ["127.0.0.1:1025"], "unavailable": []},
+            "smtp_extra": [],
+            "smtp_paused": false,
+            "http": {
+                "port": 1080,
+                "addresses": ["127.0.0.1:1080", "[::1]:1080"],
+                "unavailable": [],
+            },
+        }));
+        assert_eq!(
+            render(&info, Output::Text),
+            "MailCatcher 0.1.0 running, SMTP on 127.0.0.1:1025, HTTP on 127.0.0.1:1080 [::1]:1080"
+        );
+        let document: serde_json::Value = serde_json::from_str(&render(&info, Output::Json))?;
+        assert_eq!(document.get("running"), Some(&json!(true)));
+        assert_eq!(document.get("version"), Some(&json!("0.1.0")));
+
+        let down: crate::Result<serde_json::Value> =
+            Err(MailcatcherError::http("Connection refused"));
+        assert_eq!(
+            render(&down, Output::Text),
+            "MailCatcher not running: HTTP: Connection refused"
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&render(&down, Output::Json))?,
+            json!({"running": false, "error": "HTTP: Connection refused"})
+        );
+
+        Ok(())
+    }
+}
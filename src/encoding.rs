@@ -1,6 +1,10 @@
-use encoding::{label::encoding_from_whatwg_label, DecoderTrap};
+use std::{borrow::Cow, convert::TryFrom};
+
+use encoding::{label::encoding_from_whatwg_label, DecoderTrap, EncodingRef, RawDecoder};
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
+use thiserror::Error;
+use tide::prelude::Serialize;
 
 lazy_static! {
     static ref RE_REMOVE_SPACE: Regex =
@@ -10,6 +14,11 @@ lazy_static! {
         Regex::new(r"(=\?(?P<charset>[^?]+)\?(?P<encoding>.)\?(?P<encoded_text>.+?)\?=)").expect("re general");
     static ref RE_QUOTE: regex::bytes::Regex =
         regex::bytes::Regex::new("\x3D([\x30-\x39\x41-\x46]{2})").expect("re quote");
+    static ref RE_HTML_HIDDEN: Regex =
+        Regex::new(r"(?s)<[sS][cC][rR][iI][pP][tT][ \t\r\n>].*?</[sS][cC][rR][iI][pP][tT][ \t\r\n]*>|<[sS][tT][yY][lL][eE][ \t\r\n>].*?</[sS][tT][yY][lL][eE][ \t\r\n]*>|<[hH][eE][aA][dD][ \t\r\n>].*?</[hH][eE][aA][dD][ \t\r\n]*>|<!--.*?-->").expect("re html hidden");
+    static ref RE_HTML_TAG: Regex = Regex::new(r"(?s)<[^>]*>").expect("re html tag");
+    static ref RE_HTML_ENTITY: Regex =
+        Regex::new(r"&(?P<entity>#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z]+);").expect("re html entity");
     static ref HEX_BYTE: fnv::FnvHashMap<String, u8> = {
         let mut m: fnv::FnvHashMap<String, u8> = fnv::FnvHashMap::default();
         // Insert with 0 leading
@@ -22,6 +31,10 @@ lazy_static! {
 
 /// decode base64/quote encoded string and remove space separator between
 /// encoded string to literal values
+///
+/// The adjacent encoded strings of a charset are decoded together, the
+/// mailers splitting them in the middle of a character, or of an ISO-2022-JP
+/// escape sequence.
 #[allow(clippy::indexing_slicing)]
 pub fn decode_string(string: &str) -> String {
     let mut string: String = string.to_owned();
@@ -33,37 +46,259 @@ pub fn decode_string(string: &str) -> String {
             })
             .to_string();
     }
-    RE_GENERAL.replace_all(&string, rfc2047_decode).to_string()
+
+    let mut decoded: String = String::with_capacity(string.len());
+    // Charset and bytes of the adjacent encoded strings not decoded yet
+    let mut run: Option<(EncodingRef, Vec<u8>)> = None;
+    let mut end: usize = 0;
+    for (word, caps) in RE_GENERAL
+        .captures_iter(&string)
+        .filter_map(|caps| Some((caps.get(0)?, caps)))
+    {
+        let between: &str = string.get(end..word.start()).unwrap_or_default();
+        end = word.end();
+        let encoded: Option<(EncodingRef, Vec<u8>)> =
+            charset_encoding(&caps["charset"]).zip(word_bytes(&caps));
+        match (run.as_mut(), encoded) {
+            (Some(&mut (dec, ref mut bytes)), Some((next, more)))
+                if between.is_empty() && dec.name() == next.name() =>
+            {
+                bytes.extend(more);
+            }
+            (_, encoded) => {
+                if let Some((dec, bytes)) = run.take() {
+                    decoded.push_str(&decode_run(dec, &bytes));
+                }
+                decoded.push_str(between);
+                match encoded {
+                    Some(encoded) => run = Some(encoded),
+                    // Unknown charset or encoding, kept as it was received
+                    None => decoded.push_str(word.as_str()),
+                }
+            }
+        }
+    }
+    if let Some((dec, bytes)) = run {
+        decoded.push_str(&decode_run(dec, &bytes));
+    }
+    decoded.push_str(string.get(end..).unwrap_or_default());
+    decoded
 }
 
-/// Decode base64/quote encoded string
+/// Bytes of an encoded string, if its encoding is known (only support "b" or
+/// "q")
 #[allow(clippy::indexing_slicing)]
-fn rfc2047_decode(caps: &Captures) -> String {
-    // Search if the encoding is known
-    if let Some(dec) = encoding_from_whatwg_label(&caps["charset"].to_lowercase()) {
-        // check charset used (only support "b" or "q")
-        let text: Vec<u8> = match caps["encoding"].to_lowercase().as_str() {
-            // Base 64
-            "b" => base64::decode(&caps["encoded_text"])
+fn word_bytes(caps: &Captures) -> Option<Vec<u8>> {
+    match caps["encoding"].to_lowercase().as_str() {
+        // Base 64
+        "b" => Some(
+            base64::decode(&caps["encoded_text"])
                 .unwrap_or_else(|_| b"/!\\ Invalid Base64 encoding /!\\".to_vec()),
-            // Quote
-            "q" => {
-                let text: String = caps["encoded_text"].replace("_", "\u{20}");
-                RE_QUOTE.replace_all(text.as_bytes(), replace_byte).to_vec()
-            }
-            // Anything else
-            #[allow(clippy::panic)]
-            _ => panic!("Illegal encoding"),
+        ),
+        // Quote
+        "q" => {
+            let text: String = caps["encoded_text"].replace("_", "\u{20}");
+            Some(RE_QUOTE.replace_all(text.as_bytes(), replace_byte).to_vec())
+        }
+        // Anything else
+        _ => None,
+    }
+}
+
+/// Decode the bytes of adjacent encoded strings, the invalid ones being
+/// replaced
+fn decode_run(dec: EncodingRef, bytes: &[u8]) -> String {
+    dec.decode(bytes, DecoderTrap::Replace).unwrap_or_default()
+}
+
+/// Encoding of a charset, from its WHATWG label or from the other names the
+/// mailers give it, like `euc-cn` or `iso-2022-jp-2`
+///
+/// The RFC 2231 language, like in `iso-2022-jp*ja`, is ignored.
+pub fn charset_encoding(charset: &str) -> Option<EncodingRef> {
+    let label: String = charset
+        .split('*')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    encoding_from_whatwg_label(&label).or_else(|| match label.as_str() {
+        // Supersets of GB2312
+        "euc-cn" | "x-euc-cn" | "cp936" | "ms936" | "windows-936" => {
+            encoding_from_whatwg_label("gbk")
+        }
+        // Extensions of ISO-2022-JP, their base set being the most used
+        "iso-2022-jp-1" | "iso-2022-jp-2" | "iso-2022-jp-3" | "iso-2022-jp-ms" => {
+            encoding_from_whatwg_label("iso-2022-jp")
+        }
+        _ => None,
+    })
+}
+
+/// Check if a charset is known, and is not UTF-8
+pub fn legacy_charset(charset: &str) -> bool {
+    charset_encoding(charset).map_or(false, |dec| dec.name() != "utf-8")
+}
+
+/// Decode a line received as is: in UTF-8, or else in Latin-1 like the 8-bit
+/// contents of the older mailers, so no byte is lost
+///
+/// The line is only borrowed when it is valid UTF-8.
+pub fn decode_8bit(line: &[u8]) -> Cow<'_, str> {
+    std::str::from_utf8(line).map_or_else(
+        |_| Cow::Owned(line.iter().map(|&byte| char::from(byte)).collect()),
+        Cow::Borrowed,
+    )
+}
+
+/// Give back the bytes of a content received as is, the inverse of
+/// `decode_8bit` line by line: the lines without any character beyond Latin-1
+/// are taken as kept in Latin-1, the others as valid UTF-8
+pub fn encode_8bit(text: &str) -> Vec<u8> {
+    text.split_inclusive('\n')
+        .flat_map(|line| {
+            line.chars()
+                .map(u8::try_from)
+                .collect::<Result<Vec<u8>, _>>()
+                .unwrap_or_else(|_| line.as_bytes().to_vec())
+        })
+        .collect()
+}
+
+/// Decode a content from its declared charset, the characters that cannot be
+/// decoded being replaced
+///
+/// Without a charset, or with an unknown one, the content is decoded like an
+/// 8-bit line.
+pub fn decode_charset(content: &[u8], charset: Option<&str>) -> String {
+    charset
+        .and_then(charset_encoding)
+        .and_then(|dec| dec.decode(content, DecoderTrap::Replace).ok())
+        .unwrap_or_else(|| decode_8bit(content).into_owned())
+}
+
+/// A content that cannot be decoded from its declared charset
+#[derive(Clone, Debug, Error, PartialEq, Eq, Serialize)]
+#[error("{invalid} invalid sequence(s) in the {charset} content, the first at byte {offset}")]
+pub struct CharsetError {
+    /// Charset declared by the content
+    pub charset: String,
+    /// Offset of the first invalid sequence in the content, in bytes
+    pub offset: usize,
+    /// Bytes of the first invalid sequence, in hexadecimal
+    pub bytes: String,
+    /// Number of invalid sequences in the content
+    pub invalid: usize,
+}
+
+/// Decode a content from its declared charset, reporting the characters that
+/// cannot be decoded instead of replacing them
+///
+/// Without a charset, or with an unknown one, the content is decoded like an
+/// 8-bit line, that never fails.
+pub fn decode_charset_strict(
+    content: &[u8],
+    charset: Option<&str>,
+) -> Result<String, CharsetError> {
+    let (name, dec): (&str, EncodingRef) =
+        match charset.and_then(|name| Some((name, charset_encoding(name)?))) {
+            Some(found) => found,
+            None => return Ok(decode_8bit(content).into_owned()),
         };
-        dec.decode(&text, DecoderTrap::Strict).unwrap_or_default()
-    } else {
-        format!(
-            "=?{}?{}?{}?=",
-            &caps["charset"], &caps["encoding"], &caps["encoded_text"]
-        )
+    let mut decoder: Box<dyn RawDecoder> = dec.raw_decoder();
+    let mut text: String = String::with_capacity(content.len());
+    // Start and end of the invalid sequences
+    let mut invalid: Vec<(usize, usize)> = Vec::new();
+    let mut remaining: usize = 0;
+    loop {
+        let (processed, failure) =
+            decoder.raw_feed(content.get(remaining..).unwrap_or_default(), &mut text);
+        let unprocessed: usize = remaining.saturating_add(processed);
+        if let Some(error) = failure {
+            // The end of the sequence is relative to the input that was fed
+            let end: usize = usize::try_from(error.upto)
+                .map_or(unprocessed, |upto| remaining.saturating_add(upto))
+                .max(unprocessed.saturating_add(1))
+                .min(content.len());
+            invalid.push((unprocessed, end));
+            remaining = end;
+        } else {
+            if decoder.raw_finish(&mut text).is_some() {
+                // Truncated sequence at the end of the content
+                invalid.push((unprocessed, content.len()));
+            }
+            break;
+        }
+    }
+
+    match invalid.first() {
+        None => Ok(text),
+        Some(&(start, end)) => Err(CharsetError {
+            charset: name.to_owned(),
+            offset: start,
+            bytes: content
+                .get(start..end)
+                .unwrap_or_default()
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<String>>()
+                .concat(),
+            invalid: invalid.len(),
+        }),
     }
 }
 
+/// Decode a quoted-printable content, removing its soft line breaks
+pub fn decode_quoted_printable(text: &str) -> Vec<u8> {
+    let joined: String = text.replace("=\r\n", "").replace("=\n", "");
+    RE_QUOTE
+        .replace_all(joined.as_bytes(), replace_byte)
+        .to_vec()
+}
+
+/// Convert an HTML content to the text that is displayed, removing the tags
+/// and decoding the entities
+#[allow(clippy::indexing_slicing)]
+pub fn html_to_text(html: &str) -> String {
+    let visible = RE_HTML_HIDDEN.replace_all(html, " ");
+    let text = RE_HTML_TAG.replace_all(&visible, " ");
+    RE_HTML_ENTITY
+        .replace_all(&text, |caps: &Captures| {
+            decode_entity(&caps["entity"]).map_or_else(|| caps[0].to_owned(), String::from)
+        })
+        .to_string()
+}
+
+/// Decode an HTML entity name or number, without the `&` and `;` around it
+fn decode_entity(entity: &str) -> Option<char> {
+    let code: Option<u32> = if let Some(hex) = entity
+        .strip_prefix("#x")
+        .or_else(|| entity.strip_prefix("#X"))
+    {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(dec) = entity.strip_prefix('#') {
+        dec.parse().ok()
+    } else {
+        // Only the most used named entities
+        Some(match entity {
+            "amp" => 0x26,
+            "lt" => 0x3C,
+            "gt" => 0x3E,
+            "quot" => 0x22,
+            "apos" => 0x27,
+            "nbsp" => 0xA0,
+            "copy" => 0xA9,
+            "reg" => 0xAE,
+            "hellip" => 0x2026,
+            "mdash" => 0x2014,
+            "ndash" => 0x2013,
+            "euro" => 0x20AC,
+            _ => return None,
+        })
+    };
+    code.and_then(char::from_u32)
+}
+
 /// Quote replacing function, convert any hexadecimal value to it's representation
 #[allow(clippy::indexing_slicing)]
 fn replace_byte(caps: &regex::bytes::Captures) -> Vec<u8> {
@@ -75,6 +310,74 @@ fn replace_byte(caps: &regex::bytes::Captures) -> Vec<u8> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn charset() {
+        crate::test::log_init();
+
+        assert_eq!(decode_charset(b"caf\xE9", Some("ISO-8859-1")), "café");
+        assert_eq!(decode_charset("café".as_bytes(), Some("utf-8")), "café");
+        assert_eq!(decode_charset(b"caf\xE9", Some("utf-8")), "caf\u{FFFD}");
+        // Without a known charset, UTF-8 or else Latin-1
+        assert_eq!(decode_charset("café".as_bytes(), None), "café");
+        assert_eq!(decode_charset(b"caf\xE9", Some("x-unknown")), "café");
+    }
+
+    #[test]
+    fn strict_charset() {
+        crate::test::log_init();
+
+        assert_eq!(
+            decode_charset_strict(b"caf\xE9", Some("ISO-8859-1")),
+            Ok("café".to_owned())
+        );
+        assert_eq!(
+            decode_charset_strict(b"caf\xE9 cr\xE8me", Some("utf-8")),
+            Err(CharsetError {
+                charset: "utf-8".to_owned(),
+                offset: 3,
+                bytes: "E9".to_owned(),
+                invalid: 2,
+            })
+        );
+        // Truncated at the end, in the middle of a character
+        let error: Option<CharsetError> =
+            decode_charset_strict(b"\xd6\xd0\xce", Some("GB2312")).err();
+        assert_eq!(
+            error.map(|error| (error.offset, error.invalid)),
+            Some((2, 1))
+        );
+        // Without a known charset, nothing to report
+        assert_eq!(
+            decode_charset_strict(b"caf\xE9", Some("x-unknown")),
+            Ok("café".to_owned())
+        );
+    }
+
+    #[test]
+    fn quoted_printable() {
+        crate::test::log_init();
+
+        assert_eq!(
+            decode_quoted_printable("Caf=C3=A9 au lait, tr=\r\n=C3=A8s chaud=3D"),
+            "Café au lait, très chaud=".as_bytes()
+        );
+    }
+
+    #[test]
+    fn eight_bit_lines() {
+        crate::test::log_init();
+
+        assert!(matches!(
+            decode_8bit("Café".as_bytes()),
+            Cow::Borrowed("Café")
+        ));
+        // "Café" in Latin-1
+        assert_eq!(
+            decode_8bit(b"Caf\xe9"),
+            Cow::<str>::Owned("Café".to_owned())
+        );
+    }
+
     #[test]
     fn decode_string_literal() {
         crate::test::log_init();
@@ -118,6 +421,106 @@ mod tests {
         assert_eq!(a, "From: Patrik F\u{e4}ltstr\u{f6}m <paf@nada.kth.se>");
     }
 
+    /// Encoded strings of the Asian-language senders, with their text
+    const WORDS: &[(&str, &str)] = &[
+        (
+            "=?ISO-2022-JP?B?GyRCRnxLXDhsJE4lRiU5JUgbKEI=?=",
+            "日本語のテスト",
+        ),
+        (
+            "=?iso-2022-jp?Q?=1B$BF|K\\8l$N%F%9%H=1B(B?=",
+            "日本語のテスト",
+        ),
+        // Split while the escape sequence is in effect
+        (
+            "=?ISO-2022-JP?B?GyRCRnxLXDhsJE4=?=\r\n =?ISO-2022-JP?B?JUYlOSVIGyhC?=",
+            "日本語のテスト",
+        ),
+        (
+            "Re: =?ISO-2022-JP-2?B?GyRCJDMkcyRLJEEkTyEiQCQzJhsoQg==?=",
+            "Re: こんにちは、世界",
+        ),
+        (
+            "=?ISO-2022-JP*ja?B?GyRCRnxLXDhsJE4lRiU5JUgbKEI=?=",
+            "日本語のテスト",
+        ),
+        ("=?GB2312?B?1tDOxNPKvP6y4srU?=", "中文邮件测试"),
+        (
+            "=?gb2312?Q?=D6=D0=CE=C4=D3=CA=BC=FE=B2=E2=CA=D4?=",
+            "中文邮件测试",
+        ),
+        // Split in the middle of a character
+        (
+            "=?GB2312?B?1tDOxNPKvA==?= =?GB2312?B?/rLiytQ=?=",
+            "中文邮件测试",
+        ),
+        (
+            "=?EUC-CN?B?xOO6w6OsysC95w==?= <ni@example.cn>",
+            "你好，世界 <ni@example.cn>",
+        ),
+        // Kept as received
+        ("=?x-unknown?B?1tDOxA==?=", "=?x-unknown?B?1tDOxA==?="),
+        ("=?GB2312?X?1tDOxA==?=", "=?GB2312?X?1tDOxA==?="),
+    ];
+
+    /// Bodies of the Asian-language senders, with their charset and their text
+    const BODIES: &[(&str, &[u8], &str)] = &[
+        (
+            "ISO-2022-JP",
+            b"\x1b$B$3$s$K$A$O!\"@$3&\x1b(B\r\n",
+            "こんにちは、世界\r\n",
+        ),
+        (
+            "GB2312",
+            b"\xc4\xe3\xba\xc3\xa3\xac\xca\xc0\xbd\xe7\r\nOK\r\n",
+            "你好，世界\r\nOK\r\n",
+        ),
+        (
+            "cp936",
+            b"\xd6\xd0\xce\xc4\xd3\xca\xbc\xfe\xb2\xe2\xca\xd4",
+            "中文邮件测试",
+        ),
+    ];
+
+    #[test]
+    fn localized_corpus() {
+        crate::test::log_init();
+
+        for &(encoded, text) in WORDS {
+            assert_eq!(decode_string(encoded), text, "{}", encoded);
+        }
+        for &(charset, content, text) in BODIES {
+            assert!(legacy_charset(charset), "{}", charset);
+            assert_eq!(decode_charset(content, Some(charset)), text, "{}", charset);
+            // Kept in Latin-1 at the reception, not being valid UTF-8
+            let received: Cow<'_, str> = decode_8bit(content);
+            assert_eq!(
+                decode_charset(&encode_8bit(&received), Some(charset)),
+                text,
+                "{}",
+                charset
+            );
+        }
+        assert!(!legacy_charset("UTF-8"));
+        assert!(!legacy_charset("x-unknown"));
+    }
+
+    #[test]
+    fn html_stripping() {
+        crate::test::log_init();
+
+        let html: &str = "<html><head><title>Hidden</title></head><body>\
+<style>p {color: red}</style><!-- comment -->\
+<p class=\"main\">Caf&eacute; &amp; cr&#232;me&nbsp;br&#xFBB;l&#xe9;e</p>\
+<script>alert(\"no\")</script></body></html>";
+        let text: String = html_to_text(html);
+
+        assert_eq!(
+            text.split_whitespace().collect::<Vec<&str>>().join(" "),
+            "Caf&eacute; & cr\u{e8}me br\u{fbb}l\u{e9}e"
+        );
+    }
+
     #[test]
     #[allow(clippy::indexing_slicing)]
     fn hex_decoding() {
@@ -0,0 +1,131 @@
+use thiserror::Error;
+use tide::StatusCode;
+
+/// Error coming from another crate, kept as the source of a `MailcatcherError`
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Error of the crate, telling which part of the catcher failed
+#[derive(Debug, Error)]
+pub enum MailcatcherError {
+    /// Failure of the SMTP side: listening, talking with a client or an upstream server
+    #[error("SMTP: {0}")]
+    Smtp(#[source] BoxError),
+    /// Failure of the HTTP side: listening or answering a request
+    #[error("HTTP: {0}")]
+    Http(#[source] BoxError),
+    /// The mail broker cannot be reached, or did not reply
+    #[error("Mail broker: {0}")]
+    Broker(#[source] BoxError),
+    /// The mails cannot be read or written in their storage
+    #[error("Storage: {0}")]
+    Storage(#[source] BoxError),
+    /// Invalid configuration, like a command line option
+    #[error("Configuration: {0}")]
+    Config(#[source] BoxError),
+}
+
+impl MailcatcherError {
+    /// Failure of the SMTP side
+    pub fn smtp<E: Into<BoxError>>(e: E) -> Self {
+        Self::Smtp(e.into())
+    }
+
+    /// Failure of the HTTP side
+    pub fn http<E: Into<BoxError>>(e: E) -> Self {
+        Self::Http(e.into())
+    }
+
+    /// Failure to reach the mail broker
+    pub fn broker<E: Into<BoxError>>(e: E) -> Self {
+        Self::Broker(e.into())
+    }
+
+    /// Failure of the storage of the mails
+    pub fn storage<E: Into<BoxError>>(e: E) -> Self {
+        Self::Storage(e.into())
+    }
+
+    /// Invalid configuration
+    pub fn config<E: Into<BoxError>>(e: E) -> Self {
+        Self::Config(e.into())
+    }
+
+    /// HTTP status of the response to a request that failed with this error
+    pub const fn status(&self) -> StatusCode {
+        match *self {
+            Self::Smtp(_) => StatusCode::BadGateway,
+            Self::Http(_) | Self::Storage(_) => StatusCode::InternalServerError,
+            Self::Broker(_) => StatusCode::ServiceUnavailable,
+            Self::Config(_) => StatusCode::BadRequest,
+        }
+    }
+
+    /// Error of a failed request, with the HTTP status matching this error
+    pub fn into_http(self) -> tide::Error {
+        tide::Error::new(self.status(), self)
+    }
+
+    /// Exit code of the program stopped by this error, from `sysexits.h`
+    pub const fn exit_code(&self) -> i32 {
+        match *self {
+            // EX_UNAVAILABLE
+            Self::Smtp(_) | Self::Http(_) => 69,
+            // EX_SOFTWARE
+            Self::Broker(_) => 70,
+            // EX_IOERR
+            Self::Storage(_) => 74,
+            // EX_CONFIG
+            Self::Config(_) => 78,
+        }
+    }
+}
+
+/// A channel used with the mail broker is closed
+impl<T> From<async_std::channel::SendError<T>> for MailcatcherError {
+    fn from(e: async_std::channel::SendError<T>) -> Self {
+        Self::broker(e.to_string())
+    }
+}
+
+/// The stream of the events sent to the browsers is closed
+impl From<futures::channel::mpsc::SendError> for MailcatcherError {
+    fn from(e: futures::channel::mpsc::SendError) -> Self {
+        Self::http(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{channel, task};
+
+    use super::*;
+
+    #[test]
+    fn status_and_exit_code() {
+        crate::test::log_init();
+
+        let e: MailcatcherError = MailcatcherError::config("Invalid upstream SMTP server");
+        assert_eq!(e.to_string(), "Configuration: Invalid upstream SMTP server");
+        assert_eq!(e.status(), StatusCode::BadRequest);
+        assert_eq!(e.exit_code(), 78);
+
+        let e: tide::Error = MailcatcherError::storage("disk full").into_http();
+        assert_eq!(e.status(), StatusCode::InternalServerError);
+        assert_eq!(e.to_string(), "Storage: disk full");
+    }
+
+    #[test]
+    fn closed_broker_channel() {
+        crate::test::log_init();
+
+        let (sender, receiver): crate::Channel<u8> = channel::bounded(1);
+        drop(receiver);
+        let e: MailcatcherError = task::block_on(sender.send(1)).map_or_else(From::from, |()| {
+            MailcatcherError::http("the channel should be closed")
+        });
+
+        assert!(matches!(e, MailcatcherError::Broker(_)));
+        assert_eq!(e.status(), StatusCode::ServiceUnavailable);
+        assert_eq!(e.exit_code(), 70);
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+
+use async_std::fs;
+use tide::{
+    http::{Body, Method},
+    prelude::{json, Deserialize},
+};
+
+use crate::{
+    error::MailcatcherError,
+    utils::{local_request, Output},
+};
+
+/// Summary of a mail, as listed by the `/mails` route
+#[derive(Debug, Deserialize)]
+struct Listed {
+    /// ULID of the mail, whatever the `id` given by the instance
+    ulid: String,
+}
+
+/// Write the mails of the instance listening on the HTTP `port` in the `out`
+/// tar archive, with the `POST /api/export` route
+///
+/// Every mail is exported if no `ids` are given. With the JSON `output`, a
+/// document gives the archive, its size and the mails it holds.
+#[allow(clippy::print_stdout)]
+pub async fn export(
+    port: u16,
+    ids: &[String],
+    tags: &[String],
+    out: &Path,
+    output: Output,
+) -> crate::Result<()> {
+    let exported: Vec<String> = if ids.is_empty() {
+        local_request(port, Method::Get, "/mails", None)
+            .await?
+            .body_json::<Vec<Listed>>()
+            .await
+            .map_err(|e| MailcatcherError::http(e.to_string()))?
+            .into_iter()
+            .map(|listed| listed.ulid)
+            .collect()
+    } else {
+        ids.to_vec()
+    };
+    if exported.is_empty() {
+        return Err(MailcatcherError::http("No mail to export"));
+    }
+
+    let body: Body = Body::from_json(&json!({ "ids": exported, "tags": tags }))
+        .map_err(|e| MailcatcherError::http(e.to_string()))?;
+    let archive: Vec<u8> = local_request(port, Method::Post, "/api/export", Some(body))
+        .await?
+        .body_bytes()
+        .await
+        .map_err(|e| MailcatcherError::http(e.to_string()))?;
+    fs::write(out, &archive)
+        .await
+        .map_err(MailcatcherError::storage)?;
+
+    match output {
+        Output::Text => println!("{} mails exported to {}", exported.len(), out.display()),
+        Output::Json => println!("{}", report(out, &exported, archive.len())),
+    }
+    log::info!("{} mails exported to {}", exported.len(), out.display());
+
+    Ok(())
+}
+
+/// JSON document of the export, for `--output json`
+fn report(out: &Path, ids: &[String], size: usize) -> serde_json::Value {
+    json!({
+        "file": PathBuf::from(out),
+        "size": size,
+        "mails": ids,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_report() {
+        crate::test::log_init();
+
+        assert_eq!(
+            report(
+                Path::new("fixtures/mails.tar"),
+                &["01F0000000000000000000000A".to_owned(), "2".to_owned()],
+                4_096
+            ),
+            json!({
+                "file": "fixtures/mails.tar",
+                "size": 4_096,
+                "mails": ["01F0000000000000000000000A", "2"],
+            })
+        );
+    }
+}
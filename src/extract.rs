@@ -0,0 +1,306 @@
+use std::path::{Path, PathBuf};
+
+use async_std::fs;
+use fnv::FnvHashSet;
+use tide::{
+    http::{Method, Response},
+    prelude::{json, Deserialize, Serialize},
+};
+use ulid::Ulid;
+
+use crate::{
+    error::MailcatcherError,
+    mail::{journal::Journal, Mail},
+    utils::{local_request, Output},
+};
+
+/// Attachment of a mail, decoded
+#[derive(Debug)]
+struct Attachment {
+    /// Name of the file, as given in the mail
+    filename: Option<String>,
+    /// Decoded content
+    content: Vec<u8>,
+}
+
+/// Summary of a mail, as listed by the `/mails` route
+#[derive(Debug, Deserialize)]
+struct Listed {
+    /// ULID of the mail, whatever the `id` given by the instance
+    ulid: String,
+}
+
+/// Attachment, as listed by the `/mail/:id/attachments` route
+#[derive(Debug, Deserialize)]
+struct ListedAttachment {
+    /// Index of the attachment in the list
+    index: usize,
+    /// Name of the file, as given in the mail
+    filename: Option<String>,
+}
+
+/// Attachment written on the disk, printed with `--output json`
+#[derive(Debug, Serialize)]
+struct Written {
+    /// Id of the mail
+    id: String,
+    /// Path of the file
+    file: PathBuf,
+    /// Size of the file, in bytes
+    size: usize,
+}
+
+/// Write the decoded attachments of a mail, or of all the mails, in the `out`
+/// directory
+///
+/// The mails are read from the `journal` if it is given, or else from the
+/// instance listening on the HTTP `port`. With all the mails, the attachments of
+/// each one are written in a sub-directory named after its id.
+#[allow(clippy::print_stdout)]
+pub async fn extract(
+    port: u16,
+    journal: Option<&Path>,
+    id: Option<&str>,
+    out: &Path,
+    output: Output,
+) -> crate::Result<()> {
+    let id: Option<Ulid> = id
+        .map(|id| {
+            Ulid::from_string(id)
+                .map_err(|e| MailcatcherError::config(format!("Invalid mail id {}: {}", id, e)))
+        })
+        .transpose()?;
+
+    let mails: Vec<(Ulid, Vec<Attachment>)> = match journal {
+        Some(path) => from_journal(path, id).await?,
+        None => from_instance(port, id).await?,
+    };
+
+    let mut written: Vec<Written> = Vec::new();
+    for &(mail_id, ref attachments) in &mails {
+        let dir: PathBuf = if id.is_some() {
+            out.to_path_buf()
+        } else {
+            out.join(mail_id.to_string())
+        };
+        for (file, size) in write(&dir, attachments).await? {
+            if output == Output::Text {
+                println!("{}", file.display());
+            }
+            written.push(Written {
+                id: mail_id.to_string(),
+                file,
+                size,
+            });
+        }
+    }
+    if output == Output::Json {
+        println!("{}", json!(written));
+    }
+
+    Ok(())
+}
+
+/// Read the attachments of the mails kept in a journal file
+async fn from_journal(
+    path: &Path,
+    id: Option<Ulid>,
+) -> crate::Result<Vec<(Ulid, Vec<Attachment>)>> {
+    // Opening the journal would create it
+    if !path.exists() {
+        return Err(MailcatcherError::storage(format!(
+            "The journal {} does not exist",
+            path.display()
+        )));
+    }
+    let mails: Vec<(Ulid, Vec<Attachment>)> = Journal::read(path)
+        .await?
+        .iter()
+        .filter(|mail| id.map_or(true, |id| mail.get_id() == id))
+        .map(|mail| (mail.get_id(), attachments(mail)))
+        .collect();
+
+    match id {
+        Some(id) if mails.is_empty() => Err(MailcatcherError::storage(format!(
+            "No mail {} in the journal {}",
+            id,
+            path.display()
+        ))),
+        _ => Ok(mails),
+    }
+}
+
+/// Download the attachments of the mails from the instance listening on the HTTP
+/// `port`
+async fn from_instance(port: u16, id: Option<Ulid>) -> crate::Result<Vec<(Ulid, Vec<Attachment>)>> {
+    let ids: Vec<Ulid> = if let Some(id) = id {
+        vec![id]
+    } else {
+        let listed: Vec<Listed> =
+            body_json(local_request(port, Method::Get, "/mails", None).await?).await?;
+        listed
+            .iter()
+            .filter_map(|mail| Ulid::from_string(&mail.ulid).ok())
+            .collect()
+    };
+
+    let mut mails: Vec<(Ulid, Vec<Attachment>)> = Vec::new();
+    for id in ids {
+        let path: String = format!("/mail/{}/attachments", id);
+        let listed: Vec<ListedAttachment> =
+            body_json(local_request(port, Method::Get, &path, None).await?).await?;
+
+        let mut attachments: Vec<Attachment> = Vec::new();
+        for attachment in listed {
+            let mut response: Response = local_request(
+                port,
+                Method::Get,
+                &format!("{}/{}", path, attachment.index),
+                None,
+            )
+            .await?;
+            attachments.push(Attachment {
+                filename: attachment.filename,
+                content: response
+                    .body_bytes()
+                    .await
+                    .map_err(|e| MailcatcherError::http(e.to_string()))?,
+            });
+        }
+        mails.push((id, attachments));
+    }
+
+    Ok(mails)
+}
+
+/// Read a JSON response
+async fn body_json<T>(mut response: Response) -> crate::Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    response
+        .body_json()
+        .await
+        .map_err(|e| MailcatcherError::http(e.to_string()))
+}
+
+/// Decode the attachments of a mail
+fn attachments(mail: &Mail) -> Vec<Attachment> {
+    mail.get_attachments()
+        .iter()
+        .map(|part| Attachment {
+            filename: part.filename(),
+            content: part.decoded(),
+        })
+        .collect()
+}
+
+/// Write the attachments in a directory, returning the files and their sizes
+///
+/// Only the last component of the given names is kept, so nothing is written
+/// outside of the directory. A name used twice is prefixed with the position of
+/// the attachment.
+async fn write(dir: &Path, attachments: &[Attachment]) -> crate::Result<Vec<(PathBuf, usize)>> {
+    if attachments.is_empty() {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(dir)
+        .await
+        .map_err(MailcatcherError::storage)?;
+
+    let mut used: FnvHashSet<String> = FnvHashSet::default();
+    let mut files: Vec<(PathBuf, usize)> = Vec::new();
+    for (index, attachment) in attachments.iter().enumerate() {
+        let mut name: String = file_name(attachment.filename.as_deref(), index);
+        if !used.insert(name.clone()) {
+            name = format!("{}-{}", index.saturating_add(1), name);
+            let _ = used.insert(name.clone());
+        }
+
+        let file: PathBuf = dir.join(name);
+        fs::write(&file, &attachment.content)
+            .await
+            .map_err(|e| MailcatcherError::storage(format!("{}: {}", file.display(), e)))?;
+        files.push((file, attachment.content.len()));
+    }
+
+    Ok(files)
+}
+
+/// Name of the file of an attachment, without any directory, or named after its
+/// position if it has no usable name
+fn file_name(filename: Option<&str>, index: usize) -> String {
+    filename
+        .and_then(|filename| Path::new(filename.trim()).file_name())
+        .and_then(std::ffi::OsStr::to_str)
+        .map_or_else(
+            || format!("attachment-{}", index.saturating_add(1)),
+            ToOwned::to_owned,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+
+    #[test]
+    fn sanitized_names() {
+        crate::test::log_init();
+
+        assert_eq!(file_name(Some("report.pdf"), 0), "report.pdf");
+        assert_eq!(file_name(Some("../../etc/passwd"), 0), "passwd");
+        assert_eq!(file_name(Some("/tmp/"), 1), "tmp");
+        assert_eq!(file_name(Some(".."), 1), "attachment-2");
+        assert_eq!(file_name(Some(""), 2), "attachment-3");
+        assert_eq!(file_name(None, 0), "attachment-1");
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn write_attachments() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new(
+            "sender@example.org",
+            &["rcpt@example.org".to_owned()],
+            "Content-Type: multipart/mixed; boundary=sep\r\n\
+Subject: Invoices\r\n\
+\r\n\
+--sep\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+See attached\r\n\
+--sep\r\n\
+Content-Type: text/csv; name=\"invoice.csv\"\r\n\
+Content-Disposition: attachment\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+YSxiDQox\r\n\
+--sep\r\n\
+Content-Type: text/csv\r\n\
+Content-Disposition: attachment; filename=\"../invoice.csv\"\r\n\
+\r\n\
+c,d\r\n\
+--sep--\r\n",
+        );
+        let dir: PathBuf = std::env::temp_dir().join(format!("mailcatcher-{}", Ulid::new()));
+
+        let result: crate::Result<Vec<(PathBuf, usize)>> =
+            task::block_on(write(&dir, &attachments(&mail)));
+        let contents: std::io::Result<Vec<Vec<u8>>> = ["invoice.csv", "2-invoice.csv"]
+            .iter()
+            .map(|name| std::fs::read(dir.join(name)))
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+
+        assert_eq!(
+            result?,
+            vec![(dir.join("invoice.csv"), 6), (dir.join("2-invoice.csv"), 3)]
+        );
+        assert_eq!(contents?, vec![b"a,b\r\n1".to_vec(), b"c,d".to_vec()]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use async_std::fs;
+use chrono::{DateTime, TimeZone, Utc};
+use fake::{
+    faker::{
+        internet::en::{FreeEmailProvider, SafeEmail},
+        lorem::en::{Paragraphs, Words},
+        name::en::Name,
+    },
+    Fake,
+};
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+use textwrap::wrap;
+use tide::prelude::{json, Serialize};
+
+use crate::{error::MailcatcherError, utils::Output};
+
+/// Longest line of the encoded contents, as RFC 2045 asks
+const MAX_LINE: usize = 76;
+
+/// Sentence added to the text parts that are not in 7bit, so their charset
+/// matters
+const NON_ASCII: &str = "Café crème, naïve façade, 10 € — ✓";
+
+/// Transfer encoding of the generated parts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// ASCII lines, as is
+    SevenBit,
+    /// Quoted-printable
+    QuotedPrintable,
+    /// Base64
+    Base64,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "7bit" => Ok(Self::SevenBit),
+            "quoted-printable" => Ok(Self::QuotedPrintable),
+            "base64" => Ok(Self::Base64),
+            _ => Err(format!(
+                "Unknown encoding {}, expected 7bit, quoted-printable or base64",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Self::SevenBit => "7bit",
+            Self::QuotedPrintable => "quoted-printable",
+            Self::Base64 => "base64",
+        })
+    }
+}
+
+/// Features of the generated mails, each mail picking its own at random
+/// within them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Features {
+    /// Transfer encodings of the text parts
+    pub encodings: Vec<Encoding>,
+    /// Fold the long header fields over several lines
+    pub fold: bool,
+    /// Largest nesting depth of the multipart entities, 0 giving single part
+    /// mails
+    pub depth: usize,
+    /// Largest number of attachments of a multipart entity
+    pub attachments: usize,
+    /// Largest size of an attachment, before its encoding, in bytes
+    pub attachment_size: usize,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            encodings: vec![
+                Encoding::SevenBit,
+                Encoding::QuotedPrintable,
+                Encoding::Base64,
+            ],
+            fold: false,
+            depth: 2,
+            attachments: 2,
+            attachment_size: 4_096,
+        }
+    }
+}
+
+/// Mail written on the disk, printed with `--output json`
+#[derive(Debug, Serialize)]
+struct Written {
+    /// Path of the file
+    file: PathBuf,
+    /// Size of the file, in bytes
+    size: usize,
+}
+
+/// Write `count` generated mails in the `out` directory, the same `seed` giving
+/// the same mails, a random one being used if it is not given
+#[allow(clippy::print_stdout)]
+pub async fn fixtures(
+    features: &Features,
+    count: usize,
+    seed: Option<u64>,
+    out: &Path,
+    output: Output,
+) -> crate::Result<()> {
+    let seed: u64 = seed.unwrap_or_else(rand::random);
+    let mut rng: StdRng = StdRng::seed_from_u64(seed);
+    fs::create_dir_all(out)
+        .await
+        .map_err(MailcatcherError::storage)?;
+
+    if output == Output::Text {
+        println!("Seed {}", seed);
+    }
+    let mut written: Vec<Written> = Vec::new();
+    for index in 1..=count {
+        let mail: String = generate(features, &mut rng);
+        let file: PathBuf = out.join(format!("{:04}.eml", index));
+        fs::write(&file, &mail)
+            .await
+            .map_err(|e| MailcatcherError::storage(format!("{}: {}", file.display(), e)))?;
+        if output == Output::Text {
+            println!("{}", file.display());
+        }
+        written.push(Written {
+            file,
+            size: mail.len(),
+        });
+    }
+    if output == Output::Json {
+        println!("{}", json!({ "seed": seed, "mails": written }));
+    }
+
+    Ok(())
+}
+
+/// Generate a mail, randomized but valid per RFC 5322 and RFC 2045, with CRLF
+/// line endings
+///
+/// The attachments are binary, so they are encoded in base64, or in
+/// quoted-printable if it is the only encoding of the features besides 7bit.
+pub fn generate<R: Rng>(features: &Features, rng: &mut R) -> String {
+    let domain: String = FreeEmailProvider().fake_with_rng(rng);
+    let date: DateTime<Utc> = Utc.timestamp(rng.gen_range(1_000_000_000..1_700_000_000), 0);
+    let recipients: Vec<String> = (0..rng.gen_range(1..=4)).map(|_| mailbox(rng)).collect();
+    let words: Vec<String> = if features.fold {
+        Words(12..24).fake_with_rng(rng)
+    } else {
+        Words(3..8).fake_with_rng(rng)
+    };
+    let depth: usize = rng.gen_range(0..=features.depth);
+    let fields: Vec<String> = vec![
+        field("Date", &date.to_rfc2822(), features.fold),
+        field("From", &mailbox(rng), features.fold),
+        field("To", &recipients.join(", "), features.fold),
+        field("Subject", &words.join(" "), features.fold),
+        field(
+            "Message-ID",
+            &format!("<{}@{}>", token(rng, 16), domain),
+            features.fold,
+        ),
+        "MIME-Version: 1.0".to_owned(),
+    ];
+    format!(
+        "{}\r\n{}",
+        fields.join("\r\n"),
+        entity(features, rng, depth)
+    )
+}
+
+/// Random mailbox, with a display name
+fn mailbox<R: Rng>(rng: &mut R) -> String {
+    let name: String = Name().fake_with_rng(rng);
+    let address: String = SafeEmail().fake_with_rng(rng);
+    format!("\"{}\" <{}>", name, address)
+}
+
+/// Random alphanumeric token
+fn token<R: Rng>(rng: &mut R, len: usize) -> String {
+    (0..len)
+        .map(|_| char::from(rng.sample(Alphanumeric)))
+        .collect()
+}
+
+/// Header field, folded at the spaces if it is too long and `fold` is asked
+fn field(name: &str, value: &str, fold: bool) -> String {
+    let line: String = format!("{}: {}", name, value);
+    if fold && line.len() > MAX_LINE {
+        wrap(&line, MAX_LINE).join("\r\n ")
+    } else {
+        line
+    }
+}
+
+/// MIME entity of the given nesting `depth`: its header fields, an empty line,
+/// then its content
+fn entity<R: Rng>(features: &Features, rng: &mut R, depth: usize) -> String {
+    if depth == 0 {
+        let html: bool = rng.gen_bool(0.5);
+        return text(features, rng, html);
+    }
+    let boundary: String = format!("=_{}", token(rng, 24));
+    let (subtype, parts): (&str, Vec<String>) = if depth == 1 && rng.gen_bool(0.5) {
+        (
+            "alternative",
+            vec![text(features, rng, false), text(features, rng, true)],
+        )
+    } else {
+        let mut parts: Vec<String> = vec![entity(features, rng, depth.saturating_sub(1))];
+        for _ in 0..rng.gen_range(0..=features.attachments) {
+            parts.push(attachment(features, rng));
+        }
+        ("mixed", parts)
+    };
+    let mut lines: Vec<String> = vec![
+        format!(
+            "Content-Type: multipart/{}; boundary=\"{}\"",
+            subtype, boundary
+        ),
+        String::new(),
+        "This is a multi-part message in MIME format.".to_owned(),
+    ];
+    for part in parts {
+        lines.push(format!("--{}", boundary));
+        lines.push(part);
+    }
+    lines.push(format!("--{}--", boundary));
+    lines.join("\r\n")
+}
+
+/// Text part, in plain text or in html, with one of the encodings
+fn text<R: Rng>(features: &Features, rng: &mut R, html: bool) -> String {
+    let encoding: Encoding = features
+        .encodings
+        .get(rng.gen_range(0..features.encodings.len().max(1)))
+        .copied()
+        .unwrap_or(Encoding::SevenBit);
+    let mut paragraphs: Vec<String> = Paragraphs(1..4).fake_with_rng(rng);
+    if encoding != Encoding::SevenBit {
+        paragraphs.push(NON_ASCII.to_owned());
+    }
+    let (subtype, content): (&str, String) = if html {
+        let body: String = paragraphs
+            .iter()
+            .map(|paragraph| format!("<p>{}</p>", paragraph))
+            .collect::<Vec<String>>()
+            .join("\n");
+        (
+            "html",
+            format!("<html>\n<body>\n{}\n</body>\n</html>", body),
+        )
+    } else {
+        ("plain", paragraphs.join("\n\n"))
+    };
+    format!(
+        "Content-Type: text/{}; charset=\"{}\"\r\nContent-Transfer-Encoding: {}\r\n\r\n{}",
+        subtype,
+        if encoding == Encoding::SevenBit {
+            "us-ascii"
+        } else {
+            "utf-8"
+        },
+        encoding,
+        encode(content.as_bytes(), encoding)
+    )
+}
+
+/// Attachment of random bytes
+fn attachment<R: Rng>(features: &Features, rng: &mut R) -> String {
+    let encoding: Encoding = if features.encodings.contains(&Encoding::QuotedPrintable)
+        && !features.encodings.contains(&Encoding::Base64)
+    {
+        Encoding::QuotedPrintable
+    } else {
+        Encoding::Base64
+    };
+    let words: Vec<String> = Words(1..3).fake_with_rng(rng);
+    let filename: String = format!("{}.bin", words.join("-"));
+    let content: Vec<u8> = (0..rng.gen_range(0..=features.attachment_size))
+        .map(|_| rng.gen())
+        .collect();
+    format!(
+        "Content-Type: application/octet-stream; name=\"{0}\"\r\n\
+         Content-Disposition: attachment; filename=\"{0}\"\r\n\
+         Content-Transfer-Encoding: {1}\r\n\r\n{2}",
+        filename,
+        encoding,
+        encode(&content, encoding)
+    )
+}
+
+/// Encode a content, its lines ended by CRLF and at most 76 characters long
+fn encode(content: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::SevenBit => String::from_utf8_lossy(content)
+            .lines()
+            .flat_map(|line| wrap(line, MAX_LINE))
+            .collect::<Vec<_>>()
+            .join("\r\n"),
+        Encoding::QuotedPrintable => encode_quoted_printable(content),
+        Encoding::Base64 => base64::encode(content)
+            .as_bytes()
+            .chunks(MAX_LINE)
+            .map(String::from_utf8_lossy)
+            .collect::<Vec<_>>()
+            .join("\r\n"),
+    }
+}
+
+/// Encode a content in quoted-printable, its line feeds being kept as CRLF
+/// line breaks and the longer lines being broken by soft line breaks
+fn encode_quoted_printable(content: &[u8]) -> String {
+    let mut encoded: String = String::new();
+    let mut line_len: usize = 0;
+    let mut bytes = content.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\n' {
+            encoded.push_str("\r\n");
+            line_len = 0;
+            continue;
+        }
+        // The spaces at the end of a line would be removed by the transport
+        let at_line_end: bool = bytes.peek().map_or(true, |&&next| next == b'\n');
+        let escaped: String = match byte {
+            b' ' | b'\t' if !at_line_end => char::from(byte).to_string(),
+            b'!'..=b'~' if byte != b'=' => char::from(byte).to_string(),
+            _ => format!("={:02X}", byte),
+        };
+        // Room is kept for the `=` of the soft line break
+        if line_len.saturating_add(escaped.len()) >= MAX_LINE {
+            encoded.push_str("=\r\n");
+            line_len = 0;
+        }
+        line_len = line_len.saturating_add(escaped.len());
+        encoded.push_str(&escaped);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        encoding::decode_quoted_printable,
+        mail::{Mail, Type},
+    };
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn generated_mails() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let features: Features = Features {
+            fold: true,
+            depth: 3,
+            attachments: 3,
+            attachment_size: 300,
+            ..Features::default()
+        };
+        let mut rng: StdRng = StdRng::seed_from_u64(42);
+        let mails: Vec<String> = (0..50).map(|_| generate(&features, &mut rng)).collect();
+        // The same seed gives the same mails
+        assert_eq!(
+            generate(&features, &mut StdRng::seed_from_u64(42)),
+            mails.first().cloned().unwrap_or_default()
+        );
+
+        for data in &mails {
+            assert!(data.lines().all(|line| line.len() <= 998), "{}", data);
+            assert!(!data.replace("\r\n", "").contains('\n'), "{}", data);
+            let mail: Mail = Mail::new("from@example.org", &["to@example.net".into()], data);
+            assert_eq!(mail.get_diagnostics(), &Vec::new(), "{}", data);
+            assert!(mail.get_text().is_some() || mail.get_html().is_some());
+            for part in mail.get_attachments() {
+                assert!(part.decoded().len() <= 300);
+            }
+            // Folded, the header fields are not too long either
+            let (headers, _): (String, String) =
+                Mail::split_header_body(mail.get_data(&Type::Raw).ok_or("no raw")?);
+            assert!(headers.lines().all(|line| line.len() <= 78), "{}", headers);
+        }
+        assert!(mails.iter().any(|mail| mail.contains("multipart/mixed")));
+        assert!(mails
+            .iter()
+            .any(|mail| mail.contains("multipart/alternative")));
+        assert!(mails
+            .iter()
+            .any(|mail| mail.contains("application/octet-stream")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn quoted_printable() {
+        crate::test::log_init();
+
+        let content: String = format!("{} \nend =\t\n{}", NON_ASCII, "x".repeat(200));
+        let encoded: String = encode_quoted_printable(content.as_bytes());
+        assert!(encoded.lines().all(|line| line.len() <= MAX_LINE));
+        assert!(encoded.starts_with("Caf=C3=A9 cr=C3=A8me"));
+        assert!(encoded.contains("end =3D=09\r\n"));
+        assert_eq!(
+            decode_quoted_printable(&encoded),
+            content.replace('\n', "\r\n").into_bytes()
+        );
+    }
+
+    #[test]
+    fn encodings() {
+        crate::test::log_init();
+
+        for encoding in &[
+            Encoding::SevenBit,
+            Encoding::QuotedPrintable,
+            Encoding::Base64,
+        ] {
+            assert_eq!(encoding.to_string().parse(), Ok(*encoding));
+        }
+        assert!("8bit".parse::<Encoding>().is_err());
+    }
+}
@@ -0,0 +1,239 @@
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+use async_std::{
+    channel::{self, Receiver},
+    net::TcpListener,
+};
+
+use crate::{error::MailcatcherError, utils::Bound};
+
+/// Variable giving the listeners handed over to the new process, by side, like
+/// `smtp=3,4|7;http=5,6`, the SMTP listeners of each `--smtp` being separated
+/// by `|`
+#[cfg(unix)]
+const LISTENERS: &str = "MAILCATCHER_LISTENERS";
+
+/// Time the new process has to fail, before the old one stops
+#[cfg(unix)]
+const STARTUP: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Descriptors of the listeners of a side, to hand them over to a new process
+#[derive(Clone, Debug, Default)]
+pub struct Handles {
+    /// Descriptors of the listeners
+    #[cfg(unix)]
+    fds: Vec<RawFd>,
+}
+
+impl Handles {
+    /// Descriptors of the listeners, that must be kept open as long as they
+    /// can be handed over
+    #[cfg(unix)]
+    pub fn of(listeners: &[TcpListener]) -> Self {
+        use std::os::unix::io::AsRawFd;
+
+        Self {
+            fds: listeners.iter().map(AsRawFd::as_raw_fd).collect(),
+        }
+    }
+
+    /// Nothing to hand over without the unix descriptors
+    #[cfg(not(unix))]
+    pub fn of(_listeners: &[TcpListener]) -> Self {
+        Self::default()
+    }
+}
+
+/// Signal to hand the listeners over to a new process, sent on SIGUSR2
+#[cfg(unix)]
+pub fn on_upgrade() -> crate::Result<Receiver<()>> {
+    use signal_hook::{consts::SIGUSR2, iterator::Signals};
+
+    let (upgrade, rx_upgrade): crate::Channel<()> = channel::unbounded();
+    let mut signals: Signals = Signals::new([SIGUSR2]).map_err(MailcatcherError::config)?;
+    let _thread = std::thread::spawn(move || {
+        for _ in signals.forever() {
+            log::info!("SIGUSR2 received, handing the listeners over to a new process");
+            if upgrade.try_send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(rx_upgrade)
+}
+
+/// Signal to hand the listeners over, never sent without SIGUSR2
+#[cfg(not(unix))]
+pub fn on_upgrade() -> crate::Result<Receiver<()>> {
+    let (_upgrade, rx_upgrade): crate::Channel<()> = channel::unbounded();
+    Ok(rx_upgrade)
+}
+
+/// Start the binary again, with the same arguments, handing it the listeners
+/// of both sides, so the new version installed at the same path takes over
+///
+/// The `smtp` handles are those of each SMTP port, in the order of the
+/// arguments.
+///
+/// The new process inherits the listening sockets: the connections waiting to
+/// be accepted are kept, and it accepts the new ones as soon as it runs, or with
+/// a journal, once this process exited and released it, for the mails accepted
+/// until then to be restored. It is given some time to fail, like on an invalid
+/// argument, the listeners being kept by this process then.
+#[cfg(unix)]
+pub async fn upgrade(smtp: &[Handles], http: &Handles) -> crate::Result<()> {
+    let mut child: std::process::Child = start(smtp, http)?;
+
+    async_std::task::sleep(STARTUP).await;
+    if let Some(status) = child.try_wait().map_err(MailcatcherError::config)? {
+        return Err(MailcatcherError::config(format!(
+            "New process exited at its start: {}",
+            status
+        )));
+    }
+    log::info!("Listeners handed over to the process {}", child.id());
+    Ok(())
+}
+
+/// Start the new process, that inherits the listeners
+#[cfg(unix)]
+fn start(smtp: &[Handles], http: &Handles) -> crate::Result<std::process::Child> {
+    use std::{os::unix::process::CommandExt, process::Command};
+
+    let mut args = std::env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| MailcatcherError::config("Unknown path of the binary"))?;
+    let list = |handles: &Handles| {
+        handles
+            .fds
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    let fds: Vec<RawFd> = smtp
+        .iter()
+        .chain(std::iter::once(http))
+        .flat_map(|handles| handles.fds.iter().copied())
+        .collect();
+    let smtp: String = smtp.iter().map(list).collect::<Vec<String>>().join("|");
+
+    let mut command: Command = Command::new(program);
+    let _command: &mut Command = command
+        .args(args)
+        .env(LISTENERS, format!("smtp={};http={}", smtp, list(http)));
+    // The descriptors are closed on exec by default, they are kept open for the
+    // new process only
+    #[allow(unsafe_code)]
+    // SAFETY: only fcntl is called between fork and exec, it is async-signal-safe
+    let _command: &mut Command = unsafe {
+        command.pre_exec(move || {
+            for &fd in &fds {
+                if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        })
+    };
+    command.spawn().map_err(MailcatcherError::config)
+}
+
+/// Handing the listeners over needs the unix descriptors
+#[cfg(not(unix))]
+pub async fn upgrade(_smtp: &[Handles], _http: &Handles) -> crate::Result<()> {
+    Err(MailcatcherError::config(
+        "Handing the listeners over is only supported on unix",
+    ))
+}
+
+/// Listeners of the SMTP and HTTP sides handed over by the previous process,
+/// if this one was started by an upgrade, those of the SMTP side being grouped
+/// by port
+#[cfg(unix)]
+pub fn inherited() -> crate::Result<Option<(Vec<Bound>, Bound)>> {
+    let value: String = match std::env::var(LISTENERS) {
+        Ok(value) => value,
+        Err(_) => return Ok(None),
+    };
+    // Not handed down to the processes started later
+    std::env::remove_var(LISTENERS);
+
+    let (smtp, http): (&str, &str) = value
+        .strip_prefix("smtp=")
+        .and_then(|sides| sides.split_once(";http="))
+        .ok_or_else(|| MailcatcherError::config(format!("Invalid {}: {}", LISTENERS, value)))?;
+    // All checked before any is owned, not to close a descriptor by mistake
+    let smtp: Vec<Vec<RawFd>> = smtp
+        .split('|')
+        .map(descriptors)
+        .collect::<crate::Result<Vec<Vec<RawFd>>>>()?;
+    let http: Vec<RawFd> = descriptors(http)?;
+    Ok(Some((smtp.into_iter().map(adopt).collect(), adopt(http))))
+}
+
+/// No listener is handed over without the unix descriptors
+#[cfg(not(unix))]
+pub fn inherited() -> crate::Result<Option<(Vec<Bound>, Bound)>> {
+    Ok(None)
+}
+
+/// Descriptors of the inherited listeners of a port, separated by commas
+#[cfg(unix)]
+fn descriptors(fds: &str) -> crate::Result<Vec<RawFd>> {
+    fds.split(',')
+        .map(|fd| {
+            fd.trim().parse().map_err(|_e| {
+                MailcatcherError::config(format!("Invalid descriptor in {}: {}", LISTENERS, fd))
+            })
+        })
+        .collect()
+}
+
+/// Take the ownership of the inherited listeners of a port
+#[cfg(unix)]
+fn adopt(fds: Vec<RawFd>) -> Bound {
+    use std::os::unix::io::FromRawFd;
+
+    let mut listeners: Vec<TcpListener> = Vec::new();
+    for fd in fds {
+        #[allow(unsafe_code)]
+        // SAFETY: the descriptor is a listening socket left open by the previous
+        // process for this one, and nothing else owns it
+        let listener: std::net::TcpListener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        listeners.push(TcpListener::from(listener));
+    }
+
+    Bound {
+        listeners,
+        failures: Vec::new(),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn adopt_listeners() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        assert!(descriptors("").is_err());
+        assert!(descriptors("3,smtp").is_err());
+        assert_eq!(descriptors(" 3,4 ")?, vec![3, 4]);
+        assert!(inherited()?.is_none());
+
+        task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let handles: Handles = Handles::of(std::slice::from_ref(&listener));
+            assert_eq!(handles.fds.len(), 1);
+            Ok(())
+        })
+    }
+}
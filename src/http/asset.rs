@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt, str::FromStr};
 
 use mailcatcher_derive::AssetEmbed;
 use tide::{
@@ -10,14 +10,72 @@ use tide::{
 #[folder = "asset/"]
 pub struct Asset;
 
-/// Generate a Response based on the name of the asset and the mime type
-#[allow(clippy::indexing_slicing)]
-pub fn send(req: &Request, name: &str, mime: Mime) -> tide::Result<Response> {
+/// Theme of the assets at the root of the asset directory
+const CLASSIC: &str = "classic";
+
+/// Look of the web interface, the assets of a theme being in a sub-directory of
+/// the asset directory, named after it
+///
+/// The assets missing in a theme are those of the classic theme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme(String);
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self(CLASSIC.to_owned())
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == CLASSIC || Asset::folders().contains(&s) {
+            Ok(Self(s.to_owned()))
+        } else {
+            Err(format!(
+                "Unknown theme {}, expected one of {}",
+                s,
+                themes().collect::<Vec<&str>>().join(", ")
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Theme {
+    /// Path of an asset in the theme, or of the classic asset if the theme does
+    /// not change it
+    fn path(&self, name: &str) -> String {
+        let themed: String = format!("{}/{}", self.0, name);
+        if self.0 != CLASSIC && Asset::modif(&themed).is_some() {
+            themed
+        } else {
+            name.to_owned()
+        }
+    }
+}
+
+/// Names of the available themes
+pub fn themes() -> impl Iterator<Item = &'static str> {
+    std::iter::once(CLASSIC).chain(Asset::folders().iter().copied())
+}
+
+/// Generate a Response based on the name of the asset in the theme, and the mime type
+pub fn send(req: &Request, theme: &Theme, name: &str, mime: Mime) -> tide::Result<Response> {
+    let name: &str = &theme.path(name);
     // Look if the response can be compressed in deflate, or not
     let compressed: bool = req
         .header(headers::ACCEPT_ENCODING)
         .map_or(false, |header_value| {
-            header_value[0].as_str().contains("deflate")
+            header_value
+                .iter()
+                .any(|value| value.as_str().contains("deflate"))
         });
     // Retrieve the asset, either integrated during release compilation, or read from filesystem if it's debug build
     let content: Cow<[u8]> = Asset::get(name)
@@ -84,12 +142,12 @@ mod tests {
 
     #[test]
     #[allow(clippy::indexing_slicing, clippy::panic_in_result_fn)]
-    fn compressed() -> crate::Result<()> {
+    fn compressed() -> crate::test::Result<()> {
         crate::test::log_init();
 
         let mut request = Request::new(Method::Get, "http://localhost/");
         let _ = request.insert_header(headers::ACCEPT_ENCODING, "gzip, deflate");
-        let response = send(&request, "home.html", mime::HTML)?;
+        let response = send(&request, &Theme::default(), "home.html", mime::HTML)?;
 
         let header_type = response
             .header(headers::CONTENT_TYPE)
@@ -119,11 +177,11 @@ mod tests {
 
     #[test]
     #[allow(clippy::indexing_slicing, clippy::panic_in_result_fn)]
-    fn uncompressed() -> crate::Result<()> {
+    fn uncompressed() -> crate::test::Result<()> {
         crate::test::log_init();
 
         let request = Request::new(Method::Get, "http://localhost/");
-        let response = send(&request, "home.html", mime::HTML)?;
+        let response = send(&request, &Theme::default(), "home.html", mime::HTML)?;
 
         let header_type = response
             .header(headers::CONTENT_TYPE)
@@ -148,4 +206,35 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn themes() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let dark: Theme = "dark".parse()?;
+        for &invalid in &["unknown", "../asset", "dark/", ""] {
+            assert!(invalid.parse::<Theme>().is_err());
+        }
+        assert_eq!(
+            super::themes().collect::<Vec<&str>>(),
+            vec!["classic", "dark"]
+        );
+
+        // The theme changes the stylesheet, and keeps the other assets
+        let request = Request::new(Method::Get, "http://localhost/");
+        let length = |response: &Response| {
+            response
+                .header(headers::CONTENT_LENGTH)
+                .map(|length| length.last().as_str().to_owned())
+        };
+        let classic_css = send(&request, &Theme::default(), "theme.css", mime::CSS)?;
+        let dark_css = send(&request, &dark, "theme.css", mime::CSS)?;
+        assert_ne!(length(&classic_css), length(&dark_css));
+        let classic_html = send(&request, &Theme::default(), "home.html", mime::HTML)?;
+        let dark_html = send(&request, &dark, "home.html", mime::HTML)?;
+        assert_eq!(length(&classic_html), length(&dark_html));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use broadcaster::BroadcastChannel;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Destination of the events, like the browsers connected by SSE
+///
+/// A new notification channel (WebSocket, webhook, desktop notification, message bus...)
+/// only has to implement this trait and be given to the HTTP side in its `Params`.
+#[async_trait]
+pub trait EventSink<T>: Send + Sync {
+    /// Name of the sink, used in the logs
+    fn name(&self) -> &'static str;
+
+    /// Send the event to the sink
+    async fn notify(&self, evt: &T) -> crate::Result<()>;
+}
+
+/// Dispatch the events to all the registered sinks
+pub struct EventBus<T> {
+    /// Registered sinks
+    sinks: Vec<Arc<dyn EventSink<T>>>,
+}
+
+impl<T> Clone for EventBus<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sinks: self.sinks.clone(),
+        }
+    }
+}
+
+impl<T> Default for EventBus<T> {
+    fn default() -> Self {
+        Self { sinks: Vec::new() }
+    }
+}
+
+impl<T: Sync> EventBus<T> {
+    /// Add a sink that will receive all the next events
+    pub fn register(&mut self, sink: Arc<dyn EventSink<T>>) {
+        log::debug!("Registering the event sink {}", sink.name());
+        self.sinks.push(sink);
+    }
+
+    /// Send the event to every sink, a failing sink does not prevent the others
+    /// to be notified
+    pub async fn publish(&self, evt: &T) {
+        for sink in &self.sinks {
+            match sink.notify(evt).await {
+                Ok(()) => log::trace!("Event sent to {}", sink.name()),
+                Err(e) => log::error!("Event not sent to {}: {:?}", sink.name(), e),
+            }
+        }
+    }
+}
+
+/// Send the events to the browsers connected on `/sse`
+pub struct SseSink<T>
+where
+    T: Send + Clone + 'static,
+{
+    /// Stream read by each SSE connection
+    stream: BroadcastChannel<T, UnboundedSender<T>, UnboundedReceiver<T>>,
+}
+
+impl<T> SseSink<T>
+where
+    T: Send + Clone + 'static,
+{
+    /// Create the sink, that writes to the SSE stream
+    pub const fn new(
+        stream: BroadcastChannel<T, UnboundedSender<T>, UnboundedReceiver<T>>,
+    ) -> Self {
+        Self { stream }
+    }
+}
+
+#[async_trait]
+impl<T> EventSink<T> for SseSink<T>
+where
+    T: Send + Sync + Clone + 'static,
+{
+    fn name(&self) -> &'static str {
+        "SSE"
+    }
+
+    async fn notify(&self, evt: &T) -> crate::Result<()> {
+        self.stream.send(evt).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_std::task;
+
+    use super::*;
+    use crate::error::MailcatcherError;
+
+    /// Sink keeping the events received
+    #[derive(Default)]
+    struct Recorder {
+        /// Events received
+        events: Mutex<Vec<u8>>,
+    }
+
+    #[async_trait]
+    #[allow(clippy::unused_async)]
+    impl EventSink<u8> for Recorder {
+        fn name(&self) -> &'static str {
+            "Recorder"
+        }
+
+        async fn notify(&self, evt: &u8) -> crate::Result<()> {
+            self.events
+                .lock()
+                .map_err(|e| MailcatcherError::http(e.to_string()))?
+                .push(*evt);
+            Ok(())
+        }
+    }
+
+    /// Sink that always fails
+    struct Failing;
+
+    #[async_trait]
+    #[allow(clippy::unused_async)]
+    impl EventSink<u8> for Failing {
+        fn name(&self) -> &'static str {
+            "Failing"
+        }
+
+        async fn notify(&self, _evt: &u8) -> crate::Result<()> {
+            Err(MailcatcherError::http("unreachable sink"))
+        }
+    }
+
+    #[test]
+    fn publish_to_all_sinks() {
+        crate::test::log_init();
+
+        let first: Arc<Recorder> = Arc::default();
+        let second: Arc<Recorder> = Arc::default();
+
+        let mut bus: EventBus<u8> = EventBus::default();
+        bus.register(Arc::<Recorder>::clone(&first));
+        bus.register(Arc::new(Failing));
+        bus.register(Arc::<Recorder>::clone(&second));
+
+        task::block_on(async {
+            bus.publish(&1).await;
+            bus.clone().publish(&2).await;
+        });
+
+        for sink in &[first, second] {
+            assert_eq!(*sink.events.lock().expect("events"), vec![1, 2]);
+        }
+    }
+}
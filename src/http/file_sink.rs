@@ -0,0 +1,210 @@
+use std::{path::Path, str::FromStr};
+
+use async_std::{
+    fs::{File, OpenOptions},
+    sync::Mutex,
+};
+use async_trait::async_trait;
+use futures::AsyncWriteExt;
+
+use crate::{
+    error::MailcatcherError,
+    http::{event_sink::EventSink, sse_evt::SseEvt},
+    mail::{Mail, Type},
+};
+
+/// Layout of the mails in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Mbox, read by most mail clients: each mail begins with a `From ` line, and
+    /// the lines of its content beginning with `From ` are quoted with `>`
+    Mbox,
+    /// Each mail is its raw content, after a line with its length in bytes
+    Records,
+}
+
+impl FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("mbox") {
+            Ok(Self::Mbox)
+        } else if s.eq_ignore_ascii_case("records") {
+            Ok(Self::Records)
+        } else {
+            Err(format!(
+                "Unknown file format {}, expected mbox or records",
+                s
+            ))
+        }
+    }
+}
+
+/// Append the raw content of every new mail to a file, that is kept even if the
+/// mails are removed from the tank
+pub struct FileSink {
+    /// File opened in append mode
+    file: Mutex<File>,
+    /// Layout of the mails in the file
+    format: FileFormat,
+}
+
+impl FileSink {
+    /// Open the file, creating it if it does not exist
+    pub async fn open(path: &Path, format: FileFormat) -> crate::Result<Self> {
+        let file: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| {
+                MailcatcherError::storage(format!(
+                    "Unable to open the mail file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            format,
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink<SseEvt> for FileSink {
+    fn name(&self) -> &'static str {
+        "File"
+    }
+
+    async fn notify(&self, evt: &SseEvt) -> crate::Result<()> {
+        let mail: &Mail = match *evt {
+            SseEvt::NewMail(ref mail) => mail,
+            SseEvt::DelMail(_) | SseEvt::Ping | SseEvt::TankDigest(_) => return Ok(()),
+        };
+        let entry: Vec<u8> = entry(mail, self.format);
+
+        let mut file = self.file.lock().await;
+        file.write_all(&entry)
+            .await
+            .map_err(MailcatcherError::storage)?;
+        file.sync_data().await.map_err(MailcatcherError::storage)?;
+
+        Ok(())
+    }
+}
+
+/// Entry of a mail in the file
+fn entry(mail: &Mail, format: FileFormat) -> Vec<u8> {
+    let raw: &str = mail.get_data(&Type::Raw).map_or("", String::as_str);
+
+    match format {
+        FileFormat::Mbox => {
+            let mut entry: String = format!(
+                "From {} {}\n",
+                sender(mail.from()),
+                mail.get_received().format("%a %b %e %H:%M:%S %Y")
+            );
+            for line in raw.lines() {
+                // Quoted as in mboxrd, so the quoting can be reverted
+                if line.trim_start_matches('>').starts_with("From ") {
+                    entry.push('>');
+                }
+                entry.push_str(line);
+                entry.push('\n');
+            }
+            entry.push('\n');
+            entry.into_bytes()
+        }
+        FileFormat::Records => {
+            let mut entry: Vec<u8> = format!("{}\n", raw.len()).into_bytes();
+            entry.extend_from_slice(raw.as_bytes());
+            entry.push(b'\n');
+            entry
+        }
+    }
+}
+
+/// Address of the sender, for the `From ` line of mbox
+fn sender(from: &str) -> &str {
+    let address: &str = from
+        .rsplit('<')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('>')
+        .trim();
+    if address.is_empty() || address.contains(' ') {
+        "MAILER-DAEMON"
+    } else {
+        address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use async_std::task;
+    use ulid::Ulid;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn mbox_file() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: PathBuf = std::env::temp_dir().join(format!("mailcatcher-{}.mbox", Ulid::new()));
+        let first: Mail = Mail::new(
+            "<alice@example.org>",
+            &[],
+            "Subject: First\r\n\r\nFrom here\r\n>From there",
+        );
+        let second: Mail = Mail::new("", &[], "Subject: Second\r\n\r\nBody");
+
+        let result: crate::Result<()> = task::block_on(async {
+            let sink: FileSink = FileSink::open(&path, FileFormat::Mbox).await?;
+            sink.notify(&SseEvt::NewMail(Box::new(first.clone())))
+                .await?;
+            sink.notify(&SseEvt::Ping).await?;
+            // The file is appended, even once opened again
+            let sink: FileSink = FileSink::open(&path, FileFormat::Mbox).await?;
+            sink.notify(&SseEvt::NewMail(Box::new(second.clone())))
+                .await
+        });
+        let content: std::io::Result<String> = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap_or_default();
+        result?;
+
+        let date = |mail: &Mail| {
+            mail.get_received()
+                .format("%a %b %e %H:%M:%S %Y")
+                .to_string()
+        };
+        assert_eq!(
+            content?,
+            format!(
+                "From alice@example.org {}\nSubject: First\n\n>From here\n>>From there\n\n\
+From MAILER-DAEMON {}\nSubject: Second\n\nBody\n\n",
+                date(&first),
+                date(&second)
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn records_entry() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new("", &[], "Subject: Café\r\n\r\nBody");
+        assert_eq!(
+            entry(&mail, FileFormat::Records),
+            "22\nSubject: Café\r\n\r\nBody\n".as_bytes()
+        );
+        assert_eq!(sender("Alice <alice@example.org>"), "alice@example.org");
+        assert_eq!(sender("<>"), "MAILER-DAEMON");
+    }
+}
@@ -0,0 +1,396 @@
+use structopt::clap::{App, ErrorKind};
+use tide::prelude::Serialize;
+
+/// Description of the command line, given by `/api/help` to the users of a
+/// running instance that have no access to its host
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Help {
+    /// Name of the command
+    pub name: String,
+    /// Version of the command, only known for the program itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// What the command does
+    pub about: Option<String>,
+    /// Flags, options and positional arguments of the command, by name
+    pub arguments: Vec<Argument>,
+    /// Subcommands, described like the command
+    pub commands: Vec<Self>,
+    /// Help printed by `--help`
+    pub text: String,
+}
+
+/// An argument of the command line
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Argument {
+    /// Name of the argument
+    pub name: String,
+    /// Long flag, without its dashes
+    pub long: Option<String>,
+    /// Short flag, without its dash
+    pub short: Option<char>,
+    /// Name of the value, for the options and the positional arguments
+    pub value: Option<String>,
+    /// Value used when the argument is not given
+    pub default: Option<String>,
+    /// Only values accepted
+    pub possible_values: Vec<String>,
+    /// The argument can be given several times
+    pub multiple: bool,
+    /// The argument must be given
+    pub required: bool,
+    /// Summary of the argument
+    pub help: Option<String>,
+    /// Full description of the argument, when it is longer than its summary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long_help: Option<String>,
+}
+
+impl Help {
+    /// Describe a command from its definition
+    ///
+    /// clap has no public API listing the arguments, they are read from the
+    /// help it writes, unwrapped, the subcommands from the help they print for
+    /// `--help`.
+    pub fn new(definition: &App<'_, '_>) -> Self {
+        let app: App<'_, '_> = definition.clone().set_term_width(0);
+        let name: String = app.get_name().to_owned();
+        // Writing the help adds the help and version flags, so a copy is used
+        let mut text: Vec<u8> = Vec::new();
+        if let Err(e) = app.clone().write_long_help(&mut text) {
+            log::warn!("Help of {} not written: {}", name, e);
+        }
+        // The same help, without the author that cannot be told from the about
+        let mut described: Vec<u8> = Vec::new();
+        if let Err(e) = app
+            .clone()
+            .template(TEMPLATE)
+            .write_long_help(&mut described)
+        {
+            log::warn!("Help of {} not written: {}", name, e);
+        }
+
+        let mut help: Self = Self::from_text(&name, &String::from_utf8_lossy(&described));
+        help.version = help.version.take().filter(|version| !version.is_empty());
+        help.commands = help
+            .commands
+            .iter()
+            .filter_map(|command| Self::subcommand(&app, &[command.name.as_str()]))
+            .collect();
+        help.text = String::from_utf8_lossy(&text).into_owned();
+        help
+    }
+
+    /// Describe the subcommand at `path`, from the help it prints
+    fn subcommand(app: &App<'_, '_>, path: &[&str]) -> Option<Self> {
+        let args = std::iter::once(app.get_name())
+            .chain(path.iter().copied())
+            .chain(std::iter::once("--help"));
+        let text: String = match app.clone().get_matches_from_safe(args) {
+            Err(e) if e.kind == ErrorKind::HelpDisplayed => e.message,
+            _ => {
+                log::warn!("Help of {} not written", path.join(" "));
+                return None;
+            }
+        };
+        let mut help: Self = Self::from_text(path.last()?, &text);
+        // Only known for the program itself
+        help.version = None;
+        help.commands = help
+            .commands
+            .iter()
+            .filter_map(|command| {
+                let mut sub: Vec<&str> = path.to_vec();
+                sub.push(&command.name);
+                Self::subcommand(app, &sub)
+            })
+            .collect();
+        help.text = text;
+        Some(help)
+    }
+
+    /// Describe a command from its help, the subcommands only being named
+    fn from_text(name: &str, text: &str) -> Self {
+        let mut lines = text.lines();
+        // The name of the command and its version, then its about
+        let version: Option<String> = lines
+            .next()
+            .and_then(|first| first.split_whitespace().nth(1))
+            .map(ToOwned::to_owned);
+        let about: String = lines
+            .by_ref()
+            .take_while(|line| line.trim() != "USAGE:")
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        // Sections, like `OPTIONS:`, with their lines
+        let mut sections: Vec<(&str, Vec<&str>)> = Vec::new();
+        let mut usage_lines: Vec<&str> = Vec::new();
+        for line in lines {
+            if !line.starts_with(' ') && line.ends_with(':') {
+                sections.push((line.trim_end_matches(':'), Vec::new()));
+            } else {
+                match sections.last_mut() {
+                    Some(section) => section.1.push(line),
+                    None => usage_lines.push(line),
+                }
+            }
+        }
+        let usage: String = outside_brackets(&usage_lines.join(" "));
+
+        let mut arguments: Vec<Argument> = Vec::new();
+        let mut commands: Vec<Self> = Vec::new();
+        for (section, lines) in sections {
+            if section == "SUBCOMMANDS" {
+                commands.extend(
+                    lines
+                        .iter()
+                        .filter(|line| indent(line) <= 4)
+                        .filter_map(|line| line.split_whitespace().next())
+                        // The subcommand printing the help of the others
+                        .filter(|&command| command != "help")
+                        .map(|command| Self {
+                            name: command.to_owned(),
+                            ..Self::default()
+                        }),
+                );
+            } else {
+                arguments.extend(parse_arguments(&lines, &usage));
+            }
+        }
+        // Sorted like in the help printed by clap
+        arguments.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            name: name.to_owned(),
+            version,
+            about: Some(about.trim().to_owned()).filter(|text| !text.is_empty()),
+            arguments,
+            commands,
+            text: String::new(),
+        }
+    }
+}
+
+/// Template of the help, without the author that cannot be told from the about
+const TEMPLATE: &str = "{bin} {version}\n{about}\n\nUSAGE:\n    {usage}\n\n{all-args}";
+
+/// Number of spaces at the beginning of a line
+fn indent(line: &str) -> usize {
+    line.len().saturating_sub(line.trim_start().len())
+}
+
+/// Parts of the usage of a command outside of the brackets, the required ones
+fn outside_brackets(usage: &str) -> String {
+    let mut depth: usize = 0;
+    usage
+        .chars()
+        .filter(|&c| {
+            match c {
+                '[' => depth = depth.saturating_add(1),
+                ']' => depth = depth.saturating_sub(1),
+                _ => return depth == 0,
+            }
+            false
+        })
+        .collect()
+}
+
+/// Arguments listed in a section of the help, each one starting with its flags
+/// or its value, followed by its description
+fn parse_arguments(lines: &[&str], usage: &str) -> Vec<Argument> {
+    let mut arguments: Vec<(Argument, Vec<String>)> = Vec::new();
+    for line in lines {
+        let trimmed: &str = line.trim();
+        if indent(line) <= 8 && (trimmed.starts_with('-') || trimmed.starts_with('<')) {
+            let (spec, description): (&str, &str) =
+                trimmed.split_once("  ").unwrap_or((trimmed, ""));
+            arguments.push((parse_spec(spec, usage), vec![description.trim().to_owned()]));
+        } else {
+            // The lines before the first argument are blank
+            if let Some(last) = arguments.last_mut() {
+                last.1.push(trimmed.to_owned());
+            }
+        }
+    }
+
+    arguments
+        .into_iter()
+        // The flags added by clap to every command
+        .filter(|entry| !matches!(entry.0.long.as_deref(), Some("help" | "version")))
+        .map(|(mut argument, description)| {
+            describe(&mut argument, &description);
+            argument
+        })
+        .collect()
+}
+
+/// Argument of its flags and its value, like `-o, --output <output>...`
+fn parse_spec(spec: &str, usage: &str) -> Argument {
+    let mut argument: Argument = Argument {
+        multiple: spec.ends_with("..."),
+        ..Argument::default()
+    };
+    for word in spec.split_whitespace() {
+        let token: &str = word.trim_end_matches(',').trim_end_matches("...");
+        if let Some(long) = token.strip_prefix("--") {
+            argument.long = Some(long.to_owned());
+        } else if let Some(value) = token.strip_prefix('<') {
+            argument.value = Some(value.trim_end_matches('>').to_owned());
+        } else {
+            argument.short = token
+                .strip_prefix('-')
+                .and_then(|short| short.chars().next());
+        }
+    }
+    argument.required = match (argument.long.as_ref(), argument.value.as_ref()) {
+        (Some(long), _) => usage
+            .split_whitespace()
+            .any(|word| word == format!("--{}", long)),
+        (None, Some(value)) => usage.contains(&format!("<{}>", value)),
+        (None, None) => false,
+    };
+    argument.name = argument
+        .long
+        .clone()
+        .or_else(|| argument.short.map(String::from))
+        .or_else(|| argument.value.clone())
+        .unwrap_or_default();
+    argument
+}
+
+/// Set the help of an argument from its description, the paragraphs separated
+/// by blank lines, and the default value and the possible ones clap appends
+fn describe(argument: &mut Argument, description: &[String]) {
+    let mut text: String = description.join("\n").trim().to_owned();
+    // Annotations at the end of the description, like `[default: text]`
+    while text.ends_with(']') {
+        let start: usize = match text.rfind('[') {
+            Some(start) => start,
+            None => break,
+        };
+        let annotation: &str = text
+            .get(start.saturating_add(1)..text.len().saturating_sub(1))
+            .unwrap_or_default();
+        if let Some(default) = annotation.strip_prefix("default: ") {
+            argument.default = Some(default.to_owned());
+        } else if let Some(values) = annotation.strip_prefix("possible values: ") {
+            argument.possible_values = values.split(", ").map(ToOwned::to_owned).collect();
+        } else {
+            break;
+        }
+        text.truncate(start);
+        text = text.trim_end().to_owned();
+    }
+
+    let paragraphs: Vec<String> = text
+        .split("\n\n")
+        .map(|paragraph| {
+            paragraph
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect();
+    argument.help = paragraphs.first().cloned();
+    if paragraphs.len() > 1 {
+        argument.long_help = Some(paragraphs.join("\n\n"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::clap::{Arg, SubCommand};
+
+    use super::*;
+
+    #[test]
+    fn from_definition() {
+        crate::test::log_init();
+
+        let app: App<'_, '_> = App::new("catcher")
+            .version("1.2.3")
+            .about("Catch the mails")
+            .arg(
+                Arg::with_name("smtp")
+                    .long("smtp")
+                    .takes_value(true)
+                    .default_value("1025")
+                    .help("SMTP port"),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .value_name("FMT")
+                    .possible_values(&["mbox", "eml"]),
+            )
+            .arg(Arg::with_name("quiet").short("q").long("quiet"))
+            .arg(Arg::with_name("secret").long("secret").hidden(true))
+            .subcommand(
+                SubCommand::with_name("inject")
+                    .about("Inject mail files")
+                    .arg(Arg::with_name("files").required(true).multiple(true)),
+            );
+        let help: Help = Help::new(&app);
+
+        assert_eq!(help.name, "catcher");
+        assert_eq!(help.version.as_deref(), Some("1.2.3"));
+        assert_eq!(help.about.as_deref(), Some("Catch the mails"));
+        assert_eq!(
+            help.arguments
+                .iter()
+                .map(|arg| arg.name.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["format", "quiet", "smtp"]
+        );
+        assert_eq!(
+            help.arguments.last(),
+            Some(&Argument {
+                name: "smtp".to_owned(),
+                long: Some("smtp".to_owned()),
+                value: Some("smtp".to_owned()),
+                default: Some("1025".to_owned()),
+                help: Some("SMTP port".to_owned()),
+                ..Argument::default()
+            })
+        );
+        assert_eq!(
+            help.arguments
+                .first()
+                .map(|arg| (&arg.value, &arg.possible_values)),
+            Some((
+                &Some("FMT".to_owned()),
+                &vec!["mbox".to_owned(), "eml".to_owned()]
+            ))
+        );
+        assert_eq!(
+            help.arguments
+                .get(1)
+                .map(|arg| (arg.short, arg.value.is_none())),
+            Some((Some('q'), true))
+        );
+        assert!(help.text.contains("--smtp <smtp>"));
+        assert!(!help.text.contains("--secret"));
+        assert_eq!(
+            help.commands
+                .iter()
+                .map(|command| (command.name.as_str(), command.about.as_deref()))
+                .collect::<Vec<(&str, Option<&str>)>>(),
+            vec![("inject", Some("Inject mail files"))]
+        );
+        assert_eq!(
+            help.commands
+                .first()
+                .and_then(|command| command.arguments.first()),
+            Some(&Argument {
+                name: "files".to_owned(),
+                value: Some("files".to_owned()),
+                multiple: true,
+                required: true,
+                ..Argument::default()
+            })
+        );
+    }
+}
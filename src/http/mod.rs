@@ -1,31 +1,69 @@
-use std::time::Duration;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_std::{
     channel::{Receiver, Sender},
-    net::{SocketAddr, ToSocketAddrs},
+    future,
+    net::{SocketAddr, TcpListener},
+    prelude::FutureExt as _,
+    sync::RwLock,
     task,
 };
 use broadcaster::BroadcastChannel;
+use chrono::{DateTime, Utc};
 use futures::{
     channel::mpsc::{UnboundedReceiver, UnboundedSender},
     StreamExt,
 };
-use tide::{prelude::Listener, Server};
+use tide::{
+    listener::{ConcurrentListener, Listener},
+    Server,
+};
+use ulid::Ulid;
 
 use crate::{
-    http::sse_evt::SseEvt,
-    mail::{broker::MailEvt, Mail},
-    utils::spawn_task_and_swallow_log_errors,
+    error::MailcatcherError,
+    http::{
+        asset::Theme,
+        event_sink::{EventBus, EventSink, SseSink},
+        help::Help,
+        routes::remove::PurgeTokens,
+        sse::SseClients,
+        sse_evt::SseEvt,
+    },
+    mail::{broker::MailEvt, journal::Journal, IdStrategy, Mail},
+    otlp::Tracer,
+    settings::SharedSettings,
+    shutdown::Activity,
+    smtp::{
+        capture::Capture, greylist::Greylist, latency::Latency, metrics::Metrics, profile::Profile,
+        quarantine::Quarantine, queue::Queue, release::Target, Pause,
+    },
+    utils::{spawn_task_and_swallow_log_errors, BindFailure, Bound},
 };
 
+/// Time between the digests of the mails sent to the browsers, the first one
+/// being sent once it is over
+const DIGEST_INTERVAL: Duration = Duration::from_secs(30);
+
 /// Files in the "asset" directory
-mod asset;
+pub mod asset;
+/// Notification sinks of the events
+pub mod event_sink;
+/// Copy of the new mails in a file
+pub mod file_sink;
+/// Description of the command line
+pub mod help;
 /// Routes initialisation
 mod routes;
 /// Server-Sent Events
 mod sse;
 /// Events sent by SSE
 pub mod sse_evt;
+/// Syslog lines of the new mails
+pub mod syslog_sink;
 
 /// Tide Connection State
 #[derive(Clone)]
@@ -35,12 +73,171 @@ where
 {
     /// Stream used for receiving SSE messages
     sse_stream: BroadcastChannel<T, UnboundedSender<T>, UnboundedReceiver<T>>,
+    /// Sinks to notify of the events
+    events: EventBus<T>,
+    /// Browsers connected to the SSE stream
+    sse_clients: SseClients,
+    /// Start time of the HTTP side
+    started: DateTime<Utc>,
     /// Mail broker storage stream
     mail_broker: Sender<MailEvt>,
+    /// Settings that can be changed at runtime, like the timezone used to format
+    /// the dates if not specified in the request
+    settings: SharedSettings,
+    /// Token to give to use the management API, that is disabled without it
+    api_token: Option<String>,
+    /// Headless browser used to render the mails in images
+    #[cfg(feature = "render")]
+    render_browser: String,
+    /// Send a new mail, like the SMTP side does
+    new_mail: Sender<Mail>,
+    /// Journal the injected mails are written in before being sent, if the
+    /// mails are persisted
+    journal: Option<Journal>,
+    /// Addresses the servers listen on, that change when they are rebound
+    listening: Arc<RwLock<Listening>>,
+    /// Exporter of the spans of the requests
+    tracer: Option<Tracer>,
+    /// Tokens confirming the purge of all the mails
+    purge_tokens: PurgeTokens,
+    /// Switch simulating an outage of the SMTP side
+    smtp_pause: Pause,
+    /// Delay of the SMTP replies
+    smtp_latency: Latency,
+    /// Greylisting of the SMTP recipients
+    greylist: Greylist,
+    /// Counters of the SMTP sessions
+    smtp_metrics: Metrics,
+    /// SMTP transactions cut off in the middle of their content
+    quarantine: Quarantine,
+    /// Capture of the SMTP mails under a heavy load
+    capture: Capture,
+    /// Optional features enabled
+    capabilities: Capabilities,
+    /// Theme of the web interface, unless another is asked with `?theme=`
+    theme: Theme,
+    /// Identifier of the mails given as `id`
+    ids: IdStrategy,
+    /// SMTP server the mails are released to
+    release: Option<Target>,
+    /// Queue of the mails received, waiting for the mail broker
+    queue: Queue,
+    /// Description of the command line, given by `/api/help`
+    help: Arc<Help>,
+    /// Last activity, kept up by the requests
+    activity: Activity,
+    /// New mails waiting to be notified to the browsers
+    notifications: Receiver<Mail>,
+}
 
-    #[cfg(feature = "faking")]
-    /// Send a new Fake new mail
-    new_fake_mail: Sender<Mail>,
+impl<T> State<T>
+where
+    T: Send + Clone + 'static,
+{
+    /// Send a request to the mail broker, failing with a 503 error if it is not running
+    async fn broker_request(&self, evt: MailEvt) -> tide::Result<()> {
+        self.mail_broker.send(evt).await.map_err(|e| {
+            log::error!("Request not sent to the mail broker: {}", e);
+            MailcatcherError::broker("not running").into_http()
+        })
+    }
+
+    /// Write a mail injected over HTTP in the journal, if the mails are persisted,
+    /// before it is sent like the SMTP side does
+    async fn journal_append(&self, mail: &Mail) -> crate::Result<()> {
+        match self.journal {
+            Some(ref journal) => journal.append(mail).await.map_err(|e| {
+                log::error!("Mail {} not written in the journal: {}", mail.get_id(), e);
+                e
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait for the next reply of the mail broker, `None` once it has sent all of them
+    ///
+    /// A broker that does not reply in time gives a 503 error.
+    async fn broker_reply<R>(&self, receiver: &mut Receiver<R>) -> tide::Result<Option<R>> {
+        future::timeout(self.settings.get().await.broker_delay(), receiver.next())
+            .await
+            .map_err(|e| {
+                log::error!("No reply of the mail broker: {}", e);
+                MailcatcherError::broker("no reply in time").into_http()
+            })
+    }
+
+    /// Wait for the only reply of the mail broker
+    ///
+    /// A broker that does not reply in time, or without a reply, gives a 503 error.
+    async fn broker_single_reply<R>(&self, receiver: &mut Receiver<R>) -> tide::Result<R> {
+        self.broker_reply(receiver)
+            .await?
+            .ok_or_else(|| MailcatcherError::broker("no reply").into_http())
+    }
+}
+
+/// Addresses the servers listen on
+#[derive(Clone, Debug, Default)]
+pub struct Listening {
+    /// Addresses of the SMTP side
+    pub smtp: Vec<SocketAddr>,
+    /// Addresses of the HTTP side
+    pub http: Vec<SocketAddr>,
+    /// Addresses of the SMTP side that could not be bound
+    pub smtp_failures: Vec<BindFailure>,
+    /// Addresses of the HTTP side that could not be bound
+    pub http_failures: Vec<BindFailure>,
+    /// Profile of the SMTP side
+    pub smtp_profile: Profile,
+    /// Additional SMTP listeners, each on its own port with its own profile,
+    /// that are not rebound
+    pub smtp_extra: Vec<ProfiledListening>,
+}
+
+impl Listening {
+    /// Retrieve the addresses of the bound listeners, and those that failed
+    pub fn new(smtp: &Bound, http: &Bound) -> Self {
+        Self {
+            smtp: smtp.addresses(),
+            http: http.addresses(),
+            smtp_failures: smtp.failures.clone(),
+            http_failures: http.failures.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Addresses an additional SMTP listener listens on
+#[derive(Clone, Debug)]
+pub struct ProfiledListening {
+    /// Behavior of the listener
+    pub profile: Profile,
+    /// Addresses listened on
+    pub addresses: Vec<SocketAddr>,
+    /// Addresses of the port that could not be bound
+    pub failures: Vec<BindFailure>,
+}
+
+impl ProfiledListening {
+    /// Retrieve the addresses of the bound listeners, and those that failed
+    pub fn new(profile: Profile, bound: &Bound) -> Self {
+        Self {
+            profile,
+            addresses: bound.addresses(),
+            failures: bound.failures.clone(),
+        }
+    }
+}
+
+/// Optional features enabled at the start, given by `/api/capabilities`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Capabilities {
+    /// The mails are kept in a journal
+    pub persistence: bool,
+    /// The SMTP sessions are relayed to an upstream server
+    pub relay: bool,
+    /// The SMTP clients must give credentials with AUTH
+    pub auth: bool,
 }
 
 /// Parameters used to initialise the HTTP webserver side
@@ -49,37 +246,142 @@ pub struct Params {
     pub mail_broker: Sender<MailEvt>,
     /// Receiver stream of new mails added
     pub rx_mails: Receiver<Mail>,
-
-    #[cfg(feature = "faking")]
-    /// Sender stream to notify fake new mail
+    /// Settings that can be changed at runtime
+    pub settings: SharedSettings,
+    /// Token to give to use the management API, that is disabled without it
+    pub api_token: Option<String>,
+    /// Headless browser used to render the mails in images
+    #[cfg(feature = "render")]
+    pub render_browser: String,
+    /// Sinks notified of the events, in addition to the SSE connections
+    pub event_sinks: Vec<Arc<dyn EventSink<SseEvt>>>,
+    /// Sender stream to notify the injected or fake new mails
     pub tx_new_mail: Sender<Mail>,
+    /// Journal the mails are kept in, if they are persisted
+    pub journal: Option<Journal>,
+    /// Addresses the servers listen on, that change when they are rebound
+    pub listening: Arc<RwLock<Listening>>,
+    /// Exporter of the spans of the requests
+    pub tracer: Option<Tracer>,
+    /// Switch simulating an outage of the SMTP side
+    pub smtp_pause: Pause,
+    /// Delay of the SMTP replies
+    pub smtp_latency: Latency,
+    /// Greylisting of the SMTP recipients
+    pub greylist: Greylist,
+    /// Counters of the SMTP sessions
+    pub smtp_metrics: Metrics,
+    /// SMTP transactions cut off in the middle of their content
+    pub quarantine: Quarantine,
+    /// Capture of the SMTP mails under a heavy load
+    pub capture: Capture,
+    /// Optional features enabled
+    pub capabilities: Capabilities,
+    /// Theme of the web interface, unless another is asked with `?theme=`
+    pub theme: Theme,
+    /// Identifier of the mails given as `id`
+    pub ids: IdStrategy,
+    /// SMTP server the mails are released to
+    pub release: Option<Target>,
+    /// Queue of the mails received, waiting for the mail broker
+    pub queue: Queue,
+    /// Description of the command line, given by `/api/help`
+    pub help: Help,
+    /// Last activity, shared with the SMTP side
+    pub activity: Activity,
 }
 
 /// Initialize the HTTP webserver
 ///
 /// * Build SSE brokers
 /// * Add routes
-pub async fn init(params: Params) -> crate::Result<Server<State<SseEvt>>> {
+///
+/// The task notifying the event sinks of the new mails is returned with the
+/// server, it ends once the mails sent to `rx_mails` are all published.
+pub async fn init(params: Params) -> crate::Result<(Server<State<SseEvt>>, task::JoinHandle<()>)> {
     // Stream reader and writer for SSE notifications
     let sse_stream = BroadcastChannel::new();
 
-    let sse_stream_new_mail = sse_stream.clone();
-    let mut rx_mails: Receiver<Mail> = params.rx_mails;
-    let _mail_notification_task =
-        spawn_task_and_swallow_log_errors("Task: Mail notifier".into(), async move {
-            loop {
-                // To do on each received new mail
-                if let Some(mail) = rx_mails.next().await {
-                    log::info!(">>> Received new mail: {:?}", mail);
-                    // Append the mail to the list
-                    match sse_stream_new_mail.send(&SseEvt::NewMail(mail)).await {
-                        Ok(()) => log::trace!(">>> New mail notification sent to channel"),
-                        Err(e) => log::error!(">>> Err new mail notification to channel: {:?}", e),
-                    }
-                }
+    // Sinks notified of the events, beginning by the SSE connections
+    let mut events: EventBus<SseEvt> = EventBus::default();
+    events.register(Arc::new(SseSink::new(sse_stream.clone())));
+    for sink in params.event_sinks {
+        events.register(sink);
+    }
+
+    let notifications: Receiver<Mail> = params.rx_mails.clone();
+    let mail_notification_task: task::JoinHandle<()> =
+        spawn_mail_notifier(events.clone(), params.mail_broker.clone(), params.rx_mails)?;
+    spawn_sse_streamers(&sse_stream)?;
+
+    let state: State<SseEvt> = State {
+        sse_stream,
+        events,
+        sse_clients: SseClients::default(),
+        started: Utc::now(),
+        mail_broker: params.mail_broker,
+        settings: params.settings,
+        api_token: params.api_token,
+        #[cfg(feature = "render")]
+        render_browser: params.render_browser,
+        new_mail: params.tx_new_mail,
+        journal: params.journal,
+        listening: params.listening,
+        tracer: params.tracer,
+        purge_tokens: PurgeTokens::default(),
+        smtp_pause: params.smtp_pause,
+        smtp_latency: params.smtp_latency,
+        greylist: params.greylist,
+        smtp_metrics: params.smtp_metrics,
+        quarantine: params.quarantine,
+        capture: params.capture,
+        capabilities: params.capabilities,
+        theme: params.theme,
+        ids: params.ids,
+        release: params.release,
+        queue: params.queue,
+        help: Arc::new(params.help),
+        activity: params.activity,
+        notifications,
+    };
+
+    spawn_digest_sender(state.clone())?;
+
+    Ok((routes::init(state).await?, mail_notification_task))
+}
+
+/// Spawn the task publishing the new mails to the event sinks, until the mails
+/// sent to `rx_mails` are all published
+fn spawn_mail_notifier(
+    events: EventBus<SseEvt>,
+    mail_broker: Sender<MailEvt>,
+    mut rx_mails: Receiver<Mail>,
+) -> crate::Result<task::JoinHandle<()>> {
+    spawn_task_and_swallow_log_errors("Task: Mail notifier".into(), async move {
+        // To do on each received new mail, until the broker is gone
+        while let Some(mail) = rx_mails.next().await {
+            log::info!(">>> Received new mail: {:?}", mail);
+            let id: Ulid = mail.get_id();
+            let notifying: Instant = Instant::now();
+            // Append the mail to the list
+            events.publish(&SseEvt::NewMail(Box::new(mail))).await;
+            // The mail broker keeps the time spent by the mail in each stage
+            if let Err(e) = mail_broker
+                .send(MailEvt::Notified(id, notifying.elapsed()))
+                .await
+            {
+                log::debug!("Notification time of {} not recorded: {}", id, e);
             }
-        })?;
+        }
+        Ok(())
+    })
+    .map_err(MailcatcherError::http)
+}
 
+/// Spawn the tasks emptying the SSE stream, and sending it the pings
+fn spawn_sse_streamers(
+    sse_stream: &BroadcastChannel<SseEvt, UnboundedSender<SseEvt>, UnboundedReceiver<SseEvt>>,
+) -> crate::Result<()> {
     // Noop consumer to empty the ctream
     let mut sse_noop_stream = sse_stream.clone();
     let _noop_task =
@@ -89,7 +391,8 @@ pub async fn init(params: Params) -> crate::Result<Server<State<SseEvt>>> {
                 // Do nothing, it's just to empty the stream
                 let _sse_evt = sse_noop_stream.next().await;
             }
-        })?;
+        })
+        .map_err(MailcatcherError::http)?;
 
     // Task sending ping to SSE terminators
     let sse_tx_ping_stream = sse_stream.clone();
@@ -101,40 +404,72 @@ pub async fn init(params: Params) -> crate::Result<Server<State<SseEvt>>> {
                 sse_tx_ping_stream.send(&SseEvt::Ping).await?;
                 task::sleep(Duration::from_secs(10)).await;
             }
-        })?;
+        })
+        .map_err(MailcatcherError::http)?;
 
-    let state: State<SseEvt> = State {
-        sse_stream,
-        mail_broker: params.mail_broker,
-        #[cfg(feature = "faking")]
-        new_fake_mail: params.tx_new_mail,
-    };
+    Ok(())
+}
+
+/// Spawn the task sending the digest of the mails, for the browsers to detect
+/// they missed some events
+fn spawn_digest_sender(state: State<SseEvt>) -> crate::Result<()> {
+    let _sse_digest_task =
+        spawn_task_and_swallow_log_errors("Task: Digest SSE sender".into(), async move {
+            loop {
+                task::sleep(DIGEST_INTERVAL).await;
+                if state.sse_clients.stats().clients == 0 {
+                    continue;
+                }
+                match sse::tank_digest(&state).await {
+                    Ok(digest) => {
+                        log::trace!("Sending digest");
+                        state.sse_stream.send(&SseEvt::TankDigest(digest)).await?;
+                    }
+                    Err(e) => log::warn!("Digest of the mails not sent: {}", e),
+                }
+            }
+        })
+        .map_err(MailcatcherError::http)?;
 
-    Ok(routes::init(state).await?)
+    Ok(())
 }
 
-/// Bind the initialised webserver to the port then listen to incoming connection
-pub async fn bind<T>(app: Server<State<T>>, port: u16) -> crate::Result<()>
+/// Listen to incoming connection on the bound `listeners`, until `stop` is closed
+///
+/// Once stopped, the listeners are closed but the requests in progress go on.
+pub async fn bind<T>(
+    app: Server<State<T>>,
+    listeners: Vec<TcpListener>,
+    stop: Receiver<()>,
+) -> crate::Result<()>
 where
     T: Send + Clone + 'static,
 {
-    // Bind ports
-    let mut listener = app
-        .bind(
-            format!("localhost:{}", port)
-                .to_socket_addrs()
-                .await?
-                .collect::<Vec<SocketAddr>>(),
-        )
-        .await?;
+    let mut concurrent: ConcurrentListener<State<T>> = ConcurrentListener::new();
+    for listener in listeners {
+        concurrent.add(listener).map_err(MailcatcherError::http)?;
+    }
+    let mut listener = app.bind(concurrent).await.map_err(MailcatcherError::http)?;
     // Display binding ports
     for info in &listener.info() {
         log::info!("HTTP listening on {}", info);
     }
-    // Accept connections
-    listener.accept().await?;
-
-    unreachable!()
+    // Accept connections, each one is processed in its own task
+    let accepted: Option<std::io::Result<()>> = async { Some(listener.accept().await) }
+        .race(async {
+            stop.recv().await.unwrap_or_default();
+            None
+        })
+        .await;
+    accepted.map_or_else(
+        || {
+            for info in &listener.info() {
+                log::info!("HTTP stops listening on {}", info);
+            }
+            Ok(())
+        },
+        |result| result.map_err(MailcatcherError::http),
+    )
 }
 
 #[cfg(test)]
@@ -146,14 +481,28 @@ mod tests {
         path::{Path, PathBuf},
         prelude::FutureExt,
     };
+    use structopt::StructOpt;
     use tide::{
         http::{headers, mime, Method, Request, Response, Url},
         prelude::{json, Deserialize, Serialize},
         StatusCode,
     };
-    use ulid::Ulid;
 
-    use crate::mail::HeaderRepresentation;
+    use crate::{
+        mail::{
+            broker::{ContentTypeStats, LatencyStats, Removal, TankStats},
+            delivery::Simulator,
+            filter::Filter,
+            journal::Compaction,
+            thread::ThreadIndex,
+            timing::{Stage, Timing},
+            transcript::Transcript,
+            HeaderRepresentation, Type,
+        },
+        settings::Settings,
+        smtp::quarantine::{Cutoff, Incomplete},
+        utils::Timezone,
+    };
 
     use super::*;
 
@@ -173,17 +522,20 @@ mod tests {
         tx_mail_broker: Sender<MailEvt>,
         rx_mail_broker: Receiver<MailEvt>,
         tx_new_mail: Sender<Mail>,
-        #[cfg(feature = "faking")]
-        rx_mail_from_faking: Receiver<Mail>,
+        rx_mail_from_http: Receiver<Mail>,
+    }
+
+    async fn init() -> crate::test::Result<Init> {
+        init_with(None).await
     }
 
-    async fn init() -> crate::Result<Init> {
+    /// Init the HTTP side, keeping the injected mails in the `journal`
+    async fn init_with(journal: Option<Journal>) -> crate::test::Result<Init> {
         crate::test::log_init();
 
         let (tx_mail_broker, rx_mail_broker): crate::Channel<MailEvt> = channel::unbounded();
         let (tx_new_mail, rx_new_mail): crate::Channel<Mail> = channel::unbounded();
-        #[cfg(feature = "faking")]
-        let (tx_mail_from_faking, rx_mail_from_faking): crate::Channel<Mail> = channel::unbounded();
+        let (tx_mail_from_http, rx_mail_from_http): crate::Channel<Mail> = channel::unbounded();
 
         // Provide some mails
         let mut mails: Vec<Mail> = Vec::new();
@@ -195,18 +547,61 @@ mod tests {
         let params: Params = Params {
             mail_broker: tx_mail_broker.clone(),
             rx_mails: rx_new_mail,
-            #[cfg(feature = "faking")]
-            tx_new_mail: tx_mail_from_faking,
+            settings: SharedSettings::new(Settings {
+                broker_timeout: 500,
+                ..Settings::default()
+            }),
+            api_token: Some("secret".to_owned()),
+            #[cfg(feature = "render")]
+            render_browser: "false".to_owned(),
+            event_sinks: Vec::new(),
+            tx_new_mail: tx_mail_from_http,
+            journal,
+            listening: Arc::new(RwLock::new(Listening {
+                smtp: vec!["127.0.0.1:1025".parse()?],
+                http: vec!["127.0.0.1:1080".parse()?, "[::1]:1080".parse()?],
+                smtp_failures: vec![BindFailure {
+                    addr: "[::1]:1025".parse()?,
+                    error: "Address already in use".to_owned(),
+                }],
+                http_failures: Vec::new(),
+                smtp_profile: Profile::Normal,
+                smtp_extra: vec![ProfiledListening {
+                    profile: Profile::AlwaysTempfail,
+                    addresses: vec!["127.0.0.1:1026".parse()?],
+                    failures: Vec::new(),
+                }],
+            })),
+            tracer: None,
+            smtp_pause: Pause::default(),
+            smtp_latency: Latency::default(),
+            greylist: Greylist::new(Duration::default()),
+            smtp_metrics: Metrics::default(),
+            quarantine: Quarantine::default(),
+            capture: Capture::new(Some("1/h".parse()?), None),
+            capabilities: Capabilities {
+                persistence: true,
+                ..Capabilities::default()
+            },
+            theme: Theme::default(),
+            ids: IdStrategy::default(),
+            release: Some(Target {
+                // Nothing listens on this port
+                server: "127.0.0.1:1".to_owned(),
+                credentials: None,
+            }),
+            queue: Queue::default(),
+            help: Help::new(&crate::Opt::clap()),
+            activity: Activity::default(),
         };
 
         Ok(Init {
-            app: super::init(params).await?,
+            app: super::init(params).await?.0,
             mails,
             tx_mail_broker,
             rx_mail_broker,
             tx_new_mail,
-            #[cfg(feature = "faking")]
-            rx_mail_from_faking,
+            rx_mail_from_http,
         })
     }
 
@@ -223,8 +618,8 @@ mod tests {
 
     #[test]
     #[allow(clippy::panic)]
-    fn assets_routes() -> crate::Result<()> {
-        async fn the_test() -> crate::Result<()> {
+    fn assets_routes() -> crate::test::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
             let Init { app, .. } = init().await?;
 
             // Assets
@@ -266,8 +661,8 @@ mod tests {
 
     #[test]
     #[allow(clippy::panic)]
-    fn inflate_route() -> crate::Result<()> {
-        async fn the_test() -> crate::Result<()> {
+    fn inflate_route() -> crate::test::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
             let Init { app, .. } = init().await?;
 
             // Build request
@@ -312,7 +707,7 @@ mod tests {
     #[test]
     #[allow(clippy::panic)]
     fn all_mails_route() -> std::io::Result<()> {
-        async fn the_test(app: Server<State<SseEvt>>, mails: Vec<Mail>) -> crate::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, mails: Vec<Mail>) -> crate::test::Result<()> {
             // Build request
             let request: Request = Request::new(Method::Get, Url::parse("http://localhost/mails")?);
             // Send request and retrieve response
@@ -329,7 +724,7 @@ mod tests {
             let mut mails: Vec<MailSummary> = mails
                 .iter()
                 .map(|mail| {
-                    serde_json::from_value::<MailSummary>(mail.summary())
+                    serde_json::from_value::<MailSummary>(mail.summary(IdStrategy::Ulid))
                         .map_err(|e| format!("{:?}", e))
                 })
                 .collect::<Result<Vec<_>, _>>()?;
@@ -378,6 +773,322 @@ mod tests {
         )
     }
 
+    #[test]
+    #[allow(clippy::panic, clippy::indexing_slicing)]
+    fn mails_route_timezone() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, mails: Vec<Mail>) -> crate::test::Result<()> {
+            // Invalid timezone
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/mails?tz=Mars/Olympus")?,
+            );
+            let response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+
+            // Fixed offset, the + must be url encoded
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/mails?tz=%2B02:00")?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            let summaries: Vec<serde_json::Value> =
+                serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(summaries.len(), mails.len());
+            for summary in summaries {
+                let mail: &Mail = mails
+                    .iter()
+                    .find(|mail| summary["id"] == mail.get_id().to_string())
+                    .ok_or("unknown mail")?;
+                // Both the raw epoch and the formatted date are available
+                assert_eq!(summary["date"], mail.get_date().timestamp());
+                assert_eq!(
+                    summary["date_formatted"],
+                    mail.get_date()
+                        .with_timezone(&chrono::FixedOffset::east(7200))
+                        .to_rfc3339()
+                );
+            }
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mails,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mails_broker = mails.clone();
+
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetAll(sender) => {
+                            for mail in &mails_broker {
+                                sender.send(mail.clone()).await?;
+                            }
+                            drop(sender);
+                        }
+                        _ => unreachable!("MailEvt is not GetAll"),
+                    }
+                }
+            }
+            .race(the_test(app, mails)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn mails_route_sorted() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, mails: Vec<Mail>) -> crate::test::Result<()> {
+            let request: Request =
+                Request::new(Method::Get, Url::parse("http://localhost/mails?sort=sent")?);
+            let response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+
+            // The newest declared dates first, both dates being given
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/mails?sort=declared&desc=true")?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            let summaries: Vec<serde_json::Value> =
+                serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(summaries.len(), mails.len());
+            let declared: Vec<i64> = summaries
+                .iter()
+                .filter_map(|summary| summary.get("declared").and_then(serde_json::Value::as_i64))
+                .collect();
+            let mut expected: Vec<i64> = mails
+                .iter()
+                .filter_map(|mail| mail.get_declared().map(|date| date.timestamp()))
+                .collect();
+            expected.sort_unstable_by(|a, b| b.cmp(a));
+            assert_eq!(declared, expected);
+            for summary in &summaries {
+                let mail: &Mail = mails
+                    .iter()
+                    .find(|mail| summary.get("ulid") == Some(&json!(mail.get_id().to_string())))
+                    .ok_or("unknown mail")?;
+                assert_eq!(
+                    summary.get("received"),
+                    Some(&json!(mail.get_received().timestamp()))
+                );
+            }
+
+            // The reception order
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/mails?sort=received")?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            let summaries: Vec<serde_json::Value> =
+                serde_json::from_str(&response.body_string().await?)?;
+            let numbers: Vec<u64> = summaries
+                .iter()
+                .filter_map(|summary| summary.get("number").and_then(serde_json::Value::as_u64))
+                .collect();
+            let mut expected: Vec<u64> = mails.iter().map(Mail::get_number).collect();
+            expected.sort_unstable();
+            assert_eq!(numbers, expected);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mails,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        // Sent in the reverse order, to be sorted by the route
+        let mut mails_broker = mails.clone();
+        mails_broker.reverse();
+
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetAll(sender) => {
+                            for mail in &mails_broker {
+                                sender.send(mail.clone()).await?;
+                            }
+                            drop(sender);
+                        }
+                        _ => unreachable!("MailEvt is not GetAll"),
+                    }
+                }
+            }
+            .race(the_test(app, mails)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic, clippy::indexing_slicing)]
+    fn stats_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            let request: Request =
+                Request::new(Method::Get, Url::parse("http://localhost/api/stats")?);
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(
+                response
+                    .header(headers::CONTENT_TYPE)
+                    .ok_or("Content-Type header unavailable")?,
+                &mime::JSON.to_string()
+            );
+
+            let stats: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                stats,
+                json!({
+                    "mails": 2,
+                    "size": 300,
+                    "latency": {"count": 1, "min": 1500, "max": 1500, "mean": 1500},
+                    "content_types": {
+                        "text_only": 1,
+                        "html_only": 1,
+                        "text_and_html": 0,
+                        "multipart": 0,
+                        "with_attachments": 0,
+                    },
+                    "sse": {"clients": 0, "connections": []},
+                })
+            );
+
+            // Only the kinds of content
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/api/stats/content-types")?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            let stats: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(stats["html_only"], 1);
+            assert_eq!(stats["text_only"], 1);
+            assert!(stats.get("mails").is_none());
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetStats(sender) => {
+                            sender
+                                .send(TankStats {
+                                    mails: 2,
+                                    size: 300,
+                                    latency: LatencyStats {
+                                        count: 1,
+                                        min: Some(1500),
+                                        max: Some(1500),
+                                        mean: Some(1500),
+                                    },
+                                    content_types: ContentTypeStats {
+                                        text_only: 1,
+                                        html_only: 1,
+                                        ..ContentTypeStats::default()
+                                    },
+                                })
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetStats"),
+                    }
+                }
+            }
+            .race(the_test(app)),
+        )
+    }
+
+    #[test]
+    fn metrics_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            let metrics: &Metrics = &app.state().smtp_metrics;
+            metrics.connection();
+            metrics.command("EHLO");
+            metrics.command("RCPT");
+            metrics.rejected();
+            metrics.received(120);
+            metrics.content(100);
+
+            let request: Request =
+                Request::new(Method::Get, Url::parse("http://localhost/metrics")?);
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let body: String = response.body_string().await?;
+            for line in &[
+                "# TYPE mailcatcher_smtp_connections_total counter",
+                "mailcatcher_smtp_connections_total 1",
+                "mailcatcher_smtp_commands_total{verb=\"EHLO\"} 1",
+                "mailcatcher_smtp_commands_total{verb=\"RCPT\"} 1",
+                "mailcatcher_smtp_received_bytes_total 120",
+                "mailcatcher_smtp_rejected_commands_total 1",
+                "mailcatcher_smtp_data_size_mean_bytes 100",
+                "mailcatcher_mails 2",
+                "mailcatcher_mails_size_bytes 300",
+            ] {
+                assert!(body.lines().any(|l| l == *line), "{} not in {}", line, body);
+            }
+
+            let request: Request =
+                Request::new(Method::Get, Url::parse("http://localhost/api/metrics")?);
+            let mut response: Response = app.respond(request).await?;
+            let metrics: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(metrics.pointer("/smtp/connections"), Some(&json!(1)));
+            assert_eq!(metrics.pointer("/smtp/commands/EHLO"), Some(&json!(1)));
+            assert_eq!(metrics.pointer("/smtp/mean_data_size"), Some(&json!(100)));
+            assert_eq!(
+                metrics.pointer("/tank"),
+                Some(&json!({"mails": 2, "size": 300}))
+            );
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetStats(sender) => {
+                            sender
+                                .send(TankStats {
+                                    mails: 2,
+                                    size: 300,
+                                    ..TankStats::default()
+                                })
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetStats"),
+                    }
+                }
+            }
+            .race(the_test(app)),
+        )
+    }
+
     #[test]
     #[allow(clippy::panic)]
     fn one_nonexistent_mail_route() -> std::io::Result<()> {
@@ -388,7 +1099,7 @@ mod tests {
             data: String,
         }
 
-        async fn the_test(app: Server<State<SseEvt>>) -> crate::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
             // -------------------
             // Non existent mail id
 
@@ -442,7 +1153,7 @@ mod tests {
             data: String,
         }
 
-        async fn the_test(app: Server<State<SseEvt>>, mails: Vec<Mail>) -> crate::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, mails: Vec<Mail>) -> crate::test::Result<()> {
             #[allow(clippy::indexing_slicing)]
             let mail: &Mail = &mails[0];
 
@@ -471,15 +1182,60 @@ mod tests {
             let txt: MailAll = serde_json::from_str(&response.body_string().await?)?;
             assert_eq!(mail, txt);
 
-            Ok(())
-        }
-
-        let Init {
-            app,
-            mails,
-            mut rx_mail_broker,
+            // Header block as received
+            #[allow(clippy::indexing_slicing)]
+            let first: &Mail = &mails[0];
+            let raw_headers: &str = first.get_raw_headers().ok_or("no raw headers")?;
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse(&format!(
+                    "http://localhost/mail/{}/headers.raw",
+                    first.get_id()
+                ))?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(
+                response
+                    .content_type()
+                    .map(|mime| mime.essence().to_owned()),
+                Some(mime::PLAIN.essence().to_owned())
+            );
+            assert_eq!(
+                response.body_bytes().await?,
+                crate::encoding::encode_8bit(raw_headers)
+            );
+
+            // Header received with a byte that is not valid UTF-8
+            let latin1: &Mail = mails.last().ok_or("no 8-bit mail")?;
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse(&format!(
+                    "http://localhost/mail/{}/headers.raw",
+                    latin1.get_id()
+                ))?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(
+                response.body_bytes().await?,
+                b"Subject: Caf\xe9\r\nFrom: <from@example.org>\r\n".to_vec()
+            );
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut mails,
+            mut rx_mail_broker,
             ..
         } = task::block_on(init()).expect("Init");
+        mails.push(Mail::new(
+            "from@example.org",
+            &["to@example.org".to_owned()],
+            &crate::encoding::decode_8bit(
+                b"Subject: Caf\xe9\r\nFrom: <from@example.org>\r\n\r\nBody",
+            ),
+        ));
 
         let mails_broker = mails.clone();
         crate::test::with_timeout(
@@ -504,73 +1260,2074 @@ mod tests {
         )
     }
 
-    #[cfg(feature = "faking")]
     #[test]
     #[allow(clippy::panic)]
-    fn test_faking_routes() -> std::io::Result<()> {
-        async fn the_test() -> crate::Result<()> {
-            let Init {
-                app,
-                mut rx_mail_from_faking,
-                ..
-            } = init().await?;
+    fn search_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            // Invalid priority
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/mails/search?priority=whenever")?,
+            );
+            let response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
 
-            // Without number of mail
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/mails/search?priority=high")?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            let summaries: Vec<serde_json::Value> =
+                serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(summaries.len(), 1);
+            assert_eq!(summaries.first().ok_or("no mail")?["priority"], "high");
 
-            // Build request
-            let request: Request = Request::new(Method::Get, Url::parse("http://localhost/fake")?);
+            // Search query
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/mails/search?q=subject%3Alater%20whenever")?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            let summaries: Vec<serde_json::Value> =
+                serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(summaries.len(), 1);
+            assert_eq!(summaries.first().ok_or("no mail")?["subject"], "Later");
 
-            // Send request and retrieve response
+            // Invalid search query
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/mails/search?q=size%3A10")?,
+            );
+            let response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mails: Vec<Mail> = vec![
+            Mail::new("", &[], "Importance: high\r\n\r\nNow!"),
+            Mail::new("", &[], "Subject: Later\r\n\r\nWhenever"),
+        ];
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::Search(sender, filter) => {
+                            for mail in mails.iter().filter(|mail| filter.matches(mail)) {
+                                sender.send(mail.clone()).await?;
+                            }
+                            drop(sender);
+                        }
+                        _ => unreachable!("MailEvt is not Search"),
+                    }
+                }
+            }
+            .race(the_test(app)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn correlation_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, id: Ulid) -> crate::test::Result<()> {
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/api/by-correlation/ABC-123")?,
+            );
             let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let summaries: Vec<serde_json::Value> =
+                serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(summaries.len(), 1);
+            assert_eq!(summaries.first().ok_or("no mail")?["id"], id.to_string());
 
-            let body = response.body_string().await?;
-            assert_eq!(body, "OK: 1");
+            Ok(())
+        }
 
-            assert_eq!(rx_mail_from_faking.len(), 1);
-            let fake_mail_1 = rx_mail_from_faking.next().await.ok_or("no fake mail")?;
-            assert!(fake_mail_1
-                .get_text()
-                .ok_or("no data text")?
-                .starts_with("Lorem ipsum dolor sit "));
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
 
-            // With 1 mail
+        let mail: Mail = Mail::new("", &[], "X-Request-Id: abc-123\r\n\r\nDone");
+        let id: Ulid = mail.get_id();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::ByCorrelation(sender, correlation) => {
+                            if mail.get_correlation_ids().contains(&correlation) {
+                                sender.send(mail.clone()).await?;
+                            }
+                            drop(sender);
+                        }
+                        _ => unreachable!("MailEvt is not ByCorrelation"),
+                    }
+                }
+            }
+            .race(the_test(app, id)),
+        )
+    }
 
-            // Build request
+    #[test]
+    #[allow(clippy::panic)]
+    fn threads_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, ids: Vec<Ulid>) -> crate::test::Result<()> {
             let request: Request =
-                Request::new(Method::Get, Url::parse("http://localhost/fake/1")?);
+                Request::new(Method::Get, Url::parse("http://localhost/mails/threads")?);
             let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let threads: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(threads.as_array().map(Vec::len), Some(2));
+            // The conversation with the newest mail first
+            assert_eq!(
+                threads.pointer("/0/message_id"),
+                Some(&json!("3@example.net"))
+            );
+            assert_eq!(threads.pointer("/0/count"), Some(&json!(1)));
+            assert_eq!(
+                threads.pointer("/1/message_id"),
+                Some(&json!("1@example.net"))
+            );
+            assert_eq!(threads.pointer("/1/subject"), Some(&json!("Question")));
+            let mail_ids: Vec<&str> = threads
+                .pointer("/1/mails")
+                .and_then(serde_json::Value::as_array)
+                .ok_or("no mails")?
+                .iter()
+                .filter_map(|mail| mail.get("id").and_then(serde_json::Value::as_str))
+                .collect();
+            assert_eq!(
+                mail_ids,
+                ids.iter()
+                    .take(2)
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+            );
 
-            assert_eq!(rx_mail_from_faking.len(), 1);
-            let fake_mail_2 = rx_mail_from_faking.next().await.ok_or("no fake mail")?;
-            assert!(fake_mail_2
-                .get_text()
-                .ok_or("no data text")?
-                .starts_with("Lorem ipsum dolor sit "));
+            Ok(())
+        }
 
-            let body = response.body_string().await?;
-            assert_eq!(body, "OK: 1");
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
 
-            // With 11 mail
+        let mails: Vec<Mail> = vec![
+            Mail::new(
+                "",
+                &[],
+                "Message-Id: <1@example.net>\r\nSubject: Question\r\n\r\n?",
+            ),
+            Mail::new(
+                "",
+                &[],
+                "Message-Id: <2@example.net>\r\nIn-Reply-To: <1@example.net>\r\n\r\nYes",
+            ),
+            Mail::new("", &[], "Message-Id: <3@example.net>\r\n\r\nOther"),
+        ];
+        let mut index: ThreadIndex = ThreadIndex::default();
+        for mail in &mails {
+            index.insert(mail);
+        }
+        let ids: Vec<Ulid> = mails.iter().map(Mail::get_id).collect();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetThreads(sender) => sender.send(index.clone()).await?,
+                        MailEvt::GetAll(sender) => {
+                            for mail in &mails {
+                                sender.send(mail.clone()).await?;
+                            }
+                            drop(sender);
+                        }
+                        _ => unreachable!("MailEvt is not GetThreads or GetAll"),
+                    }
+                }
+            }
+            .race(the_test(app, ids)),
+        )
+    }
+
+    #[test]
+    fn deliveries_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
             let request: Request =
-                Request::new(Method::Get, Url::parse("http://localhost/fake/11")?);
+                Request::new(Method::Get, Url::parse("http://localhost/api/deliveries")?);
             let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let report: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                report,
+                json!({
+                    "mails": 1,
+                    "recipients": 2,
+                    "delivered": 0,
+                    "deferred": 2,
+                    "bounced": 0,
+                    "domains": {
+                        "example.net": {"delivered": 0, "deferred": 1, "bounced": 0},
+                        "example.org": {"delivered": 0, "deferred": 1, "bounced": 0},
+                    },
+                })
+            );
 
-            assert_eq!(rx_mail_from_faking.len(), 11);
-            let mut mails = Vec::new();
-            for _ in 0..11 {
-                mails.push(rx_mail_from_faking.next().await.ok_or("no fake mail")?);
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let to: Vec<String> = vec!["a@example.net".to_owned(), "b@example.org".to_owned()];
+        let mut deferred: Mail = Mail::new("", &to, "Subject: Deferred\r\n\r\n");
+        Simulator::new("deferred=1".parse().expect("Weights")).mark(&mut deferred);
+        // Not simulated, like a mail received before
+        let mails: Vec<Mail> = vec![deferred, Mail::new("", &to, "Subject: Other\r\n\r\n")];
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetAll(sender) => {
+                            for mail in &mails {
+                                sender.send(mail.clone()).await?;
+                            }
+                            drop(sender);
+                        }
+                        _ => unreachable!("MailEvt is not GetAll"),
+                    }
+                }
             }
-            assert_eq!(mails.len(), 11);
+            .race(the_test(app)),
+        )
+    }
 
-            let body = response.body_string().await?;
-            assert_eq!(body, "OK: 11");
+    #[test]
+    #[allow(clippy::panic)]
+    fn numbered_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, mail: Mail) -> crate::test::Result<()> {
+            let request = |path: String| {
+                Request::new(
+                    Method::Post,
+                    Url::parse(&format!("http://localhost{}", path)).expect("numbered url"),
+                )
+            };
+
+            // The mail is designated by its number, and given back the same way
+            let mut response: Response = app
+                .respond(request(format!("/mail/{}/lock", mail.get_number())))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({"id": mail.get_id().to_string(), "locked": true})
+            );
+            let response: Response = app
+                .respond(request(format!(
+                    "/mail/{}/lock",
+                    mail.get_number().saturating_add(1)
+                )))
+                .await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
 
-            // No more waiting in the fake stream
-            assert!(rx_mail_from_faking.is_empty());
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mail: Mail = Mail::fake();
+        let tank: Mail = mail.clone();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::ByNumber(sender, number) => {
+                            if number == tank.get_number() {
+                                sender.send(tank.get_id()).await?;
+                            }
+                        }
+                        MailEvt::Lock(sender, id, locked) => {
+                            let mut locked_mail: Mail = tank.clone();
+                            locked_mail.set_locked(locked);
+                            sender
+                                .send(Some(locked_mail).filter(|_| id == tank.get_id()))
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not ByNumber or Lock"),
+                    }
+                }
+            }
+            .race(the_test(app, mail)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn export_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, mail: Mail) -> crate::test::Result<()> {
+            let request = |body: serde_json::Value| {
+                let mut request: Request = Request::new(
+                    Method::Post,
+                    Url::parse("http://localhost/api/export").expect("export url"),
+                );
+                request.set_body(body);
+                request
+            };
+
+            // By ULID and by number, the same mail exported once
+            let mut response: Response = app
+                .respond(request(json!({
+                    "ids": [mail.get_id().to_string(), mail.get_number().to_string()],
+                    "tags": ["signup"],
+                })))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                response
+                    .content_type()
+                    .map(|mime| mime.essence().to_owned()),
+                Some("application/x-tar".to_owned())
+            );
+            let tar: Vec<u8> = response.body_bytes().await?;
+            let mut expected: crate::mail::bundle::Bundle =
+                crate::mail::bundle::Bundle::new(vec!["signup".to_owned()]);
+            expected.add(mail.clone());
+            assert_eq!(tar, expected.tar());
+
+            // Unknown mail
+            let response: Response = app
+                .respond(request(json!({"ids": [Ulid::new().to_string()]})))
+                .await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            // Nothing to export
+            let response: Response = app.respond(request(json!({"ids": []}))).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
 
             Ok(())
         }
 
-        crate::test::with_timeout(5_000, the_test())
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mail: Mail = Mail::fake();
+        let tank: Mail = mail.clone();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::ByNumber(sender, number) => {
+                            if number == tank.get_number() {
+                                sender.send(tank.get_id()).await?;
+                            }
+                        }
+                        MailEvt::GetMail(sender, id) => {
+                            sender
+                                .send(Some(tank.clone()).filter(|_| id == tank.get_id()))
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not ByNumber or GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, mail)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn purge_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            let purge = |query: &str| {
+                Request::new(
+                    Method::Post,
+                    Url::parse(&format!("http://localhost/api/mails/purge{}", query))
+                        .expect("purge url"),
+                )
+            };
+
+            // A GET is no more enough to remove all the mails
+            let request: Request =
+                Request::new(Method::Get, Url::parse("http://localhost/remove/all")?);
+            let response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            let mut response: Response = app.respond(purge("")).await?;
+            assert_eq!(response.status(), StatusCode::Accepted);
+            let confirmation: serde_json::Value =
+                serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(confirmation.get("expires_in"), Some(&json!(60)));
+            let token: &str = confirmation
+                .get("token")
+                .and_then(serde_json::Value::as_str)
+                .ok_or("no token")?;
+
+            let response: Response = app.respond(purge("?token=unknown")).await?;
+            assert_eq!(response.status(), StatusCode::Forbidden);
+
+            let mut response: Response = app.respond(purge(&format!("?token={}", token))).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(response.body_string().await?, r#"{"removed":2}"#);
+
+            // The token is used only once
+            let response: Response = app.respond(purge(&format!("?token={}", token))).await?;
+            assert_eq!(response.status(), StatusCode::Forbidden);
+
+            let mut response: Response = app.respond(purge("?force=true")).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(response.body_string().await?, r#"{"removed":2}"#);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::RemoveAll(sender) => {
+                            sender.send(Ulid::new()).await?;
+                            sender.send(Ulid::new()).await?;
+                            drop(sender);
+                        }
+                        _ => unreachable!("MailEvt is not RemoveAll"),
+                    }
+                }
+            }
+            .race(the_test(app)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn lock_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, id: Ulid) -> crate::test::Result<()> {
+            let request = |method: Method, path: String| {
+                Request::new(
+                    method,
+                    Url::parse(&format!("http://localhost{}", path)).expect("lock url"),
+                )
+            };
+
+            let mut response: Response = app
+                .respond(request(Method::Post, format!("/mail/{}/lock", id)))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({"id": id.to_string(), "locked": true})
+            );
+            let response: Response = app
+                .respond(request(Method::Get, format!("/remove/{}", id)))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Locked);
+
+            let response: Response = app
+                .respond(request(Method::Delete, format!("/mail/{}/lock", id)))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let response: Response = app
+                .respond(request(Method::Post, format!("/mail/{}/lock", Ulid::new())))
+                .await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mut mail: Mail = Mail::fake();
+        let id: Ulid = mail.get_id();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::Lock(sender, lock_id, locked) => {
+                            if lock_id == mail.get_id() {
+                                mail.set_locked(locked);
+                                sender.send(Some(mail.clone())).await?;
+                            } else {
+                                sender.send(None).await?;
+                            }
+                        }
+                        MailEvt::Remove(sender, _) => {
+                            sender
+                                .send(if mail.is_locked() {
+                                    Removal::Locked
+                                } else {
+                                    Removal::Removed
+                                })
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not Lock or Remove"),
+                    }
+                }
+            }
+            .race(the_test(app, id)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn timing_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, id: Ulid) -> crate::test::Result<()> {
+            let request = |id: Ulid| {
+                Request::new(
+                    Method::Get,
+                    Url::parse(&format!("http://localhost/mail/{}/timing", id))
+                        .expect("timing url"),
+                )
+            };
+
+            let mut response: Response = app.respond(request(id)).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({
+                    "id": id.to_string(),
+                    "stages": [
+                        {"stage": "receive", "micros": 1_500},
+                        {"stage": "parse", "micros": 200},
+                    ],
+                    "total_micros": 1_700,
+                })
+            );
+            let response: Response = app.respond(request(Ulid::new())).await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let id: Ulid = Ulid::new();
+        let mut timing: Timing = Timing::default();
+        timing.record(Stage::Receive, Duration::from_micros(1_500));
+        timing.record(Stage::Parse, Duration::from_micros(200));
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetTiming(sender, timing_id) => {
+                            sender
+                                .send(Some(timing.clone()).filter(|_| timing_id == id))
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetTiming"),
+                    }
+                }
+            }
+            .race(the_test(app, id)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn mail_errors_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, id: Ulid) -> crate::test::Result<()> {
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse(&format!("http://localhost/mail/{}/errors", id))?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let errors: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                errors,
+                json!([
+                    {"kind": "invalid_header", "line": 1, "content": "Not a header"},
+                    {"kind": "invalid_date", "value": "now"},
+                ])
+            );
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mail: Mail = Mail::new("", &[], "Not a header\r\nDate: now\r\n\r\nBody");
+        let id: Ulid = mail.get_id();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, _) => {
+                            sender.send(Some(mail.clone())).await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, id)),
+        )
+    }
+
+    #[test]
+    fn strict_decoding_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, id: Ulid) -> crate::test::Result<()> {
+            let request = |query: &str| -> crate::test::Result<Request> {
+                Ok(Request::new(
+                    Method::Get,
+                    Url::parse(&format!("http://localhost/mail/{}/text{}", id, query))?,
+                ))
+            };
+
+            // Replaced by default
+            let mut response: Response = app.respond(request("")?).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(response.body_string().await?, "Caf\u{FFFD} au lait");
+
+            let mut response: Response = app.respond(request("?lossy=false")?).await?;
+            assert_eq!(response.status(), StatusCode::UnprocessableEntity);
+            let report: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                report.pointer("/report"),
+                Some(&json!({"charset": "utf-8", "offset": 3, "bytes": "E9", "invalid": 1}))
+            );
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        // "Café" in Latin-1, declared in UTF-8
+        let mail: Mail = Mail::new(
+            "",
+            &[],
+            "Content-Type: text/plain; charset=utf-8\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\
+\r\n\
+Caf=E9 au lait",
+        );
+        let id: Ulid = mail.get_id();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, _) => {
+                            sender.send(Some(mail.clone())).await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, id)),
+        )
+    }
+
+    #[cfg(feature = "faking")]
+    #[test]
+    #[allow(clippy::panic)]
+    fn test_faking_routes() -> std::io::Result<()> {
+        async fn the_test(path: &std::path::Path) -> crate::test::Result<()> {
+            let journal: Journal = Journal::open(path, 0).await?;
+            let Init {
+                app,
+                mut rx_mail_from_http,
+                ..
+            } = init_with(Some(journal.clone())).await?;
+
+            // Without number of mail
+
+            // Build request
+            let request: Request = Request::new(Method::Get, Url::parse("http://localhost/fake")?);
+
+            // Send request and retrieve response
+            let mut response: Response = app.respond(request).await?;
+
+            let body = response.body_string().await?;
+            assert_eq!(body, "OK: 1");
+
+            assert_eq!(rx_mail_from_http.len(), 1);
+            let fake_mail_1 = rx_mail_from_http.next().await.ok_or("no fake mail")?;
+            assert!(fake_mail_1
+                .get_text()
+                .ok_or("no data text")?
+                .starts_with("Lorem ipsum dolor sit "));
+
+            // With 1 mail
+
+            // Build request
+            let request: Request =
+                Request::new(Method::Get, Url::parse("http://localhost/fake/1")?);
+            let mut response: Response = app.respond(request).await?;
+
+            assert_eq!(rx_mail_from_http.len(), 1);
+            let fake_mail_2 = rx_mail_from_http.next().await.ok_or("no fake mail")?;
+            assert!(fake_mail_2
+                .get_text()
+                .ok_or("no data text")?
+                .starts_with("Lorem ipsum dolor sit "));
+
+            let body = response.body_string().await?;
+            assert_eq!(body, "OK: 1");
+
+            // With 11 mail
+            let request: Request =
+                Request::new(Method::Get, Url::parse("http://localhost/fake/11")?);
+            let mut response: Response = app.respond(request).await?;
+
+            assert_eq!(rx_mail_from_http.len(), 11);
+            let mut mails = Vec::new();
+            for _ in 0..11 {
+                mails.push(rx_mail_from_http.next().await.ok_or("no fake mail")?);
+            }
+            assert_eq!(mails.len(), 11);
+
+            let body = response.body_string().await?;
+            assert_eq!(body, "OK: 11");
+
+            // No more waiting in the fake stream
+            assert!(rx_mail_from_http.is_empty());
+
+            // The fake mails are restored after a restart
+            assert_eq!(journal.replay().await?.len(), 13);
+
+            Ok(())
+        }
+
+        let path: std::path::PathBuf =
+            env::temp_dir().join(format!("mailcatcher-{}.journal", Ulid::new()));
+        let result: std::io::Result<()> = crate::test::with_timeout(5_000, the_test(&path));
+        fs::remove_file(&path).unwrap_or_default();
+        result
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    #[allow(clippy::panic)]
+    fn render_route() -> std::io::Result<()> {
+        async fn status(
+            app: &Server<State<SseEvt>>,
+            id: Ulid,
+            query: &str,
+        ) -> crate::test::Result<StatusCode> {
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse(&format!("http://localhost/mail/{}/render.png{}", id, query))?,
+            );
+            let response: Response = app.respond(request).await?;
+            Ok(response.status())
+        }
+
+        async fn the_test(
+            app: Server<State<SseEvt>>,
+            html: Ulid,
+            text: Ulid,
+        ) -> crate::test::Result<()> {
+            assert_eq!(
+                status(&app, html, "?width=0").await?,
+                StatusCode::BadRequest
+            );
+            assert_eq!(status(&app, text, "").await?, StatusCode::NotFound);
+            // The browser of the tests always fails
+            assert_eq!(
+                status(&app, html, "?width=320").await?,
+                StatusCode::InternalServerError
+            );
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mails: Vec<Mail> = vec![
+            Mail::new("", &[], "Content-Type: text/html\r\n\r\n<p>Hello</p>"),
+            Mail::new("", &[], "Content-Type: text/plain\r\n\r\nHello"),
+        ];
+        let (html, text): (Ulid, Ulid) = (
+            mails.first().map(Mail::get_id).expect("html"),
+            mails.last().map(Mail::get_id).expect("text"),
+        );
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, id) => {
+                            sender
+                                .send(mails.iter().find(|mail| mail.get_id() == id).cloned())
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, html, text)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn inject_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init {
+                app,
+                mut rx_mail_from_http,
+                ..
+            } = init().await?;
+
+            // The envelope is taken from the headers
+            let mut request: Request =
+                Request::new(Method::Post, Url::parse("http://localhost/api/mails")?);
+            request.set_body(
+                "From: Alice <alice@example.org>\nTo: \"Bob, Jr\" <bob@example.org>, carol@example.org\n\
+Cc: dave@example.org\nSubject: Injected\n\nHello",
+            );
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Created);
+            let reply: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            let mail: Mail = rx_mail_from_http.next().await.ok_or("no injected mail")?;
+            assert_eq!(reply, json!({"id": mail.get_id().to_string()}));
+            assert_eq!(mail.from(), "alice@example.org");
+            assert_eq!(
+                mail.to(),
+                &vec![
+                    "bob@example.org".to_owned(),
+                    "carol@example.org".to_owned(),
+                    "dave@example.org".to_owned()
+                ]
+            );
+            assert_eq!(mail.get_subject(), "Injected");
+            assert_eq!(
+                mail.get_data(&Type::Raw).ok_or("no raw")?,
+                "From: Alice <alice@example.org>\r\nTo: \"Bob, Jr\" <bob@example.org>, carol@example.org\r\n\
+Cc: dave@example.org\r\nSubject: Injected\r\n\r\nHello"
+            );
+
+            // ... or from the query
+            let mut request: Request = Request::new(
+                Method::Post,
+                Url::parse("http://localhost/api/mails?from=eve@x.test&to=frank@x.test")?,
+            );
+            request.set_body("From: alice@example.org\r\n\r\nHello");
+            let response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Created);
+            let mail: Mail = rx_mail_from_http.next().await.ok_or("no injected mail")?;
+            assert_eq!(mail.from(), "eve@x.test");
+            assert_eq!(mail.to(), &vec!["frank@x.test".to_owned()]);
+
+            // Nothing to inject
+            let request: Request =
+                Request::new(Method::Post, Url::parse("http://localhost/api/mails")?);
+            let response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+            assert!(rx_mail_from_http.is_empty());
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn inject_journal() -> crate::test::Result<()> {
+        let path: std::path::PathBuf =
+            env::temp_dir().join(format!("mailcatcher-{}.journal", Ulid::new()));
+
+        let result: crate::test::Result<(Mail, Vec<Mail>)> = task::block_on(async {
+            let journal: Journal = Journal::open(&path, 0).await?;
+            let Init {
+                app,
+                mut rx_mail_from_http,
+                ..
+            } = init_with(Some(journal.clone())).await?;
+
+            let mut request: Request =
+                Request::new(Method::Post, Url::parse("http://localhost/api/mails")?);
+            request.set_body("From: alice@example.org\r\nTo: bob@example.org\r\n\r\nHello");
+            let response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Created);
+            let mail: Mail = rx_mail_from_http.next().await.ok_or("no injected mail")?;
+
+            Ok((mail, journal.replay().await?))
+        });
+        fs::remove_file(&path).unwrap_or_default();
+        let (mail, kept): (Mail, Vec<Mail>) = result?;
+
+        // The injected mail is restored after a restart
+        assert_eq!(
+            kept.iter().map(Mail::get_id).collect::<Vec<Ulid>>(),
+            vec![mail.get_id()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn config_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+            let url: Url = Url::parse("http://localhost/api/config")?;
+            let request = |method: Method, token: &str| {
+                let mut request: Request = Request::new(method, url.clone());
+                let _ = request.insert_header(headers::AUTHORIZATION, format!("Bearer {}", token));
+                request
+            };
+
+            // The API token is required
+            let response: Response = app.respond(Request::new(Method::Get, url.clone())).await?;
+            assert_eq!(response.status(), StatusCode::Unauthorized);
+            let response: Response = app.respond(request(Method::Get, "guess")).await?;
+            assert_eq!(response.status(), StatusCode::Unauthorized);
+
+            let mut response: Response = app.respond(request(Method::Get, "secret")).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let settings: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                settings,
+                json!({
+                    "allow_cidr": [],
+                    "broker_timeout": 500,
+                    "deny_cidr": [],
+                    "headers_only": null,
+                    "http_port": 1080,
+                    "recipient_rules": [],
+                    "replies": {},
+                    "smtp_port": 1025,
+                    "tag_rules": [],
+                    "timezone": "UTC"
+                })
+            );
+
+            // Only the given settings are changed
+            let mut patch: Request = request(Method::Patch, "secret");
+            patch.set_body(json!({
+                "timezone": "+02:00",
+                "headers_only": 0,
+                "replies": {"greeting": "mx.example.com ESMTP ready"},
+                "recipient_rules": ["reject:*@bounce.test"],
+                "tag_rules": ["staging=Subject:[staging]"],
+            }));
+            let mut response: Response = app.respond(patch).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let settings: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                settings,
+                json!({
+                    "allow_cidr": [],
+                    "broker_timeout": 500,
+                    "deny_cidr": [],
+                    "headers_only": 0,
+                    "http_port": 1080,
+                    "recipient_rules": ["reject:*@bounce.test"],
+                    "replies": {"greeting": "mx.example.com ESMTP ready"},
+                    "smtp_port": 1025,
+                    "tag_rules": ["staging=Subject:[staging]"],
+                    "timezone": "+02:00"
+                })
+            );
+
+            // ... and used by the next requests
+            assert_eq!(
+                app.state().settings.get().await.timezone,
+                "+02:00".parse::<Timezone>()?
+            );
+
+            let mut patch: Request = request(Method::Patch, "secret");
+            patch.set_body(json!({"timezone": "Mars/Olympus"}));
+            let response: Response = app.respond(patch).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+            let mut patch: Request = request(Method::Patch, "secret");
+            patch.set_body(json!({"replies": {"greeting": " "}}));
+            let response: Response = app.respond(patch).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn denied_client() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+            let _ = app
+                .state()
+                .settings
+                .update(&json!({"deny_cidr": ["10.0.0.0/8"]}))
+                .await?;
+
+            let request = |peer_addr: &str| -> crate::test::Result<Request> {
+                let mut request: Request =
+                    Request::new(Method::Get, Url::parse("http://localhost/")?);
+                request.set_peer_addr(Some(peer_addr));
+                Ok(request)
+            };
+            let response: Response = app.respond(request("10.1.2.3:41000")?).await?;
+            assert_eq!(response.status(), StatusCode::Forbidden);
+            let response: Response = app.respond(request("[::ffff:10.1.2.3]:41000")?).await?;
+            assert_eq!(response.status(), StatusCode::Forbidden);
+            let response: Response = app.respond(request("192.168.1.2:41000")?).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn info_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+
+            let mut response: Response = app
+                .respond(Request::new(
+                    Method::Get,
+                    Url::parse("http://localhost/api/info")?,
+                ))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let info: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                info,
+                json!({
+                    "http": {
+                        "addresses": ["127.0.0.1:1080", "[::1]:1080"],
+                        "port": 1080,
+                        "unavailable": [],
+                    },
+                    "smtp": {
+                        "addresses": ["127.0.0.1:1025"],
+                        "port": 1025,
+                        "profile": "normal",
+                        "unavailable": [{"addr": "[::1]:1025", "error": "Address already in use"}],
+                    },
+                    "smtp_extra": [{
+                        "addresses": ["127.0.0.1:1026"],
+                        "port": 1026,
+                        "profile": "always-tempfail",
+                        "unavailable": [],
+                    }],
+                    "smtp_paused": false,
+                    "started": app.state().started.timestamp(),
+                    "version": env!("CARGO_PKG_VERSION"),
+                })
+            );
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn capabilities_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+
+            let mut response: Response = app
+                .respond(Request::new(
+                    Method::Get,
+                    Url::parse("http://localhost/api/capabilities")?,
+                ))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({
+                    "faking": cfg!(feature = "faking"),
+                    "render": cfg!(feature = "render"),
+                    "persistence": true,
+                    "relay": false,
+                    "auth": false,
+                    "release": true,
+                    "config": true,
+                    "chaos": true,
+                })
+            );
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn help_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+
+            let mut response: Response = app
+                .respond(Request::new(
+                    Method::Get,
+                    Url::parse("http://localhost/api/help")?,
+                ))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let help: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(help.pointer("/name"), Some(&json!("mailcatcher")));
+            let http: Option<&serde_json::Value> = help
+                .pointer("/arguments")
+                .and_then(serde_json::Value::as_array)
+                .and_then(|arguments| {
+                    arguments
+                        .iter()
+                        .find(|argument| argument.pointer("/long") == Some(&json!("http")))
+                });
+            assert_eq!(
+                http.and_then(|http| http.pointer("/default")),
+                Some(&json!("1080"))
+            );
+            assert!(help
+                .pointer("/text")
+                .and_then(serde_json::Value::as_str)
+                .map_or(false, |text| text.contains("--http <http>")));
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn theme_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+            let request = |query: &str| {
+                Request::new(
+                    Method::Get,
+                    Url::parse(&format!("http://localhost/theme.css{}", query)).expect("theme url"),
+                )
+            };
+
+            let mut response: Response = app.respond(request("")).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert!(response.body_string().await?.contains("Classic theme"));
+            let mut response: Response = app.respond(request("?theme=dark")).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert!(response.body_string().await?.contains("Dark theme"));
+            let response: Response = app.respond(request("?theme=unknown")).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn security_headers() -> std::io::Result<()> {
+        fn header(response: &Response, name: &str) -> crate::test::Result<String> {
+            Ok(response
+                .header(name)
+                .ok_or_else(|| format!("{} header unavailable", name))?
+                .last()
+                .as_str()
+                .to_owned())
+        }
+
+        async fn the_test(app: Server<State<SseEvt>>, id: Ulid) -> crate::test::Result<()> {
+            let request = |path: &str| {
+                Request::new(
+                    Method::Get,
+                    Url::parse(&format!("http://localhost{}", path)).expect("security url"),
+                )
+            };
+
+            let response: Response = app.respond(request("/")).await?;
+            let policy: String = header(&response, "Content-Security-Policy")?;
+            assert!(policy.contains("frame-ancestors 'none'"));
+            assert_eq!(header(&response, "X-Content-Type-Options")?, "nosniff");
+            assert_eq!(header(&response, "Referrer-Policy")?, "no-referrer");
+
+            // The html of the mails is sandboxed
+            let response: Response = app.respond(request(&format!("/mail/{}/html", id))).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let policy: String = header(&response, "Content-Security-Policy")?;
+            assert!(policy.starts_with("sandbox "));
+            assert!(policy.contains("script-src 'nonce-"));
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mail: Mail = Mail::new("", &[], "Content-Type: text/html\r\n\r\n<p>Hello</p>");
+        let id: Ulid = mail.get_id();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, _) => sender.send(Some(mail.clone())).await?,
+                        _ => unreachable!("MailEvt is not GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, id)),
+        )
+    }
+
+    #[test]
+    fn inline_images_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, id: Ulid) -> crate::test::Result<()> {
+            let request = |path: &str| -> crate::test::Result<Request> {
+                Ok(Request::new(
+                    Method::Get,
+                    Url::parse(&format!("http://localhost/mail/{}/{}", id, path))?,
+                ))
+            };
+
+            // The cid: addresses point to the parts
+            let mut response: Response = app.respond(request("html")?).await?;
+            assert_eq!(
+                response.body_string().await?,
+                "<img src=\"parts/1\" alt=\"logo\">"
+            );
+
+            let mut response: Response = app.respond(request("parts/1")?).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(response.content_type(), Some(mime::PNG));
+            assert!(response
+                .header("Content-Security-Policy")
+                .map_or(false, |policy| policy.as_str().starts_with("sandbox;")));
+            assert_eq!(response.body_bytes().await?, b"\x89PNG");
+
+            let response: Response = app.respond(request("parts/2")?).await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mail: Mail = Mail::new(
+            "",
+            &[],
+            "Content-Type: multipart/related; boundary=related\r\n\
+\r\n\
+--related\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<img src=\"cid:logo@example.org\" alt=\"logo\">\r\n\
+--related\r\n\
+Content-Type: image/png\r\n\
+Content-ID: <logo@example.org>\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+iVBORw==\r\n\
+--related--\r\n",
+        );
+        let id: Ulid = mail.get_id();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, _) => sender.send(Some(mail.clone())).await?,
+                        _ => unreachable!("MailEvt is not GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, id)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn frame_route() -> std::io::Result<()> {
+        async fn get(app: &Server<State<SseEvt>>, path: &str) -> crate::test::Result<Response> {
+            let url: Url = Url::parse(&format!("http://localhost{}", path))?;
+            Ok(app.respond(Request::new(Method::Get, url)).await?)
+        }
+
+        async fn the_test(
+            app: Server<State<SseEvt>>,
+            html: Ulid,
+            text: Ulid,
+        ) -> crate::test::Result<()> {
+            let mut nonces: Vec<String> = Vec::new();
+            for _ in 0..2 {
+                let mut response: Response = get(&app, &format!("/mail/{}/frame", html)).await?;
+                assert_eq!(response.status(), StatusCode::Ok);
+                assert_eq!(response.content_type(), Some(mime::HTML));
+                assert_eq!(
+                    response
+                        .header("X-Frame-Options")
+                        .map(|value| value.as_str()),
+                    Some("SAMEORIGIN")
+                );
+                let policy: String = response
+                    .header("Content-Security-Policy")
+                    .map(|value| value.as_str().to_owned())
+                    .ok_or("Content-Security-Policy header unavailable")?;
+                let nonce: String = policy
+                    .split("'nonce-")
+                    .nth(1)
+                    .and_then(|nonce| nonce.split('\'').next())
+                    .ok_or("no nonce")?
+                    .to_owned();
+                let body: String = response.body_string().await?;
+                assert!(body.contains(&format!("<style nonce=\"{}\">", nonce)));
+                assert!(body.contains("<iframe sandbox="));
+                nonces.push(nonce);
+            }
+            // A new nonce for each request
+            assert!(nonces.first() != nonces.last());
+
+            let mut response: Response = get(&app, &format!("/mail/{}/html", html)).await?;
+            assert_eq!(response.content_type(), Some(mime::HTML));
+            assert_eq!(response.body_string().await?, "<p>Hello</p>");
+            // Without html part, there is nothing to frame
+            let response: Response = get(&app, &format!("/mail/{}/frame", text)).await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mails: Vec<Mail> = vec![
+            Mail::new("", &[], "Content-Type: text/html\r\n\r\n<p>Hello</p>"),
+            Mail::new("", &[], "Content-Type: text/plain\r\n\r\nHello"),
+        ];
+        let (html, text): (Ulid, Ulid) = (
+            mails.first().map(Mail::get_id).expect("html"),
+            mails.last().map(Mail::get_id).expect("text"),
+        );
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, id) => {
+                            sender
+                                .send(mails.iter().find(|mail| mail.get_id() == id).cloned())
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, html, text)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn session_route() -> std::io::Result<()> {
+        async fn the_test(
+            app: Server<State<SseEvt>>,
+            smtp: Ulid,
+            http: Ulid,
+        ) -> crate::test::Result<()> {
+            let request = |id: Ulid| {
+                Request::new(
+                    Method::Get,
+                    Url::parse(&format!("http://localhost/mail/{}/session", id))
+                        .expect("session url"),
+                )
+            };
+
+            let mut response: Response = app.respond(request(smtp)).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let session: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(session.get("client"), Some(&json!("192.0.2.1")));
+            assert_eq!(session.pointer("/lines/0/side"), Some(&json!("client")));
+            assert_eq!(
+                session.pointer("/lines/0/text"),
+                Some(&json!("EHLO client"))
+            );
+            // Injected without SMTP
+            let response: Response = app.respond(request(http)).await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mut transcript: Transcript = Transcript::new("192.0.2.1".parse().ok());
+        transcript.client("EHLO client");
+        let mut mails: Vec<Mail> = vec![
+            Mail::new("", &[], "Subject: By SMTP\r\n\r\nHello"),
+            Mail::new("", &[], "Subject: By HTTP\r\n\r\nHello"),
+        ];
+        if let Some(mail) = mails.first_mut() {
+            mail.set_transcript(transcript);
+        }
+        let (smtp, http): (Ulid, Ulid) = (
+            mails.first().map(Mail::get_id).expect("smtp"),
+            mails.last().map(Mail::get_id).expect("http"),
+        );
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, id) => {
+                            sender
+                                .send(mails.iter().find(|mail| mail.get_id() == id).cloned())
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, smtp, http)),
+        )
+    }
+
+    #[test]
+    fn smtp_pause_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+            let request = |path: &str| {
+                Request::new(
+                    Method::Post,
+                    Url::parse(&format!("http://localhost/api/smtp/{}", path)).expect("pause url"),
+                )
+            };
+
+            let mut response: Response = app.respond(request("pause")).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({"paused": true})
+            );
+            assert!(app.state().smtp_pause.is_paused());
+
+            let mut response: Response = app.respond(request("resume")).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({"paused": false})
+            );
+            assert!(!app.state().smtp_pause.is_paused());
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn smtp_latency_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+            let url: Url = Url::parse("http://localhost/api/smtp/latency")?;
+
+            let mut put: Request = Request::new(Method::Put, url.clone());
+            put.set_body(json!({"delay_ms": 200}));
+            let mut response: Response = app.respond(put).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({"delay_ms": 200, "jitter_ms": 0})
+            );
+            assert_eq!(app.state().smtp_latency.get().delay_ms, 200);
+
+            let mut put: Request = Request::new(Method::Put, url.clone());
+            put.set_body(json!({"delay": 200}));
+            let response: Response = app.respond(put).await?;
+            assert_eq!(response.status(), StatusCode::UnprocessableEntity);
+
+            let mut response: Response = app.respond(Request::new(Method::Get, url)).await?;
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({"delay_ms": 200, "jitter_ms": 0})
+            );
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn greylist_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+            let url: Url = Url::parse("http://localhost/api/greylist")?;
+            let greylist: &Greylist = &app.state().greylist;
+            let _deferred: bool = greylist
+                .admit(None, "from@example.org", "to@example.net")
+                .await;
+
+            let mut response: Response =
+                app.respond(Request::new(Method::Get, url.clone())).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let body: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(body.pointer("/enabled"), Some(&json!(true)));
+            assert_eq!(
+                body.pointer("/attempts/0/to"),
+                Some(&json!("to@example.net"))
+            );
+            assert_eq!(body.pointer("/attempts/0/deferred"), Some(&json!(1)));
+            assert_eq!(body.pointer("/attempts/0/passed"), Some(&json!(null)));
+
+            let response: Response = app.respond(Request::new(Method::Delete, url)).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert!(greylist.history().await.is_empty());
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn capture_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+            let capture: &Capture = &app.state().capture;
+            assert!(!capture.envelope_only(100).await);
+            assert!(capture.envelope_only(300).await);
+
+            let mut response: Response = app
+                .respond(Request::new(
+                    Method::Get,
+                    Url::parse("http://localhost/api/capture")?,
+                ))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let body: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                body,
+                json!({
+                    "envelope_only_above": "1/h",
+                    "envelope_only": 1,
+                    "discarded_bytes": 300,
+                    "sample": null,
+                    "received": 0,
+                    "sampled_out": 0,
+                })
+            );
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn incomplete_route() -> std::io::Result<()> {
+        async fn the_test() -> crate::test::Result<()> {
+            let Init { app, .. } = init().await?;
+            let url: Url = Url::parse("http://localhost/api/incomplete")?;
+            let quarantine: &Quarantine = &app.state().quarantine;
+            let mail: Mail = Mail::new(
+                "from@example.org",
+                &["to@example.net".into()],
+                "Subject: cut\r\n\r\nfirst",
+            );
+            let id: Ulid = mail.get_id();
+            quarantine
+                .keep(Incomplete {
+                    cutoff: Cutoff::Timeout,
+                    client: Some("127.0.0.1".parse()?),
+                    at: Utc::now(),
+                    mail,
+                })
+                .await;
+
+            let mut response: Response =
+                app.respond(Request::new(Method::Get, url.clone())).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let body: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(body.pointer("/0/cutoff"), Some(&json!("timeout")));
+            assert_eq!(body.pointer("/0/client"), Some(&json!("127.0.0.1")));
+            assert_eq!(body.pointer("/0/mail/subject"), Some(&json!("cut")));
+
+            // Filtered like the search of the mails
+            for &(query, count) in &[("?q=to:to@example.net", 1), ("?q=subject:other", 0)] {
+                let mut response: Response = app
+                    .respond(Request::new(Method::Get, url.join(query)?))
+                    .await?;
+                let body: serde_json::Value = serde_json::from_str(&response.body_string().await?)?;
+                assert_eq!(body.as_array().map(Vec::len), Some(count));
+            }
+            let response: Response = app
+                .respond(Request::new(Method::Get, url.join("?q=before:")?))
+                .await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+
+            let mut response: Response = app
+                .respond(Request::new(
+                    Method::Get,
+                    url.join(&format!("incomplete/{}", id))?,
+                ))
+                .await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(response.body_string().await?, "Subject: cut\r\n\r\nfirst");
+            let response: Response = app
+                .respond(Request::new(
+                    Method::Get,
+                    url.join(&format!("incomplete/{}", Ulid::new()))?,
+                ))
+                .await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            let response: Response = app.respond(Request::new(Method::Delete, url)).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert!(quarantine.list(&Filter::default()).await.is_empty());
+
+            Ok(())
+        }
+
+        crate::test::with_timeout(5_000, the_test())
+    }
+
+    #[test]
+    fn compact_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            let url: Url = Url::parse("http://localhost/api/maintenance/compact")?;
+
+            // Only POST is allowed
+            let response: Response = app.respond(Request::new(Method::Get, url.clone())).await?;
+            assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+
+            let mut response: Response =
+                app.respond(Request::new(Method::Post, url.clone())).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let compaction: serde_json::Value =
+                serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(
+                compaction,
+                json!({"mails": 2, "size_after": 100, "size_before": 300})
+            );
+
+            // No journal
+            let response: Response = app.respond(Request::new(Method::Post, url.clone())).await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            // Failure
+            let mut response: Response = app.respond(Request::new(Method::Post, url)).await?;
+            assert_eq!(response.status(), StatusCode::InternalServerError);
+            assert_eq!(response.body_string().await?, "Storage: disk full");
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mut responses = vec![
+            Ok(Some(Compaction {
+                mails: 2,
+                size_before: 300,
+                size_after: 100,
+            })),
+            Ok(None),
+            Err("disk full".to_owned()),
+        ]
+        .into_iter();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::Compact(sender) => {
+                            sender
+                                .send(responses.next().ok_or("too many compactions")?)
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not Compact"),
+                    }
+                }
+            }
+            .race(the_test(app)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn queues_route() -> crate::test::Result<()> {
+        // The mail broker is kept, but never processes the requests
+        let Init {
+            app,
+            tx_mail_broker,
+            rx_mail_broker: _broker,
+            ..
+        } = task::block_on(init())?;
+
+        task::block_on(async {
+            // Requests the mail broker did not process yet
+            for _ in 0..2 {
+                tx_mail_broker
+                    .send(MailEvt::Notified(Ulid::new(), Duration::default()))
+                    .await?;
+            }
+
+            let request: Request = Request::new(
+                Method::Get,
+                Url::parse("http://localhost/api/stats/queues")?,
+            );
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(&response.body_string().await?)?,
+                json!({
+                    "received": {
+                        "depth": 0,
+                        "capacity": null,
+                        "overflow": "block",
+                        "arrivals_last_minute": 0,
+                        "shed": 0,
+                        "dropped": 0,
+                    },
+                    "broker": {"depth": 2},
+                    "notifications": {"depth": 0},
+                })
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn release_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>, id: Ulid) -> crate::test::Result<()> {
+            let url: Url = Url::parse(&format!("http://localhost/mail/{}/release", id))?;
+
+            // Only POST is allowed
+            let response: Response = app.respond(Request::new(Method::Get, url.clone())).await?;
+            assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+
+            // Nothing listens on the server
+            let mut response: Response =
+                app.respond(Request::new(Method::Post, url.clone())).await?;
+            assert_eq!(response.status(), StatusCode::BadGateway);
+            assert!(response.body_string().await?.starts_with("SMTP: "));
+
+            // Unknown mail
+            let response: Response = app.respond(Request::new(Method::Post, url)).await?;
+            assert_eq!(response.status(), StatusCode::NotFound);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            mails,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mail: Mail = mails.first().cloned().expect("mail");
+        let id: Ulid = mail.get_id();
+        let mut found = vec![Some(mail), None].into_iter();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, asked) => {
+                            assert_eq!(asked, id);
+                            sender
+                                .send(found.next().ok_or("too many releases")?)
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetMail"),
+                    }
+                }
+            }
+            .race(the_test(app, id)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn as_of_route() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            for as_of in &["1600000000", "2020-09-13T12:26:40Z"] {
+                let url: Url = Url::parse(&format!("http://localhost/mails?as_of={}", as_of))?;
+                let mut response: Response = app.respond(Request::new(Method::Get, url)).await?;
+                assert_eq!(response.status(), StatusCode::Ok);
+                let mails: serde_json::Value =
+                    serde_json::from_str(&response.body_string().await?)?;
+                assert_eq!(mails.as_array().map(Vec::len), Some(1));
+            }
+
+            // Invalid time, the broker is not asked
+            let url: Url = Url::parse("http://localhost/mails?as_of=yesterday")?;
+            let response: Response = app.respond(Request::new(Method::Get, url)).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+
+            // No journal
+            let url: Url = Url::parse("http://localhost/mails?as_of=1600000000")?;
+            let response: Response = app.respond(Request::new(Method::Get, url)).await?;
+            assert_eq!(response.status(), StatusCode::BadRequest);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        let mut responses = vec![
+            Ok(Some(vec![Mail::fake()])),
+            Ok(Some(vec![Mail::fake()])),
+            Ok(None),
+        ]
+        .into_iter();
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::AsOf(sender, at) => {
+                            assert_eq!(at.timestamp(), 1_600_000_000);
+                            sender
+                                .send(responses.next().ok_or("too many requests")?)
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not AsOf"),
+                    }
+                }
+            }
+            .race(the_test(app)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn broker_unavailable() -> crate::test::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            for url in &[
+                "http://localhost/mails",
+                "http://localhost/api/stats",
+                "http://localhost/mail/01F0AJ9HN4NRSZQ6WCFP3TV8BK",
+            ] {
+                let request: Request = Request::new(Method::Get, Url::parse(url)?);
+                let mut response: Response = app.respond(request).await?;
+                assert_eq!(response.status(), StatusCode::ServiceUnavailable, "{}", url);
+                assert_eq!(
+                    response.body_string().await?,
+                    "Mail broker: no reply in time"
+                );
+            }
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            rx_mail_broker,
+            ..
+        } = task::block_on(init())?;
+
+        // Wedged broker, keeping the requests without replying
+        let wedged = async move {
+            let _requests: Vec<MailEvt> = rx_mail_broker.collect().await;
+            Ok(())
+        };
+        crate::test::with_timeout(5_000, wedged.race(the_test(app)))?;
+
+        // Stopped broker
+        let Init {
+            app,
+            rx_mail_broker,
+            ..
+        } = task::block_on(init())?;
+        drop(rx_mail_broker);
+        task::block_on(async {
+            let request: Request =
+                Request::new(Method::Get, Url::parse("http://localhost/api/stats")?);
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::ServiceUnavailable);
+            assert_eq!(response.body_string().await?, "Mail broker: not running");
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn broker_without_reply() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            let id: Ulid = Ulid::new();
+            for url in &[
+                format!("http://localhost/mail/{}", id),
+                format!("http://localhost/mail/{}/text", id),
+                format!("http://localhost/remove/{}", id),
+                "http://localhost/api/stats".to_owned(),
+            ] {
+                let request: Request = Request::new(Method::Get, Url::parse(url)?);
+                let mut response: Response = app.respond(request).await?;
+                assert_eq!(response.status(), StatusCode::ServiceUnavailable, "{}", url);
+                assert_eq!(response.body_string().await?, "Mail broker: no reply");
+            }
+
+            // The mails sent before the broker stopped are kept
+            let request: Request = Request::new(Method::Get, Url::parse("http://localhost/mails")?);
+            let mut response: Response = app.respond(request).await?;
+            assert_eq!(response.status(), StatusCode::Ok);
+            let summaries: Vec<serde_json::Value> =
+                serde_json::from_str(&response.body_string().await?)?;
+            assert_eq!(summaries.len(), 1);
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for a MailTank failing while replying
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetMail(sender, _) => drop(sender),
+                        MailEvt::Remove(sender, _) => drop(sender),
+                        MailEvt::GetStats(sender) => drop(sender),
+                        MailEvt::GetAll(sender) => {
+                            sender.send(Mail::fake()).await?;
+                            drop(sender);
+                        }
+                        _ => unreachable!("MailEvt not expected"),
+                    }
+                }
+            }
+            .race(the_test(app)),
+        )
+    }
+
+    #[test]
+    fn heartbeat_stats() -> std::io::Result<()> {
+        async fn the_test(app: Server<State<SseEvt>>) -> crate::test::Result<()> {
+            let data: sse_evt::SseData = sse::heartbeat_stats(app.state()).await?;
+            assert_eq!(data.name, "ping");
+            let stats: serde_json::Value = serde_json::from_str(&data.data)?;
+            assert_eq!(
+                stats,
+                json!({"mails": 2, "size": 300, "uptime": 0, "clients": 0})
+            );
+
+            Ok(())
+        }
+
+        let Init {
+            app,
+            mut rx_mail_broker,
+            ..
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            async move {
+                loop {
+                    // Mocker for the MailTank
+                    match rx_mail_broker.next().await.ok_or("no mail_evt received")? {
+                        MailEvt::GetStats(sender) => {
+                            sender
+                                .send(TankStats {
+                                    mails: 2,
+                                    size: 300,
+                                    ..TankStats::default()
+                                })
+                                .await?;
+                        }
+                        _ => unreachable!("MailEvt is not GetStats"),
+                    }
+                }
+            }
+            .race(the_test(app)),
+        )
     }
 }
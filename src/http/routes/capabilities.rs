@@ -0,0 +1,41 @@
+use tide::{prelude::json, Body, Request, Server};
+
+use crate::http::{Capabilities, State};
+
+/// Append the route telling which optional features are enabled:
+/// `/api/capabilities`
+///
+/// The frontend and the tools adapt to them, instead of probing the routes.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_capabilities =
+        app.at("/api/capabilities")
+            .get(|req: Request<State<T>>| async move {
+                let state: &State<T> = req.state();
+                let Capabilities {
+                    persistence,
+                    relay,
+                    auth,
+                } = state.capabilities;
+                Body::from_json(&json!({
+                    // Routes creating fake mails
+                    "faking": cfg!(feature = "faking"),
+                    // Rendering of the mails in images
+                    "render": cfg!(feature = "render"),
+                    // Mails kept in a journal across the restarts
+                    "persistence": persistence,
+                    // SMTP sessions relayed to an upstream server
+                    "relay": relay,
+                    // Credentials required by the SMTP AUTH command
+                    "auth": auth,
+                    // Mails delivered for real to another SMTP server
+                    "release": state.release.is_some(),
+                    // Settings changed at runtime with the management API
+                    "config": state.api_token.is_some(),
+                    // Simulated outage of the SMTP side
+                    "chaos": true,
+                }))
+            });
+}
@@ -0,0 +1,18 @@
+use tide::{Body, Request, Server};
+
+use crate::http::State;
+
+/// Append the route giving the capture of the SMTP mails under a heavy load:
+/// `/api/capture`
+///
+/// Above the rate given by `--envelope-only-above`, only the envelope of the
+/// mails is kept, the mails and the bytes of their contents discarded being
+/// counted.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_capture = app
+        .at("/api/capture")
+        .get(|req: Request<State<T>>| async move { Body::from_json(&req.state().capture.stats()) });
+}
@@ -0,0 +1,66 @@
+use tide::{http::headers, Body, Request, Response, Server, StatusCode};
+
+use crate::{error::MailcatcherError, http::State, settings::Settings};
+
+/// Append the routes to view and change the settings at runtime: `/api/config`
+///
+/// The requests must give the API token, as `Authorization: Bearer <token>`.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_config = app
+        .at("/api/config")
+        // Get the settings in use
+        .get(|req: Request<State<T>>| async move {
+            if let Some(response) = unauthorized(&req) {
+                return Ok(response);
+            }
+            Ok(Body::from_json(&req.state().settings.get().await)?.into())
+        })
+        // Change some settings, the others are kept
+        .patch(|mut req: Request<State<T>>| async move {
+            if let Some(response) = unauthorized(&req) {
+                return Ok(response);
+            }
+            let changes: serde_json::Value = req.body_json().await?;
+            let settings: Settings = req
+                .state()
+                .settings
+                .update(&changes)
+                .await
+                .map_err(MailcatcherError::into_http)?;
+            Ok(Body::from_json(&settings)?.into())
+        });
+}
+
+/// Check the API token given in the request, returning the response to send if
+/// the request is not allowed
+fn unauthorized<T>(req: &Request<State<T>>) -> Option<Response>
+where
+    T: Send + Clone + 'static,
+{
+    let token: &str = if let Some(ref token) = req.state().api_token {
+        token
+    } else {
+        let mut response: Response = Response::new(StatusCode::Forbidden);
+        response.set_body("The management API is disabled, it needs an API token");
+        return Some(response);
+    };
+
+    let given: Option<&str> = req
+        .header(headers::AUTHORIZATION)
+        .and_then(|value| value.as_str().strip_prefix("Bearer "));
+    if given == Some(token) {
+        None
+    } else {
+        log::warn!(
+            "Invalid API token for {} {}",
+            req.method(),
+            req.url().path()
+        );
+        let mut response: Response = Response::new(StatusCode::Unauthorized);
+        response.insert_header(headers::WWW_AUTHENTICATE, "Bearer");
+        Some(response)
+    }
+}
@@ -0,0 +1,38 @@
+use async_std::channel;
+use tide::{prelude::json, Body, Request, Server};
+
+use crate::{
+    http::State,
+    mail::{broker::MailEvt, IdStrategy, Mail},
+};
+
+/// Append the route finding the mails caused by a request of the application
+/// under test: `/api/by-correlation/:id`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Get the mails having the id in a correlation header, like X-Request-Id,
+    // or as the trace id of their traceparent header
+    let _route_by_correlation =
+        app.at("/api/by-correlation/:id")
+            .get(|req: Request<State<T>>| async move {
+                let id: String = req.param("id")?.trim().to_ascii_lowercase();
+                let (s, mut r): crate::Channel<Mail> = channel::unbounded();
+                req.state()
+                    .broker_request(MailEvt::ByCorrelation(s, id))
+                    .await?;
+
+                let mut mails: Vec<Mail> = Vec::new();
+                while let Some(mail) = req.state().broker_reply(&mut r).await? {
+                    mails.push(mail);
+                }
+                mails.sort_by_key(Mail::get_received);
+
+                let ids: IdStrategy = req.state().ids;
+                Body::from_json(&json!(mails
+                    .iter()
+                    .map(|mail| mail.summary(ids))
+                    .collect::<Vec<_>>()))
+            });
+}
@@ -0,0 +1,30 @@
+use async_std::channel;
+use tide::{Body, Request, Server};
+
+use crate::{
+    http::State,
+    mail::{broker::MailEvt, delivery::Report, Mail},
+};
+
+/// Append the route giving the report of the deliveries simulated with
+/// `--simulate-delivery`: `/api/deliveries`
+///
+/// The deliveries to the recipients of the mails in the tank are counted by
+/// outcome, in all and for each domain.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_deliveries = app
+        .at("/api/deliveries")
+        .get(|req: Request<State<T>>| async move {
+            let (s, mut r): crate::Channel<Mail> = channel::unbounded();
+            req.state().broker_request(MailEvt::GetAll(s)).await?;
+            let mut mails: Vec<Mail> = Vec::new();
+            while let Some(mail) = req.state().broker_reply(&mut r).await? {
+                mails.push(mail);
+            }
+
+            Body::from_json(&Report::new(mails.iter()))
+        });
+}
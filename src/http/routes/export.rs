@@ -0,0 +1,76 @@
+use async_std::channel;
+use tide::{prelude::Deserialize, Body, Request, Response, Server, StatusCode};
+use ulid::Ulid;
+
+use super::get_mails::resolve_id;
+use crate::{
+    http::State,
+    mail::{broker::MailEvt, bundle::Bundle, Mail},
+};
+
+/// Body of the export route
+#[derive(Debug, Deserialize)]
+struct ExportRequest {
+    /// IDs of the mails, their ULID or their sequential number
+    ids: Vec<String>,
+    /// Tags of the bundle, given in its manifest
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Append the route exporting mails as a bundle of test fixtures:
+/// `/api/export`
+///
+/// The body gives the `ids` of the mails, and the `tags` of the bundle. The
+/// reply is a tar archive of the raw mails in `.eml` files, with a
+/// `manifest.json` of their envelopes, reproducible so it can be committed into
+/// a repository as regression fixtures.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_export = app
+        .at("/api/export")
+        .post(|mut req: Request<State<T>>| async move {
+            let export: ExportRequest = req.body_json().await?;
+            if export.ids.is_empty() {
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    "No mail to export",
+                ));
+            }
+
+            let mut bundle: Bundle = Bundle::new(export.tags);
+            for requested in &export.ids {
+                let mail: Option<Mail> = match resolve_id(req.state(), requested).await? {
+                    Some(id) => get(req.state(), id).await?,
+                    None => None,
+                };
+                match mail {
+                    Some(mail) => bundle.add(mail),
+                    None => {
+                        return Err(tide::Error::from_str(
+                            StatusCode::NotFound,
+                            format!("No mail {}", requested),
+                        ))
+                    }
+                }
+            }
+
+            let mut response: Response = Response::new(StatusCode::Ok);
+            response.set_body(Body::from_bytes(bundle.tar()));
+            response.set_content_type("application/x-tar");
+            response.insert_header("Content-Disposition", "attachment; filename=\"mails.tar\"");
+            Ok(response)
+        });
+}
+
+/// Mail kept by the broker with the ULID
+async fn get<T>(state: &State<T>, id: Ulid) -> tide::Result<Option<Mail>>
+where
+    T: Send + Clone + 'static,
+{
+    let (s, mut r): crate::Channel<Option<Mail>> = channel::bounded(1);
+    state.broker_request(MailEvt::GetMail(s, id)).await?;
+    state.broker_single_reply(&mut r).await
+}
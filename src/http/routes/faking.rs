@@ -23,7 +23,11 @@ async fn faking(req: Request<State<SseEvt>>) -> tide::Result<String> {
     for _ in 0..nb {
         let mail: Mail = Mail::fake();
 
-        match req.state().new_fake_mail.send(mail).await {
+        // Kept in the journal too, to be restored like the other mails
+        if req.state().journal_append(&mail).await.is_err() {
+            continue;
+        }
+        match req.state().new_mail.send(mail).await {
             Ok(()) => log::debug!("New faked mail sent!"),
             Err(e) => log::debug!("New mail error: {:?}", e),
         }
@@ -1,41 +1,173 @@
+use std::num::ParseIntError;
+
 use async_std::channel;
-use futures::StreamExt;
-use tide::{prelude::json, Body, Request, Response, Server, StatusCode};
+use chrono::{DateTime, TimeZone, Utc};
+use tide::{
+    http::mime,
+    prelude::{json, Deserialize},
+    Body, Request, Response, Server, StatusCode,
+};
 use ulid::Ulid;
 
 use crate::{
+    encoding::encode_8bit,
+    error::MailcatcherError,
     http::State,
-    mail::{broker::MailEvt, HeaderRepresentation, Mail, Type},
+    mail::{
+        broker::MailEvt,
+        dsn::Dsn,
+        filter::{parse_date, Filter},
+        mime::Part,
+        transcript::Transcript,
+        HeaderRepresentation, Mail, Priority, SortBy, Type,
+    },
+    utils::Timezone,
 };
 
+/// Query parameters of the routes that return dates
+#[derive(Debug, Deserialize)]
+struct DateQuery {
+    /// Timezone used to format the dates
+    tz: Option<String>,
+}
+
+/// Query parameters of the routes that return the text or html content
+#[derive(Debug, Deserialize)]
+struct ContentQuery {
+    /// Replace the characters that cannot be decoded from the charset, the
+    /// default, or else refuse the content with a report of them
+    lossy: Option<bool>,
+}
+
+/// Query parameters of the mail list route
+#[derive(Debug, Deserialize)]
+struct MailsQuery {
+    /// Time to look back at, as epoch in seconds, a day or a RFC 3339 date and time
+    as_of: Option<String>,
+    /// Date the mails are sorted by
+    sort: Option<SortBy>,
+    /// Sort the newest mails first
+    #[serde(default)]
+    desc: bool,
+}
+
+/// Query parameters of the search route
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    /// Search query, see `Filter` for its syntax
+    q: Option<String>,
+    /// Normalized priority of the mails
+    priority: Option<Priority>,
+    /// Date the mails are sorted by
+    sort: Option<SortBy>,
+    /// Sort the newest mails first
+    #[serde(default)]
+    desc: bool,
+}
+
 /// Append the routes to retrieve the mail list or mail details: `/mails` or `/mail/*`
+#[allow(clippy::too_many_lines)]
 pub fn append_route<T>(app: &mut Server<State<T>>)
 where
     T: Send + Clone + 'static,
 {
     // Get all mail list
     let _route_mails = app.at("/mails").get(|req: Request<State<T>>| async move {
-        let (s, mut r): crate::Channel<Mail> = channel::unbounded();
-        req.state().mail_broker.send(MailEvt::GetAll(s)).await?;
+        let timezone: Timezone = get_timezone(&req).await?;
+        let query: MailsQuery = req.query()?;
+        let mut mails: Vec<Mail> = if let Some(ref as_of) = query.as_of {
+            mails_as_of(&req, as_of).await?
+        } else {
+            let (s, mut r): crate::Channel<Mail> = channel::unbounded();
+            req.state().broker_request(MailEvt::GetAll(s)).await?;
+            let mut mails: Vec<Mail> = Vec::new();
+            while let Some(mail) = req.state().broker_reply(&mut r).await? {
+                mails.push(mail);
+            }
+            mails
+        };
+        if let Some(sort) = query.sort {
+            sort.sort(&mut mails, query.desc);
+        }
 
         let mut resp: Vec<serde_json::Value> = Vec::new();
-        while let Some(mail) = r.next().await {
-            resp.push(mail.summary());
+        for mail in mails {
+            let mut summary: serde_json::Value = mail.summary(req.state().ids);
+            if let Some(summary) = summary.as_object_mut() {
+                let _ = summary.insert(
+                    "date_formatted".to_owned(),
+                    json!(timezone.format(mail.get_date())),
+                );
+            }
+            resp.push(summary);
         }
 
         Body::from_json(&json!(&resp))
     });
+    // Get the mails matching the search query
+    let _route_mails_search = app
+        .at("/mails/search")
+        .get(|req: Request<State<T>>| async move {
+            let query: SearchQuery = req.query()?;
+            let mut filter: Filter = query
+                .q
+                .as_deref()
+                .unwrap_or_default()
+                .parse()
+                .map_err(|e: String| tide::Error::from_str(StatusCode::BadRequest, e))?;
+            if query.priority.is_some() {
+                filter.priority = query.priority;
+            }
+            let (s, mut r): crate::Channel<Mail> = channel::unbounded();
+            req.state()
+                .broker_request(MailEvt::Search(s, filter))
+                .await?;
+
+            let mut mails: Vec<Mail> = Vec::new();
+            while let Some(mail) = req.state().broker_reply(&mut r).await? {
+                mails.push(mail);
+            }
+            if let Some(sort) = query.sort {
+                sort.sort(&mut mails, query.desc);
+            }
+
+            let resp: Vec<serde_json::Value> = mails
+                .iter()
+                .map(|mail| mail.summary(req.state().ids))
+                .collect();
+            Body::from_json(&json!(&resp))
+        });
     // Get mail details
     let _route_mail_id = app
         .at("/mail/:id")
         .get(|req: Request<State<T>>| async move {
+            let timezone: Timezone = get_timezone(&req).await?;
             if let Some(mail) = get_mail(&req).await? {
+                let text: Option<String> = match mail_content(&req, &mail, &Type::Text)? {
+                    Ok(text) => text,
+                    Err(report) => return Ok(report),
+                };
                 let obj: serde_json::Value = json!({
+                    "date": mail.get_date().timestamp(),
+                    "date_formatted": timezone.format(mail.get_date()),
+                    "received": mail.get_received().timestamp(),
                     "headers": mail.get_headers(&HeaderRepresentation::Humanized),
                     "raw": mail.get_headers(&HeaderRepresentation::Raw),
-                    "data": mail.get_text().expect("json mail data").clone(),
+                    "data": text.unwrap_or_default(),
+                    "has_html": mail.get_html().is_some(),
+                    "resent": mail.get_resent(),
+                    "list": mail.get_list(),
+                    "warnings": mail.get_trackers(),
+                    "pii": mail.get_pii(),
+                    "auth_user": mail.get_auth_user(),
+                    "client": mail.get_client(),
+                    "dsn": Dsn::of(&mail),
+                    "locked": mail.is_locked(),
+                    "correlation_ids": mail.get_correlation_ids(),
+                    "dkim": mail.get_dkim(),
+                    "deliveries": mail.get_deliveries(),
                 });
-                Ok(Body::from_json(&obj).expect("body from json").into())
+                Ok(Body::from_json(&obj)?.into())
             } else {
                 Ok(Response::new(StatusCode::NotFound))
             }
@@ -44,32 +176,37 @@ where
     let _route_mail_id_text = app
         .at("/mail/:id/text")
         .get(|req: Request<State<T>>| async move {
-            (get_mail(&req).await?).map_or_else(
-                || Ok(Response::new(StatusCode::NotFound)),
-                |mail| {
-                    Ok(Body::from_string(match mail.get_text() {
-                        Some(text) => text.to_string(),
-                        None => "".to_owned(),
-                    })
-                    .into())
-                },
-            )
-        });
-    // Get mail in html format
-    let _route_mail_id_html = app
-        .at("/mail/:id/html")
-        .get(|req: Request<State<T>>| async move {
-            (get_mail(&req).await?).map_or_else(
-                || Ok(Response::new(StatusCode::NotFound)),
-                |mail| {
-                    let s = match mail.get_html() {
-                        Some(text) => &text[..],
-                        None => "",
-                    };
-                    Ok(Body::from_bytes(s.as_bytes().to_vec()).into())
-                },
-            )
+            if let Some(mail) = get_mail(&req).await? {
+                Ok(match mail_content(&req, &mail, &Type::Text)? {
+                    Ok(text) => Body::from_string(text.unwrap_or_default()).into(),
+                    Err(report) => report,
+                })
+            } else {
+                Ok(Response::new(StatusCode::NotFound))
+            }
         });
+    // Get the commands and replies of the SMTP session of the mail, the mails
+    // injected over HTTP or restored from the journal have none
+    let _route_mail_id_session =
+        app.at("/mail/:id/session")
+            .get(|req: Request<State<T>>| async move {
+                let transcript: Option<serde_json::Value> = get_mail(&req)
+                    .await?
+                    .and_then(|mail| mail.get_transcript().map(Transcript::to_json));
+                transcript.map_or_else(
+                    || Ok(Response::new(StatusCode::NotFound)),
+                    |transcript| Ok(Body::from_json(&transcript)?.into()),
+                )
+            });
+    // Get the problems found while parsing the mail
+    let _route_mail_id_errors =
+        app.at("/mail/:id/errors")
+            .get(|req: Request<State<T>>| async move {
+                (get_mail(&req).await?).map_or_else(
+                    || Ok(Response::new(StatusCode::NotFound)),
+                    |mail| Ok(Body::from_json(mail.get_diagnostics())?.into()),
+                )
+            });
     // Get RAW format mail
     let _route_mail_id_source =
         app.at("/mail/:id/source")
@@ -86,28 +223,198 @@ where
                 }
                 Ok(Response::new(StatusCode::NotFound))
             });
+    // Get the header block of the mail as received, byte for byte
+    let _route_mail_id_headers_raw =
+        app.at("/mail/:id/headers.raw")
+            .get(|req: Request<State<T>>| async move {
+                let headers: Option<String> = get_mail(&req)
+                    .await?
+                    .and_then(|mail| mail.get_raw_headers().map(ToOwned::to_owned));
+                headers.map_or_else(
+                    || Ok(Response::new(StatusCode::NotFound)),
+                    |headers| {
+                        // The 8-bit bytes kept in Latin-1 are given back as is
+                        let mut body: Body = Body::from_bytes(encode_8bit(&headers));
+                        body.set_mime(mime::PLAIN);
+                        Ok(body.into())
+                    },
+                )
+            });
+    // Get the attachments list of a mail
+    let _route_mail_id_attachments =
+        app.at("/mail/:id/attachments")
+            .get(|req: Request<State<T>>| async move {
+                (get_mail(&req).await?).map_or_else(
+                    || Ok(Response::new(StatusCode::NotFound)),
+                    |mail| {
+                        let attachments: Vec<serde_json::Value> = mail
+                            .get_attachments()
+                            .iter()
+                            .enumerate()
+                            .map(|(index, part)| {
+                                json!({
+                                    "index": index,
+                                    "filename": part.filename(),
+                                    "content_type": part.content_type(),
+                                    "content_id": part.content_id(),
+                                    "size": part.decoded().len(),
+                                })
+                            })
+                            .collect();
+                        Ok(Body::from_json(&attachments)?.into())
+                    },
+                )
+            });
+    // Get an attachment of a mail, decoded, by its index in the attachments list
+    let _route_mail_id_attachment =
+        app.at("/mail/:id/attachments/:index")
+            .get(|req: Request<State<T>>| async move {
+                let index: usize = req.param("index")?.parse().map_err(|e: ParseIntError| {
+                    tide::Error::from_str(StatusCode::BadRequest, e.to_string())
+                })?;
+                let mail: Option<Mail> = get_mail(&req).await?;
+                let part: Option<&Part> = mail
+                    .as_ref()
+                    .and_then(|mail| mail.get_attachments().get(index).copied());
+
+                Ok(part.map_or_else(
+                    || Response::new(StatusCode::NotFound),
+                    |part| {
+                        let mut response: Response = Response::new(StatusCode::Ok);
+                        response.set_body(Body::from_bytes(part.decoded()));
+                        response.set_content_type(
+                            part.content_type().parse().unwrap_or(mime::BYTE_STREAM),
+                        );
+                        if let Some(filename) = part.filename() {
+                            response.insert_header(
+                                "Content-Disposition",
+                                format!("attachment; filename=\"{}\"", filename.replace('"', "")),
+                            );
+                        }
+                        response
+                    },
+                ))
+            });
+}
+
+/// Retrieve the timezone used to format the dates, from the `tz` query
+/// parameter or the settings
+async fn get_timezone<T>(req: &Request<State<T>>) -> tide::Result<Timezone>
+where
+    T: Send + Clone + 'static,
+{
+    let query: DateQuery = req.query()?;
+    match query.tz {
+        Some(tz) => tz
+            .parse()
+            .map_err(|e: String| tide::Error::from_str(StatusCode::BadRequest, e)),
+        None => Ok(req.state().settings.get().await.timezone),
+    }
+}
+
+/// Retrieve the text or html content of a mail, decoded again from its charset
+/// when the request has `?lossy=false`
+///
+/// The content that cannot be decoded is then refused, the response given back
+/// reporting its invalid bytes.
+pub(super) fn mail_content<T>(
+    req: &Request<State<T>>,
+    mail: &Mail,
+    type_: &Type,
+) -> tide::Result<Result<Option<String>, Response>>
+where
+    T: Send + Clone + 'static,
+{
+    let query: ContentQuery = req.query()?;
+    if query.lossy.unwrap_or(true) {
+        return Ok(Ok(mail.get_data(type_).cloned()));
+    }
+    match mail.get_data_strict(type_).transpose() {
+        Ok(content) => Ok(Ok(content)),
+        Err(report) => {
+            let mut response: Response = Response::new(StatusCode::UnprocessableEntity);
+            response.set_body(Body::from_json(&json!({
+                "error": report.to_string(),
+                "report": report,
+            }))?);
+            Ok(Err(response))
+        }
+    }
+}
+
+/// Mails that were in the tank at the time `as_of`, reconstructed from the journal
+async fn mails_as_of<T>(req: &Request<State<T>>, as_of: &str) -> tide::Result<Vec<Mail>>
+where
+    T: Send + Clone + 'static,
+{
+    let at: DateTime<Utc> = as_of
+        .parse::<i64>()
+        .ok()
+        .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+        .map_or_else(|| parse_date(as_of), Ok)
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?;
+    let (s, mut r): crate::Channel<Result<Option<Vec<Mail>>, String>> = channel::bounded(1);
+    req.state().broker_request(MailEvt::AsOf(s, at)).await?;
+
+    match req.state().broker_single_reply(&mut r).await? {
+        Ok(Some(mails)) => Ok(mails),
+        Ok(None) => Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "The mails are not persisted, no journal to look back at",
+        )),
+        Err(e) => Err(MailcatcherError::storage(e).into_http()),
+    }
 }
 
 /// Retrieve a mail from the the request, extracting the ID
-async fn get_mail<T>(req: &Request<State<T>>) -> tide::Result<Option<Mail>>
+pub(super) async fn get_mail<T>(req: &Request<State<T>>) -> tide::Result<Option<Mail>>
 where
     T: Send + Clone + 'static,
 {
-    // Extract the ID
-    let id: &str = req.param("id")?;
-    // Convert ID string to Ulid
-    Ok(if let Ok(id) = Ulid::from_string(id) {
+    Ok(if let Some(id) = mail_id(req).await? {
         let (s, mut r): crate::Channel<Option<Mail>> = channel::bounded(1);
-        req.state()
-            .mail_broker
-            .send(MailEvt::GetMail(s, id))
-            .await?;
+        req.state().broker_request(MailEvt::GetMail(s, id)).await?;
         // Get mails pool
-        let mail: Option<Mail> = r.next().await.expect("received mail");
+        let mail: Option<Mail> = req.state().broker_single_reply(&mut r).await?;
         log::trace!("mail with id {} found {:?}", id, mail);
         mail
     } else {
-        log::trace!("mail with id invalid {}", id);
         None
     })
 }
+
+/// Retrieve the ULID of the mail of the request, the ID being either its ULID or
+/// its sequential number
+pub(super) async fn mail_id<T>(req: &Request<State<T>>) -> tide::Result<Option<Ulid>>
+where
+    T: Send + Clone + 'static,
+{
+    // Extract the ID
+    resolve_id(req.state(), req.param("id")?).await
+}
+
+/// Retrieve the ULID of a mail from its ID, either its ULID or its sequential
+/// number
+pub(super) async fn resolve_id<T>(state: &State<T>, id: &str) -> tide::Result<Option<Ulid>>
+where
+    T: Send + Clone + 'static,
+{
+    if let Ok(id) = Ulid::from_string(id) {
+        return Ok(Some(id));
+    }
+    if let Ok(number) = id.parse::<u64>() {
+        // No shard replies if the number is unknown
+        let (s, mut r): crate::Channel<Ulid> = channel::unbounded();
+        state.broker_request(MailEvt::ByNumber(s, number)).await?;
+        return state.broker_reply(&mut r).await;
+    }
+    log::trace!("mail with id invalid {}", id);
+    Ok(None)
+}
+
+/// ID of the mail given back in a reply, in the form it was requested
+pub(super) fn requested_id(id: Ulid, requested: &str) -> serde_json::Value {
+    requested
+        .parse::<u64>()
+        .map_or_else(|_| json!(id.to_string()), |number| json!(number))
+}
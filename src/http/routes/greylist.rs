@@ -0,0 +1,30 @@
+use tide::{prelude::json, Body, Request, Server};
+
+use crate::http::State;
+
+/// Append the routes giving the attempts of the greylisted SMTP clients:
+/// `/api/greylist`
+///
+/// Each attempt to deliver to a recipient, from a client and an expeditor, is
+/// listed, with the ones deferred by a 450 reply and the time it was accepted.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_greylist = app
+        .at("/api/greylist")
+        // Get the attempts
+        .get(|req: Request<State<T>>| async move {
+            let greylist = &req.state().greylist;
+            Body::from_json(&json!({
+                "enabled": greylist.is_enabled(),
+                "attempts": greylist.history().await,
+            }))
+        })
+        // Forget the attempts, the next ones being deferred again
+        .delete(|req: Request<State<T>>| async move {
+            req.state().greylist.clear().await;
+            log::warn!("Greylisting attempts forgotten");
+            Body::from_json(&json!({ "attempts": [] }))
+        });
+}
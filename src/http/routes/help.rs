@@ -0,0 +1,17 @@
+use tide::{Body, Request, Server};
+
+use crate::http::State;
+
+/// Append the route describing the command line, its options with their
+/// defaults and possible values: `/api/help`
+///
+/// The users of a running instance can discover how it is configured, without
+/// access to its host.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_help = app
+        .at("/api/help")
+        .get(|req: Request<State<T>>| async move { Body::from_json(&*req.state().help) });
+}
@@ -0,0 +1,83 @@
+use tide::{
+    http::mime,
+    prelude::{json, Deserialize},
+    Body, Request, Response, Server, StatusCode,
+};
+use ulid::Ulid;
+
+use crate::{
+    http::State,
+    mail::{filter::Filter, Type},
+    smtp::quarantine::Incomplete,
+};
+
+/// Query parameters of the list of the incomplete transactions
+#[derive(Debug, Deserialize)]
+struct IncompleteQuery {
+    /// Search query, see `Filter` for its syntax
+    q: Option<String>,
+}
+
+/// Append the routes giving the SMTP transactions cut off in the middle of
+/// their content: `/api/incomplete` or `/api/incomplete/:id`
+///
+/// The client closed the connection, the connection failed or timed out before
+/// the end of the content. The partial mails are kept apart from the others,
+/// filtered with the syntax of `/mails/search`, to diagnose the flaky clients.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_incomplete = app
+        .at("/api/incomplete")
+        // Get the incomplete transactions, the newest first
+        .get(|req: Request<State<T>>| async move {
+            let query: IncompleteQuery = req.query()?;
+            let filter: Filter = query
+                .q
+                .as_deref()
+                .unwrap_or_default()
+                .parse()
+                .map_err(|e: String| tide::Error::from_str(StatusCode::BadRequest, e))?;
+            let resp: Vec<serde_json::Value> = req
+                .state()
+                .quarantine
+                .list(&filter)
+                .await
+                .iter()
+                .map(|incomplete| {
+                    json!({
+                        "cutoff": incomplete.cutoff,
+                        "client": incomplete.client,
+                        "at": incomplete.at.timestamp(),
+                        "mail": incomplete.mail.summary(req.state().ids),
+                    })
+                })
+                .collect();
+            Body::from_json(&json!(&resp))
+        })
+        // Forget the incomplete transactions
+        .delete(|req: Request<State<T>>| async move {
+            req.state().quarantine.clear().await;
+            log::warn!("Incomplete SMTP transactions forgotten");
+            Ok(Response::new(StatusCode::Ok))
+        });
+    // Get the partial content of an incomplete transaction, as received
+    let _route_incomplete_id =
+        app.at("/api/incomplete/:id")
+            .get(|req: Request<State<T>>| async move {
+                let incomplete: Option<Incomplete> = match Ulid::from_string(req.param("id")?) {
+                    Ok(id) => req.state().quarantine.get(id).await,
+                    Err(_) => None,
+                };
+                if let Some(raw) = incomplete
+                    .as_ref()
+                    .and_then(|incomplete| incomplete.mail.get_data(&Type::Raw))
+                {
+                    let mut body: Body = Body::from_string(raw.clone());
+                    body.set_mime(mime::PLAIN);
+                    return Ok(body.into());
+                }
+                Ok(Response::new(StatusCode::NotFound))
+            });
+}
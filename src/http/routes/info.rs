@@ -0,0 +1,83 @@
+use std::net::SocketAddr;
+
+use tide::{prelude::Serialize, Body, Request, Server};
+
+use crate::{http::State, smtp::profile::Profile, utils::BindFailure};
+
+/// Where a server listens on
+#[derive(Debug, Serialize)]
+struct Endpoint<'a> {
+    /// Port listened on, that can be another than the requested one when it was busy
+    port: Option<u16>,
+    /// Addresses listened on
+    addresses: &'a [SocketAddr],
+    /// Addresses of the port that could not be bound
+    unavailable: &'a [BindFailure],
+    /// Behavior of the SMTP listeners
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<Profile>,
+}
+
+impl<'a> Endpoint<'a> {
+    /// Describe the addresses of a server, that all share the same port
+    fn new(
+        addresses: &'a [SocketAddr],
+        unavailable: &'a [BindFailure],
+        profile: Option<Profile>,
+    ) -> Self {
+        Self {
+            port: addresses.first().map(SocketAddr::port),
+            addresses,
+            unavailable,
+            profile,
+        }
+    }
+}
+
+/// Information about the running catcher
+#[derive(Debug, Serialize)]
+struct Info<'a> {
+    /// Version of the catcher
+    version: &'static str,
+    /// Start time of the HTTP side, as epoch in seconds
+    started: i64,
+    /// SMTP side
+    smtp: Endpoint<'a>,
+    /// Additional SMTP listeners, with their own profile
+    smtp_extra: Vec<Endpoint<'a>>,
+    /// The SMTP side is paused, simulating an outage
+    smtp_paused: bool,
+    /// HTTP side
+    http: Endpoint<'a>,
+}
+
+/// Append the route giving information about the running catcher: `/api/info`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_info = app
+        .at("/api/info")
+        .get(|req: Request<State<T>>| async move {
+            let state: &State<T> = req.state();
+            let listening = state.listening.read().await;
+            Body::from_json(&Info {
+                version: env!("CARGO_PKG_VERSION"),
+                started: state.started.timestamp(),
+                smtp: Endpoint::new(
+                    &listening.smtp,
+                    &listening.smtp_failures,
+                    Some(listening.smtp_profile),
+                ),
+                smtp_extra: listening
+                    .smtp_extra
+                    .iter()
+                    .map(|extra| {
+                        Endpoint::new(&extra.addresses, &extra.failures, Some(extra.profile))
+                    })
+                    .collect(),
+                smtp_paused: state.smtp_pause.is_paused(),
+                http: Endpoint::new(&listening.http, &listening.http_failures, None),
+            })
+        });
+}
@@ -0,0 +1,78 @@
+use tide::{
+    prelude::{json, Deserialize},
+    Body, Request, Response, Server, StatusCode,
+};
+
+use crate::{
+    error::MailcatcherError,
+    http::State,
+    mail::{HeaderRepresentation, Mail},
+    smtp::address::header_mailboxes,
+};
+
+/// Query parameters of the injection route, giving the envelope of the mail
+#[derive(Debug, Deserialize)]
+struct EnvelopeQuery {
+    /// Expeditor address, the From header if not specified
+    from: Option<String>,
+    /// Comma separated recipient addresses, the To, Cc and Bcc headers if not specified
+    to: Option<String>,
+}
+
+/// Append the route to inject a mail without SMTP: `/api/mails`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Add a mail from its raw content, like if it was received by SMTP
+    let _route_inject = app
+        .at("/api/mails")
+        .post(|mut req: Request<State<T>>| async move {
+            let query: EnvelopeQuery = req.query()?;
+            let content: String = req.body_string().await?;
+            // The mails received by SMTP always have CRLF line endings
+            let content: String = content.lines().collect::<Vec<&str>>().join("\r\n");
+            if content.trim().is_empty() {
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    "The mail content is empty",
+                ));
+            }
+
+            let headers: Mail = Mail::new("", &[], &content);
+            let from: String = query.from.unwrap_or_else(|| {
+                headers
+                    .get_header_content("From", &HeaderRepresentation::Raw)
+                    .join(",")
+            });
+            let from: String = header_mailboxes(&from)
+                .into_iter()
+                .next()
+                .unwrap_or_default();
+            let to: String = query.to.unwrap_or_else(|| {
+                ["To", "Cc", "Bcc"]
+                    .iter()
+                    .flat_map(|&name| headers.get_header_content(name, &HeaderRepresentation::Raw))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            });
+            let to: Vec<String> = header_mailboxes(&to);
+
+            let mail: Mail = Mail::new(&from, &to, &content);
+            let id: serde_json::Value = req.state().ids.id_of(&mail);
+            // Kept on the disk before being acknowledged, like by the SMTP side
+            req.state()
+                .journal_append(&mail)
+                .await
+                .map_err(MailcatcherError::into_http)?;
+            req.state().new_mail.send(mail).await.map_err(|e| {
+                log::error!("Injected mail not sent: {}", e);
+                MailcatcherError::broker("not running").into_http()
+            })?;
+            log::info!("Mail {} injected", id);
+
+            let mut response: Response = Response::new(StatusCode::Created);
+            response.set_body(Body::from_json(&json!({ "id": id }))?);
+            Ok(response)
+        });
+}
@@ -0,0 +1,43 @@
+use async_std::channel;
+use tide::{prelude::json, Body, Request, Response, Server, StatusCode};
+
+use super::get_mails::mail_id;
+use crate::{
+    http::State,
+    mail::{broker::MailEvt, Mail},
+};
+
+/// Append the routes locking the mails under investigation: `/mail/:id/lock`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Lock a mail, it cannot be removed until it is unlocked
+    let _route_lock = app
+        .at("/mail/:id/lock")
+        .post(|req: Request<State<T>>| async move { set_locked(&req, true).await })
+        // Unlock a mail
+        .delete(|req: Request<State<T>>| async move { set_locked(&req, false).await });
+}
+
+/// Lock or unlock the mail of the request
+async fn set_locked<T>(req: &Request<State<T>>, locked: bool) -> tide::Result<Response>
+where
+    T: Send + Clone + 'static,
+{
+    if let Some(id) = mail_id(req).await? {
+        let (s, mut r): crate::Channel<Option<Mail>> = channel::bounded(1);
+        req.state()
+            .broker_request(MailEvt::Lock(s, id, locked))
+            .await?;
+        if let Some(mail) = req.state().broker_single_reply(&mut r).await? {
+            log::info!("Mail {} locked: {}", id, mail.is_locked());
+            return Ok(Body::from_json(&json!({
+                "id": req.state().ids.id_of(&mail),
+                "locked": mail.is_locked(),
+            }))?
+            .into());
+        }
+    }
+    Ok(Response::new(StatusCode::NotFound))
+}
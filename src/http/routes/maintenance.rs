@@ -0,0 +1,33 @@
+use async_std::channel;
+use tide::{Body, Request, Response, Server, StatusCode};
+
+use crate::{
+    error::MailcatcherError,
+    http::State,
+    mail::{broker::MailEvt, journal::Compaction},
+};
+
+/// Append the routes for the maintenance of the storage: `/api/maintenance`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Rewrite the journal without the removed mails
+    let _route_compact =
+        app.at("/api/maintenance/compact")
+            .post(|req: Request<State<T>>| async move {
+                let (s, mut r): crate::Channel<Result<Option<Compaction>, String>> =
+                    channel::bounded(1);
+                req.state().broker_request(MailEvt::Compact(s)).await?;
+
+                match req.state().broker_single_reply(&mut r).await? {
+                    Ok(Some(compaction)) => Ok(Body::from_json(&compaction)?.into()),
+                    Ok(None) => {
+                        let mut response: Response = Response::new(StatusCode::NotFound);
+                        response.set_body("The mails are not persisted, no journal to compact");
+                        Ok(response)
+                    }
+                    Err(e) => Err(MailcatcherError::storage(e).into_http()),
+                }
+            });
+}
@@ -0,0 +1,120 @@
+use std::convert::TryFrom;
+
+use async_std::channel;
+use tide::{http::mime, prelude::json, Body, Request, Server};
+
+use crate::{
+    http::State,
+    mail::broker::{MailEvt, TankStats},
+    smtp::metrics::Snapshot,
+};
+
+/// Append the routes giving the counters of the SMTP sessions: `/metrics`, in
+/// the Prometheus text format, or `/api/metrics`
+///
+/// The connections, the commands by verb, the bytes received, the commands
+/// refused and the mean size of the contents are counted since the start, to
+/// measure the load generated by a test suite. The mails kept by the broker
+/// are given with them.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_metrics = app.at("/metrics").get(|req: Request<State<T>>| async move {
+        let tank: TankStats = tank_stats(req.state()).await?;
+        let mut body: Body =
+            Body::from_string(exposition(&req.state().smtp_metrics.snapshot(), &tank));
+        body.set_mime(mime::PLAIN);
+        Ok(body)
+    });
+    let _route_api_metrics = app
+        .at("/api/metrics")
+        .get(|req: Request<State<T>>| async move {
+            let tank: TankStats = tank_stats(req.state()).await?;
+            Body::from_json(&json!({
+                "smtp": req.state().smtp_metrics.snapshot(),
+                "tank": {"mails": tank.mails, "size": tank.size},
+            }))
+        });
+}
+
+/// Statistics of the mails kept by the broker
+async fn tank_stats<T>(state: &State<T>) -> tide::Result<TankStats>
+where
+    T: Send + Clone + 'static,
+{
+    let (s, mut r): crate::Channel<TankStats> = channel::bounded(1);
+    state.broker_request(MailEvt::GetStats(s)).await?;
+    state.broker_single_reply(&mut r).await
+}
+
+/// Counters in the Prometheus text format
+fn exposition(smtp: &Snapshot, tank: &TankStats) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+        lines.push(format!("# HELP mailcatcher_{} {}", name, help));
+        lines.push(format!("# TYPE mailcatcher_{} {}", name, kind));
+        for &(ref labels, value) in samples {
+            lines.push(format!("mailcatcher_{}{} {}", name, labels, value));
+        }
+    };
+    let single = |value: u64| vec![(String::new(), value)];
+    let count = |value: usize| single(u64::try_from(value).unwrap_or(u64::MAX));
+
+    metric(
+        "smtp_connections_total",
+        "counter",
+        "SMTP connections accepted",
+        &single(smtp.connections),
+    );
+    let commands: Vec<(String, u64)> = smtp
+        .commands
+        .iter()
+        .map(|(verb, &value)| (format!("{{verb=\"{}\"}}", verb), value))
+        .collect();
+    metric(
+        "smtp_commands_total",
+        "counter",
+        "SMTP commands received, by verb",
+        &commands,
+    );
+    metric(
+        "smtp_received_bytes_total",
+        "counter",
+        "Bytes received by the SMTP side",
+        &single(smtp.bytes_received),
+    );
+    metric(
+        "smtp_rejected_commands_total",
+        "counter",
+        "SMTP commands refused with a 4xx or 5xx reply",
+        &single(smtp.rejected),
+    );
+    metric(
+        "smtp_contents_total",
+        "counter",
+        "Contents received with DATA or BDAT",
+        &single(smtp.contents),
+    );
+    metric(
+        "smtp_data_size_mean_bytes",
+        "gauge",
+        "Mean size of the contents received",
+        &single(smtp.mean_data_size),
+    );
+    metric(
+        "mails",
+        "gauge",
+        "Mails kept by the broker",
+        &count(tank.mails),
+    );
+    metric(
+        "mails_size_bytes",
+        "gauge",
+        "Size of the mails kept by the broker",
+        &count(tank.size),
+    );
+    // The exposition ends with a line feed
+    lines.push(String::new());
+    lines.join("\n")
+}
@@ -1,32 +1,292 @@
-use tide::Server;
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use tide::{Middleware, Next, Request, Response, Server, StatusCode};
 
 use super::{sse, sse_evt::SseEvt, State};
+use crate::{
+    otlp::{Span, Tracer},
+    shutdown::Activity,
+};
+
+/// Header of the content security policy
+const CSP: &str = "Content-Security-Policy";
 
+/// Content security policy of the web interface: the scripts and the styles
+/// inline in the page are allowed, the other resources come from the catcher,
+/// and no other site can frame it
+const UI_POLICY: &str = "default-src 'self'; script-src 'self' 'unsafe-inline'; \
+    style-src 'self' 'unsafe-inline'; img-src 'self' data:; connect-src 'self'; \
+    base-uri 'none'; form-action 'self'; frame-ancestors 'none'";
+
+/// Optional features enabled
+mod capabilities;
+/// Capture of the SMTP mails under a heavy load
+mod capture;
+/// Settings changed at runtime
+mod config;
+/// Mails caused by a request of the application
+mod correlation;
+/// Reports of the simulated deliveries
+mod deliveries;
+/// Export of the mails as test fixtures
+mod export;
 #[cfg(feature = "faking")]
 /// Create fake email
 mod faking;
 /// Get mails or mail informations
 mod get_mails;
+/// Attempts of the greylisted SMTP clients
+mod greylist;
+/// Description of the command line
+mod help;
+/// SMTP transactions cut off in the middle of their content
+mod incomplete;
+/// Information about the running catcher
+mod info;
+/// Inject mails without SMTP
+mod inject;
+/// Locking of the mails under investigation
+mod lock;
+/// Maintenance of the storage
+mod maintenance;
+/// Counters of the SMTP sessions
+mod metrics;
+/// Html parts of the mails, isolated from the web interface
+mod preview;
+/// Delivery of the mails to a real SMTP server
+mod release;
 /// Removing mail(s)
-mod remove;
+pub(super) mod remove;
+#[cfg(feature = "render")]
+/// Render the mails in images
+mod render;
+/// Latency of the SMTP replies
+mod smtp_latency;
+/// Simulated outage of the SMTP side
+mod smtp_pause;
 /// Files in the asset directory
 mod static_;
+/// Statistics of the mails
+mod stats;
+/// Conversations of the mails
+mod threads;
+/// Time spent by the mails in each stage of their processing
+mod timing;
 
 /// Initialise the routes
 pub async fn init(state: State<SseEvt>) -> crate::Result<Server<State<SseEvt>>> {
+    let traced: bool = state.tracer.is_some();
     let mut app: Server<State<SseEvt>> = tide::with_state(state);
 
+    // Trace the requests, before the others so the refused ones are traced too
+    if traced {
+        let _ = app.with(Trace);
+    }
+
+    // Protect the web interface in the browsers, the refused requests included
+    let _ = app.with(SecurityHeaders);
+    // Refuse the clients that are not allowed
+    let _ = app.with(AccessControl);
+    // Log the failed requests, and explain why in the body
+    let _ = app.with(ErrorReport);
+    // Delay the shutdown on idle
+    let _ = app.with(KeepAlive);
+
     // static files
     static_::append_route(&mut app).await;
     // Retrieve mails information
     get_mails::append_route(&mut app);
+    // Display the html of the mails
+    preview::append_route(&mut app);
+    // Inject mails
+    inject::append_route(&mut app);
     // Remove mail(s)
     remove::append_route(&mut app);
+    // Lock the mails
+    lock::append_route(&mut app);
+    // Deliver the mails for real
+    release::append_route(&mut app);
+    // Processing time of the mails
+    timing::append_route(&mut app);
+    // Statistics
+    stats::append_route(&mut app);
+    // Counters of the SMTP sessions
+    metrics::append_route(&mut app);
+    // Envelope-only capture of the SMTP mails
+    capture::append_route(&mut app);
+    // Information
+    info::append_route(&mut app);
+    // Features enabled
+    capabilities::append_route(&mut app);
+    // Command line
+    help::append_route(&mut app);
+    // Maintenance
+    maintenance::append_route(&mut app);
+    // Settings
+    config::append_route(&mut app);
+    // SMTP outage
+    smtp_pause::append_route(&mut app);
+    // SMTP latency
+    smtp_latency::append_route(&mut app);
+    // SMTP greylisting
+    greylist::append_route(&mut app);
+    // Mails of a request
+    correlation::append_route(&mut app);
+    // Mails grouped by conversation
+    threads::append_route(&mut app);
+    // Reports of the simulated deliveries
+    deliveries::append_route(&mut app);
+    // Incomplete SMTP transactions
+    incomplete::append_route(&mut app);
+    // Test fixtures
+    export::append_route(&mut app);
     // SSE stream
     let _route = app.at("/sse").get(tide::sse::endpoint(sse::handle));
 
     #[cfg(feature = "faking")]
     faking::append_route(&mut app);
+    #[cfg(feature = "render")]
+    render::append_route(&mut app);
 
     Ok(app)
 }
+
+/// Middleware refusing the requests of the clients that are not allowed, with
+/// a 403 status
+#[derive(Debug)]
+struct AccessControl;
+
+#[async_trait]
+impl Middleware<State<SseEvt>> for AccessControl {
+    async fn handle(
+        &self,
+        req: Request<State<SseEvt>>,
+        next: Next<'_, State<SseEvt>>,
+    ) -> tide::Result {
+        let peer_addr: Option<SocketAddr> = req.peer_addr().and_then(|addr| addr.parse().ok());
+        if let Some(peer_addr) = peer_addr {
+            if !req.state().settings.get().await.is_allowed(peer_addr.ip()) {
+                log::warn!("Request from {} denied", peer_addr);
+                return Ok(Response::new(StatusCode::Forbidden));
+            }
+        }
+
+        Ok(next.run(req).await)
+    }
+}
+
+/// Middleware adding the security headers to the responses, with the content
+/// security policy of the web interface unless the route set another one
+#[derive(Debug)]
+struct SecurityHeaders;
+
+#[async_trait]
+impl<T> Middleware<T> for SecurityHeaders
+where
+    T: Clone + Send + Sync + 'static,
+{
+    async fn handle(&self, req: Request<T>, next: Next<'_, T>) -> tide::Result {
+        let mut response: Response = next.run(req).await;
+
+        if response.header(CSP).is_none() {
+            response.insert_header(CSP, UI_POLICY);
+        }
+        response.insert_header("X-Content-Type-Options", "nosniff");
+        response.insert_header("Referrer-Policy", "no-referrer");
+
+        Ok(response)
+    }
+}
+
+/// Middleware logging the requests that failed on the server side, with the reason
+/// of the failure also given in the response body
+#[derive(Debug)]
+struct ErrorReport;
+
+#[async_trait]
+impl<T> Middleware<T> for ErrorReport
+where
+    T: Clone + Send + Sync + 'static,
+{
+    async fn handle(&self, req: Request<T>, next: Next<'_, T>) -> tide::Result {
+        let request: String = format!("{} {}", req.method(), req.url().path());
+        let mut response: Response = next.run(req).await;
+
+        if response.status().is_server_error() {
+            if let Some(message) = response.error().map(ToString::to_string) {
+                log::error!("{} failed with {}: {}", request, response.status(), message);
+                response.set_body(message);
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+/// Middleware recording each request as an activity, at its start and at its
+/// end, for the catcher not to stop on idle while it is used
+#[derive(Debug)]
+struct KeepAlive;
+
+#[async_trait]
+impl Middleware<State<SseEvt>> for KeepAlive {
+    async fn handle(
+        &self,
+        req: Request<State<SseEvt>>,
+        next: Next<'_, State<SseEvt>>,
+    ) -> tide::Result {
+        let activity: Activity = req.state().activity.clone();
+        activity.touch();
+        let response: Response = next.run(req).await;
+        activity.touch();
+
+        Ok(response)
+    }
+}
+
+/// Middleware exporting a span for each request, in the trace of the caller if
+/// it gives a `traceparent` header
+#[derive(Debug)]
+struct Trace;
+
+#[async_trait]
+impl Middleware<State<SseEvt>> for Trace {
+    async fn handle(
+        &self,
+        req: Request<State<SseEvt>>,
+        next: Next<'_, State<SseEvt>>,
+    ) -> tide::Result {
+        let tracer: Tracer = match req.state().tracer {
+            Some(ref tracer) => tracer.clone(),
+            None => return Ok(next.run(req).await),
+        };
+
+        let mut span: Span = Span::new(&format!("{} {}", req.method(), req.url().path()));
+        if let Some(traceparent) = req.header("traceparent") {
+            span = span.with_parent(traceparent.as_str());
+        }
+        span.attribute("http.method", req.method().to_string());
+        span.attribute("http.target", req.url().path());
+        if let Some(peer_addr) = req
+            .peer_addr()
+            .and_then(|addr| addr.parse::<SocketAddr>().ok())
+        {
+            span.attribute("net.peer.ip", peer_addr.ip().to_string());
+            span.attribute("net.peer.port", peer_addr.port());
+        }
+
+        let response: Response = next.run(req).await;
+        span.attribute("http.status_code", u16::from(response.status()));
+        if response.status().is_server_error() {
+            span.fail(
+                &response
+                    .error()
+                    .map_or_else(|| response.status().to_string(), ToString::to_string),
+            );
+        }
+        tracer.end(&span);
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,150 @@
+use std::num::ParseIntError;
+
+use tide::{http::mime, Body, Request, Response, Server, StatusCode};
+use ulid::Ulid;
+
+use super::{
+    get_mails::{get_mail, mail_content},
+    CSP,
+};
+use crate::{
+    http::State,
+    mail::{
+        mime::{resolve_cids, Part},
+        Mail, Type,
+    },
+};
+
+/// Bits of the random part of an ULID, the others being its timestamp
+const RANDOM_BITS: u128 = 0xFFFF_FFFF_FFFF_FFFF_FFFF;
+
+/// Content security policy of the parts of a mail, that are only loaded by the
+/// html part, like its inline images, and never run as a page of the catcher
+const PART_POLICY: &str = "sandbox; default-src 'none'; frame-ancestors 'self'";
+
+/// Append the routes displaying the html part of a mail: `/mail/:id/html`,
+/// `/mail/:id/frame` to embed it in the web interface, and `/mail/:id/parts/:index`
+/// to load the parts it references, like its inline images
+///
+/// The html comes from the senders, so it is displayed in a sandbox, without
+/// scripts, and only the catcher can frame it. Its `cid:` addresses are
+/// rewritten to the parts with these Content-IDs.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Get mail in html format
+    let _route_mail_id_html = app
+        .at("/mail/:id/html")
+        .get(|req: Request<State<T>>| async move {
+            if let Some(mail) = get_mail(&req).await? {
+                Ok(match mail_content(&req, &mail, &Type::Html)? {
+                    Ok(html) => {
+                        let resolved: String =
+                            resolve_cids(&html.unwrap_or_default(), mail.get_parts(), |index| {
+                                format!("parts/{}", index)
+                            });
+                        preview(resolved, preview_policy(&nonce()))
+                    }
+                    Err(report) => report,
+                })
+            } else {
+                Ok(Response::new(StatusCode::NotFound))
+            }
+        });
+    // Get a part of a mail, decoded, by its index in the MIME tree
+    let _route_mail_id_part =
+        app.at("/mail/:id/parts/:index")
+            .get(|req: Request<State<T>>| async move {
+                let index: usize = req.param("index")?.parse().map_err(|e: ParseIntError| {
+                    tide::Error::from_str(StatusCode::BadRequest, e.to_string())
+                })?;
+                let mail: Option<Mail> = get_mail(&req).await?;
+                let part: Option<&Part> =
+                    mail.as_ref().and_then(|mail| mail.get_parts().get(index));
+
+                Ok(part.map_or_else(
+                    || Response::new(StatusCode::NotFound),
+                    |part| {
+                        let mut response: Response = Response::new(StatusCode::Ok);
+                        response.set_body(Body::from_bytes(part.decoded()));
+                        response.set_content_type(
+                            part.content_type().parse().unwrap_or(mime::BYTE_STREAM),
+                        );
+                        response.insert_header(CSP, PART_POLICY);
+                        response.insert_header("Cache-Control", "no-store");
+                        response
+                    },
+                ))
+            });
+    // Page framing the html of a mail, for the web interface
+    let _route_mail_id_frame = app
+        .at("/mail/:id/frame")
+        .get(|req: Request<State<T>>| async move {
+            let mail: Option<Mail> = get_mail(&req).await?;
+            if mail.as_ref().and_then(Mail::get_html).is_none() {
+                return Ok(Response::new(StatusCode::NotFound));
+            }
+            let nonce: String = nonce();
+            Ok(preview(frame(&nonce), frame_policy(&nonce)))
+        });
+}
+
+/// Html response under a content security policy, that only the catcher can
+/// frame and that is never cached, as the nonce of the policy must not be reused
+fn preview(html: String, policy: String) -> Response {
+    let mut body: Body = Body::from_string(html);
+    body.set_mime(mime::HTML);
+    let mut response: Response = body.into();
+    response.insert_header(CSP, policy);
+    response.insert_header("X-Frame-Options", "SAMEORIGIN");
+    response.insert_header("Cache-Control", "no-store");
+    response
+}
+
+/// Content security policy of the html part of a mail, displayed in a sandbox
+/// with only the images and the styles of the mail: its scripts never run, as
+/// they cannot know the nonce of the request
+fn preview_policy(nonce: &str) -> String {
+    format!(
+        "sandbox allow-popups allow-popups-to-escape-sandbox; default-src 'none'; \
+         script-src 'nonce-{}'; img-src data: cid: http: https:; \
+         style-src 'unsafe-inline'; font-src data:; frame-ancestors 'self'",
+        nonce
+    )
+}
+
+/// Page framing the html part of a mail in a sandbox, its style being allowed
+/// by the `nonce` of the request
+fn frame(nonce: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <style nonce="{}">html, body, iframe {{margin: 0; border: 0; width: 100%; height: 100%}}</style>
+</head>
+<body>
+    <iframe sandbox="allow-popups allow-popups-to-escape-sandbox" referrerpolicy="no-referrer" src="html"></iframe>
+</body>
+</html>
+"#,
+        nonce
+    )
+}
+
+/// Content security policy of the page framing the html part of a mail, that
+/// only has its style and the frame
+fn frame_policy(nonce: &str) -> String {
+    format!(
+        "default-src 'none'; style-src 'nonce-{}'; frame-src 'self'; \
+         base-uri 'none'; frame-ancestors 'self'",
+        nonce
+    )
+}
+
+/// New nonce, unpredictable as it is the random part of an ULID
+fn nonce() -> String {
+    let id: u128 = u128::from(Ulid::new());
+    format!("{:020x}", id & RANDOM_BITS)
+}
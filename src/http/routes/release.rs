@@ -0,0 +1,48 @@
+use tide::{prelude::json, Body, Request, Response, Server, StatusCode};
+
+use super::get_mails::{get_mail, requested_id};
+use crate::{
+    error::MailcatcherError,
+    http::State,
+    mail::Mail,
+    smtp::release::{release, Target},
+};
+
+/// Append the route delivering a caught mail to a real SMTP server:
+/// `/mail/:id/release`
+///
+/// The server is the one given with `--release-to`, the route is refused without it.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Release a mail, with its envelope and its raw content
+    let _route_release = app
+        .at("/mail/:id/release")
+        .post(|req: Request<State<T>>| async move {
+            let target: &Target = if let Some(ref target) = req.state().release {
+                target
+            } else {
+                let mut response: Response = Response::new(StatusCode::BadRequest);
+                response.set_body("No SMTP server to release the mails to, see --release-to");
+                return Ok(response);
+            };
+            let mail: Mail = match get_mail(&req).await? {
+                Some(mail) => mail,
+                None => return Ok(Response::new(StatusCode::NotFound)),
+            };
+
+            // The delivery is done by the request, the mail broker goes on
+            // meanwhile, and it ends with its own timeout
+            log::info!("Releasing mail {} to {}", mail.get_id(), target.server);
+            let reply: String = release(target, &mail)
+                .await
+                .map_err(MailcatcherError::into_http)?;
+            Ok(Body::from_json(&json!({
+                "id": requested_id(mail.get_id(), req.param("id")?),
+                "server": target.server,
+                "reply": reply,
+            }))?
+            .into())
+        });
+}
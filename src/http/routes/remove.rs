@@ -1,48 +1,132 @@
-use std::ops::Add;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use async_std::channel;
-use futures::StreamExt;
-use tide::{Request, Response, Server, StatusCode};
+use async_std::{channel, sync::Mutex};
+use fnv::FnvHashMap;
+use tide::{
+    prelude::{json, Deserialize},
+    Body, Request, Response, Server, StatusCode,
+};
 use ulid::Ulid;
 
+use super::get_mails::mail_id;
 use crate::{
     http::{sse_evt::SseEvt, State},
-    mail::broker::MailEvt,
+    mail::broker::{MailEvt, Removal},
 };
 
-/// Append the routes for removing mails with prefix: `/remove`
+/// Time a purge token can be used once it is given, in seconds
+const PURGE_TOKEN_VALIDITY: u64 = 60;
+
+/// Tokens confirming the purge of all the mails, each one can be used once
+#[derive(Clone, Debug, Default)]
+pub struct PurgeTokens {
+    /// Tokens given, with the time they were given
+    tokens: Arc<Mutex<FnvHashMap<String, Instant>>>,
+}
+
+impl PurgeTokens {
+    /// Give a new token, forgetting the expired ones
+    async fn issue(&self) -> String {
+        let token: String = Ulid::new().to_string();
+        let mut tokens = self.tokens.lock().await;
+        tokens.retain(|_, issued| is_valid(issued));
+        let _ = tokens.insert(token.clone(), Instant::now());
+        token
+    }
+
+    /// Use a token, that is valid if it was given and has not expired
+    async fn redeem(&self, token: &str) -> bool {
+        self.tokens
+            .lock()
+            .await
+            .remove(token)
+            .map_or(false, |issued| is_valid(&issued))
+    }
+}
+
+/// Check if a token given at `issued` has not expired
+fn is_valid(issued: &Instant) -> bool {
+    issued.elapsed() < Duration::from_secs(PURGE_TOKEN_VALIDITY)
+}
+
+/// Query parameters of the purge route
+#[derive(Debug, Deserialize)]
+struct PurgeQuery {
+    /// Token given by a previous request, confirming the purge
+    token: Option<String>,
+    /// Purge without a token
+    #[serde(default)]
+    force: bool,
+}
+
+/// Append the routes for removing mails: `/remove/:id` and `/api/mails/purge`
 pub fn append_route(app: &mut Server<State<SseEvt>>) {
-    // Remove all mails
-    let _route_remove_all = app
-        .at("/remove/all")
-        .get(|req: Request<State<SseEvt>>| async move {
-            let (s, mut r): crate::Channel<Ulid> = channel::unbounded();
-            req.state().mail_broker.send(MailEvt::RemoveAll(s)).await?;
-            let mut nb: usize = 0;
-            while let Some(id) = r.next().await {
-                nb = nb.add(1);
-                match req.state().sse_stream.send(&SseEvt::DelMail(id)).await {
-                    Ok(()) => log::trace!("Success notification of removal: {}", id.to_string()),
-                    Err(e) => log::error!("Notification of removal {}: {:?}", id.to_string(), e),
+    // Remove all mails, in two steps so a link followed by mistake cannot do it:
+    // the first request gives a token, that a second one sends back to confirm
+    let _route_purge = app
+        .at("/api/mails/purge")
+        .post(|req: Request<State<SseEvt>>| async move {
+            let query: PurgeQuery = req.query()?;
+            let confirmed: bool = match query.token {
+                Some(ref token) => req.state().purge_tokens.redeem(token).await,
+                None if query.force => true,
+                None => {
+                    let token: String = req.state().purge_tokens.issue().await;
+                    let mut response: Response = Response::new(StatusCode::Accepted);
+                    response.set_body(Body::from_json(&json!({
+                        "token": token,
+                        "expires_in": PURGE_TOKEN_VALIDITY,
+                    }))?);
+                    return Ok(response);
                 }
+            };
+            if !confirmed {
+                return Err(tide::Error::from_str(
+                    StatusCode::Forbidden,
+                    "Invalid or expired confirmation token",
+                ));
             }
-            Ok(format!("OK: {}", nb))
+
+            let removed: usize = remove_all(&req).await?;
+            log::info!("{} mails purged", removed);
+            Ok(Body::from_json(&json!({ "removed": removed }))?.into())
         });
     // Remove a mail by id
     let _route_remove_id = app
         .at("/remove/:id")
         .get(|req: Request<State<SseEvt>>| async move {
-            let id: &str = req.param("id")?;
-            if let Ok(id) = Ulid::from_string(id) {
-                let (s, mut r): crate::Channel<Option<Ulid>> = channel::bounded(1);
-                req.state().mail_broker.send(MailEvt::Remove(s, id)).await?;
-                let mail: Option<Ulid> = r.next().await.expect("received mail id");
-                if mail.is_some() {
-                    log::info!("mail removed {:?}", mail);
-                    req.state().sse_stream.send(&SseEvt::DelMail(id)).await?;
-                    return Ok("OK: 1".into());
+            if let Some(id) = mail_id(&req).await? {
+                let (s, mut r): crate::Channel<Removal> = channel::bounded(1);
+                req.state().broker_request(MailEvt::Remove(s, id)).await?;
+                match req.state().broker_single_reply(&mut r).await? {
+                    Removal::Removed => {
+                        log::info!("mail removed {}", id);
+                        req.state().events.publish(&SseEvt::DelMail(id)).await;
+                        return Ok("OK: 1".into());
+                    }
+                    Removal::Locked => {
+                        let mut response: Response = Response::new(StatusCode::Locked);
+                        response.set_body("The mail is locked, unlock it to remove it");
+                        return Ok(response);
+                    }
+                    Removal::NotFound => {}
                 }
             }
             Ok(Response::new(StatusCode::NotFound))
         });
 }
+
+/// Remove all the mails, returning their number
+async fn remove_all(req: &Request<State<SseEvt>>) -> tide::Result<usize> {
+    let (s, mut r): crate::Channel<Ulid> = channel::unbounded();
+    req.state().broker_request(MailEvt::RemoveAll(s)).await?;
+    let mut nb: usize = 0;
+    while let Some(id) = req.state().broker_reply(&mut r).await? {
+        nb = nb.saturating_add(1);
+        req.state().events.publish(&SseEvt::DelMail(id)).await;
+    }
+    Ok(nb)
+}
@@ -0,0 +1,138 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_std::{fs, future, process::Command};
+use tide::{http::mime, prelude::Deserialize, Body, Request, Response, Server, StatusCode};
+use ulid::Ulid;
+
+use super::get_mails::get_mail;
+use crate::{error::MailcatcherError, http::State, mail::Mail};
+
+/// Largest width or height of a rendered image, in pixels
+const MAX_SIZE: u32 = 4_096;
+
+/// Longest time given to the browser to render a page, it is killed then
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Query parameters of the render route
+#[derive(Debug, Deserialize)]
+struct RenderQuery {
+    /// Width of the browser window, in pixels
+    width: Option<u32>,
+    /// Height of the browser window, in pixels
+    height: Option<u32>,
+}
+
+/// Append the route rendering the html part of a mail to an image: `/mail/:id/render.png`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_mail_id_render =
+        app.at("/mail/:id/render.png")
+            .get(|req: Request<State<T>>| async move {
+                let query: RenderQuery = req.query()?;
+                let (width, height): (u32, u32) =
+                    (query.width.unwrap_or(1_024), query.height.unwrap_or(768));
+                if !(1..=MAX_SIZE).contains(&width) || !(1..=MAX_SIZE).contains(&height) {
+                    return Err(tide::Error::from_str(
+                        StatusCode::BadRequest,
+                        format!("The size must be between 1 and {} pixels", MAX_SIZE),
+                    ));
+                }
+
+                let mail: Option<Mail> = get_mail(&req).await?;
+                let html: &str = match mail.as_ref().and_then(Mail::get_html) {
+                    Some(html) => html,
+                    None => return Ok(Response::new(StatusCode::NotFound)),
+                };
+                let png: Vec<u8> = render(&req.state().render_browser, html, width, height).await?;
+
+                let mut response: Response = Response::new(StatusCode::Ok);
+                response.set_body(Body::from_bytes(png));
+                response.set_content_type(mime::PNG);
+                Ok(response)
+            });
+}
+
+/// Take a screenshot of the html content with the headless `browser`, in a
+/// window of `width` by `height` pixels
+///
+/// The browser is a command, with its arguments separated by spaces, that
+/// accepts the options of Chromium, like `chromium --no-sandbox`.
+async fn render(browser: &str, html: &str, width: u32, height: u32) -> tide::Result<Vec<u8>> {
+    let base: PathBuf = std::env::temp_dir().join(format!("mailcatcher-{}", Ulid::new()));
+    let (page, screenshot): (PathBuf, PathBuf) =
+        (base.with_extension("html"), base.with_extension("png"));
+    fs::write(&page, html)
+        .await
+        .map_err(|e| MailcatcherError::storage(e).into_http())?;
+
+    let result: tide::Result<Vec<u8>> =
+        screenshot_page(browser, &page, &screenshot, width, height).await;
+    fs::remove_file(&page).await.unwrap_or_default();
+    fs::remove_file(&screenshot).await.unwrap_or_default();
+    result
+}
+
+/// Run the browser to write the screenshot of the page, and read it
+///
+/// A browser still running after `TIMEOUT`, like on a page that never finishes
+/// loading, is killed.
+async fn screenshot_page(
+    browser: &str,
+    page: &Path,
+    screenshot: &Path,
+    width: u32,
+    height: u32,
+) -> tide::Result<Vec<u8>> {
+    let mut words = browser.split_whitespace();
+    let program: &str = words.next().ok_or_else(|| {
+        tide::Error::from_str(
+            StatusCode::ServiceUnavailable,
+            "No browser to render the mails",
+        )
+    })?;
+
+    let rendering = Command::new(program)
+        .args(words)
+        .arg("--headless")
+        .arg("--disable-gpu")
+        .arg("--hide-scrollbars")
+        .arg(format!("--window-size={},{}", width, height))
+        .arg(format!("--screenshot={}", screenshot.display()))
+        .arg(format!("file://{}", page.display()))
+        // Dropped with the rendering when it takes too long
+        .kill_on_drop(true)
+        .output();
+    let output = future::timeout(TIMEOUT, rendering)
+        .await
+        .map_err(|_e| {
+            log::error!("The browser {} did not render in {:?}", program, TIMEOUT);
+            tide::Error::from_str(
+                StatusCode::GatewayTimeout,
+                format!("The browser did not render the mail in {:?}", TIMEOUT),
+            )
+        })?
+        .map_err(|e| {
+            log::error!("Unable to run the browser {}: {}", program, e);
+            tide::Error::from_str(
+                StatusCode::ServiceUnavailable,
+                format!("Unable to run the browser {}: {}", program, e),
+            )
+        })?;
+    if !output.status.success() {
+        return Err(MailcatcherError::http(format!(
+            "The browser failed to render the mail, {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into_http());
+    }
+
+    fs::read(screenshot)
+        .await
+        .map_err(|e| MailcatcherError::http(format!("No image rendered: {}", e)).into_http())
+}
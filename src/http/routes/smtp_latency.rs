@@ -0,0 +1,30 @@
+use tide::{Body, Request, Server};
+
+use crate::{http::State, smtp::latency::Delay};
+
+/// Append the routes changing the latency of the SMTP replies: `/api/smtp/latency`
+///
+/// The delay is given in milliseconds, like `{"delay_ms": 200, "jitter_ms": 50}`,
+/// and applies to the next replies of the sessions in progress too.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_latency =
+        app.at("/api/smtp/latency")
+            // Get the delay in use
+            .get(|req: Request<State<T>>| async move {
+                Body::from_json(&req.state().smtp_latency.get())
+            })
+            // Change the delay, a missing part being set to 0
+            .put(|mut req: Request<State<T>>| async move {
+                let delay: Delay = req.body_json().await?;
+                req.state().smtp_latency.set(delay);
+                log::warn!(
+                    "SMTP replies delayed by {} ms, up to {} ms more",
+                    delay.delay_ms,
+                    delay.jitter_ms
+                );
+                Body::from_json(&delay)
+            });
+}
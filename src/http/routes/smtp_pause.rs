@@ -0,0 +1,31 @@
+use tide::{prelude::json, Body, Request, Response, Server};
+
+use crate::http::State;
+
+/// Append the routes simulating an outage of the SMTP side: `/api/smtp`
+///
+/// While paused, the SMTP clients are answered with a 421 reply, the mails
+/// already received are kept.
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Begin the outage
+    let _route_pause = app
+        .at("/api/smtp/pause")
+        .post(|req: Request<State<T>>| async move { set_paused(&req, true) });
+    // End the outage
+    let _route_resume = app
+        .at("/api/smtp/resume")
+        .post(|req: Request<State<T>>| async move { set_paused(&req, false) });
+}
+
+/// Pause or resume the SMTP side
+fn set_paused<T>(req: &Request<State<T>>, paused: bool) -> tide::Result<Response>
+where
+    T: Send + Clone + 'static,
+{
+    req.state().smtp_pause.set(paused);
+    log::warn!("SMTP {}", if paused { "paused" } else { "resumed" });
+    Ok(Body::from_json(&json!({ "paused": paused }))?.into())
+}
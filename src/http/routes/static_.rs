@@ -1,18 +1,48 @@
-use tide::{http::mime, Request, Server};
+use tide::{
+    http::{mime, Mime},
+    prelude::Deserialize,
+    Request, Response, Server, StatusCode,
+};
 
-use crate::http::{asset::send, sse_evt::SseEvt, State};
+use crate::http::{
+    asset::{send, Theme},
+    sse_evt::SseEvt,
+    State,
+};
+
+/// Query parameters of the asset routes
+#[derive(Debug, Deserialize)]
+struct ThemeQuery {
+    /// Theme of the asset, instead of the one chosen at the start
+    theme: Option<String>,
+}
+
+/// Send an asset in the theme asked with `?theme=`, or in the one chosen at the start
+fn send_themed(req: Request<State<SseEvt>>, name: &str, mime: Mime) -> tide::Result<Response> {
+    let query: ThemeQuery = req.query()?;
+    let theme: Theme = match query.theme {
+        Some(ref theme) => theme
+            .parse()
+            .map_err(|e: String| tide::Error::from_str(StatusCode::BadRequest, e))?,
+        None => req.state().theme.clone(),
+    };
+    send(&req.into(), &theme, name, mime)
+}
 
 /// Append route for files inside the Asset directory
 pub async fn append_route(app: &mut Server<State<SseEvt>>) {
     let _route_html = app.at("/").get(|req: Request<State<SseEvt>>| async move {
-        send(&req.into(), "home.html", mime::HTML)
+        send_themed(req, "home.html", mime::HTML)
     });
     let _route_js = app
         .at("/hyperapp.js")
         .get(|req: Request<State<SseEvt>>| async move {
-            send(&req.into(), "hyperapp.js", mime::JAVASCRIPT)
+            send_themed(req, "hyperapp.js", mime::JAVASCRIPT)
         });
     let _route_css = app
         .at("/w3.css")
-        .get(|req: Request<State<SseEvt>>| async move { send(&req.into(), "w3.css", mime::CSS) });
+        .get(|req: Request<State<SseEvt>>| async move { send_themed(req, "w3.css", mime::CSS) });
+    let _route_theme = app
+        .at("/theme.css")
+        .get(|req: Request<State<SseEvt>>| async move { send_themed(req, "theme.css", mime::CSS) });
 }
@@ -0,0 +1,66 @@
+use async_std::channel;
+use tide::{
+    prelude::{json, Serialize},
+    Body, Request, Server,
+};
+
+use crate::{
+    http::{sse::SseStats, State},
+    mail::broker::{MailEvt, TankStats},
+};
+
+/// Statistics of the catcher
+#[derive(Debug, Serialize)]
+struct Stats {
+    /// Statistics of the mails in the tank
+    #[serde(flatten)]
+    tank: TankStats,
+    /// Statistics of the browsers connected by SSE
+    sse: SseStats,
+}
+
+/// Append the routes for the statistics: `/api/stats` or `/api/stats/*`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Get the statistics of the mails in the tank
+    let _route_stats = app
+        .at("/api/stats")
+        .get(|req: Request<State<T>>| async move {
+            let (s, mut r): crate::Channel<TankStats> = channel::bounded(1);
+            req.state().broker_request(MailEvt::GetStats(s)).await?;
+            let tank: TankStats = req.state().broker_single_reply(&mut r).await?;
+
+            Body::from_json(&Stats {
+                tank,
+                sse: req.state().sse_clients.stats(),
+            })
+        });
+    // Get the number of mails by kind of content, to find the ones without a
+    // plain text alternative for example
+    let _route_stats_content_types =
+        app.at("/api/stats/content-types")
+            .get(|req: Request<State<T>>| async move {
+                let (s, mut r): crate::Channel<TankStats> = channel::bounded(1);
+                req.state().broker_request(MailEvt::GetStats(s)).await?;
+                let tank: TankStats = req.state().broker_single_reply(&mut r).await?;
+
+                Body::from_json(&tank.content_types)
+            });
+    // Get the depth of the queues between the sides, and the arrival rate of
+    // the mails
+    let _route_stats_queues =
+        app.at("/api/stats/queues")
+            .get(|req: Request<State<T>>| async move {
+                let state: &State<T> = req.state();
+                Body::from_json(&json!({
+                    // Mails received, waiting for the mail broker
+                    "received": state.queue.stats().await,
+                    // Requests waiting for the mail broker
+                    "broker": {"depth": state.mail_broker.len()},
+                    // New mails waiting to be notified to the browsers
+                    "notifications": {"depth": state.notifications.len()},
+                }))
+            });
+}
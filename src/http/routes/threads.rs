@@ -0,0 +1,57 @@
+use async_std::channel;
+use tide::{prelude::json, Body, Request, Server};
+use ulid::Ulid;
+
+use crate::{
+    http::State,
+    mail::{broker::MailEvt, thread::ThreadIndex, IdStrategy, Mail},
+};
+
+/// Append the route giving the mails grouped by conversation, from their
+/// `Message-Id`, `In-Reply-To` and `References` headers: `/mails/threads`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    // Get the conversations, the one with the newest mail first, each with its
+    // mails from the oldest
+    let _route_threads = app
+        .at("/mails/threads")
+        .get(|req: Request<State<T>>| async move {
+            let (s, mut r): crate::Channel<ThreadIndex> = channel::bounded(1);
+            req.state().broker_request(MailEvt::GetThreads(s)).await?;
+            let index: ThreadIndex = req.state().broker_single_reply(&mut r).await?;
+
+            let (s, mut r): crate::Channel<Mail> = channel::unbounded();
+            req.state().broker_request(MailEvt::GetAll(s)).await?;
+            let mut mails: fnv::FnvHashMap<Ulid, Mail> = fnv::FnvHashMap::default();
+            while let Some(mail) = req.state().broker_reply(&mut r).await? {
+                let _ = mails.insert(mail.get_id(), mail);
+            }
+
+            let ids: IdStrategy = req.state().ids;
+            let threads: Vec<serde_json::Value> = index
+                .threads()
+                .iter()
+                .filter_map(|ids_of_thread| {
+                    // A mail removed meanwhile is left out
+                    let thread: Vec<&Mail> = ids_of_thread
+                        .iter()
+                        .filter_map(|id| mails.get(id))
+                        .collect();
+                    let first: &Mail = thread.first()?;
+                    Some(json!({
+                        "message_id": first.get_message_id(),
+                        "subject": first.get_subject(),
+                        "count": thread.len(),
+                        "mails": thread
+                            .iter()
+                            .map(|mail| mail.summary(ids))
+                            .collect::<Vec<_>>(),
+                    }))
+                })
+                .collect();
+
+            Body::from_json(&json!(threads))
+        });
+}
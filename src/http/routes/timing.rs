@@ -0,0 +1,36 @@
+use async_std::channel;
+use tide::{prelude::json, Body, Request, Response, Server, StatusCode};
+
+use super::get_mails::{mail_id, requested_id};
+use crate::{
+    http::State,
+    mail::{broker::MailEvt, timing::Timing},
+};
+
+/// Append the route giving the time spent by a mail in each stage of its
+/// processing: `/mail/:id/timing`
+pub fn append_route<T>(app: &mut Server<State<T>>)
+where
+    T: Send + Clone + 'static,
+{
+    let _route_timing = app
+        .at("/mail/:id/timing")
+        .get(|req: Request<State<T>>| async move {
+            if let Some(id) = mail_id(&req).await? {
+                let (s, mut r): crate::Channel<Option<Timing>> = channel::bounded(1);
+                req.state()
+                    .broker_request(MailEvt::GetTiming(s, id))
+                    .await?;
+                if let Some(timing) = req.state().broker_single_reply(&mut r).await? {
+                    // The mails restored from the journal have no stage
+                    return Ok(Body::from_json(&json!({
+                        "id": requested_id(id, req.param("id")?),
+                        "stages": timing.laps(),
+                        "total_micros": timing.total_micros(),
+                    }))?
+                    .into());
+                }
+            }
+            Ok(Response::new(StatusCode::NotFound))
+        });
+}
@@ -1,15 +1,66 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_std::channel;
+use chrono::Utc;
 use futures::StreamExt;
-use tide::{sse::Sender, Request};
+use tide::{
+    prelude::{json, Deserialize, Serialize},
+    sse::Sender,
+    Request,
+};
 
 use super::{
     sse_evt::{SseData, SseEvt},
     State,
 };
+use crate::mail::broker::{MailEvt, TankDigest, TankStats};
+
+/// Content of the ping events sent to the browser
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Heartbeat {
+    /// A heart, only to keep the connection alive
+    Emoji,
+    /// The statistics of the catcher, to refresh the counters of the browser
+    Stats,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::Emoji
+    }
+}
+
+/// Query parameters of `/sse`
+#[derive(Debug, Deserialize)]
+struct SseQuery {
+    /// Content of the ping events
+    heartbeat: Option<Heartbeat>,
+}
 
 /// Handle Server-Sent Events
 pub async fn handle(req: Request<State<SseEvt>>, sender: Sender) -> tide::Result<()> {
     // Retrieve the SSE stream notifications
     let mut sse_stream = req.state().sse_stream.clone();
+    // Visible in the statistics until the end of the connection
+    let connection: SseConnection = req.state().sse_clients.connect();
+    // The connection is already opened, so an invalid query cannot be refused
+    let heartbeat: Heartbeat = req
+        .query::<SseQuery>()
+        .map_or_else(
+            |e| {
+                log::warn!("Invalid SSE query, using the default heartbeat: {}", e);
+                None
+            },
+            |query| query.heartbeat,
+        )
+        .unwrap_or_default();
 
     // Do for each event
     while let Some(mail_evt) = sse_stream.next().await {
@@ -18,11 +69,22 @@ pub async fn handle(req: Request<State<SseEvt>>, sender: Sender) -> tide::Result
             mail_evt
         );
 
-        // Convert the Event to a data struct
-        let data: SseData = mail_evt.into();
+        // Convert the Event to a data struct, an event that cannot be is not sent
+        let data: tide::Result<SseData> = match (heartbeat, mail_evt) {
+            (Heartbeat::Stats, SseEvt::Ping) => heartbeat_stats(req.state()).await,
+            (_, mail_evt) => SseData::from_evt(mail_evt, req.state().ids).map_err(Into::into),
+        };
+        let data: SseData = match data {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("SSE notification not sent: {}", e);
+                continue;
+            }
+        };
         log::trace!("data to send: {:?}", data);
         // Send the generated data
         let sent = sender.send(data.name, data.data.as_ref(), None).await;
+        connection.sent(sent.is_ok());
         // Check the send result, exit of the SSE if any error, generally from a disconnection
         if sent.is_err() {
             log::warn!("Err, disconnected: {:?}", sent);
@@ -33,3 +95,178 @@ pub async fn handle(req: Request<State<SseEvt>>, sender: Sender) -> tide::Result
     log::info!("### Exit /sse");
     Ok(())
 }
+
+/// Ping event carrying the statistics of the catcher
+pub async fn heartbeat_stats(state: &State<SseEvt>) -> tide::Result<SseData<'static>> {
+    let (s, mut r): crate::Channel<TankStats> = channel::bounded(1);
+    state.broker_request(MailEvt::GetStats(s)).await?;
+    let tank: TankStats = state.broker_single_reply(&mut r).await?;
+
+    Ok(SseData {
+        name: "ping",
+        data: serde_json::to_string(&json!({
+            "mails": tank.mails,
+            "size": tank.size,
+            "uptime": Utc::now().signed_duration_since(state.started).num_seconds(),
+            "clients": state.sse_clients.stats().clients,
+        }))?
+        .into(),
+    })
+}
+
+/// Digest of the mails kept, sent to the browsers for them to resync their
+/// list if it drifted
+pub async fn tank_digest(state: &State<SseEvt>) -> tide::Result<TankDigest> {
+    let (s, mut r): crate::Channel<TankDigest> = channel::bounded(1);
+    state.broker_request(MailEvt::GetDigest(s)).await?;
+    state.broker_single_reply(&mut r).await
+}
+
+/// Browsers connected to `/sse`
+#[derive(Clone, Debug, Default)]
+pub struct SseClients {
+    /// Last id given to a connection
+    last_id: Arc<AtomicU64>,
+    /// Connected clients, by their id
+    clients: Arc<Mutex<BTreeMap<u64, SseClient>>>,
+}
+
+/// A browser connected to `/sse`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct SseClient {
+    /// Id of the connection
+    pub id: u64,
+    /// Connection time, as epoch in seconds
+    pub connected: i64,
+    /// Duration of the connection, in seconds
+    pub duration: i64,
+    /// Number of events sent
+    pub events: usize,
+    /// Time of the last event sent, as epoch in seconds
+    pub last_send: Option<i64>,
+    /// Whether the last event was successfully sent
+    pub last_send_ok: Option<bool>,
+}
+
+/// Statistics of the browsers connected to `/sse`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SseStats {
+    /// Number of browsers connected
+    pub clients: usize,
+    /// Connected browsers, the oldest first
+    pub connections: Vec<SseClient>,
+}
+
+impl SseClients {
+    /// Record a new connection, that lasts until the returned value is dropped
+    pub fn connect(&self) -> SseConnection {
+        let id: u64 = self
+            .last_id
+            .fetch_add(1, Ordering::Relaxed)
+            .saturating_add(1);
+        let client: SseClient = SseClient {
+            id,
+            connected: Utc::now().timestamp(),
+            duration: 0,
+            events: 0,
+            last_send: None,
+            last_send_ok: None,
+        };
+        if let Ok(mut clients) = self.clients.lock() {
+            let _ = clients.insert(id, client);
+        }
+        log::debug!("SSE client {} connected", id);
+
+        SseConnection {
+            id,
+            clients: self.clone(),
+        }
+    }
+
+    /// Statistics of the connected browsers
+    pub fn stats(&self) -> SseStats {
+        let now: i64 = Utc::now().timestamp();
+        let connections: Vec<SseClient> = self
+            .clients
+            .lock()
+            .map(|clients| {
+                clients
+                    .values()
+                    .map(|client| SseClient {
+                        duration: now.saturating_sub(client.connected),
+                        ..*client
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        SseStats {
+            clients: connections.len(),
+            connections,
+        }
+    }
+}
+
+/// Connection of a browser to `/sse`, removed from the clients when dropped
+#[derive(Debug)]
+pub struct SseConnection {
+    /// Id of the connection
+    id: u64,
+    /// Clients where the connection is recorded
+    clients: SseClients,
+}
+
+impl SseConnection {
+    /// Record the outcome of sending an event to the browser
+    pub fn sent(&self, ok: bool) {
+        if let Ok(mut clients) = self.clients.clients.lock() {
+            if let Some(client) = clients.get_mut(&self.id) {
+                client.events = client.events.saturating_add(1);
+                client.last_send = Some(Utc::now().timestamp());
+                client.last_send_ok = Some(ok);
+            }
+        }
+    }
+}
+
+impl Drop for SseConnection {
+    fn drop(&mut self) {
+        if let Ok(mut clients) = self.clients.clients.lock() {
+            let _ = clients.remove(&self.id);
+        }
+        log::debug!("SSE client {} disconnected", self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_stats() {
+        crate::test::log_init();
+
+        let clients: SseClients = SseClients::default();
+        assert_eq!(clients.stats(), SseStats::default());
+
+        let first: SseConnection = clients.connect();
+        let second: SseConnection = clients.connect();
+        first.sent(true);
+        second.sent(true);
+        second.sent(false);
+
+        let stats: SseStats = clients.stats();
+        assert_eq!(stats.clients, 2);
+        let summary: Vec<(u64, usize, Option<bool>)> = stats
+            .connections
+            .iter()
+            .map(|client| (client.id, client.events, client.last_send_ok))
+            .collect();
+        assert_eq!(summary, vec![(1, 1, Some(true)), (2, 2, Some(false))]);
+
+        drop(first);
+        let stats: SseStats = clients.stats();
+        assert_eq!(stats.clients, 1);
+        assert_eq!(stats.connections.first().map(|client| client.id), Some(2));
+    }
+}
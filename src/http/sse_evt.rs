@@ -1,20 +1,25 @@
 use std::borrow::Cow;
 
-use async_std::task;
-use tide::Body;
+use tide::prelude::json;
 use ulid::Ulid;
 
-use crate::mail::Mail;
+use crate::{
+    error::MailcatcherError,
+    mail::{broker::TankDigest, IdStrategy, Mail},
+};
 
 /// Events that can be sent to SSE
 #[derive(Clone, Debug)]
 pub enum SseEvt {
     /// A new mail has arrived
-    NewMail(Mail),
+    NewMail(Box<Mail>),
     /// A mail was deleted
     DelMail(Ulid),
     /// Ping to test connection
     Ping,
+    /// Count and newest ids of the mails kept, for the browsers to detect they
+    /// missed some events
+    TankDigest(TankDigest),
 }
 
 /// Data that can be sent to client browsers with SSE
@@ -26,24 +31,17 @@ pub struct SseData<'a> {
     pub data: Cow<'a, str>,
 }
 
-/// Convert from `SseEvt` to `SseData`
-impl From<SseEvt> for SseData<'_> {
-    fn from(sse_evt: SseEvt) -> Self {
-        match sse_evt {
-            SseEvt::NewMail(mail) => {
-                let mail: serde_json::Value = mail.summary();
-                let data: String = task::block_on(async move {
-                    Body::from_json(&mail)
-                        .unwrap_or_else(|_| "".into())
-                        .into_string()
-                        .await
-                        .expect("json new mail")
-                });
-                SseData {
-                    name: "newMail",
-                    data: Cow::Owned(data),
-                }
-            }
+impl SseData<'_> {
+    /// Convert from `SseEvt` to `SseData`, the mails having the `id` given by
+    /// the strategy
+    pub fn from_evt(sse_evt: SseEvt, ids: IdStrategy) -> Result<Self, MailcatcherError> {
+        Ok(match sse_evt {
+            SseEvt::NewMail(mail) => SseData {
+                name: "newMail",
+                data: Cow::Owned(
+                    serde_json::to_string(&mail.summary(ids)).map_err(MailcatcherError::http)?,
+                ),
+            },
             SseEvt::DelMail(id) => SseData {
                 name: "delMail",
                 data: Cow::Owned(id.to_string()),
@@ -52,7 +50,21 @@ impl From<SseEvt> for SseData<'_> {
                 name: "ping",
                 data: Cow::Borrowed("\u{1f493}"),
             },
-        }
+            SseEvt::TankDigest(digest) => SseData {
+                name: "tankDigest",
+                data: Cow::Owned(
+                    serde_json::to_string(&json!({
+                        "mails": digest.mails,
+                        "latest": digest
+                            .latest
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<String>>(),
+                    }))
+                    .map_err(MailcatcherError::http)?,
+                ),
+            },
+        })
     }
 }
 
@@ -65,16 +77,24 @@ mod tests {
         crate::test::log_init();
 
         let sse_evt: SseEvt = SseEvt::Ping;
-        let data: SseData = sse_evt.into();
+        let data: SseData = SseData::from_evt(sse_evt, IdStrategy::Ulid).expect("sse data");
         assert_eq!(data.name, "ping");
         assert_eq!(data.data, "\u{1f493}");
 
         let id: Ulid = Ulid::new();
         let sse_evt: SseEvt = SseEvt::DelMail(id);
-        let data: SseData = sse_evt.into();
+        let data: SseData = SseData::from_evt(sse_evt, IdStrategy::Ulid).expect("sse data");
         assert_eq!(data.name, "delMail");
         assert_eq!(data.data, id.to_string());
 
+        let sse_evt: SseEvt = SseEvt::TankDigest(TankDigest::new(vec![id].into_iter()));
+        let data: SseData = SseData::from_evt(sse_evt, IdStrategy::Ulid).expect("sse data");
+        assert_eq!(data.name, "tankDigest");
+        assert_eq!(
+            data.data,
+            format!("{{\"latest\":[\"{}\"],\"mails\":1}}", id)
+        );
+
         let mail: Mail = Mail::new(
             "from@example.org",
             &["to@example.net".into()],
@@ -88,9 +108,12 @@ X-Mailer: swaks v20201014.0 jetmore.org/john/code/swaks/
 This is a test mailing",
         );
         let id: Ulid = mail.get_id();
-        let sse_evt: SseEvt = SseEvt::NewMail(mail);
-        let data: SseData = sse_evt.into();
+        let number: u64 = mail.get_number();
+        let received: i64 = mail.get_received().timestamp();
+        let latency: i64 = mail.get_latency().expect("latency").num_milliseconds();
+        let sse_evt: SseEvt = SseEvt::NewMail(Box::new(mail));
+        let data: SseData = SseData::from_evt(sse_evt, IdStrategy::Sequential).expect("sse data");
         assert_eq!(data.name, "newMail");
-        assert_eq!(data.data, format!("{{\"date\":1606006703,\"from\":\"from@example.org\",\"id\":\"{}\",\"size\":248,\"subject\":\"test Sun, 22 Nov 2020 01:58:23 +0100\",\"to\":[\"to@example.net\"]}}", id));
+        assert_eq!(data.data, format!("{{\"attachments\":0,\"cc\":[],\"client\":null,\"date\":1606006703,\"declared\":1606006703,\"errors\":0,\"from\":\"from@example.org\",\"from_params\":{{}},\"has_html\":false,\"has_text\":true,\"id\":{},\"latency\":{},\"locked\":false,\"mismatch\":true,\"number\":{},\"pii\":0,\"priority\":\"normal\",\"received\":{},\"recipients\":1,\"size\":248,\"snippet\":\"This is a test mailing\",\"subject\":\"test Sun, 22 Nov 2020 01:58:23 +0100\",\"tags\":[],\"to\":[\"to@example.net\"],\"to_params\":[{{}}],\"ulid\":\"{}\"}}", number, latency, number, received, id));
     }
 }
@@ -0,0 +1,332 @@
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::{io, net::SocketAddr, str::FromStr};
+
+#[cfg(unix)]
+use async_std::os::unix::net::UnixDatagram;
+use async_std::{
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    sync::Mutex,
+};
+use async_trait::async_trait;
+use chrono::SecondsFormat;
+use futures::AsyncWriteExt;
+
+use crate::{
+    error::MailcatcherError,
+    http::{event_sink::EventSink, sse_evt::SseEvt},
+    mail::Mail,
+};
+
+/// Priority of the lines: facility mail (2), severity informational (6)
+const PRIORITY: u8 = 2 * 8 + 6;
+
+/// Id of the structured data of the lines, under the enterprise number reserved
+/// for the documentation
+const SD_ID: &str = "mail@32473";
+
+/// Port of the syslog collectors, when not given
+const DEFAULT_PORT: u16 = 514;
+
+/// Collector of the syslog lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTarget {
+    /// `udp://host:port`, each line is a datagram
+    Udp(String),
+    /// `tcp://host:port`, the lines are separated by a line feed
+    Tcp(String),
+    /// `unix:/path`, a datagram socket like `/dev/log`
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for SyslogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let with_port = |addr: &str| {
+            if addr.is_empty() {
+                Err(format!("The syslog target has no host: {}", s))
+            } else if addr.rsplit_once(':').map_or(false, |(host, port)| {
+                // The colons of an IPv6 address are not followed by a port
+                port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+            }) {
+                Ok(addr.to_owned())
+            } else {
+                Ok(format!("{}:{}", addr, DEFAULT_PORT))
+            }
+        };
+
+        if let Some(addr) = s.strip_prefix("udp://") {
+            with_port(addr).map(Self::Udp)
+        } else if let Some(addr) = s.strip_prefix("tcp://") {
+            with_port(addr).map(Self::Tcp)
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return match path.trim_start_matches("//") {
+                "" => Err(format!("The syslog target has no path: {}", s)),
+                path => Ok(Self::Unix(PathBuf::from(path))),
+            };
+            #[cfg(not(unix))]
+            return Err(format!("Unix sockets are not available: {}", path));
+        } else {
+            Err(format!(
+                "Unknown syslog target {}, expected udp://host:port, tcp://host:port or unix:/path",
+                s
+            ))
+        }
+    }
+}
+
+/// Connection to the collector
+enum Transport {
+    /// UDP socket, connected to the collector
+    Udp(UdpSocket),
+    /// TCP stream
+    Tcp(TcpStream),
+    /// Unix datagram socket, connected to the collector
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+impl Transport {
+    /// Connect to the collector
+    async fn connect(target: &SyslogTarget) -> io::Result<Self> {
+        match *target {
+            SyslogTarget::Udp(ref addr) => {
+                let addr: SocketAddr = addr.to_socket_addrs().await?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("{} not resolved", addr))
+                })?;
+                let local: &str = if addr.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket: UdpSocket = UdpSocket::bind(local).await?;
+                socket.connect(addr).await?;
+                Ok(Self::Udp(socket))
+            }
+            SyslogTarget::Tcp(ref addr) => Ok(Self::Tcp(TcpStream::connect(addr).await?)),
+            #[cfg(unix)]
+            SyslogTarget::Unix(ref path) => {
+                let socket: UnixDatagram = UnixDatagram::unbound()?;
+                socket.connect(path).await?;
+                Ok(Self::Unix(socket))
+            }
+        }
+    }
+
+    /// Send a line to the collector
+    async fn send(&mut self, line: &str) -> io::Result<()> {
+        match *self {
+            Self::Udp(ref socket) => socket.send(line.as_bytes()).await.map(|_| ()),
+            Self::Tcp(ref mut stream) => stream.write_all(format!("{}\n", line).as_bytes()).await,
+            #[cfg(unix)]
+            Self::Unix(ref socket) => socket.send(line.as_bytes()).await.map(|_| ()),
+        }
+    }
+}
+
+/// Send a syslog line (RFC 5424) summarizing every new mail, with its envelope
+/// in the structured data
+pub struct SyslogSink {
+    /// Collector of the lines
+    target: SyslogTarget,
+    /// Host name written in the lines
+    hostname: String,
+    /// Connection to the collector, opened again after a failure
+    transport: Mutex<Option<Transport>>,
+}
+
+impl SyslogSink {
+    /// Connect to the collector, the lines being sent from `hostname`
+    pub async fn connect(target: SyslogTarget, hostname: &str) -> crate::Result<Self> {
+        let transport: Transport = Transport::connect(&target).await.map_err(|e| {
+            MailcatcherError::http(format!(
+                "Unable to connect to the syslog collector {:?}: {}",
+                target, e
+            ))
+        })?;
+
+        Ok(Self {
+            target,
+            hostname: hostname.to_owned(),
+            transport: Mutex::new(Some(transport)),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink<SseEvt> for SyslogSink {
+    fn name(&self) -> &'static str {
+        "Syslog"
+    }
+
+    async fn notify(&self, evt: &SseEvt) -> crate::Result<()> {
+        let mail: &Mail = match *evt {
+            SseEvt::NewMail(ref mail) => mail,
+            SseEvt::DelMail(_) | SseEvt::Ping | SseEvt::TankDigest(_) => return Ok(()),
+        };
+        let line: String = line(mail, &self.hostname);
+
+        let mut transport = self.transport.lock().await;
+        if let Some(ref mut connected) = *transport {
+            match connected.send(&line).await {
+                Ok(()) => return Ok(()),
+                Err(e) => log::debug!("Syslog collector disconnected: {}", e),
+            }
+        }
+        // The previous connection is broken, so the line is sent in a new one
+        *transport = None;
+        let mut connected: Transport = Transport::connect(&self.target)
+            .await
+            .map_err(MailcatcherError::http)?;
+        connected
+            .send(&line)
+            .await
+            .map_err(MailcatcherError::http)?;
+        *transport = Some(connected);
+
+        Ok(())
+    }
+}
+
+/// Syslog line of a mail
+fn line(mail: &Mail, hostname: &str) -> String {
+    let mut params: Vec<(&str, String)> = vec![
+        ("id", mail.get_id().to_string()),
+        ("from", mail.from().clone()),
+        ("to", mail.to().join(",")),
+        ("subject", mail.get_subject().clone()),
+        ("size", mail.get_size().to_string()),
+    ];
+    if let Some(user) = mail.get_auth_user() {
+        params.push(("auth_user", user.clone()));
+    }
+    let data: String = params
+        .iter()
+        .map(|&(name, ref value)| format!(" {}=\"{}\"", name, escape(value)))
+        .collect::<Vec<String>>()
+        .concat();
+
+    format!(
+        "<{}>1 {} {} mailcatcher {} new-mail [{}{}] \u{feff}Mail {} from {} to {}: {}",
+        PRIORITY,
+        mail.get_received()
+            .to_rfc3339_opts(SecondsFormat::Micros, true),
+        header_field(hostname),
+        std::process::id(),
+        SD_ID,
+        data,
+        mail.get_id(),
+        printable(mail.from()),
+        printable(&mail.to().join(", ")),
+        printable(mail.get_subject())
+    )
+}
+
+/// Value of a structured data parameter, where `"`, `\` and `]` are escaped
+fn escape(value: &str) -> String {
+    let mut escaped: String = String::with_capacity(value.len());
+    for c in printable(value).chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Text without the control characters, so the mail stays on one line
+fn printable(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// Field of the header of the line, made of printable ASCII characters, or `-`
+/// if empty
+fn header_field(value: &str) -> String {
+    let field: String = value.chars().filter(char::is_ascii_graphic).collect();
+    if field.is_empty() {
+        "-".to_owned()
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        crate::test::log_init();
+
+        assert_eq!(
+            "udp://localhost".parse(),
+            Ok(SyslogTarget::Udp("localhost:514".to_owned()))
+        );
+        assert_eq!(
+            "tcp://127.0.0.1:1514".parse(),
+            Ok(SyslogTarget::Tcp("127.0.0.1:1514".to_owned()))
+        );
+        assert_eq!(
+            "udp://[::1]".parse(),
+            Ok(SyslogTarget::Udp("[::1]:514".to_owned()))
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            "unix:///dev/log".parse(),
+            Ok(SyslogTarget::Unix(PathBuf::from("/dev/log")))
+        );
+        assert!("udp://".parse::<SyslogTarget>().is_err());
+        assert!("localhost:514".parse::<SyslogTarget>().is_err());
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn udp_line() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let mut mail: Mail = Mail::new(
+            "<alice@example.org>",
+            &["bob@example.net".to_owned(), "carol@example.net".to_owned()],
+            "Subject: [Report] \"Q3\"\r\n\r\nBody",
+        );
+        mail.set_auth_user(Some("alice".to_owned()));
+
+        let line: String = crate::test::with_timeout(1_000, async {
+            let collector: UdpSocket = UdpSocket::bind("127.0.0.1:0").await?;
+            let target: SyslogTarget = format!("udp://{}", collector.local_addr()?).parse()?;
+            let sink: SyslogSink = SyslogSink::connect(target, "QA host").await?;
+            sink.notify(&SseEvt::Ping).await?;
+            sink.notify(&SseEvt::NewMail(Box::new(mail.clone())))
+                .await?;
+
+            let mut buffer: Vec<u8> = vec![0; 1_024];
+            let size: usize = collector.recv(&mut buffer).await?;
+            buffer.truncate(size);
+            crate::test::Result::Ok(String::from_utf8(buffer)?)
+        })?;
+
+        assert_eq!(
+            line,
+            format!(
+                "<22>1 {} QAhost mailcatcher {} new-mail [mail@32473 id=\"{}\" \
+from=\"<alice@example.org>\" to=\"bob@example.net,carol@example.net\" \
+subject=\"[Report\\] \\\"Q3\\\"\" size=\"{}\" auth_user=\"alice\"] \u{feff}Mail {} from \
+<alice@example.org> to bob@example.net, carol@example.net: [Report] \"Q3\"",
+                mail.get_received()
+                    .to_rfc3339_opts(SecondsFormat::Micros, true),
+                std::process::id(),
+                mail.get_id(),
+                mail.get_size(),
+                mail.get_id()
+            )
+        );
+
+        Ok(())
+    }
+}
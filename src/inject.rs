@@ -0,0 +1,193 @@
+use std::path::{Path, PathBuf};
+
+use async_std::fs;
+use tide::{
+    http::{Body, Method, Response},
+    prelude::{json, Deserialize, Serialize},
+};
+
+use crate::{
+    error::MailcatcherError,
+    utils::{local_request, Output},
+};
+
+/// Reply of the injection route
+#[derive(Debug, Deserialize)]
+struct Injected {
+    /// Id of the new mail
+    id: String,
+}
+
+/// Injection of a file, printed with `--output json`
+#[derive(Debug, Serialize)]
+struct Outcome {
+    /// Injected file
+    file: PathBuf,
+    /// Id of the new mail, if it was injected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// Reason of the failure, if it was not injected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Inject the mail files in the instance listening on the HTTP `port`, with the
+/// `POST /api/mails` route
+///
+/// Every file is tried, even if an earlier one failed. With the JSON `output`,
+/// a single document is printed at the end, with the outcome of each file.
+#[allow(clippy::print_stdout)]
+pub async fn inject(port: u16, patterns: &[String], output: Output) -> crate::Result<()> {
+    let files: Vec<PathBuf> = expand(patterns)?;
+    let mut outcomes: Vec<Outcome> = Vec::new();
+
+    for file in &files {
+        let outcome: Outcome = match inject_file(port, file).await {
+            Ok(id) => {
+                if output == Output::Text {
+                    println!("{}: {}", file.display(), id);
+                }
+                Outcome {
+                    file: file.clone(),
+                    id: Some(id),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                log::error!("{} not injected: {}", file.display(), e);
+                Outcome {
+                    file: file.clone(),
+                    id: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        outcomes.push(outcome);
+    }
+
+    let failed: usize = outcomes
+        .iter()
+        .filter(|outcome| outcome.id.is_none())
+        .count();
+    if output == Output::Json {
+        println!("{}", report(&outcomes, failed));
+    }
+    if failed > 0 {
+        return Err(MailcatcherError::http(format!(
+            "{} of {} mails not injected",
+            failed,
+            files.len()
+        )));
+    }
+    Ok(())
+}
+
+/// JSON document of the injection, for `--output json`
+fn report(outcomes: &[Outcome], failed: usize) -> serde_json::Value {
+    json!({
+        "injected": outcomes.len().saturating_sub(failed),
+        "failed": failed,
+        "files": outcomes,
+    })
+}
+
+/// Expand the glob patterns into the files they match, sorted by name
+///
+/// A pattern that matches no file is an error, so a typo is not silently ignored.
+fn expand(patterns: &[String]) -> crate::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for pattern in patterns {
+        let mut matches: Vec<PathBuf> = glob::glob(pattern)
+            .map_err(|e| MailcatcherError::config(format!("Invalid pattern {}: {}", pattern, e)))?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect();
+        if matches.is_empty() {
+            return Err(MailcatcherError::config(format!(
+                "No file matching {}",
+                pattern
+            )));
+        }
+        matches.sort();
+        files.append(&mut matches);
+    }
+
+    Ok(files)
+}
+
+/// Send the raw content of a mail file, returning the id of the new mail
+async fn inject_file(port: u16, path: &Path) -> crate::Result<String> {
+    let content: Vec<u8> = fs::read(path).await.map_err(MailcatcherError::storage)?;
+    let mut body: Body = Body::from_string(String::from_utf8_lossy(&content).into_owned());
+    body.set_mime("message/rfc822");
+
+    let mut response: Response =
+        local_request(port, Method::Post, "/api/mails", Some(body)).await?;
+    let injected: Injected = response
+        .body_json()
+        .await
+        .map_err(|e| MailcatcherError::http(e.to_string()))?;
+
+    Ok(injected.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use ulid::Ulid;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn expand_patterns() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let dir: PathBuf = std::env::temp_dir().join(format!("mailcatcher-{}", Ulid::new()));
+        std::fs::create_dir_all(&dir)?;
+        for name in &["b.eml", "a.eml", "notes.txt"] {
+            std::fs::write(dir.join(name), "Subject: Test\r\n\r\nBody")?;
+        }
+        let pattern = |name: &str| dir.join(name).display().to_string();
+
+        let result: crate::Result<Vec<PathBuf>> = expand(&[pattern("*.eml"), pattern("notes.txt")]);
+        let missing: crate::Result<Vec<PathBuf>> = expand(&[pattern("*.msg")]);
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+
+        assert_eq!(
+            result?,
+            vec![dir.join("a.eml"), dir.join("b.eml"), dir.join("notes.txt")]
+        );
+        assert_eq!(missing.map_err(|e| e.exit_code()).err(), Some(78));
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_report() {
+        let outcomes: Vec<Outcome> = vec![
+            Outcome {
+                file: PathBuf::from("a.eml"),
+                id: Some("01F0000000000000000000000A".to_owned()),
+                error: None,
+            },
+            Outcome {
+                file: PathBuf::from("b.eml"),
+                id: None,
+                error: Some("HTTP error: 400: The mail content is empty".to_owned()),
+            },
+        ];
+
+        assert_eq!(
+            report(&outcomes, 1),
+            json!({
+                "injected": 1,
+                "failed": 1,
+                "files": [
+                    {"file": "a.eml", "id": "01F0000000000000000000000A"},
+                    {"file": "b.eml", "error": "HTTP error: 400: The mail content is empty"},
+                ],
+            })
+        );
+    }
+}
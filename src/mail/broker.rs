@@ -1,44 +1,322 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Borrow,
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
 
 use async_std::channel::{Receiver, Sender};
+use chrono::{DateTime, Utc};
 use futures::StreamExt;
+use tide::prelude::Serialize;
 use ulid::Ulid;
 
-use crate::mail::Mail;
+use crate::mail::{
+    filter::Filter,
+    journal::{Compaction, Journal},
+    store::MailStore,
+    thread::ThreadIndex,
+    timing::{Stage, Timing},
+    Mail,
+};
 
 /// Mail events sent from the SMTP (for `NewMail`) or HTTP side for the other from streams
 #[derive(Clone, Debug)]
 pub enum MailEvt {
     /// Add a new mail to the tank
-    NewMail(Mail),
+    NewMail(Box<Mail>),
     /// Get a mail from the id
     GetMail(Sender<Option<Mail>>, Ulid),
     /// Get all mails in the tank
     GetAll(Sender<Mail>),
+    /// Get the mails matching the filter
+    Search(Sender<Mail>, Filter),
+    /// Get the mails caused by a request, from its correlation id in lowercase
+    ByCorrelation(Sender<Mail>, String),
+    /// Get the id of the mail having a sequential number, nothing is sent back
+    /// if no mail has it
+    ByNumber(Sender<Ulid>, u64),
     /// Remove a mail by it's id
-    Remove(Sender<Option<Ulid>>, Ulid),
-    /// Clear the mail tank
+    Remove(Sender<Removal>, Ulid),
+    /// Clear the mail tank, except the locked mails
     RemoveAll(Sender<Ulid>),
+    /// Lock a mail so it cannot be removed, or unlock it, the mail is sent back
+    Lock(Sender<Option<Mail>>, Ulid, bool),
+    /// Get the statistics of the tank
+    GetStats(Sender<TankStats>),
+    /// Get the digest of the tank, for the browsers to check their list
+    GetDigest(Sender<TankDigest>),
+    /// Compact the journal, `None` is sent back if the mails are not persisted
+    Compact(Sender<Result<Option<Compaction>, String>>),
+    /// Get the mails that were in the tank at a given time, from the journal,
+    /// `None` is sent back if the mails are not persisted
+    AsOf(Sender<Result<Option<Vec<Mail>>, String>>, DateTime<Utc>),
+    /// Record the time spent to notify the browsers of a new mail
+    Notified(Ulid, Duration),
+    /// Get the time spent by a mail in each stage of its processing, `None` is
+    /// sent back if the mail is not in the tank
+    GetTiming(Sender<Option<Timing>>, Ulid),
+    /// Get the index of the conversations of the mails
+    GetThreads(Sender<ThreadIndex>),
+}
+
+/// Outcome of the removal of a mail
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Removal {
+    /// The mail has been removed
+    Removed,
+    /// The mail is locked, so it has been kept
+    Locked,
+    /// There is no mail with this id
+    NotFound,
+}
+
+/// Number of the newest mails whose ids are given in the digests
+pub const DIGEST_LATEST: usize = 10;
+
+/// Digest of the mails in the tank, pushed to the browsers so they notice the
+/// events they missed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TankDigest {
+    /// Number of mails
+    pub mails: usize,
+    /// Ids of the newest mails, the newest first, `DIGEST_LATEST` at most
+    pub latest: Vec<Ulid>,
+}
+
+impl TankDigest {
+    /// Compute the digest of the mails, from their ids
+    pub fn new<I: Iterator<Item = Ulid>>(ids: I) -> Self {
+        let mut digest: Self = Self::default();
+        for id in ids {
+            digest.mails = digest.mails.saturating_add(1);
+            digest.latest.push(id);
+        }
+        digest.keep_latest();
+        digest
+    }
+
+    /// Add the digest of other mails, like those of another shard of the tank
+    pub fn merge(&mut self, other: &Self) {
+        self.mails = self.mails.saturating_add(other.mails);
+        self.latest.extend(&other.latest);
+        self.keep_latest();
+    }
+
+    /// Keep the ids of the newest mails only, the ULID being sorted by the
+    /// reception time
+    fn keep_latest(&mut self) {
+        self.latest.sort_unstable_by(|a, b| b.cmp(a));
+        self.latest.truncate(DIGEST_LATEST);
+    }
+}
+
+/// Statistics of the mails in the tank
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TankStats {
+    /// Number of mails
+    pub mails: usize,
+    /// Total size of the mails
+    pub size: usize,
+    /// Delivery latency of the mails
+    pub latency: LatencyStats,
+    /// Kinds of content of the mails
+    pub content_types: ContentTypeStats,
+}
+
+/// Aggregation of the delivery latency, in milliseconds, of the mails having a Date header
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct LatencyStats {
+    /// Number of mails with a latency
+    pub count: usize,
+    /// Shortest latency
+    pub min: Option<i64>,
+    /// Longest latency
+    pub max: Option<i64>,
+    /// Average latency
+    pub mean: Option<i64>,
+}
+
+/// Number of mails by kind of content, a mail can be of several kinds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ContentTypeStats {
+    /// Mails with a text content but no html one
+    pub text_only: usize,
+    /// Mails with a html content but no plain text alternative
+    pub html_only: usize,
+    /// Mails with both a text and a html content
+    pub text_and_html: usize,
+    /// Mails whose content is split in MIME parts
+    pub multipart: usize,
+    /// Mails with attachments
+    pub with_attachments: usize,
+}
+
+impl ContentTypeStats {
+    /// Count the mail in its kinds of content
+    fn add(&mut self, mail: &Mail) {
+        let text: bool = mail.get_text().is_some();
+        let html: bool = mail.get_html().is_some();
+        for (matches, count) in [
+            (text && !html, &mut self.text_only),
+            (html && !text, &mut self.html_only),
+            (text && html, &mut self.text_and_html),
+            (mail.is_multipart(), &mut self.multipart),
+            (
+                !mail.get_attachments().is_empty(),
+                &mut self.with_attachments,
+            ),
+        ] {
+            if matches {
+                *count = count.saturating_add(1);
+            }
+        }
+    }
+
+    /// Add the counts of other mails
+    const fn merge(&mut self, other: &Self) {
+        self.text_only = self.text_only.saturating_add(other.text_only);
+        self.html_only = self.html_only.saturating_add(other.html_only);
+        self.text_and_html = self.text_and_html.saturating_add(other.text_and_html);
+        self.multipart = self.multipart.saturating_add(other.multipart);
+        self.with_attachments = self.with_attachments.saturating_add(other.with_attachments);
+    }
+}
+
+impl TankStats {
+    /// Compute the statistics of the mails
+    pub fn new<M: Borrow<Mail>, I: Iterator<Item = M>>(mails: I) -> Self {
+        let mut stats: Self = Self::default();
+        let mut latency_sum: i64 = 0;
+
+        for mail in mails {
+            let mail: &Mail = mail.borrow();
+            stats.mails = stats.mails.saturating_add(1);
+            stats.size = stats.size.saturating_add(mail.get_size());
+            stats.content_types.add(mail);
+            if let Some(latency) = mail.get_latency() {
+                let latency: i64 = latency.num_milliseconds();
+                stats.latency.count = stats.latency.count.saturating_add(1);
+                stats.latency.min = Some(stats.latency.min.map_or(latency, |min| min.min(latency)));
+                stats.latency.max = Some(stats.latency.max.map_or(latency, |max| max.max(latency)));
+                latency_sum = latency_sum.saturating_add(latency);
+            }
+        }
+        stats.latency.mean = i64::try_from(stats.latency.count)
+            .ok()
+            .and_then(|count| latency_sum.checked_div(count));
+
+        stats
+    }
+
+    /// Add the statistics of other mails, like those of another shard of the
+    /// tank
+    ///
+    /// The mean latency is weighted by the number of mails of each side, it is
+    /// exact to the millisecond.
+    pub fn merge(&mut self, other: &Self) {
+        self.mails = self.mails.saturating_add(other.mails);
+        self.size = self.size.saturating_add(other.size);
+        self.content_types.merge(&other.content_types);
+
+        let total = |latency: &LatencyStats| {
+            latency.mean.map_or(0, |mean| {
+                i64::try_from(latency.count).map_or(i64::MAX, |count| mean.saturating_mul(count))
+            })
+        };
+        let latency_sum: i64 = total(&self.latency).saturating_add(total(&other.latency));
+        self.latency.count = self.latency.count.saturating_add(other.latency.count);
+        self.latency.min = match (self.latency.min, other.latency.min) {
+            (Some(min), Some(other)) => Some(min.min(other)),
+            (min, other) => min.or(other),
+        };
+        self.latency.max = self.latency.max.max(other.latency.max);
+        self.latency.mean = i64::try_from(self.latency.count)
+            .ok()
+            .and_then(|count| latency_sum.checked_div(count));
+    }
 }
 
 /// Mail tank broker
 pub struct MailTank {
     /// Mails tank
-    mails: fnv::FnvHashMap<Ulid, Mail>,
+    mails: Box<dyn MailStore>,
     /// Channel to access the tank from the outside
     receiver: Receiver<MailEvt>,
+    /// Journal where to record the removals, if the mails are persisted
+    journal: Option<Journal>,
+    /// Time spent by the mails in each stage of their processing, by their id
+    timings: fnv::FnvHashMap<Ulid, Timing>,
+    /// Conversations of the mails
+    threads: ThreadIndex,
 }
 
 impl MailTank {
-    /// Instantiate a new broker
-    pub fn new(receiver: Receiver<MailEvt>) -> Self {
+    /// Instantiate a new broker, keeping the mails in the store
+    pub fn new(
+        receiver: Receiver<MailEvt>,
+        mails: Box<dyn MailStore>,
+        journal: Option<Journal>,
+    ) -> Self {
         Self {
-            mails: HashMap::default(),
+            mails,
             receiver,
+            journal,
+            timings: fnv::FnvHashMap::default(),
+            threads: ThreadIndex::default(),
         }
     }
 
-    /// Mail storage broker. All communication is from the `Receiver` stream
+    /// Add a new mail to the tank, keeping the time spent to get it there
+    fn insert(&mut self, mut mail: Mail) {
+        let mut timing: Timing = mail.take_timing();
+        timing.dequeue();
+        let id: Ulid = mail.get_id();
+        let start: Instant = Instant::now();
+        self.threads.insert(&mail);
+        self.mails.insert(mail);
+        timing.since(Stage::Insert, start);
+        let _ = self.timings.insert(id, timing);
+    }
+
+    /// Record the removal of a mail in the journal, so it will not be restored
+    async fn journal_removal(&self, id: Ulid) {
+        if let Some(ref journal) = self.journal {
+            if let Err(e) = journal.remove(id).await {
+                log::error!("Removal of {} not written in the journal: {}", id, e);
+            }
+        }
+    }
+
+    /// Compact the journal if enough removals were recorded since the last time
+    async fn auto_compact(&self) {
+        if let Some(ref journal) = self.journal {
+            if journal.needs_compaction() {
+                if let Err(e) = journal.compact().await {
+                    log::error!("Automatic compaction of the journal failed: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Remove a mail, unless it is locked
+    async fn remove(&mut self, id: Ulid) -> Removal {
+        match self.mails.get(&id) {
+            Some(ref mail) if mail.is_locked() => Removal::Locked,
+            Some(_) => {
+                let _ = self.mails.remove(&id);
+                let _ = self.timings.remove(&id);
+                self.threads.remove(&id);
+                self.journal_removal(id).await;
+                self.auto_compact().await;
+                Removal::Removed
+            }
+            None => Removal::NotFound,
+        }
+    }
+
+    /// Mail storage broker. All communication is from the `Receiver` stream, until
+    /// every sender is dropped
+    #[allow(clippy::too_many_lines)]
     pub async fn process(mut self) -> crate::Result<()> {
         loop {
             if let Some(evt) = self.receiver.next().await {
@@ -47,52 +325,172 @@ impl MailTank {
                     // A new mail, add it to the list
                     MailEvt::NewMail(mail) => {
                         log::trace!("Adding new mail");
-                        let _ = self.mails.insert(mail.get_id(), mail.clone());
+                        self.insert(*mail);
                     }
                     // Want to retrieve the mail from this id
                     MailEvt::GetMail(sender, id) => {
-                        let mail = self.mails.get(&id);
+                        let mail: Option<Mail> = self.mails.get(&id);
                         log::trace!("Mail found: {:?}", mail);
-                        sender.send(mail.cloned()).await?;
+                        let _ = reply(&sender, mail).await;
                         drop(sender);
                     }
                     // Want to retrieve all mails
                     MailEvt::GetAll(sender) => {
                         log::trace!("All mails retrieved");
-                        for mail in self.mails.values().cloned() {
-                            sender.send(mail).await?
+                        for mail in self.mails.iter() {
+                            if !reply(&sender, mail).await {
+                                break;
+                            }
+                        }
+                        drop(sender);
+                    }
+                    // Want to retrieve the mails matching a filter
+                    MailEvt::Search(sender, filter) => {
+                        log::trace!("Searching mails: {:?}", filter);
+                        for mail in self.mails.search(&filter) {
+                            if !reply(&sender, mail).await {
+                                break;
+                            }
+                        }
+                        drop(sender);
+                    }
+                    // Want to retrieve the mails caused by a request
+                    MailEvt::ByCorrelation(sender, id) => {
+                        log::trace!("Mails of the request {}", id);
+                        for mail in self.mails.by_correlation(&id) {
+                            if !reply(&sender, mail).await {
+                                break;
+                            }
+                        }
+                        drop(sender);
+                    }
+                    // Want to retrieve the id of the mail from its number
+                    MailEvt::ByNumber(sender, number) => {
+                        if let Some(id) = self.mails.by_number(number) {
+                            log::trace!("Mail {} numbered {}", id, number);
+                            let _ = reply(&sender, id).await;
                         }
                         drop(sender);
                     }
                     // Remove a mail by the id
                     MailEvt::Remove(sender, id) => {
-                        let mail_id = self.mails.remove(&id).map(|m| m.get_id());
-                        log::trace!("Mail deleted: {:?}", mail_id);
-                        sender.send(mail_id).await?;
+                        let removal: Removal = self.remove(id).await;
+                        log::trace!("Mail {} deleted: {:?}", id, removal);
+                        let _ = reply(&sender, removal).await;
+                        drop(sender);
+                    }
+                    // Compute the statistics of the tank
+                    MailEvt::GetStats(sender) => {
+                        log::trace!("Stats computed");
+                        let _ = reply(&sender, TankStats::new(self.mails.iter())).await;
+                        drop(sender);
+                    }
+                    MailEvt::GetDigest(sender) => {
+                        log::trace!("Digest computed");
+                        let ids = self.mails.iter().map(|mail| mail.get_id());
+                        let _ = reply(&sender, TankDigest::new(ids)).await;
                         drop(sender);
                     }
                     // Remove all mails
                     MailEvt::RemoveAll(sender) => {
-                        let ids: Vec<Ulid> = self.mails.keys().cloned().collect();
-                        log::trace!("All mails removed");
+                        let ids: Vec<Ulid> = self.mails.unlocked_ids();
+                        log::trace!("All mails removed, except the locked ones");
 
                         for id in ids {
                             let _ = self.mails.remove(&id);
-                            sender.send(id).await?;
+                            let _ = self.timings.remove(&id);
+                            self.threads.remove(&id);
+                            self.journal_removal(id).await;
+                            let _ = reply(&sender, id).await;
                         }
                         drop(sender);
+                        self.auto_compact().await;
+                    }
+                    // Lock or unlock a mail
+                    MailEvt::Lock(sender, id, locked) => {
+                        let mail: Option<Mail> = self.mails.get(&id).map(|mut mail| {
+                            mail.set_locked(locked);
+                            self.mails.insert(mail.clone());
+                            mail
+                        });
+                        log::trace!("Mail {} locked {}: {}", id, locked, mail.is_some());
+                        let _ = reply(&sender, mail).await;
+                        drop(sender);
+                    }
+                    // Compact the journal on demand
+                    MailEvt::Compact(sender) => {
+                        let compaction: Result<Option<Compaction>, String> = match self.journal {
+                            Some(ref journal) => {
+                                journal.compact().await.map(Some).map_err(|e| e.to_string())
+                            }
+                            None => Ok(None),
+                        };
+                        log::trace!("Journal compacted: {:?}", compaction);
+                        let _ = reply(&sender, compaction).await;
+                        drop(sender);
+                    }
+                    // Reconstruct the tank of the past from the journal
+                    MailEvt::AsOf(sender, at) => {
+                        let mails: Result<Option<Vec<Mail>>, String> = match self.journal {
+                            Some(ref journal) => {
+                                journal.as_of(at).await.map(Some).map_err(|e| e.to_string())
+                            }
+                            None => Ok(None),
+                        };
+                        log::trace!("Mails as of {} retrieved", at);
+                        let _ = reply(&sender, mails).await;
+                        drop(sender);
+                    }
+                    // The browsers were notified of a new mail
+                    MailEvt::Notified(id, duration) => {
+                        if let Some(timing) = self.timings.get_mut(&id) {
+                            timing.record(Stage::Notify, duration);
+                        }
+                    }
+                    // Want to retrieve the timing of a mail
+                    MailEvt::GetTiming(sender, id) => {
+                        let timing: Option<Timing> = self
+                            .mails
+                            .get(&id)
+                            .map(|_| self.timings.get(&id).cloned().unwrap_or_default());
+                        log::trace!("Timing of {}: {:?}", id, timing);
+                        let _ = reply(&sender, timing).await;
+                        drop(sender);
+                    }
+                    // Want to retrieve the conversations of the mails
+                    MailEvt::GetThreads(sender) => {
+                        log::trace!("Thread index retrieved");
+                        let _ = reply(&sender, self.threads.clone()).await;
+                        drop(sender);
                     }
                 }
+            } else {
+                // Every sender is gone, nothing can be asked anymore
+                return Ok(());
             }
         }
     }
 }
 
+/// Send a reply to a request, telling if it was sent
+///
+/// The requester can be gone, after a timeout or when the HTTP client
+/// disconnected: the reply is dropped, and the broker goes on.
+async fn reply<T>(sender: &Sender<T>, reply: T) -> bool {
+    let sent: bool = sender.send(reply).await.is_ok();
+    if !sent {
+        log::debug!("Reply dropped, the requester is gone");
+    }
+    sent
+}
+
 #[cfg(test)]
 mod tests {
     use async_std::{channel, prelude::FutureExt, task};
+    use futures::TryFutureExt;
 
     use super::*;
+    use crate::mail::{store::MemoryStore, Priority};
 
     struct Init {
         mails: Vec<Mail>,
@@ -100,7 +498,7 @@ mod tests {
         broker: MailTank,
     }
 
-    async fn init() -> crate::Result<Init> {
+    async fn init() -> crate::test::Result<Init> {
         // Enable crate log output
         crate::test::log_init();
 
@@ -111,10 +509,10 @@ mod tests {
         for _ in 0..3 {
             let mail = Mail::fake();
             mails.push(mail.clone());
-            sender.send(MailEvt::NewMail(mail)).await?;
+            sender.send(MailEvt::NewMail(Box::new(mail))).await?;
         }
 
-        let broker = MailTank::new(receiver);
+        let broker = MailTank::new(receiver, Box::new(MemoryStore::default()), None);
 
         Ok(Init {
             mails,
@@ -126,7 +524,7 @@ mod tests {
     #[test]
     fn get_one_mail() -> std::io::Result<()> {
         #[allow(clippy::indexing_slicing, clippy::panic)]
-        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::Result<()> {
+        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::test::Result<()> {
             // -----------------------
             // Test getting 1 mail by id
             // -----------------------
@@ -168,13 +566,16 @@ mod tests {
             broker,
         } = task::block_on(init()).expect("Init");
 
-        crate::test::with_timeout(5_000, broker.process().race(the_test(mails, sender)))
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
     }
 
     #[test]
     fn get_all_mails() -> std::io::Result<()> {
         #[allow(clippy::indexing_slicing, clippy::panic)]
-        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::Result<()> {
+        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::test::Result<()> {
             // -----------------------
             // Test getting all mails
             // -----------------------
@@ -206,13 +607,72 @@ mod tests {
             broker,
         } = task::block_on(init()).expect("Init");
 
-        crate::test::with_timeout(5_000, broker.process().race(the_test(mails, sender)))
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
+    }
+
+    #[test]
+    fn search_mails() -> std::io::Result<()> {
+        async fn the_test(sender: Sender<MailEvt>) -> crate::test::Result<()> {
+            let urgent: Mail = Mail::new("", &[], "X-Priority: 1\r\n\r\nNow!");
+            sender
+                .send(MailEvt::NewMail(Box::new(urgent.clone())))
+                .await?;
+
+            // Stream channel to communicate
+            let (s, mut r): crate::Channel<Mail> = channel::unbounded();
+
+            sender
+                .send(MailEvt::Search(
+                    s,
+                    Filter {
+                        priority: Some(Priority::High),
+                        ..Filter::default()
+                    },
+                ))
+                .await?;
+            let mut mail_retrieved = Vec::new();
+            while let Some(received_mail) = r.next().await {
+                mail_retrieved.push(received_mail.get_id());
+            }
+            // Fake mails have no priority
+            assert_eq!(mail_retrieved, vec![urgent.get_id()]);
+
+            Ok(())
+        }
+
+        let Init { sender, broker, .. } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(5_000, broker.process().err_into().race(the_test(sender)))
+    }
+
+    #[test]
+    fn compact_without_journal() -> std::io::Result<()> {
+        async fn the_test(sender: Sender<MailEvt>) -> crate::test::Result<()> {
+            let (s, mut r): crate::Channel<Result<Option<Compaction>, String>> =
+                channel::bounded(1);
+            sender.send(MailEvt::Compact(s)).await?;
+
+            // The mails are only in memory, nothing to compact
+            assert_eq!(r.next().await.ok_or("no response received")?, Ok(None));
+
+            Ok(())
+        }
+
+        let Init { sender, broker, .. } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(5_000, broker.process().err_into().race(the_test(sender)))
     }
 
     #[test]
     fn remove_one_mail() -> std::io::Result<()> {
         #[allow(clippy::indexing_slicing, clippy::panic)]
-        async fn the_test(mut mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::Result<()> {
+        async fn the_test(
+            mut mails: Vec<Mail>,
+            sender: Sender<MailEvt>,
+        ) -> crate::test::Result<()> {
             // -----------------------
             // Test removing 1 mail
             // -----------------------
@@ -220,19 +680,19 @@ mod tests {
             //  ... for unknown id
             {
                 // Stream channel to communicate
-                let (s, mut r): crate::Channel<Option<Ulid>> = channel::unbounded();
+                let (s, mut r): crate::Channel<Removal> = channel::unbounded();
 
                 sender.send(MailEvt::Remove(s, Ulid::new())).await?;
                 // Read unknown id result
-                let received_none: Option<Ulid> = r.next().await.ok_or("no received response")?;
-                assert!(received_none.is_none());
+                let removal: Removal = r.next().await.ok_or("no received response")?;
+                assert_eq!(removal, Removal::NotFound);
                 // 0 mails added, so must found 0 too...
             }
 
             // ... for known id
             {
                 // Stream channel to communicate
-                let (s, mut r): crate::Channel<Option<Ulid>> = channel::unbounded();
+                let (s, mut r): crate::Channel<Removal> = channel::unbounded();
 
                 let removed_mail = mails.remove(0);
 
@@ -240,8 +700,28 @@ mod tests {
                     .send(MailEvt::Remove(s, removed_mail.get_id()))
                     .await?;
                 // Read known id result
-                let received_id: Option<Ulid> = r.next().await.ok_or("no received response")?;
-                assert_eq!(received_id.expect("id"), removed_mail.get_id());
+                let removal: Removal = r.next().await.ok_or("no received response")?;
+                assert_eq!(removal, Removal::Removed);
+            }
+
+            // ... for a locked mail, kept until it is unlocked
+            {
+                let id: Ulid = mails[0].get_id();
+                for &(locked, expected) in &[(true, Removal::Locked), (false, Removal::Removed)] {
+                    let (s, mut r): crate::Channel<Option<Mail>> = channel::unbounded();
+                    sender.send(MailEvt::Lock(s, id, locked)).await?;
+                    let mail: Option<Mail> = r.next().await.ok_or("no received response")?;
+                    assert_eq!(mail.map(|mail| mail.is_locked()), Some(locked));
+
+                    let (s, mut r): crate::Channel<Removal> = channel::unbounded();
+                    sender.send(MailEvt::Remove(s, id)).await?;
+                    let removal: Removal = r.next().await.ok_or("no received response")?;
+                    assert_eq!(removal, expected);
+                }
+
+                let (s, mut r): crate::Channel<Option<Mail>> = channel::unbounded();
+                sender.send(MailEvt::Lock(s, id, true)).await?;
+                assert!(r.next().await.ok_or("no received response")?.is_none());
             }
 
             Ok(())
@@ -253,13 +733,228 @@ mod tests {
             broker,
         } = task::block_on(init()).expect("Init");
 
-        crate::test::with_timeout(5_000, broker.process().race(the_test(mails, sender)))
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
+    }
+
+    #[test]
+    fn get_digest() -> std::io::Result<()> {
+        #[allow(clippy::panic)]
+        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::test::Result<()> {
+            let (s, r): crate::Channel<TankDigest> = channel::bounded(1);
+            sender.send(MailEvt::GetDigest(s)).await?;
+            let digest: TankDigest = r.recv().await?;
+
+            let mut ids: Vec<Ulid> = mails.iter().map(Mail::get_id).collect();
+            ids.sort_unstable_by(|a, b| b.cmp(a));
+            ids.truncate(DIGEST_LATEST);
+            assert_eq!(digest.mails, mails.len());
+            assert_eq!(digest.latest, ids);
+
+            // Only the newest ids are kept once merged
+            let mut ids: Vec<Ulid> = (0..15).map(|_| Ulid::new()).collect();
+            ids.sort_unstable();
+            let mut merged: TankDigest = TankDigest::new(ids.iter().copied().step_by(2));
+            merged.merge(&TankDigest::new(ids.iter().copied().skip(1).step_by(2)));
+            ids.reverse();
+            ids.truncate(DIGEST_LATEST);
+            assert_eq!(merged.mails, 15);
+            assert_eq!(merged.latest, ids);
+
+            Ok(())
+        }
+
+        let Init {
+            mails,
+            sender,
+            broker,
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
+    }
+
+    #[test]
+    fn get_stats() -> std::io::Result<()> {
+        #[allow(clippy::panic)]
+        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::test::Result<()> {
+            // Stream channel to communicate
+            let (s, mut r): crate::Channel<TankStats> = channel::unbounded();
+
+            sender.send(MailEvt::GetStats(s)).await?;
+            let stats: TankStats = r.next().await.ok_or("no received response")?;
+
+            assert_eq!(stats.mails, mails.len());
+            assert_eq!(stats.size, mails.iter().map(Mail::get_size).sum::<usize>());
+            // Fake mails always have a Date header in the past
+            let latencies: Vec<i64> = mails
+                .iter()
+                .filter_map(|mail| mail.get_latency().map(|l| l.num_milliseconds()))
+                .collect();
+            assert_eq!(stats.latency.count, mails.len());
+            assert_eq!(stats.latency.min, latencies.iter().min().copied());
+            assert_eq!(stats.latency.max, latencies.iter().max().copied());
+            assert!(stats.latency.mean >= stats.latency.min);
+            assert!(stats.latency.mean <= stats.latency.max);
+
+            Ok(())
+        }
+
+        let Init {
+            mails,
+            sender,
+            broker,
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
+    }
+
+    #[test]
+    fn requester_gone() -> std::io::Result<()> {
+        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::test::Result<()> {
+            // The requesters give up before the broker replies
+            let (s, r): crate::Channel<Mail> = channel::unbounded();
+            drop(r);
+            sender.send(MailEvt::GetAll(s)).await?;
+            let (s, r): crate::Channel<Option<Mail>> = channel::unbounded();
+            drop(r);
+            let id: Ulid = mails.first().ok_or("no mail")?.get_id();
+            sender.send(MailEvt::GetMail(s, id)).await?;
+
+            // The broker still serves the next request
+            let (s, mut r): crate::Channel<TankStats> = channel::unbounded();
+            sender.send(MailEvt::GetStats(s)).await?;
+            let stats: TankStats = r.next().await.ok_or("no received response")?;
+            assert_eq!(stats.mails, mails.len());
+
+            Ok(())
+        }
+
+        let Init {
+            mails,
+            sender,
+            broker,
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
+    }
+
+    #[test]
+    fn mail_timing() -> std::io::Result<()> {
+        async fn get_timing(
+            sender: &Sender<MailEvt>,
+            id: Ulid,
+        ) -> crate::test::Result<Option<Timing>> {
+            let (s, mut r): crate::Channel<Option<Timing>> = channel::unbounded();
+            sender.send(MailEvt::GetTiming(s, id)).await?;
+            Ok(r.next().await.ok_or("no received response")?)
+        }
+
+        #[allow(clippy::indexing_slicing, clippy::panic)]
+        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::test::Result<()> {
+            let stages = |timing: &Timing| -> Vec<Stage> {
+                timing.laps().iter().map(|lap| lap.stage).collect()
+            };
+
+            // A new mail, through all the stages
+            let mut mail: Mail = Mail::fake();
+            let id: Ulid = mail.get_id();
+            mail.timing_mut()
+                .record(Stage::Receive, Duration::from_millis(2));
+            mail.timing_mut().enqueue();
+            sender.send(MailEvt::NewMail(Box::new(mail))).await?;
+            sender
+                .send(MailEvt::Notified(id, Duration::from_micros(40)))
+                .await?;
+            let timing: Timing = get_timing(&sender, id).await?.ok_or("no timing")?;
+            assert_eq!(
+                stages(&timing),
+                vec![Stage::Receive, Stage::Queue, Stage::Insert, Stage::Notify]
+            );
+            assert!(timing.total_micros() >= 2_040);
+
+            // The mails added without being enqueued are only inserted
+            let timing: Timing = get_timing(&sender, mails[0].get_id())
+                .await?
+                .ok_or("no timing")?;
+            assert_eq!(stages(&timing), vec![Stage::Insert]);
+
+            // The timing is removed with its mail
+            let (s, mut r): crate::Channel<Removal> = channel::unbounded();
+            sender.send(MailEvt::Remove(s, id)).await?;
+            assert_eq!(r.next().await, Some(Removal::Removed));
+            assert!(get_timing(&sender, id).await?.is_none());
+
+            Ok(())
+        }
+
+        let Init {
+            mails,
+            sender,
+            broker,
+        } = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
+    }
+
+    #[test]
+    fn content_type_stats() {
+        crate::test::log_init();
+
+        let mails: Vec<Mail> = vec![
+            Mail::new("", &[], "Subject: Text\r\n\r\nHello"),
+            Mail::new("", &[], "Content-Type: text/html\r\n\r\n<p>Hello</p>"),
+            Mail::new(
+                "",
+                &[],
+                "Content-Type: multipart/mixed; boundary=\"frontier\"\r\n\
+\r\n\
+--frontier\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Hello\r\n\
+--frontier\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>Hello</p>\r\n\
+--frontier\r\n\
+Content-Type: text/csv\r\n\
+Content-Disposition: attachment; filename=\"report.csv\"\r\n\
+\r\n\
+a,b\r\n\
+--frontier--\r\n",
+            ),
+        ];
+
+        assert_eq!(
+            TankStats::new(mails.iter()).content_types,
+            ContentTypeStats {
+                text_only: 1,
+                html_only: 1,
+                text_and_html: 1,
+                multipart: 1,
+                with_attachments: 1,
+            }
+        );
     }
 
     #[test]
     fn remove_all_mails() -> std::io::Result<()> {
         #[allow(clippy::indexing_slicing, clippy::panic)]
-        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::Result<()> {
+        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::test::Result<()> {
             // -----------------------
             // Test removing all mails from tha pool
             // -----------------------
@@ -292,6 +987,9 @@ mod tests {
             broker,
         } = task::block_on(init()).expect("Init");
 
-        crate::test::with_timeout(5_000, broker.process().race(the_test(mails, sender)))
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
     }
 }
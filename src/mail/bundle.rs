@@ -0,0 +1,269 @@
+use std::convert::TryFrom;
+
+use tide::prelude::{json, Serialize};
+
+use crate::mail::{Client, Mail, Parameters, Type};
+
+/// Size of the blocks of a tar archive
+const BLOCK: usize = 512;
+
+/// Name of the manifest in the bundle
+const MANIFEST: &str = "manifest.json";
+
+/// Mails bundled as test fixtures, to be committed into a repository: a tar
+/// archive of their raw content, in `<ulid>.eml` files, with a manifest of
+/// their envelopes
+///
+/// The same mails and tags give the same archive, byte for byte: the files
+/// are dated from the reception of the mails, without owner.
+#[derive(Clone, Debug, Default)]
+pub struct Bundle {
+    /// Tags of the bundle, given in the manifest
+    tags: Vec<String>,
+    /// Mails bundled, in the order they were added
+    mails: Vec<Mail>,
+}
+
+/// Mail described in the manifest
+#[derive(Clone, Debug, Serialize)]
+struct Described<'a> {
+    /// File of its raw content in the bundle
+    file: String,
+    /// ULID of the mail
+    id: String,
+    /// Expeditor address of the envelope
+    from: &'a str,
+    /// Recipient addresses of the envelope
+    to: &'a [String],
+    /// ESMTP parameters of the expeditor address
+    from_params: &'a Parameters,
+    /// ESMTP parameters of each recipient address
+    to_params: &'a [Parameters],
+    /// Reception time, in RFC 3339
+    received: String,
+    /// Subject of the mail
+    subject: &'a str,
+    /// Size of the raw content, in bytes
+    size: usize,
+    /// User the SMTP client was authenticated as
+    auth_user: Option<&'a String>,
+    /// SMTP client the mail was received from
+    client: Option<&'a Client>,
+    /// Tags given by the rules at the reception
+    tags: &'a [String],
+}
+
+impl Bundle {
+    /// Bundle tagged with `tags`
+    pub const fn new(tags: Vec<String>) -> Self {
+        Self {
+            tags,
+            mails: Vec::new(),
+        }
+    }
+
+    /// Add a mail to the bundle, unless it is already in it
+    pub fn add(&mut self, mail: Mail) {
+        if self.mails.iter().all(|kept| kept.get_id() != mail.get_id()) {
+            self.mails.push(mail);
+        }
+    }
+
+    /// Manifest of the bundle, with the envelope of each mail
+    fn manifest(&self) -> String {
+        let mails: Vec<Described<'_>> = self
+            .mails
+            .iter()
+            .map(|mail| Described {
+                file: file_name(mail),
+                id: mail.get_id().to_string(),
+                from: mail.from(),
+                to: mail.to(),
+                from_params: mail.get_from_params(),
+                to_params: mail.get_to_params(),
+                received: mail.get_received().to_rfc3339(),
+                subject: mail.get_subject(),
+                size: mail.get_size(),
+                auth_user: mail.get_auth_user(),
+                client: mail.get_client(),
+                tags: mail.get_tags(),
+            })
+            .collect();
+        let manifest: serde_json::Value = json!({
+            "version": 1,
+            "tags": self.tags,
+            "mails": mails,
+        });
+        format!("{:#}\n", manifest)
+    }
+
+    /// Tar archive of the bundle, the manifest first
+    pub fn tar(&self) -> Vec<u8> {
+        let latest: i64 = self
+            .mails
+            .iter()
+            .map(|mail| mail.get_received().timestamp())
+            .max()
+            .unwrap_or_default();
+        let mut tar: Vec<u8> = Vec::new();
+        append(&mut tar, MANIFEST, latest, self.manifest().as_bytes());
+        for mail in &self.mails {
+            let data: &str = mail.get_data(&Type::Raw).map_or("", String::as_str);
+            append(
+                &mut tar,
+                &file_name(mail),
+                mail.get_received().timestamp(),
+                data.as_bytes(),
+            );
+        }
+        // The archive ends with two empty blocks
+        tar.resize(tar.len().saturating_add(BLOCK.saturating_mul(2)), 0);
+        tar
+    }
+}
+
+/// Name of the file of a mail in the bundle
+fn file_name(mail: &Mail) -> String {
+    format!("{}.eml", mail.get_id())
+}
+
+/// Append a file to a tar archive, in the ustar format, its content padded to
+/// a whole block
+fn append(tar: &mut Vec<u8>, name: &str, mtime: i64, content: &[u8]) {
+    let mut header: [u8; BLOCK] = [0; BLOCK];
+    put(&mut header, 0, name.as_bytes());
+    put(&mut header, 100, b"0000644\0");
+    put(&mut header, 108, b"0000000\0");
+    put(&mut header, 116, b"0000000\0");
+    put(
+        &mut header,
+        124,
+        format!("{:011o}\0", content.len()).as_bytes(),
+    );
+    put(
+        &mut header,
+        136,
+        format!("{:011o}\0", u64::try_from(mtime).unwrap_or_default()).as_bytes(),
+    );
+    // The checksum is computed with its own field filled with spaces
+    put(&mut header, 148, b"        ");
+    put(&mut header, 156, b"0");
+    put(&mut header, 257, b"ustar\0");
+    put(&mut header, 263, b"00");
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    put(&mut header, 148, format!("{:06o}\0 ", checksum).as_bytes());
+
+    tar.extend_from_slice(&header);
+    tar.extend_from_slice(content);
+    let padding: usize = content.len().wrapping_neg() % BLOCK;
+    tar.resize(tar.len().saturating_add(padding), 0);
+}
+
+/// Write a field of a tar header at its offset
+fn put(header: &mut [u8], offset: usize, value: &[u8]) {
+    if let Some(field) = header.get_mut(offset..offset.saturating_add(value.len())) {
+        field.copy_from_slice(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Files of a tar archive, with their name, their size and their content
+    fn files(tar: &[u8]) -> Vec<(String, usize, Vec<u8>)> {
+        let mut files: Vec<(String, usize, Vec<u8>)> = Vec::new();
+        let mut offset: usize = 0;
+        while let Some(header) = tar.get(offset..offset.saturating_add(BLOCK)) {
+            let name: String = String::from_utf8_lossy(header.get(..100).unwrap_or_default())
+                .trim_end_matches('\0')
+                .to_owned();
+            if name.is_empty() {
+                break;
+            }
+            let size: &str =
+                std::str::from_utf8(header.get(124..135).unwrap_or_default()).unwrap_or_default();
+            let size: usize = usize::from_str_radix(size, 8).unwrap_or_default();
+            let start: usize = offset.saturating_add(BLOCK);
+            let content: Vec<u8> = tar
+                .get(start..start.saturating_add(size))
+                .unwrap_or_default()
+                .to_vec();
+            files.push((name, size, content));
+            offset = start
+                .saturating_add(size)
+                .saturating_add(size.wrapping_neg() % BLOCK);
+        }
+        files
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn tar_bundle() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let first: Mail = Mail::new(
+            "from@example.org",
+            &["to@example.net".into()],
+            "Subject: First\r\n\r\nContent",
+        );
+        let second: Mail = Mail::fake();
+        let mut bundle: Bundle = Bundle::new(vec!["signup".to_owned()]);
+        bundle.add(first.clone());
+        bundle.add(second.clone());
+        bundle.add(first.clone());
+
+        let tar: Vec<u8> = bundle.tar();
+        assert_eq!(tar.len() % BLOCK, 0);
+        // Reproducible
+        assert_eq!(tar, bundle.tar());
+
+        let files: Vec<(String, usize, Vec<u8>)> = files(&tar);
+        let names: Vec<&str> = files.iter().map(|file| file.0.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                MANIFEST.to_owned(),
+                format!("{}.eml", first.get_id()),
+                format!("{}.eml", second.get_id()),
+            ]
+        );
+        let eml: &(String, usize, Vec<u8>) = files.get(1).ok_or("no eml")?;
+        assert_eq!(eml.2, b"Subject: First\r\n\r\nContent".to_vec());
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&files.first().ok_or("no manifest")?.2)?;
+        assert_eq!(manifest.pointer("/tags"), Some(&json!(["signup"])));
+        assert_eq!(
+            manifest.pointer("/mails/0/id"),
+            Some(&json!(first.get_id().to_string()))
+        );
+        assert_eq!(manifest.pointer("/mails/0/subject"), Some(&json!("First")));
+        assert_eq!(
+            manifest.pointer("/mails/0/to"),
+            Some(&json!(["to@example.net"]))
+        );
+        assert_eq!(
+            manifest.pointer("/mails/1/file"),
+            Some(&json!(format!("{}.eml", second.get_id())))
+        );
+
+        // The checksum of the headers is the one checked by tar
+        let header: &[u8] = tar.get(..BLOCK).ok_or("no header")?;
+        let stored: &str = std::str::from_utf8(header.get(148..154).ok_or("no checksum")?)?;
+        let computed: u32 = header
+            .iter()
+            .enumerate()
+            .map(|(index, &byte)| {
+                if (148..156).contains(&index) {
+                    u32::from(b' ')
+                } else {
+                    u32::from(byte)
+                }
+            })
+            .sum();
+        assert_eq!(u32::from_str_radix(stored, 8)?, computed);
+
+        Ok(())
+    }
+}
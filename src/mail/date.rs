@@ -0,0 +1,222 @@
+use std::convert::TryFrom;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+/// Names of the months, matched on their first 3 letters
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Names of the days of the week, matched on their first 3 letters
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Time zones given by their name, with their offset in hours
+const ZONES: [(&str, i32); 15] = [
+    ("z", 0),
+    ("ut", 0),
+    ("utc", 0),
+    ("gmt", 0),
+    ("est", -5),
+    ("edt", -4),
+    ("cst", -6),
+    ("cdt", -5),
+    ("mst", -7),
+    ("mdt", -6),
+    ("pst", -8),
+    ("pdt", -7),
+    ("bst", 1),
+    ("cet", 1),
+    ("cest", 2),
+];
+
+/// Parse a date of a header, like `Date`, following RFC 5322 if possible, or
+/// else leniently, returning it with `true` if it had to be
+///
+/// The dates sent by the sloppy clients are accepted: an RFC 3339 date, a
+/// day of the week that is missing or wrong, a year on 2 digits, a time
+/// without seconds, a zone like `GMT+0200` or `CEST`, or none at all for UTC.
+pub fn parse(value: &str) -> Option<(DateTime<FixedOffset>, bool)> {
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some((date, false));
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value.trim()) {
+        return Some((date, true));
+    }
+    lenient(value).map(|date| (date, true))
+}
+
+/// Parse the parts of a date, in any order but the day before the year
+fn lenient(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value: String = without_comments(value);
+    let mut tokens: Vec<&str> = value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.is_empty())
+        .collect();
+    // The day of the week is not checked against the date
+    if tokens
+        .first()
+        .map_or(false, |token| name_in(token, &DAYS).is_some())
+    {
+        let _day_of_week: &str = tokens.remove(0);
+    }
+    // The month may be before the day, like `Nov 22 2020`
+    if tokens.len() >= 2
+        && tokens
+            .first()
+            .map_or(false, |token| name_in(token, &MONTHS).is_some())
+    {
+        tokens.swap(0, 1);
+    }
+
+    let mut tokens = tokens.into_iter();
+    let day: u32 = tokens.next()?.parse().ok()?;
+    let month: u32 = tokens.next().and_then(|month| name_in(month, &MONTHS))?;
+    let year: i32 = tokens.next().and_then(year)?;
+    let time: NaiveTime = tokens.next().and_then(time)?;
+    // A missing zone is UTC, RFC 5322 asking for `-0000` when it is unknown
+    let offset: FixedOffset = match tokens.next() {
+        Some(zone) => offset(zone)?,
+        None => FixedOffset::east_opt(0)?,
+    };
+
+    let date: NaiveDateTime = NaiveDate::from_ymd_opt(year, month, day)?.and_time(time);
+    offset.from_local_datetime(&date).single()
+}
+
+/// Text without the comments between parentheses, like `(CEST)`
+fn without_comments(value: &str) -> String {
+    let mut depth: usize = 0;
+    value
+        .chars()
+        .filter(|&c| {
+            match c {
+                '(' => depth = depth.saturating_add(1),
+                ')' => depth = depth.saturating_sub(1),
+                _ => return depth == 0,
+            }
+            false
+        })
+        .collect()
+}
+
+/// Position, starting at 1, of the name among the `names`, from its first 3
+/// letters
+fn name_in(token: &str, names: &[&str]) -> Option<u32> {
+    let token: String = token.to_lowercase();
+    let prefix: &str = token.get(..3)?;
+    names
+        .iter()
+        .position(|&name| name == prefix)
+        .and_then(|index| u32::try_from(index.saturating_add(1)).ok())
+}
+
+/// Year on 4 digits, the years on 2 digits being from 1950 to 2049, and those
+/// on 3 digits from 1900, as RFC 5322 asks
+fn year(token: &str) -> Option<i32> {
+    let year: i32 = token.parse().ok()?;
+    Some(match token.len() {
+        1 | 2 if year < 50 => year.saturating_add(2000),
+        1..=3 => year.saturating_add(1900),
+        _ => year,
+    })
+}
+
+/// Time, with or without the seconds, and the fraction of a second ignored
+fn time(token: &str) -> Option<NaiveTime> {
+    let mut parts = token.split(':');
+    let hour: u32 = parts.next()?.parse().ok()?;
+    let minute: u32 = parts.next()?.parse().ok()?;
+    let second: u32 = match parts.next() {
+        Some(second) => second.split('.').next()?.parse().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    NaiveTime::from_hms_opt(hour, minute, second)
+}
+
+/// Offset of a zone, like `+0200`, `+02:00`, `GMT+2`, `UTC` or `CEST`
+fn offset(zone: &str) -> Option<FixedOffset> {
+    let zone: String = zone.to_lowercase();
+    if let Some(&(_, hours)) = ZONES.iter().find(|&&(name, _)| name == zone) {
+        return FixedOffset::east_opt(hours.saturating_mul(3_600));
+    }
+    // Numeric offset, after the name of the zone it is relative to if any
+    let numeric: &str = zone.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let (sign, digits): (i32, &str) = match numeric.get(..1) {
+        Some("+") => (1, numeric.get(1..)?),
+        Some("-") => (-1, numeric.get(1..)?),
+        _ => return None,
+    };
+    let (hours, minutes): (&str, &str) = match digits.split_once(':') {
+        Some(parts) => parts,
+        None if digits.len() > 2 => digits.split_at(digits.len().saturating_sub(2)),
+        None => (digits, "0"),
+    };
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(
+        sign.saturating_mul(
+            hours
+                .saturating_mul(3_600)
+                .saturating_add(minutes.saturating_mul(60)),
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lenient_dates() {
+        crate::test::log_init();
+
+        let date = |value: &str| parse(value).map(|(date, lenient)| (date.to_rfc3339(), lenient));
+
+        assert_eq!(
+            date("Sun, 22 Nov 2020 01:58:23 +0100"),
+            Some(("2020-11-22T01:58:23+01:00".to_owned(), false))
+        );
+        for &(value, expected) in &[
+            ("2020-11-22T01:58:23+01:00", "2020-11-22T01:58:23+01:00"),
+            (
+                "Mon, 22 Nov 2020 01:58:23 +0100",
+                "2020-11-22T01:58:23+01:00",
+            ),
+            (
+                "Sun, 22 Nov 20 01:58:23 GMT+0200",
+                "2020-11-22T01:58:23+02:00",
+            ),
+            ("22 Nov 98 01:58 +01:00", "1998-11-22T01:58:00+01:00"),
+            (
+                "Sunday, 22 November 2020 01:58:23.123 UTC+2",
+                "2020-11-22T01:58:23+02:00",
+            ),
+            ("Sun Nov 22 2020 01:58:23 CEST", "2020-11-22T01:58:23+02:00"),
+            (
+                "22 Nov 2020 01:58:23 -0530 (IST)",
+                "2020-11-22T01:58:23-05:30",
+            ),
+            ("22 Nov 2020 01:58:23", "2020-11-22T01:58:23+00:00"),
+        ] {
+            assert_eq!(date(value), Some((expected.to_owned(), true)), "{}", value);
+        }
+        for value in &[
+            "now",
+            "",
+            "31 Feb 2020 01:58:23 +0100",
+            "22 Nov 2020 25:58:23 +0100",
+            "22 Nov 2020 01:58:23 +2500",
+            "22 Nov 2020 01:58:23 Paris",
+            "Nov",
+            "Mon, Nov",
+        ] {
+            assert_eq!(date(value), None, "{}", value);
+        }
+    }
+}
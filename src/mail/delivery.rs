@@ -0,0 +1,293 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use rand::Rng;
+use tide::prelude::Serialize;
+
+use crate::mail::Mail;
+
+/// Simulated outcome of the delivery of a mail to a recipient
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// Delivered to the mailbox of the recipient
+    Delivered,
+    /// Delayed, the remote server asking to try again later
+    Deferred,
+    /// Refused for good, a bounce being sent back
+    Bounced,
+}
+
+impl Outcome {
+    /// Reply of the remote server for this outcome, like a MTA logs it
+    const fn reply(self) -> &'static str {
+        match self {
+            Self::Delivered => "250 2.0.0 Ok: delivered",
+            Self::Deferred => "451 4.2.1 Mailbox temporarily unavailable",
+            Self::Bounced => "550 5.1.1 Mailbox does not exist",
+        }
+    }
+}
+
+/// Simulated delivery of a mail to a recipient
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Delivery {
+    /// Address of the recipient
+    pub to: String,
+    /// What happened to the mail
+    pub outcome: Outcome,
+    /// Reply of the remote server
+    pub reply: String,
+}
+
+/// Weights of the outcomes of the simulated deliveries, given as
+/// `delivered=90,deferred=8,bounced=2`, the missing outcomes weighing nothing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Weights {
+    /// Weight of the delivered mails
+    delivered: u32,
+    /// Weight of the deferred mails
+    deferred: u32,
+    /// Weight of the bounced mails
+    bounced: u32,
+}
+
+impl Weights {
+    /// Sum of the weights
+    const fn total(self) -> u32 {
+        self.delivered
+            .saturating_add(self.deferred)
+            .saturating_add(self.bounced)
+    }
+
+    /// Outcome for a roll between 0 and the sum of the weights
+    const fn outcome(self, roll: u32) -> Outcome {
+        if roll < self.delivered {
+            Outcome::Delivered
+        } else if roll < self.delivered.saturating_add(self.deferred) {
+            Outcome::Deferred
+        } else {
+            Outcome::Bounced
+        }
+    }
+}
+
+impl FromStr for Weights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights: Self = Self::default();
+        for weight in s.split(',').map(str::trim) {
+            let (outcome, number): (&str, &str) = weight.split_once('=').ok_or_else(|| {
+                format!(
+                    "The weight must be \"outcome=number\", like delivered=90: {}",
+                    weight
+                )
+            })?;
+            let value: u32 = number
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid weight {}: {}", weight, e))?;
+            match outcome.trim().to_ascii_lowercase().as_str() {
+                "delivered" => weights.delivered = value,
+                "deferred" => weights.deferred = value,
+                "bounced" => weights.bounced = value,
+                _ => {
+                    return Err(format!(
+                        "Unknown outcome {}, expected delivered, deferred or bounced",
+                        outcome
+                    ))
+                }
+            }
+        }
+        if weights.total() == 0 {
+            return Err(format!("The weights give no outcome: {}", s));
+        }
+        Ok(weights)
+    }
+}
+
+/// Simulator of the delivery of the mails to their recipients, for the
+/// dashboards of the application under test to get realistic data
+#[derive(Clone, Copy, Debug)]
+pub struct Simulator {
+    /// Weights of the outcomes
+    weights: Weights,
+}
+
+impl Simulator {
+    /// Create a simulator drawing the outcomes with these weights
+    pub const fn new(weights: Weights) -> Self {
+        Self { weights }
+    }
+
+    /// Draw the outcome of the delivery of a mail to each of its recipients
+    pub fn simulate(&self, mail: &Mail) -> Vec<Delivery> {
+        let mut rng = rand::thread_rng();
+        mail.to()
+            .iter()
+            .map(|to| {
+                let outcome: Outcome = self.weights.outcome(rng.gen_range(0..self.weights.total()));
+                Delivery {
+                    to: to.clone(),
+                    outcome,
+                    reply: outcome.reply().to_owned(),
+                }
+            })
+            .collect()
+    }
+
+    /// Simulate the delivery of a mail, and record it in the mail
+    pub fn mark(&self, mail: &mut Mail) {
+        mail.set_deliveries(self.simulate(mail));
+    }
+}
+
+/// Number of deliveries by outcome
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    /// Deliveries to the mailboxes
+    pub delivered: usize,
+    /// Deliveries delayed
+    pub deferred: usize,
+    /// Deliveries refused
+    pub bounced: usize,
+}
+
+impl Counts {
+    /// Count a delivery
+    const fn add(&mut self, outcome: Outcome) {
+        let count: &mut usize = match outcome {
+            Outcome::Delivered => &mut self.delivered,
+            Outcome::Deferred => &mut self.deferred,
+            Outcome::Bounced => &mut self.bounced,
+        };
+        *count = count.saturating_add(1);
+    }
+}
+
+/// Aggregation of the simulated deliveries of the mails, given by
+/// `/api/deliveries`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Report {
+    /// Mails with simulated deliveries
+    pub mails: usize,
+    /// Deliveries, one per recipient
+    pub recipients: usize,
+    /// Deliveries by outcome
+    #[serde(flatten)]
+    pub outcomes: Counts,
+    /// Deliveries by outcome, for each domain of the recipients
+    pub domains: BTreeMap<String, Counts>,
+}
+
+impl Report {
+    /// Aggregate the deliveries of the mails
+    pub fn new<'a, I>(mails: I) -> Self
+    where
+        I: Iterator<Item = &'a Mail>,
+    {
+        let mut report: Self = Self::default();
+        for mail in mails.filter(|mail| !mail.get_deliveries().is_empty()) {
+            report.mails = report.mails.saturating_add(1);
+            for delivery in mail.get_deliveries() {
+                let domain: String = delivery
+                    .to
+                    .rsplit_once('@')
+                    .map_or("", |(_, domain)| domain)
+                    .to_ascii_lowercase();
+                report.recipients = report.recipients.saturating_add(1);
+                report.outcomes.add(delivery.outcome);
+                report
+                    .domains
+                    .entry(domain)
+                    .or_default()
+                    .add(delivery.outcome);
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights() {
+        crate::test::log_init();
+
+        let weights: Weights = Weights {
+            delivered: 90,
+            deferred: 8,
+            bounced: 2,
+        };
+        assert_eq!("delivered=90, deferred=8,bounced=2".parse(), Ok(weights));
+        assert_eq!(weights.outcome(0), Outcome::Delivered);
+        assert_eq!(weights.outcome(89), Outcome::Delivered);
+        assert_eq!(weights.outcome(90), Outcome::Deferred);
+        assert_eq!(weights.outcome(98), Outcome::Bounced);
+        assert_eq!(
+            "Bounced=1".parse(),
+            Ok(Weights {
+                bounced: 1,
+                ..Weights::default()
+            })
+        );
+        assert!("delivered=0".parse::<Weights>().is_err());
+        assert!("lost=1".parse::<Weights>().is_err());
+        assert!("delivered".parse::<Weights>().is_err());
+        assert!("delivered=-1".parse::<Weights>().is_err());
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn report() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let to: Vec<String> = vec![
+            "a@example.net".to_owned(),
+            "b@Example.NET".to_owned(),
+            "c@example.org".to_owned(),
+        ];
+        let mut bounced: Mail = Mail::new("from@example.org", &to, "Subject: bounced\r\n\r\n");
+        Simulator::new("bounced=1".parse()?).mark(&mut bounced);
+        assert_eq!(
+            bounced.get_deliveries().first(),
+            Some(&Delivery {
+                to: "a@example.net".to_owned(),
+                outcome: Outcome::Bounced,
+                reply: "550 5.1.1 Mailbox does not exist".to_owned(),
+            })
+        );
+        let mut delivered: Mail = Mail::new(
+            "from@example.org",
+            &["a@example.net".to_owned()],
+            "Subject: ok\r\n\r\n",
+        );
+        Simulator::new("delivered=1".parse()?).mark(&mut delivered);
+        let unknown: Mail = Mail::new("from@example.org", &to, "Subject: unknown\r\n\r\n");
+
+        let report: Report = Report::new([&bounced, &delivered, &unknown].iter().copied());
+        assert_eq!(report.mails, 2);
+        assert_eq!(report.recipients, 4);
+        assert_eq!(
+            report.outcomes,
+            Counts {
+                delivered: 1,
+                deferred: 0,
+                bounced: 3,
+            }
+        );
+        assert_eq!(
+            report.domains.get("example.net"),
+            Some(&Counts {
+                delivered: 1,
+                deferred: 0,
+                bounced: 2,
+            })
+        );
+        assert_eq!(report.domains.len(), 2);
+
+        Ok(())
+    }
+}
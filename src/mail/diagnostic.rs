@@ -0,0 +1,106 @@
+use tide::prelude::Serialize;
+
+/// A problem found while parsing a mail, the mail is stored anyway
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Diagnostic {
+    /// A line of the content is not valid UTF-8, it has been read as Latin-1
+    InvalidUtf8 {
+        /// Line number in the mail content, starting at 1
+        line: usize,
+    },
+    /// A line of the content ends with a bare LF, it has been normalized to CRLF
+    BareLineFeed {
+        /// Line number in the mail content, starting at 1
+        line: usize,
+    },
+    /// A line of the headers block is neither a header nor a continuation of one
+    InvalidHeader {
+        /// Line number in the mail content, starting at 1
+        line: usize,
+        /// Content of the line
+        content: String,
+    },
+    /// There is no blank line between the headers and the body
+    MissingSeparator,
+    /// A multipart entity has no boundary parameter, so it is kept as a single part
+    MissingBoundary {
+        /// Content type of the entity
+        content_type: String,
+    },
+    /// A multipart entity is not closed by its final delimiter
+    UnterminatedMultipart {
+        /// Boundary of the entity
+        boundary: String,
+    },
+    /// The Date header cannot be parsed, the reception time is used instead
+    InvalidDate {
+        /// Content of the header
+        value: String,
+    },
+    /// The Date header does not follow RFC 5322, but it could be read leniently
+    LenientDate {
+        /// Content of the header
+        value: String,
+    },
+    /// The body has been discarded at the reception, only the headers are kept
+    BodyDiscarded {
+        /// Size of the whole content, in bytes
+        size: usize,
+    },
+    /// The content has been discarded at the reception under a heavy load, only
+    /// the envelope is kept
+    ContentDiscarded {
+        /// Size of the whole content, in bytes
+        size: usize,
+    },
+}
+
+/// Check each line of a headers block, returning a diagnostic for the malformed ones
+pub fn check_headers(headers: &str) -> Vec<Diagnostic> {
+    headers
+        .lines()
+        .enumerate()
+        .filter(|&(idx, line)| {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                // A continuation is only valid after a header
+                idx == 0
+            } else {
+                line.find(':').map_or(true, |colon| colon == 0)
+            }
+        })
+        .map(|(idx, line)| Diagnostic::InvalidHeader {
+            line: idx.saturating_add(1),
+            content: line.to_owned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_headers() {
+        crate::test::log_init();
+
+        assert!(check_headers("Subject: ok\r\n\tcontinued\r\nX-Empty:").is_empty());
+        assert_eq!(
+            check_headers(" orphan\r\nSubject: ok\r\nno colon\r\n: no name"),
+            vec![
+                Diagnostic::InvalidHeader {
+                    line: 1,
+                    content: " orphan".to_owned()
+                },
+                Diagnostic::InvalidHeader {
+                    line: 3,
+                    content: "no colon".to_owned()
+                },
+                Diagnostic::InvalidHeader {
+                    line: 4,
+                    content: ": no name".to_owned()
+                },
+            ]
+        );
+    }
+}
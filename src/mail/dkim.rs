@@ -0,0 +1,722 @@
+use std::{fs, str::FromStr, sync::Arc, time::Duration};
+
+use async_std::prelude::FutureExt;
+use async_std_resolver::{
+    lookup::TxtLookup, resolver_from_system_conf, AsyncStdResolver, ResolveError,
+};
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use tide::prelude::{Deserialize, Serialize};
+use trust_dns_resolver::error::ResolveErrorKind;
+
+use crate::mail::{Mail, Type};
+
+/// Name of the header of the signatures
+const HEADER: &str = "DKIM-Signature";
+
+/// Outcome of the verification of a signature, named like in the
+/// `Authentication-Results` headers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// The signature is valid
+    Pass,
+    /// The signature or the hash of the body do not match the mail
+    Fail,
+    /// The hash of the body matches, the signature was not verified without
+    /// its public key
+    Neutral,
+    /// The public key could not be retrieved for now
+    TempError,
+    /// The signature or its public key are malformed, or not supported
+    PermError,
+}
+
+/// Report of a `DKIM-Signature` header of a mail
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Report {
+    /// Signing domain, from the `d=` tag
+    pub domain: Option<String>,
+    /// Selector of the public key, from the `s=` tag
+    pub selector: Option<String>,
+    /// Algorithm, from the `a=` tag
+    pub algorithm: Option<String>,
+    /// Signed headers, from the `h=` tag
+    pub headers: Vec<String>,
+    /// Outcome of the verification
+    pub result: Outcome,
+    /// Why the signature did not pass
+    pub reason: Option<String>,
+}
+
+impl Report {
+    /// Report of a signature with its outcome, and why if it did not pass
+    fn new(signature: &Signature, result: Outcome, reason: Option<String>) -> Self {
+        Self {
+            domain: signature.tag("d").map(ToOwned::to_owned),
+            selector: signature.tag("s").map(ToOwned::to_owned),
+            algorithm: signature.tag("a").map(ToOwned::to_owned),
+            headers: signature.headers(),
+            result,
+            reason,
+        }
+    }
+}
+
+/// Public key given on the command line, like
+/// `selector._domainkey.example.com=key.txt`
+///
+/// The file has the DNS record of the key, like `v=DKIM1; k=rsa; p=MIGfMA0…`,
+/// or the key in PEM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DkimKey {
+    /// DNS name of the key, in lowercase
+    name: String,
+    /// Record of the key
+    record: String,
+}
+
+impl FromStr for DkimKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, path): (&str, &str) = s
+            .split_once('=')
+            .ok_or_else(|| format!("The key must be \"selector._domainkey.domain=file\": {}", s))?;
+        let content: String = fs::read_to_string(path)
+            .map_err(|e| format!("Key of {} unreadable from {}: {}", name, path, e))?;
+        // A PEM key is given as the record of its base64 content
+        let record: String = if content.contains("-----BEGIN") {
+            let key: String = content
+                .lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect();
+            format!("p={}", key)
+        } else {
+            content
+        };
+        Ok(Self {
+            name: name.trim().to_ascii_lowercase(),
+            record,
+        })
+    }
+}
+
+/// Verifier of the DKIM signatures of the mails, with the public keys given on
+/// the command line, then those of the DNS with `--verify-dkim`
+///
+/// Without a public key, only the hash of the body is checked, which already
+/// tells if the content was changed after the signing.
+#[derive(Clone, Default)]
+pub struct Verifier {
+    /// Public keys given on the command line, by their name
+    keys: Arc<fnv::FnvHashMap<String, String>>,
+    /// Resolver looking the other public keys up in the DNS, `None` to only
+    /// use the keys given
+    resolver: Option<AsyncStdResolver>,
+}
+
+impl Verifier {
+    /// Create a verifier using the `keys`, then the DNS if `dns` is set, with
+    /// the resolver of the system
+    pub async fn new(keys: Vec<DkimKey>, dns: bool) -> Result<Self, ResolveError> {
+        let resolver: Option<AsyncStdResolver> = if dns {
+            Some(resolver_from_system_conf().await?)
+        } else {
+            None
+        };
+        Ok(Self {
+            keys: Arc::new(keys.into_iter().map(|key| (key.name, key.record)).collect()),
+            resolver,
+        })
+    }
+
+    /// Verify the signatures of a mail, and record the reports in the mail
+    ///
+    /// Each lookup of a public key in the DNS is given up after `delay`.
+    pub async fn mark(&self, mail: &mut Mail, delay: Duration) {
+        let reports: Vec<Report> = self.verify(mail, delay).await;
+        mail.set_dkim(reports);
+    }
+
+    /// Verify the signatures of a mail, in the order of its headers
+    async fn verify(&self, mail: &Mail, delay: Duration) -> Vec<Report> {
+        let raw: &str = mail.get_data(&Type::Raw).map_or("", String::as_str);
+        let headers: &str = mail.get_raw_headers().unwrap_or_default();
+        let fields: Vec<String> = fields(headers);
+        // The body starts after the empty line ending the headers
+        let body: &str = raw.get(headers.len()..).map_or("", |rest| {
+            rest.strip_prefix("\r\n")
+                .or_else(|| rest.strip_prefix('\n'))
+                .unwrap_or(rest)
+        });
+
+        let mut reports: Vec<Report> = Vec::new();
+        for field in fields.iter().filter(|field| is_named(field, HEADER)) {
+            let signature: Signature = Signature::parse(field);
+            reports.push(self.verify_one(&signature, &fields, body, delay).await);
+        }
+        reports
+    }
+
+    /// Verify a signature of the header `fields` and the `body`
+    async fn verify_one(
+        &self,
+        signature: &Signature,
+        fields: &[String],
+        body: &str,
+        delay: Duration,
+    ) -> Report {
+        if let Err((result, reason)) = signature.check(body) {
+            return Report::new(signature, result, Some(reason));
+        }
+        let name: String = format!(
+            "{}._domainkey.{}",
+            signature.tag("s").unwrap_or_default(),
+            signature.tag("d").unwrap_or_default()
+        )
+        .to_ascii_lowercase();
+        let record: String = match self.record(&name, delay).await {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                let reason: String = format!("no public key for {}, signature not verified", name);
+                return Report::new(signature, Outcome::Neutral, Some(reason));
+            }
+            Err((result, reason)) => return Report::new(signature, result, Some(reason)),
+        };
+
+        let header_data: Vec<u8> = signature.header_data(fields);
+        let (result, reason): (Outcome, Option<String>) =
+            match verify_rsa(&record, &header_data, signature) {
+                Ok(true) => (Outcome::Pass, None),
+                Ok(false) => (Outcome::Fail, Some("signature mismatch".to_owned())),
+                Err(reason) => (Outcome::PermError, Some(reason)),
+            };
+        Report::new(signature, result, reason)
+    }
+
+    /// Record of the public key with this name, from the command line or the
+    /// DNS, `None` if it cannot be looked up
+    async fn record(
+        &self,
+        name: &str,
+        delay: Duration,
+    ) -> Result<Option<String>, (Outcome, String)> {
+        if let Some(record) = self.keys.get(name) {
+            return Ok(Some(record.clone()));
+        }
+        let resolver: &AsyncStdResolver = match self.resolver {
+            Some(ref resolver) => resolver,
+            None => return Ok(None),
+        };
+
+        let lookup: TxtLookup = resolver
+            .txt_lookup(name)
+            .timeout(delay)
+            .await
+            .unwrap_or_else(|_| Err(ResolveError::from(format!("timed out after {:?}", delay))))
+            .map_err(|e| {
+                if let ResolveErrorKind::NoRecordsFound { .. } = *e.kind() {
+                    (Outcome::PermError, format!("no public key for {}", name))
+                } else {
+                    (
+                        Outcome::TempError,
+                        format!("lookup of {} failed: {}", name, e),
+                    )
+                }
+            })?;
+        // The strings of a record are concatenated
+        Ok(lookup.iter().next().map(|txt| {
+            txt.txt_data()
+                .iter()
+                .map(|data| String::from_utf8_lossy(data).into_owned())
+                .collect()
+        }))
+    }
+}
+
+/// Check the RSA signature of the header data, with the key of the record
+fn verify_rsa(record: &str, header_data: &[u8], signature: &Signature) -> Result<bool, String> {
+    let tags: Vec<(String, String)> = tag_list(record);
+    let tag = |name: &str| {
+        tags.iter()
+            .find(|tag| tag.0 == name)
+            .map(|tag| tag.1.as_str())
+    };
+    if tag("k").map_or(false, |kind| kind != "rsa") {
+        return Err(format!(
+            "unsupported key type {}",
+            tag("k").unwrap_or_default()
+        ));
+    }
+    let key: &str = tag("p").ok_or("public key without p= tag")?;
+    if key.is_empty() {
+        return Err("public key revoked".to_owned());
+    }
+    let der: Vec<u8> =
+        base64::decode(key).map_err(|e| format!("public key not in base64: {}", e))?;
+    let public: RsaPublicKey = RsaPublicKey::from_public_key_der(&der)
+        .or_else(|_| RsaPublicKey::from_pkcs1_der(&der))
+        .map_err(|e| format!("invalid public key: {}", e))?;
+    let sig: Vec<u8> = base64::decode(signature.tag("b").unwrap_or_default())
+        .map_err(|e| format!("signature not in base64: {}", e))?;
+
+    let hash = Sha256::digest(header_data);
+    Ok(public
+        .verify(Pkcs1v15Sign::new::<Sha256>(), &hash, &sig)
+        .is_ok())
+}
+
+/// Canonicalization of the headers or of the body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Canon {
+    /// Kept as they are
+    Simple,
+    /// The spaces are reduced, the header names in lowercase
+    Relaxed,
+}
+
+impl Canon {
+    /// Canonicalization from its name
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "simple" => Ok(Self::Simple),
+            "relaxed" => Ok(Self::Relaxed),
+            _ => Err(format!("unknown canonicalization {}", name)),
+        }
+    }
+
+    /// Canonicalize a header field, given with its folding but without its last
+    /// line ending
+    fn header(self, field: &str) -> String {
+        match self {
+            Self::Simple => format!("{}\r\n", field),
+            Self::Relaxed => {
+                let (name, folded): (&str, &str) = field.split_once(':').unwrap_or((field, ""));
+                let value: String = folded.replace("\r\n", "");
+                format!(
+                    "{}:{}\r\n",
+                    name.trim().to_ascii_lowercase(),
+                    reduce_spaces(&value).trim()
+                )
+            }
+        }
+    }
+
+    /// Canonicalize the body
+    fn body(self, body: &str) -> String {
+        let mut lines: Vec<String> = body
+            .lines()
+            .map(|line| match self {
+                Self::Simple => line.to_owned(),
+                Self::Relaxed => reduce_spaces(line).trim_end().to_owned(),
+            })
+            .collect();
+        while lines.last().map_or(false, String::is_empty) {
+            let _ = lines.pop();
+        }
+        if lines.is_empty() && self == Self::Simple {
+            return "\r\n".to_owned();
+        }
+        let mut canonical: String = lines.join("\r\n");
+        if !lines.is_empty() {
+            canonical.push_str("\r\n");
+        }
+        canonical
+    }
+}
+
+/// Tags of a `DKIM-Signature` header
+#[derive(Clone, Debug)]
+struct Signature {
+    /// The header field, as received
+    field: String,
+    /// Tags and their values, without their spaces
+    tags: Vec<(String, String)>,
+}
+
+impl Signature {
+    /// Parse the tags of the header field
+    fn parse(field: &str) -> Self {
+        let value: &str = field.split_once(':').map_or("", |(_, value)| value);
+        Self {
+            field: field.to_owned(),
+            tags: tag_list(value),
+        }
+    }
+
+    /// Value of a tag
+    fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.0 == name)
+            .map(|tag| tag.1.as_str())
+    }
+
+    /// Names of the signed headers, in lowercase
+    fn headers(&self) -> Vec<String> {
+        self.tag("h")
+            .map(|names| {
+                names
+                    .split(':')
+                    .map(|name| name.trim().to_ascii_lowercase())
+                    .filter(|name| !name.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Canonicalizations of the headers and of the body, from the `c=` tag
+    fn canon(&self) -> Result<(Canon, Canon), String> {
+        let c: &str = self.tag("c").unwrap_or("simple/simple");
+        let (header, body): (&str, &str) = c.split_once('/').unwrap_or((c, "simple"));
+        Ok((Canon::parse(header)?, Canon::parse(body)?))
+    }
+
+    /// Check the tags and the hash of the body, before the signature itself
+    fn check(&self, body: &str) -> Result<(), (Outcome, String)> {
+        self.check_tags()
+            .map_err(|reason| (Outcome::PermError, reason))?;
+        self.check_body(body)
+            .map_err(|reason| (Outcome::Fail, reason))
+    }
+
+    /// Check the required tags are given, with supported values
+    fn check_tags(&self) -> Result<(), String> {
+        for &tag in &["v", "a", "b", "bh", "d", "h", "s"] {
+            if self.tag(tag).is_none() {
+                return Err(format!("missing {}= tag", tag));
+            }
+        }
+        if self.tag("v") != Some("1") {
+            return Err(format!(
+                "unsupported version {}",
+                self.tag("v").unwrap_or_default()
+            ));
+        }
+        match self.tag("a").unwrap_or_default() {
+            "rsa-sha256" => {}
+            "rsa-sha1" => return Err("rsa-sha1 is no longer allowed".to_owned()),
+            algorithm => return Err(format!("unsupported algorithm {}", algorithm)),
+        }
+        if !self.headers().iter().any(|name| name == "from") {
+            return Err("the From header is not signed".to_owned());
+        }
+        self.canon().map(|_| ())
+    }
+
+    /// Check the hash of the body, that only covers its first `l=` bytes if
+    /// given
+    fn check_body(&self, body: &str) -> Result<(), String> {
+        let (_, body_canon): (Canon, Canon) = self.canon()?;
+        let canonical: String = body_canon.body(body);
+        let signed: &[u8] = match self.tag("l") {
+            Some(given) => {
+                let length: usize = given
+                    .parse()
+                    .map_err(|e| format!("invalid length {}: {}", given, e))?;
+                canonical
+                    .as_bytes()
+                    .get(..length)
+                    .ok_or("length longer than the body")?
+            }
+            None => canonical.as_bytes(),
+        };
+        if base64::encode(Sha256::digest(signed)) == self.tag("bh").unwrap_or_default() {
+            Ok(())
+        } else {
+            Err("body hash mismatch, the content was changed after the signing".to_owned())
+        }
+    }
+
+    /// Data signed by the signature: the signed headers, then the signature
+    /// header without its signature
+    fn header_data(&self, fields: &[String]) -> Vec<u8> {
+        let (header_canon, _): (Canon, Canon) =
+            self.canon().unwrap_or((Canon::Simple, Canon::Simple));
+        let mut data: Vec<u8> = Vec::new();
+        // Each name takes the last instance not taken yet, from the bottom
+        let mut taken: Vec<bool> = vec![false; fields.len()];
+        for name in self.headers() {
+            let found: Option<usize> = fields
+                .iter()
+                .enumerate()
+                .rev()
+                .find(|&(index, field)| {
+                    !taken.get(index).copied().unwrap_or(true) && is_named(field, &name)
+                })
+                .map(|(index, _)| index);
+            if let Some(index) = found {
+                if let (Some(used), Some(field)) = (taken.get_mut(index), fields.get(index)) {
+                    *used = true;
+                    data.extend(header_canon.header(field).as_bytes());
+                }
+            }
+        }
+
+        // The value of the b= tag is removed, the others are kept as they are
+        let (name, tags): (&str, &str) = self.field.split_once(':').unwrap_or((&self.field, ""));
+        let unsigned: String = tags
+            .split(';')
+            .map(|tag| match tag.split_once('=') {
+                Some((tag_name, _)) if tag_name.trim() == "b" => format!("{}=", tag_name),
+                _ => tag.to_owned(),
+            })
+            .collect::<Vec<String>>()
+            .join(";");
+        let own: String = header_canon.header(&format!("{}:{}", name, unsigned));
+        data.extend(own.trim_end_matches("\r\n").as_bytes());
+        data
+    }
+}
+
+/// Tags of a tag list, like `v=1; a=rsa-sha256`, the spaces of their values
+/// being removed
+fn tag_list(list: &str) -> Vec<(String, String)> {
+    list.split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(name, value)| {
+            (
+                name.trim().to_owned(),
+                value.chars().filter(|c| !c.is_whitespace()).collect(),
+            )
+        })
+        .collect()
+}
+
+/// Header fields of a header block, each with its folding but without its last
+/// line ending, the lines being ended by CRLF
+fn fields(headers: &str) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    for line in headers.lines() {
+        match fields.last_mut() {
+            Some(field) if line.starts_with([' ', '\t']) => {
+                field.push_str("\r\n");
+                field.push_str(line);
+            }
+            _ => fields.push(line.to_owned()),
+        }
+    }
+    fields
+}
+
+/// Check the name of a header field, without the case
+fn is_named(field: &str, name: &str) -> bool {
+    field.split_once(':').map_or(false, |(field_name, _)| {
+        field_name.trim().eq_ignore_ascii_case(name)
+    })
+}
+
+/// Reduce the sequences of spaces and tabs to a single space
+fn reduce_spaces(s: &str) -> String {
+    let mut reduced: String = String::with_capacity(s.len());
+    let mut space: bool = false;
+    for c in s.chars() {
+        if c == ' ' || c == '\t' {
+            space = true;
+        } else {
+            if space {
+                reduced.push(' ');
+                space = false;
+            }
+            reduced.push(c);
+        }
+    }
+    if space {
+        reduced.push(' ');
+    }
+    reduced
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std_resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        resolver,
+    };
+
+    use super::*;
+
+    /// Public key of the signatures, as published in the DNS
+    const RECORD: &str = "v=DKIM1; k=rsa; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDOcIDLAW5TmsaR92SIk3ijs7T9GyUugsn+ZGi/j42fzf8g/u1xEqLODRTfn/kIUOUKVhxTEH4p5RcGGUv8H1B35gNLwbOaT9E2npjBK8DVA3fgcRZnjKX/mkMpk+HFbsZYvV7Xfmy88t3ue1uCIXmLf4C39xWqT0WJox4xbEoaOwIDAQAB";
+
+    /// Signature of the mail with the relaxed canonicalization
+    const RELAXED: &str = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.net;\r\n s=test; h=From:To:Subject;\r\n bh=nis7oo7SrfnM+22mHMH0fvkUohBFYQPM8HH29E4KQ7s=;\r\n b=DWcSkpjpXc6RF5FSWY2JLSxcHOgNxsX5xkSs9HZjemS0z/n5GMPgqDHKjgaP1tLijD7UZy0YZ/MG6ngdbnm+/oo9nCP3u3oYeldWB7ujzxWZ+6GdSs6Povgm1Dsf08Lk5vMopTTybfw8u/Xkoh0/o4uhhGz7xou1KOq7X0266OU=";
+
+    /// Signature of the mail with the simple canonicalization
+    const SIMPLE: &str = "DKIM-Signature: v=1; a=rsa-sha256; c=simple/simple; d=example.net;\r\n s=test; h=From:To:Subject;\r\n bh=GURiAzrvHe4entBZX3wo7iS85eSDPmqggEGlPx1Od84=;\r\n b=JRpGw/dUhqAfjzlbT4GAtsSJtvZoYeF6gEUXd8U3kTTjzWfUxf7aVk3ScJazB7ZlcUj91fdWWOXeMhTdysyb0/FVWhk3Yj7GaE2frzWL02jtQpIPn1QKIO9by7WCP0a+uAIGU/62xRVoGWTGBCBJfd2lsOOEOLK5LEzAc/ntpy0=";
+
+    /// Delay of the lookups of the public keys
+    const DELAY: Duration = Duration::from_millis(100);
+
+    /// Subject of the signed mail, folded with extra spaces
+    const SUBJECT: &str = "Signed\r\n  \tmail   here ";
+
+    /// Body of the signed mail, with extra spaces and empty lines
+    const BODY: &str = "Hello  Bob, \r\n\r\nSee you.\r\n\r\n\r\n";
+
+    /// Mail signed with the signature, with its subject and its body
+    fn mail(signature: &str, subject: &str, body: &str) -> Mail {
+        Mail::new(
+            "alice@example.net",
+            &["bob@example.org".to_owned()],
+            &format!(
+                "{}\r\nFrom: Alice <alice@example.net>\r\nTo: bob@example.org\r\nSubject: {}\r\n\r\n{}",
+                signature, subject, body
+            ),
+        )
+    }
+
+    /// Verifier with the public key of the signatures
+    fn verifier() -> Verifier {
+        let key: DkimKey = DkimKey {
+            name: "test._domainkey.example.net".to_owned(),
+            record: RECORD.to_owned(),
+        };
+        Verifier {
+            keys: Arc::new(std::iter::once((key.name, key.record)).collect()),
+            resolver: None,
+        }
+    }
+
+    /// Outcomes of the signatures of a mail, with why
+    fn outcomes(verifier: &Verifier, mail: &Mail) -> Vec<(Outcome, Option<String>)> {
+        async_std::task::block_on(verifier.verify(mail, DELAY))
+            .into_iter()
+            .map(|report| (report.result, report.reason))
+            .collect()
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn valid_signatures() {
+        crate::test::log_init();
+
+        for &signature in &[RELAXED, SIMPLE] {
+            let mut signed: Mail = mail(signature, SUBJECT, BODY);
+            assert_eq!(outcomes(&verifier(), &signed), vec![(Outcome::Pass, None)]);
+
+            async_std::task::block_on(verifier().mark(&mut signed, DELAY));
+            let reports: &[Report] = signed.get_dkim();
+            assert_eq!(reports[0].domain.as_deref(), Some("example.net"));
+            assert_eq!(reports[0].selector.as_deref(), Some("test"));
+            assert_eq!(reports[0].headers, vec!["from", "to", "subject"]);
+        }
+
+        // Only the spaces change, that the relaxed canonicalization ignores
+        let respaced: Mail = mail(
+            RELAXED,
+            "Signed mail here",
+            "Hello Bob,\r\n\r\nSee you.\r\n",
+        );
+        assert_eq!(
+            outcomes(&verifier(), &respaced),
+            vec![(Outcome::Pass, None)]
+        );
+        let respaced: Mail = mail(SIMPLE, "Signed mail here", "Hello Bob,\r\n\r\nSee you.\r\n");
+        assert_eq!(outcomes(&verifier(), &respaced)[0].0, Outcome::Fail);
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn broken_signatures() {
+        crate::test::log_init();
+
+        // The body changed after the signing
+        let changed: Mail = mail(RELAXED, SUBJECT, "Hello Eve,\r\n");
+        let outcome: Vec<(Outcome, Option<String>)> = outcomes(&verifier(), &changed);
+        assert_eq!(outcome[0].0, Outcome::Fail);
+        assert!(outcome[0]
+            .1
+            .as_deref()
+            .unwrap_or_default()
+            .starts_with("body hash mismatch"));
+
+        // A signed header changed
+        let changed: Mail = mail(RELAXED, "Unsigned", BODY);
+        assert_eq!(
+            outcomes(&verifier(), &changed),
+            vec![(Outcome::Fail, Some("signature mismatch".to_owned()))]
+        );
+
+        // Without the public key, only the body hash is checked
+        let signed: Mail = mail(RELAXED, SUBJECT, BODY);
+        assert_eq!(
+            outcomes(&Verifier::default(), &signed)[0].0,
+            Outcome::Neutral
+        );
+
+        // The From header must be signed, and the tags given
+        let unsigned_from: Mail = mail(&RELAXED.replace("From:", ""), SUBJECT, BODY);
+        assert_eq!(
+            outcomes(&verifier(), &unsigned_from),
+            vec![(
+                Outcome::PermError,
+                Some("the From header is not signed".to_owned())
+            )]
+        );
+        let sha1: Mail = mail(&RELAXED.replace("rsa-sha256", "rsa-sha1"), SUBJECT, BODY);
+        assert_eq!(outcomes(&verifier(), &sha1)[0].0, Outcome::PermError);
+
+        // Nothing to report without a signature
+        let plain: Mail = mail("X-Mailer: test", SUBJECT, BODY);
+        assert!(outcomes(&verifier(), &plain).is_empty());
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn lookup_timeout() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            // The name server never replies
+            let server: async_std::net::UdpSocket =
+                async_std::net::UdpSocket::bind("127.0.0.1:0").await?;
+            let address: std::net::SocketAddr = server.local_addr()?;
+            let config: ResolverConfig = ResolverConfig::from_parts(
+                None,
+                Vec::new(),
+                NameServerConfigGroup::from_ips_clear(&[address.ip()], address.port(), true),
+            );
+            let verifier: Verifier = Verifier {
+                keys: Arc::default(),
+                resolver: Some(resolver(config, ResolverOpts::default()).await?),
+            };
+
+            let signed: Mail = mail(RELAXED, SUBJECT, BODY);
+            let started: std::time::Instant = std::time::Instant::now();
+            let reports: Vec<Report> = verifier.verify(&signed, DELAY).await;
+            assert!(started.elapsed() < Duration::from_secs(2));
+            assert_eq!(
+                reports
+                    .first()
+                    .map(|report| (report.result, report.reason.as_deref())),
+                Some((
+                    Outcome::TempError,
+                    Some("lookup of test._domainkey.example.net failed: timed out after 100ms")
+                ))
+            );
+            drop(server);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn canonicalization() {
+        crate::test::log_init();
+
+        assert_eq!(
+            Canon::Relaxed.header("Subject: a \t b\r\n\tc  "),
+            "subject:a b c\r\n"
+        );
+        assert_eq!(Canon::Simple.header("Subject: a  b"), "Subject: a  b\r\n");
+        assert_eq!(Canon::Relaxed.body(" a  b \r\n\r\n"), " a b\r\n");
+        assert_eq!(Canon::Relaxed.body("\r\n\r\n"), "");
+        assert_eq!(Canon::Simple.body(""), "\r\n");
+        assert_eq!(
+            fields("A: 1\r\nB: 2\r\n 3\r\nC: 4"),
+            vec!["A: 1", "B: 2\r\n 3", "C: 4"]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use tide::prelude::Serialize;
+
+use crate::mail::{Mail, Parameters};
+
+/// Longest envelope id, as RFC 3461 asks
+const MAX_ENVID: usize = 100;
+
+/// Event a delivery status notification is asked for, given by `NOTIFY`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Notify {
+    /// No notification at all
+    Never,
+    /// The mail is delivered
+    Success,
+    /// The mail cannot be delivered
+    Failure,
+    /// The delivery is delayed
+    Delay,
+}
+
+/// Part of the mail returned with a notification, given by `RET`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Ret {
+    /// The whole mail
+    Full,
+    /// Only its headers
+    Hdrs,
+}
+
+/// Delivery status notifications asked by the client, following RFC 3461
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Dsn {
+    /// Part of the mail returned, given with MAIL FROM
+    pub ret: Option<Ret>,
+    /// Id of the envelope, given with MAIL FROM, decoded
+    pub envid: Option<String>,
+    /// Notifications asked for each recipient, in the order of the envelope
+    pub recipients: Vec<RecipientDsn>,
+}
+
+/// Delivery status notifications asked for a recipient
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RecipientDsn {
+    /// Address of the recipient
+    pub to: String,
+    /// Events notified, the default ones of the server if `None`
+    pub notify: Option<Vec<Notify>>,
+    /// Original recipient, like `rfc822;bob@example.net`, decoded
+    pub orcpt: Option<String>,
+}
+
+impl Dsn {
+    /// Notifications asked with the envelope of the mail, `None` if the client
+    /// asked for none
+    ///
+    /// The invalid parameters, like those of the mails restored from an older
+    /// journal, are ignored.
+    pub fn of(mail: &Mail) -> Option<Self> {
+        let from: &Parameters = mail.get_from_params();
+        let dsn: Self = Self {
+            ret: ret(from).ok().flatten(),
+            envid: envid(from).ok().flatten(),
+            recipients: mail
+                .to()
+                .iter()
+                .zip(mail.get_to_params())
+                .map(|(to, params)| RecipientDsn {
+                    to: to.clone(),
+                    notify: notify(params).ok().flatten(),
+                    orcpt: orcpt(params).ok().flatten(),
+                })
+                .collect(),
+        };
+        let asked: bool = dsn.ret.is_some()
+            || dsn.envid.is_some()
+            || dsn
+                .recipients
+                .iter()
+                .any(|rcpt| rcpt.notify.is_some() || rcpt.orcpt.is_some());
+        Some(dsn).filter(|_| asked)
+    }
+}
+
+/// Check the DSN parameters of MAIL FROM, `RET` and `ENVID`
+pub fn check_mail(params: &Parameters) -> Result<(), String> {
+    let _ = ret(params)?;
+    let _ = envid(params)?;
+    Ok(())
+}
+
+/// Check the DSN parameters of RCPT TO, `NOTIFY` and `ORCPT`
+pub fn check_rcpt(params: &Parameters) -> Result<(), String> {
+    let _ = notify(params)?;
+    let _ = orcpt(params)?;
+    Ok(())
+}
+
+/// Part of the mail to return, from `RET`
+fn ret(params: &Parameters) -> Result<Option<Ret>, String> {
+    value(params, "RET")?
+        .map(|value| match value.to_ascii_uppercase().as_str() {
+            "FULL" => Ok(Ret::Full),
+            "HDRS" => Ok(Ret::Hdrs),
+            _ => Err(format!("Invalid RET: {}", value)),
+        })
+        .transpose()
+}
+
+/// Id of the envelope, from `ENVID`, decoded
+pub fn envid(params: &Parameters) -> Result<Option<String>, String> {
+    value(params, "ENVID")?
+        .map(|value| {
+            decode_xtext(value)
+                .filter(|_| value.len() <= MAX_ENVID)
+                .ok_or_else(|| format!("Invalid ENVID: {}", value))
+        })
+        .transpose()
+}
+
+/// Events to notify, from `NOTIFY`, `NEVER` being alone
+fn notify(params: &Parameters) -> Result<Option<Vec<Notify>>, String> {
+    value(params, "NOTIFY")?
+        .map(|value| {
+            let events: Vec<Notify> = value
+                .split(',')
+                .map(|event| match event.to_ascii_uppercase().as_str() {
+                    "NEVER" => Ok(Notify::Never),
+                    "SUCCESS" => Ok(Notify::Success),
+                    "FAILURE" => Ok(Notify::Failure),
+                    "DELAY" => Ok(Notify::Delay),
+                    _ => Err(format!("Invalid NOTIFY: {}", value)),
+                })
+                .collect::<Result<Vec<Notify>, String>>()?;
+            if events.len() > 1 && events.contains(&Notify::Never) {
+                return Err(format!("NEVER is not alone in NOTIFY: {}", value));
+            }
+            Ok(events)
+        })
+        .transpose()
+}
+
+/// Original recipient, from `ORCPT`, like `rfc822;bob@example.net`, decoded
+fn orcpt(params: &Parameters) -> Result<Option<String>, String> {
+    value(params, "ORCPT")?
+        .map(|value| {
+            value
+                .split_once(';')
+                .filter(|&(kind, _)| !kind.is_empty())
+                .and_then(|(kind, address)| {
+                    decode_xtext(address).map(|address| format!("{};{}", kind, address))
+                })
+                .ok_or_else(|| format!("Invalid ORCPT: {}", value))
+        })
+        .transpose()
+}
+
+/// Value of a parameter, that must have one if it is given
+fn value<'a>(params: &'a Parameters, keyword: &str) -> Result<Option<&'a str>, String> {
+    match params.get(keyword).map(Option::as_deref) {
+        Some(Some(value)) => Ok(Some(value)),
+        Some(None) => Err(format!("{} without a value", keyword)),
+        None => Ok(None),
+    }
+}
+
+/// Decode a `xtext` of RFC 3461, where the characters that are not printable,
+/// `+` and `=` are written like `+2B`
+pub fn decode_xtext(xtext: &str) -> Option<String> {
+    let mut decoded: Vec<u8> = Vec::with_capacity(xtext.len());
+    let mut bytes = xtext.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'+' => {
+                let hex: [u8; 2] = [bytes.next()?, bytes.next()?];
+                let hex: &str = std::str::from_utf8(&hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b'!'..=b'~' if byte != b'=' => decoded.push(byte),
+            _ => return None,
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parameters of an address, from `KEYWORD=value` pairs
+    fn params(pairs: &[(&str, &str)]) -> Parameters {
+        pairs
+            .iter()
+            .map(|&(keyword, value)| (keyword.to_owned(), Some(value.to_owned())))
+            .collect()
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn dsn_parameters() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        assert_eq!(decode_xtext("QQ+2BR+3D1"), Some("QQ+R=1".to_owned()));
+        assert_eq!(decode_xtext("a+2"), None);
+        assert_eq!(decode_xtext("a=b"), None);
+        assert_eq!(decode_xtext("a b"), None);
+
+        assert!(check_mail(&params(&[("RET", "hdrs"), ("ENVID", "QQ314159")])).is_ok());
+        assert!(check_mail(&params(&[("RET", "BODY")])).is_err());
+        assert!(check_mail(&params(&[("ENVID", &"x".repeat(101))])).is_err());
+        let mut no_value: Parameters = Parameters::new();
+        let _ = no_value.insert("RET".to_owned(), None);
+        assert!(check_mail(&no_value).is_err());
+
+        assert!(check_rcpt(&params(&[("NOTIFY", "SUCCESS,failure,DELAY")])).is_ok());
+        assert!(check_rcpt(&params(&[("NOTIFY", "NEVER")])).is_ok());
+        assert!(check_rcpt(&params(&[("NOTIFY", "NEVER,SUCCESS")])).is_err());
+        assert!(check_rcpt(&params(&[("NOTIFY", "")])).is_err());
+        assert!(check_rcpt(&params(&[("ORCPT", "rfc822;bob@example.net")])).is_ok());
+        assert!(check_rcpt(&params(&[("ORCPT", "bob@example.net")])).is_err());
+
+        let mut mail: Mail = Mail::new(
+            "from@example.org",
+            &["one@example.net".into(), "two@example.net".into()],
+            "Subject: DSN\r\n\r\nContent",
+        );
+        assert_eq!(Dsn::of(&mail), None);
+        mail.set_params(
+            params(&[("RET", "HDRS"), ("ENVID", "QQ+2B314159")]),
+            vec![
+                params(&[("NOTIFY", "SUCCESS,FAILURE"), ("ORCPT", "rfc822;one+40x")]),
+                Parameters::new(),
+            ],
+        );
+        assert_eq!(
+            Dsn::of(&mail),
+            Some(Dsn {
+                ret: Some(Ret::Hdrs),
+                envid: Some("QQ+314159".to_owned()),
+                recipients: vec![
+                    RecipientDsn {
+                        to: "one@example.net".to_owned(),
+                        notify: Some(vec![Notify::Success, Notify::Failure]),
+                        orcpt: Some("rfc822;one@x".to_owned()),
+                    },
+                    RecipientDsn {
+                        to: "two@example.net".to_owned(),
+                        ..RecipientDsn::default()
+                    },
+                ],
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Dsn::of(&mail))?.pointer("/recipients/0/notify"),
+            Some(&serde_json::json!(["SUCCESS", "FAILURE"]))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+use crate::mail::{Mail, Priority};
+
+/// Content that a mail can have, for the `has:` criterion
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Content {
+    /// At least one attachment
+    Attachment,
+    /// A text version
+    Text,
+    /// An html version
+    Html,
+    /// Personal data, found by the scanner
+    Pii,
+    /// Envelope recipients differing from those of the headers
+    Mismatch,
+}
+
+/// Criteria to select mails, an unset criterion selects every mail
+///
+/// The filter can be parsed from a search query, made of space separated terms that
+/// must all match: `from:alice to:*@x.test subject:"reset" after:2024-01-01 has:attachment
+/// tag:staging`.
+/// Addresses, subjects and free words are case insensitive and match a part of the
+/// content, unless they contain a `*` wildcard, then they must match an address entirely.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// Normalized priority of the mail
+    pub priority: Option<Priority>,
+    /// Patterns the expeditor must match
+    pub from: Vec<String>,
+    /// Patterns one of the recipients must match
+    pub to: Vec<String>,
+    /// Patterns the subject must match
+    pub subject: Vec<String>,
+    /// The mail must be dated from this date
+    pub after: Option<DateTime<Utc>>,
+    /// The mail must be dated before this date
+    pub before: Option<DateTime<Utc>>,
+    /// Content the mail must have
+    pub has: Vec<Content>,
+    /// Tags the mail must have
+    pub tags: Vec<String>,
+    /// Words the subject or the content must contain
+    pub words: Vec<String>,
+}
+
+impl Filter {
+    /// Check if the mail fulfills all the criteria
+    pub fn matches(&self, mail: &Mail) -> bool {
+        self.priority
+            .map_or(true, |priority| mail.get_priority() == priority)
+            && self
+                .from
+                .iter()
+                .all(|pattern| address_match(pattern, mail.from()))
+            && self.to.iter().all(|pattern| {
+                mail.to()
+                    .iter()
+                    .any(|address| address_match(pattern, address))
+            })
+            && self
+                .subject
+                .iter()
+                .all(|pattern| text_match(pattern, mail.get_subject()))
+            && self.after.map_or(true, |after| mail.get_date() >= after)
+            && self.before.map_or(true, |before| mail.get_date() < before)
+            && self.has.iter().all(|&content| match content {
+                Content::Attachment => !mail.get_attachments().is_empty(),
+                Content::Text => mail.get_text().is_some(),
+                Content::Html => mail.get_html().is_some(),
+                Content::Pii => !mail.get_pii().is_empty(),
+                Content::Mismatch => mail.has_mismatch(),
+            })
+            && self.tags.iter().all(|tag| {
+                mail.get_tags()
+                    .iter()
+                    .any(|kept| kept.eq_ignore_ascii_case(tag))
+            })
+            && self.words.iter().all(|word| {
+                text_match(word, mail.get_subject())
+                    || mail.get_text().map_or(false, |text| text_match(word, text))
+                    || mail.get_html().map_or(false, |html| text_match(word, html))
+            })
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let mut filter: Self = Self::default();
+
+        for term in split_terms(query) {
+            let (key, value): (&str, &str) = if let Some(colon) = term.find(':') {
+                (
+                    term.get(..colon).unwrap_or_default(),
+                    term.get(colon.saturating_add(1)..).unwrap_or_default(),
+                )
+            } else {
+                filter.words.push(term.to_lowercase());
+                continue;
+            };
+            if value.is_empty() {
+                return Err(format!("Missing value for the search term \"{}\"", key));
+            }
+            match key.to_lowercase().as_str() {
+                "from" => filter.from.push(value.to_lowercase()),
+                "to" => filter.to.push(value.to_lowercase()),
+                "subject" => filter.subject.push(value.to_lowercase()),
+                "tag" => filter.tags.push(value.to_owned()),
+                "after" => filter.after = Some(parse_date(value)?),
+                "before" => filter.before = Some(parse_date(value)?),
+                "has" => filter.has.push(match value.to_lowercase().as_str() {
+                    "attachment" | "attachments" => Content::Attachment,
+                    "text" => Content::Text,
+                    "html" => Content::Html,
+                    "pii" => Content::Pii,
+                    "mismatch" => Content::Mismatch,
+                    _ => return Err(format!("Unknown content \"{}\"", value)),
+                }),
+                "priority" => {
+                    filter.priority = Some(match value.to_lowercase().as_str() {
+                        "high" => Priority::High,
+                        "normal" => Priority::Normal,
+                        "low" => Priority::Low,
+                        _ => return Err(format!("Unknown priority \"{}\"", value)),
+                    });
+                }
+                _ => return Err(format!("Unknown search term \"{}\"", key)),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+/// Split a query into its terms, on spaces that are not between double quotes,
+/// the quotes are removed
+fn split_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    let mut current: String = String::new();
+    let mut quoted: bool = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' if !quoted => {
+                if !current.is_empty() {
+                    terms.push(current.clone());
+                    current.clear();
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        terms.push(current);
+    }
+
+    terms
+}
+
+/// Parse a date, either a day (`2024-01-01`, at midnight UTC) or a RFC 3339 date and time
+pub fn parse_date(date: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|day| Utc.from_utc_datetime(&day))
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(date)
+                .ok()
+                .map(|date| date.with_timezone(&Utc))
+        })
+        .ok_or_else(|| format!("Invalid date \"{}\"", date))
+}
+
+/// Check if a lowercase pattern matches a part of a text, ignoring its case
+fn text_match(pattern: &str, text: &str) -> bool {
+    text.to_lowercase().contains(pattern)
+}
+
+/// Check if a lowercase pattern matches an address, ignoring its case
+///
+/// With a wildcard, the address (without the name and the angle brackets) must be matched
+/// entirely, otherwise the pattern must be found in the address or its name.
+fn address_match(pattern: &str, address: &str) -> bool {
+    if pattern.contains('*') {
+        let address: String = address.to_lowercase();
+        // Keep the address between angle brackets, if any
+        let address: &str = match (address.rfind('<'), address.rfind('>')) {
+            (Some(start), Some(end)) if start < end => address
+                .get(start.saturating_add(1)..end)
+                .unwrap_or_default(),
+            _ => address.trim(),
+        };
+        wildcard_match(pattern, address)
+    } else {
+        text_match(pattern, address)
+    }
+}
+
+/// Check if a text matches entirely a pattern, where `*` matches any characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts: Vec<&str> = pattern.split('*').collect();
+    // There is always a first part, maybe empty
+    let first: &str = parts.first().copied().unwrap_or_default();
+    let mut rest: &str = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    // Without a wildcard, there is no last part
+    let last: Option<&str> = if parts.len() > 1 { parts.pop() } else { None };
+    let last: &str = match last {
+        Some(last) => last,
+        None => return rest.is_empty(),
+    };
+
+    // The middle parts must be found in order
+    for part in parts.iter().skip(1) {
+        match rest.find(part) {
+            Some(idx) => {
+                rest = rest
+                    .get(idx.saturating_add(part.len())..)
+                    .unwrap_or_default();
+            }
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::pii::Finding;
+
+    #[test]
+    fn filter_priority() {
+        crate::test::log_init();
+
+        let urgent: Mail = Mail::new("", &[], "Importance: high\r\n\r\nNow!");
+        let normal: Mail = Mail::new("", &[], "Subject: Later\r\n\r\nWhenever");
+
+        let filter: Filter = Filter::default();
+        assert!(filter.matches(&urgent));
+        assert!(filter.matches(&normal));
+
+        let filter: Filter = Filter {
+            priority: Some(Priority::High),
+            ..Filter::default()
+        };
+        assert!(filter.matches(&urgent));
+        assert!(!filter.matches(&normal));
+    }
+
+    #[test]
+    fn parse_query() {
+        crate::test::log_init();
+
+        let filter: Filter =
+            "from:Alice to:*@x.test subject:\"Reset password\" after:2024-01-01 has:attachment urgent"
+                .parse()
+                .expect("valid query");
+        assert_eq!(
+            filter,
+            Filter {
+                from: vec!["alice".to_owned()],
+                to: vec!["*@x.test".to_owned()],
+                subject: vec!["reset password".to_owned()],
+                after: Some(Utc.ymd(2024, 1, 1).and_hms(0, 0, 0)),
+                has: vec![Content::Attachment],
+                words: vec!["urgent".to_owned()],
+                ..Filter::default()
+            }
+        );
+
+        assert!("".parse::<Filter>().expect("empty query") == Filter::default());
+        for query in &["unknown:term", "after:yesterday", "has:", "priority:urgent"] {
+            assert!(query.parse::<Filter>().is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn query_matches() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new(
+            "Alice <alice@example.org>",
+            &["<bob@x.test>".to_owned(), "carol@y.test".to_owned()],
+            "Date: Tue, 2 Jan 2024 10:00:00 +0000\r\nSubject: Reset your password\r\n\r\nClick here",
+        );
+
+        for query in &[
+            "",
+            "from:alice",
+            "from:*@example.org",
+            "to:*@x.test",
+            "to:carol@*",
+            "to:b*b@x.*",
+            "subject:reset",
+            "after:2024-01-01 before:2024-01-03",
+            "after:2024-01-02T09:00:00Z",
+            "has:text",
+            "has:mismatch",
+            "click PASSWORD",
+        ] {
+            let filter: Filter = query.parse().expect("valid query");
+            assert!(filter.matches(&mail), "{}", query);
+        }
+        for query in &[
+            "from:bob",
+            "to:*@z.test",
+            "to:*.test.org",
+            "subject:\"your reset\"",
+            "before:2024-01-02",
+            "has:html",
+            "has:attachment",
+            "has:pii",
+            "unsubscribe",
+            "priority:low",
+        ] {
+            let filter: Filter = query.parse().expect("valid query");
+            assert!(!filter.matches(&mail), "{}", query);
+        }
+
+        // Marked by the scanner
+        let mut mail: Mail = mail;
+        mail.set_pii(vec![Finding {
+            kind: "ssn".to_owned(),
+            location: "text".to_owned(),
+            masked: "*******6789".to_owned(),
+        }]);
+        let filter: Filter = "has:pii".parse().expect("valid query");
+        assert!(filter.matches(&mail));
+
+        // Tagged at the reception
+        mail.set_tags(vec!["staging".to_owned(), "eu".to_owned()]);
+        for &(query, matches) in &[
+            ("tag:Staging", true),
+            ("tag:staging tag:eu", true),
+            ("tag:stag", false),
+            ("tag:staging tag:us", false),
+        ] {
+            let filter: Filter = query.parse().expect("valid query");
+            assert_eq!(filter.matches(&mail), matches, "{}", query);
+        }
+    }
+}
@@ -0,0 +1,763 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use async_std::{
+    fs::{self, File, OpenOptions},
+    sync::Mutex,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::AsyncWriteExt;
+use tide::prelude::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    error::MailcatcherError,
+    mail::{dkim::Report, Client, Mail, Parameters, Type},
+};
+
+/// Operation recorded in the journal, one per line in JSON
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    /// A mail has been accepted
+    Add(Box<Record>),
+    /// A mail has been removed
+    Remove {
+        /// Id of the mail
+        id: String,
+        /// Removal time, as epoch in milliseconds, missing in the older journals
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        at: Option<i64>,
+    },
+    /// The DKIM signatures of a mail have been verified, after its reception
+    Dkim {
+        /// Id of the mail
+        id: String,
+        /// Reports of the signatures
+        reports: Vec<Report>,
+    },
+}
+
+/// Accepted mail, as recorded in the journal
+#[derive(Debug, Deserialize, Serialize)]
+struct Record {
+    /// Id of the mail
+    id: String,
+    /// Sequential number of the mail, missing in the older journals
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    number: Option<u64>,
+    /// Expeditor address of the envelope
+    from: String,
+    /// Recipient addresses of the envelope
+    to: Vec<String>,
+    /// Reception time, as epoch in milliseconds
+    received: i64,
+    /// Raw content of the mail
+    data: String,
+    /// User the client was authenticated as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth_user: Option<String>,
+    /// SMTP client the mail was received from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client: Option<Client>,
+    /// Tags given by the rules at the reception
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// ESMTP parameters of the expeditor address
+    #[serde(default, skip_serializing_if = "Parameters::is_empty")]
+    from_params: Parameters,
+    /// ESMTP parameters of each recipient address
+    #[serde(default, skip_serializing_if = "no_params")]
+    to_params: Vec<Parameters>,
+    /// Reports of the DKIM signatures, verified after the reception
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dkim: Vec<Report>,
+}
+
+impl Record {
+    /// Mail restored from the record, if its id is valid
+    fn into_mail(self) -> Option<Mail> {
+        let id: Ulid = Ulid::from_string(&self.id).ok()?;
+        let received = Utc.timestamp_millis(self.received);
+        let mut mail: Mail = Mail::restore(id, received, &self.from, &self.to, &self.data);
+        if let Some(number) = self.number {
+            mail.set_number(number);
+        }
+        mail.set_auth_user(self.auth_user);
+        mail.set_client(self.client);
+        mail.set_tags(self.tags);
+        mail.set_dkim(self.dkim);
+        if self.to_params.len() == self.to.len() {
+            mail.set_params(self.from_params, self.to_params);
+        } else {
+            mail.set_params(self.from_params, vec![Parameters::new(); self.to.len()]);
+        }
+        Some(mail)
+    }
+}
+
+/// Mails restored from the records, the mails recorded without their number
+/// being numbered after the others
+fn restore(records: Vec<Record>) -> Vec<Mail> {
+    if let Some(last) = records.iter().filter_map(|record| record.number).max() {
+        Mail::numbered_after(last);
+    }
+    records.into_iter().filter_map(Record::into_mail).collect()
+}
+
+/// Check that none of the recipients has ESMTP parameters
+fn no_params(params: &[Parameters]) -> bool {
+    params.iter().all(Parameters::is_empty)
+}
+
+/// Outcome of a compaction of the journal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Compaction {
+    /// Number of mails kept in the journal
+    pub mails: usize,
+    /// Size of the journal before the compaction, in bytes
+    pub size_before: usize,
+    /// Size of the journal after the compaction, in bytes
+    pub size_after: usize,
+}
+
+/// Write-ahead journal of the mails, to restore them after a restart or a crash
+///
+/// Each accepted mail is appended before being acknowledged, each removal is
+/// appended too, so replaying the journal gives back the mails that were kept.
+/// Compacting the journal rewrites it with only the mails that were kept.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    /// Location of the journal
+    path: PathBuf,
+    /// Journal opened in append mode, shared by the SMTP connections and the broker
+    file: Arc<Mutex<File>>,
+    /// Number of removals after which the journal should be compacted, 0 to never do it
+    compact_after: usize,
+    /// Number of removals recorded since the last compaction
+    removals: Arc<AtomicUsize>,
+}
+
+impl Journal {
+    /// Open the journal, creating it if it does not exist
+    ///
+    /// It should be compacted once `compact_after` removals are recorded, unless
+    /// it is 0.
+    pub async fn open(path: &Path, compact_after: usize) -> crate::Result<Self> {
+        let file: File = Self::open_file(path).await.map_err(|e| {
+            MailcatcherError::storage(format!(
+                "Unable to open the journal {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        lock(&file, false).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+                MailcatcherError::storage(format!(
+                    "The journal {} is used by a running instance, use its HTTP API instead",
+                    path.display()
+                ))
+            } else {
+                MailcatcherError::storage(format!(
+                    "Unable to lock the journal {}: {}",
+                    path.display(),
+                    e
+                ))
+            }
+        })?;
+
+        Ok(Self::with_file(path, file, compact_after))
+    }
+
+    /// Open the journal like `open`, once the previous process handing its
+    /// listeners over to this one released it
+    ///
+    /// The mails that process accepts until it stops are then in the journal, and
+    /// it cannot compact the journal anymore.
+    #[cfg(unix)]
+    pub async fn open_released(path: &Path, compact_after: usize) -> crate::Result<Self> {
+        let locked: PathBuf = path.to_path_buf();
+        let file: std::fs::File = async_std::task::spawn_blocking(move || wait_lock(&locked))
+            .await
+            .map_err(|e| {
+                MailcatcherError::storage(format!(
+                    "Unable to lock the journal {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        log::info!(
+            "Journal {} released by the previous process",
+            path.display()
+        );
+
+        Ok(Self::with_file(path, File::from(file), compact_after))
+    }
+
+    /// Without the unix advisory locks, no process hands its listeners over
+    #[cfg(not(unix))]
+    pub async fn open_released(path: &Path, compact_after: usize) -> crate::Result<Self> {
+        Self::open(path, compact_after).await
+    }
+
+    /// Journal written to the opened and locked `file`
+    fn with_file(path: &Path, file: File, compact_after: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            file: Arc::new(Mutex::new(file)),
+            compact_after,
+            removals: Arc::default(),
+        }
+    }
+
+    /// Read a journal without opening it, returning the mails that have not been
+    /// removed, like `replay`
+    ///
+    /// The journal is not locked, so it can be read while an instance writes it.
+    pub async fn read(path: &Path) -> crate::Result<Vec<Mail>> {
+        let content: String = fs::read_to_string(path)
+            .await
+            .map_err(MailcatcherError::storage)?;
+
+        Ok(restore(records(path, &content, None)))
+    }
+
+    /// Open the journal file in append mode, creating it if needed
+    async fn open_file(path: &Path) -> std::io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+    }
+
+    /// Record an accepted mail, returning once it is written on the disk
+    pub async fn append(&self, mail: &Mail) -> crate::Result<()> {
+        self.write(&Entry::Add(Box::new(Record {
+            id: mail.get_id().to_string(),
+            number: Some(mail.get_number()),
+            from: mail.from().clone(),
+            to: mail.to().clone(),
+            received: mail.get_received().timestamp_millis(),
+            data: mail.get_data(&Type::Raw).cloned().unwrap_or_default(),
+            auth_user: mail.get_auth_user().cloned(),
+            client: mail.get_client().cloned(),
+            tags: mail.get_tags().to_vec(),
+            from_params: mail.get_from_params().clone(),
+            to_params: mail.get_to_params().clone(),
+            dkim: mail.get_dkim().to_vec(),
+        })))
+        .await
+    }
+
+    /// Record the removal of a mail
+    pub async fn remove(&self, id: Ulid) -> crate::Result<()> {
+        self.write(&Entry::Remove {
+            id: id.to_string(),
+            at: Some(Utc::now().timestamp_millis()),
+        })
+        .await?;
+        let _ = self.removals.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Record the reports of the DKIM signatures of a mail, verified once it was
+    /// appended, so they are restored without being verified again
+    pub async fn verified(&self, mail: &Mail) -> crate::Result<()> {
+        if mail.get_dkim().is_empty() {
+            return Ok(());
+        }
+        self.write(&Entry::Dkim {
+            id: mail.get_id().to_string(),
+            reports: mail.get_dkim().to_vec(),
+        })
+        .await
+    }
+
+    /// Write an entry on its own line, and flush it to the disk
+    async fn write(&self, entry: &Entry) -> crate::Result<()> {
+        let mut line: String = serde_json::to_string(entry).map_err(MailcatcherError::storage)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().await;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(MailcatcherError::storage)?;
+        file.sync_data().await.map_err(MailcatcherError::storage)?;
+
+        Ok(())
+    }
+
+    /// Read the journal, returning the mails that have not been removed, sorted
+    /// by their reception time
+    ///
+    /// A line that cannot be read, like the last one when the program crashed while
+    /// writing it, is skipped.
+    pub async fn replay(&self) -> crate::Result<Vec<Mail>> {
+        let content: String = fs::read_to_string(&self.path)
+            .await
+            .map_err(MailcatcherError::storage)?;
+        let mails: Vec<Mail> = restore(records(&self.path, &content, None));
+        log::info!(
+            "{} mails restored from the journal {}",
+            mails.len(),
+            self.path.display()
+        );
+
+        Ok(mails)
+    }
+
+    /// Read the journal, returning the mails that existed at a given time, sorted
+    /// by their reception time
+    ///
+    /// The history only goes back to the last compaction: the mails removed
+    /// before it are forgotten, and the removals recorded without their time, by
+    /// an older version, are always applied.
+    pub async fn as_of(&self, at: DateTime<Utc>) -> crate::Result<Vec<Mail>> {
+        let content: String = fs::read_to_string(&self.path)
+            .await
+            .map_err(MailcatcherError::storage)?;
+
+        Ok(restore(records(
+            &self.path,
+            &content,
+            Some(at.timestamp_millis()),
+        )))
+    }
+
+    /// Check if enough removals were recorded to compact the journal
+    pub fn needs_compaction(&self) -> bool {
+        self.compact_after > 0 && self.removals.load(Ordering::Relaxed) >= self.compact_after
+    }
+
+    /// Rewrite the journal with only the mails that have not been removed
+    ///
+    /// The journal is read again instead of using the mails of the broker, to keep
+    /// the mails that are accepted but not yet stored. The new journal is written
+    /// aside, then replaces the old one, so a crash keeps one of them complete.
+    /// It is locked before replacing the old one, so the lock is never released.
+    pub async fn compact(&self) -> crate::Result<Compaction> {
+        // Nothing can be appended until the new journal is in place
+        let mut file = self.file.lock().await;
+
+        let content: String = fs::read_to_string(&self.path)
+            .await
+            .map_err(MailcatcherError::storage)?;
+        let records: Vec<Record> = records(&self.path, &content, None);
+        let mails: usize = records.len();
+        let mut compacted: String = String::new();
+        for record in records {
+            compacted.push_str(
+                &serde_json::to_string(&Entry::Add(Box::new(record)))
+                    .map_err(MailcatcherError::storage)?,
+            );
+            compacted.push('\n');
+        }
+
+        let mut path: std::ffi::OsString = self.path.clone().into_os_string();
+        path.push(".compact");
+        let path: PathBuf = PathBuf::from(path);
+        let mut new_file: File = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .await
+            .map_err(MailcatcherError::storage)?;
+        lock(&new_file, false).map_err(MailcatcherError::storage)?;
+        new_file
+            .write_all(compacted.as_bytes())
+            .await
+            .map_err(MailcatcherError::storage)?;
+        new_file
+            .sync_all()
+            .await
+            .map_err(MailcatcherError::storage)?;
+        fs::rename(&path, &self.path)
+            .await
+            .map_err(MailcatcherError::storage)?;
+        // Written up to its end, the new journal is appended to from there
+        *file = new_file;
+        self.removals.store(0, Ordering::Relaxed);
+
+        let compaction: Compaction = Compaction {
+            mails,
+            size_before: content.len(),
+            size_after: compacted.len(),
+        };
+        log::info!(
+            "Journal {} compacted: {:?}",
+            self.path.display(),
+            compaction
+        );
+
+        Ok(compaction)
+    }
+}
+
+/// Parse the content of the journal at `path`, returning the mails that have not been
+/// removed, sorted by their reception time
+///
+/// With a time `until`, as epoch in milliseconds, only the mails received and
+/// removed until then are considered.
+fn records(path: &Path, content: &str, until: Option<i64>) -> Vec<Record> {
+    let mut records: HashMap<Ulid, Record> = HashMap::new();
+    let before = |time: i64| until.map_or(true, |until| time <= until);
+
+    for (idx, line) in content.lines().enumerate() {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(Entry::Add(record)) => {
+                if let Ok(id) = Ulid::from_string(&record.id) {
+                    if before(record.received) {
+                        let _ = records.insert(id, *record);
+                    }
+                }
+            }
+            Ok(Entry::Remove { id, at }) => {
+                if let Ok(id) = Ulid::from_string(&id) {
+                    if at.map_or(true, before) {
+                        let _ = records.remove(&id);
+                    }
+                }
+            }
+            Ok(Entry::Dkim { id, reports }) => {
+                if let Some(record) = Ulid::from_string(&id)
+                    .ok()
+                    .and_then(|ulid| records.get_mut(&ulid))
+                {
+                    record.dkim = reports;
+                }
+            }
+            Err(e) => log::warn!(
+                "Journal {}, line {} skipped: {}",
+                path.display(),
+                idx.saturating_add(1),
+                e
+            ),
+        }
+    }
+
+    let mut records: Vec<Record> = records.into_values().collect();
+    records.sort_by_key(|record| record.received);
+    records
+}
+
+/// Take the advisory lock of the journal, so a single process writes it: another
+/// one would lose its writes when this one compacts it
+///
+/// Without `wait`, a lock held by another process is an error of the kind
+/// `WouldBlock`.
+#[cfg(unix)]
+fn lock<F: std::os::unix::io::AsRawFd>(file: &F, wait: bool) -> std::io::Result<()> {
+    let operation: libc::c_int = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    #[allow(unsafe_code)]
+    // SAFETY: flock only locks the descriptor, that `file` keeps open
+    let locked: bool = unsafe { libc::flock(file.as_raw_fd(), operation) } == 0;
+    if locked {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// The journal is not locked without the unix advisory locks
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+fn lock(_file: &File, _wait: bool) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Open the journal and wait for its lock, blocking the thread
+///
+/// The journal replaced by a compaction while waiting is opened again, the lock
+/// of the replaced one being useless.
+#[cfg(unix)]
+fn wait_lock(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::MetadataExt;
+
+    loop {
+        let file: std::fs::File = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        lock(&file, true)?;
+        let locked: std::fs::Metadata = file.metadata()?;
+        let current: std::fs::Metadata = std::fs::metadata(path)?;
+        if (locked.dev(), locked.ino()) == (current.dev(), current.ino()) {
+            return Ok(file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn append_remove_replay() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("mailcatcher-{}.journal", Ulid::new()));
+
+        let kept: Mail = Mail::fake();
+        let removed: Mail = Mail::fake();
+        let mut without_date: Mail =
+            Mail::new("from@example.org", &[], "Subject: No date\r\n\r\nBody");
+        let mut params: Parameters = Parameters::new();
+        let _ = params.insert("BODY".to_owned(), Some("8BITMIME".to_owned()));
+        without_date.set_params(params, Vec::new());
+        without_date.set_client(Some(Client {
+            helo: Some("client.example.org".to_owned()),
+            ip: Some("192.0.2.1".parse()?),
+            tls: false,
+        }));
+        without_date.set_tags(vec!["staging".to_owned()]);
+
+        let result: crate::test::Result<Vec<Mail>> = task::block_on(async {
+            let journal: Journal = Journal::open(&path, 0).await?;
+            journal.append(&kept).await?;
+            journal.append(&removed).await?;
+            journal.append(&without_date).await?;
+            journal.remove(removed.get_id()).await?;
+            // Crash while writing an entry
+            OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .await?
+                .write_all(b"{\"op\":\"add\",\"id\":")
+                .await?;
+            // Locked until it is closed
+            assert!(Journal::open(&path, 0).await.is_err());
+            drop(journal);
+
+            // Reopening appends to the existing journal
+            Ok(Journal::open(&path, 0).await?.replay().await?)
+        });
+        std::fs::remove_file(&path).unwrap_or_default();
+        let mails: Vec<Mail> = result?;
+
+        assert_eq!(mails.len(), 2);
+        for original in &[kept, without_date] {
+            let mail: &Mail = mails
+                .iter()
+                .find(|mail| mail.get_id() == original.get_id())
+                .ok_or("mail not restored")?;
+            assert_eq!(mail.from(), original.from());
+            assert_eq!(mail.to(), original.to());
+            assert_eq!(mail.get_number(), original.get_number());
+            assert_eq!(mail.get_from_params(), original.get_from_params());
+            assert_eq!(mail.get_client(), original.get_client());
+            assert_eq!(mail.get_tags(), original.get_tags());
+            assert_eq!(mail.get_subject(), original.get_subject());
+            assert_eq!(
+                mail.get_date().timestamp_millis(),
+                original.get_date().timestamp_millis()
+            );
+            assert_eq!(
+                mail.get_received().timestamp_millis(),
+                original.get_received().timestamp_millis()
+            );
+            assert_eq!(mail.get_data(&Type::Raw), original.get_data(&Type::Raw));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn as_of() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("mailcatcher-{}.journal", Ulid::new()));
+
+        let at = |millis: i64| Utc.timestamp_millis(millis);
+        let data: &str = "Subject: Past\r\n\r\nBody";
+        let first: Mail = Mail::restore(Ulid::new(), at(1_000), "from@example.org", &[], data);
+        let second: Mail = Mail::restore(Ulid::new(), at(2_000), "from@example.org", &[], data);
+        let legacy: Mail = Mail::restore(Ulid::new(), at(3_000), "from@example.org", &[], data);
+
+        let result: crate::test::Result<Vec<Vec<Ulid>>> = task::block_on(async {
+            let journal: Journal = Journal::open(&path, 0).await?;
+            journal.append(&first).await?;
+            journal.append(&second).await?;
+            journal.append(&legacy).await?;
+            journal.remove(first.get_id()).await?;
+            // Removal recorded without its time
+            OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .await?
+                .write_all(
+                    format!("{{\"op\":\"remove\",\"id\":\"{}\"}}\n", legacy.get_id()).as_bytes(),
+                )
+                .await?;
+
+            let mut states: Vec<Vec<Ulid>> = Vec::new();
+            for &time in &[
+                500,
+                1_500,
+                2_500,
+                Utc::now().timestamp_millis().saturating_add(1_000),
+            ] {
+                let mails: Vec<Mail> = journal.as_of(at(time)).await?;
+                states.push(mails.iter().map(Mail::get_id).collect());
+            }
+            Ok(states)
+        });
+        std::fs::remove_file(&path).unwrap_or_default();
+
+        assert_eq!(
+            result?,
+            vec![
+                vec![],
+                vec![first.get_id()],
+                vec![first.get_id(), second.get_id()],
+                vec![second.get_id()],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn dkim_reports() -> crate::test::Result<()> {
+        use crate::mail::dkim::Outcome;
+
+        crate::test::log_init();
+
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("mailcatcher-{}.journal", Ulid::new()));
+        let mut mail: Mail = Mail::fake();
+        let reports: Vec<Report> = vec![Report {
+            domain: Some("example.org".to_owned()),
+            selector: Some("s1".to_owned()),
+            algorithm: Some("rsa-sha256".to_owned()),
+            headers: vec!["from".to_owned()],
+            result: Outcome::TempError,
+            reason: Some("lookup timed out".to_owned()),
+        }];
+
+        let result: crate::test::Result<Vec<Vec<Mail>>> = task::block_on(async {
+            let journal: Journal = Journal::open(&path, 0).await?;
+            // Appended before being verified
+            journal.append(&mail).await?;
+            mail.set_dkim(reports.clone());
+            journal.verified(&mail).await?;
+
+            let replayed: Vec<Mail> = journal.replay().await?;
+            let _ = journal.compact().await?;
+            Ok(vec![replayed, journal.replay().await?])
+        });
+        std::fs::remove_file(&path).unwrap_or_default();
+
+        // Once replayed, then once compacted
+        for mails in &result? {
+            assert_eq!(mails.len(), 1);
+            assert_eq!(mails.first().map(Mail::get_dkim), Some(&reports[..]));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn compact() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("mailcatcher-{}.journal", Ulid::new()));
+
+        let kept: Mail = Mail::fake();
+        let removed: Mail = Mail::fake();
+
+        let result: crate::test::Result<(Compaction, Vec<Mail>, usize)> = task::block_on(async {
+            let journal: Journal = Journal::open(&path, 1).await?;
+            journal.append(&kept).await?;
+            journal.append(&removed).await?;
+            assert!(!journal.needs_compaction());
+            journal.remove(removed.get_id()).await?;
+            assert!(journal.needs_compaction());
+
+            let compaction: Compaction = journal.compact().await?;
+            assert!(!journal.needs_compaction());
+            // Still appending to the compacted journal
+            journal.append(&removed).await?;
+
+            let lines: usize = fs::read_to_string(&path).await?.lines().count();
+            Ok((compaction, journal.replay().await?, lines))
+        });
+        std::fs::remove_file(&path).unwrap_or_default();
+        let (compaction, mails, lines): (Compaction, Vec<Mail>, usize) = result?;
+
+        assert_eq!(compaction.mails, 1);
+        assert!(compaction.size_after < compaction.size_before);
+        assert_eq!(lines, 2);
+        let ids: Vec<Ulid> = mails.iter().map(Mail::get_id).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&kept.get_id()));
+        assert!(ids.contains(&removed.get_id()));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn released() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("mailcatcher-{}.journal", Ulid::new()));
+        let removed: Mail = Mail::fake();
+        let late: Mail = Mail::fake();
+
+        let result: crate::test::Result<Vec<Mail>> = task::block_on(async {
+            let previous: Journal = Journal::open(&path, 0).await?;
+            previous.append(&removed).await?;
+            let opening = task::spawn({
+                let path: PathBuf = path.clone();
+                async move {
+                    let journal = Journal::open_released(&path, 0).await;
+                    (std::time::Instant::now(), journal)
+                }
+            });
+
+            // Still written and compacted by the previous process meanwhile
+            previous.remove(removed.get_id()).await?;
+            let _ = previous.compact().await?;
+            previous.append(&late).await?;
+            task::sleep(std::time::Duration::from_millis(100)).await;
+            let released: std::time::Instant = std::time::Instant::now();
+            drop(previous);
+
+            let (opened, journal) = opening.await;
+            assert!(opened >= released);
+            Ok(journal?.replay().await?)
+        });
+        std::fs::remove_file(&path).unwrap_or_default();
+        let mails: Vec<Mail> = result?;
+
+        assert_eq!(
+            mails.iter().map(Mail::get_id).collect::<Vec<Ulid>>(),
+            vec![late.get_id()]
+        );
+
+        Ok(())
+    }
+}
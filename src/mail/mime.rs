@@ -0,0 +1,492 @@
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::{
+    encoding::{
+        decode_charset, decode_charset_strict, decode_quoted_printable, decode_string, encode_8bit,
+        legacy_charset, CharsetError,
+    },
+    mail::{diagnostic::Diagnostic, Mail},
+};
+
+lazy_static! {
+    static ref RE_CID: Regex = Regex::new(r#"[cC][iI][dD]:(?P<id>[^"'\s)>]+)"#).expect("re cid");
+}
+
+/// Split a headers block into the headers list, joining the multiline ones
+pub fn parse_headers(headers: &str) -> Vec<String> {
+    let mut list: Vec<String> = Vec::new();
+
+    for header in headers.lines() {
+        if header.starts_with(' ') || header.starts_with('\t') {
+            // Multiline header, so append it into multiline content and last entry of the array
+            if let Some(prev_line) = list.last_mut() {
+                prev_line.push_str("\r\n");
+                prev_line.push_str(header);
+                continue;
+            }
+        }
+        // Single line, or first line of a multiline header
+        list.push(header.to_owned());
+    }
+
+    list
+}
+
+/// Retrieve the first header value from its name, ignoring the case of the name
+pub fn find_header<'a>(headers: &'a [String], name: &str) -> Option<&'a str> {
+    headers.iter().find_map(|header| {
+        let (key, value) = header.split_at(header.find(':')?);
+        if key.eq_ignore_ascii_case(name) {
+            // Skip the colon
+            value.get(1..).map(str::trim)
+        } else {
+            None
+        }
+    })
+}
+
+/// Extract a parameter from a header value, like the `boundary` of a `Content-Type`
+pub fn header_param(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_at(param.find('=')?);
+        if key.trim().eq_ignore_ascii_case(name) {
+            // Skip the equal sign, then remove the quotes if any
+            value
+                .get(1..)
+                .map(|value| value.trim().trim_matches('"').to_owned())
+        } else {
+            None
+        }
+    })
+}
+
+/// A leaf of the MIME tree of a mail
+#[derive(Debug, Clone)]
+pub struct Part {
+    /// Array of headers
+    headers: Vec<String>,
+    /// Content type in lowercase, without the parameters
+    content_type: String,
+    /// Content-ID, without its angle brackets, referenced by the html parts
+    /// with a `cid:` address
+    content_id: Option<String>,
+    /// Content of the part, like it was received
+    body: String,
+}
+
+impl Part {
+    /// Parse a MIME entity, returning all the leaves of its tree
+    ///
+    /// A broken structure is kept as much as possible, its problems are added to `diagnostics`
+    pub fn parse(headers: Vec<String>, body: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<Self> {
+        let content_type: String = find_header(&headers, "Content-Type")
+            .and_then(|value| value.split(';').next())
+            .map_or_else(|| "text/plain".to_owned(), |t| t.trim().to_lowercase());
+
+        if content_type.starts_with("multipart/") {
+            if let Some(boundary) = find_header(&headers, "Content-Type")
+                .and_then(|value| header_param(value, "boundary"))
+            {
+                let (entities, terminated): (Vec<String>, bool) = split_multipart(body, &boundary);
+                if !terminated {
+                    diagnostics.push(Diagnostic::UnterminatedMultipart { boundary });
+                }
+                return entities
+                    .iter()
+                    .flat_map(|entity| {
+                        let (headers, body): (String, String) = Mail::split_header_body(entity);
+                        Self::parse(parse_headers(&headers), &body, diagnostics)
+                    })
+                    .collect();
+            }
+            diagnostics.push(Diagnostic::MissingBoundary {
+                content_type: content_type.clone(),
+            });
+        }
+
+        let content_id: Option<String> = find_header(&headers, "Content-ID")
+            .map(|value| {
+                value
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_owned()
+            })
+            .filter(|id| !id.is_empty());
+        vec![Self {
+            headers,
+            content_type,
+            content_id,
+            body: body.to_owned(),
+        }]
+    }
+
+    /// Retrieve the content type, in lowercase
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Retrieve the Content-ID, without its angle brackets
+    pub fn content_id(&self) -> Option<&str> {
+        self.content_id.as_deref()
+    }
+
+    /// Is the part an attachment, or a text to display
+    pub fn is_attachment(&self) -> bool {
+        let disposition: bool = find_header(&self.headers, "Content-Disposition")
+            .map_or(false, |value| {
+                value.to_lowercase().starts_with("attachment")
+            });
+        disposition || !self.content_type.starts_with("text/")
+    }
+
+    /// Retrieve the file name of an attachment, from the `Content-Disposition` or
+    /// the `Content-Type` header
+    pub fn filename(&self) -> Option<String> {
+        find_header(&self.headers, "Content-Disposition")
+            .and_then(|value| header_param(value, "filename"))
+            .or_else(|| {
+                find_header(&self.headers, "Content-Type")
+                    .and_then(|value| header_param(value, "name"))
+            })
+            .map(|name| decode_string(&name))
+    }
+
+    /// Retrieve the content, decoded from its `Content-Transfer-Encoding`
+    ///
+    /// A content that is not valid base64 is kept like it was received.
+    pub fn decoded(&self) -> Vec<u8> {
+        match self.transfer_encoding().as_str() {
+            "base64" => {
+                let compact: String = self.body.split_whitespace().collect();
+                base64::decode(&compact).unwrap_or_else(|_| self.body.as_bytes().to_vec())
+            }
+            "quoted-printable" => decode_quoted_printable(&self.body),
+            _ => self.body.as_bytes().to_vec(),
+        }
+    }
+
+    /// Retrieve the content as a text, decoded from its `Content-Transfer-Encoding`
+    /// then from the `charset` of its `Content-Type`
+    ///
+    /// The 8-bit lines that were not valid UTF-8 were kept in Latin-1 at the
+    /// reception, they are given back their bytes to be decoded from a charset
+    /// like GB2312.
+    pub fn text(&self) -> String {
+        let (content, charset): (Vec<u8>, Option<String>) = self.charset_content();
+        decode_charset(&content, charset.as_deref())
+    }
+
+    /// Retrieve the content as a text like `text`, reporting the bytes that
+    /// cannot be decoded from the `charset` instead of replacing them
+    pub fn text_strict(&self) -> Result<String, CharsetError> {
+        let (content, charset): (Vec<u8>, Option<String>) = self.charset_content();
+        decode_charset_strict(&content, charset.as_deref())
+    }
+
+    /// Retrieve the content to decode from the `charset` of the `Content-Type`,
+    /// with this charset
+    fn charset_content(&self) -> (Vec<u8>, Option<String>) {
+        let charset: Option<String> = find_header(&self.headers, "Content-Type")
+            .and_then(|value| header_param(value, "charset"));
+        let content: Vec<u8> = match self.transfer_encoding().as_str() {
+            "base64" | "quoted-printable" => self.decoded(),
+            _ if charset.as_deref().map_or(false, legacy_charset) => encode_8bit(&self.body),
+            _ => self.decoded(),
+        };
+        (content, charset)
+    }
+
+    /// Retrieve the `Content-Transfer-Encoding`, in lowercase
+    fn transfer_encoding(&self) -> String {
+        find_header(&self.headers, "Content-Transfer-Encoding")
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    }
+}
+
+/// Rewrite the `cid:` addresses of a html content to the parts they reference by
+/// their Content-ID, `url` giving the address of a part from its index
+///
+/// The addresses of the unknown Content-IDs are kept as they are.
+#[allow(clippy::indexing_slicing)]
+pub fn resolve_cids<F>(html: &str, parts: &[Part], url: F) -> String
+where
+    F: Fn(usize) -> String,
+{
+    RE_CID
+        .replace_all(html, |caps: &Captures| {
+            let id: String = percent_decode(&caps["id"]);
+            parts
+                .iter()
+                .position(|part| part.content_id() == Some(id.as_str()))
+                .map_or_else(|| caps[0].to_owned(), &url)
+        })
+        .into_owned()
+}
+
+/// Decode the `%hh` escapes of a `cid:` address, the invalid ones being kept
+fn percent_decode(text: &str) -> String {
+    let mut bytes: Vec<u8> = Vec::with_capacity(text.len());
+    let mut rest: &[u8] = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped: Option<u8> = if byte == b'%' {
+            tail.get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        if let Some(unescaped) = escaped {
+            bytes.push(unescaped);
+            rest = tail.get(2..).unwrap_or_default();
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Split the body of a multipart entity into the entities it contains, and tell if the
+/// closing delimiter has been found
+fn split_multipart(body: &str, boundary: &str) -> (Vec<String>, bool) {
+    let delimiter: String = format!("--{}", boundary);
+    let close_delimiter: String = format!("--{}--", boundary);
+
+    let mut entities: Vec<String> = Vec::new();
+    // Lines of the current entity, None while in the preamble
+    let mut current: Option<Vec<&str>> = None;
+
+    for line in body.lines() {
+        let trimmed: &str = line.trim_end();
+        if trimmed == delimiter || trimmed == close_delimiter {
+            if let Some(lines) = current.take() {
+                entities.push(lines.join("\r\n"));
+            }
+            if trimmed == close_delimiter {
+                // The epilogue is ignored
+                return (entities, true);
+            }
+            current = Some(Vec::new());
+        } else if let Some(lines) = current.as_mut() {
+            lines.push(line);
+        } else {
+            // The preamble is ignored
+        }
+    }
+    // Unterminated multipart, keep what was received
+    if let Some(lines) = current {
+        entities.push(lines.join("\r\n"));
+    }
+
+    (entities, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_and_params() {
+        crate::test::log_init();
+
+        let headers: Vec<String> = parse_headers(
+            "Content-type: multipart/mixed;\r\n\tboundary=\"frontier\"\r\nCC: a@example.org",
+        );
+
+        assert_eq!(headers.len(), 2);
+        let content_type: &str = find_header(&headers, "Content-Type").expect("content type");
+        assert_eq!(content_type, "multipart/mixed;\r\n\tboundary=\"frontier\"");
+        assert_eq!(
+            header_param(content_type, "Boundary"),
+            Some("frontier".to_owned())
+        );
+        assert_eq!(find_header(&headers, "cc"), Some("a@example.org"));
+        assert!(find_header(&headers, "Subject").is_none());
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn multipart_leaves() {
+        crate::test::log_init();
+
+        let body: &str = "Preamble\r\n\
+--outer\r\n\
+Content-Type: multipart/alternative; boundary=inner\r\n\
+\r\n\
+--inner\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Plain text\r\n\
+--inner\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>Html</p>\r\n\
+--inner--\r\n\
+--outer\r\n\
+Content-Type: application/pdf; name=\"doc.pdf\"\r\n\
+Content-Disposition: attachment\r\n\
+\r\n\
+JVBERi0=\r\n\
+--outer--\r\n\
+Epilogue";
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let parts: Vec<Part> = Part::parse(
+            vec!["Content-Type: multipart/mixed; boundary=outer".to_owned()],
+            body,
+            &mut diagnostics,
+        );
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].content_type(), "text/plain");
+        assert_eq!(parts[0].text(), "Plain text");
+        assert!(!parts[0].is_attachment());
+        assert_eq!(parts[1].content_type(), "text/html");
+        assert_eq!(parts[1].text(), "<p>Html</p>");
+        assert_eq!(parts[2].content_type(), "application/pdf");
+        assert!(parts[2].is_attachment());
+        assert_eq!(parts[2].filename(), Some("doc.pdf".to_owned()));
+        assert_eq!(parts[0].filename(), None);
+    }
+
+    #[test]
+    fn cid_references() {
+        crate::test::log_init();
+
+        let body: &str = "--related\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<img src=\"cid:logo@example.org\"><img src='CID:chart%40example.org'>\
+<div style=\"background: url(cid:unknown@example.org)\"></div>\r\n\
+--related\r\n\
+Content-Type: image/png\r\n\
+Content-ID: <logo@example.org>\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+iVBORw0KGgo=\r\n\
+--related\r\n\
+Content-Type: image/png\r\n\
+Content-Id: chart@example.org\r\n\
+\r\n\
+PNG\r\n\
+--related--\r\n";
+        let parts: Vec<Part> = Part::parse(
+            vec!["Content-Type: multipart/related; boundary=related".to_owned()],
+            body,
+            &mut Vec::new(),
+        );
+
+        assert_eq!(
+            parts
+                .iter()
+                .map(Part::content_id)
+                .collect::<Vec<Option<&str>>>(),
+            vec![None, Some("logo@example.org"), Some("chart@example.org")]
+        );
+        let html: String = parts.first().map(Part::text).unwrap_or_default();
+        assert_eq!(
+            resolve_cids(&html, &parts, |index| format!("parts/{}", index)),
+            "<img src=\"parts/1\"><img src='parts/2'>\
+<div style=\"background: url(cid:unknown@example.org)\"></div>"
+        );
+    }
+
+    #[test]
+    fn decoded_content() {
+        crate::test::log_init();
+
+        let part = |encoding: &str, body: &str| -> Vec<u8> {
+            let headers: Vec<String> = vec![
+                "Content-Type: text/csv".to_owned(),
+                format!("Content-Transfer-Encoding: {}", encoding),
+            ];
+            Part::parse(headers, body, &mut Vec::new())
+                .first()
+                .map(Part::decoded)
+                .unwrap_or_default()
+        };
+
+        assert_eq!(part("base64", "YSxiDQox\r\nLDI="), b"a,b\r\n1,2");
+        assert_eq!(part("Quoted-Printable", "caf=C3=A9"), "café".as_bytes());
+        assert_eq!(part("7bit", "a,b"), b"a,b");
+        // Invalid base64 is kept as is
+        assert_eq!(part("base64", "not base64!"), b"not base64!");
+    }
+
+    #[test]
+    fn text_content() {
+        crate::test::log_init();
+
+        let part = |content_type: &str, encoding: &str, body: &str| -> String {
+            let headers: Vec<String> = vec![
+                format!("Content-Type: {}", content_type),
+                format!("Content-Transfer-Encoding: {}", encoding),
+            ];
+            Part::parse(headers, body, &mut Vec::new())
+                .first()
+                .map(Part::text)
+                .unwrap_or_default()
+        };
+
+        assert_eq!(
+            part(
+                "text/plain; charset=utf-8",
+                "base64",
+                "Q2Fmw6kgYXUg
+bGFpdA=="
+            ),
+            "Café au lait"
+        );
+        assert_eq!(
+            part(
+                "text/html; charset=\"iso-8859-1\"",
+                "quoted-printable",
+                "<p class=3D\"x\">Caf=E9 au =\r\nlait</p>"
+            ),
+            "<p class=\"x\">Café au lait</p>"
+        );
+        assert_eq!(part("text/plain", "8bit", "Café"), "Café");
+        // GB2312 kept in Latin-1 at the reception, not being valid UTF-8
+        let received: String =
+            crate::encoding::decode_8bit(b"\xc4\xe3\xba\xc3\xa3\xac\xca\xc0\xbd\xe7").into_owned();
+        assert_eq!(
+            part("text/plain; charset=gb2312", "8bit", &received),
+            "你好，世界"
+        );
+    }
+
+    #[test]
+    fn broken_multipart() {
+        crate::test::log_init();
+
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let parts: Vec<Part> = Part::parse(
+            vec!["Content-Type: multipart/mixed".to_owned()],
+            "--lost\r\n\r\nText",
+            &mut diagnostics,
+        );
+        assert_eq!(parts.len(), 1);
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::MissingBoundary {
+                content_type: "multipart/mixed".to_owned()
+            }]
+        );
+
+        diagnostics.clear();
+        let parts: Vec<Part> = Part::parse(
+            vec!["Content-Type: multipart/mixed; boundary=cut".to_owned()],
+            "--cut\r\nContent-Type: text/plain\r\n\r\nTruncated",
+            &mut diagnostics,
+        );
+        assert_eq!(parts.len(), 1);
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::UnterminatedMultipart {
+                boundary: "cut".to_owned()
+            }]
+        );
+    }
+}
@@ -1,6 +1,12 @@
-use std::ops::Sub;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    ops::Sub,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
 use fake::{
     faker::{
         chrono::en::DateTimeBetween,
@@ -12,13 +18,75 @@ use fake::{
 };
 use serde_json::Value;
 use textwrap::wrap;
-use tide::prelude::json;
+use tide::prelude::{json, Deserialize, Serialize};
 use ulid::Ulid;
 
-use crate::encoding::decode_string;
+use crate::{
+    encoding::{decode_string, html_to_text, CharsetError},
+    mail::{
+        delivery::Delivery,
+        diagnostic::{check_headers, Diagnostic},
+        dkim::Report,
+        mime::{parse_headers, Part},
+        pii::Finding,
+        timing::Timing,
+        tracking::Tracker,
+        transcript::Transcript,
+    },
+};
 
 /// Mail storage broker
 pub mod broker;
+/// Bundles of mails exported as test fixtures
+pub mod bundle;
+/// Lenient parsing of the dates of the headers
+pub mod date;
+/// Simulated deliveries of the mails to their recipients
+pub mod delivery;
+/// Problems found while parsing a mail
+pub mod diagnostic;
+/// Verification of the DKIM signatures
+pub mod dkim;
+/// Delivery status notifications asked by the clients
+pub mod dsn;
+/// Selection of mails
+pub mod filter;
+/// Write-ahead journal of the mails
+pub mod journal;
+/// MIME structure of a mail
+pub mod mime;
+/// Detection of the personal data in the mails
+pub mod pii;
+/// Split of the mail broker in shards processed in parallel
+pub mod shard;
+/// Storage backends of the broker
+pub mod store;
+/// Automatic tags of the mails, given by rules at their reception
+pub mod tag;
+/// Conversations of the mails, from their threading headers
+pub mod thread;
+/// Time spent by the mails in each stage of their processing
+pub mod timing;
+/// Detection of the trackers in the html contents
+pub mod tracking;
+/// Commands and replies of the SMTP sessions
+pub mod transcript;
+
+/// Maximum length of the text preview of a mail
+const SNIPPET_LENGTH: usize = 120;
+
+/// Headers where the sending application writes the id of the request that
+/// caused the mail
+const CORRELATION_HEADERS: [&str; 4] = [
+    "X-Request-Id",
+    "X-Correlation-Id",
+    "Request-Id",
+    "Correlation-Id",
+];
+
+/// ESMTP parameters given with an address of the envelope, like
+/// `BODY=8BITMIME`, by keyword in uppercase
+pub type Parameters = BTreeMap<String, Option<String>>;
 
 /// Describe the data type that is held
 #[derive(Hash, Eq, PartialEq, Debug, Clone)]
@@ -33,6 +101,16 @@ pub enum Type {
     //Other(String, String),
 }
 
+impl Type {
+    /// Type of the content of a part that is not an attachment
+    fn of(part: &Part) -> Self {
+        match part.content_type() {
+            "text/html" => Self::Html,
+            _ => Self::Text,
+        }
+    }
+}
+
 /// How to export mail header
 /// * Raw: like it was received
 /// * Humanized: decode it
@@ -43,68 +121,305 @@ pub enum HeaderRepresentation {
     Humanized,
 }
 
+/// Priority of a mail, normalized from the `X-Priority`, `Importance`, `Priority`
+/// and `X-MSMail-Priority` headers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Urgent mail
+    High,
+    /// No priority specified
+    Normal,
+    /// Bulk mail
+    Low,
+}
+
+impl Priority {
+    /// Normalize a priority header content, from its name
+    fn from_header(name: &str, value: &str) -> Option<Self> {
+        let value: String = value.trim().to_lowercase();
+        if name.eq_ignore_ascii_case("X-Priority") {
+            // A digit, from 1 (highest) to 5 (lowest), with an optional comment
+            match value.chars().next()? {
+                '1' | '2' => Some(Self::High),
+                '3' => Some(Self::Normal),
+                '4' | '5' => Some(Self::Low),
+                _ => None,
+            }
+        } else {
+            match value.as_str() {
+                "high" | "urgent" => Some(Self::High),
+                "normal" => Some(Self::Normal),
+                "low" | "non-urgent" => Some(Self::Low),
+                _ => None,
+            }
+        }
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+/// Sequential number of the next mail created by the process
+static NEXT_NUMBER: AtomicU64 = AtomicU64::new(1);
+
+/// Identifier of the mails given as `id` by the API, the ULID and the sequential
+/// number being both given too, as `ulid` and `number`
+///
+/// Both are accepted to designate a mail in the routes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdStrategy {
+    /// The ULID, sorted by the reception time and unique across the restarts
+    Ulid,
+    /// The sequential number, starting from 1, like the original catcher in Ruby
+    Sequential,
+}
+
+impl IdStrategy {
+    /// Identifier of the mail given as `id`
+    pub fn id_of(self, mail: &Mail) -> Value {
+        match self {
+            Self::Ulid => json!(mail.get_id().to_string()),
+            Self::Sequential => json!(mail.get_number()),
+        }
+    }
+}
+
+impl Default for IdStrategy {
+    fn default() -> Self {
+        Self::Ulid
+    }
+}
+
+impl FromStr for IdStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ulid" => Ok(Self::Ulid),
+            "sequential" => Ok(Self::Sequential),
+            _ => Err(format!(
+                "Unknown id strategy {}, expected ulid or sequential",
+                s
+            )),
+        }
+    }
+}
+
+/// Date the mail lists are sorted by, with `?sort=`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// Reception time, the order the mails really arrived in
+    Received,
+    /// Date declared by the sender in the Date header, or the reception time
+    /// without it
+    Declared,
+}
+
+impl SortBy {
+    /// Sort the mails by their date, the oldest first unless `descending`, the
+    /// mails received at the same time being kept in their reception order
+    pub fn sort(self, mails: &mut [Mail], descending: bool) {
+        mails.sort_by(|a, b| {
+            let order = match self {
+                Self::Received => a.get_received().cmp(&b.get_received()),
+                Self::Declared => a.get_date().cmp(&b.get_date()),
+            }
+            .then_with(|| a.get_number().cmp(&b.get_number()));
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+    }
+}
+
+/// Last resending of a mail, from the `Resent-*` headers
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Resent {
+    /// Addresses of the resender
+    from: Vec<String>,
+    /// Addresses the mail has been resent to
+    to: Vec<String>,
+    /// Date of the resending, as epoch
+    date: Option<i64>,
+}
+
+/// Mailing list the mail has been sent through, from the `List-*` headers
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MailingList {
+    /// Identifier of the list
+    id: Option<String>,
+    /// Description of the list
+    name: Option<String>,
+    /// Addresses (mailto or web) to unsubscribe from the list
+    unsubscribe: Vec<String>,
+}
+
+/// SMTP client a mail was received from
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Client {
+    /// Name given with HELO or EHLO
+    pub helo: Option<String>,
+    /// Address of the client
+    pub ip: Option<IpAddr>,
+    /// The session was secured with TLS
+    pub tls: bool,
+}
+
 /// Content of a mail
 #[derive(Debug, Clone)]
 pub struct Mail {
     /// Id, internal used and JavaScript identifier
     id: Ulid,
+    /// Sequential number, in the order the mails are created
+    number: u64,
     /// From address
     from: String,
     /// Array of receivers
     to: Vec<String>,
+    /// ESMTP parameters of the MAIL FROM command
+    from_params: Parameters,
+    /// ESMTP parameters of the RCPT TO command of each receiver
+    to_params: Vec<Parameters>,
     /// Subject of the mail
     subject: String,
-    /// Date of reception
+    /// Date of the mail, from the Date header or the reception time
     date: DateTime<Utc>,
+    /// Date of reception
+    received: DateTime<Utc>,
+    /// Date specified by the sender in the Date header
+    declared: Option<DateTime<Utc>>,
     /// Array of headers
     headers: Vec<String>,
     /// Content of the mail, split in Type
     data: fnv::FnvHashMap<Type, String>,
+    /// Leaves of the MIME tree
+    parts: Vec<Part>,
+    /// Beginning of the displayed content, on a single line
+    snippet: String,
+    /// Problems found while parsing the mail
+    diagnostics: Vec<Diagnostic>,
+    /// Priority of the mail
+    priority: Priority,
+    /// Personal data found by the scanner
+    pii: Vec<Finding>,
+    /// User the SMTP client was authenticated as
+    auth_user: Option<String>,
+    /// SMTP client the mail was received from, `None` if it did not come by
+    /// SMTP
+    client: Option<Client>,
+    /// Tags given by the rules at the reception
+    tags: Vec<String>,
+    /// Simulated deliveries to the recipients, empty if not simulated
+    deliveries: Vec<Delivery>,
+    /// Reports of the DKIM signatures, verified at the reception
+    dkim: Vec<Report>,
+    /// The mail is under investigation, it cannot be removed
+    locked: bool,
+    /// Time spent in the stages of the processing, until the mail is in the tank
+    timing: Timing,
+    /// Commands and replies of the SMTP session, until the mail was accepted
+    transcript: Option<Transcript>,
 }
 
 impl Mail {
     /// Create a new mail
     pub fn new(from: &str, to: &[String], data: &str) -> Self {
+        let now: DateTime<Utc> = Utc::now();
         let mut mail = Self {
             id: Ulid::new(),
+            number: NEXT_NUMBER.fetch_add(1, Ordering::Relaxed),
             from: from.to_owned(),
             to: to.to_vec(),
+            from_params: Parameters::new(),
+            to_params: vec![Parameters::new(); to.len()],
             subject: "(No subject)".to_owned(),
-            date: Utc::now(),
+            date: now,
+            received: now,
+            declared: None,
             headers: Vec::default(),
             data: fnv::FnvHashMap::default(),
+            parts: Vec::new(),
+            snippet: String::new(),
+            diagnostics: Vec::new(),
+            priority: Priority::default(),
+            pii: Vec::new(),
+            auth_user: None,
+            client: None,
+            tags: Vec::new(),
+            deliveries: Vec::new(),
+            dkim: Vec::new(),
+            locked: false,
+            timing: Timing::default(),
+            transcript: None,
         };
 
         // Store RAW mail content
         let _ = mail.data.insert(Type::Raw, data.to_owned());
 
-        let (headers, body): (String, String) = Self::split_header_body(data);
+        let (mut headers, mut body): (String, String) = Self::split_header_body(data);
 
-        // Parse the headers
-        for header in headers.lines() {
-            #[allow(clippy::indexing_slicing)]
-            if &header[..1] == " " {
-                // Multiline header, so append it into multiline content and last entry of the array
-                if let Some(prev_line) = mail.headers.last_mut() {
-                    prev_line.push_str("\r\n");
-                    prev_line.push_str(header);
-                    continue;
-                }
-            }
-            // Single line, or first line of a multiline header
-            mail.headers.push(header.to_owned());
+        // Parse the headers, malformed ones without a blank line after them are in fact
+        // a body without any header
+        mail.diagnostics = check_headers(&headers);
+        if !mail.diagnostics.is_empty() && !data.lines().any(str::is_empty) {
+            mail.diagnostics = vec![Diagnostic::MissingSeparator];
+            headers.clear();
+            body = data.lines().collect::<Vec<&str>>().join("\r\n");
+        }
+        mail.headers = parse_headers(&headers);
+
+        // Parse the MIME structure, then keep the first text and html contents
+        mail.parts = Part::parse(mail.headers.clone(), &body, &mut mail.diagnostics);
+        for part in mail.parts.iter().filter(|part| !part.is_attachment()) {
+            let _ = mail
+                .data
+                .entry(Type::of(part))
+                .or_insert_with(|| part.text());
         }
 
-        let _ = mail.data.insert(Type::Text, body);
+        // Generate the preview once, from the html content if there is no text
+        mail.snippet = match (mail.get_text(), mail.get_html()) {
+            (Some(text), _) => make_snippet(text),
+            (None, Some(html)) => make_snippet(&html_to_text(html)),
+            (None, None) => String::new(),
+        };
 
         // Extract Date
         let date_header: Vec<String> = mail.get_header_content("Date", &HeaderRepresentation::Raw);
         if let Some(date_str) = date_header.first() {
-            if let Ok(local_date) = DateTime::parse_from_rfc2822(date_str.as_str()) {
+            if let Some((local_date, lenient)) = date::parse(date_str) {
                 mail.date = local_date.with_timezone(&Utc);
+                mail.declared = Some(mail.date);
+                if lenient {
+                    mail.diagnostics.push(Diagnostic::LenientDate {
+                        value: date_str.clone(),
+                    });
+                }
+            } else {
+                mail.diagnostics.push(Diagnostic::InvalidDate {
+                    value: date_str.clone(),
+                });
             }
         }
 
+        // Extract Priority, from the first header that is understood
+        mail.priority = ["X-Priority", "Importance", "Priority", "X-MSMail-Priority"]
+            .iter()
+            .find_map(|&name| {
+                mail.get_header_content(name, &HeaderRepresentation::Raw)
+                    .first()
+                    .and_then(|value| Priority::from_header(name, value))
+            })
+            .unwrap_or_default();
+
         // Extract Subject
         let subject_header: Vec<String> =
             mail.get_header_content("Subject", &HeaderRepresentation::Raw);
@@ -115,6 +430,54 @@ impl Mail {
         mail
     }
 
+    /// Create a new mail, keeping only the headers when the content is larger than
+    /// `headers_only` bytes
+    ///
+    /// The envelope and the headers are enough to check who receives which mail,
+    /// discarding the body keeps the memory low when a lot of mails are sent.
+    pub fn with_headers_only(
+        from: &str,
+        to: &[String],
+        data: &str,
+        headers_only: Option<usize>,
+    ) -> Self {
+        match headers_only {
+            Some(limit) if data.len() > limit => {
+                let (headers, _): (String, String) = Self::split_header_body(data);
+                let mut mail: Self = Self::new(from, to, &format!("{}\r\n\r\n", headers));
+                mail.diagnostics
+                    .push(Diagnostic::BodyDiscarded { size: data.len() });
+                mail
+            }
+            _ => Self::new(from, to, data),
+        }
+    }
+
+    /// Create a mail keeping only its envelope, its content of `size` bytes
+    /// being discarded
+    pub fn envelope_only(from: &str, to: &[String], size: usize) -> Self {
+        let mut mail: Self = Self::new(from, to, "");
+        mail.diagnostics.push(Diagnostic::ContentDiscarded { size });
+        mail
+    }
+
+    /// Rebuild a mail that was received earlier, keeping its id and reception time
+    pub fn restore(
+        id: Ulid,
+        received: DateTime<Utc>,
+        from: &str,
+        to: &[String],
+        data: &str,
+    ) -> Self {
+        let mut mail: Self = Self::new(from, to, data);
+        mail.id = id;
+        mail.received = received;
+        if mail.declared.is_none() {
+            mail.date = received;
+        }
+        mail
+    }
+
     /// Split the string, returning a tuple that is the headers then the body
     pub fn split_header_body(content: &str) -> (String, String) {
         let mut headers: String = String::new();
@@ -145,11 +508,36 @@ impl Mail {
         (headers.trim_end().into(), body)
     }
 
+    /// Add a header before all the others, in the headers list and in the raw content
+    pub fn prepend_header(&mut self, name: &str, value: &str) {
+        let header: String = format!("{}: {}", name, value);
+        if let Some(raw) = self.data.get_mut(&Type::Raw) {
+            raw.insert_str(0, &format!("{}\r\n", header));
+        }
+        self.headers.insert(0, header);
+    }
+
     /// Retrieve the ID of the mail
     pub const fn get_id(&self) -> Ulid {
         self.id
     }
 
+    /// Retrieve the sequential number of the mail
+    pub const fn get_number(&self) -> u64 {
+        self.number
+    }
+
+    /// Give back its number to a restored mail
+    pub fn set_number(&mut self, number: u64) {
+        Self::numbered_after(number);
+        self.number = number;
+    }
+
+    /// Number the next mails after this one, it is taken by a restored mail
+    pub fn numbered_after(number: u64) {
+        let _ = NEXT_NUMBER.fetch_max(number.saturating_add(1), Ordering::Relaxed);
+    }
+
     /// Retrieve the sender address
     pub const fn from(&self) -> &String {
         &self.from
@@ -164,11 +552,33 @@ impl Mail {
         self.date
     }
 
+    /// Retrieve the reception time
+    pub const fn get_received(&self) -> DateTime<Utc> {
+        self.received
+    }
+
+    /// Retrieve the date declared by the sender in the Date header, if any
+    pub const fn get_declared(&self) -> Option<DateTime<Utc>> {
+        self.declared
+    }
+
+    /// Retrieve the delivery latency, from the date claimed by the sender to the reception,
+    /// negative if the Date header is in the future
+    pub fn get_latency(&self) -> Option<Duration> {
+        self.declared
+            .map(|declared| self.received.signed_duration_since(declared))
+    }
+
     /// Retrieve the subject
     pub const fn get_subject(&self) -> &String {
         &self.subject
     }
 
+    /// Retrieve the normalized priority
+    pub const fn get_priority(&self) -> Priority {
+        self.priority
+    }
+
     /// Retrieve the content in text format
     pub fn get_text(&self) -> Option<&String> {
         self.data.get(&Type::Text)
@@ -189,9 +599,12 @@ impl Mail {
         // Iterate over headers list to find the header
         self.get_headers(raw)
             .iter()
-            // Filter over key name
+            // Filter over key name, that is case insensitive
             .filter_map(|header| {
-                if header.find(' ') == Some(key_len.sub(1)) && &header[..key_len] == key.as_str() {
+                if header
+                    .get(..key_len)
+                    .map_or(false, |name| name.eq_ignore_ascii_case(&key))
+                {
                     // strip only to header content
                     Some(header[key_len..].to_string())
                 } else {
@@ -218,20 +631,369 @@ impl Mail {
         self.data[&Type::Raw].as_bytes().len()
     }
 
+    /// Find the tracking pixels and the tracked links of the html parts, for a
+    /// privacy review of the mail
+    pub fn get_trackers(&self) -> Vec<Tracker> {
+        self.parts
+            .iter()
+            .filter(|part| part.content_type() == "text/html" && !part.is_attachment())
+            .flat_map(|part| tracking::detect(&part.text()))
+            .collect()
+    }
+
+    /// Check if the content is split in MIME parts
+    pub fn is_multipart(&self) -> bool {
+        self.get_header_content("Content-Type", &HeaderRepresentation::Raw)
+            .first()
+            .map_or(false, |content_type| {
+                content_type
+                    .trim_start()
+                    .to_ascii_lowercase()
+                    .starts_with("multipart/")
+            })
+    }
+
+    /// Retrieve the leaves of the MIME tree
+    pub fn get_parts(&self) -> &[Part] {
+        &self.parts
+    }
+
+    /// Retrieve the MIME parts that are attachments
+    pub fn get_attachments(&self) -> Vec<&Part> {
+        self.parts
+            .iter()
+            .filter(|part| part.is_attachment())
+            .collect()
+    }
+
+    /// Retrieve the carbon copy receivers, from the Cc headers
+    pub fn get_cc(&self) -> Vec<String> {
+        self.get_header_content("Cc", &HeaderRepresentation::Humanized)
+            .iter()
+            .flat_map(|cc| split_addresses(cc))
+            .collect()
+    }
+
+    /// Check if the envelope recipients differ from the addresses of the `To` and
+    /// `Cc` headers, as with the blind carbon copies, or a recipient forgotten
+    /// in the envelope or in the headers
+    pub fn has_mismatch(&self) -> bool {
+        let envelope: BTreeSet<String> = self.to.iter().map(|to| bare_address(to)).collect();
+        let headers: BTreeSet<String> = self
+            .get_header_content("To", &HeaderRepresentation::Humanized)
+            .iter()
+            .flat_map(|to| split_addresses(to))
+            .chain(self.get_cc())
+            .map(|address| bare_address(&address))
+            .collect();
+        envelope != headers
+    }
+
+    /// Retrieve the last resending of the mail, if it has been resent
+    pub fn get_resent(&self) -> Option<Resent> {
+        // Each resending prepends its own block, so the first headers are the last ones
+        let first = |name: &str| -> Option<String> {
+            self.get_header_content(name, &HeaderRepresentation::Humanized)
+                .into_iter()
+                .next()
+        };
+        let from: Option<String> = first("Resent-From");
+        let to: Option<String> = first("Resent-To");
+        let date: Option<String> = first("Resent-Date");
+        if from.is_none() && to.is_none() && date.is_none() {
+            return None;
+        }
+
+        Some(Resent {
+            from: from.as_deref().map(split_addresses).unwrap_or_default(),
+            to: to.as_deref().map(split_addresses).unwrap_or_default(),
+            date: date
+                .and_then(|date| date::parse(&date))
+                .map(|(date, _lenient)| date.timestamp()),
+        })
+    }
+
+    /// Retrieve the mailing list the mail has been sent through, if any
+    pub fn get_list(&self) -> Option<MailingList> {
+        let list_id: Option<String> = self
+            .get_header_content("List-Id", &HeaderRepresentation::Humanized)
+            .into_iter()
+            .next();
+        let unsubscribe: Vec<String> = self
+            .get_header_content("List-Unsubscribe", &HeaderRepresentation::Raw)
+            .iter()
+            .flat_map(|header| header.split(','))
+            .map(|address| {
+                address
+                    .trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_owned()
+            })
+            .filter(|address| !address.is_empty())
+            .collect();
+        if list_id.is_none() && unsubscribe.is_empty() {
+            return None;
+        }
+
+        // The identifier is between angle brackets, after an optional description
+        let (name, id): (Option<String>, Option<String>) =
+            list_id.map_or((None, None), |list_id| {
+                match (list_id.rfind('<'), list_id.rfind('>')) {
+                    (Some(start), Some(end)) if start < end => (
+                        list_id
+                            .get(..start)
+                            .map(|name| name.trim().trim_matches('"').to_owned())
+                            .filter(|name| !name.is_empty()),
+                        list_id.get(start.saturating_add(1)..end).map(str::to_owned),
+                    ),
+                    _ => (None, Some(list_id.trim().to_owned())),
+                }
+            });
+
+        Some(MailingList {
+            id,
+            name,
+            unsubscribe,
+        })
+    }
+
+    /// Retrieve the beginning of the displayed content, on a single line
+    pub const fn get_snippet(&self) -> &String {
+        &self.snippet
+    }
+
+    /// Retrieve the problems found while parsing the mail
+    pub const fn get_diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
+
+    /// Retrieve the personal data found by the scanner
+    pub const fn get_pii(&self) -> &Vec<Finding> {
+        &self.pii
+    }
+
+    /// Mark the mail with the personal data found in it
+    pub fn set_pii(&mut self, pii: Vec<Finding>) {
+        self.pii = pii;
+    }
+
+    /// Retrieve the user the SMTP client was authenticated as
+    pub const fn get_auth_user(&self) -> Option<&String> {
+        self.auth_user.as_ref()
+    }
+
+    /// Record the user the SMTP client was authenticated as
+    pub fn set_auth_user(&mut self, user: Option<String>) {
+        self.auth_user = user;
+    }
+
+    /// Retrieve the SMTP client the mail was received from, if it came by SMTP
+    pub const fn get_client(&self) -> Option<&Client> {
+        self.client.as_ref()
+    }
+
+    /// Record the SMTP client the mail was received from
+    pub fn set_client(&mut self, client: Option<Client>) {
+        self.client = client;
+    }
+
+    /// Retrieve the tags given by the rules at the reception
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Record the tags given by the rules at the reception
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        self.tags = tags;
+    }
+
+    /// Retrieve the simulated deliveries to the recipients
+    pub fn get_deliveries(&self) -> &[Delivery] {
+        &self.deliveries
+    }
+
+    /// Record the simulated deliveries to the recipients
+    pub fn set_deliveries(&mut self, deliveries: Vec<Delivery>) {
+        self.deliveries = deliveries;
+    }
+
+    /// Retrieve the reports of the DKIM signatures
+    pub fn get_dkim(&self) -> &[Report] {
+        &self.dkim
+    }
+
+    /// Record the reports of the DKIM signatures
+    pub fn set_dkim(&mut self, reports: Vec<Report>) {
+        self.dkim = reports;
+    }
+
+    /// Retrieve the SMTP session the mail was received in, if it came by SMTP
+    pub const fn get_transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+
+    /// Attach the SMTP session the mail was received in
+    pub fn set_transcript(&mut self, transcript: Transcript) {
+        self.transcript = Some(transcript);
+    }
+
+    /// Check if the mail is locked, so it cannot be removed
+    pub const fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Lock the mail while it is investigated, or unlock it
+    pub const fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    /// Access the time spent in the stages of the processing, to record a new one
+    pub const fn timing_mut(&mut self) -> &mut Timing {
+        &mut self.timing
+    }
+
+    /// Take the time spent in the stages of the processing, once the mail is in
+    /// the tank
+    pub fn take_timing(&mut self) -> Timing {
+        std::mem::take(&mut self.timing)
+    }
+
+    /// Retrieve the ESMTP parameters of the MAIL FROM command
+    pub const fn get_from_params(&self) -> &Parameters {
+        &self.from_params
+    }
+
+    /// Retrieve the ESMTP parameters of the RCPT TO command of each receiver
+    pub const fn get_to_params(&self) -> &Vec<Parameters> {
+        &self.to_params
+    }
+
+    /// Record the ESMTP parameters of the envelope, `to` having those of each
+    /// receiver
+    pub fn set_params(&mut self, from: Parameters, to: Vec<Parameters>) {
+        self.from_params = from;
+        self.to_params = to;
+    }
+
+    /// Retrieve the ids of the requests that caused the mail, in lowercase
+    ///
+    /// They are the values of the correlation headers, like `X-Request-Id`,
+    /// the trace id of a W3C `traceparent` header, and the envelope id given
+    /// with `ENVID`.
+    pub fn get_correlation_ids(&self) -> Vec<String> {
+        let mut values: Vec<String> = Vec::new();
+        values.extend(dsn::envid(&self.from_params).ok().flatten());
+        for &name in &CORRELATION_HEADERS {
+            values.extend(self.get_header_content(name, &HeaderRepresentation::Raw));
+        }
+        // 00-<trace id>-<parent id>-<flags>
+        for traceparent in self.get_header_content("traceparent", &HeaderRepresentation::Raw) {
+            values.extend(traceparent.split('-').nth(1).map(ToOwned::to_owned));
+        }
+
+        let mut ids: Vec<String> = Vec::new();
+        for value in values {
+            let id: String = value.trim().to_ascii_lowercase();
+            if !id.is_empty() && !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Retrieve the id given by the `Message-Id` header
+    pub fn get_message_id(&self) -> Option<String> {
+        self.get_header_content("Message-Id", &HeaderRepresentation::Raw)
+            .iter()
+            .flat_map(|value| thread::message_ids(value))
+            .next()
+    }
+
+    /// Retrieve the ids of the mails it replies to, from the `In-Reply-To` header
+    pub fn get_in_reply_to(&self) -> Vec<String> {
+        self.get_header_content("In-Reply-To", &HeaderRepresentation::Raw)
+            .iter()
+            .flat_map(|value| thread::message_ids(value))
+            .collect()
+    }
+
+    /// Retrieve the ids of the previous mails of its conversation, the first
+    /// one first, from the `References` header
+    pub fn get_references(&self) -> Vec<String> {
+        self.get_header_content("References", &HeaderRepresentation::Raw)
+            .iter()
+            .flat_map(|value| thread::message_ids(value))
+            .collect()
+    }
+
+    /// Report a problem found while receiving the mail
+    pub fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
     /// Retrieve the data type part of the mail
     pub fn get_data(&self, type_: &Type) -> Option<&String> {
         self.data.get(type_)
     }
 
-    /// Return a symplification of the email, for sending it over JSON
-    pub fn summary(&self) -> Value {
+    /// Retrieve the text or html content of the mail like `get_data`, decoded
+    /// again from its part to report the bytes that cannot be decoded from its
+    /// charset instead of replacing them
+    pub fn get_data_strict(&self, type_: &Type) -> Option<Result<String, CharsetError>> {
+        self.parts
+            .iter()
+            .filter(|part| !part.is_attachment())
+            .find(|part| Type::of(part) == *type_)
+            .map(Part::text_strict)
+    }
+
+    /// Retrieve the header block of the raw content, byte for byte: the case of
+    /// the names, the folding and the line endings are kept, up to the line
+    /// ending of the last header
+    pub fn get_raw_headers(&self) -> Option<&str> {
+        let raw: &str = self.data.get(&Type::Raw)?;
+        let mut end: usize = 0;
+        for line in raw.split_inclusive('\n') {
+            // An empty line ends the headers
+            if line == "\r\n" || line == "\n" {
+                break;
+            }
+            end = end.saturating_add(line.len());
+        }
+        raw.get(..end)
+    }
+
+    /// Return a symplification of the email, for sending it over JSON, with the
+    /// `id` given by the strategy
+    pub fn summary(&self, ids: IdStrategy) -> Value {
         json!({
-            "id": self.get_id().to_string(),
+            "id": ids.id_of(self),
+            "ulid": self.get_id().to_string(),
+            "number": self.get_number(),
             "from": self.from().to_string(),
             "to": self.to(),
+            "recipients": self.to().len(),
+            "mismatch": self.has_mismatch(),
+            "from_params": self.get_from_params(),
+            "to_params": self.get_to_params(),
             "subject": self.get_subject().to_string(),
             "date": self.get_date().timestamp(),
+            "received": self.get_received().timestamp(),
+            "declared": self.get_declared().map(|declared| declared.timestamp()),
             "size": self.get_size(),
+            "cc": self.get_cc(),
+            "attachments": self.get_attachments().len(),
+            "has_text": self.get_text().is_some(),
+            "has_html": self.get_html().is_some(),
+            "snippet": self.get_snippet(),
+            "latency": self.get_latency().map(|latency| latency.num_milliseconds()),
+            "errors": self.get_diagnostics().len(),
+            "pii": self.get_pii().len(),
+            "priority": self.get_priority(),
+            "locked": self.is_locked(),
+            "client": self.get_client(),
+            "tags": self.get_tags(),
         })
     }
 
@@ -300,6 +1062,53 @@ impl Mail {
     }
 }
 
+/// Keep the beginning of a text, on a single line
+fn make_snippet(text: &str) -> String {
+    let text: String = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    match text.char_indices().nth(SNIPPET_LENGTH) {
+        Some((idx, _)) => format!("{}\u{2026}", text.get(..idx).unwrap_or_default()),
+        None => text,
+    }
+}
+
+/// Keep the address between angle brackets, if any, in lowercase
+fn bare_address(address: &str) -> String {
+    let address: &str = match (address.rfind('<'), address.rfind('>')) {
+        (Some(start), Some(end)) if start < end => address
+            .get(start.saturating_add(1)..end)
+            .unwrap_or_default(),
+        _ => address,
+    };
+    address.trim().to_lowercase()
+}
+
+/// Split an addresses list header content, like `To` or `Cc`, to each address
+fn split_addresses(list: &str) -> Vec<String> {
+    let mut addresses: Vec<String> = Vec::new();
+    let mut current: String = String::new();
+    let mut quoted: bool = false;
+    let mut bracketed: bool = false;
+
+    for c in list.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '<' if !quoted => bracketed = true,
+            '>' if !quoted => bracketed = false,
+            ',' | ';' if !quoted && !bracketed => {
+                addresses.push(current.trim().to_owned());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    addresses.push(current.trim().to_owned());
+
+    addresses.retain(|address| !address.is_empty());
+    addresses
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::TimeZone;
@@ -340,6 +1149,24 @@ This is the content of this mail... but it says nothing now.";
         assert_eq!(mail.data[&Type::Text], "This is a test mailing\r\n\r\n");
     }
 
+    #[test]
+    fn raw_headers() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new(
+            "",
+            &[],
+            "SUBJECT: Folded\r\n\tsubject \r\nx-Mixed:  spaces\n\r\nBody\r\n\r\nMore",
+        );
+        assert_eq!(
+            mail.get_raw_headers(),
+            Some("SUBJECT: Folded\r\n\tsubject \r\nx-Mixed:  spaces\n")
+        );
+        // Without body, the whole content is the header block
+        let mail: Mail = Mail::new("", &[], "Subject: Alone");
+        assert_eq!(mail.get_raw_headers(), Some("Subject: Alone"));
+    }
+
     #[test]
     fn test_getting_datetime() {
         crate::test::log_init();
@@ -353,6 +1180,167 @@ This is the content of this mail... but it says nothing now.";
         assert_eq!(date, dt);
     }
 
+    #[test]
+    fn malformed_mail() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new("", &[], "Just some text\r\nwithout any header");
+        assert_eq!(mail.get_diagnostics(), &vec![Diagnostic::MissingSeparator]);
+        assert!(mail.headers.is_empty());
+        assert_eq!(
+            mail.get_text().expect("mail text"),
+            "Just some text\r\nwithout any header"
+        );
+
+        let mail: Mail = Mail::new(
+            "",
+            &[],
+            "Date: yesterday\r\nnot a header\r\nSubject: Broken\r\n\r\nBody",
+        );
+        assert_eq!(
+            mail.get_diagnostics(),
+            &vec![
+                Diagnostic::InvalidHeader {
+                    line: 2,
+                    content: "not a header".to_owned()
+                },
+                Diagnostic::InvalidDate {
+                    value: "yesterday".to_owned()
+                },
+            ]
+        );
+        assert_eq!(mail.get_subject(), "Broken");
+        assert_eq!(mail.get_text().expect("mail text"), "Body");
+        assert_eq!(
+            mail.summary(IdStrategy::Ulid).get("errors"),
+            Some(&json!(2))
+        );
+
+        // A sloppy date is read leniently, instead of the reception time
+        let mail: Mail = Mail::new("", &[], "Date: Sun, 22 Nov 20 01:58 GMT+0200\r\n\r\nBody");
+        assert_eq!(mail.get_date().to_rfc3339(), "2020-11-21T23:58:00+00:00");
+        assert_eq!(
+            mail.get_diagnostics(),
+            &vec![Diagnostic::LenientDate {
+                value: "Sun, 22 Nov 20 01:58 GMT+0200".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn headers_only() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::with_headers_only("", &[], DATA_COMPLEX, Some(1_000));
+        assert!(mail.get_diagnostics().is_empty());
+        assert!(mail.get_text().is_some());
+
+        let mail: Mail = Mail::with_headers_only("", &[], DATA_COMPLEX, Some(100));
+        assert_eq!(
+            mail.get_diagnostics(),
+            &vec![Diagnostic::BodyDiscarded {
+                size: DATA_COMPLEX.len()
+            }]
+        );
+        assert_eq!(mail.headers.len(), 4);
+        assert_eq!(
+            mail.get_subject(),
+            Mail::new("", &[], DATA_COMPLEX).get_subject()
+        );
+        assert_eq!(mail.get_text().map(String::as_str), Some(""));
+    }
+
+    #[test]
+    fn resent_and_list_headers() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new("", &[], DATA_SIMPLE);
+        assert!(mail.get_resent().is_none());
+        assert!(mail.get_list().is_none());
+
+        let mail: Mail = Mail::new(
+            "",
+            &[],
+            "Resent-From: Bob <bob@example.org>\r\n\
+Resent-To: a@example.net, \"Doe, C\" <c@example.net>\r\n\
+Resent-Date: Sun, 22 Nov 2020 01:58:23 +0100\r\n\
+Resent-From: old@example.org\r\n\
+List-Id: \"Catchers\" <catchers.example.org>\r\n\
+List-Unsubscribe: <mailto:leave@example.org>,\r\n <https://example.org/leave>\r\n\
+\r\n\
+Body",
+        );
+        assert_eq!(
+            mail.get_resent(),
+            Some(Resent {
+                from: vec!["Bob <bob@example.org>".to_owned()],
+                to: vec![
+                    "a@example.net".to_owned(),
+                    "\"Doe, C\" <c@example.net>".to_owned()
+                ],
+                date: Some(1_606_006_703),
+            })
+        );
+        assert_eq!(
+            mail.get_list(),
+            Some(MailingList {
+                id: Some("catchers.example.org".to_owned()),
+                name: Some("Catchers".to_owned()),
+                unsubscribe: vec![
+                    "mailto:leave@example.org".to_owned(),
+                    "https://example.org/leave".to_owned()
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn priority_headers() {
+        crate::test::log_init();
+
+        for &(headers, priority) in &[
+            ("Subject: none", Priority::Normal),
+            ("X-Priority: 1 (Highest)", Priority::High),
+            ("X-Priority: 5", Priority::Low),
+            ("importance: High", Priority::High),
+            ("Priority: non-urgent", Priority::Low),
+            ("X-MSMail-Priority: Low", Priority::Low),
+            ("X-Priority: unknown\r\nImportance: low", Priority::Low),
+        ] {
+            let mail: Mail = Mail::new("", &[], &format!("{}\r\n\r\nBody", headers));
+            assert_eq!(mail.get_priority(), priority, "{}", headers);
+        }
+    }
+
+    #[test]
+    fn delivery_latency() {
+        crate::test::log_init();
+
+        // The Date header is used for the latency
+        let mail: Mail = Mail::new("", &[], DATA_SIMPLE);
+        let dt: DateTime<Utc> = Utc.ymd(2020, 11, 22).and_hms(0, 58, 23);
+        assert_eq!(
+            mail.get_latency().expect("latency"),
+            mail.get_received().signed_duration_since(dt)
+        );
+
+        // Date in the future, so negative
+        let date: DateTime<Utc> = Utc::now()
+            .checked_add_signed(Duration::hours(1))
+            .expect("date");
+        let mail: Mail = Mail::new(
+            "",
+            &[],
+            &format!("Date: {}\r\n\r\nFuture", date.to_rfc2822()),
+        );
+        assert!(mail.get_latency().expect("latency") < Duration::zero());
+
+        // Without Date header, there is no latency
+        let mail: Mail = Mail::new("", &[], DATA_COMPLEX);
+        assert!(mail.get_latency().is_none());
+        assert_eq!(mail.get_date(), mail.get_received());
+    }
+
     #[test]
     fn get_text() {
         crate::test::log_init();
@@ -369,22 +1357,124 @@ This is the content of this mail... but it says nothing now.";
         assert!(mail.get_html().is_none());
     }
 
+    #[test]
+    fn recipients_mismatch() {
+        crate::test::log_init();
+
+        let to: Vec<String> = vec!["bob@x.test".to_owned(), "Carol@Y.test".to_owned()];
+        let mail: Mail = Mail::new(
+            "alice@example.org",
+            &to,
+            "To: \"Bob\" <bob@x.test>\r\nCc: carol@y.test\r\n\r\nHello",
+        );
+        assert!(!mail.has_mismatch());
+
+        // Carol in blind carbon copy
+        let mail: Mail = Mail::new(
+            "alice@example.org",
+            &to,
+            "To: Bob <bob@x.test>\r\n\r\nHello",
+        );
+        assert!(mail.has_mismatch());
+        // Carol forgotten in the envelope
+        let mail: Mail = Mail::new(
+            "alice@example.org",
+            &["bob@x.test".to_owned()],
+            "To: bob@x.test, carol@y.test\r\n\r\nHello",
+        );
+        assert!(mail.has_mismatch());
+    }
+
     #[test]
     fn summary_is_json() {
         crate::test::log_init();
 
         let mail: Mail = Mail::new("from@example.org", &["to@example.net".into()], DATA_SIMPLE);
-        let summary: String = mail.summary().to_string();
+        let summary: String = mail.summary(IdStrategy::Ulid).to_string();
 
         assert_eq!(
             summary,
             format!(
-                r#"{{"date":1606006703,"from":"from@example.org","id":"{}","size":251,"subject":"test Sun, 22 Nov 2020 01:58:23 +0100","to":["to@example.net"]}}"#,
-                mail.id
+                r#"{{"attachments":0,"cc":[],"client":null,"date":1606006703,"declared":1606006703,"errors":0,"from":"from@example.org","from_params":{{}},"has_html":false,"has_text":true,"id":"{}","latency":{},"locked":false,"mismatch":true,"number":{},"pii":0,"priority":"normal","received":{},"recipients":1,"size":251,"snippet":"This is a test mailing","subject":"test Sun, 22 Nov 2020 01:58:23 +0100","tags":[],"to":["to@example.net"],"to_params":[{{}}],"ulid":"{}"}}"#,
+                mail.id,
+                mail.get_latency().expect("latency").num_milliseconds(),
+                mail.number,
+                mail.received.timestamp(),
+                mail.id,
             )
         );
     }
 
+    #[test]
+    fn sort_by_dates() {
+        crate::test::log_init();
+
+        // Received before the others, but declared after the second one
+        let early: Mail = Mail::restore(
+            Ulid::new(),
+            Utc.ymd(2019, 1, 1).and_hms(0, 0, 0),
+            "",
+            &[],
+            DATA_SIMPLE,
+        );
+        let dated: Mail = Mail::new("", &[], "Date: Sun, 22 Nov 2015 01:58:23 +0100\r\n\r\nBody");
+        let undated: Mail = Mail::new("", &[], "Subject: Undated\r\n\r\nBody");
+        assert_eq!(
+            early.get_declared().map(|date| date.timestamp()),
+            Some(1_606_006_703)
+        );
+        assert_eq!(undated.get_declared(), None);
+        assert_eq!(undated.get_date(), undated.get_received());
+
+        let ids = |mails: &[Mail]| mails.iter().map(Mail::get_id).collect::<Vec<Ulid>>();
+        let mut mails: Vec<Mail> = vec![undated.clone(), dated.clone(), early.clone()];
+        SortBy::Received.sort(&mut mails, false);
+        assert_eq!(
+            ids(&mails),
+            vec![early.get_id(), dated.get_id(), undated.get_id()]
+        );
+        SortBy::Declared.sort(&mut mails, false);
+        assert_eq!(
+            ids(&mails),
+            vec![dated.get_id(), early.get_id(), undated.get_id()]
+        );
+        SortBy::Declared.sort(&mut mails, true);
+        assert_eq!(
+            ids(&mails),
+            vec![undated.get_id(), early.get_id(), dated.get_id()]
+        );
+    }
+
+    #[test]
+    fn sequential_ids() {
+        crate::test::log_init();
+
+        let first: Mail = Mail::new("", &[], DATA_SIMPLE);
+        let second: Mail = Mail::new("", &[], DATA_SIMPLE);
+        assert!(second.get_number() > first.get_number());
+
+        let summary: Value = second.summary(IdStrategy::Sequential);
+        assert_eq!(summary.get("id"), Some(&json!(second.get_number())));
+        assert_eq!(summary.get("number"), Some(&json!(second.get_number())));
+        assert_eq!(
+            summary.get("ulid"),
+            Some(&json!(second.get_id().to_string()))
+        );
+
+        // A restored mail keeps its number, the next ones come after it
+        let mut restored: Mail = Mail::new("", &[], DATA_SIMPLE);
+        restored.set_number(second.get_number().saturating_add(1_000));
+        assert_eq!(
+            restored.get_number(),
+            second.get_number().saturating_add(1_000)
+        );
+        assert!(Mail::new("", &[], DATA_SIMPLE).get_number() > restored.get_number());
+
+        assert_eq!("Sequential".parse(), Ok(IdStrategy::Sequential));
+        assert_eq!("ulid".parse(), Ok(IdStrategy::Ulid));
+        assert!("uuid".parse::<IdStrategy>().is_err());
+    }
+
     #[test]
     #[allow(clippy::indexing_slicing)]
     fn multiline_header_content_and_humanized() {
@@ -409,6 +1499,109 @@ This is the content of this mail... but it says nothing now.";
         );
     }
 
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn summary_of_multipart() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new(
+            "from@example.org",
+            &["to@example.net".into()],
+            "Cc: \"Doe, John\" <john@example.org>, jane@example.org\r\n\
+Content-Type: multipart/mixed; boundary=\"frontier\"\r\n\
+\r\n\
+--frontier\r\n\
+Content-Type: text/html; charset=utf-8\r\n\
+\r\n\
+<p>Hello</p>\r\n\
+--frontier\r\n\
+Content-Type: text/csv\r\n\
+Content-Disposition: attachment; filename=\"report.csv\"\r\n\
+\r\n\
+a,b\r\n\
+--frontier--\r\n",
+        );
+        let summary: Value = mail.summary(IdStrategy::Ulid);
+
+        assert_eq!(summary["attachments"], 1);
+        assert_eq!(summary["has_html"], true);
+        assert_eq!(summary["has_text"], false);
+        assert_eq!(summary["snippet"], "Hello");
+        assert_eq!(
+            summary["cc"],
+            json!(["\"Doe, John\" <john@example.org>", "jane@example.org"])
+        );
+        assert_eq!(mail.get_html().expect("html content"), "<p>Hello</p>");
+        assert!(mail.is_multipart());
+    }
+
+    #[test]
+    fn decoded_bodies() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new(
+            "from@example.org",
+            &["to@example.net".into()],
+            "Content-Type: multipart/alternative; boundary=\"frontier\"\r\n\
+\r\n\
+--frontier\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+Q2Fmw6kgYXUgbGFpdA==\r\n\
+--frontier\r\n\
+Content-Type: text/html; charset=iso-8859-1\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\
+\r\n\
+<p style=3D\"color: red\">Caf=E9 au =\r\n\
+lait</p>\r\n\
+--frontier--\r\n",
+        );
+
+        assert_eq!(mail.get_text().expect("text content"), "Café au lait");
+        assert_eq!(
+            mail.get_html().expect("html content"),
+            "<p style=\"color: red\">Café au lait</p>"
+        );
+        assert_eq!(mail.snippet, "Café au lait");
+    }
+
+    #[test]
+    fn long_snippet() {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new(
+            "",
+            &[],
+            &format!("Subject: long\r\n\r\n{}", "word\r\n".repeat(50)),
+        );
+        let snippet: &String = mail.get_snippet();
+
+        assert_eq!(snippet.chars().count(), 121);
+        assert!(snippet.starts_with("word word "));
+        assert!(snippet.ends_with('\u{2026}'));
+    }
+
+    #[test]
+    fn prepend_header() {
+        crate::test::log_init();
+
+        let mut mail: Mail = Mail::new("from@example.org", &["to@example.net".into()], DATA_SIMPLE);
+        let size: usize = mail.get_size();
+        mail.prepend_header("Received", "from client ([127.0.0.1])\r\n by MailCatcher");
+
+        assert_eq!(
+            mail.get_header_content("Received", &HeaderRepresentation::Raw),
+            vec!["from client ([127.0.0.1])\r\n by MailCatcher".to_owned()]
+        );
+        assert_eq!(mail.get_headers(&HeaderRepresentation::Raw).len(), 7);
+        assert!(mail
+            .get_data(&Type::Raw)
+            .expect("raw mail")
+            .starts_with("Received: from client ([127.0.0.1])\r\n by MailCatcher\r\nDate: "));
+        assert_eq!(mail.get_size(), size + 54);
+    }
+
     #[test]
     fn get_data() {
         crate::test::log_init();
@@ -0,0 +1,314 @@
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::{Match, Regex};
+use tide::prelude::Serialize;
+
+use crate::mail::Mail;
+
+lazy_static! {
+    static ref RE_CARD: Regex = Regex::new(r"[0-9](?:[ -]?[0-9]){12,18}").expect("re card");
+    static ref RE_SSN: Regex =
+        Regex::new(r"(?P<area>[0-9]{3})-(?P<group>[0-9]{2})-(?P<serial>[0-9]{4})").expect("re ssn");
+    static ref RE_NIR: Regex =
+        Regex::new(r"[12] ?[0-9]{2} ?[0-9]{2} ?[0-9]{2} ?[0-9]{3} ?[0-9]{3} ?[0-9]{2}")
+            .expect("re nir");
+}
+
+/// Built-in detector: the kind of data, the regex of the candidates and the
+/// check of their validity
+type Detector = (&'static str, &'static Regex, fn(&str) -> bool);
+
+/// Number of characters of a found value that are kept visible
+const VISIBLE: usize = 4;
+
+/// Personal data found in a mail
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    /// What was found: `credit_card`, `ssn`, `nir`, or the name of a custom pattern
+    pub kind: String,
+    /// Where it was found: `text`, `html`, or the name of an attachment
+    pub location: String,
+    /// The found value, masked except for its last characters
+    pub masked: String,
+}
+
+/// Custom pattern to look for, given as `name=regex`
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    /// Name reported in the findings
+    name: String,
+    /// Regular expression matching the data
+    regex: Regex,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, regex): (&str, &str) = s
+            .split_once('=')
+            .ok_or_else(|| format!("The pattern must be \"name=regex\": {}", s))?;
+        if name.is_empty() {
+            return Err(format!("The pattern has no name: {}", s));
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            regex: Regex::new(regex).map_err(|e| format!("Invalid pattern {}: {}", name, e))?,
+        })
+    }
+}
+
+/// Scanner of the mails, looking for personal data in their contents and their
+/// attachments
+#[derive(Clone, Debug, Default)]
+pub struct Scanner {
+    /// Look for the credit card numbers and the national ids
+    builtin: bool,
+    /// Custom patterns to look for
+    patterns: Vec<Pattern>,
+}
+
+impl Scanner {
+    /// Create a scanner, none if it has nothing to look for
+    pub fn new(builtin: bool, patterns: Vec<Pattern>) -> Option<Self> {
+        if builtin || !patterns.is_empty() {
+            Some(Self { builtin, patterns })
+        } else {
+            None
+        }
+    }
+
+    /// Find the personal data in the contents and the attachments of a mail
+    pub fn scan(&self, mail: &Mail) -> Vec<Finding> {
+        let mut findings: Vec<Finding> = Vec::new();
+        let mut attachments: usize = 0;
+
+        for part in mail.get_parts() {
+            let location: String = if part.is_attachment() {
+                attachments = attachments.saturating_add(1);
+                part.filename()
+                    .unwrap_or_else(|| format!("attachment-{}", attachments))
+            } else {
+                match part.content_type() {
+                    "text/plain" => "text".to_owned(),
+                    "text/html" => "html".to_owned(),
+                    content_type => content_type.to_owned(),
+                }
+            };
+            let content: String = part.text();
+            for (kind, value) in self.find(&content) {
+                findings.push(Finding {
+                    kind,
+                    location: location.clone(),
+                    masked: mask(&value),
+                });
+            }
+        }
+
+        findings
+    }
+
+    /// Scan a mail, and mark it with the personal data found
+    pub fn mark(&self, mail: &mut Mail) {
+        mail.set_pii(self.scan(mail));
+        if !mail.get_pii().is_empty() {
+            log::warn!(
+                "Personal data found in the mail {}: {:?}",
+                mail.get_id(),
+                mail.get_pii()
+            );
+        }
+    }
+
+    /// Find the personal data in a content, returning their kind and their value
+    fn find(&self, content: &str) -> Vec<(String, String)> {
+        let mut found: Vec<(String, String)> = Vec::new();
+
+        if self.builtin {
+            let detectors: [Detector; 3] = [
+                ("credit_card", &RE_CARD, is_card),
+                ("ssn", &RE_SSN, is_ssn),
+                ("nir", &RE_NIR, is_nir),
+            ];
+            for &(kind, regex, valid) in &detectors {
+                for m in regex.find_iter(content) {
+                    if is_isolated(content, &m) && valid(m.as_str()) {
+                        found.push((kind.to_owned(), m.as_str().to_owned()));
+                    }
+                }
+            }
+        }
+        for pattern in &self.patterns {
+            for m in pattern.regex.find_iter(content) {
+                found.push((pattern.name.clone(), m.as_str().to_owned()));
+            }
+        }
+
+        found
+    }
+}
+
+/// Check that a number is not a part of a longer one
+fn is_isolated(content: &str, m: &Match<'_>) -> bool {
+    let before: Option<char> = content.get(..m.start()).and_then(|s| s.chars().next_back());
+    let after: Option<char> = content.get(m.end()..).and_then(|s| s.chars().next());
+    !before.map_or(false, |c| c.is_ascii_digit()) && !after.map_or(false, |c| c.is_ascii_digit())
+}
+
+/// Digits of a number, without its separators
+fn digits(number: &str) -> Vec<u32> {
+    number.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+/// Check if a number is a valid credit card number, with the Luhn checksum
+fn is_card(number: &str) -> bool {
+    let digits: Vec<u32> = digits(number);
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, &digit)| {
+            if index % 2 == 1 {
+                let double: u32 = digit.saturating_mul(2);
+                if double > 9 {
+                    double.saturating_sub(9)
+                } else {
+                    double
+                }
+            } else {
+                digit
+            }
+        })
+        .fold(0, u32::saturating_add);
+    let remainder: u32 = sum % 10;
+
+    (13..=19).contains(&digits.len()) && remainder == 0
+}
+
+/// Check if a number is a valid US social security number, that has no zero
+/// group and is not in a never assigned area
+fn is_ssn(number: &str) -> bool {
+    RE_SSN.captures(number).map_or(false, |caps| {
+        let field = |name: &str| caps.name(name).map_or("", |m| m.as_str());
+        let area: &str = field("area");
+        area != "000"
+            && area != "666"
+            && !area.starts_with('9')
+            && field("group") != "00"
+            && field("serial") != "0000"
+    })
+}
+
+/// Check if a number is a valid French social security number (NIR), with its
+/// key: 97 minus the number modulo 97
+fn is_nir(number: &str) -> bool {
+    let digits: Vec<u32> = digits(number);
+    let (number, key): (&[u32], &[u32]) = digits.split_at(digits.len().saturating_sub(2));
+    let fold = |digits: &[u32]| {
+        digits.iter().fold(0_u64, |acc, &digit| {
+            acc.saturating_mul(10).saturating_add(digit.into())
+        })
+    };
+
+    key.len() == 2 && 97_u64.saturating_sub(fold(number) % 97) == fold(key)
+}
+
+/// Hide a value, except for its last characters
+fn mask(value: &str) -> String {
+    let count: usize = value.chars().count();
+    value
+        .chars()
+        .enumerate()
+        .map(|(index, c)| {
+            if index.saturating_add(VISIBLE) < count || count <= VISIBLE {
+                '*'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn builtin_detectors() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let scanner: Scanner = Scanner::new(true, Vec::new()).ok_or("no scanner")?;
+        let found = |content: &str| -> Vec<String> {
+            scanner
+                .find(content)
+                .into_iter()
+                .map(|(kind, _)| kind)
+                .collect()
+        };
+
+        assert_eq!(found("Card: 4111 1111 1111 1111."), vec!["credit_card"]);
+        assert_eq!(found("Card: 4111-1111-1111-1112"), Vec::<String>::new());
+        assert_eq!(found("Order 41111111111111110"), Vec::<String>::new());
+        assert_eq!(found("SSN 123-45-6789"), vec!["ssn"]);
+        assert_eq!(found("SSN 666-45-6789, 123-00-6789"), Vec::<String>::new());
+        assert_eq!(found("NIR 1 84 12 76 451 089 46"), vec!["nir"]);
+        assert_eq!(found("NIR 1 84 12 76 451 089 47"), Vec::<String>::new());
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn scan_mail() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let patterns: Vec<Pattern> = vec!["employee=EMP-[0-9]{6}".parse()?];
+        assert!("no separator".parse::<Pattern>().is_err());
+        assert!("broken=[0-9".parse::<Pattern>().is_err());
+        assert!(Scanner::new(false, Vec::new()).is_none());
+
+        let scanner: Scanner = Scanner::new(true, patterns).ok_or("no scanner")?;
+        let mail: Mail = Mail::new(
+            "sender@example.org",
+            &["rcpt@example.org".to_owned()],
+            "Content-Type: multipart/mixed; boundary=sep\r\n\
+\r\n\
+--sep\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Paid with 5500 0000 0000 0004 by EMP-004217\r\n\
+--sep\r\n\
+Content-Type: text/csv; name=\"staff.csv\"\r\n\
+Content-Disposition: attachment\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+bmFtZSxzc24NCmJvYiwxMjMtNDUtNjc4OQ==\r\n\
+--sep--\r\n",
+        );
+
+        assert_eq!(
+            scanner.scan(&mail),
+            vec![
+                Finding {
+                    kind: "credit_card".to_owned(),
+                    location: "text".to_owned(),
+                    masked: "***************0004".to_owned(),
+                },
+                Finding {
+                    kind: "employee".to_owned(),
+                    location: "text".to_owned(),
+                    masked: "******4217".to_owned(),
+                },
+                Finding {
+                    kind: "ssn".to_owned(),
+                    location: "staff.csv".to_owned(),
+                    masked: "*******6789".to_owned(),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+use std::convert::TryFrom;
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    task,
+};
+use futures::StreamExt;
+use ulid::Ulid;
+
+use crate::{
+    error::MailcatcherError,
+    mail::{
+        broker::{MailEvt, MailTank, TankDigest, TankStats},
+        journal::Journal,
+        store::MailStore,
+        thread::ThreadIndex,
+    },
+};
+
+/// Mail broker split in several tanks, each one processed by its own task, with
+/// a router in front sending them the events
+///
+/// A mail always goes to the same shard, chosen from the random part of its id:
+/// the time part is shared by the mails received in the same millisecond, and
+/// would send a burst of mails to a single shard. The events about all the mails
+/// are sent to every shard, their replies being merged in the same channel.
+pub struct ShardedTank {
+    /// Channel to access the tanks from the outside
+    receiver: Receiver<MailEvt>,
+    /// Store of each shard
+    stores: Vec<Box<dyn MailStore>>,
+    /// Journal shared by the shards, if the mails are persisted
+    journal: Option<Journal>,
+}
+
+impl ShardedTank {
+    /// Instantiate a new broker, with a shard for each store
+    pub fn new(
+        receiver: Receiver<MailEvt>,
+        stores: Vec<Box<dyn MailStore>>,
+        journal: Option<Journal>,
+    ) -> Self {
+        Self {
+            receiver,
+            stores,
+            journal,
+        }
+    }
+
+    /// Start a task for each shard, then route them the events from the
+    /// `Receiver` stream, until every sender is dropped
+    pub async fn process(self) -> crate::Result<()> {
+        let mut shards: Vec<Sender<MailEvt>> = Vec::with_capacity(self.stores.len());
+        let mut workers: Vec<task::JoinHandle<crate::Result<()>>> =
+            Vec::with_capacity(self.stores.len());
+        for store in self.stores {
+            let (sender, receiver): crate::Channel<MailEvt> = channel::unbounded();
+            let tank: MailTank = MailTank::new(receiver, store, self.journal.clone());
+            workers.push(task::spawn(tank.process()));
+            shards.push(sender);
+        }
+        log::debug!("Mail broker split in {} shards", shards.len());
+
+        let mut receiver: Receiver<MailEvt> = self.receiver;
+        while let Some(evt) = receiver.next().await {
+            route(&shards, evt).await?;
+        }
+
+        // Every sender is gone, let the shards finish their work
+        drop(shards);
+        for worker in workers {
+            worker.await?;
+        }
+        Ok(())
+    }
+}
+
+/// Index of the shard keeping the mail with this id, among `count` shards
+fn index(count: usize, id: Ulid) -> Option<usize> {
+    u128::try_from(count)
+        .ok()
+        .and_then(|count| u128::from(id).checked_rem(count))
+        .and_then(|index| usize::try_from(index).ok())
+}
+
+/// Shard keeping the mail with this id
+fn shard_of(shards: &[Sender<MailEvt>], id: Ulid) -> crate::Result<&Sender<MailEvt>> {
+    index(shards.len(), id)
+        .and_then(|index| shards.get(index))
+        .ok_or_else(|| MailcatcherError::broker("The mail broker has no shard"))
+}
+
+/// Send an event to the shards concerned by it
+async fn route(shards: &[Sender<MailEvt>], evt: MailEvt) -> crate::Result<()> {
+    match evt {
+        // Events about a single mail
+        MailEvt::NewMail(ref mail) => shard_of(shards, mail.get_id())?.send(evt).await?,
+        MailEvt::GetMail(_, id)
+        | MailEvt::Remove(_, id)
+        | MailEvt::Lock(_, id, _)
+        | MailEvt::Notified(id, _)
+        | MailEvt::GetTiming(_, id) => shard_of(shards, id)?.send(evt).await?,
+        // Events about all the mails, the reply channel is closed once every
+        // shard has dropped its copy of the sender
+        MailEvt::GetAll(sender) => {
+            for shard in shards {
+                shard.send(MailEvt::GetAll(sender.clone())).await?;
+            }
+        }
+        MailEvt::Search(sender, filter) => {
+            for shard in shards {
+                shard
+                    .send(MailEvt::Search(sender.clone(), filter.clone()))
+                    .await?;
+            }
+        }
+        MailEvt::ByCorrelation(sender, id) => {
+            for shard in shards {
+                shard
+                    .send(MailEvt::ByCorrelation(sender.clone(), id.clone()))
+                    .await?;
+            }
+        }
+        MailEvt::ByNumber(sender, number) => {
+            for shard in shards {
+                shard
+                    .send(MailEvt::ByNumber(sender.clone(), number))
+                    .await?;
+            }
+        }
+        MailEvt::RemoveAll(sender) => {
+            for shard in shards {
+                shard.send(MailEvt::RemoveAll(sender.clone())).await?;
+            }
+        }
+        MailEvt::GetStats(sender) => {
+            gather(shards, sender, MailEvt::GetStats, TankStats::merge).await?;
+        }
+        MailEvt::GetDigest(sender) => {
+            gather(shards, sender, MailEvt::GetDigest, TankDigest::merge).await?;
+        }
+        MailEvt::GetThreads(sender) => {
+            gather(shards, sender, MailEvt::GetThreads, ThreadIndex::merge).await?;
+        }
+        // The journal is shared, any shard can read or compact it
+        MailEvt::Compact(_) | MailEvt::AsOf(_, _) => {
+            shard_of(shards, Ulid::nil())?.send(evt).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Ask every shard for its statistics, its digest or its thread index, built by
+/// `ask`, and send them back merged
+///
+/// The questions are sent at once, after the events routed before, and the
+/// replies are waited for aside, so the router goes on meanwhile: the merged
+/// reply is still sent once every previous event is processed.
+async fn gather<T>(
+    shards: &[Sender<MailEvt>],
+    sender: Sender<T>,
+    ask: fn(Sender<T>) -> MailEvt,
+    merge: fn(&mut T, &T),
+) -> crate::Result<()>
+where
+    T: Default + Send + 'static,
+{
+    let mut replies: Vec<Receiver<T>> = Vec::with_capacity(shards.len());
+    for shard in shards {
+        let (tx_reply, rx_reply): crate::Channel<T> = channel::bounded(1);
+        shard.send(ask(tx_reply)).await?;
+        replies.push(rx_reply);
+    }
+    let _merge = task::spawn(async move {
+        let mut merged: T = T::default();
+        for reply in replies {
+            match reply.recv().await {
+                Ok(shard) => merge(&mut merged, &shard),
+                // The shard failed, its failure is reported by the broker
+                Err(_) => return,
+            }
+        }
+        sender.send(merged).await.unwrap_or_default();
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::prelude::FutureExt;
+    use futures::TryFutureExt;
+
+    use super::*;
+    use crate::mail::{broker::Removal, store::MemoryStore, Mail};
+
+    /// Broker split in 3 shards, with 10 mails
+    async fn init() -> crate::test::Result<(Vec<Mail>, Sender<MailEvt>, ShardedTank)> {
+        crate::test::log_init();
+
+        let (sender, receiver): crate::Channel<MailEvt> = channel::unbounded();
+        let mut mails: Vec<Mail> = Vec::new();
+        for _ in 0..10 {
+            let mail: Mail = Mail::fake();
+            mails.push(mail.clone());
+            sender.send(MailEvt::NewMail(Box::new(mail))).await?;
+        }
+        let stores: Vec<Box<dyn MailStore>> = (0..3)
+            .map(|_| -> Box<dyn MailStore> { Box::new(MemoryStore::default()) })
+            .collect();
+
+        Ok((mails, sender, ShardedTank::new(receiver, stores, None)))
+    }
+
+    #[test]
+    fn same_shard() {
+        crate::test::log_init();
+
+        let id: Ulid = Ulid::new();
+
+        assert!(index(3, id).map_or(false, |index| index < 3));
+        assert_eq!(index(3, id), index(3, id));
+        assert_eq!(index(2, Ulid::from(2_u128)), Some(0));
+        assert_eq!(index(2, Ulid::from(3_u128)), Some(1));
+        assert_eq!(index(0, id), None);
+        assert!(shard_of(&[], id).is_err());
+    }
+
+    #[test]
+    fn sharded_tank() -> std::io::Result<()> {
+        #[allow(clippy::panic)]
+        async fn the_test(mails: Vec<Mail>, sender: Sender<MailEvt>) -> crate::test::Result<()> {
+            // Every mail is found in its shard
+            for mail in &mails {
+                let (s, r): crate::Channel<Option<Mail>> = channel::bounded(1);
+                sender.send(MailEvt::GetMail(s, mail.get_id())).await?;
+                assert_eq!(
+                    r.recv().await?.map(|found| found.get_id()),
+                    Some(mail.get_id())
+                );
+            }
+
+            // The mails of all the shards are gathered
+            let (s, r): crate::Channel<Mail> = channel::unbounded();
+            sender.send(MailEvt::GetAll(s)).await?;
+            let mut ids: Vec<Ulid> = r.map(|mail| mail.get_id()).collect().await;
+            let mut expected: Vec<Ulid> = mails.iter().map(Mail::get_id).collect();
+            ids.sort();
+            expected.sort();
+            assert_eq!(ids, expected);
+
+            // The mail having a number is found among all the shards
+            let last: &Mail = mails.last().ok_or("no mail")?;
+            let (s, r): crate::Channel<Ulid> = channel::unbounded();
+            sender.send(MailEvt::ByNumber(s, last.get_number())).await?;
+            assert_eq!(r.collect::<Vec<Ulid>>().await, vec![last.get_id()]);
+
+            let first: Ulid = expected.first().copied().ok_or("no mail")?;
+            let (s, r): crate::Channel<Removal> = channel::bounded(1);
+            sender.send(MailEvt::Remove(s, first)).await?;
+            assert_eq!(r.recv().await?, Removal::Removed);
+
+            // The statistics of the shards are merged
+            let (s, r): crate::Channel<TankStats> = channel::bounded(1);
+            sender.send(MailEvt::GetStats(s)).await?;
+            let stats: TankStats = r.recv().await?;
+            let left: Vec<&Mail> = mails.iter().filter(|mail| mail.get_id() != first).collect();
+            let single: TankStats = TankStats::new(left.iter().copied());
+            assert_eq!(stats.mails, 9);
+            assert_eq!(stats.size, single.size);
+            assert_eq!(stats.content_types, single.content_types);
+            assert_eq!(stats.latency.count, single.latency.count);
+            assert_eq!(stats.latency.min, single.latency.min);
+            assert_eq!(stats.latency.max, single.latency.max);
+
+            // And so are their digests
+            let (s, r): crate::Channel<TankDigest> = channel::bounded(1);
+            sender.send(MailEvt::GetDigest(s)).await?;
+            let digest: TankDigest = r.recv().await?;
+            assert_eq!(
+                digest,
+                TankDigest::new(left.iter().map(|mail| mail.get_id()))
+            );
+
+            // All the mails are removed, from every shard
+            let (s, r): crate::Channel<Ulid> = channel::unbounded();
+            sender.send(MailEvt::RemoveAll(s)).await?;
+            assert_eq!(r.collect::<Vec<Ulid>>().await.len(), 9);
+
+            Ok(())
+        }
+
+        let (mails, sender, broker) = task::block_on(init()).expect("Init");
+
+        crate::test::with_timeout(
+            5_000,
+            broker.process().err_into().race(the_test(mails, sender)),
+        )
+    }
+}
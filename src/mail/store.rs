@@ -0,0 +1,207 @@
+use ulid::Ulid;
+
+use crate::mail::{filter::Filter, Mail};
+
+/// Storage backend of the mail broker
+///
+/// Only the broker accesses the store, that is kept across the awaits of its loop.
+pub trait MailStore: Send + Sync {
+    /// Add a mail, replacing the one having the same id if any
+    fn insert(&mut self, mail: Mail);
+
+    /// Retrieve a mail from its id
+    fn get(&self, id: &Ulid) -> Option<Mail>;
+
+    /// Remove a mail from its id, returning it if it was stored
+    fn remove(&mut self, id: &Ulid) -> Option<Mail>;
+
+    /// Iterate over all the mails, in no particular order
+    fn iter(&self) -> Box<dyn Iterator<Item = Mail> + Send + '_>;
+
+    /// Iterate over the mails matching the filter, in no particular order
+    fn search<'a>(&'a self, filter: &'a Filter) -> Box<dyn Iterator<Item = Mail> + Send + 'a> {
+        Box::new(self.iter().filter(move |mail| filter.matches(mail)))
+    }
+
+    /// Retrieve the ids of all the mails that are not locked, so they can be
+    /// removed
+    fn unlocked_ids(&self) -> Vec<Ulid> {
+        self.iter()
+            .filter(|mail| !mail.is_locked())
+            .map(|mail| mail.get_id())
+            .collect()
+    }
+
+    /// Retrieve the id of the mail having this sequential number
+    fn by_number(&self, number: u64) -> Option<Ulid> {
+        self.iter()
+            .find(|mail| mail.get_number() == number)
+            .map(|mail| mail.get_id())
+    }
+
+    /// Retrieve the mails caused by a request, from its correlation id in
+    /// lowercase, see `Mail::get_correlation_ids`
+    fn by_correlation(&self, id: &str) -> Vec<Mail> {
+        self.iter()
+            .filter(|mail| {
+                mail.get_correlation_ids()
+                    .iter()
+                    .any(|mail_id| mail_id == id)
+            })
+            .collect()
+    }
+}
+
+/// Mails kept in memory, lost when the program exits
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    /// Mails, by their id
+    mails: fnv::FnvHashMap<Ulid, Mail>,
+    /// Ids of the mails, by the correlation ids of their requests
+    correlations: fnv::FnvHashMap<String, Vec<Ulid>>,
+}
+
+impl MemoryStore {
+    /// Remove a mail from the index of the correlation ids
+    fn unindex(&mut self, mail: &Mail) {
+        for correlation in mail.get_correlation_ids() {
+            if let Some(ids) = self.correlations.get_mut(&correlation) {
+                ids.retain(|&id| id != mail.get_id());
+                if ids.is_empty() {
+                    let _ = self.correlations.remove(&correlation);
+                }
+            }
+        }
+    }
+}
+
+impl MailStore for MemoryStore {
+    fn insert(&mut self, mail: Mail) {
+        if let Some(replaced) = self.mails.remove(&mail.get_id()) {
+            self.unindex(&replaced);
+        }
+        for correlation in mail.get_correlation_ids() {
+            self.correlations
+                .entry(correlation)
+                .or_default()
+                .push(mail.get_id());
+        }
+        let _ = self.mails.insert(mail.get_id(), mail);
+    }
+
+    fn get(&self, id: &Ulid) -> Option<Mail> {
+        self.mails.get(id).cloned()
+    }
+
+    fn remove(&mut self, id: &Ulid) -> Option<Mail> {
+        let mail: Option<Mail> = self.mails.remove(id);
+        if let Some(ref mail) = mail {
+            self.unindex(mail);
+        }
+        mail
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Mail> + Send + '_> {
+        Box::new(self.mails.values().cloned())
+    }
+
+    fn unlocked_ids(&self) -> Vec<Ulid> {
+        self.mails
+            .values()
+            .filter(|mail| !mail.is_locked())
+            .map(Mail::get_id)
+            .collect()
+    }
+
+    fn by_correlation(&self, id: &str) -> Vec<Mail> {
+        self.correlations.get(id).map_or_else(Vec::new, |ids| {
+            ids.iter()
+                .filter_map(|id| self.mails.get(id).cloned())
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::Priority;
+
+    #[test]
+    fn memory_store() {
+        crate::test::log_init();
+
+        let mut store: MemoryStore = MemoryStore::default();
+        let urgent: Mail = Mail::new("", &[], "X-Priority: 1\r\n\r\nNow!");
+        let fake: Mail = Mail::fake();
+        store.insert(urgent.clone());
+        store.insert(fake.clone());
+
+        assert_eq!(store.iter().count(), 2);
+        let mut ids: Vec<Ulid> = store.unlocked_ids();
+        ids.sort();
+        let mut expected: Vec<Ulid> = vec![urgent.get_id(), fake.get_id()];
+        expected.sort();
+        assert_eq!(ids, expected);
+        // A locked mail cannot be removed
+        let mut locked: Mail = fake.clone();
+        locked.set_locked(true);
+        store.insert(locked);
+        assert_eq!(store.unlocked_ids(), vec![urgent.get_id()]);
+        store.insert(fake.clone());
+
+        let filter: Filter = Filter {
+            priority: Some(Priority::High),
+            ..Filter::default()
+        };
+        let found: Vec<Ulid> = store.search(&filter).map(|mail| mail.get_id()).collect();
+        assert_eq!(found, vec![urgent.get_id()]);
+
+        assert_eq!(
+            store.get(&fake.get_id()).map(|mail| mail.get_id()),
+            Some(fake.get_id())
+        );
+        assert!(store.remove(&fake.get_id()).is_some());
+        assert!(store.remove(&fake.get_id()).is_none());
+        assert!(store.get(&fake.get_id()).is_none());
+    }
+
+    #[test]
+    fn correlation_index() {
+        crate::test::log_init();
+
+        let mut store: MemoryStore = MemoryStore::default();
+        let first: Mail = Mail::new("", &[], "X-Request-Id: ABC-123\r\n\r\nFirst");
+        let second: Mail = Mail::new(
+            "",
+            &[],
+            "Correlation-Id: abc-123\r\n\
+traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\n\r\nSecond",
+        );
+        store.insert(first.clone());
+        store.insert(second.clone());
+        store.insert(Mail::fake());
+        // Inserted again, but indexed once
+        store.insert(first.clone());
+
+        let found = |store: &MemoryStore, id: &str| -> Vec<Ulid> {
+            let mut ids: Vec<Ulid> = store.by_correlation(id).iter().map(Mail::get_id).collect();
+            ids.sort();
+            ids
+        };
+        let mut both: Vec<Ulid> = vec![first.get_id(), second.get_id()];
+        both.sort();
+        assert_eq!(found(&store, "abc-123"), both);
+        assert_eq!(
+            found(&store, "4bf92f3577b34da6a3ce929d0e0e4736"),
+            vec![second.get_id()]
+        );
+        assert_eq!(found(&store, "unknown"), Vec::new());
+
+        let _ = store.remove(&second.get_id());
+        assert_eq!(found(&store, "abc-123"), vec![first.get_id()]);
+        assert!(!store
+            .correlations
+            .contains_key("4bf92f3577b34da6a3ce929d0e0e4736"));
+    }
+}
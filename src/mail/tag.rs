@@ -0,0 +1,126 @@
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+use tide::prelude::{Deserialize, Serialize};
+
+use crate::mail::{HeaderRepresentation, Mail};
+
+/// Rule tagging the mails at their reception, given as `tag=Header:prefix`,
+/// like `staging=Subject:[staging]` or `eu=X-Region:eu-`: the mails with a
+/// header starting with the prefix get the tag
+///
+/// The headers are decoded, and compared without the case. An empty prefix
+/// tags the mails having the header. The rules are compared by their text.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct TagRule {
+    /// Tag given to the mails matched
+    tag: String,
+    /// Name of the header compared
+    header: String,
+    /// Beginning of the header value, as it was given
+    prefix: String,
+}
+
+impl TagRule {
+    /// Tag given to the mail, if the rule matches it
+    pub(crate) fn tag_of(&self, mail: &Mail) -> Option<&str> {
+        let prefix: String = self.prefix.to_lowercase();
+        let matches: bool = mail
+            .get_header_content(&self.header, &HeaderRepresentation::Humanized)
+            .iter()
+            .any(|value| value.trim().to_lowercase().starts_with(&prefix));
+        Some(self.tag.as_str()).filter(|_| matches)
+    }
+}
+
+impl FromStr for TagRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "The rule must be \"tag=Header:prefix\", like \"staging=Subject:[staging]\": {}",
+                s
+            )
+        };
+        let (tag, pattern): (&str, &str) = s.split_once('=').ok_or_else(invalid)?;
+        let (header, prefix): (&str, &str) = pattern.split_once(':').ok_or_else(invalid)?;
+        let tag: &str = tag.trim();
+        if tag.is_empty() || tag.contains(char::is_whitespace) {
+            return Err(format!("Invalid tag \"{}\", it must be a word", tag));
+        }
+        let header: &str = header.trim();
+        if header.is_empty() || header.contains(char::is_whitespace) {
+            return Err(format!("Invalid header name \"{}\"", header));
+        }
+
+        Ok(Self {
+            tag: tag.to_owned(),
+            header: header.to_owned(),
+            prefix: prefix.trim().to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for TagRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}:{}", self.tag, self.header, self.prefix)
+    }
+}
+
+impl From<TagRule> for String {
+    #[inline]
+    fn from(rule: TagRule) -> Self {
+        rule.to_string()
+    }
+}
+
+impl TryFrom<String> for TagRule {
+    type Error = String;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn rules() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new(
+            "from@example.org",
+            &["to@example.net".into()],
+            "Subject: =?utf-8?q?[Staging]_R=C3=A9initialiser?=\r\nX-Region: eu-west-1\r\n\r\nBody",
+        );
+
+        let subject: TagRule = "staging = Subject:[staging]".parse()?;
+        assert_eq!(subject.tag_of(&mail), Some("staging"));
+        assert_eq!(subject.to_string(), "staging=Subject:[staging]");
+
+        let header: TagRule = "eu=x-region:EU-".parse()?;
+        assert_eq!(header.tag_of(&mail), Some("eu"));
+        let present: TagRule = "tracked=X-Region:".parse()?;
+        assert_eq!(present.tag_of(&mail), Some("tracked"));
+        let other: TagRule = "us=X-Region:us-".parse()?;
+        assert_eq!(other.tag_of(&mail), None);
+        let missing: TagRule = "campaign=X-Campaign:".parse()?;
+        assert_eq!(missing.tag_of(&mail), None);
+
+        for invalid in &[
+            "staging",
+            "staging=[staging]",
+            "=Subject:x",
+            "two words=Subject:x",
+            "tag=:x",
+        ] {
+            assert!(invalid.parse::<TagRule>().is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use std::cmp::Reverse;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use ulid::Ulid;
+
+use crate::mail::Mail;
+
+lazy_static! {
+    /// Message id between angle brackets, like `<1234@example.net>`
+    static ref RE_MSG_ID: Regex = Regex::new(r"<([^<>\s]+)>").expect("re msg id");
+}
+
+/// Message ids of a header value, like those of `References`, the value itself
+/// being the id if it has no angle brackets
+pub fn message_ids(value: &str) -> Vec<String> {
+    let ids: Vec<String> = RE_MSG_ID
+        .captures_iter(value)
+        .filter_map(|captures| captures.get(1))
+        .map(|id| id.as_str().to_owned())
+        .collect();
+    if ids.is_empty() {
+        value
+            .split_whitespace()
+            .next()
+            .map(ToOwned::to_owned)
+            .into_iter()
+            .collect()
+    } else {
+        ids
+    }
+}
+
+/// Links of a mail to the others of its conversation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Links {
+    /// Sequential number of the mail, giving the order of the conversation
+    number: u64,
+    /// Id given by the `Message-Id` header
+    message_id: Option<String>,
+    /// Ids of the mails it replies to, from its `References` and `In-Reply-To`
+    /// headers
+    parents: Vec<String>,
+}
+
+/// Index of the conversations of the mails, from their `Message-Id`,
+/// `In-Reply-To` and `References` headers
+///
+/// A mail is in the thread of the mails it references and of those referencing
+/// it, even through a mail that is not in the tank, like the first of a
+/// conversation that was removed.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadIndex {
+    /// Links of the mails, by their id
+    links: fnv::FnvHashMap<Ulid, Links>,
+}
+
+impl ThreadIndex {
+    /// Index a mail
+    pub fn insert(&mut self, mail: &Mail) {
+        let mut parents: Vec<String> = mail.get_references();
+        for parent in mail.get_in_reply_to() {
+            if !parents.contains(&parent) {
+                parents.push(parent);
+            }
+        }
+        let links: Links = Links {
+            number: mail.get_number(),
+            message_id: mail.get_message_id(),
+            parents,
+        };
+        let _ = self.links.insert(mail.get_id(), links);
+    }
+
+    /// Remove a mail from the index
+    pub fn remove(&mut self, id: &Ulid) {
+        let _ = self.links.remove(id);
+    }
+
+    /// Add the mails of another index, like that of another shard of the tank
+    pub fn merge(&mut self, other: &Self) {
+        self.links
+            .extend(other.links.iter().map(|(&id, links)| (id, links.clone())));
+    }
+
+    /// Ids of the mails grouped by conversation, the oldest mail first in each
+    /// one, and the conversation having the newest mail first
+    #[allow(clippy::indexing_slicing)]
+    pub fn threads(&self) -> Vec<Vec<Ulid>> {
+        // Union-find of the mails and of the message ids they give, the indexes
+        // of the mails coming first
+        let mut ids: Vec<(u64, Ulid)> = self
+            .links
+            .iter()
+            .map(|(&id, links)| (links.number, id))
+            .collect();
+        ids.sort_unstable();
+        let mut nodes: fnv::FnvHashMap<&str, usize> = fnv::FnvHashMap::default();
+        let mut parents: Vec<usize> = (0..ids.len()).collect();
+        for (mail, &(_, id)) in ids.iter().enumerate() {
+            if let Some(links) = self.links.get(&id) {
+                for message_id in links.message_id.iter().chain(&links.parents) {
+                    let next: usize = parents.len();
+                    let node: usize = *nodes.entry(message_id.as_str()).or_insert(next);
+                    if node == next {
+                        parents.push(next);
+                    }
+                    union(&mut parents, mail, node);
+                }
+            }
+        }
+
+        // Each thread with the index of its newest mail
+        let mut roots: fnv::FnvHashMap<usize, (usize, Vec<Ulid>)> = fnv::FnvHashMap::default();
+        for (mail, &(_, id)) in ids.iter().enumerate() {
+            let root: usize = find(&mut parents, mail);
+            let thread: &mut (usize, Vec<Ulid>) = roots.entry(root).or_default();
+            thread.0 = mail;
+            thread.1.push(id);
+        }
+        let mut threads: Vec<(usize, Vec<Ulid>)> = roots.into_values().collect();
+        threads.sort_unstable_by_key(|&(newest, _)| Reverse(newest));
+        threads.into_iter().map(|(_, thread)| thread).collect()
+    }
+}
+
+/// Root of the set of a node, shortening the path to it on the way
+#[allow(clippy::indexing_slicing)]
+fn find(parents: &mut [usize], mut node: usize) -> usize {
+    while parents[node] != node {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+    node
+}
+
+/// Join the sets of two nodes
+#[allow(clippy::indexing_slicing)]
+fn union(parents: &mut [usize], first: usize, second: usize) {
+    let (a, b): (usize, usize) = (find(parents, first), find(parents, second));
+    if a != b {
+        // The smallest root is kept, so a mail stays the root of its set
+        parents[a.max(b)] = a.min(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mail with the threading headers
+    fn mail(message_id: &str, in_reply_to: Option<&str>, references: Option<&str>) -> Mail {
+        let header = |name: &str, value: Option<&str>| {
+            value.map_or_else(String::new, |content| format!("{}: {}\r\n", name, content))
+        };
+        Mail::new(
+            "from@example.net",
+            &["to@example.net".to_owned()],
+            &format!(
+                "{}{}{}Subject: thread\r\n\r\nbody\r\n",
+                header("Message-Id", Some(message_id)),
+                header("In-Reply-To", in_reply_to),
+                header("References", references)
+            ),
+        )
+    }
+
+    #[test]
+    fn ids_of_headers() {
+        crate::test::log_init();
+
+        assert_eq!(
+            message_ids("<a@example.net>\r\n <b@example.net>"),
+            vec!["a@example.net".to_owned(), "b@example.net".to_owned()]
+        );
+        assert_eq!(
+            message_ids(" c@example.net "),
+            vec!["c@example.net".to_owned()]
+        );
+        assert!(message_ids("  ").is_empty());
+    }
+
+    #[test]
+    fn conversations() {
+        crate::test::log_init();
+
+        let first: Mail = mail("<1@example.net>", None, None);
+        let reply: Mail = mail("<2@example.net>", Some("<1@example.net>"), None);
+        let other: Mail = mail("<3@example.net>", None, None);
+        // Only references the reply, the first mail being dropped
+        let late: Mail = mail("<4@example.net>", None, Some("<2@example.net>"));
+        // References a removed mail, that still links it to the last one
+        let orphan: Mail = mail("<5@example.net>", Some("<gone@example.net>"), None);
+        let sibling: Mail = mail("<6@example.net>", None, Some("<gone@example.net>"));
+
+        let mut index: ThreadIndex = ThreadIndex::default();
+        let mut shard: ThreadIndex = ThreadIndex::default();
+        for mail in &[&first, &reply, &other] {
+            index.insert(mail);
+        }
+        for mail in &[&late, &orphan, &sibling] {
+            shard.insert(mail);
+        }
+        index.merge(&shard);
+
+        assert_eq!(
+            index.threads(),
+            vec![
+                vec![orphan.get_id(), sibling.get_id()],
+                vec![first.get_id(), reply.get_id(), late.get_id()],
+                vec![other.get_id()],
+            ]
+        );
+
+        // Removing the reply splits its thread
+        index.remove(&reply.get_id());
+        assert_eq!(
+            index.threads(),
+            vec![
+                vec![orphan.get_id(), sibling.get_id()],
+                vec![late.get_id()],
+                vec![other.get_id()],
+                vec![first.get_id()],
+            ]
+        );
+    }
+}
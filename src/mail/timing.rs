@@ -0,0 +1,115 @@
+use std::{
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
+
+use tide::prelude::Serialize;
+
+/// Stage of the processing of a mail, from its reception to the notification
+/// of the browsers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Reception of the content by the SMTP side, from DATA or the first BDAT
+    Receive,
+    /// Parsing of the content
+    Parse,
+    /// Writing in the journal, if the mails are persisted
+    Journal,
+    /// Scan of the personal data, if enabled
+    Scan,
+    /// Wait for the mail broker
+    Queue,
+    /// Insertion in the tank, with the indexing of the correlation ids
+    Insert,
+    /// Notification of the browsers and the other sinks
+    Notify,
+}
+
+/// Time spent by a mail in a stage
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Lap {
+    /// Stage of the processing
+    pub stage: Stage,
+    /// Time spent, in microseconds
+    pub micros: u64,
+}
+
+/// Time spent by a mail in each stage of its processing, to find where the
+/// latency comes from
+#[derive(Clone, Debug, Default)]
+pub struct Timing {
+    /// Stages the mail passed through, in order
+    laps: Vec<Lap>,
+    /// Since when the mail waits for the mail broker
+    queued: Option<Instant>,
+}
+
+impl Timing {
+    /// Record the time spent in a stage
+    pub fn record(&mut self, stage: Stage, duration: Duration) {
+        self.laps.push(Lap {
+            stage,
+            micros: u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+        });
+    }
+
+    /// Record the time spent in a stage that began at `start`
+    pub fn since(&mut self, stage: Stage, start: Instant) {
+        self.record(stage, start.elapsed());
+    }
+
+    /// The mail is sent to the mail broker
+    pub fn enqueue(&mut self) {
+        self.queued = Some(Instant::now());
+    }
+
+    /// The mail broker received the mail, the wait is recorded if the mail was
+    /// sent with `enqueue`
+    pub fn dequeue(&mut self) {
+        if let Some(queued) = self.queued.take() {
+            self.since(Stage::Queue, queued);
+        }
+    }
+
+    /// Retrieve the stages the mail passed through, in order
+    pub fn laps(&self) -> &[Lap] {
+        &self.laps
+    }
+
+    /// Total time spent in the stages, in microseconds
+    pub fn total_micros(&self) -> u64 {
+        self.laps
+            .iter()
+            .fold(0, |total, lap| total.saturating_add(lap.micros))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_stages() {
+        crate::test::log_init();
+
+        let mut timing: Timing = Timing::default();
+        // Without enqueue, as for the mails restored from the journal
+        timing.dequeue();
+        assert!(timing.laps().is_empty());
+
+        timing.record(Stage::Receive, Duration::from_millis(3));
+        timing.record(Stage::Parse, Duration::from_micros(250));
+        timing.enqueue();
+        timing.dequeue();
+        timing.since(Stage::Insert, Instant::now());
+
+        let stages: Vec<Stage> = timing.laps().iter().map(|lap| lap.stage).collect();
+        assert_eq!(
+            stages,
+            vec![Stage::Receive, Stage::Parse, Stage::Queue, Stage::Insert]
+        );
+        assert_eq!(timing.laps().first().map(|lap| lap.micros), Some(3_000));
+        assert!(timing.total_micros() >= 3_250);
+    }
+}
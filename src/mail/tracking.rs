@@ -0,0 +1,198 @@
+use fnv::FnvHashMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+use tide::prelude::Serialize;
+
+lazy_static! {
+    static ref RE_IMG_TAG: Regex = Regex::new(r"<[iI][mM][gG][ \t\r\n][^>]*>").expect("re img tag");
+    static ref RE_LINK_TAG: Regex = Regex::new(r"<[aA][ \t\r\n][^>]*>").expect("re link tag");
+    static ref RE_ATTRIBUTE: Regex =
+        Regex::new(r#"[ \t\r\n](?P<name>[a-zA-Z-]+)[ \t\r\n]*=[ \t\r\n]*(?:"(?P<dquoted>[^"]*)"|'(?P<squoted>[^']*)'|(?P<bare>[^ \t\r\n>]+))"#)
+            .expect("re attribute");
+    static ref RE_STYLE_SIZE: Regex =
+        Regex::new(r"(?:^|[; \t\r\n])(?P<name>[wW][iI][dD][tT][hH]|[hH][eE][iI][gG][hH][tT])[ \t\r\n]*:[ \t\r\n]*(?P<value>[0-9]+)")
+            .expect("re style size");
+}
+
+/// Domains of the known link tracking services, that redirect to the real target
+const TRACKING_DOMAINS: &[&str] = &[
+    "awstrack.me",
+    "createsend1.com",
+    "ct.sendgrid.net",
+    "exct.net",
+    "hubspotlinks.com",
+    "klclick.com",
+    "list-manage.com",
+    "mandrillapp.com",
+    "mjt.lu",
+    "rs6.net",
+    "sparkpostmail.com",
+];
+
+/// Something in a html content that tells the sender when the mail is read, or
+/// when a link is followed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Tracker {
+    /// A remote image of 1x1 pixel at most, loaded when the mail is displayed
+    Pixel {
+        /// Address of the image
+        src: String,
+    },
+    /// A link through a known tracking redirect service
+    Link {
+        /// Address of the link
+        href: String,
+        /// Domain of the tracking service
+        domain: String,
+    },
+}
+
+/// Find the tracking pixels and the tracked links of a html content
+pub fn detect(html: &str) -> Vec<Tracker> {
+    let pixels = RE_IMG_TAG
+        .find_iter(html)
+        .map(|tag| attributes(tag.as_str()))
+        .filter(is_tiny)
+        .filter_map(|mut attributes| attributes.remove("src"))
+        .filter(|src| host(src).is_some())
+        .map(|src| Tracker::Pixel { src });
+    let links = RE_LINK_TAG
+        .find_iter(html)
+        .filter_map(|tag| attributes(tag.as_str()).remove("href"))
+        .filter_map(|href| {
+            let host: String = host(&href)?;
+            TRACKING_DOMAINS
+                .iter()
+                .find(|&&domain| {
+                    host == domain
+                        || host
+                            .strip_suffix(domain)
+                            .map_or(false, |sub| sub.ends_with('.'))
+                })
+                .map(|&domain| Tracker::Link {
+                    href: href.clone(),
+                    domain: domain.to_owned(),
+                })
+        });
+
+    pixels.chain(links).collect()
+}
+
+/// Retrieve the attributes of a html tag, with their names in lowercase
+fn attributes(tag: &str) -> FnvHashMap<String, String> {
+    RE_ATTRIBUTE
+        .captures_iter(tag)
+        .filter_map(|caps| {
+            let name: String = caps.name("name")?.as_str().to_ascii_lowercase();
+            let value = caps
+                .name("dquoted")
+                .or_else(|| caps.name("squoted"))
+                .or_else(|| caps.name("bare"))?;
+            Some((name, value.as_str().trim().to_owned()))
+        })
+        .collect()
+}
+
+/// Check if an image is displayed in 1x1 pixel at most, from its attributes or
+/// its style
+fn is_tiny(attributes: &FnvHashMap<String, String>) -> bool {
+    let mut sizes: FnvHashMap<String, u32> = FnvHashMap::default();
+    if let Some(style) = attributes.get("style") {
+        for caps in RE_STYLE_SIZE.captures_iter(style) {
+            if let (Some(name), Some(value)) = (caps.name("name"), caps.name("value")) {
+                if let Ok(value) = value.as_str().parse() {
+                    let _ = sizes.insert(name.as_str().to_ascii_lowercase(), value);
+                }
+            }
+        }
+    }
+    for &name in &["width", "height"] {
+        let value: Option<u32> = attributes
+            .get(name)
+            .and_then(|value| value.trim_end_matches("px").trim().parse().ok());
+        if let Some(value) = value {
+            let _ = sizes.insert(name.to_owned(), value);
+        }
+    }
+
+    ["width", "height"]
+        .iter()
+        .all(|&name| sizes.get(name).map_or(false, |&size| size <= 1))
+}
+
+/// Retrieve the host, in lowercase, of a remote address
+fn host(url: &str) -> Option<String> {
+    let lowercase: String = url.to_ascii_lowercase();
+    let rest: &str = ["http://", "https://", "//"]
+        .iter()
+        .find_map(|&scheme| lowercase.strip_prefix(scheme))?;
+    let authority: &str = rest.split(&['/', '?', '#'][..]).next().unwrap_or_default();
+    let host: &str = authority
+        .rsplit('@')
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .next()
+        .unwrap_or_default();
+
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracking_pixels() {
+        crate::test::log_init();
+
+        let html: &str = r#"<p>Hello</p>
+<img src="https://example.org/logo.png" width="200" height="50">
+<IMG SRC='https://t.example.org/open?id=42' WIDTH=1 HEIGHT=1 alt="">
+<img src="https://t.example.org/o.gif" style="width: 1px; height: 0px; border: 0">
+<img src="cid:logo@example.org" width="1" height="1">
+<img src="https://example.org/spacer.gif" width="1">"#;
+
+        assert_eq!(
+            detect(html),
+            vec![
+                Tracker::Pixel {
+                    src: "https://t.example.org/open?id=42".to_owned()
+                },
+                Tracker::Pixel {
+                    src: "https://t.example.org/o.gif".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tracked_links() {
+        crate::test::log_init();
+
+        let html: &str = r#"<a href="https://example.org/offer">Offer</a>
+<a class="button" href="https://eu1.list-manage.com/track/click?u=1&amp;id=2">Read</a>
+<a href='http://u123.ct.sendgrid.net/ls/click?upn=abc'>Unsubscribe</a>
+<a href="https://notlist-manage.com/">Lookalike</a>
+<a href="mailto:contact@example.org">Contact</a>"#;
+
+        assert_eq!(
+            detect(html),
+            vec![
+                Tracker::Link {
+                    href: "https://eu1.list-manage.com/track/click?u=1&amp;id=2".to_owned(),
+                    domain: "list-manage.com".to_owned(),
+                },
+                Tracker::Link {
+                    href: "http://u123.ct.sendgrid.net/ls/click?upn=abc".to_owned(),
+                    domain: "ct.sendgrid.net".to_owned(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::{collections::VecDeque, net::IpAddr};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tide::prelude::{json, Serialize};
+
+/// Largest number of lines kept for a session, the oldest ones being dropped
+const MAX_LINES: usize = 1_000;
+
+/// Side of the SMTP session that sent a line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    /// Command of the client
+    Client,
+    /// Reply of the server
+    Server,
+}
+
+/// Line exchanged during a SMTP session
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    /// When the line was received or sent
+    at: DateTime<Utc>,
+    /// Who sent the line
+    side: Side,
+    /// The line, without its line ending
+    text: String,
+}
+
+/// Commands and replies of a SMTP session, to see what the sending library did
+/// on the wire
+///
+/// The content of the mails is not kept, only the commands around it, and the
+/// credentials are masked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transcript {
+    /// Address of the client
+    client: Option<IpAddr>,
+    /// Lines exchanged, in order
+    lines: VecDeque<Line>,
+}
+
+impl Transcript {
+    /// New transcript of a session with a `client`
+    pub const fn new(client: Option<IpAddr>) -> Self {
+        Self {
+            client,
+            lines: VecDeque::new(),
+        }
+    }
+
+    /// Record a command of the client
+    pub fn client(&mut self, line: &str) {
+        self.push(Side::Client, line);
+    }
+
+    /// Record a reply of the server, that may span several lines
+    pub fn server(&mut self, message: &str) {
+        for line in message.lines() {
+            self.push(Side::Server, line);
+        }
+    }
+
+    /// Record a line, dropping the oldest one if there are too many
+    fn push(&mut self, side: Side, text: &str) {
+        if self.lines.len() >= MAX_LINES {
+            let _ = self.lines.pop_front();
+        }
+        self.lines.push_back(Line {
+            at: Utc::now(),
+            side,
+            text: text.trim_end_matches(&['\r', '\n'][..]).to_owned(),
+        });
+    }
+
+    /// Transcript as JSON, the times in RFC 3339 with the milliseconds
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "client": self.client.map(|client| client.to_string()),
+            "lines": self
+                .lines
+                .iter()
+                .map(|line| json!({
+                    "at": line.at.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    "side": line.side,
+                    "text": line.text,
+                }))
+                .collect::<Vec<serde_json::Value>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_lines() {
+        crate::test::log_init();
+
+        let mut transcript: Transcript = Transcript::new("192.0.2.1".parse().ok());
+        transcript.server("220 Catcher ESMTP\r\n");
+        transcript.client("EHLO client");
+        transcript.server("250-Catcher\r\n250 PIPELINING\r\n");
+
+        let sides: Vec<(Side, &str)> = transcript
+            .lines
+            .iter()
+            .map(|line| (line.side, line.text.as_str()))
+            .collect();
+        assert_eq!(
+            sides,
+            vec![
+                (Side::Server, "220 Catcher ESMTP"),
+                (Side::Client, "EHLO client"),
+                (Side::Server, "250-Catcher"),
+                (Side::Server, "250 PIPELINING"),
+            ]
+        );
+        let json: serde_json::Value = transcript.to_json();
+        assert_eq!(json.get("client"), Some(&json!("192.0.2.1")));
+        assert_eq!(
+            json.pointer("/lines/1"),
+            Some(&json!({
+                "at": transcript.lines.get(1).map(|line| line
+                    .at
+                    .to_rfc3339_opts(SecondsFormat::Millis, true)),
+                "side": "client",
+                "text": "EHLO client",
+            }))
+        );
+
+        // The oldest lines are dropped
+        for _ in 0..MAX_LINES {
+            transcript.client("NOOP");
+        }
+        assert_eq!(transcript.lines.len(), MAX_LINES);
+        assert_eq!(
+            transcript.lines.front().map(|line| line.text.as_str()),
+            Some("NOOP")
+        );
+    }
+}
@@ -67,37 +67,118 @@
 //!
 //! It DOES NOT really send them to any remote recipient address.
 
+use std::{
+    env,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use async_std::{
     channel::{self, Receiver, Sender},
+    net::SocketAddr,
     prelude::FutureExt,
+    sync::RwLock,
     task,
 };
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
 use structopt::StructOpt;
-use tide::Server;
+use tide::{prelude::json, Server};
 
 use crate::{
-    http::{bind as bind_http, sse_evt::SseEvt, Params, State},
+    error::MailcatcherError,
+    fixture::{Encoding, Features},
+    http::{
+        asset::Theme,
+        event_sink::EventSink,
+        file_sink::{FileFormat, FileSink},
+        help::Help,
+        sse_evt::SseEvt,
+        syslog_sink::{SyslogSink, SyslogTarget},
+        Capabilities, Listening, Params, ProfiledListening, State,
+    },
     mail::{
         broker::{MailEvt, MailTank},
-        Mail,
+        delivery::{Simulator, Weights},
+        dkim::{DkimKey, Verifier},
+        journal::Journal,
+        pii::{Pattern, Scanner},
+        shard::ShardedTank,
+        store::{MailStore, MemoryStore},
+        tag::TagRule,
+        timing::Stage,
+        IdStrategy, Mail,
+    },
+    otlp::Tracer,
+    preset::Preset,
+    purge::Age,
+    rebind::{Servers, Signals},
+    settings::{Settings, SharedSettings},
+    shutdown::Activity,
+    smtp::{
+        auth::Credentials,
+        capture::{Capture, Sample},
+        greylist::Greylist,
+        headers::HeaderLimits,
+        latency::{Delay, Latency},
+        limit::{Limits, Rate},
+        metrics::Metrics,
+        profile::ListenerSpec,
+        quarantine::Quarantine,
+        queue::{Overflow, Queue},
+        release::Target,
+        reply::Replies,
+        rules::RecipientRule,
+        LineEndings,
+    },
+    utils::{
+        bind_port, spawn_task_and_swallow_log_errors, AddressFamily, Bound, Cidr, Output, Timezone,
     },
-    utils::spawn_task_and_swallow_log_errors,
 };
 
+/// Check that an instance is running
+mod check;
 /// Decode encoded string
 mod encoding;
+/// Errors of the crate
+mod error;
+/// Export of the mails of a running instance to an archive
+mod export;
+/// Extraction of the attachments of the mails to a directory
+mod extract;
+/// Generation of randomized mails, to test the mail consumers
+mod fixture;
+/// Handover of the listeners to a new process, to upgrade the binary
+mod handover;
 /// Display mail content with HTTP content
 mod http;
+/// Injection of mail files in a running instance
+mod inject;
 /// Mail representation/gestion
 mod mail;
+/// Export of the traces to an OpenTelemetry collector
+mod otlp;
+/// Bundles of options for a use of the catcher
+mod preset;
+/// Removal of the old mails, for the housekeeping
+mod purge;
+/// Rebinding of the listeners while running
+mod rebind;
+/// Conformance checks of the SMTP side
+mod selftest;
+/// Settings changed at runtime
+mod settings;
+/// Graceful shutdown on the termination signals
+mod shutdown;
 /// SMTP part
 mod smtp;
+/// Statistics of a running instance
+mod stats;
 /// Deals with async tasks
 mod utils;
 
 /// Result type commonly used in this crate
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+type Result<T> = std::result::Result<T, MailcatcherError>;
 
 /// Type alias for stream channel
 type Channel<T> = (Sender<T>, Receiver<T>);
@@ -105,15 +186,39 @@ type Channel<T> = (Sender<T>, Receiver<T>);
 /// Command line arguments
 #[derive(Debug, StructOpt)]
 #[structopt(about, author)]
+#[allow(clippy::struct_excessive_bools)]
 struct Opt {
-    /// SMTP listening port
-    #[structopt(long, default_value = "1025")]
-    smtp: u16,
+    /// SMTP listening port, with the profile of its listeners as "port:profile"
+    ///
+    /// Can be given several times, each port playing a server: "normal" catches
+    /// the mails and "always-tempfail" defers the transactions with a 451 reply.
+    /// Only the first port is taken from the settings, and rebound
+    #[structopt(long, default_value = "1025", number_of_values = 1)]
+    smtp: Vec<ListenerSpec>,
 
     /// HTTP listening port
     #[structopt(long, default_value = "1080")]
     http: u16,
 
+    /// Only listen on the IPv4 addresses
+    #[structopt(long, conflicts_with = "ipv6-only")]
+    ipv4_only: bool,
+
+    /// Only listen on the IPv6 addresses
+    #[structopt(long)]
+    ipv6_only: bool,
+
+    /// Try the next ports, up to this number, when a port is busy
+    ///
+    /// The ports finally used are logged and given by "/api/info"
+    #[structopt(long, default_value = "0")]
+    port_fallback: u16,
+
+    /// Fail to start if an address of a port cannot be bound, instead of
+    /// listening on the other ones
+    #[structopt(long)]
+    strict_bind: bool,
+
     /// Allow to use StartTls (not yet implemented!)
     #[structopt(skip)]
     use_starttls: bool,
@@ -124,83 +229,1120 @@ struct Opt {
     #[structopt(long, default_value = "MailCatcher")]
     smtp_name: String,
 
+    /// Timezone used to format the dates
+    ///
+    /// Either a name of the tz database, like "Europe/Paris", or an offset
+    /// like "+02:00". Can be overridden on each request with the "tz" parameter
+    #[structopt(long, default_value = "UTC")]
+    timezone: Timezone,
+
     /// Open browser's webpage at the start
     #[structopt(long)]
     browser: bool,
+
+    /// Theme of the web interface, like "classic" or "dark"
+    ///
+    /// Can be overridden on each page with the "theme" parameter
+    #[structopt(long, default_value = "classic")]
+    theme: Theme,
+
+    /// Identifier of the mails given as "id" by the API, "ulid" or "sequential"
+    ///
+    /// A sequential number, from 1, is given like the original catcher in Ruby. Both
+    /// are given anyway, as "ulid" and "number", and accepted in the routes
+    #[structopt(long, default_value = "ulid")]
+    id_strategy: IdStrategy,
+
+    /// Relay SMTP sessions to this server (host:port)
+    ///
+    /// The catcher then acts as a transparent proxy, that keeps a copy of
+    /// each mail sent to the upstream server
+    #[structopt(long)]
+    smtp_upstream: Option<String>,
+
+    /// Credentials the SMTP clients must give with AUTH, as "user:password"
+    ///
+    /// The PLAIN and LOGIN mechanisms are advertised, and any credentials are
+    /// accepted if not specified. The authentication is optional either way
+    #[structopt(long)]
+    smtp_auth: Option<Credentials>,
+
+    /// Real SMTP server (host:port) the mails can be released to, with
+    /// "POST /mail/:id/release"
+    ///
+    /// The mail is delivered with its envelope and its raw content, without TLS
+    #[structopt(long)]
+    release_to: Option<String>,
+
+    /// Credentials given to the "--release-to" server with AUTH PLAIN, as
+    /// "user:password"
+    #[structopt(long, requires = "release-to")]
+    release_auth: Option<Credentials>,
+
+    /// Keep the mails in this journal file
+    ///
+    /// Each accepted mail is written in it before being acknowledged, and the
+    /// mails are restored from it at the next start
+    #[structopt(long, parse(from_os_str))]
+    journal: Option<PathBuf>,
+
+    /// Append the raw content of every new mail to this file
+    ///
+    /// The file is kept even if the mails are removed, and is independent of the
+    /// journal. The mails are in the format of "--mail-file-format"
+    #[structopt(long, parse(from_os_str))]
+    mail_file: Option<PathBuf>,
+
+    /// Format of the mail file: mbox, or records where each mail is preceded
+    /// by a line with its length in bytes
+    #[structopt(long, default_value = "mbox")]
+    mail_file_format: FileFormat,
+
+    /// Send a syslog line (RFC 5424) for every new mail to this collector
+    ///
+    /// The collector is `udp://host:port`, `tcp://host:port` or `unix:/path`
+    /// like `unix:/dev/log`, the port being 514 by default. The
+    /// envelope of the mail is in the structured data of the line
+    #[structopt(long)]
+    syslog: Option<SyslogTarget>,
+
+    /// Export the traces of the SMTP sessions and the HTTP requests to this
+    /// OpenTelemetry collector, like `http://localhost:4318`
+    ///
+    /// The spans are sent with OTLP/HTTP in JSON. A request giving a
+    /// `traceparent` header is traced in the trace of its caller
+    #[structopt(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Name of the service in the exported traces
+    #[structopt(long, default_value = "mailcatcher")]
+    otlp_service_name: String,
+
+    /// Compact the journal after this number of removals
+    ///
+    /// The journal is rewritten with only the mails that are kept, so it does
+    /// not grow indefinitely. 0 disables the automatic compaction, that can still
+    /// be requested with "POST /api/maintenance/compact"
+    #[structopt(long, default_value = "1000")]
+    journal_compact_after: usize,
+
+    /// Time to wait for the mail storage to reply, in milliseconds
+    ///
+    /// The HTTP requests fail with a 503 status when it is exceeded
+    #[structopt(long, default_value = "5000")]
+    broker_timeout: u64,
+
+    /// Only keep the headers of the mails larger than this size, in bytes
+    ///
+    /// The body is discarded at the reception, the envelope and the headers are
+    /// kept. Useful for load testing, where the content does not matter but the
+    /// memory does. 0 discards the body of every mail
+    #[structopt(long)]
+    headers_only: Option<usize>,
+
+    /// Only accept the clients from this range of addresses, like "192.168.0.0/16"
+    ///
+    /// Can be repeated. It applies to both the SMTP and HTTP sides, any client
+    /// is accepted if not specified
+    #[structopt(long, number_of_values = 1)]
+    allow_cidr: Vec<Cidr>,
+
+    /// Refuse the clients from this range of addresses, even if allowed
+    ///
+    /// Can be repeated. It applies to both the SMTP and HTTP sides
+    #[structopt(long, number_of_values = 1)]
+    deny_cidr: Vec<Cidr>,
+
+    /// Accept or reject the recipients matching a pattern, like
+    /// "reject:*@bounce.test", the rejected ones getting a 550 reply
+    ///
+    /// Can be repeated, the first rule matching a recipient deciding. The glob
+    /// patterns must match the whole address, the regular expressions given
+    /// between slashes a part of it. Can be changed while running with
+    /// "/api/config"
+    #[structopt(long, number_of_values = 1)]
+    recipient_rule: Vec<RecipientRule>,
+
+    /// Tag the mails having a header starting with a prefix, like
+    /// "staging=Subject:[staging]" or "eu=X-Region:eu-"
+    ///
+    /// Can be repeated, every rule matching a mail giving its tag. The headers
+    /// are decoded and compared without the case. The mails can be searched by
+    /// their tags with "tag:staging". Can be changed while running with
+    /// "/api/config"
+    #[structopt(long, number_of_values = 1)]
+    tag_rule: Vec<TagRule>,
+
+    /// Look for the credit card numbers and the national ids (US SSN, French
+    /// NIR) in the contents and the attachments of the mails
+    ///
+    /// The mails where some are found are marked, and can be searched with
+    /// "has:pii". The values are masked in the reports
+    #[structopt(long)]
+    pii_scan: bool,
+
+    /// Also look for this pattern, given as "name=regex", like
+    /// "employee=EMP-[0-9]{6}"
+    ///
+    /// Can be repeated, and enables the scanner without "--pii-scan"
+    #[structopt(long, number_of_values = 1)]
+    pii_pattern: Vec<Pattern>,
+
+    /// Verify the DKIM signatures of the mails with the public keys of the DNS,
+    /// the results being given with the details of the mails
+    ///
+    /// Without it, only the hash of the signed body is checked, unless the
+    /// public key is given with "--dkim-key"
+    #[structopt(long)]
+    verify_dkim: bool,
+
+    /// Public key of the DKIM signatures of a selector, given as
+    /// "selector._domainkey.example.com=file", the file having the DNS record
+    /// of the key or the key in PEM
+    ///
+    /// Can be repeated. Used before the DNS, to verify the signatures offline,
+    /// like in the tests
+    #[structopt(long, number_of_values = 1)]
+    dkim_key: Vec<DkimKey>,
+
+    /// Simulate the delivery of each mail to its recipients, the outcomes
+    /// drawn with these weights, like "delivered=90,deferred=8,bounced=2"
+    ///
+    /// The outcomes are given with the details of the mails, and counted by
+    /// domain by /api/deliveries, to feed the dashboards of the application
+    #[structopt(long)]
+    simulate_delivery: Option<Weights>,
+
+    /// Disconnect the SMTP clients that stay silent for this time, in seconds
+    ///
+    /// They are answered with a 421 reply, even in the middle of a mail, that
+    /// is then discarded. 0 disables the timeout
+    #[structopt(long, default_value = "300")]
+    smtp_idle_timeout: u64,
+
+    /// Disconnect the SMTP clients connected for this time, in seconds, even
+    /// if they are active
+    ///
+    /// They are answered with a 421 reply. 0 disables the limit
+    #[structopt(long, default_value = "0")]
+    smtp_session_timeout: u64,
+
+    /// Largest number of SMTP sessions at the same time
+    ///
+    /// The next clients are answered with a 421 reply, and disconnected
+    #[structopt(long)]
+    smtp_max_connections: Option<usize>,
+
+    /// Largest number of SMTP connections from an address, like "60/min"
+    ///
+    /// The period is "s", "min" or "h". The next clients are answered with a
+    /// 421 reply, and disconnected
+    #[structopt(long)]
+    smtp_rate: Option<Rate>,
+
+    /// Rate of the mails above which only their envelope is kept, like
+    /// "100/s"
+    ///
+    /// The content of the next mails is counted and discarded, so a load test
+    /// can use the catcher as a sink. The counters are given by /api/capture
+    #[structopt(long)]
+    envelope_only_above: Option<Rate>,
+
+    /// Share of the SMTP mails kept, like "1/100" for every hundredth mail
+    ///
+    /// The other mails are acknowledged and discarded, all of them being
+    /// counted, for a soak test to get representative mails and accurate
+    /// counts. The counters are given by /api/capture
+    #[structopt(long)]
+    sample: Option<Sample>,
+
+    /// Delay before each SMTP reply, in milliseconds, to test the timeouts of
+    /// the clients
+    ///
+    /// Can be changed while running with "/api/smtp/latency"
+    #[structopt(long, default_value = "0")]
+    smtp_latency: u64,
+
+    /// Largest random delay added to "--smtp-latency", in milliseconds
+    #[structopt(long, default_value = "0")]
+    smtp_latency_jitter: u64,
+
+    /// Delay before the SMTP greeting, in milliseconds, like the servers
+    /// checking that the clients wait for it
+    #[structopt(long)]
+    banner_delay: Option<u64>,
+
+    /// Refuse the SMTP clients talking during "--banner-delay", before the
+    /// greeting, with a 554 reply instead of it, like an anti-spam check does
+    #[structopt(long, requires = "banner-delay")]
+    reject_early_talkers: bool,
+
+    /// Longest wait for the SMTP transactions in progress on SIGINT or SIGTERM,
+    /// in seconds, before exiting
+    #[structopt(long, default_value = "5")]
+    shutdown_grace: u64,
+
+    /// Exit when there is no SMTP or HTTP activity for this time, like `10m`,
+    /// `30s` or `1h`, the mails being flushed to the journal first
+    ///
+    /// For the catchers started by a CI job not to linger when it is cancelled.
+    #[structopt(long)]
+    idle_timeout: Option<Age>,
+
+    /// Exit when the process with this id exits, like the test process that
+    /// started the catcher, the mails being flushed to the journal first
+    ///
+    /// Only on unix, "--exit-on-stdin-close" works everywhere.
+    #[structopt(long)]
+    parent_pid: Option<u32>,
+
+    /// Exit when the standard input is closed, as it is when the process that
+    /// started the catcher with a pipe to it exits, even if killed
+    #[structopt(long)]
+    exit_on_stdin_close: bool,
+
+    /// Largest number of recipients of a mail, the next ones being refused with
+    /// a 452 reply, 0 to accept them all
+    #[structopt(long, default_value = "100")]
+    max_recipients: usize,
+
+    /// Largest number of header fields of a mail, the mails with more being
+    /// refused with a 552 reply, 0 for no limit
+    #[structopt(long, default_value = "1000")]
+    max_headers: usize,
+
+    /// Largest size of a header field of a mail, with its folded lines, in
+    /// bytes, the mails with a larger one being refused with a 552 reply, 0 for
+    /// no limit
+    #[structopt(long, default_value = "65536")]
+    max_header_length: usize,
+
+    /// Largest size of the header section of a mail, in bytes, the mails with
+    /// a larger one being refused with a 552 reply, 0 for no limit
+    #[structopt(long, default_value = "1048576")]
+    max_header_bytes: usize,
+
+    /// Reject the lines ended by a bare LF instead of CRLF, as RFC 5321 asks:
+    /// the commands with a 500 reply, and the mails with such lines once
+    /// received
+    ///
+    /// Otherwise, the bare LF are accepted, and normalized to CRLF in the
+    /// mails, with a diagnostic
+    #[structopt(long)]
+    strict_crlf: bool,
+
+    /// Greylist the recipients: the first attempt to deliver to a recipient,
+    /// from a client and an expeditor, is deferred with a 450 reply, and the
+    /// attempts coming this number of seconds later are accepted, 0 for the
+    /// first retry
+    ///
+    /// The attempts are listed by "/api/greylist"
+    #[structopt(long)]
+    greylist: Option<u64>,
+
+    /// Largest number of received mails waiting for the mail broker, 0 for no
+    /// limit
+    ///
+    /// The depth of the queue is given by "/api/stats/queues"
+    #[structopt(long, default_value = "1000")]
+    queue_size: usize,
+
+    /// What happens to a new mail when the queue is full: "block" the session
+    /// until there is room, "shed" the mail with a 452 reply, or "drop-oldest"
+    /// mail waiting
+    #[structopt(long, default_value = "block")]
+    queue_overflow: Overflow,
+
+    /// Number of shards of the mail broker, each one keeping a part of the
+    /// mails in its own task, to spread a high rate of mails over the cores
+    #[structopt(long, default_value = "1")]
+    broker_shards: usize,
+
+    /// Token to give to use the management API ("/api/config")
+    ///
+    /// It is sent in the "Authorization: Bearer <token>" header. Without it, the
+    /// settings cannot be changed at runtime
+    #[structopt(long)]
+    api_token: Option<String>,
+
+    /// Headless browser used to render the mails in images, by
+    /// "/mail/:id/render.png"
+    ///
+    /// A command accepting the options of Chromium, with its own arguments
+    /// separated by spaces, like "chromium --no-sandbox"
+    #[cfg(feature = "render")]
+    #[structopt(long, default_value = "chromium")]
+    render_browser: String,
+
+    /// Save the settings changed at runtime in this file, in JSON
+    ///
+    /// They are applied at the next start, over the command line options. On
+    /// SIGHUP, the file is read again and the listeners are rebound if their
+    /// ports changed, without stopping the sessions in progress
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Bundle of options for a use of the catcher: "ci", "dev" or "demo"
+    ///
+    /// "ci" listens on ephemeral ports, announced in JSON on the standard
+    /// output; "dev" tries the next ports when busy and opens the browser;
+    /// "demo" also gives sequential ids and marks the personal data. The
+    /// options given on the command line win over those of the profile
+    #[structopt(long)]
+    profile: Option<Preset>,
+
+    /// Format of the results printed by the commands: text or json
+    ///
+    /// In json, the server also announces the addresses it listens on, in a
+    /// line on the standard output
+    #[structopt(long, global = true, default_value = "text")]
+    output: Output,
+
+    /// Command run instead of starting a new instance
+    #[structopt(subcommand)]
+    cmd: Option<Cmd>,
 }
 
-fn main() -> Result<()> {
+/// Commands run instead of starting a new instance, most of them using a
+/// running one
+#[derive(Debug, StructOpt)]
+enum Cmd {
+    /// Inject mail files in the instance listening on the HTTP port
+    ///
+    /// Each file is the raw content of a mail, like a ".eml" file. The envelope
+    /// is taken from its From, To, Cc and Bcc headers
+    Inject {
+        /// Files to inject, the glob patterns like "corpus/*.eml" are expanded
+        #[structopt(required = true)]
+        files: Vec<String>,
+    },
+    /// Write the mails of the instance listening on the HTTP port in a tar
+    /// archive of test fixtures
+    ///
+    /// The archive holds the raw mails in ".eml" files, with a "manifest.json"
+    /// of their envelopes. Every mail is exported if no id is given
+    Export {
+        /// Ids of the mails, their ULID or their sequential number
+        ids: Vec<String>,
+        /// Tags of the archive, given in its manifest
+        #[structopt(long = "tag", number_of_values = 1)]
+        tags: Vec<String>,
+        /// Archive to write
+        #[structopt(long, parse(from_os_str))]
+        out: PathBuf,
+    },
+    /// Print the statistics of the instance listening on the HTTP port
+    Stats,
+    /// Check that an instance is listening on the HTTP port
+    ///
+    /// The exit code is not 0 if no instance replied, so a script can wait for
+    /// it to be ready
+    Check,
+    /// Write the decoded attachments of the mails in a directory
+    ///
+    /// The mails are read from the journal if "--journal" is given, or else from
+    /// the instance listening on the HTTP port. With "--all", the attachments of
+    /// each mail are written in a sub-directory named after its id
+    Extract {
+        /// Id of the mail
+        #[structopt(long, required_unless = "all")]
+        id: Option<String>,
+        /// Extract the attachments of all the mails
+        #[structopt(long, conflicts_with = "id")]
+        all: bool,
+        /// Directory where to write the attachments, created if needed
+        #[structopt(long, parse(from_os_str))]
+        out: PathBuf,
+    },
+    /// Remove the old mails, or those of an expeditor, like in a cron job
+    ///
+    /// The mails are removed from the journal if "--journal" is given, that is
+    /// compacted then, or else from the instance listening on the HTTP port,
+    /// where the locked mails are kept. The criteria must all match
+    Purge {
+        /// Age of the mails to remove, from their reception, like "7d", "12h"
+        /// or "30m"
+        #[structopt(long, required_unless = "from")]
+        older_than: Option<Age>,
+        /// Address of the expeditor of the mails to remove, "*" matching any
+        /// characters, like "*@spam.test"
+        #[structopt(long)]
+        from: Option<String>,
+    },
+    /// Check the conformance of the SMTP server, with client sessions against a
+    /// listener of its own
+    ///
+    /// The sessions are valid, malformed, pipelined, with an oversized address
+    /// and with 8-bit content. The exit code is not 0 if a check failed
+    Selftest,
+    /// Write randomized mails, valid per the RFCs, in a directory, for the
+    /// property-based tests of the mail consumers
+    ///
+    /// Each mail picks its encodings, nesting and attachments at random within
+    /// the given features. The same seed gives the same mails, the seed used is
+    /// printed
+    Fixtures {
+        /// Number of mails
+        #[structopt(long, default_value = "10")]
+        count: usize,
+        /// Seed of the random generator, a random one if not given
+        #[structopt(long)]
+        seed: Option<u64>,
+        /// Transfer encodings of the text parts, among "7bit",
+        /// "quoted-printable" and "base64"
+        #[structopt(
+            long,
+            use_delimiter = true,
+            default_value = "7bit,quoted-printable,base64"
+        )]
+        encodings: Vec<Encoding>,
+        /// Fold the long header fields over several lines
+        #[structopt(long)]
+        fold: bool,
+        /// Largest nesting depth of the multipart entities, 0 for single part
+        /// mails
+        #[structopt(long, default_value = "2")]
+        depth: usize,
+        /// Largest number of attachments of a multipart entity
+        #[structopt(long, default_value = "2")]
+        attachments: usize,
+        /// Largest size of an attachment, in bytes
+        #[structopt(long, default_value = "4096")]
+        attachment_size: usize,
+        /// Directory where to write the mails, created if needed
+        #[structopt(long, parse(from_os_str))]
+        out: PathBuf,
+    },
+}
+
+fn main() {
     // Initialize the log crate/macros based on RUST_LOG env value
     env_logger::init();
 
-    let opt: Opt = Opt::from_args();
+    // The options of the profile are layered under the given ones
+    let opt: Opt = Opt::from_iter(preset::layered(Opt::clap(), env::args_os().collect()));
+    if let Some(preset) = opt.profile {
+        log::info!(
+            "Options of the {:?} profile layered under the given ones",
+            preset
+        );
+    }
     log::debug!("Options: {:?}", opt);
 
     // Start the program, that is async, so block waiting it's end
-    task::block_on(main_fut(opt))
+    let result: Result<()> = match opt.cmd {
+        Some(Cmd::Inject { ref files }) => {
+            task::block_on(inject::inject(opt.http, files, opt.output))
+        }
+        Some(Cmd::Export {
+            ref ids,
+            ref tags,
+            ref out,
+        }) => task::block_on(export::export(opt.http, ids, tags, out, opt.output)),
+        Some(Cmd::Stats) => task::block_on(stats::stats(opt.http, opt.output)),
+        Some(Cmd::Check) => task::block_on(check::check(opt.http, opt.output)),
+        Some(Cmd::Extract {
+            ref id,
+            all,
+            ref out,
+        }) => task::block_on(extract::extract(
+            opt.http,
+            opt.journal.as_deref(),
+            id.as_deref().filter(|_| !all),
+            out,
+            opt.output,
+        )),
+        Some(Cmd::Purge {
+            older_than,
+            ref from,
+        }) => task::block_on(purge::purge(
+            opt.http,
+            opt.journal.as_deref(),
+            older_than,
+            from.as_deref(),
+            opt.output,
+        )),
+        Some(Cmd::Selftest) => task::block_on(selftest::selftest(opt.output)),
+        Some(Cmd::Fixtures {
+            count,
+            seed,
+            ref encodings,
+            fold,
+            depth,
+            attachments,
+            attachment_size,
+            ref out,
+        }) => {
+            let features: Features = Features {
+                encodings: encodings.clone(),
+                fold,
+                depth,
+                attachments,
+                attachment_size,
+            };
+            task::block_on(fixture::fixtures(&features, count, seed, out, opt.output))
+        }
+        None => task::block_on(main_fut(opt)),
+    };
+    if let Err(e) = result {
+        log::error!("{}", e);
+        // The exit code tells which part failed
+        #[allow(clippy::exit)]
+        std::process::exit(e.exit_code());
+    }
+}
+
+impl Opt {
+    /// First SMTP listener, whose port is in the settings
+    fn smtp_main(&self) -> ListenerSpec {
+        self.smtp.first().copied().unwrap_or_default()
+    }
+
+    /// Additional SMTP listeners, on their own port with their own profile
+    fn smtp_extra(&self) -> &[ListenerSpec] {
+        self.smtp.get(1..).unwrap_or_default()
+    }
+
+    /// IP versions of the addresses to listen on
+    const fn family(&self) -> AddressFamily {
+        if self.ipv4_only {
+            AddressFamily::V4
+        } else if self.ipv6_only {
+            AddressFamily::V6
+        } else {
+            AddressFamily::Any
+        }
+    }
+
+    /// Queue of the mails received, waiting for the mail broker
+    fn queue(&self) -> (Channel<Mail>, Queue) {
+        let (sender, receiver): Channel<Mail> = if self.queue_size > 0 {
+            channel::bounded(self.queue_size)
+        } else {
+            channel::unbounded()
+        };
+        let queue: Queue = Queue::new(receiver.clone(), self.queue_overflow);
+        ((sender, receiver), queue)
+    }
+
+    /// Start the mail broker, split in shards if more than one is asked
+    fn broker(
+        &self,
+        receiver: Receiver<MailEvt>,
+        journal: Option<Journal>,
+    ) -> task::JoinHandle<Result<()>> {
+        if self.broker_shards > 1 {
+            let stores: Vec<Box<dyn MailStore>> = (0..self.broker_shards)
+                .map(|_| -> Box<dyn MailStore> { Box::new(MemoryStore::default()) })
+                .collect();
+            task::spawn(ShardedTank::new(receiver, stores, journal).process())
+        } else {
+            task::spawn(
+                MailTank::new(receiver, Box::new(MemoryStore::default()), journal).process(),
+            )
+        }
+    }
+
+    /// SMTP server the mails are released to, with its credentials
+    fn release(&self) -> Option<Target> {
+        self.release_to.clone().map(|server| Target {
+            server,
+            credentials: self.release_auth.clone(),
+        })
+    }
+
+    /// Handling of the lines ended by a bare LF, rejected with `--strict-crlf`
+    const fn line_endings(&self) -> LineEndings {
+        if self.strict_crlf {
+            LineEndings::Strict
+        } else {
+            LineEndings::Lenient
+        }
+    }
+
+    /// Delay of the SMTP replies, from `--smtp-latency` and its jitter
+    fn smtp_latency(&self) -> Latency {
+        Latency::new(Delay {
+            delay_ms: self.smtp_latency,
+            jitter_ms: self.smtp_latency_jitter,
+        })
+    }
+
+    /// Limits of the header section of the mails, 0 disabling a limit
+    fn header_limits(&self) -> HeaderLimits {
+        let limit = |max: usize| Some(max).filter(|&max| max > 0);
+        HeaderLimits {
+            count: limit(self.max_headers),
+            length: limit(self.max_header_length),
+            bytes: limit(self.max_header_bytes),
+        }
+    }
+
+    /// Optional features enabled, the mails being kept on the disk if
+    /// `persistent`
+    const fn capabilities(&self, persistent: bool) -> Capabilities {
+        Capabilities {
+            persistence: persistent,
+            relay: self.smtp_upstream.is_some(),
+            auth: self.smtp_auth.is_some(),
+        }
+    }
+
+    /// Parameters of the SMTP side, sending the new mails to `mails_broker`,
+    /// with the runtime controls it shares with the `http` side
+    fn smtp_params(
+        &self,
+        http: &Params,
+        mails_broker: Sender<Mail>,
+        tracer: Option<Tracer>,
+        journal: Option<Journal>,
+        settings: SharedSettings,
+    ) -> smtp::Params {
+        smtp::Params {
+            server_name: self.smtp_name.clone(),
+            mails_broker,
+            use_starttls: self.use_starttls,
+            upstream: self.smtp_upstream.clone(),
+            credentials: self.smtp_auth.clone(),
+            tracer,
+            journal,
+            settings,
+            pause: http.smtp_pause.clone(),
+            latency: http.smtp_latency.clone(),
+            greylist: http.greylist.clone(),
+            metrics: http.smtp_metrics.clone(),
+            quarantine: http.quarantine.clone(),
+            capture: http.capture.clone(),
+            activity: http.activity.clone(),
+            idle_timeout: timeout(self.smtp_idle_timeout),
+            session_timeout: timeout(self.smtp_session_timeout),
+            limits: Limits::new(self.smtp_max_connections, self.smtp_rate),
+            max_recipients: Some(self.max_recipients).filter(|&max| max > 0),
+            header_limits: self.header_limits(),
+            banner_delay: self.banner_delay.map(Duration::from_millis),
+            reject_early_talkers: self.reject_early_talkers,
+            line_endings: self.line_endings(),
+            queue: http.queue.clone(),
+        }
+    }
+
+    /// Signals stopping the catcher, besides the termination ones: idle for
+    /// `--idle-timeout` from the `activity`, or orphaned
+    fn signals(&self, activity: Activity) -> Result<Signals> {
+        let mut signals: Signals = Signals::new()?;
+        if let Some(Age(timeout)) = self.idle_timeout {
+            signals.idle = shutdown::on_idle(activity, timeout)?;
+        }
+        signals.orphaned = shutdown::on_orphaned(self.parent_pid, self.exit_on_stdin_close)?;
+        Ok(signals)
+    }
+
+    /// Greylisting of the recipients, deferring none without `--greylist`
+    fn greylist(&self) -> Greylist {
+        self.greylist.map_or_else(Greylist::default, |delay| {
+            Greylist::new(Duration::from_secs(delay))
+        })
+    }
+}
+
+/// Listeners of both sides, bound at the start or handed over by the previous
+/// process
+struct Bindings {
+    /// SMTP listeners of the port from the settings
+    smtp: Bound,
+    /// Additional SMTP listeners, with their port and profile
+    smtp_extra: Vec<(ListenerSpec, Bound)>,
+    /// HTTP listeners
+    http: Bound,
+    /// Addresses listened on, given by `/api/info`
+    listening: Listening,
+    /// The listeners were handed over by the previous process
+    handed_over: bool,
+}
+
+/// Bind the SMTP and HTTP ports of the settings, and the additional SMTP ports,
+/// on the addresses of the chosen IP versions
+///
+/// With `--strict-bind`, an address that cannot be bound stops the startup. The
+/// listeners handed over by a previous process are used as they are.
+#[allow(clippy::print_stdout)]
+async fn bind_servers(opt: &Opt, settings: &Settings) -> Result<Bindings> {
+    let inherited: Option<(Vec<Bound>, Bound)> = handover::inherited()?;
+    let handed_over: bool = inherited.is_some();
+    let (smtp, smtp_extra, http): (Bound, Vec<(ListenerSpec, Bound)>, Bound) =
+        if let Some((mut smtp, http)) = inherited {
+            log::info!("Listeners handed over by the previous process");
+            let extra: Vec<Bound> = smtp.split_off(1);
+            match smtp.pop() {
+                Some(smtp) if extra.len() == opt.smtp_extra().len() => (
+                    smtp,
+                    opt.smtp_extra().iter().copied().zip(extra).collect(),
+                    http,
+                ),
+                _ => {
+                    return Err(MailcatcherError::config(
+                        "The SMTP ports handed over do not match the --smtp options",
+                    ))
+                }
+            }
+        } else {
+            let family: AddressFamily = opt.family();
+            let smtp: Bound =
+                bind_port("SMTP", settings.smtp_port, family, opt.port_fallback).await?;
+            let mut extra: Vec<(ListenerSpec, Bound)> = Vec::new();
+            for &spec in opt.smtp_extra() {
+                let bound: Bound = bind_port("SMTP", spec.port, family, opt.port_fallback).await?;
+                extra.push((spec, bound));
+            }
+            let http: Bound =
+                bind_port("HTTP", settings.http_port, family, opt.port_fallback).await?;
+            (smtp, extra, http)
+        };
+    let listening: Listening = Listening {
+        smtp_profile: opt.smtp_main().profile,
+        smtp_extra: smtp_extra
+            .iter()
+            .map(|&(spec, ref bound)| ProfiledListening::new(spec.profile, bound))
+            .collect(),
+        ..Listening::new(&smtp, &http)
+    };
+    if opt.strict_bind {
+        let failures: Vec<String> = listening
+            .smtp_failures
+            .iter()
+            .chain(
+                listening
+                    .smtp_extra
+                    .iter()
+                    .flat_map(|extra| &extra.failures),
+            )
+            .chain(&listening.http_failures)
+            .map(ToString::to_string)
+            .collect();
+        if !failures.is_empty() {
+            return Err(MailcatcherError::config(format!(
+                "Addresses not bound: {}",
+                failures.join(", ")
+            )));
+        }
+    }
+
+    log::info!(
+        "Listening for SMTP on {:?} and for HTTP on {:?}",
+        listening.smtp,
+        listening.http
+    );
+    if opt.output == Output::Json {
+        // For the scripts to find the ports, the ephemeral ones included
+        println!(
+            "{}",
+            json!({
+                "smtp": listening.smtp,
+                "smtp_extra": listening
+                    .smtp_extra
+                    .iter()
+                    .map(|extra| &extra.addresses)
+                    .collect::<Vec<&Vec<SocketAddr>>>(),
+                "http": listening.http,
+            })
+        );
+    }
+    Ok(Bindings {
+        smtp,
+        smtp_extra,
+        http,
+        listening,
+        handed_over,
+    })
+}
+
+/// Open the journal if the mails are persisted, and send the mails kept in it to
+/// the broker
+///
+/// When the listeners were `handed_over`, the journal is opened once the previous
+/// process released it, so the mails it accepted until it stopped are restored.
+/// The DKIM signatures are not verified again, their reports being restored.
+async fn restore_journal(
+    opt: &Opt,
+    handed_over: bool,
+    scanner: Option<&Scanner>,
+    broker: &Sender<MailEvt>,
+) -> Result<Option<Journal>> {
+    let journal: Journal = match opt.journal {
+        Some(ref path) if handed_over => {
+            Journal::open_released(path, opt.journal_compact_after).await?
+        }
+        Some(ref path) => Journal::open(path, opt.journal_compact_after).await?,
+        None => return Ok(None),
+    };
+    for mut mail in journal.replay().await? {
+        if let Some(scanner) = scanner {
+            mail.set_pii(scanner.scan(&mail));
+        }
+        broker.send(MailEvt::NewMail(Box::new(mail))).await?;
+    }
+
+    Ok(Some(journal))
+}
+
+/// Sinks notified of the events in addition to the browsers
+async fn event_sinks(opt: &Opt) -> Result<Vec<Arc<dyn EventSink<SseEvt>>>> {
+    let mut sinks: Vec<Arc<dyn EventSink<SseEvt>>> = Vec::new();
+    if let Some(ref path) = opt.mail_file {
+        sinks.push(Arc::new(FileSink::open(path, opt.mail_file_format).await?));
+    }
+    if let Some(ref target) = opt.syslog {
+        sinks.push(Arc::new(
+            SyslogSink::connect(target.clone(), &opt.smtp_name).await?,
+        ));
+    }
+
+    Ok(sinks)
+}
+
+/// Settings that can be changed at runtime, as given on the command line
+fn cli_settings(opt: &Opt) -> Settings {
+    Settings {
+        smtp_port: opt.smtp_main().port,
+        http_port: opt.http,
+        timezone: opt.timezone,
+        broker_timeout: opt.broker_timeout,
+        headers_only: opt.headers_only,
+        allow_cidr: opt.allow_cidr.clone(),
+        deny_cidr: opt.deny_cidr.clone(),
+        replies: Replies::new(),
+        recipient_rules: opt.recipient_rule.clone(),
+        tag_rules: opt.tag_rule.clone(),
+    }
+}
+
+/// Settings that can be changed at runtime, from the command line, overridden
+/// by the configuration file if any
+async fn shared_settings(opt: &Opt) -> Result<SharedSettings> {
+    let settings: Settings = cli_settings(opt);
+    match opt.config {
+        Some(ref path) => SharedSettings::load(settings, path).await,
+        None => Ok(SharedSettings::new(settings)),
+    }
+}
+
+/// Timeout given in seconds on the command line, 0 meaning no timeout
+fn timeout(secs: u64) -> Option<Duration> {
+    Some(secs).filter(|&secs| secs > 0).map(Duration::from_secs)
+}
+
+/// Exporter of the traces, if a collector is given
+fn tracer(opt: &Opt) -> Result<Option<Tracer>> {
+    opt.otlp_endpoint
+        .as_ref()
+        .map(|endpoint| Tracer::start(endpoint, &opt.otlp_service_name))
+        .transpose()
+}
+
+/// Processing of the new mails received by the SMTP side, before they reach the
+/// mail broker
+struct Marking {
+    /// Personal data to look for in the mails
+    scanner: Option<Scanner>,
+    /// Verifier of the DKIM signatures
+    verifier: Verifier,
+    /// Journal recording the DKIM reports, if the mails are persisted
+    journal: Option<Journal>,
+    /// Settings, giving the longest wait of the DNS lookups
+    settings: SharedSettings,
+    /// Simulator of the deliveries to the recipients
+    simulator: Option<Simulator>,
+}
+
+impl Marking {
+    /// Look for the personal data of a new mail, verify its DKIM signatures and
+    /// simulate its deliveries
+    async fn mark(&self, mail: &mut Mail) {
+        if let Some(ref scanner) = self.scanner {
+            let scanning: Instant = Instant::now();
+            scanner.mark(mail);
+            mail.timing_mut().since(Stage::Scan, scanning);
+        }
+        self.verifier
+            .mark(mail, self.settings.get().await.broker_delay())
+            .await;
+        if let Some(ref journal) = self.journal {
+            if let Err(e) = journal.verified(mail).await {
+                log::error!(
+                    "DKIM reports of the mail {} not written in the journal: {}",
+                    mail.get_id(),
+                    e
+                );
+            }
+        }
+        if let Some(ref simulator) = self.simulator {
+            simulator.mark(mail);
+        }
+    }
+}
+
+/// Spawn the task sending the mails received by the SMTP side to the mail broker,
+/// then to the HTTP side, once they are marked
+///
+/// The mails are marked concurrently, so a slow lookup of a DKIM key does not
+/// hold the next mails back. The task ends once the channel of the SMTP side is
+/// closed, and the mails it had are all sent.
+fn spawn_mail_notifier(
+    rx_mail_from_smtp: Receiver<Mail>,
+    marking: Marking,
+    tx_http_new_mail: Sender<MailEvt>,
+    tx_new_mail: Sender<Mail>,
+) -> Result<task::JoinHandle<()>> {
+    spawn_task_and_swallow_log_errors("Task: Mail notifier".into(), async move {
+        let (marking, tx_http_new_mail, tx_new_mail) = (&marking, &tx_http_new_mail, &tx_new_mail);
+        // To do on each received new mail, until the SMTP side is gone
+        rx_mail_from_smtp
+            .map(Ok)
+            .try_for_each_concurrent(None, |mut mail: Mail| async move {
+                log::info!("Received new mail: {:?}", mail);
+                marking.mark(&mut mail).await;
+                mail.timing_mut().enqueue();
+                // Notify javascript side by SSE
+                match tx_http_new_mail
+                    .send(MailEvt::NewMail(Box::new(mail.clone())))
+                    .await
+                {
+                    Ok(()) => {
+                        tx_new_mail.send(mail).await?;
+                        log::trace!("Mail stored successfully")
+                    }
+                    Err(e) => log::error!("Mail stored error: {:?}", e),
+                }
+                Ok(())
+            })
+            .await
+    })
+    .map_err(MailcatcherError::broker)
 }
 
 /// async main
 async fn main_fut(opt: Opt) -> Result<()> {
     log::info!(
         "Starting MailCatcher on port smtp({}) and http({})",
-        opt.smtp,
+        opt.smtp_main().port,
         opt.http
     );
 
+    // Settings that can be changed at runtime
+    let settings: SharedSettings = shared_settings(&opt).await?;
+
+    let Bindings {
+        smtp: smtp_bound,
+        smtp_extra,
+        http: http_bound,
+        listening,
+        handed_over,
+    } = bind_servers(&opt, &settings.get().await).await?;
+    let http_port: u16 = listening.http.first().map_or(opt.http, SocketAddr::port);
+    let listening: Arc<RwLock<Listening>> = Arc::new(RwLock::new(listening));
+
     // Channels used to notify a new mail arrived in SMTP side to HTTP side
-    let (tx_mail_from_smtp, mut rx_mail_from_smtp): Channel<Mail> = channel::unbounded();
+    let ((tx_mail_from_smtp, rx_mail_from_smtp), queue): (Channel<Mail>, Queue) = opt.queue();
     let (tx_mail_broker, rx_mail_broker): Channel<MailEvt> = channel::unbounded();
+    // Kept to flush the mail broker on shutdown
+    let received: Sender<Mail> = tx_mail_from_smtp.clone();
+    let tx_flush: Sender<MailEvt> = tx_mail_broker.clone();
+
+    // Personal data to look for in the new mails
+    let scanner: Option<Scanner> = Scanner::new(opt.pii_scan, opt.pii_pattern.clone());
+
+    // Verifier of the DKIM signatures of the new mails
+    let verifier: Verifier = Verifier::new(opt.dkim_key.clone(), opt.verify_dkim)
+        .await
+        .map_err(MailcatcherError::config)?;
+
+    // Restore the mails kept in the journal
+    let journal: Option<Journal> =
+        restore_journal(&opt, handed_over, scanner.as_ref(), &tx_mail_broker).await?;
 
-    let mail_broker = MailTank::new(rx_mail_broker);
+    // The mail broker only ends on a failure, it goes on during the shutdown
+    let broker: task::JoinHandle<Result<()>> = opt.broker(rx_mail_broker, journal.clone());
+
+    // Exporter of the spans of the SMTP sessions and the HTTP requests
+    let tracer: Option<Tracer> = tracer(&opt)?;
 
     let (tx_new_mail, rx_new_mail): Channel<Mail> = channel::unbounded();
     let tx_http_new_mail: Sender<MailEvt> = tx_mail_broker.clone();
     let http_params: Params = Params {
         mail_broker: tx_mail_broker,
         rx_mails: rx_new_mail,
-        #[cfg(feature = "faking")]
+        settings: settings.clone(),
+        api_token: opt.api_token.clone(),
+        #[cfg(feature = "render")]
+        render_browser: opt.render_browser.clone(),
+        event_sinks: event_sinks(&opt).await?,
         tx_new_mail: tx_mail_from_smtp.clone(),
+        journal: journal.clone(),
+        listening: Arc::clone(&listening),
+        tracer: tracer.clone(),
+        smtp_pause: smtp::Pause::default(),
+        smtp_latency: opt.smtp_latency(),
+        greylist: opt.greylist(),
+        smtp_metrics: Metrics::default(),
+        quarantine: Quarantine::default(),
+        capture: Capture::new(opt.envelope_only_above, opt.sample),
+        capabilities: opt.capabilities(journal.is_some()),
+        theme: opt.theme.clone(),
+        ids: opt.id_strategy,
+        release: opt.release(),
+        queue: queue.clone(),
+        help: Help::new(&Opt::clap()),
+        activity: Activity::default(),
     };
-    let _mail_notifier_task =
-        spawn_task_and_swallow_log_errors("Task: Mail notifier".into(), async move {
-            loop {
-                // To do on each received new mail
-                if let Some(mail) = rx_mail_from_smtp.next().await {
-                    log::info!("Received new mail: {:?}", mail);
-                    // Notify javascript side by SSE
-                    match tx_http_new_mail.send(MailEvt::NewMail(mail.clone())).await {
-                        Ok(()) => {
-                            tx_new_mail.send(mail).await?;
-                            log::trace!("Mail stored successfully")
-                        }
-                        Err(e) => log::error!("Mail stored error: {:?}", e),
-                    }
-                }
-            }
-        })?;
+    let marking: Marking = Marking {
+        scanner,
+        verifier,
+        journal: journal.clone(),
+        settings: settings.clone(),
+        simulator: opt.simulate_delivery.map(Simulator::new),
+    };
+    let notifier: task::JoinHandle<()> =
+        spawn_mail_notifier(rx_mail_from_smtp, marking, tx_http_new_mail, tx_new_mail)?;
 
-    // Starting SMTP side
-    let s = smtp::serve(
-        opt.smtp,
-        &opt.smtp_name,
+    // SMTP side
+    let smtp_params: smtp::Params = opt.smtp_params(
+        &http_params,
         tx_mail_from_smtp,
-        opt.use_starttls,
+        tracer,
+        journal,
+        settings.clone(),
     );
-    // Starting HTTP side
-    let http_app: Server<State<SseEvt>> = http::init(http_params).await?;
+    // Kept to stop on idle
+    let activity: Activity = http_params.activity.clone();
+    // HTTP side
+    let (http_app, notifications): (Server<State<SseEvt>>, task::JoinHandle<()>) =
+        http::init(http_params).await?;
 
     // Open browser window at start if specified
     if opt.browser {
-        opener::open(format!("http://localhost:{}/", opt.http))?;
+        opener::open(format!("http://localhost:{}/", http_port)).map_err(MailcatcherError::http)?;
     }
 
-    // Waiting for both to complete
-    s.try_join(bind_http(http_app, opt.http))
-        .try_join(mail_broker.process())
+    // Starting both sides, and waiting for them to complete
+    let servers: Servers = Servers {
+        smtp: smtp_params,
+        profile: opt.smtp_main().profile,
+        http: http_app,
+        family: opt.family(),
+        fallback: opt.port_fallback,
+        settings,
+        listening,
+        grace: Duration::from_secs(opt.shutdown_grace),
+    };
+    let signals: Signals = opt.signals(activity)?;
+    servers
+        .run(smtp_bound, smtp_extra, http_bound, signals)
+        .race(broker)
         .await?;
+    shutdown::flush(&received, notifier, notifications, &tx_flush).await?;
+    log::info!("MailCatcher stopped");
 
-    unreachable!()
+    Ok(())
 }
 
 #[cfg(test)]
@@ -212,6 +1354,9 @@ mod test {
     use log::{kv, Level, Record};
     use std::time::{Duration, SystemTime};
 
+    /// Result type of the tests, accepting any error
+    pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
     /// Initialize logging for the tests
     pub fn log_init() {
         use std::io::Write;
@@ -251,7 +1396,7 @@ mod test {
                     &mut self,
                     key: kv::Key<'kvs>,
                     val: kv::Value<'kvs>,
-                ) -> Result<(), kv::Error> {
+                ) -> std::result::Result<(), kv::Error> {
                     let string = &format!("\u{2716} {} \u{203a} {} ", style(key).magenta(), val);
                     self.string.push_str(string);
                     Ok(())
@@ -315,9 +1460,10 @@ mod test {
     }
 
     /// Timeout the tests
-    pub fn with_timeout<F, T>(millis: u64, f: F) -> Result<T, std::io::Error>
+    pub fn with_timeout<F, T, E>(millis: u64, f: F) -> std::io::Result<T>
     where
-        F: std::future::Future<Output = crate::Result<T>>,
+        F: std::future::Future<Output = std::result::Result<T, E>>,
+        E: std::fmt::Debug,
     {
         async_std::task::block_on(async_std::io::timeout(
             Duration::from_millis(millis),
@@ -0,0 +1,313 @@
+use std::time::Duration;
+
+use async_std::{
+    channel::{self, Receiver, Sender, TrySendError},
+    net::TcpStream,
+    task,
+};
+use chrono::{DateTime, Utc};
+use tide::{
+    http::{Method, Request, Url},
+    prelude::json,
+    Body,
+};
+use ulid::Ulid;
+
+use crate::{error::MailcatcherError, utils::spawn_task_and_swallow_log_errors};
+
+/// Number of finished spans waiting to be exported, the next ones being dropped
+const QUEUE_SIZE: usize = 1_024;
+
+/// Largest number of spans exported in a request
+const BATCH_SIZE: usize = 256;
+
+/// Delay between two exports, so the spans are sent in batches
+const EXPORT_DELAY: Duration = Duration::from_secs(1);
+
+/// OTLP span kind of the sessions and requests handled by the catcher
+const KIND_SERVER: u8 = 2;
+
+/// OTLP status code of a failed span
+const STATUS_ERROR: u8 = 2;
+
+/// Operation traced in a span, like a SMTP session or an HTTP request
+#[derive(Debug, Clone)]
+pub struct Span {
+    /// Name of the operation
+    name: String,
+    /// Id of the trace, in hexadecimal
+    trace_id: String,
+    /// Id of the span, in hexadecimal
+    id: String,
+    /// Id of the span of the caller, in hexadecimal, if it is in a trace
+    parent_id: Option<String>,
+    /// Beginning of the operation
+    start: DateTime<Utc>,
+    /// Attributes of the operation, like the address of the peer
+    attributes: Vec<(&'static str, serde_json::Value)>,
+    /// Reason of the failure of the operation
+    error: Option<String>,
+}
+
+impl Span {
+    /// Begin a span, in a new trace
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            trace_id: format!("{:032x}", u128::from(Ulid::new())),
+            id: span_id(),
+            parent_id: None,
+            start: Utc::now(),
+            attributes: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Continue the trace of a W3C `traceparent` header, like
+    /// `00-<trace id>-<parent span id>-<flags>`
+    ///
+    /// An invalid header is ignored, the span staying in its own trace.
+    pub fn with_parent(mut self, traceparent: &str) -> Self {
+        let fields: Vec<&str> = traceparent.trim().split('-').collect();
+        if let [version, trace_id, parent_id, _flags] = *fields.as_slice() {
+            let hex = |id: &str, len: usize| {
+                id.len() == len
+                    && id.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+                    && id.chars().any(|c| c != '0')
+            };
+            if version == "00" && hex(trace_id, 32) && hex(parent_id, 16) {
+                trace_id.clone_into(&mut self.trace_id);
+                self.parent_id = Some(parent_id.to_owned());
+            }
+        }
+        self
+    }
+
+    /// Set an attribute of the operation
+    pub fn attribute<V: Into<serde_json::Value>>(&mut self, key: &'static str, value: V) {
+        self.attributes.push((key, value.into()));
+    }
+
+    /// Mark the operation as failed
+    pub fn fail<E: ToString>(&mut self, reason: &E) {
+        self.error = Some(reason.to_string());
+    }
+
+    /// Finished span, in the OTLP JSON encoding
+    fn to_json(&self, end: DateTime<Utc>) -> serde_json::Value {
+        let mut span: serde_json::Value = json!({
+            "traceId": self.trace_id,
+            "spanId": self.id,
+            "name": self.name,
+            "kind": KIND_SERVER,
+            "startTimeUnixNano": self.start.timestamp_nanos().to_string(),
+            "endTimeUnixNano": end.timestamp_nanos().to_string(),
+            "attributes": self
+                .attributes
+                .iter()
+                .map(|&(key, ref value)| attribute(key, value))
+                .collect::<Vec<serde_json::Value>>(),
+        });
+        if let Some(fields) = span.as_object_mut() {
+            if let Some(ref parent_id) = self.parent_id {
+                let _ = fields.insert("parentSpanId".to_owned(), json!(parent_id));
+            }
+            if let Some(ref error) = self.error {
+                let _ = fields.insert(
+                    "status".to_owned(),
+                    json!({"code": STATUS_ERROR, "message": error}),
+                );
+            }
+        }
+        span
+    }
+}
+
+/// Exporter of the spans to an OTLP collector, like Jaeger or Tempo, with the
+/// OTLP/HTTP protocol in JSON
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    /// Finished spans, exported in the background
+    spans: Sender<serde_json::Value>,
+}
+
+impl Tracer {
+    /// Export the spans to the collector at `endpoint`, like
+    /// `http://localhost:4318`, as the service `service_name`
+    pub fn start(endpoint: &str, service_name: &str) -> crate::Result<Self> {
+        let url: Url = Url::parse(endpoint)
+            .and_then(|endpoint| endpoint.join("v1/traces"))
+            .map_err(|e| {
+                MailcatcherError::config(format!("Invalid OTLP endpoint {}: {}", endpoint, e))
+            })?;
+        if url.scheme() != "http" {
+            return Err(MailcatcherError::config(format!(
+                "The OTLP endpoint must use http: {}",
+                endpoint
+            )));
+        }
+
+        let (spans, rx_spans): crate::Channel<serde_json::Value> = channel::bounded(QUEUE_SIZE);
+        let service_name: String = service_name.to_owned();
+        let _export_task = spawn_task_and_swallow_log_errors(
+            "Task: OTLP exporter".into(),
+            export_loop(url, service_name, rx_spans),
+        )
+        .map_err(MailcatcherError::config)?;
+
+        Ok(Self { spans })
+    }
+
+    /// End a span, and queue it for the export
+    ///
+    /// The span is dropped if the collector does not keep up, so the traced
+    /// operations never wait for it.
+    pub fn end(&self, span: &Span) {
+        match self.spans.try_send(span.to_json(Utc::now())) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                log::warn!("Span {} dropped, too many waiting", span.name);
+            }
+            Err(TrySendError::Closed(_)) => log::debug!("Span {} dropped, no exporter", span.name),
+        }
+    }
+}
+
+/// Export the queued spans by batches, until the tracers are dropped
+async fn export_loop(
+    url: Url,
+    service_name: String,
+    rx_spans: Receiver<serde_json::Value>,
+) -> crate::Result<()> {
+    while let Ok(first) = rx_spans.recv().await {
+        task::sleep(EXPORT_DELAY).await;
+        let mut batch: Vec<serde_json::Value> = vec![first];
+        while batch.len() < BATCH_SIZE {
+            match rx_spans.try_recv() {
+                Ok(span) => batch.push(span),
+                Err(_) => break,
+            }
+        }
+
+        let count: usize = batch.len();
+        match export(&url, &service_name, batch).await {
+            Ok(()) => log::trace!("{} spans exported", count),
+            Err(e) => log::warn!("{} spans not exported to {}: {}", count, url, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a batch of spans to the collector
+async fn export(url: &Url, service_name: &str, spans: Vec<serde_json::Value>) -> crate::Result<()> {
+    let payload: serde_json::Value = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", &json!(service_name))],
+            },
+            "scopeSpans": [{
+                "scope": {"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")},
+                "spans": spans,
+            }],
+        }],
+    });
+    let mut request: Request = Request::new(Method::Post, url.clone());
+    request.set_body(Body::from_json(&payload).map_err(|e| MailcatcherError::http(e.to_string()))?);
+
+    let host: &str = url.host_str().unwrap_or("localhost");
+    let port: u16 = url.port_or_known_default().unwrap_or(80);
+    let stream: TcpStream = TcpStream::connect((host, port))
+        .await
+        .map_err(MailcatcherError::http)?;
+    let response = async_h1::connect(stream, request)
+        .await
+        .map_err(|e| MailcatcherError::http(e.to_string()))?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(MailcatcherError::http(format!(
+            "the collector replied {}",
+            response.status()
+        )))
+    }
+}
+
+/// Attribute in the OTLP JSON encoding, with the integers kept as such
+fn attribute(key: &str, value: &serde_json::Value) -> serde_json::Value {
+    let value: serde_json::Value = match *value {
+        serde_json::Value::Bool(value) => json!({ "boolValue": value }),
+        serde_json::Value::Number(ref number) if number.is_i64() || number.is_u64() => {
+            json!({"intValue": number.to_string()})
+        }
+        serde_json::Value::String(ref value) => json!({ "stringValue": value }),
+        serde_json::Value::Null
+        | serde_json::Value::Number(_)
+        | serde_json::Value::Array(_)
+        | serde_json::Value::Object(_) => json!({"stringValue": value.to_string()}),
+    };
+    json!({"key": key, "value": value})
+}
+
+/// New id of span, the random part of an ULID
+fn span_id() -> String {
+    let id: u128 = u128::from(Ulid::new());
+    format!("{:016x}", id & u128::from(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent() {
+        crate::test::log_init();
+
+        let span: Span = Span::new("test")
+            .with_parent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        assert_eq!(span.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span.parent_id, Some("00f067aa0ba902b7".to_owned()));
+
+        for &invalid in &[
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902-01",
+            "garbage",
+        ] {
+            let span: Span = Span::new("test").with_parent(invalid);
+            assert_eq!(span.trace_id.len(), 32);
+            assert_eq!(span.parent_id, None);
+        }
+    }
+
+    #[test]
+    fn span_json() {
+        crate::test::log_init();
+
+        let mut span: Span = Span::new("smtp session");
+        span.attribute("net.peer.ip", "127.0.0.1");
+        span.attribute("smtp.mails", 2_usize);
+        span.fail(&"connection reset");
+        let end: DateTime<Utc> = span.start + chrono::Duration::milliseconds(5);
+
+        assert_eq!(
+            span.to_json(end),
+            json!({
+                "traceId": span.trace_id,
+                "spanId": span.id,
+                "name": "smtp session",
+                "kind": 2,
+                "startTimeUnixNano": span.start.timestamp_nanos().to_string(),
+                "endTimeUnixNano": end.timestamp_nanos().to_string(),
+                "attributes": [
+                    {"key": "net.peer.ip", "value": {"stringValue": "127.0.0.1"}},
+                    {"key": "smtp.mails", "value": {"intValue": "2"}},
+                ],
+                "status": {"code": 2, "message": "connection reset"},
+            })
+        );
+        assert_eq!(span.id.len(), 16);
+    }
+}
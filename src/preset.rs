@@ -0,0 +1,165 @@
+use std::{ffi::OsString, str::FromStr};
+
+use structopt::clap::{App, ArgMatches};
+
+/// Bundle of options for a use of the catcher, given with `--profile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Continuous integration: ephemeral ports, announced in JSON on the
+    /// standard output for the test scripts to find them, and an exit after
+    /// 10 minutes without activity, for a cancelled job not to leave it behind
+    Ci,
+    /// Development computer: the next ports if busy, and the web interface
+    /// opened in the browser
+    Dev,
+    /// Demonstration: short sequential ids, the personal data marked, and the
+    /// web interface opened in the browser
+    Demo,
+}
+
+impl Preset {
+    /// Options of the preset, by their long name, with their value if they
+    /// take one
+    const fn options(self) -> &'static [(&'static str, Option<&'static str>)] {
+        match self {
+            Self::Ci => &[
+                ("smtp", Some("0")),
+                ("http", Some("0")),
+                ("output", Some("json")),
+                ("idle-timeout", Some("10m")),
+            ],
+            Self::Dev => &[("port-fallback", Some("10")), ("browser", None)],
+            Self::Demo => &[
+                ("port-fallback", Some("10")),
+                ("id-strategy", Some("sequential")),
+                ("pii-scan", None),
+                ("browser", None),
+            ],
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ci" => Ok(Self::Ci),
+            "dev" => Ok(Self::Dev),
+            "demo" => Ok(Self::Demo),
+            _ => Err(format!("Unknown profile {}, expected ci, dev or demo", s)),
+        }
+    }
+}
+
+/// Add the options of the preset given with `--profile` to the command line
+/// `args`, as defaults layered under those given: an option of the preset
+/// already on the command line is not added
+///
+/// The arguments are given back as they are when they cannot be parsed, for
+/// the parsing of the options to report why.
+pub fn layered(app: App<'_, '_>, args: Vec<OsString>) -> Vec<OsString> {
+    let matches: ArgMatches<'_> = match app.get_matches_from_safe(&args) {
+        Ok(matches) => matches,
+        Err(_) => return args,
+    };
+    let preset: Preset = match matches.value_of("profile").map(str::parse) {
+        Some(Ok(preset)) => preset,
+        _ => return args,
+    };
+
+    let mut iter = args.into_iter();
+    // The program name, then the options of the preset before the given ones,
+    // so they stay before the subcommand
+    let mut layered: Vec<OsString> = iter.next().into_iter().collect();
+    for &(name, value) in preset.options() {
+        if matches.occurrences_of(name) == 0 {
+            layered.push(format!("--{}", name).into());
+            layered.extend(value.map(OsString::from));
+        }
+    }
+    layered.extend(iter);
+    layered
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::clap::{Arg, SubCommand};
+
+    use super::*;
+
+    fn app() -> App<'static, 'static> {
+        App::new("catcher")
+            .arg(Arg::with_name("profile").long("profile").takes_value(true))
+            .arg(Arg::with_name("smtp").long("smtp").takes_value(true))
+            .arg(
+                Arg::with_name("http")
+                    .long("http")
+                    .takes_value(true)
+                    .default_value("1080"),
+            )
+            .arg(Arg::with_name("output").long("output").takes_value(true))
+            .arg(Arg::with_name("browser").long("browser"))
+            .arg(
+                Arg::with_name("port-fallback")
+                    .long("port-fallback")
+                    .takes_value(true),
+            )
+            .subcommand(SubCommand::with_name("selftest"))
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn presets() {
+        crate::test::log_init();
+
+        assert_eq!("CI".parse(), Ok(Preset::Ci));
+        assert_eq!("demo".parse(), Ok(Preset::Demo));
+        assert!("prod".parse::<Preset>().is_err());
+
+        // The given options win over the preset
+        assert_eq!(
+            layered(
+                app(),
+                args(&["catcher", "--profile", "ci", "--http", "8080"])
+            ),
+            args(&[
+                "catcher",
+                "--smtp",
+                "0",
+                "--output",
+                "json",
+                "--idle-timeout",
+                "10m",
+                "--profile",
+                "ci",
+                "--http",
+                "8080"
+            ])
+        );
+        // Before the subcommand
+        assert_eq!(
+            layered(app(), args(&["catcher", "--profile=dev", "selftest"])),
+            args(&[
+                "catcher",
+                "--port-fallback",
+                "10",
+                "--browser",
+                "--profile=dev",
+                "selftest"
+            ])
+        );
+        // Without a preset, or with invalid options, nothing is added
+        assert_eq!(
+            layered(app(), args(&["catcher", "--http", "8080"])),
+            args(&["catcher", "--http", "8080"])
+        );
+        assert_eq!(
+            layered(app(), args(&["catcher", "--profile", "ci", "--unknown"])),
+            args(&["catcher", "--profile", "ci", "--unknown"])
+        );
+    }
+}
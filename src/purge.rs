@@ -0,0 +1,232 @@
+use std::{path::Path, str::FromStr, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tide::{
+    http::{Method, Url},
+    prelude::{json, Deserialize},
+};
+use ulid::Ulid;
+
+use crate::{
+    error::MailcatcherError,
+    mail::{filter::Filter, journal::Journal},
+    utils::{local_request, Output},
+};
+
+/// Age of the mails to purge, like `7d`, `12h`, `30m`, `45s` or `2w`, also
+/// used for the idle timeout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Age(pub Duration);
+
+impl FromStr for Age {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s: &str = s.trim();
+        let unit: u64 = match s.chars().last() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 3_600,
+            Some('d') => 86_400,
+            Some('w') => 604_800,
+            _ => return Err(format!("Invalid age {}, expected like 7d, 12h or 30m", s)),
+        };
+        s.get(..s.len().saturating_sub(1))
+            .and_then(|count| count.parse::<u64>().ok())
+            .and_then(|count| count.checked_mul(unit))
+            .map(|secs| Self(Duration::from_secs(secs)))
+            .ok_or_else(|| format!("Invalid age {}, expected like 7d, 12h or 30m", s))
+    }
+}
+
+/// Mail selected by the search route
+#[derive(Debug, Deserialize)]
+struct Listed {
+    /// ULID of the mail, whatever the `id` given by the instance
+    ulid: String,
+    /// The mail cannot be removed
+    #[serde(default)]
+    locked: bool,
+}
+
+/// Remove the mails received more than `older_than` ago, and sent by an address
+/// matching `from`, like `*@spam.test`
+///
+/// The mails are removed from the `journal` if it is given, that is compacted
+/// then, or else from the instance listening on the HTTP `port`. The locked mails
+/// of the instance are kept. A journal used by a running instance is refused, as
+/// the instance would go on writing the journal replaced by the compaction.
+#[allow(clippy::print_stdout)]
+pub async fn purge(
+    port: u16,
+    journal: Option<&Path>,
+    older_than: Option<Age>,
+    from: Option<&str>,
+    output: Output,
+) -> crate::Result<()> {
+    let received_before: Option<DateTime<Utc>> = older_than
+        .map(|Age(age)| {
+            chrono::Duration::from_std(age)
+                .ok()
+                .and_then(|age| Utc::now().checked_sub_signed(age))
+                .ok_or_else(|| MailcatcherError::config("The age of the mails is too large"))
+        })
+        .transpose()?;
+    let old_enough = |id: &Ulid| received_before.map_or(true, |before| id.datetime() < before);
+
+    let purged: Vec<Ulid> = match journal {
+        Some(path) => from_journal(path, from, old_enough).await?,
+        None => from_instance(port, from, old_enough).await?,
+    };
+
+    match output {
+        Output::Text => {
+            for id in &purged {
+                println!("{}", id);
+            }
+        }
+        Output::Json => println!(
+            "{}",
+            json!({
+                "purged": purged.iter().map(ToString::to_string).collect::<Vec<String>>()
+            })
+        ),
+    }
+    log::info!("{} mails purged", purged.len());
+
+    Ok(())
+}
+
+/// Remove the selected mails from a journal file, then compact it
+async fn from_journal<F>(path: &Path, from: Option<&str>, old_enough: F) -> crate::Result<Vec<Ulid>>
+where
+    F: Fn(&Ulid) -> bool,
+{
+    // Opening the journal would create it
+    if !path.exists() {
+        return Err(MailcatcherError::storage(format!(
+            "The journal {} does not exist",
+            path.display()
+        )));
+    }
+    let filter: Filter = Filter {
+        from: from.map(str::to_lowercase).into_iter().collect(),
+        ..Filter::default()
+    };
+    let journal: Journal = Journal::open(path, 0).await?;
+
+    let mut purged: Vec<Ulid> = Vec::new();
+    for mail in journal.replay().await? {
+        if old_enough(&mail.get_id()) && filter.matches(&mail) {
+            journal.remove(mail.get_id()).await?;
+            purged.push(mail.get_id());
+        }
+    }
+    if !purged.is_empty() {
+        let _ = journal.compact().await?;
+    }
+
+    Ok(purged)
+}
+
+/// Remove the selected mails from the instance listening on the HTTP `port`
+async fn from_instance<F>(port: u16, from: Option<&str>, old_enough: F) -> crate::Result<Vec<Ulid>>
+where
+    F: Fn(&Ulid) -> bool,
+{
+    let query: String = from
+        .map(|from| format!("from:\"{}\"", from))
+        .unwrap_or_default();
+    let mut url: Url =
+        Url::parse("http://localhost/mails/search").map_err(MailcatcherError::config)?;
+    let _ = url.query_pairs_mut().append_pair("q", &query);
+    let path: String = format!("{}?{}", url.path(), url.query().unwrap_or_default());
+    let listed: Vec<Listed> = local_request(port, Method::Get, &path, None)
+        .await?
+        .body_json()
+        .await
+        .map_err(|e| MailcatcherError::http(e.to_string()))?;
+
+    let mut purged: Vec<Ulid> = Vec::new();
+    for mail in listed {
+        let id: Ulid = match Ulid::from_string(&mail.ulid) {
+            Ok(id) if old_enough(&id) => id,
+            Ok(_) | Err(_) => continue,
+        };
+        if mail.locked {
+            log::info!("Mail {} locked, it is kept", id);
+            continue;
+        }
+        let _ = local_request(port, Method::Get, &format!("/remove/{}", id), None).await?;
+        purged.push(id);
+    }
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use async_std::task;
+
+    use super::*;
+    use crate::mail::Mail;
+
+    #[test]
+    fn ages() {
+        crate::test::log_init();
+
+        for &(age, secs) in &[
+            ("45s", 45),
+            ("30m", 1_800),
+            ("7d", 604_800),
+            ("2w", 1_209_600),
+        ] {
+            assert_eq!(age.parse::<Age>().map(|Age(age)| age.as_secs()), Ok(secs));
+        }
+        assert!("7".parse::<Age>().is_err());
+        assert!("d".parse::<Age>().is_err());
+        assert!("-1d".parse::<Age>().is_err());
+        assert!("99999999999999999999w".parse::<Age>().is_err());
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn purge_journal() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("mailcatcher-{}.journal", Ulid::new()));
+        let to: Vec<String> = vec!["rcpt@example.org".to_owned()];
+        let spam: Mail = Mail::new("<offers@spam.test>", &to, "Subject: Offer\r\n\r\nBuy");
+        let kept: Mail = Mail::new("<alice@example.org>", &to, "Subject: Hello\r\n\r\nHi");
+
+        let result: crate::test::Result<(bool, Vec<Ulid>, Vec<Ulid>, Vec<Mail>)> =
+            task::block_on(async {
+                let journal: Journal = Journal::open(&path, 0).await?;
+                journal.append(&spam).await?;
+                journal.append(&kept).await?;
+                // Refused while the instance holds the journal
+                let refused: bool = from_journal(&path, None, |_| true).await.is_err();
+                drop(journal);
+
+                let too_recent: Vec<Ulid> =
+                    from_journal(&path, Some("*@spam.test"), |_| false).await?;
+                let purged: Vec<Ulid> = from_journal(&path, Some("*@SPAM.test"), |_| true).await?;
+                Ok((refused, too_recent, purged, Journal::read(&path).await?))
+            });
+        std::fs::remove_file(&path).unwrap_or_default();
+        let (refused, too_recent, purged, mails) = result?;
+
+        assert!(refused);
+        assert!(too_recent.is_empty());
+        assert_eq!(purged, vec![spam.get_id()]);
+        assert_eq!(
+            mails.iter().map(Mail::get_id).collect::<Vec<Ulid>>(),
+            vec![kept.get_id()]
+        );
+
+        Ok(())
+    }
+}
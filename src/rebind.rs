@@ -0,0 +1,344 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    prelude::FutureExt,
+    sync::RwLock,
+    task::{self, JoinHandle},
+};
+use futures::future;
+use tide::Server;
+
+use crate::{
+    error::MailcatcherError,
+    handover::{self, Handles},
+    http::{bind as bind_http, sse_evt::SseEvt, Listening, State},
+    settings::SharedSettings,
+    shutdown,
+    smtp::{
+        self,
+        profile::{ListenerSpec, Profile},
+        Listener,
+    },
+    utils::{bind_port, AddressFamily, Bound},
+};
+
+/// Listeners of a side in use, they are stopped once `stop` is dropped
+#[derive(Debug)]
+struct Running {
+    /// Requested port, another one can be listened if it was busy
+    port: u16,
+    /// Closed to stop the listeners
+    stop: Sender<()>,
+    /// Task of the side, that ends once its sessions are over
+    task: JoinHandle<()>,
+    /// Descriptors of the listeners, to hand them over to a new process
+    handles: Handles,
+}
+
+impl Running {
+    /// Stop the listeners, then wait at most `grace` for the sessions in
+    /// progress to end, returning if they did
+    async fn drain(self, grace: Duration) -> bool {
+        drop(self.stop);
+        self.task.timeout(grace).await.is_ok()
+    }
+}
+
+/// What ended the wait of the servers
+enum Event {
+    /// The listeners must be rebound
+    Rebind,
+    /// The listeners must be handed over to a new process
+    Upgrade,
+    /// The servers must stop
+    Shutdown,
+    /// A side failed
+    Failed(MailcatcherError),
+}
+
+/// Signals received by the servers while running
+#[derive(Debug)]
+pub struct Signals {
+    /// Rebind the listeners, on the ports of the reloaded settings
+    pub rebind: Receiver<()>,
+    /// Hand the listeners over to a new process, then stop like on shutdown
+    pub upgrade: Receiver<()>,
+    /// Stop the servers
+    pub shutdown: Receiver<()>,
+    /// Stop the servers, as there was no activity for too long
+    pub idle: Receiver<()>,
+    /// Stop the servers, as the process that started them is gone
+    pub orphaned: Receiver<()>,
+}
+
+impl Signals {
+    /// Listen to the unix signals: SIGHUP to rebind, SIGUSR2 to upgrade, and
+    /// SIGINT or SIGTERM to shut down
+    ///
+    /// The idle and orphaned signals are never sent, their channels being closed.
+    pub fn new() -> crate::Result<Self> {
+        Ok(Self {
+            rebind: on_hangup()?,
+            upgrade: handover::on_upgrade()?,
+            shutdown: shutdown::on_terminate()?,
+            idle: channel::bounded(1).1,
+            orphaned: channel::bounded(1).1,
+        })
+    }
+}
+
+/// Wait for the next signal, a closed channel never giving one
+async fn next(signal: &Receiver<()>, event: Event) -> Event {
+    match signal.recv().await {
+        Ok(()) => event,
+        // Nothing can send this signal anymore
+        Err(_) => async_std::future::pending().await,
+    }
+}
+
+/// SMTP and HTTP servers, whose listeners can be rebound on other ports while
+/// running
+///
+/// When a port changes, the new listeners are bound first, then the old ones
+/// stop accepting the connections, but their sessions in progress go on until
+/// their end. The mails are kept, as the mail broker is not involved.
+///
+/// On shutdown, the listeners stop accepting the connections too, and the SMTP
+/// transactions in progress are finished, the sessions being closed between two
+/// transactions. On upgrade, the listeners are handed over to a new process
+/// first: the mails are then only kept if they are persisted in a journal, that
+/// the new process restores once this one released it, before accepting the
+/// connections.
+///
+/// The additional SMTP listeners, each with its own profile, are never rebound,
+/// but they stop and they are handed over with the others.
+pub struct Servers {
+    /// SMTP side parameters
+    pub smtp: smtp::Params,
+    /// Profile of the SMTP listeners of the port from the settings
+    pub profile: Profile,
+    /// HTTP side
+    pub http: Server<State<SseEvt>>,
+    /// IP versions of the addresses to listen on
+    pub family: AddressFamily,
+    /// Number of the next ports to try when a port is busy
+    pub fallback: u16,
+    /// Settings, that give the ports to listen on
+    pub settings: SharedSettings,
+    /// Addresses the servers listen on, given by `/api/info`
+    pub listening: Arc<RwLock<Listening>>,
+    /// Longest wait for the SMTP sessions to end on shutdown
+    pub grace: Duration,
+}
+
+impl Servers {
+    /// Serve on the bound listeners, and rebind them each time a signal asks to,
+    /// until a signal asks to shut down, or to upgrade
+    ///
+    /// The settings are reloaded before rebinding, to know the new ports.
+    pub async fn run(
+        self,
+        smtp: Bound,
+        extra: Vec<(ListenerSpec, Bound)>,
+        http: Bound,
+        signals: Signals,
+    ) -> crate::Result<()> {
+        let (tx_errors, rx_errors): crate::Channel<MailcatcherError> = channel::unbounded();
+        let settings = self.settings.get().await;
+        let mut smtp: Running = self.spawn_smtp(settings.smtp_port, self.profile, smtp, &tx_errors);
+        let extra: Vec<Running> = extra
+            .into_iter()
+            .map(|(spec, bound)| self.spawn_smtp(spec.port, spec.profile, bound, &tx_errors))
+            .collect();
+        let mut http: Running = self.spawn_http(settings.http_port, http, &tx_errors);
+
+        loop {
+            let event: Event = next(&signals.rebind, Event::Rebind)
+                .race(next(&signals.upgrade, Event::Upgrade))
+                .race(next(&signals.shutdown, Event::Shutdown))
+                .race(next(&signals.idle, Event::Shutdown))
+                .race(next(&signals.orphaned, Event::Shutdown))
+                .race(async {
+                    match rx_errors.recv().await {
+                        Ok(e) => Event::Failed(e),
+                        Err(_) => async_std::future::pending().await,
+                    }
+                })
+                .await;
+
+            match event {
+                Event::Failed(e) => return Err(e),
+                Event::Upgrade => {
+                    let handles: Vec<Handles> = std::iter::once(&smtp)
+                        .chain(&extra)
+                        .map(|running| running.handles.clone())
+                        .collect();
+                    if let Err(e) = handover::upgrade(&handles, &http.handles).await {
+                        log::error!("Listeners not handed over: {}", e);
+                        continue;
+                    }
+                    self.stop(smtp, extra, http).await;
+                    return Ok(());
+                }
+                Event::Shutdown => {
+                    self.stop(smtp, extra, http).await;
+                    return Ok(());
+                }
+                Event::Rebind => {
+                    let settings = match self.settings.reload().await {
+                        Ok(settings) => settings,
+                        Err(e) => {
+                            log::error!("Listeners not rebound: {}", e);
+                            continue;
+                        }
+                    };
+                    if let Some(bound) = self.bind("SMTP", smtp.port, settings.smtp_port).await {
+                        let mut listening = self.listening.write().await;
+                        listening.smtp = bound.addresses();
+                        listening.smtp_failures.clone_from(&bound.failures);
+                        smtp = self.spawn_smtp(settings.smtp_port, self.profile, bound, &tx_errors);
+                    }
+                    if let Some(bound) = self.bind("HTTP", http.port, settings.http_port).await {
+                        let mut listening = self.listening.write().await;
+                        listening.http = bound.addresses();
+                        listening.http_failures.clone_from(&bound.failures);
+                        http = self.spawn_http(settings.http_port, bound, &tx_errors);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stop both sides, waiting at most the grace period for the SMTP
+    /// transactions in progress
+    async fn stop(&self, smtp: Running, extra: Vec<Running>, http: Running) {
+        // The sessions between two transactions are closed at their next
+        // command, the others at the end of their transaction
+        self.smtp.pause.set(true);
+        drop(http);
+        log::info!("Waiting for the SMTP transactions in progress");
+        let drained: Vec<bool> = future::join_all(
+            std::iter::once(smtp)
+                .chain(extra)
+                .map(|running| running.drain(self.grace)),
+        )
+        .await;
+        if drained.contains(&false) {
+            log::warn!(
+                "SMTP sessions still in progress after {:?}, closed",
+                self.grace
+            );
+        }
+    }
+
+    /// Bind the new port of a side, if it changed
+    ///
+    /// The side keeps its listeners if the new port cannot be bound.
+    async fn bind(&self, name: &str, current: u16, port: u16) -> Option<Bound> {
+        if port == current {
+            log::info!("{} port {} unchanged", name, port);
+            return None;
+        }
+        match bind_port(name, port, self.family, self.fallback).await {
+            Ok(bound) => {
+                log::info!(
+                    "{} rebound from port {} to {:?}",
+                    name,
+                    current,
+                    bound.addresses()
+                );
+                Some(bound)
+            }
+            Err(e) => {
+                log::error!("{} not rebound: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Serve SMTP on the bound listeners with the `profile`, until the returned
+    /// value is dropped
+    fn spawn_smtp(
+        &self,
+        port: u16,
+        profile: Profile,
+        bound: Bound,
+        errors: &Sender<MailcatcherError>,
+    ) -> Running {
+        let params: smtp::Params = self.smtp.clone();
+        let handles: Handles = Handles::of(&bound.listeners);
+        let listeners: Vec<Listener> = bound
+            .listeners
+            .into_iter()
+            .map(|socket| Listener { socket, profile })
+            .collect();
+        spawn(port, handles, errors, |stop| {
+            smtp::serve(listeners, stop, params)
+        })
+    }
+
+    /// Serve HTTP on the bound listeners, until the returned value is dropped
+    fn spawn_http(&self, port: u16, bound: Bound, errors: &Sender<MailcatcherError>) -> Running {
+        let app: Server<State<SseEvt>> = self.http.clone();
+        let handles: Handles = Handles::of(&bound.listeners);
+        spawn(port, handles, errors, |stop| {
+            bind_http(app, bound.listeners, stop)
+        })
+    }
+}
+
+/// Run a side in its own task, so it can drain its sessions while the new
+/// listeners accept, its error is sent to `errors`
+fn spawn<F, Fut>(
+    port: u16,
+    handles: Handles,
+    errors: &Sender<MailcatcherError>,
+    serve: F,
+) -> Running
+where
+    F: FnOnce(Receiver<()>) -> Fut,
+    Fut: Future<Output = crate::Result<()>> + Send + 'static,
+{
+    let (stop, stopped): crate::Channel<()> = channel::bounded(1);
+    let errors: Sender<MailcatcherError> = errors.clone();
+    let served = serve(stopped);
+    let task: JoinHandle<()> = task::spawn(async move {
+        if let Err(e) = served.await {
+            errors.send(e).await.unwrap_or_default();
+        }
+    });
+
+    Running {
+        port,
+        stop,
+        task,
+        handles,
+    }
+}
+
+/// Signal to rebind the listeners, sent on each SIGHUP
+#[cfg(unix)]
+pub fn on_hangup() -> crate::Result<Receiver<()>> {
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+    let (rebind, rx_rebind): crate::Channel<()> = channel::unbounded();
+    let mut signals: Signals = Signals::new([SIGHUP]).map_err(MailcatcherError::config)?;
+    let _thread = std::thread::spawn(move || {
+        for _ in signals.forever() {
+            log::info!("SIGHUP received, rebinding the listeners");
+            if rebind.try_send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(rx_rebind)
+}
+
+/// Signal to rebind the listeners, never sent without SIGHUP
+#[cfg(not(unix))]
+pub fn on_hangup() -> crate::Result<Receiver<()>> {
+    let (_rebind, rx_rebind): crate::Channel<()> = channel::unbounded();
+    Ok(rx_rebind)
+}
@@ -0,0 +1,297 @@
+use std::time::Duration;
+
+use async_std::{
+    channel::{bounded, Receiver, Sender},
+    io::{
+        prelude::{BufReadExt, WriteExt},
+        BufReader, Lines,
+    },
+    net::{TcpListener, TcpStream},
+    prelude::FutureExt,
+    task,
+};
+use futures::StreamExt;
+use tide::prelude::{json, Serialize};
+
+use crate::{
+    error::MailcatcherError,
+    mail::Mail,
+    settings::SharedSettings,
+    shutdown::Activity,
+    smtp::{
+        self, capture::Capture, greylist::Greylist, headers::HeaderLimits, latency::Latency,
+        limit::Limits, metrics::Metrics, quarantine::Quarantine, queue::Queue, LineEndings, Params,
+        Pause,
+    },
+    utils::Output,
+};
+
+/// Longest duration of a check, so a server that does not reply fails it
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Client session against the server, each step being sent at once before its
+/// replies are read
+#[derive(Debug)]
+struct Check {
+    /// Name of the check, in the report
+    name: &'static str,
+    /// Commands sent, with the codes of the replies expected to them
+    steps: Vec<(String, &'static [u16])>,
+    /// Subject of the mail the session delivers, if any
+    subject: Option<&'static str>,
+}
+
+/// Outcome of a check, printed with `--output json`
+#[derive(Debug, Serialize)]
+struct Outcome {
+    /// Name of the check
+    name: &'static str,
+    /// The server replied as expected
+    passed: bool,
+    /// Reason of the failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Check the conformance of the SMTP side, with client sessions against a
+/// listener of its own, on a free local port
+///
+/// Every check is run, even if an earlier one failed. With the JSON `output`,
+/// a single document is printed at the end, with the outcome of each check.
+#[allow(clippy::print_stdout)]
+pub async fn selftest(output: Output) -> crate::Result<()> {
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(MailcatcherError::smtp)?;
+    let port: u16 = listener
+        .local_addr()
+        .map_err(MailcatcherError::smtp)?
+        .port();
+    let (stop, stopped): crate::Channel<()> = bounded(1);
+    let (sender, receiver): crate::Channel<Mail> = bounded(1);
+    let server = task::spawn(smtp::serve(vec![listener.into()], stopped, params(sender)));
+
+    let mut outcomes: Vec<Outcome> = Vec::new();
+    for check in checks() {
+        let result: crate::Result<()> = run(port, &check, &receiver)
+            .timeout(TIMEOUT)
+            .await
+            .unwrap_or_else(|_| Err(MailcatcherError::smtp("No reply in time")));
+        if output == Output::Text {
+            match result {
+                Ok(()) => println!("pass {}", check.name),
+                Err(ref e) => println!("FAIL {}: {}", check.name, e),
+            }
+        }
+        outcomes.push(Outcome {
+            name: check.name,
+            passed: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+    // The server stops once the channel is closed
+    drop(stop);
+    server.await?;
+
+    let failed: usize = outcomes.iter().filter(|outcome| !outcome.passed).count();
+    if output == Output::Json {
+        println!(
+            "{}",
+            json!({
+                "passed": outcomes.len().saturating_sub(failed),
+                "failed": failed,
+                "checks": outcomes,
+            })
+        );
+    }
+    if failed > 0 {
+        return Err(MailcatcherError::smtp(format!(
+            "{} of {} checks failed",
+            failed,
+            outcomes.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Parameters of the server checked, the default ones of the catcher
+fn params(mails_broker: Sender<Mail>) -> Params {
+    Params {
+        server_name: "SelfTest".to_owned(),
+        mails_broker,
+        use_starttls: false,
+        upstream: None,
+        journal: None,
+        settings: SharedSettings::default(),
+        credentials: None,
+        tracer: None,
+        pause: Pause::default(),
+        idle_timeout: None,
+        session_timeout: None,
+        limits: Limits::default(),
+        latency: Latency::default(),
+        max_recipients: None,
+        header_limits: HeaderLimits::default(),
+        banner_delay: None,
+        reject_early_talkers: false,
+        line_endings: LineEndings::default(),
+        queue: Queue::default(),
+        greylist: Greylist::default(),
+        metrics: Metrics::default(),
+        quarantine: Quarantine::default(),
+        capture: Capture::default(),
+        activity: Activity::default(),
+    }
+}
+
+/// Sessions of the self-test: valid, malformed, pipelined, oversized and 8-bit
+fn checks() -> Vec<Check> {
+    let step = |command: &str, codes: &'static [u16]| (command.to_owned(), codes);
+    let local_part: String = "a".repeat(65);
+
+    vec![
+        Check {
+            name: "valid session",
+            steps: vec![
+                step("EHLO selftest\r\n", &[250]),
+                step("MAIL FROM:<from@selftest.test>\r\n", &[250]),
+                step("RCPT TO:<to@selftest.test>\r\n", &[250]),
+                step("DATA\r\n", &[354]),
+                step("Subject: Valid\r\n\r\nContent\r\n.\r\n", &[250]),
+                step("QUIT\r\n", &[221]),
+            ],
+            subject: Some("Valid"),
+        },
+        Check {
+            name: "malformed commands",
+            steps: vec![
+                step("EHLO selftest\r\n", &[250]),
+                step("RCPT TO:<to@selftest.test>\r\n", &[503]),
+                step("DATA\r\n", &[503]),
+                step("MAIL FROM:<from@selftest.test\r\n", &[501]),
+                step("UNKNOWN\r\n", &[502]),
+                step("QUIT\r\n", &[221]),
+            ],
+            subject: None,
+        },
+        Check {
+            name: "pipelined commands",
+            steps: vec![
+                step("EHLO selftest\r\n", &[250]),
+                step(
+                    "MAIL FROM:<from@selftest.test>\r\nRCPT TO:<to@selftest.test>\r\n\
+                     RCPT TO:<cc@selftest.test>\r\nDATA\r\n",
+                    &[250, 250, 250, 354],
+                ),
+                step(
+                    "Subject: Pipelined\r\n\r\nContent\r\n.\r\nQUIT\r\n",
+                    &[250, 221],
+                ),
+            ],
+            subject: Some("Pipelined"),
+        },
+        Check {
+            name: "oversized address",
+            steps: vec![
+                step("HELO selftest\r\n", &[250]),
+                (
+                    format!("MAIL FROM:<{}@selftest.test>\r\n", local_part),
+                    &[500],
+                ),
+                step("RSET\r\n", &[250]),
+                step("QUIT\r\n", &[221]),
+            ],
+            subject: None,
+        },
+        Check {
+            name: "8-bit content",
+            steps: vec![
+                step("EHLO selftest\r\n", &[250]),
+                step(
+                    "MAIL FROM:<from@selftest.test> BODY=8BITMIME SMTPUTF8\r\n",
+                    &[250],
+                ),
+                step("RCPT TO:<usagé@selftest.test>\r\n", &[250]),
+                step("DATA\r\n", &[354]),
+                step("Subject: Café\r\n\r\nDéjà vu\r\n.\r\n", &[250]),
+                step("QUIT\r\n", &[221]),
+            ],
+            subject: Some("Café"),
+        },
+    ]
+}
+
+/// Run the session of a check, then look at the mail it delivered
+async fn run(port: u16, check: &Check, mails: &Receiver<Mail>) -> crate::Result<()> {
+    let mut stream: TcpStream = TcpStream::connect(("127.0.0.1", port))
+        .await
+        .map_err(MailcatcherError::smtp)?;
+    let mut lines: Lines<BufReader<TcpStream>> = BufReader::new(stream.clone()).lines();
+
+    expect(&mut lines, "the connection", &[220]).await?;
+    for &(ref command, codes) in &check.steps {
+        stream
+            .write_all(command.as_bytes())
+            .await
+            .map_err(MailcatcherError::smtp)?;
+        expect(&mut lines, command, codes).await?;
+    }
+
+    if let Some(subject) = check.subject {
+        let mail: Mail = mails.recv().await.map_err(MailcatcherError::broker)?;
+        if mail.get_subject() != subject {
+            return Err(MailcatcherError::smtp(format!(
+                "Subject \"{}\" received, expected \"{}\"",
+                mail.get_subject(),
+                subject
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Read the replies to a command, and compare their codes with those expected
+///
+/// A reply can span several lines, its code being followed by a dash on each
+/// one but the last.
+async fn expect(
+    lines: &mut Lines<BufReader<TcpStream>>,
+    command: &str,
+    codes: &[u16],
+) -> crate::Result<()> {
+    // Name the command by its first line in the errors
+    let command: &str = command.lines().next().unwrap_or_default();
+    for &code in codes {
+        loop {
+            let line: String = lines
+                .next()
+                .await
+                .ok_or_else(|| MailcatcherError::smtp(format!("No reply to {}", command)))?
+                .map_err(MailcatcherError::smtp)?;
+            if line.get(3..4) == Some("-") {
+                continue;
+            }
+            if line.get(..3).and_then(|got| got.parse().ok()) != Some(code) {
+                return Err(MailcatcherError::smtp(format!(
+                    "\"{}\" replied to {}, expected {}",
+                    line, command, code
+                )));
+            }
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_checks_pass() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        task::block_on(selftest(Output::Json))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_std::{fs, sync::RwLock};
+use serde_json::{Map, Value};
+use tide::prelude::{Deserialize, Serialize};
+
+use crate::{
+    error::MailcatcherError,
+    mail::{tag::TagRule, Mail},
+    smtp::{
+        reply::Replies,
+        rules::{Action, RecipientRule},
+    },
+    utils::{Cidr, Timezone},
+};
+
+/// Settings that can be changed while running, with `PATCH /api/config`
+///
+/// The ports are only applied when the listeners are rebound.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// SMTP listening port
+    pub smtp_port: u16,
+    /// HTTP listening port
+    pub http_port: u16,
+    /// Timezone used to format the dates
+    pub timezone: Timezone,
+    /// Time to wait for the mail broker to reply, in milliseconds
+    pub broker_timeout: u64,
+    /// Size above which only the headers of the mails are kept
+    pub headers_only: Option<usize>,
+    /// Clients allowed to connect, any client if empty
+    pub allow_cidr: Vec<Cidr>,
+    /// Clients not allowed to connect, even if they are allowed by `allow_cidr`
+    pub deny_cidr: Vec<Cidr>,
+    /// Custom texts of the SMTP replies, to mimic a production provider
+    pub replies: Replies,
+    /// Rules accepting or rejecting the recipients, the first one matching a
+    /// recipient deciding
+    pub recipient_rules: Vec<RecipientRule>,
+    /// Rules tagging the mails at their reception, every rule matching a mail
+    /// giving its tag
+    pub tag_rules: Vec<TagRule>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            smtp_port: 1025,
+            http_port: 1080,
+            timezone: Timezone::default(),
+            broker_timeout: 5_000,
+            headers_only: None,
+            allow_cidr: Vec::new(),
+            deny_cidr: Vec::new(),
+            replies: Replies::new(),
+            recipient_rules: Vec::new(),
+            tag_rules: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Time to wait for the mail broker to reply
+    pub const fn broker_delay(&self) -> Duration {
+        Duration::from_millis(self.broker_timeout)
+    }
+
+    /// Check if a client can connect, to submit or to browse the mails
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        (self.allow_cidr.is_empty() || self.allow_cidr.iter().any(|cidr| cidr.contains(addr)))
+            && !self.deny_cidr.iter().any(|cidr| cidr.contains(addr))
+    }
+
+    /// Check if a recipient is accepted by the rules, the recipients matched by
+    /// none of them being accepted
+    pub fn accepts_recipient(&self, recipient: &str) -> bool {
+        self.recipient_rules
+            .iter()
+            .find_map(|rule| rule.action_on(recipient))
+            .map_or(true, |action| action == Action::Accept)
+    }
+
+    /// Tags of a mail, given by the rules matching it, each tag once
+    pub fn tags_of(&self, mail: &Mail) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for tag in self.tag_rules.iter().filter_map(|rule| rule.tag_of(mail)) {
+            if !tags.iter().any(|kept| kept == tag) {
+                tags.push(tag.to_owned());
+            }
+        }
+        tags
+    }
+
+    /// Apply the overrides, a JSON object with the settings to change, a `null`
+    /// value unsets an optional setting
+    fn with_overrides(&self, overrides: &Map<String, Value>) -> Result<Self, String> {
+        let mut settings: Value = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        if let Some(settings) = settings.as_object_mut() {
+            for (name, value) in overrides {
+                let _ = settings.insert(name.clone(), value.clone());
+            }
+        }
+        let settings: Self = serde_json::from_value(settings).map_err(|e| e.to_string())?;
+
+        if settings.broker_timeout == 0 {
+            return Err("broker_timeout must be greater than 0".to_owned());
+        }
+        if settings.replies.values().any(|text| text.trim().is_empty()) {
+            return Err("the replies must have a text".to_owned());
+        }
+        Ok(settings)
+    }
+}
+
+/// Current settings and what was changed at runtime
+#[derive(Debug, Default)]
+struct Overridden {
+    /// Settings given on the command line
+    base: Settings,
+    /// Settings in use
+    settings: Settings,
+    /// Settings changed since the start, as given in the requests
+    overrides: Map<String, Value>,
+}
+
+/// Settings shared by the SMTP and HTTP sides
+///
+/// If a file is specified, the settings changed at runtime are saved in it and
+/// applied again at the next start, over the command line options.
+#[derive(Clone, Debug, Default)]
+pub struct SharedSettings {
+    /// Settings in use
+    inner: Arc<RwLock<Overridden>>,
+    /// File where to save the changed settings
+    path: Option<PathBuf>,
+}
+
+impl SharedSettings {
+    /// Share the settings, without saving their changes
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Overridden {
+                base: settings.clone(),
+                settings,
+                overrides: Map::new(),
+            })),
+            path: None,
+        }
+    }
+
+    /// Share the settings, applying the changes saved in the file if it exists
+    pub async fn load(settings: Settings, path: &Path) -> crate::Result<Self> {
+        let overrides: Map<String, Value> = read_overrides(path).await?;
+        let overridden: Settings = apply_overrides(&settings, &overrides, path)?;
+
+        Ok(Self {
+            inner: Arc::new(RwLock::new(Overridden {
+                base: settings,
+                settings: overridden,
+                overrides,
+            })),
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Read the file again, to apply the changes made in it while running
+    ///
+    /// Without a file, the settings are kept. An invalid file is a configuration
+    /// error, and nothing is changed then.
+    pub async fn reload(&self) -> crate::Result<Settings> {
+        let path: &Path = if let Some(ref path) = self.path {
+            path
+        } else {
+            return Ok(self.get().await);
+        };
+
+        let overrides: Map<String, Value> = read_overrides(path).await?;
+        let mut inner = self.inner.write().await;
+        let settings: Settings = apply_overrides(&inner.base, &overrides, path)?;
+        log::info!("Settings reloaded from {}", path.display());
+        inner.settings = settings.clone();
+        inner.overrides = overrides;
+
+        Ok(settings)
+    }
+
+    /// Retrieve the settings in use
+    pub async fn get(&self) -> Settings {
+        self.inner.read().await.settings.clone()
+    }
+
+    /// Change some settings, returning the new ones
+    ///
+    /// The changes are a JSON object with only the settings to change, like a
+    /// JSON merge patch. Invalid changes are a configuration error, and nothing is
+    /// changed then.
+    pub async fn update(&self, changes: &Value) -> crate::Result<Settings> {
+        let changes: &Map<String, Value> = changes
+            .as_object()
+            .ok_or_else(|| MailcatcherError::config("The settings must be a JSON object"))?;
+
+        let mut inner = self.inner.write().await;
+        let settings: Settings = inner
+            .settings
+            .with_overrides(changes)
+            .map_err(MailcatcherError::config)?;
+        let mut overrides: Map<String, Value> = inner.overrides.clone();
+        for (name, value) in changes {
+            let _ = overrides.insert(name.clone(), value.clone());
+        }
+
+        if let Some(ref path) = self.path {
+            let content: String =
+                serde_json::to_string_pretty(&overrides).map_err(MailcatcherError::storage)?;
+            fs::write(path, content)
+                .await
+                .map_err(MailcatcherError::storage)?;
+        }
+        log::info!("Settings changed: {:?}", changes);
+        inner.settings = settings.clone();
+        inner.overrides = overrides;
+
+        Ok(settings)
+    }
+}
+
+/// Read the settings saved in the file, none if it does not exist
+async fn read_overrides(path: &Path) -> crate::Result<Map<String, Value>> {
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+    let content: String = fs::read_to_string(path)
+        .await
+        .map_err(MailcatcherError::storage)?;
+    serde_json::from_str(&content).map_err(|e| {
+        MailcatcherError::config(format!("Invalid settings in {}: {}", path.display(), e))
+    })
+}
+
+/// Apply the settings saved in the file over the command line ones
+fn apply_overrides(
+    settings: &Settings,
+    overrides: &Map<String, Value>,
+    path: &Path,
+) -> crate::Result<Settings> {
+    settings.with_overrides(overrides).map_err(|e| {
+        MailcatcherError::config(format!("Invalid settings in {}: {}", path.display(), e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+    use tide::prelude::json;
+    use ulid::Ulid;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn update_and_reload() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: PathBuf = std::env::temp_dir().join(format!("mailcatcher-{}.json", Ulid::new()));
+        let cli: Settings = Settings {
+            headers_only: Some(1_000),
+            ..Settings::default()
+        };
+
+        let result: crate::test::Result<(Settings, Settings)> = task::block_on(async {
+            let shared: SharedSettings = SharedSettings::load(cli.clone(), &path).await?;
+            assert_eq!(shared.get().await, cli);
+
+            let updated: Settings = shared
+                .update(&json!({"timezone": "Europe/Paris", "headers_only": null}))
+                .await?;
+            // Nothing is changed by an invalid update
+            for invalid in &[
+                json!({"timezone": "Mars/Olympus"}),
+                json!({"broker_timeout": 0}),
+                json!({"unknown": true}),
+                json!([]),
+            ] {
+                assert_eq!(
+                    shared
+                        .update(invalid)
+                        .await
+                        .map_err(|e| e.exit_code())
+                        .err(),
+                    Some(78)
+                );
+            }
+            assert_eq!(shared.get().await, updated);
+
+            // Only the changes are saved, the other settings come from the command line
+            let reloaded: Settings = SharedSettings::load(
+                Settings {
+                    broker_timeout: 100,
+                    ..cli
+                },
+                &path,
+            )
+            .await?
+            .get()
+            .await;
+            Ok((updated, reloaded))
+        });
+        std::fs::remove_file(&path).unwrap_or_default();
+        let (updated, reloaded): (Settings, Settings) = result?;
+
+        let expected: Settings = Settings {
+            timezone: "Europe/Paris".parse()?,
+            ..Settings::default()
+        };
+        assert_eq!(updated, expected);
+        assert_eq!(
+            reloaded,
+            Settings {
+                broker_timeout: 100,
+                ..expected
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn reload_file() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: PathBuf = std::env::temp_dir().join(format!("mailcatcher-{}.json", Ulid::new()));
+
+        let result: crate::test::Result<(Settings, Option<i32>, Settings)> =
+            task::block_on(async {
+                let shared: SharedSettings =
+                    SharedSettings::load(Settings::default(), &path).await?;
+                let _ = shared.update(&json!({"timezone": "Europe/Paris"})).await?;
+
+                // The file is edited while running, the settings missing in it
+                // are back to the command line ones
+                std::fs::write(&path, r#"{"smtp_port": 2525}"#)?;
+                let reloaded: Settings = shared.reload().await?;
+
+                std::fs::write(&path, r#"{"smtp_port": "none"}"#)?;
+                let invalid: Option<i32> = shared.reload().await.map_err(|e| e.exit_code()).err();
+                Ok((reloaded, invalid, shared.get().await))
+            });
+        std::fs::remove_file(&path).unwrap_or_default();
+        let (reloaded, invalid, kept): (Settings, Option<i32>, Settings) = result?;
+
+        let expected: Settings = Settings {
+            smtp_port: 2525,
+            ..Settings::default()
+        };
+        assert_eq!(reloaded, expected);
+        assert_eq!(invalid, Some(78));
+        assert_eq!(kept, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn allowed_clients() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let settings: Settings = Settings {
+            allow_cidr: vec!["10.0.0.0/8".parse()?, "::1".parse()?],
+            deny_cidr: vec!["10.0.0.13".parse()?],
+            ..Settings::default()
+        };
+        for &(addr, allowed) in &[
+            ("10.1.2.3", true),
+            ("::1", true),
+            ("10.0.0.13", false),
+            ("192.168.0.1", false),
+        ] {
+            assert_eq!(settings.is_allowed(addr.parse()?), allowed, "{}", addr);
+        }
+
+        let settings: Settings = Settings {
+            deny_cidr: vec!["10.0.0.0/8".parse()?],
+            ..Settings::default()
+        };
+        assert!(settings.is_allowed("192.168.0.1".parse()?));
+        assert!(!settings.is_allowed("10.0.0.1".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn recipient_rules() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        assert!(Settings::default().accepts_recipient("any@example.com"));
+
+        let shared: SharedSettings = SharedSettings::new(Settings::default());
+        let settings: Settings = task::block_on(shared.update(&json!({
+            "recipient_rules": ["accept:/^postmaster@/", "reject:*@bounce.test"],
+        })))?;
+        for &(recipient, accepted) in &[
+            ("user@bounce.test", false),
+            ("postmaster@bounce.test", true),
+            ("user@example.com", true),
+        ] {
+            assert_eq!(
+                settings.accepts_recipient(recipient),
+                accepted,
+                "{}",
+                recipient
+            );
+        }
+
+        let invalid: Option<i32> =
+            task::block_on(shared.update(&json!({"recipient_rules": ["bounce:*"]})))
+                .map_err(|e| e.exit_code())
+                .err();
+        assert_eq!(invalid, Some(78));
+        assert_eq!(task::block_on(shared.get()), settings);
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn tag_rules() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let mail: Mail = Mail::new(
+            "from@example.org",
+            &["to@example.net".into()],
+            "Subject: [staging] Welcome\r\nX-Env: staging\r\n\r\nBody",
+        );
+        assert!(Settings::default().tags_of(&mail).is_empty());
+
+        let settings: Settings = Settings {
+            tag_rules: vec![
+                "staging=Subject:[staging]".parse()?,
+                "prod=Subject:[prod]".parse()?,
+                "staging=X-Env:staging".parse()?,
+                "env=X-Env:".parse()?,
+            ],
+            ..Settings::default()
+        };
+        assert_eq!(settings.tags_of(&mail), vec!["staging", "env"]);
+
+        let invalid: Option<i32> = task::block_on(
+            SharedSettings::new(settings).update(&json!({"tag_rules": ["staging"]})),
+        )
+        .map_err(|e| e.exit_code())
+        .err();
+        assert_eq!(invalid, Some(78));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    convert::TryFrom,
+    io::{ErrorKind, Read, Stdin},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    task::{self, JoinHandle},
+};
+
+use crate::{
+    error::MailcatcherError,
+    mail::{
+        broker::{MailEvt, TankStats},
+        Mail,
+    },
+};
+
+/// Interval between two checks that the parent process is still running
+const WATCHDOG: Duration = Duration::from_secs(1);
+
+/// Wait for the mails `received` by the SMTP side to reach the mail `broker` and
+/// the event sinks, then for the broker to process the events sent before
+///
+/// The channel of the SMTP side is closed, so the `notifier` task ends once the
+/// mails it had are marked and sent, then the `notifications` task of the HTTP
+/// side once they are published to the event sinks.
+///
+/// The mails are already in the journal, if any, as they are written there
+/// before being acknowledged: this only lets the broker finish its work, like
+/// the removals to record in the journal.
+pub async fn flush(
+    received: &Sender<Mail>,
+    notifier: JoinHandle<()>,
+    notifications: JoinHandle<()>,
+    broker: &Sender<MailEvt>,
+) -> crate::Result<()> {
+    let _closed: bool = received.close();
+    notifier.await;
+    notifications.await;
+    // The events are processed in order, so the previous ones are done once
+    // this one is answered
+    let (tx_stats, rx_stats): crate::Channel<TankStats> = channel::bounded(1);
+    broker
+        .send(MailEvt::GetStats(tx_stats))
+        .await
+        .map_err(MailcatcherError::broker)?;
+    let stats: TankStats = rx_stats.recv().await.map_err(MailcatcherError::broker)?;
+    log::info!("Mail broker flushed, {} mails in the tank", stats.mails);
+    Ok(())
+}
+
+/// Time of the last activity of the SMTP and HTTP sides, shared by both
+#[derive(Clone, Debug)]
+pub struct Activity {
+    /// Start of the clock of the activity
+    start: Instant,
+    /// Last activity, in milliseconds since `start`
+    last: Arc<AtomicU64>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            last: Arc::default(),
+        }
+    }
+}
+
+impl Activity {
+    /// Record an activity: a SMTP connection or line, or a HTTP request
+    pub fn touch(&self) {
+        self.last.store(self.elapsed_millis(), Ordering::Relaxed);
+    }
+
+    /// Time since the last activity
+    pub fn idle(&self) -> Duration {
+        let last: u64 = self.last.load(Ordering::Relaxed);
+        Duration::from_millis(self.elapsed_millis().saturating_sub(last))
+    }
+
+    /// Time since the start of the clock, in milliseconds
+    fn elapsed_millis(&self) -> u64 {
+        u64::try_from(self.start.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+}
+
+/// Signal to shut down, sent once there was no `activity` for the `timeout`
+pub fn on_idle(activity: Activity, timeout: Duration) -> crate::Result<Receiver<()>> {
+    let (idle, rx_idle): crate::Channel<()> = channel::bounded(1);
+    let _idle_task = task::Builder::new()
+        .name("Task: Idle shutdown".into())
+        .spawn(async move {
+            loop {
+                let idle: Duration = activity.idle();
+                if idle >= timeout {
+                    break;
+                }
+                task::sleep(timeout.saturating_sub(idle)).await;
+            }
+            log::info!("No activity for {:?}, shutting down", timeout);
+            idle.try_send(()).unwrap_or_default();
+        })
+        .map_err(MailcatcherError::config)?;
+
+    Ok(rx_idle)
+}
+
+/// Signal to shut down, sent once the process that started the catcher is
+/// gone: the process `parent`, if given, has exited, or the standard input, if
+/// watched with `stdin`, is closed
+///
+/// A test process killed without a chance to stop its catcher leaves it
+/// orphaned, both are the ways to notice it.
+pub fn on_orphaned(parent: Option<u32>, stdin: bool) -> crate::Result<Receiver<()>> {
+    let (orphaned, rx_orphaned): crate::Channel<()> = channel::bounded(1);
+    if let Some(pid) = parent {
+        let watchdog: Sender<()> = orphaned.clone();
+        let _watchdog_task = task::Builder::new()
+            .name("Task: Parent watchdog".into())
+            .spawn(async move {
+                while is_running(pid) {
+                    task::sleep(WATCHDOG).await;
+                }
+                log::info!("Parent process {} exited, shutting down", pid);
+                watchdog.try_send(()).unwrap_or_default();
+            })
+            .map_err(MailcatcherError::config)?;
+    }
+    if stdin {
+        // Nothing is read from the standard input, it is only drained until
+        // its end, in a thread as the reading blocks
+        let _thread = std::thread::spawn(move || {
+            let mut buffer: [u8; 512] = [0; 512];
+            let mut input: Stdin = std::io::stdin();
+            loop {
+                match input.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        log::warn!("Standard input unreadable: {}", e);
+                        break;
+                    }
+                }
+            }
+            log::info!("Standard input closed, shutting down");
+            orphaned.try_send(()).unwrap_or_default();
+        });
+    }
+
+    Ok(rx_orphaned)
+}
+
+/// Check a process is still running, by sending it the null signal that only
+/// checks it can be signaled: a refusal means the process exists
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let process: libc::pid_t = match libc::pid_t::try_from(pid) {
+        Ok(process) => process,
+        Err(_) => return false,
+    };
+    #[allow(unsafe_code)]
+    // SAFETY: the null signal is never delivered, kill only checks the process
+    let sent: bool = unsafe { libc::kill(process, 0) } == 0;
+    sent || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without the unix signals, the parent process is never seen as exited, only
+/// the closing of the standard input can be watched
+#[cfg(not(unix))]
+const fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// Signal to shut down, sent on SIGINT or SIGTERM
+#[cfg(unix)]
+pub fn on_terminate() -> crate::Result<Receiver<()>> {
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+
+    let (terminate, rx_terminate): crate::Channel<()> = channel::bounded(1);
+    let mut signals: Signals = Signals::new([SIGINT, SIGTERM]).map_err(MailcatcherError::config)?;
+    let _thread = std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            log::info!("Signal {} received, shutting down", signal);
+            terminate.try_send(()).unwrap_or_default();
+        }
+    });
+
+    Ok(rx_terminate)
+}
+
+/// Signal to shut down, never sent without the unix signals
+#[cfg(not(unix))]
+pub fn on_terminate() -> crate::Result<Receiver<()>> {
+    let (_terminate, rx_terminate): crate::Channel<()> = channel::bounded(1);
+    Ok(rx_terminate)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::prelude::FutureExt;
+
+    use super::*;
+    use crate::mail::{broker::MailTank, store::MemoryStore};
+
+    #[test]
+    #[cfg(unix)]
+    #[allow(clippy::panic_in_result_fn)]
+    fn parent_watchdog() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        assert!(is_running(std::process::id()));
+        // A child that exits at once, reaped so its pid is free
+        let mut child: std::process::Child = std::process::Command::new("true").spawn()?;
+        let pid: u32 = child.id();
+        let _status = child.wait()?;
+        assert!(!is_running(pid));
+
+        task::block_on(async {
+            on_orphaned(Some(pid), false)?
+                .recv()
+                .timeout(Duration::from_secs(5))
+                .await??;
+            // Nothing to watch, the signal never comes
+            assert!(on_orphaned(None, false)?.recv().await.is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn idle_shutdown() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let the_test = async {
+            let activity: Activity = Activity::default();
+            let timeout: Duration = Duration::from_millis(200);
+            let rx_idle: Receiver<()> = on_idle(activity.clone(), timeout)?;
+
+            // Kept alive by the activity
+            for _ in 0..3 {
+                task::sleep(Duration::from_millis(100)).await;
+                activity.touch();
+                assert!(rx_idle.is_empty());
+            }
+            let waiting: Instant = Instant::now();
+            rx_idle.recv().await?;
+            assert!(waiting.elapsed() >= Duration::from_millis(150));
+            assert!(activity.idle() >= timeout);
+
+            Ok(())
+        };
+        task::block_on(the_test)
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn flush_broker() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let (tx_broker, rx_broker): crate::Channel<MailEvt> = channel::unbounded();
+        let (tx_received, rx_received): crate::Channel<Mail> = channel::unbounded();
+        let broker = MailTank::new(rx_broker, Box::new(MemoryStore::default()), None);
+
+        let the_test = async {
+            // A mail is taken by the notifier, that is still marking it
+            tx_received.send(Mail::fake()).await?;
+            let (tx_new_mail, rx_new_mail): crate::Channel<Mail> = channel::unbounded();
+            let broker_notifier: Sender<MailEvt> = tx_broker.clone();
+            let notifier: JoinHandle<()> = task::spawn(async move {
+                while let Ok(mail) = rx_received.recv().await {
+                    task::sleep(Duration::from_millis(50)).await;
+                    broker_notifier
+                        .send(MailEvt::NewMail(Box::new(mail.clone())))
+                        .await
+                        .unwrap_or_default();
+                    tx_new_mail.send(mail).await.unwrap_or_default();
+                }
+            });
+            // The event sinks are notified by the HTTP side
+            let published: Arc<AtomicU64> = Arc::default();
+            let notifications: JoinHandle<()> = task::spawn({
+                let published: Arc<AtomicU64> = Arc::clone(&published);
+                async move {
+                    while rx_new_mail.recv().await.is_ok() {
+                        task::sleep(Duration::from_millis(20)).await;
+                        let _ = published.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+
+            flush(&tx_received, notifier, notifications, &tx_broker).await?;
+            assert_eq!(published.load(Ordering::Relaxed), 1);
+            let (sender, receiver): crate::Channel<TankStats> = channel::bounded(1);
+            tx_broker.send(MailEvt::GetStats(sender)).await?;
+            assert_eq!(receiver.recv().await?.mails, 1);
+
+            Ok(())
+        };
+        task::block_on(the_test.race(async {
+            broker.process().await?;
+            Ok(())
+        }))
+    }
+}
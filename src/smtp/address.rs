@@ -0,0 +1,224 @@
+use std::str::FromStr;
+
+use crate::mail::Parameters;
+
+/// Path given to MAIL FROM or RCPT TO: the mailbox, then its ESMTP parameters,
+/// like `<alice@example.org> SIZE=1024 BODY=8BITMIME`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Path {
+    /// Address of the mailbox, empty for the null reverse path `<>`
+    pub mailbox: String,
+    /// ESMTP parameters, by keyword in uppercase
+    pub params: Parameters,
+}
+
+impl FromStr for Path {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s: &str = s.trim();
+        let (mailbox, params): (&str, &str) = match s.strip_prefix('<') {
+            Some(path) => {
+                let end: usize =
+                    closing_bracket(path).ok_or_else(|| format!("Unclosed address: {}", s))?;
+                let (mailbox, params): (&str, &str) = path.split_at(end);
+                let params: &str = params.get(1..).unwrap_or_default();
+                if !params.is_empty() && !params.starts_with(' ') {
+                    return Err(format!("Missing space after the address: {}", s));
+                }
+                (mailbox, params)
+            }
+            // Some clients forget the angle brackets
+            None => s.split_once(' ').unwrap_or((s, "")),
+        };
+        // The source routes, like `@relay.example:alice@example.org`, are ignored
+        let mailbox: &str = if mailbox.starts_with('@') {
+            mailbox
+                .split_once(':')
+                .map_or(mailbox, |(_, mailbox)| mailbox)
+        } else {
+            mailbox
+        };
+
+        let mut parsed: Parameters = Parameters::new();
+        for param in params.split_whitespace() {
+            let (keyword, value): (&str, Option<&str>) = match param.split_once('=') {
+                Some((keyword, value)) => (keyword, Some(value)),
+                None => (param, None),
+            };
+            let valid_keyword: bool = keyword.starts_with(|c: char| c.is_ascii_alphanumeric())
+                && keyword
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-');
+            if !valid_keyword || value.map_or(false, str::is_empty) {
+                return Err(format!("Invalid ESMTP parameter: {}", param));
+            }
+            let _ = parsed.insert(keyword.to_ascii_uppercase(), value.map(ToOwned::to_owned));
+        }
+
+        Ok(Self {
+            mailbox: mailbox.to_owned(),
+            params: parsed,
+        })
+    }
+}
+
+impl Path {
+    /// Local part of the mailbox, before the last `@`
+    pub fn local_part(&self) -> &str {
+        self.mailbox
+            .rsplit_once('@')
+            .map_or(self.mailbox.as_str(), |(local, _)| local)
+    }
+}
+
+/// Mailboxes of an address list header, like `To` or `Cc`, without their display
+/// names: `"Doe, John" <john@example.org>, bob@example.net` gives
+/// `john@example.org` and `bob@example.net`
+pub fn header_mailboxes(list: &str) -> Vec<String> {
+    let mut addresses: Vec<&str> = Vec::new();
+    let mut start: usize = 0;
+    let mut quoted: bool = false;
+    let mut escaped: bool = false;
+    let mut bracketed: bool = false;
+    for (index, c) in list.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '<' if !quoted => bracketed = true,
+            '>' if !quoted => bracketed = false,
+            ',' | ';' if !quoted && !bracketed => {
+                addresses.push(list.get(start..index).unwrap_or_default());
+                start = index.saturating_add(1);
+            }
+            _ => {}
+        }
+    }
+    addresses.push(list.get(start..).unwrap_or_default());
+
+    addresses.into_iter().filter_map(header_mailbox).collect()
+}
+
+/// Mailbox of an address of a header, between the angle brackets following the
+/// display name if any, `None` if it is empty
+fn header_mailbox(address: &str) -> Option<String> {
+    let address: &str = address.trim();
+    let mailbox: &str = match opening_bracket(address) {
+        Some(start) => {
+            let path: &str = address.get(start.saturating_add(1)..)?;
+            path.get(..closing_bracket(path)?)?
+        }
+        None => address,
+    };
+    Some(mailbox.trim().to_owned()).filter(|mailbox| !mailbox.is_empty())
+}
+
+/// Position of the `<` opening an address, after the display name that can be
+/// quoted, like `"Doe <John>" <john@example.org>`
+fn opening_bracket(address: &str) -> Option<usize> {
+    let mut quoted: bool = false;
+    let mut escaped: bool = false;
+    for (index, c) in address.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '<' if !quoted => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Position of the `>` closing an address, skipping the quoted strings of its
+/// local part, like `"John <Doe>"@example.org`
+fn closing_bracket(address: &str) -> Option<usize> {
+    let mut quoted: bool = false;
+    let mut escaped: bool = false;
+    for (index, c) in address.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '>' if !quoted => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn parse_paths() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let path: Path = "<alice@example.org> size=1024 BODY=8BITMIME SMTPUTF8".parse()?;
+        assert_eq!(path.mailbox, "alice@example.org");
+        assert_eq!(path.local_part(), "alice");
+        assert_eq!(
+            path.params
+                .into_iter()
+                .collect::<Vec<(String, Option<String>)>>(),
+            vec![
+                ("BODY".to_owned(), Some("8BITMIME".to_owned())),
+                ("SIZE".to_owned(), Some("1024".to_owned())),
+                ("SMTPUTF8".to_owned(), None),
+            ]
+        );
+
+        for &(path, mailbox) in &[
+            ("<>", ""),
+            ("  <bob@example.net>", "bob@example.net"),
+            ("bob@example.net", "bob@example.net"),
+            (
+                "<@relay.example,@other.example:bob@example.net>",
+                "bob@example.net",
+            ),
+            (
+                r#"<"John \"<Doe>\""@example.org>"#,
+                r#""John \"<Doe>\""@example.org"#,
+            ),
+        ] {
+            assert_eq!(path.parse::<Path>()?.mailbox, mailbox);
+        }
+
+        for &invalid in &[
+            "<alice@example.org",
+            "<alice@example.org>SIZE=10",
+            "<alice@example.org> SIZE=",
+            "<alice@example.org> -SIZE=10",
+            "<alice@example.org> SI_ZE=10",
+        ] {
+            assert!(invalid.parse::<Path>().is_err(), "{} is valid", invalid);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_header_mailboxes() {
+        crate::test::log_init();
+
+        assert_eq!(
+            header_mailboxes(
+                r#""Doe, John" <john@example.org>, bob@example.net; Alice <"a,b"@example.com>"#
+            ),
+            vec![
+                "john@example.org".to_owned(),
+                "bob@example.net".to_owned(),
+                r#""a,b"@example.com"#.to_owned(),
+            ]
+        );
+        assert_eq!(
+            header_mailboxes(r#""Doe <John>" <john@example.org>"#),
+            vec!["john@example.org".to_owned()]
+        );
+        assert!(header_mailboxes(" , ").is_empty());
+        assert!(header_mailboxes("").is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use std::str::FromStr;
+
+/// Credentials given with the AUTH command, as `user:password`: those the clients
+/// must give, or those the catcher gives to the server it releases the mails to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    /// Name of the user
+    user: String,
+    /// Password of the user
+    password: String,
+}
+
+impl FromStr for Credentials {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, password): (&str, &str) = s
+            .split_once(':')
+            .ok_or_else(|| "The credentials must be \"user:password\"".to_owned())?;
+        if user.is_empty() {
+            return Err("The credentials have no user".to_owned());
+        }
+        Ok(Self {
+            user: user.to_owned(),
+            password: password.to_owned(),
+        })
+    }
+}
+
+impl Credentials {
+    /// Check the credentials given by a client
+    pub fn accepts(&self, user: &str, password: &str) -> bool {
+        self.user == user && self.password == password
+    }
+
+    /// Response giving the credentials with the PLAIN mechanism, in base64
+    pub fn plain(&self) -> String {
+        base64::encode(format!("\0{}\0{}", self.user, self.password))
+    }
+}
+
+/// Step of an authentication, waiting for a response of the client
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthStep {
+    /// PLAIN mechanism, waiting for the credentials
+    Plain,
+    /// LOGIN mechanism, waiting for the user name
+    LoginUser,
+    /// LOGIN mechanism, waiting for the password of the user
+    LoginPassword(String),
+}
+
+/// Decode a response of the client, in base64
+pub fn decode(response: &str) -> Option<String> {
+    base64::decode(response.trim())
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+}
+
+/// Decode the credentials of the PLAIN mechanism, returning the user and its
+/// password
+///
+/// They are given as `authorization id NUL user NUL password`, where the
+/// authorization id is usually empty.
+pub fn decode_plain(response: &str) -> Option<(String, String)> {
+    let decoded: String = decode(response)?;
+    let mut fields = decoded.split('\0').skip(1);
+    let user: &str = fields.next()?;
+    let password: &str = fields.next()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((user.to_owned(), password.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_responses() {
+        crate::test::log_init();
+
+        // base64 of "\0alice\0secret" and "admin\0alice\0se:cret"
+        assert_eq!(
+            decode_plain("AGFsaWNlAHNlY3JldA=="),
+            Some(("alice".to_owned(), "secret".to_owned()))
+        );
+        assert_eq!(
+            decode_plain("YWRtaW4AYWxpY2UAc2U6Y3JldA=="),
+            Some(("alice".to_owned(), "se:cret".to_owned()))
+        );
+        // base64 of "alice"
+        assert_eq!(decode_plain("YWxpY2U="), None);
+        assert_eq!(decode("YWxpY2U="), Some("alice".to_owned()));
+        assert_eq!(decode("not base64!"), None);
+
+        let credentials: Result<Credentials, String> = "alice:se:cret".parse();
+        assert_eq!(
+            credentials.map(|credentials| credentials.accepts("alice", "se:cret")),
+            Ok(true)
+        );
+        assert_eq!(
+            "alice:secret"
+                .parse::<Credentials>()
+                .map(|credentials| credentials.plain()),
+            Ok("AGFsaWNlAHNlY3JldA==".to_owned())
+        );
+        assert!("alice".parse::<Credentials>().is_err());
+        assert!(":secret".parse::<Credentials>().is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use async_std::sync::Mutex;
+use tide::prelude::Serialize;
+
+use crate::smtp::limit::Rate;
+
+/// Share of the mails kept, like `1/100` for every hundredth mail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Mails kept in each run of `every` mails
+    kept: u64,
+    /// Length of a run of mails, never 0
+    every: u64,
+}
+
+impl Sample {
+    /// Check if the mail of sequential number `number`, from 0, is kept
+    ///
+    /// The length of the runs `every` is greater than 0, the parsing refusing
+    /// any other.
+    pub fn keeps(self, number: u64) -> bool {
+        number
+            .checked_rem(self.every)
+            .map_or(true, |rest| rest < self.kept)
+    }
+}
+
+impl FromStr for Sample {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid sample {}, expected like 1/100", s);
+        let (kept, every): (&str, &str) = s.trim().split_once('/').ok_or_else(invalid)?;
+        match (kept.parse::<u64>(), every.parse::<u64>()) {
+            (Ok(kept_n), Ok(every_n)) if kept_n > 0 && kept_n <= every_n => Ok(Self {
+                kept: kept_n,
+                every: every_n,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.kept, self.every)
+    }
+}
+
+/// Capture of the mails under a heavy load, shared with the HTTP side
+///
+/// Above the rate, only the envelope of the mails is kept, their content being
+/// counted and discarded, so a load test sending millions of mails can use the
+/// catcher as a sink without drowning it. With a sample, only a share of the
+/// mails is kept, all of them being counted, for a soak test to get
+/// representative mails and accurate counts.
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    /// Rate of the mails above which only their envelope is kept, the whole
+    /// mails are always kept if `None`
+    above: Option<Rate>,
+    /// Share of the mails kept, all of them if `None`
+    sample: Option<Sample>,
+    /// Mails received, kept or not
+    received: Arc<AtomicU64>,
+    /// Mails acknowledged but left out of the sample
+    sampled_out: Arc<AtomicU64>,
+    /// Times of the recent mails, at most one more than the rate
+    recent: Arc<Mutex<VecDeque<Instant>>>,
+    /// Mails whose content was discarded
+    envelopes: Arc<AtomicU64>,
+    /// Bytes of the contents discarded
+    discarded: Arc<AtomicU64>,
+}
+
+/// State of the capture, given by `/api/capture`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CaptureStats {
+    /// Rate above which only the envelopes are kept, like `100/s`
+    pub envelope_only_above: Option<String>,
+    /// Mails whose content was discarded
+    pub envelope_only: u64,
+    /// Bytes of the contents discarded
+    pub discarded_bytes: u64,
+    /// Share of the mails kept, like `1/100`
+    pub sample: Option<String>,
+    /// Mails received, kept or not
+    pub received: u64,
+    /// Mails acknowledged but left out of the sample
+    pub sampled_out: u64,
+}
+
+impl Capture {
+    /// Keep only the envelope of the mails received above the rate, and only
+    /// the share of the mails given by the sample
+    pub fn new(above: Option<Rate>, sample: Option<Sample>) -> Self {
+        Self {
+            above,
+            sample,
+            ..Self::default()
+        }
+    }
+
+    /// Count a mail received, telling if it is kept in the sample
+    pub fn sampled(&self) -> bool {
+        let number: u64 = self.received.fetch_add(1, Ordering::Relaxed);
+        let kept: bool = self.sample.map_or(true, |sample| sample.keeps(number));
+        if !kept {
+            let _ = self.sampled_out.fetch_add(1, Ordering::Relaxed);
+        }
+        kept
+    }
+
+    /// Record a mail received with a content of `size` bytes, telling if only
+    /// its envelope is kept
+    pub async fn envelope_only(&self, size: usize) -> bool {
+        let rate: Rate = match self.above {
+            Some(rate) => rate,
+            None => return false,
+        };
+        let above: bool = {
+            let mut recent = self.recent.lock().await;
+            while recent
+                .front()
+                .map_or(false, |time| time.elapsed() >= rate.period())
+            {
+                let _ = recent.pop_front();
+            }
+            recent.push_back(Instant::now());
+            // The mails beyond the rate are enough to know it is exceeded
+            while recent.len() > rate.count().saturating_add(1) {
+                let _ = recent.pop_front();
+            }
+            recent.len() > rate.count()
+        };
+        if above {
+            let size: u64 = u64::try_from(size).unwrap_or(u64::MAX);
+            let _ = self.envelopes.fetch_add(1, Ordering::Relaxed);
+            let _ = self.discarded.fetch_add(size, Ordering::Relaxed);
+        }
+        above
+    }
+
+    /// State of the capture at this time
+    pub fn stats(&self) -> CaptureStats {
+        CaptureStats {
+            envelope_only_above: self.above.map(|rate| rate.to_string()),
+            envelope_only: self.envelopes.load(Ordering::Relaxed),
+            discarded_bytes: self.discarded.load(Ordering::Relaxed),
+            sample: self.sample.map(|sample| sample.to_string()),
+            received: self.received.load(Ordering::Relaxed),
+            sampled_out: self.sampled_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn envelope_only_above() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        task::block_on(async {
+            let capture: Capture = Capture::default();
+            assert!(!capture.envelope_only(100).await);
+            assert_eq!(capture.stats(), CaptureStats::default());
+
+            let capture: Capture = Capture::new(Some("2/h".parse()?), None);
+            let shared: Capture = capture.clone();
+            let mut kept: Vec<bool> = Vec::new();
+            for size in &[10, 20, 30, 40] {
+                kept.push(shared.envelope_only(*size).await);
+            }
+            assert_eq!(kept, vec![false, false, true, true]);
+            assert_eq!(
+                capture.stats(),
+                CaptureStats {
+                    envelope_only_above: Some("2/h".to_owned()),
+                    envelope_only: 2,
+                    discarded_bytes: 70,
+                    ..CaptureStats::default()
+                }
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn sample() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        assert_eq!(
+            "1/100".parse(),
+            Ok(Sample {
+                kept: 1,
+                every: 100
+            })
+        );
+        assert!("0/10".parse::<Sample>().is_err());
+        assert!("3/2".parse::<Sample>().is_err());
+        assert!("10".parse::<Sample>().is_err());
+
+        let capture: Capture = Capture::default();
+        assert!(capture.sampled());
+        assert_eq!(capture.stats().received, 1);
+
+        // The first mail of each run is kept
+        let capture: Capture = Capture::new(None, Some("1/3".parse()?));
+        let kept: Vec<bool> = (0..7).map(|_| capture.sampled()).collect();
+        assert_eq!(kept, vec![true, false, false, true, false, false, true]);
+        assert_eq!(
+            capture.stats(),
+            CaptureStats {
+                sample: Some("1/3".to_owned()),
+                received: 7,
+                sampled_out: 4,
+                ..CaptureStats::default()
+            }
+        );
+
+        Ok(())
+    }
+}
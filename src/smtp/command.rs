@@ -9,6 +9,10 @@ pub enum Command<'a> {
     Ehllo(String),
     /// STARTTLS
     StartTls,
+    /// AUTH, with the mechanism and the optional initial response
+    Auth(String),
+    /// Internal, response of the client during an authentication
+    AuthResponse(String),
     /// MAIL FROM:
     From(String),
     /// RCPT TO:
@@ -19,12 +23,113 @@ pub enum Command<'a> {
     DataStart,
     /// Internal, data ended
     DataEnd,
+    /// BDAT, with the size of the chunk following the line and if it is the last one
+    Bdat(usize, bool),
     /// NOOP
     Noop,
     /// RSET
     Reset,
     /// QUIT
     Quit,
+    /// VRFY, with the user or the mailbox to verify
+    Vrfy(String),
+    /// EXPN, with the mailing list to expand
+    Expn(String),
+    /// HELP, the topic is ignored
+    Help,
     /// An error with it's message
     Error(String),
 }
+
+impl<'a> Command<'a> {
+    /// Identify the command sent by the client
+    ///
+    /// `receive_data` tells if the line is part of the DATA content, and
+    /// `use_starttls` if the STARTTLS command is accepted
+    ///
+    /// Only the ASCII letters are lowercased, so the arguments are at the same
+    /// offsets in the line, even if it has UTF-8 addresses.
+    pub fn parse(command_line: Cow<'a, str>, receive_data: bool, use_starttls: bool) -> Self {
+        if !receive_data {
+            let args = |offset: usize| command_line.get(offset..).unwrap_or_default();
+            match command_line.to_ascii_lowercase().as_str() {
+                "data" => Self::DataStart,
+                "rset" => Self::Reset,
+                "quit" => Self::Quit,
+                "starttls" if use_starttls => Self::StartTls,
+                // Helo
+                line if line.len() > 5 && line.starts_with("helo ") => {
+                    Self::Hello(args(5).to_owned())
+                }
+                // Ehlo
+                line if line.len() > 5 && line.starts_with("ehlo ") => {
+                    Self::Ehllo(args(5).to_owned())
+                }
+                // Auth
+                line if line.len() > 5 && line.starts_with("auth ") => {
+                    Self::Auth(args(5).trim().to_owned())
+                }
+                // Bdat
+                line if line.len() > 5 && line.starts_with("bdat ") => {
+                    let mut params = line.get(5..).unwrap_or_default().split_whitespace();
+                    let size: Option<usize> = params.next().and_then(|size| size.parse().ok());
+                    match (size, params.next(), params.next()) {
+                        (Some(size), None, None) => Self::Bdat(size, false),
+                        (Some(size), Some("last"), None) => Self::Bdat(size, true),
+                        _ => Self::Error(command_line.to_string()),
+                    }
+                }
+                // From
+                line if line.len() > 10 && line.starts_with("mail from:") => {
+                    Self::From(args(10).trim_start().to_owned())
+                }
+                // To
+                line if line.len() > 8 && line.starts_with("rcpt to:") => {
+                    Self::Recipient(args(8).trim_start().to_owned())
+                }
+                // Noop
+                line if line == "noop" || line.starts_with("noop ") => Self::Noop,
+                // Vrfy
+                line if line == "vrfy" || line.starts_with("vrfy ") => {
+                    Self::Vrfy(args(4).trim().to_owned())
+                }
+                // Expn
+                line if line == "expn" || line.starts_with("expn ") => {
+                    Self::Expn(args(4).trim().to_owned())
+                }
+                // Help
+                line if line == "help" || line.starts_with("help ") => Self::Help,
+                // Anything else
+                _ => Self::Error(command_line.to_string()),
+            }
+        } else if &command_line == "." {
+            Self::DataEnd
+        } else {
+            Self::Data(command_line)
+        }
+    }
+
+    /// Verb of the command sent by the client, `None` for the lines of the
+    /// content and the responses to the authentication challenges
+    pub const fn verb(&self) -> Option<&'static str> {
+        Some(match *self {
+            Self::Hello(_) => "HELO",
+            Self::Ehllo(_) => "EHLO",
+            Self::StartTls => "STARTTLS",
+            Self::Auth(_) => "AUTH",
+            Self::From(_) => "MAIL",
+            Self::Recipient(_) => "RCPT",
+            Self::DataStart => "DATA",
+            Self::Bdat(..) => "BDAT",
+            Self::Noop => "NOOP",
+            Self::Reset => "RSET",
+            Self::Quit => "QUIT",
+            Self::Vrfy(_) => "VRFY",
+            Self::Expn(_) => "EXPN",
+            Self::Help => "HELP",
+            // Unknown, or with invalid arguments
+            Self::Error(_) => "INVALID",
+            Self::AuthResponse(_) | Self::Data(_) | Self::DataEnd => return None,
+        })
+    }
+}
@@ -0,0 +1,188 @@
+use std::{convert::TryFrom, net::IpAddr, sync::Arc, time::Duration};
+
+use async_std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use fnv::FnvHashMap;
+use tide::prelude::Serialize;
+
+/// Client address, expeditor and recipient of a delivery attempt, the addresses
+/// in lowercase
+type Triple = (Option<IpAddr>, String, String);
+
+/// Delivery attempts of a client, from an expeditor to a recipient, given by
+/// `/api/greylist`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Attempts {
+    /// Address of the client
+    pub ip: Option<IpAddr>,
+    /// Expeditor address of the envelope, empty for the null reverse path
+    pub from: String,
+    /// Recipient address
+    pub to: String,
+    /// Times of the attempts, as epoch
+    pub times: Vec<i64>,
+    /// Number of attempts deferred with a 450 reply
+    pub deferred: usize,
+    /// Time the recipient was accepted the first time, as epoch, `None` while
+    /// the attempts are deferred
+    pub passed: Option<i64>,
+}
+
+/// Simulated greylisting, shared with the HTTP side to see the attempts
+///
+/// The first attempt to deliver to a recipient, from a client and an
+/// expeditor, is deferred with a 450 reply, like a server greylisting the
+/// unknown senders does. The attempts coming once the delay is over are
+/// accepted, and so are the next ones.
+#[derive(Clone, Debug, Default)]
+pub struct Greylist {
+    /// Time before a new attempt is accepted, no attempt is deferred if `None`
+    delay: Option<Duration>,
+    /// Attempts, by client, expeditor and recipient
+    attempts: Arc<Mutex<FnvHashMap<Triple, Attempts>>>,
+}
+
+impl Greylist {
+    /// Greylisting accepting the attempts coming `delay` after the first one
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay: Some(delay),
+            ..Self::default()
+        }
+    }
+
+    /// Check if the attempts are greylisted
+    pub const fn is_enabled(&self) -> bool {
+        self.delay.is_some()
+    }
+
+    /// Record an attempt to deliver to a recipient, returning if it is accepted
+    pub async fn admit(&self, ip: Option<IpAddr>, from: &str, to: &str) -> bool {
+        let delay: Duration = match self.delay {
+            Some(delay) => delay,
+            None => return true,
+        };
+        let now: DateTime<Utc> = Utc::now();
+        let (from, to): (String, String) = (from.to_lowercase(), to.to_lowercase());
+
+        let mut attempts = self.attempts.lock().await;
+        let attempts: &mut Attempts = attempts
+            .entry((ip, from.clone(), to.clone()))
+            .or_insert_with(|| Attempts {
+                ip,
+                from,
+                to,
+                times: Vec::new(),
+                deferred: 0,
+                passed: None,
+            });
+        let first: Option<i64> = attempts.times.first().copied();
+        attempts.times.push(now.timestamp());
+
+        let waited: bool = first.map_or(false, |first| {
+            let delay: i64 = i64::try_from(delay.as_secs()).unwrap_or(i64::MAX);
+            now.timestamp().saturating_sub(first) >= delay
+        });
+        if attempts.passed.is_none() && waited {
+            attempts.passed = Some(now.timestamp());
+        }
+        if attempts.passed.is_none() {
+            attempts.deferred = attempts.deferred.saturating_add(1);
+        }
+        attempts.passed.is_some()
+    }
+
+    /// Attempts recorded, sorted by their first one
+    pub async fn history(&self) -> Vec<Attempts> {
+        let mut history: Vec<Attempts> = self.attempts.lock().await.values().cloned().collect();
+        history.sort_by(|a, b| a.times.first().cmp(&b.times.first()));
+        history
+    }
+
+    /// Forget the attempts, so the next ones are deferred again
+    pub async fn clear(&self) {
+        self.attempts.lock().await.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn greylisting() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        task::block_on(async {
+            let ip: Option<IpAddr> = Some("192.0.2.1".parse()?);
+
+            let disabled: Greylist = Greylist::default();
+            assert!(!disabled.is_enabled());
+            assert!(
+                disabled
+                    .admit(ip, "from@example.org", "to@example.net")
+                    .await
+            );
+            assert!(disabled.history().await.is_empty());
+
+            let greylist: Greylist = Greylist::new(Duration::default());
+            assert!(greylist.is_enabled());
+            assert!(
+                !greylist
+                    .admit(ip, "from@example.org", "to@example.net")
+                    .await
+            );
+            // Another recipient, or another client, is deferred too
+            assert!(
+                !greylist
+                    .admit(ip, "from@example.org", "cc@example.net")
+                    .await
+            );
+            assert!(
+                !greylist
+                    .admit(None, "from@example.org", "to@example.net")
+                    .await
+            );
+            // The retry is accepted, and the next attempts too
+            assert!(
+                greylist
+                    .admit(ip, "From@Example.org", "to@example.net")
+                    .await
+            );
+            assert!(
+                greylist
+                    .admit(ip, "from@example.org", "to@example.net")
+                    .await
+            );
+
+            let history: Vec<Attempts> = greylist.history().await;
+            assert_eq!(history.len(), 3);
+            let attempts: &Attempts = history
+                .iter()
+                .find(|attempts| attempts.ip == ip && attempts.to == "to@example.net")
+                .ok_or("no attempt")?;
+            assert_eq!(attempts.times.len(), 3);
+            assert_eq!(attempts.deferred, 1);
+            assert!(attempts.passed.is_some());
+
+            // A retry too early is deferred again
+            let slow: Greylist = Greylist::new(Duration::from_mins(5));
+            assert!(!slow.admit(ip, "", "to@example.net").await);
+            assert!(!slow.admit(ip, "", "to@example.net").await);
+            assert_eq!(slow.history().await.first().map(|a| a.deferred), Some(2));
+
+            greylist.clear().await;
+            assert!(greylist.history().await.is_empty());
+            assert!(
+                !greylist
+                    .admit(ip, "from@example.org", "to@example.net")
+                    .await
+            );
+
+            Ok(())
+        })
+    }
+}
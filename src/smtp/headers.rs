@@ -0,0 +1,125 @@
+use std::fmt;
+
+use crate::smtp::reply::Reply;
+
+/// Limits of the header section of the mails, against the pathological ones
+/// sent by a fuzzer, that would be slow to parse and to display
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// Largest number of header fields, without limit if `None`
+    pub count: Option<usize>,
+    /// Largest size of a header field, with its folded lines, in bytes,
+    /// without limit if `None`
+    pub length: Option<usize>,
+    /// Largest size of the whole header section, in bytes, without limit if
+    /// `None`
+    pub bytes: Option<usize>,
+}
+
+/// Limit of the header section exceeded by a mail
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exceeded {
+    /// More header fields than the limit
+    Count(usize),
+    /// A header field larger than the limit, in bytes
+    Length(usize),
+    /// A header section larger than the limit, in bytes
+    Bytes(usize),
+}
+
+impl Exceeded {
+    /// Reply refusing the mail
+    pub const fn reply(self) -> Reply {
+        match self {
+            Self::Count(_) => Reply::TooManyHeaderFields,
+            Self::Length(_) => Reply::HeaderFieldTooLong,
+            Self::Bytes(_) => Reply::HeaderTooLarge,
+        }
+    }
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Count(max) => write!(f, "more than {} header fields", max),
+            Self::Length(max) => write!(f, "a header field larger than {} bytes", max),
+            Self::Bytes(max) => write!(f, "a header section larger than {} bytes", max),
+        }
+    }
+}
+
+impl HeaderLimits {
+    /// Check the header section of the content of a mail, before it is parsed
+    ///
+    /// The section ends at the first empty line, the lines beginning with a
+    /// space or a tab being the continuation of the previous field. The sizes
+    /// count the line endings.
+    pub fn check(&self, data: &str) -> Result<(), Exceeded> {
+        let (mut count, mut length, mut bytes): (usize, usize, usize) = (0, 0, 0);
+        for line in data.split('\n') {
+            let line: &str = line.strip_suffix('\r').unwrap_or(line);
+            if line.is_empty() {
+                break;
+            }
+            let size: usize = line.len().saturating_add(2);
+            if line.starts_with([' ', '\t']) && count > 0 {
+                length = length.saturating_add(size);
+            } else {
+                count = count.saturating_add(1);
+                length = size;
+            }
+            bytes = bytes.saturating_add(size);
+
+            match (self.count, self.length, self.bytes) {
+                (Some(max), _, _) if count > max => return Err(Exceeded::Count(max)),
+                (_, Some(max), _) if length > max => return Err(Exceeded::Length(max)),
+                (_, _, Some(max)) if bytes > max => return Err(Exceeded::Bytes(max)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_limits() {
+        crate::test::log_init();
+
+        let data: &str = "Subject: test\r\nTo: one@example.net,\r\n two@example.net\r\n\
+            \r\nBody: not a header\r\nBody: not a header";
+
+        assert_eq!(HeaderLimits::default().check(data), Ok(()));
+        let limits: HeaderLimits = HeaderLimits {
+            count: Some(2),
+            length: Some(40),
+            bytes: Some(55),
+        };
+        assert_eq!(limits.check(data), Ok(()));
+        assert_eq!(limits.check(""), Ok(()));
+
+        let limits: HeaderLimits = HeaderLimits {
+            count: Some(1),
+            ..HeaderLimits::default()
+        };
+        assert_eq!(limits.check(data), Err(Exceeded::Count(1)));
+        // The folded lines are part of the field
+        let limits: HeaderLimits = HeaderLimits {
+            length: Some(39),
+            ..HeaderLimits::default()
+        };
+        assert_eq!(limits.check(data), Err(Exceeded::Length(39)));
+        let limits: HeaderLimits = HeaderLimits {
+            bytes: Some(54),
+            ..HeaderLimits::default()
+        };
+        assert_eq!(limits.check(data), Err(Exceeded::Bytes(54)));
+        assert_eq!(
+            Exceeded::Bytes(54).to_string(),
+            "a header section larger than 54 bytes"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_std::task;
+use tide::prelude::{Deserialize, Serialize};
+use ulid::Ulid;
+
+/// Delay before each reply, with a random part so the replies are not all as slow
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Delay {
+    /// Fixed part of the delay, in milliseconds
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Largest random part added to the fixed one, in milliseconds
+    #[serde(default)]
+    pub jitter_ms: u64,
+}
+
+/// Latency of the SMTP replies, shared with the HTTP side to change it while
+/// running, to test the timeouts of the clients and their connection pools
+#[derive(Clone, Debug, Default)]
+pub struct Latency {
+    /// Fixed part of the delay, in milliseconds
+    delay_ms: Arc<AtomicU64>,
+    /// Largest random part of the delay, in milliseconds
+    jitter_ms: Arc<AtomicU64>,
+}
+
+impl Latency {
+    /// Delay the replies by `delay`
+    pub fn new(delay: Delay) -> Self {
+        let latency: Self = Self::default();
+        latency.set(delay);
+        latency
+    }
+
+    /// Change the delay, for the next replies of all the sessions
+    pub fn set(&self, delay: Delay) {
+        self.delay_ms.store(delay.delay_ms, Ordering::Relaxed);
+        self.jitter_ms.store(delay.jitter_ms, Ordering::Relaxed);
+    }
+
+    /// Delay in use
+    pub fn get(&self) -> Delay {
+        Delay {
+            delay_ms: self.delay_ms.load(Ordering::Relaxed),
+            jitter_ms: self.jitter_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Time to wait before the next reply, the random part being drawn again
+    /// each time
+    fn next(&self) -> Duration {
+        let Delay {
+            delay_ms,
+            jitter_ms,
+        } = self.get();
+        let jitter: u64 = if jitter_ms == 0 {
+            0
+        } else {
+            // The random part of an ULID
+            let random: u64 =
+                u64::try_from(u128::from(Ulid::new()) & u128::from(u64::MAX)).unwrap_or_default();
+            random
+                .checked_rem(jitter_ms.saturating_add(1))
+                .unwrap_or_default()
+        };
+        Duration::from_millis(delay_ms.saturating_add(jitter))
+    }
+
+    /// Wait before a reply, if there is a latency
+    pub async fn wait(&self) {
+        let delay: Duration = self.next();
+        if delay > Duration::default() {
+            task::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn jittered_delays() {
+        crate::test::log_init();
+
+        let latency: Latency = Latency::default();
+        assert_eq!(latency.next(), Duration::default());
+
+        latency.set(Delay {
+            delay_ms: 20,
+            jitter_ms: 10,
+        });
+        let delays: Vec<u128> = (0..100).map(|_| latency.next().as_millis()).collect();
+        assert!(delays.iter().all(|delay| (20..=30).contains(delay)));
+        assert!(delays.iter().any(|&delay| delay != 20));
+
+        let start: Instant = Instant::now();
+        task::block_on(latency.wait());
+        assert!(start.elapsed().as_millis() >= 20);
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::IpAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_std::sync::Mutex;
+use fnv::FnvHashMap;
+
+/// Largest number of connections from an address during a period, like `60/min`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rate {
+    /// Number of connections
+    count: usize,
+    /// Period during which they are counted
+    period: Duration,
+}
+
+impl Rate {
+    /// Number of events allowed during the period
+    pub const fn count(self) -> usize {
+        self.count
+    }
+
+    /// Period during which the events are counted
+    pub const fn period(self) -> Duration {
+        self.period
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid rate {}, expected like 60/min, 10/s or 1000/h", s);
+        let (count, period): (&str, &str) = s.trim().split_once('/').ok_or_else(invalid)?;
+        let secs: u64 = match period {
+            "s" => 1,
+            "min" => 60,
+            "h" => 3_600,
+            _ => return Err(invalid()),
+        };
+        match count.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(Self {
+                count,
+                period: Duration::from_secs(secs),
+            }),
+            Ok(_) | Err(_) => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let period: &str = match self.period.as_secs() {
+            1 => "s",
+            60 => "min",
+            _ => "h",
+        };
+        write!(f, "{}/{}", self.count, period)
+    }
+}
+
+/// Limits of the SMTP connections, shared by the listeners so the load tests
+/// cannot spawn an unbounded number of sessions
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// Largest number of sessions at the same time
+    max_connections: Option<usize>,
+    /// Largest number of connections from an address
+    rate: Option<Rate>,
+    /// Number of sessions in progress
+    connections: Arc<AtomicUsize>,
+    /// Times of the recent connections, by address of the client
+    recent: Arc<Mutex<FnvHashMap<IpAddr, VecDeque<Instant>>>>,
+}
+
+impl Limits {
+    /// Limit the number of sessions at the same time, and the rate of the
+    /// connections from each address
+    pub fn new(max_connections: Option<usize>, rate: Option<Rate>) -> Self {
+        Self {
+            max_connections,
+            rate,
+            ..Self::default()
+        }
+    }
+
+    /// Admit a new client from `ip`, unless a limit is exceeded
+    ///
+    /// The session keeps the slot until it is over.
+    pub async fn admit(&self, ip: Option<IpAddr>) -> Option<Slot> {
+        let max_connections: usize = self.max_connections.unwrap_or(usize::MAX);
+        if self
+            .connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |connections| {
+                (connections < max_connections).then(|| connections.saturating_add(1))
+            })
+            .is_err()
+        {
+            log::warn!(
+                "Connection from {:?} refused, {} sessions in progress",
+                ip,
+                max_connections
+            );
+            return None;
+        }
+        let slot: Slot = Slot {
+            connections: Arc::clone(&self.connections),
+        };
+
+        if let (Some(rate), Some(ip)) = (self.rate, ip) {
+            let mut recent = self.recent.lock().await;
+            // Forget the connections out of the period, and the addresses without any
+            recent.retain(|_, times| {
+                while times
+                    .front()
+                    .map_or(false, |time| time.elapsed() >= rate.period)
+                {
+                    let _ = times.pop_front();
+                }
+                !times.is_empty()
+            });
+            let times: &mut VecDeque<Instant> = recent.entry(ip).or_default();
+            if times.len() >= rate.count {
+                log::warn!("Connection from {} refused, more than {}", ip, rate);
+                return None;
+            }
+            times.push_back(Instant::now());
+        }
+
+        Some(slot)
+    }
+}
+
+/// Place of a session in the limit of the connections, released once dropped
+#[derive(Debug)]
+pub struct Slot {
+    /// Number of sessions in progress
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let _ = self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+
+    #[test]
+    fn rates() {
+        crate::test::log_init();
+
+        for &(rate, count, secs) in &[
+            ("60/min", 60, 60),
+            ("10/s", 10, 1),
+            ("1000/h", 1_000, 3_600),
+        ] {
+            let parsed: Result<Rate, String> = rate.parse();
+            assert_eq!(
+                parsed
+                    .as_ref()
+                    .map(|parsed| (parsed.count, parsed.period.as_secs())),
+                Ok((count, secs))
+            );
+            assert_eq!(parsed.map(|parsed| parsed.to_string()), Ok(rate.to_owned()));
+        }
+        for &invalid in &["60", "0/min", "-1/min", "60/day", "/min"] {
+            assert!(invalid.parse::<Rate>().is_err());
+        }
+    }
+
+    #[test]
+    fn admit_clients() {
+        crate::test::log_init();
+
+        let limits: Limits = Limits::new(Some(2), "3/h".parse().ok());
+        let alice: Option<IpAddr> = "192.0.2.1".parse().ok();
+        let bob: Option<IpAddr> = "192.0.2.2".parse().ok();
+
+        task::block_on(async {
+            let first: Option<Slot> = limits.admit(alice).await;
+            let second: Option<Slot> = limits.admit(bob).await;
+            assert!(first.is_some() && second.is_some());
+            // Too many sessions at the same time
+            assert!(limits.admit(bob).await.is_none());
+            drop(second);
+            // Too many connections from alice, the refused ones are not counted
+            assert!(limits.admit(alice).await.is_some());
+            assert!(limits.admit(alice).await.is_some());
+            assert!(limits.admit(alice).await.is_none());
+            assert!(limits.admit(bob).await.is_some());
+            // Without address, only the number of sessions is limited
+            assert!(limits.admit(None).await.is_some());
+        });
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tide::prelude::Serialize;
+
+/// Counters of the SMTP sessions, shared with the HTTP side to measure the load
+/// generated by the clients, like a test suite
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    /// Counters, updated by all the sessions
+    counters: Arc<Counters>,
+}
+
+/// Counters of the SMTP sessions
+#[derive(Debug, Default)]
+struct Counters {
+    /// Connections accepted
+    connections: AtomicU64,
+    /// Commands received, by verb
+    commands: Mutex<BTreeMap<&'static str, u64>>,
+    /// Bytes received, commands and contents
+    bytes: AtomicU64,
+    /// Commands refused with a 4xx or 5xx reply
+    rejected: AtomicU64,
+    /// Contents received, with DATA or BDAT
+    contents: AtomicU64,
+    /// Bytes of the contents received
+    content_bytes: AtomicU64,
+}
+
+/// Counters of the SMTP sessions at a given time, given by `/api/metrics`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    /// Connections accepted
+    pub connections: u64,
+    /// Commands received, by verb
+    pub commands: BTreeMap<String, u64>,
+    /// Bytes received, commands and contents
+    pub bytes_received: u64,
+    /// Commands refused with a 4xx or 5xx reply
+    pub rejected: u64,
+    /// Contents received, with DATA or BDAT
+    pub contents: u64,
+    /// Mean size of the contents received, in bytes
+    pub mean_data_size: u64,
+}
+
+impl Metrics {
+    /// Count an accepted connection
+    pub fn connection(&self) {
+        let _ = self.counters.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a command, by its verb
+    pub fn command(&self, verb: &'static str) {
+        if let Ok(mut commands) = self.counters.commands.lock() {
+            let count: &mut u64 = commands.entry(verb).or_default();
+            *count = count.saturating_add(1);
+        }
+    }
+
+    /// Count the bytes received
+    pub fn received(&self, bytes: usize) {
+        let bytes: u64 = u64::try_from(bytes).unwrap_or(u64::MAX);
+        let _ = self.counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Count a command refused
+    pub fn rejected(&self) {
+        let _ = self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a content received, with its size
+    pub fn content(&self, size: usize) {
+        let size: u64 = u64::try_from(size).unwrap_or(u64::MAX);
+        let _ = self.counters.contents.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .counters
+            .content_bytes
+            .fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Counters at this time
+    pub fn snapshot(&self) -> Snapshot {
+        let contents: u64 = self.counters.contents.load(Ordering::Relaxed);
+        let content_bytes: u64 = self.counters.content_bytes.load(Ordering::Relaxed);
+        Snapshot {
+            connections: self.counters.connections.load(Ordering::Relaxed),
+            commands: self
+                .counters
+                .commands
+                .lock()
+                .map(|commands| {
+                    commands
+                        .iter()
+                        .map(|(&verb, &count)| (verb.to_owned(), count))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            bytes_received: self.counters.bytes.load(Ordering::Relaxed),
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+            contents,
+            mean_data_size: content_bytes.checked_div(contents).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_sessions() {
+        crate::test::log_init();
+
+        let metrics: Metrics = Metrics::default();
+        assert_eq!(metrics.snapshot(), Snapshot::default());
+
+        let shared: Metrics = metrics.clone();
+        shared.connection();
+        shared.command("EHLO");
+        shared.command("RCPT");
+        shared.command("RCPT");
+        shared.received(30);
+        shared.rejected();
+        shared.content(100);
+        shared.content(51);
+
+        let snapshot: Snapshot = metrics.snapshot();
+        assert_eq!(snapshot.connections, 1);
+        assert_eq!(snapshot.commands.get("EHLO"), Some(&1));
+        assert_eq!(snapshot.commands.get("RCPT"), Some(&2));
+        assert_eq!(snapshot.commands.get("MAIL"), None);
+        assert_eq!(snapshot.bytes_received, 30);
+        assert_eq!(snapshot.rejected, 1);
+        assert_eq!(snapshot.contents, 2);
+        assert_eq!(snapshot.mean_data_size, 75);
+    }
+}
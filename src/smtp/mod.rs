@@ -1,51 +1,236 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use async_std::{
-    channel::Sender,
-    io::BufReader,
-    net::{Incoming, SocketAddr, TcpListener, ToSocketAddrs},
-    stream, task,
+    channel::{Receiver, Sender},
+    io::{self, BufReader},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    prelude::FutureExt as _,
+    stream,
 };
+use chrono::Utc;
 use futures::{
     stream::FuturesUnordered,
-    AsyncRead, AsyncWrite, {future, AsyncBufReadExt, AsyncWriteExt, StreamExt},
+    AsyncBufRead, AsyncRead, AsyncWrite,
+    {future, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, StreamExt},
 };
 
-use crate::{mail::Mail, smtp::command::Command, utils::ConnectionInfo};
+use crate::{
+    encoding::decode_8bit,
+    error::MailcatcherError,
+    mail::{
+        diagnostic::Diagnostic, dsn, journal::Journal, timing::Stage, transcript::Transcript,
+        Client, Mail,
+    },
+    otlp::{Span, Tracer},
+    settings::{Settings, SharedSettings},
+    shutdown::Activity,
+    smtp::{
+        address::Path,
+        auth::{decode, decode_plain, AuthStep, Credentials},
+        capture::Capture,
+        command::Command,
+        greylist::Greylist,
+        headers::HeaderLimits,
+        latency::Latency,
+        limit::{Limits, Slot},
+        metrics::Metrics,
+        profile::Profile,
+        quarantine::{Cutoff, Incomplete, Quarantine},
+        queue::Queue,
+        reply::{Replies, Reply},
+    },
+    utils::ConnectionInfo,
+};
 
+/// Addresses of the envelope, with their ESMTP parameters
+pub mod address;
+/// Authentication of the clients
+pub mod auth;
+/// Capture of the mails under a heavy load
+pub mod capture;
 /// SMTP command enum
 mod command;
+/// Simulated greylisting of the recipients
+pub mod greylist;
+/// Limits of the header section of the mails
+pub mod headers;
+/// Delay of the replies, against the clients that expect a fast server
+pub mod latency;
+/// Limits of the connections, against the load tests
+pub mod limit;
+/// Counters of the sessions, to measure the load
+pub mod metrics;
+/// Behaviors of the listeners, to play several servers
+pub mod profile;
+/// Relay to an upstream SMTP server
+mod proxy;
+/// Transactions cut off in the middle of their content
+pub mod quarantine;
+/// Queue of the received mails, waiting for the mail broker
+pub mod queue;
+/// Delivery of the caught mails to a real SMTP server
+pub mod release;
+/// Replies of the server, whose text can be changed
+pub mod reply;
+/// Rules accepting or rejecting the recipients
+pub mod rules;
+
+/// Replacement of the credentials in the transcripts
+const MASK: &str = "*****";
+
+/// Size of the replies kept before writing them, even if more pipelined
+/// commands are waiting
+const MAX_PENDING_REPLIES: usize = 4_096;
+
+/// Parameters of the SMTP side, that are kept when the listeners are rebound
+#[derive(Clone, Debug)]
+pub struct Params {
+    /// Name of the server, given in the greeting
+    pub server_name: String,
+    /// Sender stream of the received mails
+    pub mails_broker: Sender<Mail>,
+    /// Allow to use STARTTLS
+    pub use_starttls: bool,
+    /// Real SMTP server where to relay the sessions
+    pub upstream: Option<String>,
+    /// Journal where the mails are written before being acknowledged
+    pub journal: Option<Journal>,
+    /// Settings that can be changed at runtime
+    pub settings: SharedSettings,
+    /// Credentials the clients must give with AUTH, any one is accepted if none
+    pub credentials: Option<Credentials>,
+    /// Exporter of the spans of the sessions
+    pub tracer: Option<Tracer>,
+    /// Switch simulating an outage of the service
+    pub pause: Pause,
+    /// Time a client can stay silent before being disconnected, without limit
+    /// if `None`
+    pub idle_timeout: Option<Duration>,
+    /// Longest duration of a session, without limit if `None`
+    pub session_timeout: Option<Duration>,
+    /// Limits of the connections, shared by the listeners
+    pub limits: Limits,
+    /// Delay before each reply, shared with the HTTP side
+    pub latency: Latency,
+    /// Largest number of recipients of a mail, without limit if `None`
+    pub max_recipients: Option<usize>,
+    /// Limits of the header section of the mails
+    pub header_limits: HeaderLimits,
+    /// Delay before the greeting, sent at once if `None`
+    pub banner_delay: Option<Duration>,
+    /// Refuse the clients talking before the greeting, during its delay
+    pub reject_early_talkers: bool,
+    /// Handling of the lines ended by a bare LF
+    pub line_endings: LineEndings,
+    /// Queue of the received mails, shared with the HTTP side
+    pub queue: Queue,
+    /// Greylisting of the recipients, shared with the HTTP side
+    pub greylist: Greylist,
+    /// Counters of the sessions, shared with the HTTP side
+    pub metrics: Metrics,
+    /// Transactions cut off in the middle of their content, shared with the
+    /// HTTP side
+    pub quarantine: Quarantine,
+    /// Capture of the mails under a heavy load, shared with the HTTP side
+    pub capture: Capture,
+    /// Last activity, kept up by the connections and the lines received,
+    /// shared with the HTTP side
+    pub activity: Activity,
+}
+
+/// Switch simulating an outage of the SMTP service, shared with the HTTP side
+///
+/// While paused, the new clients and those between two transactions are
+/// answered with a 421 reply and disconnected, the mails already received are
+/// kept.
+#[derive(Clone, Debug, Default)]
+pub struct Pause {
+    /// The service is paused
+    paused: Arc<AtomicBool>,
+}
+
+impl Pause {
+    /// Pause or resume the service
+    pub fn set(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Check if the service is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
+/// Listener of the SMTP side, with the behavior of its sessions
+#[derive(Debug)]
+pub struct Listener {
+    /// Bound socket
+    pub socket: TcpListener,
+    /// Behavior of the sessions accepted
+    pub profile: Profile,
+}
+
+impl From<TcpListener> for Listener {
+    /// Listener catching the mails
+    fn from(socket: TcpListener) -> Self {
+        Self {
+            socket,
+            profile: Profile::Normal,
+        }
+    }
+}
+
+/// Handling of the lines ended by a bare LF instead of CRLF
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEndings {
+    /// The lines are accepted, and normalized to CRLF in the mails
+    Lenient,
+    /// The commands and the mails with such lines are rejected, as RFC 5321
+    /// asks
+    Strict,
+}
+
+impl Default for LineEndings {
+    fn default() -> Self {
+        Self::Lenient
+    }
+}
 
-/// SMTP return message: All is alright, please continue
-const MSG_250_OK: &[u8] = b"250 OK\r\n";
-/// SMTP return message: DATA accepted, starting mail content
-const MSG_354_NEXT_DATA: &[u8] = b"354 Start mail input; end with <CRLF>.<CRLF>\r\n";
-/// SMTP return message: Line length is too long
-const MSG_500_LENGTH_TOO_LONG: &[u8] = b"500 Line too long.\r\n";
-/// SMTP return message: Command not understood
-const MSG_502_NOT_IMPLEMENTED: &[u8] = b"502 Command not implemented\r\n";
-/// SMTP return message: Command not allowed here
-const MSG_503_BAD_SEQUENCE: &[u8] = b"503 Bad sequence of commands\r\n";
-
-/// Serve SMTP
+/// Serve SMTP on the bound `listeners`, each with its profile, until `stop` is
+/// closed
+///
+/// If an `upstream` server is specified, the sessions are relayed to it and only
+/// a copy of the mails is kept, whatever the profile. If a `journal` is specified, each mail is written
+/// in it before being acknowledged. The `settings` tell if only the headers of
+/// the large mails are kept.
+///
+/// Once stopped, the listeners are closed at once, but this returns only when
+/// the sessions in progress are over.
 pub async fn serve(
-    port: u16,
-    server_name: &str,
-    mails_broker: Sender<Mail>,
-    use_starttls: bool,
+    listeners: Vec<Listener>,
+    stop: Receiver<()>,
+    params: Params,
 ) -> crate::Result<()> {
-    // Convert port to socket address
-    let addr: Vec<SocketAddr> = format!("localhost:{}", port)
-        .to_socket_addrs()
-        .await?
-        .collect();
-
-    // For each socket address IPv4/IPv6 ...
-    addr.iter()
-        // ... bind TCP port for each address ...
-        .map(bind)
-        // ... spawn a handler to process incoming connection
-        .map(|listener| accept_loop(listener, server_name, mails_broker.clone(), use_starttls))
+    // Fail now if the upstream server cannot be found, instead of on each connection
+    if let Some(ref upstream) = params.upstream {
+        let _ = upstream.to_socket_addrs().await.map_err(|e| {
+            MailcatcherError::config(format!("Invalid upstream SMTP server {}: {}", upstream, e))
+        })?;
+    }
+
+    // For each socket address IPv4/IPv6, spawn a handler to process incoming connection
+    listeners
+        .into_iter()
+        .map(|listener| accept_loop(listener, stop.clone(), &params))
         .collect::<FuturesUnordered<_>>()
         .skip_while(|r| future::ready(r.is_ok()))
         .take(1)
@@ -58,224 +243,968 @@ pub async fn serve(
         .await
 }
 
-/// Handler that deals to a single socket address
-#[allow(clippy::panic)]
-fn bind(addr: &SocketAddr) -> TcpListener {
-    // Bind to the address
-    match task::block_on(async move {
-        TcpListener::bind(addr)
-            .await
-            .map_err(|e| format!("Unable to bind {:?}: {}", addr, e))
-    }) {
-        Ok(socket) => socket,
-        Err(e) => {
-            panic!("{:?}", e)
+/// Accept the connections of a listener, until `stop` is closed
+async fn accept_loop(listener: Listener, stop: Receiver<()>, params: &Params) -> crate::Result<()> {
+    let Listener {
+        socket: listener,
+        profile,
+    } = listener;
+    let addr: SocketAddr = listener.local_addr().map_err(MailcatcherError::smtp)?;
+    log::info!("SMTP listening on {:?}, {}", addr, profile);
+
+    // Listen to incoming connection, the listener is dropped as soon as it is
+    // stopped so its port can be bound again
+    let incoming = futures::stream::unfold((listener, stop), |(listener, stop)| async move {
+        let accepted: Option<io::Result<TcpStream>> =
+            async { Some(listener.accept().await.map(|(stream, _)| stream)) }
+                .race(async {
+                    stop.recv().await.unwrap_or_default();
+                    None
+                })
+                .await;
+        if accepted.is_none() {
+            log::info!("SMTP stops listening on {:?}, draining its sessions", addr);
         }
-    }
-}
-
-/// Handler that deals to a single socket address
-async fn accept_loop(
-    listener: TcpListener,
-    server_name: &str,
-    mails_broker: Sender<Mail>,
-    use_starttls: bool,
-) -> crate::Result<()> {
-    // Listen to incoming connection
-    let incoming: Incoming = listener.incoming();
-    log::info!("SMTP listening on {:?}", listener.local_addr()?);
+        accepted.map(|stream| (stream, (listener, stop)))
+    });
 
     // Stream to repeat mails sender stream
-    let mails_sender = stream::repeat(mails_broker);
+    let mails_sender = stream::repeat(params.mails_broker.clone());
 
     // For each new connection
     incoming
         .zip(mails_sender)
         .for_each_concurrent(None, |(stream, mails_broker)| async move {
-            // Retrieve the Stream
-            let stream = stream.expect("tcp stream");
+            // Retrieve the Stream, a failed accept only loses that client
+            let mut stream: TcpStream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::error!("SMTP connection not accepted: {}", e);
+                    return;
+                }
+            };
+            let settings: Settings = params.settings.get().await;
+            // Refuse the clients that are not allowed, before anything else, then
+            // simulate an outage if paused, the client may try again later
+            let refusal: Option<Reply> = match stream.peer_addr() {
+                Ok(peer_addr) if !settings.is_allowed(peer_addr.ip()) => {
+                    log::warn!("Connection from {} denied", peer_addr);
+                    Some(Reply::AccessDenied)
+                }
+                _ if params.pause.is_paused() => {
+                    log::info!("SMTP paused, connection closed");
+                    Some(Reply::Unavailable)
+                }
+                _ => None,
+            };
+            if let Some(refusal) = refusal {
+                refuse(&mut stream, refusal, &settings, &params.server_name).await;
+                return;
+            }
+            // Limit the load, the slot is released once the session is over
+            let peer_ip: Option<IpAddr> = stream.peer_addr().ok().map(|addr| addr.ip());
+            let slot: Option<Slot> = params.limits.admit(peer_ip).await;
+            if slot.is_none() {
+                let reply: Reply = Reply::TooManyConnections;
+                refuse(&mut stream, reply, &settings, &params.server_name).await;
+                return;
+            }
+            params.metrics.connection();
+            params.activity.touch();
             // New connection for information
             let conn: ConnectionInfo =
                 ConnectionInfo::new(stream.local_addr().ok(), stream.peer_addr().ok());
-            log::info!(
-                "Accepting new connection from: {}",
-                stream.peer_addr().expect("peer address")
-            );
-            // Spawn local processing
-            connection_loop(
-                stream,
-                conn,
-                server_name.to_owned(),
-                use_starttls,
-                mails_broker,
-            )
-            .await
-            .expect("connection processed");
+            log::info!("Accepting new connection from: {:?}", conn.peer_addr);
+            let conn_peer: Option<SocketAddr> = conn.peer_addr;
+            // Trace the session, if the spans are exported
+            let mut span: Option<Span> = params.tracer.as_ref().map(|_| session_span(&conn));
+            let result: crate::Result<()> = if let Some(ref upstream) = params.upstream {
+                // Relay the session to the real SMTP server, the settings in use at the
+                // connection apply to the whole session
+                proxy::passthrough(
+                    stream,
+                    upstream,
+                    conn,
+                    mails_broker,
+                    params.journal.clone(),
+                    &settings,
+                    &params.server_name,
+                )
+                .await
+            } else {
+                // Spawn local processing
+                connection_loop(stream, conn, mails_broker, params, profile, span.as_mut()).await
+            };
+            // A failed session, like a client resetting its connection, only
+            // ends that session, the listener goes on accepting the others
+            if let Err(ref e) = result {
+                log::warn!("SMTP session with {:?} failed: {}", conn_peer, e);
+            }
+            if let (Some(tracer), Some(mut span)) = (params.tracer.as_ref(), span) {
+                if let Err(ref e) = result {
+                    span.fail(e);
+                }
+                tracer.end(&span);
+            }
         })
         .await;
+    log::info!("SMTP sessions on {:?} are over", addr);
 
     Ok(())
 }
 
+/// Refuse a client, with the `reply` telling why
+async fn refuse(stream: &mut TcpStream, reply: Reply, settings: &Settings, server_name: &str) {
+    stream
+        .write_all(reply.message(&settings.replies, server_name).as_bytes())
+        .await
+        .unwrap_or_default();
+}
+
+/// Span of a SMTP session, with the addresses of the connection
+fn session_span(conn: &ConnectionInfo) -> Span {
+    let mut span: Span = Span::new("smtp session");
+    if let Some(peer_addr) = conn.peer_addr {
+        span.attribute("net.peer.ip", peer_addr.ip().to_string());
+        span.attribute("net.peer.port", peer_addr.port());
+    }
+    if let Some(local_addr) = conn.local_addr {
+        span.attribute("net.host.port", local_addr.port());
+    }
+    span
+}
+
 /// Deals with each new connection
+///
+/// The client name, the authenticated user and the number of mails are added
+/// to the `span` of the session.
 async fn connection_loop<S>(
     stream: S,
     conn: ConnectionInfo,
-    server_name: String,
-    use_starttls: bool,
     mails_broker: Sender<Mail>,
+    params: &Params,
+    profile: Profile,
+    span: Option<&mut Span>,
 ) -> crate::Result<()>
 where
     S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone,
 {
     // Initialize the SMTP connection
-    let mut smtp = Smtp::new(&stream, server_name, use_starttls);
+    let replies: Replies = params.settings.get().await.replies;
+    let mut smtp = Smtp::new(&stream, params, conn.peer_addr, replies, profile);
 
-    // Send SMTP banner to client
-    smtp.send_server_name().await?;
+    // Generate a line reader to process commands, bytes are read as is to handle
+    // the 8-bit lines that are not valid UTF-8
+    let mut reader = BufReader::new(stream);
 
-    // Generate a line reader to process commands
-    let reader = BufReader::new(stream);
-    let mut lines = reader.lines();
+    // Send SMTP banner to client, unless it talked first
+    if !greet(&mut smtp, &mut reader, params).await? {
+        return Ok(());
+    }
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut mails: usize = 0;
+    let started: Instant = Instant::now();
 
     // Begin command loop
-    while let Some(line) = lines.next().await {
-        // Process a new command line
-        let line: String = line?;
+    loop {
+        let read = within(
+            read_timeout(params, started),
+            reader.read_until(b'\n', &mut buffer),
+        );
+        match smtp.checked(read.await).await? {
+            Some(0) => {
+                smtp.cut_off(Cutoff::Closed).await;
+                break;
+            }
+            Some(read) => {
+                params.metrics.received(read);
+                params.activity.touch();
+            }
+            None => {
+                log::info!("SMTP session timed out");
+                smtp.reply(Reply::Timeout).await?;
+                break;
+            }
+        }
+        let bare_lf: bool = buffer.ends_with(b"\n") && !buffer.ends_with(b"\r\n");
+        // Process a new command line, without its line ending
+        let line: String = {
+            let line: Cow<str> = decode_8bit(&buffer);
+            if let Cow::Owned(_) = line {
+                smtp.invalid_utf8();
+            }
+            let line: &str = line.strip_suffix('\n').unwrap_or(&line);
+            line.strip_suffix('\r').unwrap_or(line).to_owned()
+        };
+        buffer.clear();
+        // The service is paused between two transactions, not in the middle of a mail
+        if params.pause.is_paused() && !smtp.receive_data && smtp.chunks == 0 {
+            log::info!("SMTP paused, session closed");
+            smtp.reply(Reply::Unavailable).await?;
+            break;
+        }
+        smtp.record(&line);
         // Identify the action
         let action: Command = smtp.process_line(Cow::Owned(line));
         log::trace!("{:?}", action);
-        // Process the action
-        let mail: Option<Mail> = smtp.process_command(&action).await?;
+        if let Some(verb) = action.verb() {
+            params.metrics.command(verb);
+        }
+        // The chunk of BDAT follows its line, it is read even if the command is refused
+        if let Command::Bdat(size, _) = action {
+            if !read_chunk(&mut smtp, &mut reader, &action, size, params, started).await? {
+                break;
+            }
+        }
+        // Process the action, unless its line is refused
+        let mail: Option<Mail> = if bare_lf && smtp.bare_line_feed(&action).await? {
+            None
+        } else {
+            smtp.process_command(&action).await?
+        };
         // If a mail has been emitted, send it to the HTTP side
         if let Some(mail) = mail {
-            mails_broker.send(mail).await?;
+            params.metrics.content(mail.get_size());
+            enqueue(mail, &mails_broker, params).await?;
+            mails = mails.saturating_add(1);
         };
+        // The responses are written once the commands sent together by the client
+        // are processed, as the client waits for them before sending the next ones
+        if reader.buffer().is_empty() || matches!(action, Command::Quit) {
+            smtp.flush().await?;
+        }
         // If the command ask to quit, exit the command processing
         if let Command::Quit = action {
             break;
         }
     }
+    smtp.flush().await?;
 
     log::info!(">>> {}", conn);
+    if let Some(span) = span {
+        if let Some(ref remote_name) = smtp.remote_name {
+            span.attribute("smtp.helo", remote_name.as_str());
+        }
+        if let Some(ref auth_user) = smtp.auth_user {
+            span.attribute("smtp.auth_user", auth_user.as_str());
+        }
+        span.attribute("smtp.mails", mails);
+    }
+
+    Ok(())
+}
+
+/// Read the chunk following a BDAT command, returning `false` if the client
+/// closed the connection or stayed silent too long before its end
+async fn read_chunk<S, R>(
+    smtp: &mut Smtp<'_, S>,
+    reader: &mut R,
+    action: &Command<'_>,
+    size: usize,
+    params: &Params,
+    started: Instant,
+) -> crate::Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone,
+    R: AsyncBufRead + Unpin,
+{
+    let reading: Instant = Instant::now();
+    let mut chunk: Vec<u8> = Vec::new();
+    let mut taken = reader.take(u64::try_from(size).unwrap_or(u64::MAX));
+    let read = within(read_timeout(params, started), taken.read_to_end(&mut chunk)).await;
+    params.metrics.received(chunk.len());
+    params.activity.touch();
+    if chunk.len() < size {
+        // The partial chunk is kept with the transaction cut off
+        if smtp.is_valid(action) {
+            smtp.push_chunk(&chunk);
+        }
+        if smtp.checked(read).await?.is_some() {
+            log::warn!("Connection closed in a BDAT chunk");
+            smtp.cut_off(Cutoff::Closed).await;
+        } else {
+            log::info!("SMTP session timed out in a BDAT chunk");
+            smtp.reply(Reply::Timeout).await?;
+        }
+        return Ok(false);
+    }
+    smtp.chunk = chunk;
+    // The content is received from the first chunk
+    if smtp.chunks == 0 {
+        smtp.data_started = Some(reading);
+    }
+    Ok(true)
+}
 
+/// Send a received mail to the mail broker, dropping the oldest one waiting if
+/// the queue is full and its policy says so
+async fn enqueue(mail: Mail, mails_broker: &Sender<Mail>, params: &Params) -> crate::Result<()> {
+    if let Some(dropped) = params.queue.make_room() {
+        if let Some(ref journal) = params.journal {
+            if let Err(e) = journal.remove(dropped.get_id()).await {
+                log::error!(
+                    "Dropped mail {} kept in the journal: {}",
+                    dropped.get_id(),
+                    e
+                );
+            }
+        }
+    }
+    mails_broker.send(mail).await?;
+    params.queue.arrived().await;
     Ok(())
 }
 
+/// Time to wait for the client, the shortest of the idle timeout and of the
+/// time left in the session that `started`
+fn read_timeout(params: &Params, started: Instant) -> Option<Duration> {
+    let left: Option<Duration> = params
+        .session_timeout
+        .map(|session| session.saturating_sub(started.elapsed()));
+    match (params.idle_timeout, left) {
+        (Some(idle), Some(left)) => Some(idle.min(left)),
+        (idle, left) => idle.or(left),
+    }
+}
+
+/// Wait for a future, `None` if it is not over before the `timeout`
+async fn within<F: Future>(timeout: Option<Duration>, fut: F) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => fut.timeout(timeout).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// Send the greeting once its delay is over, returning `false` if the client
+/// was refused for talking before it
+async fn greet<S, R>(smtp: &mut Smtp<'_, S>, reader: &mut R, params: &Params) -> crate::Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone,
+    R: AsyncBufRead + Unpin,
+{
+    if let Some(delay) = params.banner_delay {
+        if params.reject_early_talkers {
+            if talks_early(reader, delay).await {
+                log::warn!("Client talking before the greeting refused");
+                smtp.reply(Reply::EarlyTalker).await?;
+                smtp.flush().await?;
+                return Ok(false);
+            }
+        } else {
+            async_std::task::sleep(delay).await;
+        }
+    }
+    smtp.send_server_name().await?;
+    smtp.flush().await?;
+    Ok(true)
+}
+
+/// Wait for the `delay` of the greeting, telling if the client sent something
+/// in the meantime, a client closing the connection having nothing to say
+async fn talks_early<R: AsyncBufRead + Unpin>(reader: &mut R, delay: Duration) -> bool {
+    match within(Some(delay), reader.fill_buf()).await {
+        Some(Ok(buffered)) => !buffered.is_empty(),
+        Some(Err(_)) | None => false,
+    }
+}
+
 /// SMTP transaction internal state
 struct Smtp<'a, S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone> {
     /// Server name identification (=my name)
     server_name: String,
     /// Stream where to write responses
     write_stream: S,
+    /// Responses waiting to be written, once the pipelined commands are processed
+    pending: Vec<u8>,
+    /// Custom texts of the replies, in use at the connection
+    replies: Replies,
     /// Use TLS for connection support
     use_starttls: bool,
     /// Reported remote client name
     remote_name: Option<String>,
+    /// The client greeted with EHLO
+    extended: bool,
+    /// Remote client address
+    peer_addr: Option<SocketAddr>,
     /// Expeditor mail address
-    addr_from: Option<String>,
+    addr_from: Option<Path>,
     /// Recipient(s) address
-    addr_to: Vec<String>,
+    addr_to: Vec<Path>,
     /// Are we in data reception or not
     receive_data: bool,
     /// Received data
     data: Cow<'a, str>,
+    /// Number of lines in the received data
+    data_lines: usize,
+    /// Lines of the received data that were not valid UTF-8
+    invalid_lines: Vec<usize>,
+    /// Lines of the received data ended by a bare LF
+    bare_lines: Vec<usize>,
+    /// Handling of the lines ended by a bare LF
+    line_endings: LineEndings,
+    /// Journal where to write the mails before acknowledging them
+    journal: Option<Journal>,
+    /// Settings that can change while the connection is open
+    settings: SharedSettings,
+    /// Credentials the client must give, any one is accepted if none
+    credentials: Option<Credentials>,
+    /// Step of the authentication in progress
+    auth_step: Option<AuthStep>,
+    /// User the client is authenticated as
+    auth_user: Option<String>,
+    /// Chunk read after the BDAT command being processed
+    chunk: Vec<u8>,
+    /// Number of chunks of the content received with BDAT, DATA is refused
+    /// once there is one
+    chunks: usize,
+    /// Beginning of the reception of the content, with DATA or the first BDAT
+    data_started: Option<Instant>,
+    /// Delay before each reply
+    latency: Latency,
+    /// Commands and replies of the session
+    transcript: Transcript,
+    /// Largest number of recipients of a mail
+    max_recipients: Option<usize>,
+    /// Limits of the header section of the mails
+    header_limits: HeaderLimits,
+    /// Queue of the received mails, to refuse the new ones when it is full
+    queue: Queue,
+    /// Greylisting of the recipients
+    greylist: Greylist,
+    /// Behavior of the listener the session was accepted by
+    profile: Profile,
+    /// Counters of the sessions
+    metrics: Metrics,
+    /// Transactions cut off in the middle of their content
+    quarantine: Quarantine,
+    /// Capture of the mails, keeping only their envelope under a heavy load
+    capture: Capture,
 }
 
 #[allow(unused_lifetimes)]
 impl<'a, S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone> Smtp<'a, S> {
     /// New connection
-    pub fn new(stream: &S, server_name: String, use_starttls: bool) -> Smtp<'a, S> {
+    pub fn new(
+        stream: &S,
+        params: &Params,
+        peer_addr: Option<SocketAddr>,
+        replies: Replies,
+        profile: Profile,
+    ) -> Smtp<'a, S> {
         Self {
-            server_name,
+            server_name: params.server_name.clone(),
             write_stream: stream.clone(),
-            use_starttls,
+            pending: Vec::new(),
+            replies,
+            use_starttls: params.use_starttls,
             remote_name: None,
+            extended: false,
+            peer_addr,
             addr_from: None,
             addr_to: Vec::new(),
             receive_data: false,
             data: Cow::default(),
+            data_lines: 0,
+            invalid_lines: Vec::new(),
+            bare_lines: Vec::new(),
+            line_endings: params.line_endings,
+            journal: params.journal.clone(),
+            settings: params.settings.clone(),
+            credentials: params.credentials.clone(),
+            auth_step: None,
+            auth_user: None,
+            chunk: Vec::new(),
+            chunks: 0,
+            data_started: None,
+            latency: params.latency.clone(),
+            transcript: Transcript::new(peer_addr.map(|addr| addr.ip())),
+            max_recipients: params.max_recipients,
+            header_limits: params.header_limits,
+            queue: params.queue.clone(),
+            greylist: params.greylist.clone(),
+            profile,
+            metrics: params.metrics.clone(),
+            quarantine: params.quarantine.clone(),
+            capture: params.capture.clone(),
         }
     }
 
     /// Write response data to the client
+    ///
+    /// The response is delayed by the latency, then queued, to be written with
+    /// the responses of the other commands that were pipelined by the client,
+    /// see `flush`.
     async fn write(&mut self, message: &[u8]) -> crate::Result<()> {
+        self.latency.wait().await;
         log::debug!("Sending message: {:?}", message);
-        self.write_stream.write_all(message).await?;
+        self.transcript.server(&String::from_utf8_lossy(message));
+        self.pending.extend_from_slice(message);
+        // A client that pipelines a lot of commands may not read the responses
+        // before it has sent them all
+        if self.pending.len() > MAX_PENDING_REPLIES {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Write the queued responses
+    pub async fn flush(&mut self) -> crate::Result<()> {
+        if !self.pending.is_empty() {
+            self.write_stream
+                .write_all(&self.pending)
+                .await
+                .map_err(MailcatcherError::smtp)?;
+            self.pending.clear();
+        }
         Ok(())
     }
 
+    /// Write a reply, with its custom text if there is one
+    ///
+    /// The 4xx and 5xx replies are counted as refused commands, but the one
+    /// closing a session that timed out.
+    async fn reply(&mut self, reply: Reply) -> crate::Result<()> {
+        if reply.code() >= 400 && reply != Reply::Timeout {
+            self.metrics.rejected();
+        }
+        let message: String = reply.message(&self.replies, &self.server_name);
+        self.write(message.as_bytes()).await
+    }
+
     /// Write the greeting message
     pub async fn send_server_name(&mut self) -> crate::Result<()> {
-        // Write greeting server string
-        self.write(format!("220 {} ESMTP\r\n", self.server_name).as_bytes())
-            .await?;
-        Ok(())
+        self.reply(Reply::Greeting).await
     }
 
     /// process client input, and return the command used
-    #[allow(clippy::indexing_slicing)]
     pub fn process_line(&self, command_line: Cow<'a, str>) -> Command<'a> {
         log::debug!("texte: {}", command_line);
-        if !self.receive_data {
-            match command_line.to_lowercase().as_str() {
-                "data" => Command::DataStart,
-                "rset" => Command::Reset,
-                "quit" => Command::Quit,
-                "starttls" if self.use_starttls => Command::StartTls,
-                // Helo
-                line if line.len() > 5 && &line[..5] == "helo " => {
-                    Command::Hello(command_line[5..].to_string())
-                }
-                // Ehlo
-                line if line.len() > 5 && &line[..5] == "ehlo " => {
-                    Command::Ehllo(command_line[5..].to_string())
+        // During an authentication, the lines are the responses to the challenges
+        if self.auth_step.is_some() {
+            return Command::AuthResponse(command_line.into_owned());
+        }
+        Command::parse(command_line, self.receive_data, self.use_starttls)
+    }
+
+    /// Extensions advertised in the EHLO reply
+    fn extensions(&self) -> Vec<&'static str> {
+        let mut extensions: Vec<&'static str> = vec![
+            "AUTH PLAIN LOGIN",
+            "CHUNKING",
+            "8BITMIME",
+            "SMTPUTF8",
+            "ENHANCEDSTATUSCODES",
+            "PIPELINING",
+            "DSN",
+        ];
+        if self.use_starttls {
+            extensions.push("STARTTLS");
+        }
+        extensions
+    }
+
+    /// Begin an authentication, with the mechanism and the optional initial
+    /// response given with AUTH
+    async fn auth_start(&mut self, params: &str) -> crate::Result<()> {
+        let mut params = params.split_whitespace();
+        let mechanism: String = params.next().unwrap_or_default().to_ascii_uppercase();
+        // "=" is an empty initial response
+        let initial: Option<&str> = params.next().filter(|&initial| initial != "=");
+
+        match (mechanism.as_str(), initial) {
+            ("PLAIN", Some(response)) => self.auth_plain(response).await,
+            ("PLAIN", None) => {
+                self.auth_step = Some(AuthStep::Plain);
+                self.write(b"334 \r\n").await
+            }
+            ("LOGIN", Some(response)) => match decode(response) {
+                Some(user) => {
+                    self.auth_step = Some(AuthStep::LoginPassword(user));
+                    // "Password:" in base64
+                    self.write(b"334 UGFzc3dvcmQ6\r\n").await
                 }
-                // From
-                line if line.len() > 10 && &line[..10] == "mail from:" => {
-                    Command::From(command_line[10..].trim_start().to_owned())
+                None => self.reply(Reply::AuthAborted).await,
+            },
+            ("LOGIN", None) => {
+                self.auth_step = Some(AuthStep::LoginUser);
+                // "Username:" in base64
+                self.write(b"334 VXNlcm5hbWU6\r\n").await
+            }
+            _ => self.reply(Reply::UnknownMechanism).await,
+        }
+    }
+
+    /// Go on with the authentication in progress, with a response of the client
+    async fn auth_continue(&mut self, response: &str) -> crate::Result<()> {
+        let step: Option<AuthStep> = self.auth_step.take();
+        // The client cancels the authentication with "*"
+        if response == "*" {
+            return self.reply(Reply::AuthAborted).await;
+        }
+
+        match step {
+            Some(AuthStep::Plain) => self.auth_plain(response).await,
+            Some(AuthStep::LoginUser) => match decode(response) {
+                Some(user) => {
+                    self.auth_step = Some(AuthStep::LoginPassword(user));
+                    self.write(b"334 UGFzc3dvcmQ6\r\n").await
                 }
-                // To
-                line if line.len() > 8 && &line[..8] == "rcpt to:" => {
-                    Command::Recipient(command_line[8..].trim_start().to_owned())
+                None => self.reply(Reply::AuthAborted).await,
+            },
+            Some(AuthStep::LoginPassword(user)) => match decode(response) {
+                Some(password) => self.auth_end(user, &password).await,
+                None => self.reply(Reply::AuthAborted).await,
+            },
+            None => self.reply(Reply::BadSequence).await,
+        }
+    }
+
+    /// Check the credentials of the PLAIN mechanism
+    async fn auth_plain(&mut self, response: &str) -> crate::Result<()> {
+        match decode_plain(response) {
+            Some((user, password)) => self.auth_end(user, &password).await,
+            None => self.reply(Reply::AuthAborted).await,
+        }
+    }
+
+    /// Check the credentials given by the client, if some are expected
+    async fn auth_end(&mut self, user: String, password: &str) -> crate::Result<()> {
+        let accepted: bool = self
+            .credentials
+            .as_ref()
+            .map_or(true, |credentials| credentials.accepts(&user, password));
+        if accepted {
+            log::info!("Client authenticated as {}", user);
+            self.auth_user = Some(user);
+            self.reply(Reply::Authenticated).await
+        } else {
+            log::warn!("Invalid credentials for {}", user);
+            self.reply(Reply::InvalidCredentials).await
+        }
+    }
+
+    /// Record a line of the client in the transcript, without the content of the
+    /// mail and with the credentials masked
+    pub fn record(&mut self, line: &str) {
+        if self.receive_data {
+            // Only the end of the content
+            if line == "." {
+                self.transcript.client(line);
+            }
+        } else if self.auth_step.is_some() {
+            self.transcript.client(MASK);
+        } else {
+            // The mechanism of AUTH is kept, not its initial response
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some(auth), Some(mechanism), Some(_)) if auth.eq_ignore_ascii_case("AUTH") => {
+                    self.transcript
+                        .client(&format!("{} {} {}", auth, mechanism, MASK));
                 }
-                // Noop
-                line if (line.len() == 4 && line == "noop")
-                    || (line.len() > 4 && &line[..5] == "noop ") =>
-                {
-                    Command::Noop
+                _ => self.transcript.client(line),
+            }
+        }
+    }
+
+    /// Report that the line being processed is not valid UTF-8
+    pub fn invalid_utf8(&mut self) {
+        // Only the mail content is reported, the command will be rejected anyway
+        if self.receive_data {
+            self.invalid_lines.push(self.data_lines.saturating_add(1));
+        }
+    }
+
+    /// Handle a line ended by a bare LF, returning if the command is refused
+    ///
+    /// The lines of the mail content are kept, the mail being checked once
+    /// complete. The end of the content is accepted, so the client is not left
+    /// waiting for a reply.
+    async fn bare_line_feed(&mut self, command: &Command<'_>) -> crate::Result<bool> {
+        match *command {
+            Command::Data(_) => {
+                self.bare_lines.push(self.data_lines.saturating_add(1));
+                Ok(false)
+            }
+            Command::DataEnd => Ok(false),
+            Command::Hello(_)
+            | Command::Ehllo(_)
+            | Command::StartTls
+            | Command::Auth(_)
+            | Command::AuthResponse(_)
+            | Command::From(_)
+            | Command::Recipient(_)
+            | Command::DataStart
+            | Command::Bdat(_, _)
+            | Command::Noop
+            | Command::Reset
+            | Command::Quit
+            | Command::Vrfy(_)
+            | Command::Expn(_)
+            | Command::Help
+            | Command::Error(_) => {
+                if self.line_endings != LineEndings::Strict {
+                    return Ok(false);
                 }
-                // Anything else
-                _ => Command::Error(command_line.into()),
+                log::warn!("Command ended by a bare LF refused");
+                self.auth_step = None;
+                self.reply(Reply::BareLineFeed).await?;
+                Ok(true)
             }
-        } else if &command_line == "." {
-            Command::DataEnd
-        } else {
-            Command::Data(command_line)
         }
     }
 
     /// Reset the data state
     pub fn reset(&mut self) {
         self.data.to_mut().clear();
+        self.data_lines = 0;
+        self.invalid_lines.clear();
+        self.bare_lines.clear();
         self.receive_data = false;
+        self.chunks = 0;
+        self.data_started = None;
         self.addr_to.clear();
         self.remote_name = None;
         self.addr_from = None;
     }
 
+    /// Outcome of a read from the client, `None` if it stayed silent too long
+    ///
+    /// The transaction in progress is cut off if the connection failed or
+    /// timed out, see `cut_off`.
+    async fn checked<T>(&mut self, read: Option<io::Result<T>>) -> crate::Result<Option<T>> {
+        match read.transpose() {
+            Ok(Some(read)) => Ok(Some(read)),
+            Ok(None) => {
+                self.cut_off(Cutoff::Timeout).await;
+                Ok(None)
+            }
+            Err(e) => {
+                self.cut_off(Cutoff::Failed).await;
+                Err(MailcatcherError::smtp(e))
+            }
+        }
+    }
+
+    /// The connection ended in the middle of a content, keep the partial
+    /// transaction in the quarantine, apart from the mails
+    ///
+    /// Nothing is kept between two transactions.
+    async fn cut_off(&mut self, cutoff: Cutoff) {
+        if !self.receive_data && self.chunks == 0 {
+            return;
+        }
+        let from: String = self
+            .addr_from
+            .as_ref()
+            .map(|from| from.mailbox.clone())
+            .unwrap_or_default();
+        let to: Vec<String> = self.addr_to.iter().map(|to| to.mailbox.clone()).collect();
+        let mut mail: Mail = Mail::new(&from, &to, &self.data);
+        mail.set_params(
+            self.addr_from
+                .as_ref()
+                .map(|from| from.params.clone())
+                .unwrap_or_default(),
+            self.addr_to.iter().map(|to| to.params.clone()).collect(),
+        );
+        for &line in &self.invalid_lines {
+            mail.push_diagnostic(Diagnostic::InvalidUtf8 { line });
+        }
+        mail.set_client(Some(self.client()));
+        mail.set_transcript(self.transcript.clone());
+        log::warn!(
+            "Transaction {} cut off, {}, after {} bytes of content",
+            mail.get_id(),
+            cutoff,
+            self.data.len()
+        );
+        self.quarantine
+            .keep(Incomplete {
+                cutoff,
+                client: self.peer_addr.map(|addr| addr.ip()),
+                at: Utc::now(),
+                mail,
+            })
+            .await;
+        self.reset();
+    }
+
     /// Store a new line, removing any one dot at the beginning of a line
-    #[allow(clippy::indexing_slicing)]
     fn push_data(&mut self, line: &str) {
-        let start_idx: usize = if !line.is_empty() && &line[0..1] == "." {
-            1
-        } else {
-            0
-        };
         if !self.data.is_empty() {
             self.data.to_mut().push_str("\r\n");
         }
-        self.data.to_mut().push_str(&line[start_idx..]);
+        self.data
+            .to_mut()
+            .push_str(line.strip_prefix('.').unwrap_or(line));
+        self.data_lines = self.data_lines.saturating_add(1);
+    }
+
+    /// Store a chunk received with BDAT, as is
+    fn push_chunk(&mut self, chunk: &[u8]) {
+        let mut newlines: usize = 0;
+        for (index, line) in chunk.split(|&byte| byte == b'\n').enumerate() {
+            if index > 0 {
+                self.data.to_mut().push('\n');
+            }
+            let line: Cow<str> = decode_8bit(line);
+            if let Cow::Owned(_) = line {
+                self.invalid_lines
+                    .push(self.data_lines.saturating_add(index).saturating_add(1));
+            }
+            self.data.to_mut().push_str(&line);
+            newlines = index;
+        }
+        self.data_lines = self.data_lines.saturating_add(newlines);
+        self.chunks = self.chunks.saturating_add(1);
+    }
+
+    /// The content of the mail is complete, create the mail and keep it before
+    /// acknowledging it
+    async fn end_data(&mut self) -> crate::Result<Option<Mail>> {
+        log::trace!("{}", self.data);
+        // The pathological headers are refused before being parsed, the client
+        // keeping its name for the next transaction
+        if let Err(exceeded) = self.header_limits.check(&self.data) {
+            log::warn!("Mail refused, with {}", exceeded);
+            let remote_name: Option<String> = self.remote_name.take();
+            self.reset();
+            self.remote_name = remote_name;
+            self.reply(exceeded.reply()).await?;
+            return Ok(None);
+        }
+        let receive: Option<Duration> = self.data_started.take().map(|started| started.elapsed());
+        let bare_lines: Vec<usize> = std::mem::take(&mut self.bare_lines);
+        let parsing: Instant = Instant::now();
+        // Instantiate a new mail
+        let from: Path = self
+            .addr_from
+            .take()
+            .ok_or_else(|| MailcatcherError::smtp("No sender mail address"))?;
+        let to: Vec<String> = self.addr_to.iter().map(|to| to.mailbox.clone()).collect();
+        let settings: Settings = self.settings.get().await;
+        let mut mail: Mail = if self.capture.envelope_only(self.data.len()).await {
+            Mail::envelope_only(&from.mailbox, &to, self.data.len())
+        } else {
+            Mail::with_headers_only(&from.mailbox, &to, &self.data, settings.headers_only)
+        };
+        mail.set_tags(settings.tags_of(&mail));
+        mail.set_params(
+            from.params,
+            self.addr_to.iter().map(|to| to.params.clone()).collect(),
+        );
+        // Trace the reception like any MTA does
+        let received: String = self.received(&mail);
+        mail.prepend_header("Received", &received);
+        for line in self.invalid_lines.drain(..) {
+            mail.push_diagnostic(Diagnostic::InvalidUtf8 { line });
+        }
+        mail.set_auth_user(self.auth_user.clone());
+        mail.set_client(Some(self.client()));
+        if let Some(receive) = receive {
+            mail.timing_mut().record(Stage::Receive, receive);
+        }
+        mail.timing_mut().since(Stage::Parse, parsing);
+
+        self.receive_data = false;
+        self.chunks = 0;
+        self.addr_from = None;
+        self.addr_to.clear();
+        self.data.to_mut().clear();
+        self.data_lines = 0;
+
+        if !bare_lines.is_empty() {
+            // The whole mail is refused, its lines being checked once it is complete
+            if self.line_endings == LineEndings::Strict {
+                log::warn!(
+                    "Mail {} refused, {} lines ended by a bare LF",
+                    mail.get_id(),
+                    bare_lines.len()
+                );
+                self.reply(Reply::BareLineFeed).await?;
+                return Ok(None);
+            }
+            for line in bare_lines {
+                mail.push_diagnostic(Diagnostic::BareLineFeed { line });
+            }
+        }
+        // Refused before being kept anywhere, the client tries again later
+        if self.queue.sheds() {
+            log::warn!(
+                "Queue of the received mails full, mail {} refused",
+                mail.get_id()
+            );
+            self.reply(Reply::QueueFull).await?;
+            return Ok(None);
+        }
+        // Left out of the sample, the mail is only counted and acknowledged
+        let sampled: bool = self.capture.sampled();
+        // Keep the mail on the disk before acknowledging it
+        if let (true, Some(journal)) = (sampled, self.journal.as_ref()) {
+            let journaling: Instant = Instant::now();
+            if let Err(e) = journal.append(&mail).await {
+                log::error!("Mail {} not written in the journal: {}", mail.get_id(), e);
+                self.reply(Reply::LocalError).await?;
+                return Ok(None);
+            }
+            mail.timing_mut().since(Stage::Journal, journaling);
+        }
+
+        let queued: String = Reply::Queued
+            .text(&self.replies, &self.server_name)
+            .replace("{id}", &mail.get_id().to_string());
+        self.write(reply::format(Reply::Queued.code(), queued.lines()).as_bytes())
+            .await?;
+        if !sampled {
+            log::debug!("Mail {} left out of the sample", mail.get_id());
+            return Ok(None);
+        }
+        mail.set_transcript(self.transcript.clone());
+        Ok(Some(mail))
+    }
+
+    /// SMTP client of the session, as given to the mails
+    fn client(&self) -> Client {
+        Client {
+            helo: self.remote_name.clone(),
+            ip: self.peer_addr.map(|addr| addr.ip()),
+            // STARTTLS is not yet implemented, so the session is never secured
+            tls: false,
+        }
+    }
+
+    /// Generate the `Received` header content of the mail, as described in RFC 5321 section 4.4
+    fn received(&self, mail: &Mail) -> String {
+        let peer: String = self
+            .peer_addr
+            .map_or_else(|| "unknown".to_owned(), |addr| format!("[{}]", addr.ip()));
+        // STARTTLS is not yet implemented, so the session is never "ESMTPS"
+        let protocol: &str = match (
+            mail.get_from_params().contains_key("SMTPUTF8"),
+            self.extended,
+            self.auth_user.is_some(),
+        ) {
+            (true, true, true) => "UTF8SMTPA",
+            (true, true, false) => "UTF8SMTP",
+            (false, true, true) => "ESMTPA",
+            (false, true, false) => "ESMTP",
+            (_, false, _) => "SMTP",
+        };
+        // Only disclose the recipient when there is a single one
+        let recipient: String = match *self.addr_to.as_slice() {
+            [ref to] => format!(" for <{}>", to.mailbox),
+            _ => String::new(),
+        };
+        format!(
+            "from {} ({})\r\n by {} with {} id {}{};\r\n {}",
+            self.remote_name.as_deref().unwrap_or("unknown"),
+            peer,
+            self.server_name,
+            protocol,
+            mail.get_id(),
+            recipient,
+            Utc::now().to_rfc2822(),
+        )
     }
 
     /// return if the command is valid at this time of the speak
@@ -285,19 +1214,37 @@ impl<'a, S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone> Smtp<'a, S> {
             Command::Hello(_) | Command::Ehllo(_) => {
                 self.addr_from.is_none() && self.addr_to.is_empty()
             }
+            // Only once after EHLO, before the mail transaction
+            Command::Auth(_) => {
+                self.extended && self.auth_user.is_none() && self.addr_from.is_none()
+            }
             // Remote server name is specified, no recipient
             Command::From(_) => self.remote_name.is_some() && self.addr_to.is_empty(),
             // A server name AND an expeditor
             Command::Recipient(_) => self.remote_name.is_some() && self.addr_from.is_some(),
-            // Recipient has been used
+            // Recipient has been used, and BDAT was not
             Command::Data(_) | Command::DataStart => {
-                self.remote_name.is_some() && self.addr_from.is_some() && !self.addr_to.is_empty()
+                self.remote_name.is_some()
+                    && self.addr_from.is_some()
+                    && !self.addr_to.is_empty()
+                    && self.chunks == 0
+            }
+            // Recipient has been used, and DATA was not
+            Command::Bdat(_, _) => {
+                self.remote_name.is_some()
+                    && self.addr_from.is_some()
+                    && !self.addr_to.is_empty()
+                    && !self.receive_data
             }
             // Always valid at anytime
             Command::Noop
+            | Command::Vrfy(_)
+            | Command::Expn(_)
+            | Command::Help
             | Command::Quit
             | Command::Reset
             | Command::DataEnd
+            | Command::AuthResponse(_)
             | Command::Error(_) => true,
             // Only valid if specified at command line option, invalid otherwise
             Command::StartTls if self.use_starttls => true,
@@ -306,31 +1253,48 @@ impl<'a, S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone> Smtp<'a, S> {
     }
 
     /// Process command and value
+    #[allow(clippy::too_many_lines)]
     pub async fn process_command(&mut self, command: &Command<'a>) -> crate::Result<Option<Mail>> {
         log::debug!("{:?}", command);
+        // Only kept for the BDAT command it follows
+        let chunk: Vec<u8> = std::mem::take(&mut self.chunk);
         #[allow(clippy::pattern_type_mismatch, clippy::unimplemented)]
         match command {
             // Check if command is valid at this time of speaking
             action if !self.is_valid(action) => {
-                self.write(MSG_503_BAD_SEQUENCE).await?;
+                self.reply(Reply::BadSequence).await?;
                 Ok(None)
             }
             // Do nothing
             Command::Noop => {
-                self.write(MSG_250_OK).await?;
+                self.reply(Reply::Ok).await?;
                 Ok(None)
             }
             // The client is greeting to the server, so indicate if starttls is supported or not
             Command::Ehllo(remote_name) | Command::Hello(remote_name) => {
                 self.remote_name = Some(remote_name.clone());
-                let greeting: String = if self.use_starttls {
-                    format!("250-{}\r\n250 STARTTLS\r\n", self.server_name)
+                self.extended = matches!(command, Command::Ehllo(_));
+                // Only EHLO has a multiline reply, listing the extensions
+                let hello: String = Reply::Hello.text(&self.replies, &self.server_name);
+                let extensions: Vec<&str> = if self.extended {
+                    self.extensions()
                 } else {
-                    format!("250 {}\r\n", self.server_name)
+                    Vec::new()
                 };
+                let greeting: String =
+                    reply::format(Reply::Hello.code(), hello.lines().chain(extensions));
                 self.write(greeting.as_bytes()).await?;
                 Ok(None)
             }
+            // Authenticate the client
+            Command::Auth(params) => {
+                self.auth_start(params).await?;
+                Ok(None)
+            }
+            Command::AuthResponse(response) => {
+                self.auth_continue(response).await?;
+                Ok(None)
+            }
             // WiP
             Command::StartTls => {
                 // TODO: need to implement it
@@ -339,30 +1303,78 @@ impl<'a, S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone> Smtp<'a, S> {
             // Reset the state
             Command::Reset => {
                 self.reset();
-                self.write(MSG_250_OK).await?;
+                self.reply(Reply::Ok).await?;
                 Ok(None)
             }
             // Store the expeditor address
             Command::From(from) => {
-                if from.len() > 64 {
-                    log::error!("Username too long.");
-                    self.write(MSG_500_LENGTH_TOO_LONG).await?
-                } else {
-                    self.addr_from = Some(from.clone());
-                    self.write(MSG_250_OK).await?
+                let from = from
+                    .parse::<Path>()
+                    .and_then(|from| dsn::check_mail(&from.params).map(|()| from));
+                match from {
+                    // The transactions never start on the listeners playing a failing server
+                    Ok(_) if self.profile == Profile::AlwaysTempfail => {
+                        log::info!("Transaction deferred, failing temporarily");
+                        self.reply(Reply::TemporaryFailure).await?;
+                    }
+                    Ok(from) if from.local_part().len() > 64 => {
+                        log::error!("Username too long.");
+                        self.reply(Reply::LineTooLong).await?;
+                    }
+                    Ok(from) => {
+                        self.addr_from = Some(from);
+                        self.reply(Reply::Ok).await?;
+                    }
+                    Err(e) => {
+                        log::warn!("{}", e);
+                        self.reply(Reply::SyntaxError).await?;
+                    }
                 }
                 Ok(None)
             }
             // Store the recipient addresses
             Command::Recipient(to) => {
-                self.addr_to.push(to.clone());
-                self.write(MSG_250_OK).await?;
+                let to = to
+                    .parse::<Path>()
+                    .and_then(|to| dsn::check_rcpt(&to.params).map(|()| to));
+                match to {
+                    Ok(to) if !to.mailbox.is_empty() => {
+                        let max: usize = self.max_recipients.unwrap_or(usize::MAX);
+                        let from: &str = self.addr_from.as_ref().map_or("", |from| &from.mailbox);
+                        if !self.settings.get().await.accepts_recipient(&to.mailbox) {
+                            log::info!("Recipient {} rejected by the rules", to.mailbox);
+                            self.reply(Reply::MailboxUnavailable).await?;
+                        } else if !self
+                            .greylist
+                            .admit(self.peer_addr.map(|addr| addr.ip()), from, &to.mailbox)
+                            .await
+                        {
+                            log::info!("Recipient {} greylisted", to.mailbox);
+                            self.reply(Reply::Greylisted).await?;
+                        } else if self.addr_to.len() < max {
+                            self.addr_to.push(to);
+                            self.reply(Reply::Ok).await?;
+                        } else {
+                            log::warn!("Recipient {} refused, more than {}", to.mailbox, max);
+                            self.reply(Reply::TooManyRecipients).await?;
+                        }
+                    }
+                    Ok(_) => {
+                        log::warn!("Empty recipient address");
+                        self.reply(Reply::SyntaxError).await?;
+                    }
+                    Err(e) => {
+                        log::warn!("{}", e);
+                        self.reply(Reply::SyntaxError).await?;
+                    }
+                }
                 Ok(None)
             }
             // DATA command sent, so entering data mode
             Command::DataStart => {
                 self.receive_data = true;
-                self.write(MSG_354_NEXT_DATA).await?;
+                self.data_started = Some(Instant::now());
+                self.reply(Reply::StartData).await?;
                 Ok(None)
             }
             // Receive data, store it if line length is valid
@@ -372,46 +1384,53 @@ impl<'a, S: AsyncRead + AsyncWrite + Send + Sync + Unpin + Clone> Smtp<'a, S> {
                         "Data line length ({}) cannot exceed 998 characters.",
                         line.len()
                     );
-                    self.write(MSG_500_LENGTH_TOO_LONG).await?
+                    self.reply(Reply::LineTooLong).await?
                 }
                 self.push_data(line);
                 Ok(None)
             }
             // A line containing only "." specified, so mail is complete
-            Command::DataEnd => {
-                log::trace!("{}", self.data);
-                // Instantiate a new mail
-                let mail: Mail = Mail::new(
-                    self.addr_from.as_ref().ok_or("No sender mail address")?,
-                    &self.addr_to,
-                    &self.data,
-                );
-
-                self.receive_data = false;
-                self.addr_from = None;
-                self.addr_to.clear();
-                self.data.to_mut().clear();
-
-                self.write(MSG_250_OK).await?;
-                Ok(Some(mail))
+            Command::DataEnd => self.end_data().await,
+            // A chunk of the content, the mail is complete with the last one
+            Command::Bdat(_, last) => {
+                self.push_chunk(&chunk);
+                if !*last {
+                    self.reply(Reply::Ok).await?;
+                    return Ok(None);
+                }
+                // Like with DATA, the content does not end with a line ending
+                if self.data.ends_with("\r\n") {
+                    let length: usize = self.data.len().saturating_sub(2);
+                    self.data.to_mut().truncate(length);
+                }
+                self.end_data().await
+            }
+            // The addresses are not verified, but any one is accepted
+            Command::Vrfy(address) | Command::Expn(address) => {
+                if address.is_empty() {
+                    self.reply(Reply::SyntaxError).await?;
+                } else if let Command::Vrfy(_) = command {
+                    self.reply(Reply::CannotVerify).await?;
+                } else {
+                    self.reply(Reply::CannotExpand).await?;
+                }
+                Ok(None)
+            }
+            // The same banner is given for any topic
+            Command::Help => {
+                self.reply(Reply::Help).await?;
+                Ok(None)
             }
             // Exit the connection
             Command::Quit => {
-                self.write(
-                    format!(
-                        "221 {} Service closing transmission channel\r\n",
-                        self.server_name
-                    )
-                    .as_bytes(),
-                )
-                .await?;
+                self.reply(Reply::Closing).await?;
                 Ok(None)
             }
             // An error message, because the command is not supported
             Command::Error(err) => {
                 log::error!("Unsupported command: \"{}\"", err);
 
-                self.write(MSG_502_NOT_IMPLEMENTED).await?;
+                self.reply(Reply::NotImplemented).await?;
                 Ok(None)
             }
         }
@@ -427,12 +1446,19 @@ mod tests {
         prelude::{FutureExt, Stream},
     };
     use futures::{io::Lines, TryFutureExt};
+    use tide::prelude::json;
 
-    use crate::mail::Type;
+    use crate::{
+        mail::{filter::Filter, IdStrategy, Type},
+        settings::Settings,
+        smtp::greylist::Attempts,
+    };
 
     use super::*;
 
-    async fn connect_to(port: u16) -> crate::Result<(Lines<BufReader<TcpStream>>, TcpStream)> {
+    async fn connect_to(
+        port: u16,
+    ) -> crate::test::Result<(Lines<BufReader<TcpStream>>, TcpStream)> {
         let stream: TcpStream = TcpStream::connect(format!("127.0.0.1:{}", port)).await?;
 
         let reader: BufReader<TcpStream> = BufReader::new(stream.clone());
@@ -441,12 +1467,41 @@ mod tests {
         Ok((lines, stream))
     }
 
+    fn params(server_name: &str, mails_broker: Sender<Mail>) -> Params {
+        Params {
+            server_name: server_name.to_owned(),
+            mails_broker,
+            use_starttls: false,
+            upstream: None,
+            journal: None,
+            settings: SharedSettings::default(),
+            credentials: None,
+            tracer: None,
+            pause: Pause::default(),
+            idle_timeout: None,
+            session_timeout: None,
+            limits: Limits::default(),
+            latency: Latency::default(),
+            max_recipients: None,
+            header_limits: HeaderLimits::default(),
+            banner_delay: None,
+            reject_early_talkers: false,
+            line_endings: LineEndings::default(),
+            queue: Queue::default(),
+            greylist: Greylist::default(),
+            metrics: Metrics::default(),
+            quarantine: Quarantine::default(),
+            capture: Capture::default(),
+            activity: Activity::default(),
+        }
+    }
+
     #[test]
     #[allow(clippy::too_many_lines, clippy::indexing_slicing)]
     fn invalid_smtp_commands() -> std::io::Result<()> {
         const MY_NAME: &str = "UnitTest";
 
-        async fn the_test(port: u16, my_name: &str) -> crate::Result<()> {
+        async fn the_test(port: u16, my_name: &str) -> crate::test::Result<()> {
             let (mut lines, mut stream) = connect_to(port).await?;
 
             // Check if greeting is sent by the server
@@ -458,12 +1513,12 @@ mod tests {
             assert_eq!(line, format!("220 {} ESMTP", my_name));
 
             // --------------------------
-            // Nothing is accepted but Helo, Ehlo, Reset or Noop
+            // Nothing is accepted but Helo, Ehlo, Reset, Noop or the informational commands
             log::trace!("INVALID");
             log::debug!("{:?}", lines.size_hint());
             stream.write_all(b"INVALID\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "502 Command not implemented".to_owned());
+            assert_eq!(line, "502 5.5.1 Command not implemented".to_owned());
 
             log::trace!("MAIL FROM first");
             log::debug!("{:?}", lines.size_hint());
@@ -471,34 +1526,62 @@ mod tests {
                 .write_all(b"MAIL FROM:<test@example.org>\r\n")
                 .await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "503 Bad sequence of commands".to_owned());
+            assert_eq!(line, "503 5.5.1 Bad sequence of commands".to_owned());
 
             log::trace!("RCPT TO first");
             stream.write_all(b"RCPT TO:<test@example.org>\r\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "503 Bad sequence of commands".to_owned());
+            assert_eq!(line, "503 5.5.1 Bad sequence of commands".to_owned());
 
             log::trace!("DATA first");
             stream.write_all(b"DATA\r\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "503 Bad sequence of commands".to_owned());
+            assert_eq!(line, "503 5.5.1 Bad sequence of commands".to_owned());
 
             log::trace!("NOOP");
             stream.write_all(b"NOOP\r\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "250 OK".to_owned());
+            assert_eq!(line, "250 2.0.0 OK".to_owned());
 
             log::trace!("NOOP with message");
             stream
                 .write_all(b"NOOP ignore the end of the line\r\n")
                 .await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "250 OK".to_owned());
+            assert_eq!(line, "250 2.0.0 OK".to_owned());
 
             log::trace!("RESET without upcase");
             stream.write_all(b"rSET\r\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "250 OK".to_owned());
+            assert_eq!(line, "250 2.0.0 OK".to_owned());
+
+            log::trace!("VRFY, EXPN and HELP");
+            for &(command, reply) in &[
+                (
+                    "VRFY alice\r\n",
+                    "252 2.0.0 Cannot VRFY user, but will accept message and attempt delivery",
+                ),
+                (
+                    "vrfy\r\n",
+                    "501 5.5.4 Syntax error in parameters or arguments",
+                ),
+                (
+                    "EXPN staff\r\n",
+                    "252 2.0.0 Cannot EXPN list, but will accept message and attempt delivery",
+                ),
+                ("HELP\r\n", "214-2.0.0 Commands supported:"),
+                ("", "214-2.0.0 HELO EHLO AUTH MAIL RCPT DATA BDAT"),
+                ("", "214-2.0.0 RSET NOOP QUIT VRFY EXPN HELP"),
+                ("", "214 2.0.0 End of HELP info"),
+                ("HELP MAIL\r\n", "214-2.0.0 Commands supported:"),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+            for _ in 0..3 {
+                let _line = lines.next().await.ok_or("no next line")??;
+            }
 
             log::trace!("HELO");
             stream.write_all(b"HELO client\r\n").await?;
@@ -512,19 +1595,64 @@ mod tests {
 
         let listener: TcpListener = crate::test::with_timeout(
             1_000,
-            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0))
-                .map_err(|e| e.into()),
+            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
         )?;
         let port: u16 = listener.local_addr()?.port();
 
         let (sender, _receiver): crate::Channel<Mail> = bounded(1);
+        let (_stop, stopped): crate::Channel<()> = bounded(1);
 
         crate::test::with_timeout(
             5_000,
-            accept_loop(listener, MY_NAME, sender, false).race(the_test(port, MY_NAME)),
+            accept_loop(listener.into(), stopped, &params(MY_NAME, sender))
+                .err_into()
+                .race(the_test(port, MY_NAME)),
         )
     }
 
+    #[test]
+    fn denied_client() -> crate::test::Result<()> {
+        async fn the_test(port: u16) -> crate::test::Result<()> {
+            let (mut lines, _stream) = connect_to(port).await?;
+
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "554 5.7.1 Access denied");
+            // ... then the connection is closed
+            assert!(lines.next().await.is_none());
+
+            Ok(())
+        }
+
+        crate::test::log_init();
+
+        let listener: TcpListener = crate::test::with_timeout(
+            1_000,
+            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
+        )?;
+        let port: u16 = listener.local_addr()?.port();
+        let settings: SharedSettings = SharedSettings::new(Settings {
+            allow_cidr: vec!["192.168.0.0/16".parse()?],
+            ..Settings::default()
+        });
+
+        let (sender, _receiver): crate::Channel<Mail> = bounded(1);
+        let (_stop, stopped): crate::Channel<()> = bounded(1);
+
+        Ok(crate::test::with_timeout(
+            5_000,
+            accept_loop(
+                listener.into(),
+                stopped,
+                &Params {
+                    settings,
+                    ..params("", sender)
+                },
+            )
+            .err_into()
+            .race(the_test(port)),
+        )?)
+    }
+
     #[test]
     #[allow(clippy::too_many_lines, clippy::indexing_slicing)]
     fn valid_smtp_commands() -> std::io::Result<()> {
@@ -534,7 +1662,7 @@ mod tests {
             port: u16,
             my_name: &str,
             mut receiver: Receiver<Mail>,
-        ) -> crate::Result<()> {
+        ) -> crate::test::Result<()> {
             let (mut lines, mut stream) = connect_to(port).await?;
 
             // --------------------------
@@ -550,8 +1678,22 @@ mod tests {
             log::trace!("EHLO");
             stream.write_all(b"eHLO client\r\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, format!("250 {}", my_name));
-
+            assert_eq!(line, format!("250-{}", my_name));
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250-AUTH PLAIN LOGIN");
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250-CHUNKING");
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250-8BITMIME");
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250-SMTPUTF8");
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250-ENHANCEDSTATUSCODES");
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250-PIPELINING");
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250 DSN");
+
             // --------------------------
             // From
             log::trace!("MAIL FROM");
@@ -559,19 +1701,19 @@ mod tests {
                 .write_all(b"mAiL frOM:<from@example.org>\r\n")
                 .await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "250 OK".to_owned());
+            assert_eq!(line, "250 2.0.0 OK".to_owned());
 
             // --------------------------
             // To
             log::trace!("RCPT TO 1");
             stream.write_all(b"RCpT tO:<to@example.net>\r\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "250 OK".to_owned());
+            assert_eq!(line, "250 2.0.0 OK".to_owned());
 
             log::trace!("RCPT TO 2");
             stream.write_all(b"rcpt TO:<to@example.org>\r\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "250 OK".to_owned());
+            assert_eq!(line, "250 2.0.0 OK".to_owned());
 
             // --------------------------
             // Begin data
@@ -596,25 +1738,92 @@ Subject: =?ISO-8859-1?B?SWYgeW91IGNhbiByZWFkIHRoaXMgeW8=?=\r\n\
                 .await?;
             log::trace!("End of mail content");
             let line = lines.next().await.ok_or("no next line")??;
-            assert_eq!(line, "250 OK");
+            assert_eq!(line, "250 2.0.0 OK");
 
             // --------------------------
             // Check the received mail
             log::trace!("Mail received");
             let mail: Mail = receiver.next().await.ok_or("no next line")?;
 
+            // --------------------------
+            // A content that is not valid UTF-8 is read as Latin-1
+            log::trace!("Latin-1 mail");
+            stream
+                .write_all(b"MAIL FROM:<from@example.org>\r\nRCPT TO:<to@example.net>\r\nDATA\r\n")
+                .await?;
+            for _ in 0..3_u8 {
+                let _ = lines.next().await.ok_or("no next line")??;
+            }
+            stream
+                .write_all(b"Subject: Latin-1\r\n\r\nCaf\xe9\r\n.\r\n")
+                .await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250 2.0.0 OK");
+            let latin: Mail = receiver.next().await.ok_or("no next line")?;
+            assert_eq!(latin.get_text().ok_or("no text")?, "Caf\u{e9}");
+            assert_eq!(
+                latin.get_diagnostics(),
+                &vec![Diagnostic::InvalidUtf8 { line: 3 }]
+            );
+
+            // --------------------------
+            // International addresses and content, in UTF-8
+            log::trace!("SMTPUTF8 mail");
+            stream
+                .write_all(
+                    "MAIL FROM:<\u{e9}lise@exemple.fr> SMTPUTF8 BODY=8BITMIME\r\n\
+RCPT TO:<\u{7528}\u{6237}@\u{4f8b}\u{5b50}.\u{5e7f}\u{544a}>\r\nDATA\r\n"
+                        .as_bytes(),
+                )
+                .await?;
+            for _ in 0..3_u8 {
+                let line = lines.next().await.ok_or("no next line")??;
+                assert!(line.starts_with("250 ") || line.starts_with("354 "));
+            }
+            stream
+                .write_all(
+                    "Subject: \u{c9}t\u{e9}\r\n\r\n\u{2026}d\u{e9}j\u{e0} vu\r\n.\r\n".as_bytes(),
+                )
+                .await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250 2.0.0 OK");
+            let international: Mail = receiver.next().await.ok_or("no next line")?;
+            assert_eq!(international.from(), "\u{e9}lise@exemple.fr");
+            assert_eq!(
+                international.to(),
+                &vec!["\u{7528}\u{6237}@\u{4f8b}\u{5b50}.\u{5e7f}\u{544a}".to_owned()]
+            );
+            assert_eq!(international.get_subject(), "\u{c9}t\u{e9}");
+            assert_eq!(
+                international.get_text().ok_or("no text")?,
+                "\u{2026}d\u{e9}j\u{e0} vu"
+            );
+            assert!(international.get_diagnostics().is_empty());
+            assert!(international
+                .get_data(&Type::Raw)
+                .ok_or("no raw")?
+                .contains(" with UTF8SMTP id "));
+
             // --------------------------
             // Close connection
             log::trace!("QUIT");
             stream.write_all(b"quit\r\n").await?;
             let line = lines.next().await.ok_or("no next line")??;
             assert_eq!(
-                line[..(4 + my_name.len())].to_string(),
-                format!("221 {}", my_name)
+                line[..(10 + my_name.len())].to_string(),
+                format!("221 2.0.0 {}", my_name)
             );
 
             log::trace!("Check mail received");
             let raw = mail.get_data(&Type::Raw).ok_or("no next line")?;
+            // The reception is traced first
+            let trace: String = format!(
+                "Received: from client ([127.0.0.1])\r\n by {} with ESMTP id {};\r\n ",
+                my_name,
+                mail.get_id()
+            );
+            assert_eq!(&raw[..trace.len()], trace);
+            let raw: &str = &raw[(raw.find("\r\nFrom: ").ok_or("no From header")? + 2)..];
             assert_eq!(
                 raw,
                 "From: =?US-ASCII?Q?Keith_Moore?= <moore@cs.utk.edu>;\r\n\
@@ -633,16 +1842,1575 @@ This is the content of this mail... but it says nothing now.\r\n"
 
         let listener: TcpListener = crate::test::with_timeout(
             1_000,
-            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0))
-                .map_err(|e| e.into()),
+            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
+        )?;
+        let port: u16 = listener.local_addr()?.port();
+
+        let (sender, receiver): crate::Channel<Mail> = bounded(1);
+        let (_stop, stopped): crate::Channel<()> = bounded(1);
+
+        crate::test::with_timeout(
+            5_000,
+            accept_loop(listener.into(), stopped, &params(MY_NAME, sender))
+                .err_into()
+                .race(the_test(port, MY_NAME, receiver)),
+        )
+    }
+
+    #[test]
+    fn bdat_commands() -> std::io::Result<()> {
+        async fn the_test(port: u16, mut receiver: Receiver<Mail>) -> crate::test::Result<()> {
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            stream.write_all(b"EHLO client\r\n").await?;
+            for _ in 0..8 {
+                let _extension = lines.next().await.ok_or("no next line")??;
+            }
+
+            for &(command, reply) in &[
+                // The chunk is read, but refused before the transaction
+                ("BDAT 4\r\nQUIT", "503 5.5.1 Bad sequence of commands"),
+                ("BDAT four\r\n", "502 5.5.1 Command not implemented"),
+                ("MAIL FROM:<from@example.org>\r\n", "250 2.0.0 OK"),
+                ("RCPT TO:<to@example.net>\r\n", "250 2.0.0 OK"),
+                // The chunks are kept as is, even a line with only a dot
+                (
+                    "BDAT 27\r\nSubject: Chunked\r\n\r\nFirst\r\n",
+                    "250 2.0.0 OK",
+                ),
+                ("BDAT 3\r\n.\r\n", "250 2.0.0 OK"),
+                // DATA cannot follow BDAT
+                ("DATA\r\n", "503 5.5.1 Bad sequence of commands"),
+                ("bdat 8 LAST\r\nSecond\r\n", "250 2.0.0 OK"),
+                ("BDAT 0 LAST\r\n", "503 5.5.1 Bad sequence of commands"),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+
+            let mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(mail.get_subject(), "Chunked");
+            assert!(mail
+                .get_data(&Type::Raw)
+                .ok_or("no raw")?
+                .ends_with("\r\n\r\nFirst\r\n.\r\nSecond"));
+
+            Ok(())
+        }
+
+        crate::test::log_init();
+
+        let listener: TcpListener = crate::test::with_timeout(
+            1_000,
+            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
+        )?;
+        let port: u16 = listener.local_addr()?.port();
+
+        let (sender, receiver): crate::Channel<Mail> = bounded(1);
+        let (_stop, stopped): crate::Channel<()> = bounded(1);
+
+        crate::test::with_timeout(
+            5_000,
+            accept_loop(listener.into(), stopped, &params("Chunking", sender))
+                .err_into()
+                .race(the_test(port, receiver)),
+        )
+    }
+
+    #[test]
+    fn envelope_params() -> std::io::Result<()> {
+        async fn the_test(port: u16, mut receiver: Receiver<Mail>) -> crate::test::Result<()> {
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+
+            for &(command, reply) in &[
+                ("HELO client\r\n", "250 Params"),
+                (
+                    "MAIL FROM:<from@example.org>SIZE=10\r\n",
+                    "501 5.5.4 Syntax error in parameters or arguments",
+                ),
+                (
+                    "MAIL FROM:<from@example.org> RET=BODY\r\n",
+                    "501 5.5.4 Syntax error in parameters or arguments",
+                ),
+                (
+                    "MAIL FROM:<from@example.org> SIZE=120 body=8BITMIME ENVID=QQ+2B314\r\n",
+                    "250 2.0.0 OK",
+                ),
+                (
+                    "RCPT TO:<>\r\n",
+                    "501 5.5.4 Syntax error in parameters or arguments",
+                ),
+                (
+                    "RCPT TO:<to@example.net> NOTIFY=NEVER,DELAY\r\n",
+                    "501 5.5.4 Syntax error in parameters or arguments",
+                ),
+                ("RCPT TO:<to@example.net> NOTIFY=NEVER\r\n", "250 2.0.0 OK"),
+                ("RCPT TO:cc@example.net\r\n", "250 2.0.0 OK"),
+                ("DATA\r\n", "354 Start mail input; end with <CRLF>.<CRLF>"),
+                ("Subject: Params\r\n\r\nContent\r\n.\r\n", "250 2.0.0 OK"),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+
+            let mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(mail.from(), "from@example.org");
+            assert_eq!(
+                mail.to(),
+                &vec!["to@example.net".to_owned(), "cc@example.net".to_owned()]
+            );
+            let summary: serde_json::Value = mail.summary(IdStrategy::Ulid);
+            assert_eq!(
+                summary.get("from_params"),
+                Some(&json!({"BODY": "8BITMIME", "ENVID": "QQ+2B314", "SIZE": "120"}))
+            );
+            // The envelope id correlates the mail
+            assert_eq!(mail.get_correlation_ids(), vec!["qq+314".to_owned()]);
+            assert_eq!(
+                summary.get("to_params"),
+                Some(&json!([{"NOTIFY": "NEVER"}, {}]))
+            );
+
+            Ok(())
+        }
+
+        crate::test::log_init();
+
+        let listener: TcpListener = crate::test::with_timeout(
+            1_000,
+            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
+        )?;
+        let port: u16 = listener.local_addr()?.port();
+
+        let (sender, receiver): crate::Channel<Mail> = bounded(1);
+        let (_stop, stopped): crate::Channel<()> = bounded(1);
+
+        crate::test::with_timeout(
+            5_000,
+            accept_loop(listener.into(), stopped, &params("Params", sender))
+                .err_into()
+                .race(the_test(port, receiver)),
+        )
+    }
+
+    #[test]
+    fn pipelined_commands() -> std::io::Result<()> {
+        async fn the_test(port: u16, mut receiver: Receiver<Mail>) -> crate::test::Result<()> {
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+
+            // Each group of commands is sent at once, before reading the responses
+            for &(commands, replies) in &[
+                (
+                    "EHLO client\r\nMAIL FROM:<from@example.org>\r\n\
+RCPT TO:<to@example.net>\r\nRCPT TO:<>\r\nDATA\r\n",
+                    &[
+                        "250-Pipelining",
+                        "250-AUTH PLAIN LOGIN",
+                        "250-CHUNKING",
+                        "250-8BITMIME",
+                        "250-SMTPUTF8",
+                        "250-ENHANCEDSTATUSCODES",
+                        "250-PIPELINING",
+                        "250 DSN",
+                        "250 2.0.0 OK",
+                        "250 2.0.0 OK",
+                        "501 5.5.4 Syntax error in parameters or arguments",
+                        "354 Start mail input; end with <CRLF>.<CRLF>",
+                    ][..],
+                ),
+                (
+                    "Subject: Pipelined\r\n\r\nContent\r\n.\r\nNOOP\r\nQUIT\r\n",
+                    &[
+                        "250 2.0.0 OK",
+                        "250 2.0.0 OK",
+                        "221 2.0.0 Pipelining Service closing transmission channel",
+                    ][..],
+                ),
+            ] {
+                stream.write_all(commands.as_bytes()).await?;
+                for &reply in replies {
+                    let line = lines.next().await.ok_or("no next line")??;
+                    assert_eq!(line, reply);
+                }
+            }
+
+            let mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(mail.get_subject(), "Pipelined");
+            assert_eq!(mail.to(), &vec!["to@example.net".to_owned()]);
+
+            Ok(())
+        }
+
+        crate::test::log_init();
+
+        let listener: TcpListener = crate::test::with_timeout(
+            1_000,
+            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
+        )?;
+        let port: u16 = listener.local_addr()?.port();
+
+        let (sender, receiver): crate::Channel<Mail> = bounded(1);
+        let (_stop, stopped): crate::Channel<()> = bounded(1);
+
+        crate::test::with_timeout(
+            5_000,
+            accept_loop(listener.into(), stopped, &params("Pipelining", sender))
+                .err_into()
+                .race(the_test(port, receiver)),
+        )
+    }
+
+    #[test]
+    fn auth_commands() -> std::io::Result<()> {
+        async fn the_test(port: u16, mut receiver: Receiver<Mail>) -> crate::test::Result<()> {
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+
+            // "\0alice\0wrong", "\0alice\0secret", "alice" and "secret" in base64
+            for &(command, reply) in &[
+                ("HELO client\r\n", "250 Auth"),
+                // Only after EHLO
+                (
+                    "AUTH PLAIN AGFsaWNlAHNlY3JldA==\r\n",
+                    "503 5.5.1 Bad sequence of commands",
+                ),
+                ("EHLO client\r\n", "250-Auth"),
+                ("", "250-AUTH PLAIN LOGIN"),
+                ("", "250-CHUNKING"),
+                ("", "250-8BITMIME"),
+                ("", "250-SMTPUTF8"),
+                ("", "250-ENHANCEDSTATUSCODES"),
+                ("", "250-PIPELINING"),
+                ("", "250 DSN"),
+                (
+                    "AUTH CRAM-MD5\r\n",
+                    "504 5.5.4 Unrecognized authentication type",
+                ),
+                (
+                    "AUTH PLAIN AGFsaWNlAHdyb25n\r\n",
+                    "535 5.7.8 Authentication credentials invalid",
+                ),
+                ("AUTH PLAIN\r\n", "334 "),
+                ("*\r\n", "501 5.5.2 Authentication aborted"),
+                ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6"),
+                ("not base64!\r\n", "501 5.5.2 Authentication aborted"),
+                ("AUTH LOGIN YWxpY2U=\r\n", "334 UGFzc3dvcmQ6"),
+                ("c2VjcmV0\r\n", "235 2.7.0 Authentication successful"),
+                // Only once
+                (
+                    "AUTH PLAIN AGFsaWNlAHNlY3JldA==\r\n",
+                    "503 5.5.1 Bad sequence of commands",
+                ),
+                ("MAIL FROM:<from@example.org>\r\n", "250 2.0.0 OK"),
+                ("RCPT TO:<to@example.net>\r\n", "250 2.0.0 OK"),
+                ("DATA\r\n", "354 Start mail input; end with <CRLF>.<CRLF>"),
+                (
+                    "Subject: Authenticated\r\n\r\nContent\r\n.\r\n",
+                    "250 2.0.0 OK",
+                ),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+
+            let mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(mail.get_auth_user(), Some(&"alice".to_owned()));
+            assert_eq!(
+                mail.get_client(),
+                Some(&Client {
+                    helo: Some("client".to_owned()),
+                    ip: Some("127.0.0.1".parse()?),
+                    tls: false,
+                })
+            );
+            assert!(mail
+                .get_data(&Type::Raw)
+                .ok_or("no raw")?
+                .contains(" with ESMTPA id "));
+
+            Ok(())
+        }
+
+        crate::test::log_init();
+
+        let listener: TcpListener = crate::test::with_timeout(
+            1_000,
+            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
         )?;
         let port: u16 = listener.local_addr()?.port();
 
         let (sender, receiver): crate::Channel<Mail> = bounded(1);
+        let (_stop, stopped): crate::Channel<()> = bounded(1);
+
+        crate::test::with_timeout(
+            5_000,
+            accept_loop(
+                listener.into(),
+                stopped,
+                &Params {
+                    credentials: "alice:secret".parse().ok(),
+                    ..params("Auth", sender)
+                },
+            )
+            .err_into()
+            .race(the_test(port, receiver)),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn passthrough_smtp_commands() -> std::io::Result<()> {
+        const MY_NAME: &str = "Upstream";
+
+        async fn the_test(
+            port: u16,
+            my_name: &str,
+            mut upstream_receiver: Receiver<Mail>,
+            mut receiver: Receiver<Mail>,
+        ) -> crate::test::Result<()> {
+            let (mut lines, mut stream) = connect_to(port).await?;
+
+            // The greeting is the upstream one
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, format!("220 {} ESMTP", my_name));
+
+            for &(command, reply) in &[
+                ("HELO client\r\n", "250 Upstream"),
+                ("MAIL FROM:<from@example.org>\r\n", "250 2.0.0 OK"),
+                ("RCPT TO:<to@example.net>\r\n", "250 2.0.0 OK"),
+                ("DATA\r\n", "354 "),
+                ("Subject: Relayed\r\n\r\nContent\r\n.\r\n", "250 2.0.0 OK"),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(&line[..reply.len()], reply);
+            }
+
+            // Both the upstream server and the catcher have the mail
+            let upstream_mail: Mail = upstream_receiver.next().await.ok_or("no upstream mail")?;
+            let mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(mail.get_subject(), "Relayed");
+            // ... only the upstream server traced the reception
+            assert!(upstream_mail
+                .get_data(&Type::Raw)
+                .ok_or("no upstream raw")?
+                .ends_with(mail.get_data(&Type::Raw).ok_or("no raw")?.as_str()));
+
+            Ok(())
+        }
+
+        crate::test::log_init();
+
+        let bind_local = || {
+            crate::test::with_timeout(
+                1_000,
+                TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
+            )
+        };
+        let upstream_listener: TcpListener = bind_local()?;
+        let upstream: String = upstream_listener.local_addr()?.to_string();
+        let listener: TcpListener = bind_local()?;
+        let port: u16 = listener.local_addr()?.port();
+
+        let (upstream_sender, upstream_receiver): crate::Channel<Mail> = bounded(1);
+        let (sender, receiver): crate::Channel<Mail> = bounded(1);
+        let (_stop, stopped): crate::Channel<()> = bounded(1);
 
         crate::test::with_timeout(
             5_000,
-            accept_loop(listener, MY_NAME, sender, false).race(the_test(port, MY_NAME, receiver)),
+            accept_loop(
+                upstream_listener.into(),
+                stopped.clone(),
+                &params(MY_NAME, upstream_sender),
+            )
+            .race(accept_loop(
+                listener.into(),
+                stopped,
+                &Params {
+                    upstream: Some(upstream),
+                    ..params("", sender)
+                },
+            ))
+            .err_into()
+            .race(the_test(port, MY_NAME, upstream_receiver, receiver)),
         )
     }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn unreachable_upstream() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            // A port nothing listens on anymore
+            let upstream: String = TcpListener::bind("127.0.0.1:0")
+                .await?
+                .local_addr()?
+                .to_string();
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, _receiver): crate::Channel<Mail> = bounded(1);
+            let _server = async_std::task::spawn(serve(
+                vec![listener.into()],
+                stopped,
+                Params {
+                    upstream: Some(upstream),
+                    ..params("Proxy", sender)
+                },
+            ));
+
+            // Each client is told the service is unavailable, the listener
+            // going on
+            for _ in 0..2_u8 {
+                let (mut lines, _stream) = connect_to(port).await?;
+                let reply: String = lines.next().await.ok_or("no next line")??;
+                assert!(reply.starts_with("421 "), "{}", reply);
+                assert!(lines.next().await.is_none());
+            }
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn release_mail() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let server: String = listener.local_addr()?.to_string();
+            let (stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, mut receiver): crate::Channel<Mail> = bounded(1);
+            let serving = async_std::task::spawn(serve(
+                vec![listener.into()],
+                stopped,
+                Params {
+                    credentials: Some("alice:secret".parse()?),
+                    ..params("Real", sender)
+                },
+            ));
+
+            let caught: Mail = Mail::new(
+                "from@example.org",
+                &["to@example.net".to_owned(), "cc@example.net".to_owned()],
+                "Subject: Released\r\n\r\nContent\r\n.dot\r\n",
+            );
+            let target = |credentials: &str| -> crate::test::Result<release::Target> {
+                Ok(release::Target {
+                    server: server.clone(),
+                    credentials: Some(credentials.parse()?),
+                })
+            };
+
+            // Delivered with its envelope and its content
+            let reply: String = release::release(&target("alice:secret")?, &caught).await?;
+            assert!(reply.starts_with("250 "));
+            let mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(mail.from(), caught.from());
+            assert_eq!(mail.to(), caught.to());
+            assert_eq!(mail.get_subject(), "Released");
+            let raw: &String = mail.get_data(&Type::Raw).ok_or("no raw")?;
+            assert!(raw.contains("\r\nContent\r\n.dot"));
+            assert!(!raw.contains("..dot"));
+
+            // Refused with other credentials
+            let refused = release::release(&target("alice:wrong")?, &caught).await;
+            assert!(refused
+                .map_err(|e| e.to_string())
+                .err()
+                .ok_or("mail released")?
+                .contains("535 5.7.8"));
+
+            drop(stop);
+            serving.await?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn drain_on_stop() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, mut receiver): crate::Channel<Mail> = bounded(1);
+            let server = async_std::task::spawn(serve(
+                vec![listener.into()],
+                stopped,
+                params("Draining", sender),
+            ));
+
+            // A session is in progress when the listener is stopped...
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 Draining ESMTP");
+            drop(stop);
+            async_std::task::sleep(Duration::from_millis(100)).await;
+
+            // ... no new connection is accepted, the port is free again
+            assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+            let rebound: TcpListener = TcpListener::bind(("127.0.0.1", port)).await?;
+            drop(rebound);
+
+            // ... but the session goes on until its end
+            for &(command, reply) in &[
+                ("HELO client\r\n", "250 Draining"),
+                ("MAIL FROM:<from@example.org>\r\n", "250 2.0.0 OK"),
+                ("RCPT TO:<to@example.net>\r\n", "250 2.0.0 OK"),
+                ("DATA\r\n", "354 Start mail input; end with <CRLF>.<CRLF>"),
+                ("Subject: Drained\r\n\r\nContent\r\n.\r\n", "250 2.0.0 OK"),
+                (
+                    "QUIT\r\n",
+                    "221 2.0.0 Draining Service closing transmission channel",
+                ),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+            let mut mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(mail.get_subject(), "Drained");
+            // The time spent in the stages of the SMTP side is recorded
+            let stages: Vec<Stage> = mail
+                .take_timing()
+                .laps()
+                .iter()
+                .map(|lap| lap.stage)
+                .collect();
+            assert_eq!(stages, vec![Stage::Receive, Stage::Parse]);
+
+            // The server is over with its last session
+            server.timeout(Duration::from_secs(1)).await??;
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn pause_and_resume() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, mut receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = params("Paused", sender);
+            let pause: Pause = params.pause.clone();
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+            let unavailable: &str =
+                "421 4.3.2 Paused Service not available, closing transmission channel";
+
+            // A session in progress ends its mail, and is closed before the next one
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 Paused ESMTP");
+            for &(command, reply) in &[
+                ("HELO client\r\n", "250 Paused"),
+                ("MAIL FROM:<from@example.org>\r\n", "250 2.0.0 OK"),
+                ("RCPT TO:<to@example.net>\r\n", "250 2.0.0 OK"),
+                ("DATA\r\n", "354 Start mail input; end with <CRLF>.<CRLF>"),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+            pause.set(true);
+            stream
+                .write_all(b"Subject: Before the outage\r\n\r\nContent\r\n.\r\n")
+                .await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250 2.0.0 OK");
+            stream
+                .write_all(b"MAIL FROM:<from@example.org>\r\n")
+                .await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, unavailable);
+            assert!(lines.next().await.is_none());
+            let mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(mail.get_subject(), "Before the outage");
+
+            // The new clients are refused at once
+            let (mut lines, _stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, unavailable);
+            assert!(lines.next().await.is_none());
+
+            // Once resumed, the service is back
+            pause.set(false);
+            let (mut lines, _stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 Paused ESMTP");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn session_timeouts() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = Params {
+                idle_timeout: Some(Duration::from_millis(300)),
+                session_timeout: Some(Duration::from_millis(1_500)),
+                ..params("Slow", sender)
+            };
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+            let timeout: &str = "421 4.4.2 Slow Timeout exceeded, closing transmission channel";
+
+            // A client silent in the middle of a mail is disconnected, the mail
+            // is discarded
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 Slow ESMTP");
+            for &(command, reply) in &[
+                ("HELO client\r\n", "250 Slow"),
+                ("MAIL FROM:<from@example.org>\r\n", "250 2.0.0 OK"),
+                ("RCPT TO:<to@example.net>\r\n", "250 2.0.0 OK"),
+                ("DATA\r\n", "354 Start mail input; end with <CRLF>.<CRLF>"),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+            stream.write_all(b"Subject: Never ended\r\n").await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, timeout);
+            assert!(lines.next().await.is_none());
+
+            // An active client is disconnected at the end of its session
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 Slow ESMTP");
+            let line: String = loop {
+                async_std::task::sleep(Duration::from_millis(200)).await;
+                stream.write_all(b"NOOP\r\n").await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                if line != "250 2.0.0 OK" {
+                    break line;
+                }
+            };
+            assert_eq!(line, timeout);
+            assert!(lines.next().await.is_none());
+            assert!(receiver.try_recv().is_err());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn max_connections() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, _receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = Params {
+                limits: Limits::new(Some(1), None),
+                ..params("Busy", sender)
+            };
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 Busy ESMTP");
+
+            // The session in progress takes the only slot
+            let (mut refused, _stream) = connect_to(port).await?;
+            let line = refused.next().await.ok_or("no next line")??;
+            assert_eq!(line, "421 4.7.0 Busy Too many connections");
+            assert!(refused.next().await.is_none());
+
+            // The slot is released once the session is over
+            stream.write_all(b"QUIT\r\n").await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "221 2.0.0 Busy Service closing transmission channel");
+            assert!(lines.next().await.is_none());
+            let (mut lines, _stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 Busy ESMTP");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn custom_replies() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let replies: Replies = serde_json::from_value(json!({
+            "greeting": "mx.example.com ESMTP ready",
+            "hello": "mx.example.com at your service\n[127.0.0.1]",
+            "queued": "2.0.0 OK: queued as {id}",
+            "syntax_error": "5.5.2 Syntax error",
+        }))?;
+        let settings: SharedSettings = SharedSettings::new(Settings {
+            replies,
+            ..Settings::default()
+        });
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, mut receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = Params {
+                settings,
+                ..params("Custom", sender)
+            };
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 mx.example.com ESMTP ready");
+            // The extensions follow the custom lines
+            stream.write_all(b"EHLO client\r\n").await?;
+            for &reply in &[
+                "250-mx.example.com at your service",
+                "250-[127.0.0.1]",
+                "250-AUTH PLAIN LOGIN",
+            ] {
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+            while !lines
+                .next()
+                .await
+                .ok_or("no next line")??
+                .starts_with("250 ")
+            {}
+            for &(command, reply) in &[
+                (
+                    "MAIL FROM:<from@example.org> BODY=\r\n",
+                    "501 5.5.2 Syntax error",
+                ),
+                ("MAIL FROM:<from@example.org>\r\n", "250 2.0.0 OK"),
+                ("RCPT TO:<to@example.net>\r\n", "250 2.0.0 OK"),
+                ("DATA\r\n", "354 Start mail input; end with <CRLF>.<CRLF>"),
+            ] {
+                stream.write_all(command.as_bytes()).await?;
+                let line = lines.next().await.ok_or("no next line")??;
+                assert_eq!(line, reply);
+            }
+            stream
+                .write_all(b"Subject: Custom\r\n\r\nContent\r\n.\r\n")
+                .await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            let mail: Mail = receiver.next().await.ok_or("no mail")?;
+            assert_eq!(line, format!("250 2.0.0 OK: queued as {}", mail.get_id()));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn replies_latency() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, _receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = params("Latency", sender);
+            let latency: Latency = params.latency.clone();
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "220 Latency ESMTP");
+
+            // The delay changes while the session is open
+            latency.set(latency::Delay {
+                delay_ms: 200,
+                jitter_ms: 0,
+            });
+            let start: Instant = Instant::now();
+            stream.write_all(b"NOOP\r\n").await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250 2.0.0 OK");
+            assert!(start.elapsed().as_millis() >= 200);
+
+            latency.set(latency::Delay::default());
+            let start: Instant = Instant::now();
+            stream.write_all(b"NOOP\r\n").await?;
+            let line = lines.next().await.ok_or("no next line")??;
+            assert_eq!(line, "250 2.0.0 OK");
+            assert!(start.elapsed().as_millis() < 200);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn max_recipients() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = Params {
+                max_recipients: Some(2),
+                ..params("Recipients", sender)
+            };
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            stream
+                .write_all(
+                    b"HELO client\r\nMAIL FROM:<from@example.org>\r\n\
+                      RCPT TO:<one@example.net>\r\nRCPT TO:<two@example.net>\r\n\
+                      RCPT TO:<three@example.net>\r\n",
+                )
+                .await?;
+            let mut replies: Vec<String> = Vec::new();
+            for _ in 0..5 {
+                replies.push(lines.next().await.ok_or("no next line")??);
+            }
+            assert_eq!(
+                replies.get(2..),
+                Some(
+                    &[
+                        "250 2.0.0 OK".to_owned(),
+                        "250 2.0.0 OK".to_owned(),
+                        "452 4.5.3 Too many recipients".to_owned()
+                    ][..]
+                )
+            );
+
+            // The mail is sent to the accepted recipients
+            stream
+                .write_all(b"DATA\r\nSubject: Test\r\n\r\nContent\r\n.\r\n")
+                .await?;
+            let mail: Mail = receiver.recv().await?;
+            assert_eq!(mail.to(), &vec!["one@example.net", "two@example.net"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn banner_delay() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, _receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = Params {
+                banner_delay: Some(Duration::from_millis(300)),
+                reject_early_talkers: true,
+                ..params("Banner", sender)
+            };
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            // The client waiting for the greeting gets it once the delay is over
+            let connecting: Instant = Instant::now();
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let greeting: String = lines.next().await.ok_or("no next line")??;
+            assert_eq!(greeting, "220 Banner ESMTP");
+            assert!(connecting.elapsed() >= Duration::from_millis(300));
+            stream.write_all(b"QUIT\r\n").await?;
+
+            // The client talking first is refused
+            let (mut lines, mut stream) = connect_to(port).await?;
+            stream.write_all(b"EHLO early\r\n").await?;
+            assert_eq!(
+                lines.next().await.ok_or("no next line")??,
+                "554 5.5.1 Banner Protocol error, talking before the greeting"
+            );
+            assert!(lines.next().await.is_none());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn header_limits() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = Params {
+                header_limits: HeaderLimits {
+                    count: Some(2),
+                    length: Some(40),
+                    ..HeaderLimits::default()
+                },
+                ..params("Headers", sender)
+            };
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            stream
+                .write_all(
+                    b"HELO client\r\nMAIL FROM:<from@example.org>\r\n\
+                      RCPT TO:<to@example.net>\r\nDATA\r\n\
+                      Subject: Test\r\nX-One: 1\r\nX-Two: 2\r\n\r\nContent\r\n.\r\n\
+                      MAIL FROM:<from@example.org>\r\nRCPT TO:<to@example.net>\r\nDATA\r\n\
+                      Subject: Test\r\nX-Long: 0123456789\r\n 0123456789 0123456789\r\n\
+                      \r\nContent\r\n.\r\n",
+                )
+                .await?;
+            let mut replies: Vec<String> = Vec::new();
+            for _ in 0..9 {
+                replies.push(lines.next().await.ok_or("no next line")??);
+            }
+            assert_eq!(
+                replies.get(4),
+                Some(&"552 5.3.4 Too many header fields".to_owned())
+            );
+            assert_eq!(
+                replies.get(8),
+                Some(&"552 5.3.4 Header field too long".to_owned())
+            );
+
+            // The next transaction is accepted
+            stream
+                .write_all(
+                    b"MAIL FROM:<from@example.org>\r\nRCPT TO:<to@example.net>\r\n\
+                      DATA\r\nSubject: Test\r\n\r\nContent\r\n.\r\n",
+                )
+                .await?;
+            let mail: Mail = receiver.recv().await?;
+            assert_eq!(mail.to(), &vec!["to@example.net"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn recipient_rules() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let settings: SharedSettings = SharedSettings::new(Settings {
+            recipient_rules: vec![
+                "accept:keep@bounce.test".parse()?,
+                "reject:*@bounce.test".parse()?,
+            ],
+            tag_rules: vec![
+                "bounces=To:*@bounce.test".parse()?,
+                "test=Subject:Te".parse()?,
+            ],
+            ..Settings::default()
+        });
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = Params {
+                settings: settings.clone(),
+                ..params("Rules", sender)
+            };
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            stream
+                .write_all(
+                    b"HELO client\r\nMAIL FROM:<from@example.org>\r\n\
+                      RCPT TO:<gone@Bounce.test>\r\nRCPT TO:<keep@bounce.test>\r\n\
+                      RCPT TO:<to@example.net>\r\n",
+                )
+                .await?;
+            let mut replies: Vec<String> = Vec::new();
+            for _ in 0..5 {
+                replies.push(lines.next().await.ok_or("no next line")??);
+            }
+            assert_eq!(
+                replies.get(2..),
+                Some(
+                    &[
+                        "550 5.1.1 Requested action not taken: mailbox unavailable".to_owned(),
+                        "250 2.0.0 OK".to_owned(),
+                        "250 2.0.0 OK".to_owned()
+                    ][..]
+                )
+            );
+
+            // The rules changed while running apply to the next recipients
+            let _settings: Settings = settings
+                .update(&json!({"recipient_rules": ["reject:/^late@/"]}))
+                .await?;
+            stream
+                .write_all(b"RCPT TO:<late@example.net>\r\nRCPT TO:<gone@bounce.test>\r\n")
+                .await?;
+            assert_eq!(
+                lines.next().await.ok_or("no next line")??,
+                "550 5.1.1 Requested action not taken: mailbox unavailable"
+            );
+            assert_eq!(lines.next().await.ok_or("no next line")??, "250 2.0.0 OK");
+
+            // The mail is sent to the accepted recipients only
+            stream
+                .write_all(b"DATA\r\nSubject: Test\r\n\r\nContent\r\n.\r\n")
+                .await?;
+            let mail: Mail = receiver.recv().await?;
+            assert_eq!(
+                mail.to(),
+                &vec!["keep@bounce.test", "to@example.net", "gone@bounce.test"]
+            );
+            // ... and tagged by the rules
+            assert_eq!(mail.get_tags(), &["test".to_owned()][..]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn greylisting() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let greylist: Greylist = Greylist::new(Duration::default());
+            let params: Params = Params {
+                greylist: greylist.clone(),
+                ..params("Greylist", sender)
+            };
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            stream
+                .write_all(
+                    b"HELO client\r\nMAIL FROM:<from@example.org>\r\n\
+                      RCPT TO:<to@example.net>\r\nRCPT TO:<to@example.net>\r\n\
+                      DATA\r\nSubject: Test\r\n\r\nContent\r\n.\r\n",
+                )
+                .await?;
+            let mut replies: Vec<String> = Vec::new();
+            for _ in 0..4 {
+                replies.push(lines.next().await.ok_or("no next line")??);
+            }
+            // The first attempt is deferred, the retry is accepted
+            assert_eq!(
+                replies.get(2..),
+                Some(
+                    &[
+                        "450 4.7.1 Greylisted, try again later".to_owned(),
+                        "250 2.0.0 OK".to_owned()
+                    ][..]
+                )
+            );
+            assert_eq!(receiver.recv().await?.to(), &vec!["to@example.net"]);
+
+            let history: Vec<Attempts> = greylist.history().await;
+            assert_eq!(history.len(), 1);
+            assert_eq!(history.first().map(|attempts| attempts.deferred), Some(1));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn line_endings() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let lenient: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let strict: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let ports: (u16, u16) = (lenient.local_addr()?.port(), strict.local_addr()?.port());
+            let _lenient = async_std::task::spawn(serve(
+                vec![lenient.into()],
+                stopped.clone(),
+                params("Lenient", sender.clone()),
+            ));
+            let params: Params = Params {
+                line_endings: LineEndings::Strict,
+                ..params("Strict", sender)
+            };
+            let _strict = async_std::task::spawn(serve(vec![strict.into()], stopped, params));
+            let session: &[u8] = b"HELO client\r\nNOOP\nMAIL FROM:<from@example.org>\r\n\
+                RCPT TO:<to@example.net>\r\nDATA\r\nSubject: Bare\n\r\nContent\n.\r\n";
+
+            // The bare LF are normalized
+            let (mut lines, mut stream) = connect_to(ports.0).await?;
+            stream.write_all(session).await?;
+            for _ in 0..6_u8 {
+                let _ = lines.next().await.ok_or("no next line")??;
+            }
+            assert_eq!(lines.next().await.ok_or("no next line")??, "250 2.0.0 OK");
+            let mail: Mail = receiver.recv().await?;
+            assert!(mail
+                .get_data(&Type::Raw)
+                .ok_or("no raw data")?
+                .ends_with("\r\nSubject: Bare\r\n\r\nContent"));
+            assert_eq!(
+                mail.get_diagnostics(),
+                &vec![
+                    Diagnostic::BareLineFeed { line: 1 },
+                    Diagnostic::BareLineFeed { line: 3 }
+                ]
+            );
+
+            // The command and the mail are refused
+            let (mut lines, mut stream) = connect_to(ports.1).await?;
+            stream.write_all(session).await?;
+            let mut replies: Vec<String> = Vec::new();
+            for _ in 0..7 {
+                replies.push(lines.next().await.ok_or("no next line")??);
+            }
+            assert_eq!(
+                replies.get(2..),
+                Some(
+                    &[
+                        "500 5.5.2 Bare <LF> received, lines must end with <CRLF>".to_owned(),
+                        "250 2.0.0 OK".to_owned(),
+                        "250 2.0.0 OK".to_owned(),
+                        "354 Start mail input; end with <CRLF>.<CRLF>".to_owned(),
+                        "500 5.5.2 Bare <LF> received, lines must end with <CRLF>".to_owned()
+                    ][..]
+                )
+            );
+            assert!(receiver.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn profiles() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let mut listeners: Vec<Listener> = Vec::new();
+            let mut ports: Vec<u16> = Vec::new();
+            for &profile in &[Profile::Normal, Profile::AlwaysTempfail] {
+                let socket: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+                ports.push(socket.local_addr()?.port());
+                listeners.push(Listener { socket, profile });
+            }
+            let _server =
+                async_std::task::spawn(serve(listeners, stopped, params("Profiles", sender)));
+
+            let mut replies: Vec<String> = Vec::new();
+            for &port in &ports {
+                let (mut lines, mut stream) = connect_to(port).await?;
+                let _greeting = lines.next().await.ok_or("no next line")??;
+                stream
+                    .write_all(b"HELO client\r\nMAIL FROM:<from@example.org>\r\nQUIT\r\n")
+                    .await?;
+                let _hello = lines.next().await.ok_or("no next line")??;
+                replies.push(lines.next().await.ok_or("no next line")??);
+            }
+            assert_eq!(
+                replies,
+                vec![
+                    "250 2.0.0 OK",
+                    "451 4.3.0 Temporary failure, try again later",
+                ]
+            );
+            assert!(receiver.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn session_metrics() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = params("Metrics", sender);
+            let metrics: Metrics = params.metrics.clone();
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            let session: &str = "EHLO client\r\nMAIL FROM:<from@example.org>\r\n\
+                RCPT TO:<to@example.net>\r\nRCPT TO:<cc@example.net>\r\nFOO\r\nDATA\r\n\
+                Subject: metrics\r\n\r\nbody\r\n.\r\nQUIT\r\n";
+            stream.write_all(session.as_bytes()).await?;
+            let _mail: Mail = receiver.recv().await?;
+            while !lines
+                .next()
+                .await
+                .ok_or("no next line")??
+                .starts_with("221 ")
+            {}
+
+            let snapshot = metrics.snapshot();
+            assert_eq!(snapshot.connections, 1);
+            assert_eq!(
+                snapshot
+                    .commands
+                    .into_iter()
+                    .collect::<Vec<(String, u64)>>(),
+                vec![
+                    ("DATA".to_owned(), 1),
+                    ("EHLO".to_owned(), 1),
+                    ("INVALID".to_owned(), 1),
+                    ("MAIL".to_owned(), 1),
+                    ("QUIT".to_owned(), 1),
+                    ("RCPT".to_owned(), 2),
+                ]
+            );
+            assert_eq!(snapshot.bytes_received, u64::try_from(session.len())?);
+            assert_eq!(snapshot.rejected, 1);
+            assert_eq!(snapshot.contents, 1);
+            assert!(snapshot.mean_data_size > 0);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn envelope_only_capture() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(2);
+            let params: Params = Params {
+                capture: Capture::new(Some("1/h".parse()?), None),
+                ..params("Capture", sender)
+            };
+            let capture: Capture = params.capture.clone();
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            let content: &str = "Subject: load\r\n\r\nContent";
+            stream
+                .write_all(
+                    format!(
+                        "HELO client\r\nMAIL FROM:<from@example.org>\r\n\
+                         RCPT TO:<to@example.net>\r\nDATA\r\n{0}\r\n.\r\n\
+                         MAIL FROM:<from@example.org>\r\nRCPT TO:<to@example.net>\r\n\
+                         DATA\r\n{0}\r\n.\r\nQUIT\r\n",
+                        content
+                    )
+                    .as_bytes(),
+                )
+                .await?;
+            // Both mails are acknowledged, the second one being above the rate
+            let whole: Mail = receiver.recv().await?;
+            assert_eq!(whole.get_subject(), "load");
+            let envelope: Mail = receiver.recv().await?;
+            assert_eq!(envelope.from(), "from@example.org");
+            assert_eq!(envelope.to(), &vec!["to@example.net".to_owned()]);
+            assert_eq!(envelope.get_subject(), "(No subject)");
+            assert_eq!(
+                envelope.get_diagnostics(),
+                &vec![Diagnostic::ContentDiscarded {
+                    size: content.len()
+                }]
+            );
+            assert_eq!(capture.stats().envelope_only, 1);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn quarantine_cut_off() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, _receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = params("Quarantine", sender);
+            let quarantine: Quarantine = params.quarantine.clone();
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            // Closed between two transactions, nothing is kept
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            stream.write_all(b"HELO client\r\nQUIT\r\n").await?;
+            while lines.next().await.is_some() {}
+            // Closed in the middle of the content, without the final CRLF
+            for session in &[
+                "DATA\r\nSubject: cut\r\n\r\nfirst line\r\nsecond",
+                "BDAT 100 LAST\r\nSubject: chunk\r\n\r\ncut",
+            ] {
+                let (mut lines, mut stream) = connect_to(port).await?;
+                let _greeting = lines.next().await.ok_or("no next line")??;
+                stream
+                    .write_all(
+                        format!(
+                            "HELO client\r\nMAIL FROM:<from@example.org>\r\n\
+                             RCPT TO:<to@example.net>\r\n{}",
+                            session
+                        )
+                        .as_bytes(),
+                    )
+                    .await?;
+                stream.shutdown(std::net::Shutdown::Write)?;
+                while lines.next().await.is_some() {}
+            }
+
+            let mut incomplete: Vec<Incomplete> = Vec::new();
+            for _ in 0..100_u8 {
+                incomplete = quarantine.list(&Filter::default()).await;
+                if incomplete.len() == 2 {
+                    break;
+                }
+                async_std::task::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(incomplete.len(), 2);
+            let chunk: &Incomplete = incomplete.first().ok_or("no chunk")?;
+            assert_eq!(chunk.cutoff, Cutoff::Closed);
+            assert_eq!(chunk.mail.get_subject(), "chunk");
+            let data: &Incomplete = incomplete.get(1).ok_or("no data")?;
+            assert_eq!(data.cutoff, Cutoff::Closed);
+            assert_eq!(data.client, Some("127.0.0.1".parse()?));
+            assert_eq!(data.mail.to(), &vec!["to@example.net".to_owned()]);
+            assert_eq!(
+                data.mail.get_data(&Type::Raw).map(String::as_str),
+                Some("Subject: cut\r\n\r\nfirst line\r\nsecond")
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn reset_in_data() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = params("Reset", sender);
+            let quarantine: Quarantine = params.quarantine.clone();
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            // Closed with the replies unread, the connection is reset
+            let (lines, mut stream) = connect_to(port).await?;
+            stream
+                .write_all(
+                    b"HELO client\r\nMAIL FROM:<from@example.org>\r\n\
+                      RCPT TO:<to@example.net>\r\nDATA\r\nSubject: reset\r\n\r\ncut",
+                )
+                .await?;
+            async_std::task::sleep(Duration::from_millis(200)).await;
+            drop(lines);
+            drop(stream);
+
+            let mut incomplete: Vec<Incomplete> = Vec::new();
+            for _ in 0..100_u8 {
+                incomplete = quarantine.list(&Filter::default()).await;
+                if !incomplete.is_empty() {
+                    break;
+                }
+                async_std::task::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(
+                incomplete.first().map(|incomplete| incomplete.cutoff),
+                Some(Cutoff::Failed)
+            );
+
+            // The next sessions are still served
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let greeting: String = lines.next().await.ok_or("no next line")??;
+            assert!(greeting.starts_with("220 "));
+            stream
+                .write_all(
+                    b"HELO client\r\nMAIL FROM:<from@example.org>\r\n\
+                      RCPT TO:<to@example.net>\r\nDATA\r\nSubject: next\r\n\r\n.\r\nQUIT\r\n",
+                )
+                .await?;
+            assert_eq!(receiver.recv().await?.get_subject(), "next");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn queue_overflow() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let params: Params = Params {
+                queue: Queue::new(receiver.clone(), queue::Overflow::Shed),
+                ..params("Overflow", sender)
+            };
+            let queue: Queue = params.queue.clone();
+            let _server = async_std::task::spawn(serve(vec![listener.into()], stopped, params));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            stream.write_all(b"HELO client\r\n").await?;
+            let _hello = lines.next().await.ok_or("no next line")??;
+            // The first mail fills the queue, the second one is refused
+            let mut replies: Vec<String> = Vec::new();
+            for subject in &["First", "Second"] {
+                stream
+                    .write_all(
+                        format!(
+                            "MAIL FROM:<from@example.org>\r\nRCPT TO:<to@example.net>\r\n\
+                             DATA\r\nSubject: {}\r\n\r\nContent\r\n.\r\n",
+                            subject
+                        )
+                        .as_bytes(),
+                    )
+                    .await?;
+                for _ in 0..4 {
+                    replies.push(lines.next().await.ok_or("no next line")??);
+                }
+            }
+            assert!(replies.get(3).ok_or("no reply")?.starts_with("250 2.0.0 "));
+            assert_eq!(
+                replies.get(7).map(String::as_str),
+                Some("452 4.3.1 Insufficient system storage")
+            );
+
+            let stats: queue::QueueStats = queue.stats().await;
+            assert_eq!((stats.depth, stats.shed), (1, 1));
+            assert_eq!(receiver.recv().await?.get_subject(), "First");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn session_transcript() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        async_std::task::block_on(async {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = listener.local_addr()?.port();
+            let (_stop, stopped): crate::Channel<()> = bounded(1);
+            let (sender, receiver): crate::Channel<Mail> = bounded(1);
+            let _server = async_std::task::spawn(serve(
+                vec![listener.into()],
+                stopped,
+                params("Transcript", sender),
+            ));
+
+            let (mut lines, mut stream) = connect_to(port).await?;
+            let _greeting = lines.next().await.ok_or("no next line")??;
+            stream
+                .write_all(
+                    b"EHLO client\r\nAUTH PLAIN AHVzZXIAc2VjcmV0\r\n\
+                      MAIL FROM:<from@example.org>\r\nRCPT TO:<to@example.net>\r\nDATA\r\n",
+                )
+                .await?;
+            for _ in 0..12 {
+                let _reply = lines.next().await.ok_or("no next line")??;
+            }
+            stream
+                .write_all(b"Subject: Secret\r\n\r\nContent\r\n.\r\n")
+                .await?;
+            let mail: Mail = receiver.recv().await?;
+
+            let transcript: serde_json::Value = mail
+                .get_transcript()
+                .map(Transcript::to_json)
+                .ok_or("no transcript")?;
+            assert_eq!(transcript.get("client"), Some(&"127.0.0.1".into()));
+            let exchanged: Vec<String> = transcript
+                .get("lines")
+                .and_then(serde_json::Value::as_array)
+                .ok_or("no lines")?
+                .iter()
+                .map(|line| {
+                    format!(
+                        "{} {}",
+                        line.get("side")
+                            .and_then(serde_json::Value::as_str)
+                            .unwrap_or_default(),
+                        line.get("text")
+                            .and_then(serde_json::Value::as_str)
+                            .unwrap_or_default()
+                    )
+                })
+                .collect();
+            assert_eq!(
+                exchanged,
+                vec![
+                    "server 220 Transcript ESMTP",
+                    "client EHLO client",
+                    "server 250-Transcript",
+                    "server 250-AUTH PLAIN LOGIN",
+                    "server 250-CHUNKING",
+                    "server 250-8BITMIME",
+                    "server 250-SMTPUTF8",
+                    "server 250-ENHANCEDSTATUSCODES",
+                    "server 250-PIPELINING",
+                    "server 250 DSN",
+                    // The credentials and the content are not kept
+                    "client AUTH PLAIN *****",
+                    "server 235 2.7.0 Authentication successful",
+                    "client MAIL FROM:<from@example.org>",
+                    "server 250 2.0.0 OK",
+                    "client RCPT TO:<to@example.net>",
+                    "server 250 2.0.0 OK",
+                    "client DATA",
+                    "server 354 Start mail input; end with <CRLF>.<CRLF>",
+                    "client .",
+                    "server 250 2.0.0 OK",
+                ]
+            );
+
+            Ok(())
+        })
+    }
 }
@@ -0,0 +1,129 @@
+use std::{fmt, str::FromStr};
+
+use tide::prelude::Serialize;
+
+/// Port listened on by default
+const DEFAULT_PORT: u16 = 1025;
+
+/// Behavior of a SMTP listener, so a single catcher can play several servers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// The mails are caught
+    Normal,
+    /// The transactions are deferred with a 451 reply, like a server always
+    /// failing temporarily
+    AlwaysTempfail,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "normal" => Ok(Self::Normal),
+            // Its clients could never go on, without STARTTLS to offer them
+            "tls-required" => Err(
+                "The tls-required profile needs STARTTLS, which is not supported yet".to_owned(),
+            ),
+            "always-tempfail" => Ok(Self::AlwaysTempfail),
+            _ => Err(format!(
+                "Unknown profile {}, expected normal or always-tempfail",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Self::Normal => "normal",
+            Self::AlwaysTempfail => "always-tempfail",
+        })
+    }
+}
+
+/// Port of a SMTP listener with its profile, given as `port[:profile]`, like
+/// `1025` or `1027:always-tempfail`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListenerSpec {
+    /// Port to listen on
+    pub port: u16,
+    /// Behavior of the listener
+    pub profile: Profile,
+}
+
+impl Default for ListenerSpec {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            profile: Profile::default(),
+        }
+    }
+}
+
+impl FromStr for ListenerSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (port, profile): (&str, Option<&str>) = match s.split_once(':') {
+            Some((port, profile)) => (port, Some(profile)),
+            None => (s, None),
+        };
+        Ok(Self {
+            port: port
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid port {}: {}", port, e))?,
+            profile: profile.map(str::parse).transpose()?.unwrap_or_default(),
+        })
+    }
+}
+
+impl fmt::Display for ListenerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.port, self.profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn listener_specs() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        assert_eq!("1025".parse(), Ok(ListenerSpec::default()));
+        let spec: ListenerSpec = "1027:Always-Tempfail".parse()?;
+        assert_eq!(
+            spec,
+            ListenerSpec {
+                port: 1027,
+                profile: Profile::AlwaysTempfail
+            }
+        );
+        assert_eq!(spec.to_string(), "1027:always-tempfail");
+
+        for invalid in &[
+            "",
+            "smtp",
+            "70000",
+            "1025:",
+            "1025:slow",
+            "1026:tls-required",
+        ] {
+            assert!(invalid.parse::<ListenerSpec>().is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex},
+};
+
+use async_std::{channel::Sender, io::BufReader, net::TcpStream, prelude::FutureExt};
+use futures::{AsyncBufReadExt, AsyncWriteExt};
+
+use crate::{
+    encoding::decode_8bit,
+    error::MailcatcherError,
+    mail::{journal::Journal, Client, Mail},
+    settings::Settings,
+    smtp::{address::Path, command::Command, reply::Reply},
+    utils::ConnectionInfo,
+};
+
+/// Mail transaction seen while relaying a session to the upstream server
+#[derive(Debug, Default)]
+struct Capture {
+    /// Expeditor mail address
+    addr_from: Option<Path>,
+    /// Recipient(s) address
+    addr_to: Vec<Path>,
+    /// DATA has been sent by the client, waiting for the upstream to accept it
+    data_requested: bool,
+    /// Are we in data reception or not
+    receive_data: bool,
+    /// Received data
+    data: String,
+    /// Mail whose content is complete, waiting for the upstream to accept it
+    pending: Option<Mail>,
+    /// Size above which only the headers of the mails are kept
+    headers_only: Option<usize>,
+    /// Client of the session, as given to the mails
+    client: Client,
+    /// EHLO has been sent by the client, waiting for the upstream reply
+    ehlo_requested: bool,
+    /// Lines of the EHLO reply of the upstream kept so far
+    ehlo_reply: Vec<String>,
+}
+
+/// Extensions of the upstream that the relay cannot follow, the session being
+/// relayed line by line in clear: they are not advertised to the client
+const UNRELAYED: [&str; 3] = ["STARTTLS", "CHUNKING", "BINARYMIME"];
+
+/// What to send to the client for a reply line of the upstream server
+#[derive(Debug, PartialEq, Eq)]
+enum Forward {
+    /// The line, as it was received
+    AsIs,
+    /// Nothing yet, the line being part of the EHLO reply
+    Held,
+    /// The whole EHLO reply, without the extensions that cannot be relayed
+    Ehlo(String),
+}
+
+impl Capture {
+    /// Track a line sent by the client, the mail being pending once its content
+    /// is complete
+    #[allow(clippy::indexing_slicing)]
+    fn client_line(&mut self, line: &str) {
+        match Command::parse(Cow::Borrowed(line), self.receive_data, false) {
+            // An invalid address is refused by the upstream server too
+            Command::From(from) => self.addr_from = from.parse().ok(),
+            Command::Recipient(to) => self.addr_to.extend(to.parse::<Path>().ok()),
+            Command::DataStart => self.data_requested = true,
+            // Store the line, removing any one dot at the beginning of a line
+            Command::Data(content) => {
+                let start_idx: usize = usize::from(content.starts_with('.'));
+                if !self.data.is_empty() {
+                    self.data.push_str("\r\n");
+                }
+                self.data.push_str(&content[start_idx..]);
+            }
+            // Mail content is complete, keep a copy of it until the upstream
+            // replies
+            Command::DataEnd => {
+                let from: Path = self.addr_from.take().unwrap_or_default();
+                let to: Vec<String> = self.addr_to.iter().map(|to| to.mailbox.clone()).collect();
+                let mut mail: Mail =
+                    Mail::with_headers_only(&from.mailbox, &to, &self.data, self.headers_only);
+                mail.set_params(
+                    from.params,
+                    self.addr_to.iter().map(|to| to.params.clone()).collect(),
+                );
+                mail.set_client(Some(self.client.clone()));
+                self.reset();
+                self.pending = Some(mail);
+            }
+            Command::Reset => self.reset(),
+            Command::Hello(remote_name) => self.client.helo = Some(remote_name),
+            Command::Ehllo(remote_name) => {
+                self.client.helo = Some(remote_name);
+                self.ehlo_requested = true;
+                self.ehlo_reply.clear();
+            }
+            // Nothing to capture
+            Command::StartTls
+            | Command::Auth(_)
+            | Command::AuthResponse(_)
+            | Command::Bdat(_, _)
+            | Command::Noop
+            | Command::Vrfy(_)
+            | Command::Expn(_)
+            | Command::Help
+            | Command::Quit
+            | Command::Error(_) => {}
+        }
+    }
+
+    /// Track a reply line sent by the upstream server, returning the pending
+    /// mail once the upstream accepted it
+    fn server_line(&mut self, line: &str) -> Option<Mail> {
+        // Only a DATA command can receive a 354 reply
+        if self.data_requested && line.starts_with("354") {
+            self.data_requested = false;
+            self.receive_data = true;
+        }
+        // The reply to the end of the content is the next one, only its last
+        // line is considered
+        if line.as_bytes().get(3) == Some(&b'-') {
+            return None;
+        }
+        let mail: Mail = self.pending.take()?;
+        if line.starts_with('2') {
+            Some(mail)
+        } else {
+            log::warn!("Mail {} refused by the upstream: {}", mail.get_id(), line);
+            None
+        }
+    }
+
+    /// Filter a reply line sent by the upstream server, the EHLO reply being
+    /// sent once complete, without the extensions in `UNRELAYED`
+    fn forward(&mut self, line: &str) -> Forward {
+        if !self.ehlo_requested {
+            return Forward::AsIs;
+        }
+        let keyword: &str = line
+            .get(4..)
+            .and_then(|text| text.split_whitespace().next())
+            .unwrap_or_default();
+        // The first line greets the client, it is never an extension
+        if self.ehlo_reply.is_empty()
+            || !UNRELAYED
+                .iter()
+                .any(|extension| keyword.eq_ignore_ascii_case(extension))
+        {
+            self.ehlo_reply.push(line.to_owned());
+        }
+        if line.as_bytes().get(3) == Some(&b'-') {
+            return Forward::Held;
+        }
+
+        self.ehlo_requested = false;
+        let last: usize = self.ehlo_reply.len().saturating_sub(1);
+        let mut reply: String = String::new();
+        for (idx, line) in self.ehlo_reply.drain(..).enumerate() {
+            reply.push_str(line.get(..3).unwrap_or(&line));
+            reply.push(if idx == last { ' ' } else { '-' });
+            reply.push_str(line.get(4..).unwrap_or_default());
+            reply.push_str("\r\n");
+        }
+        Forward::Ehlo(reply)
+    }
+
+    /// Forget the current mail transaction
+    fn reset(&mut self) {
+        self.addr_from = None;
+        self.addr_to.clear();
+        self.data_requested = false;
+        self.receive_data = false;
+        self.data.clear();
+    }
+}
+
+/// Relay the client session to the upstream SMTP server, keeping a copy of each mail sent
+///
+/// The whole transaction is logged, each line prefixed by `C:` when sent by the
+/// client or `S:` when sent by the upstream server.
+///
+/// Only the mails accepted by the upstream server are kept. If it cannot be
+/// reached, the client is answered with a 421 reply and disconnected.
+///
+/// The bytes are relayed as they are received, the 8-bit lines included. The
+/// extensions switching to TLS or to binary chunks are removed from the EHLO
+/// reply of the upstream server, as the relay follows the session in clear, line
+/// by line.
+pub async fn passthrough(
+    mut client: TcpStream,
+    upstream: &str,
+    conn: ConnectionInfo,
+    mails_broker: Sender<Mail>,
+    journal: Option<Journal>,
+    settings: &Settings,
+    server_name: &str,
+) -> crate::Result<()> {
+    // Connect to the real SMTP server
+    let server: TcpStream = match TcpStream::connect(upstream).await {
+        Ok(server) => server,
+        Err(e) => {
+            let unavailable: String = Reply::Unavailable.message(&settings.replies, server_name);
+            client
+                .write_all(unavailable.as_bytes())
+                .await
+                .unwrap_or_default();
+            return Err(MailcatcherError::smtp(format!(
+                "Upstream {} unreachable: {}",
+                upstream, e
+            )));
+        }
+    };
+    log::info!("Relaying {} to upstream {}", conn, upstream);
+
+    let capture: Arc<Mutex<Capture>> = Arc::new(Mutex::new(Capture {
+        headers_only: settings.headers_only,
+        client: Client {
+            ip: conn.peer_addr.map(|addr| addr.ip()),
+            ..Client::default()
+        },
+        ..Capture::default()
+    }));
+
+    // Forward both directions, until one of the side closes the connection
+    relay_client(
+        BufReader::new(client.clone()),
+        server.clone(),
+        Arc::clone(&capture),
+    )
+    .race(relay_server(
+        BufReader::new(server),
+        client,
+        capture,
+        mails_broker,
+        journal,
+    ))
+    .await?;
+
+    log::info!(">>> {}", conn);
+
+    Ok(())
+}
+
+/// Line read as is, decoded like the command lines of the SMTP sessions,
+/// without its line ending
+fn text_line(buffer: &[u8]) -> String {
+    let line: Cow<str> = decode_8bit(buffer);
+    let line: &str = line.strip_suffix('\n').unwrap_or(&line);
+    line.strip_suffix('\r').unwrap_or(line).to_owned()
+}
+
+/// Forward the client lines to the upstream server, their bytes being sent as
+/// they were received
+async fn relay_client(
+    mut reader: BufReader<TcpStream>,
+    mut server: TcpStream,
+    capture: Arc<Mutex<Capture>>,
+) -> crate::Result<()> {
+    let mut buffer: Vec<u8> = Vec::new();
+    while reader
+        .read_until(b'\n', &mut buffer)
+        .await
+        .map_err(MailcatcherError::smtp)?
+        > 0
+    {
+        let line: String = text_line(&buffer);
+        log::debug!("C: {}", line);
+        capture
+            .lock()
+            .map_err(|e| MailcatcherError::smtp(e.to_string()))?
+            .client_line(&line);
+        server
+            .write_all(&buffer)
+            .await
+            .map_err(MailcatcherError::smtp)?;
+        buffer.clear();
+    }
+    Ok(())
+}
+
+/// Forward the upstream server replies to the client, keeping the mails it
+/// accepted
+async fn relay_server(
+    mut reader: BufReader<TcpStream>,
+    mut client: TcpStream,
+    capture: Arc<Mutex<Capture>>,
+    mails_broker: Sender<Mail>,
+    journal: Option<Journal>,
+) -> crate::Result<()> {
+    let mut buffer: Vec<u8> = Vec::new();
+    while reader
+        .read_until(b'\n', &mut buffer)
+        .await
+        .map_err(MailcatcherError::smtp)?
+        > 0
+    {
+        let line: String = text_line(&buffer);
+        log::debug!("S: {}", line);
+        let (mail, forward): (Option<Mail>, Forward) = {
+            let mut tracked = capture
+                .lock()
+                .map_err(|e| MailcatcherError::smtp(e.to_string()))?;
+            (tracked.server_line(&line), tracked.forward(&line))
+        };
+        let sent: &[u8] = match forward {
+            Forward::AsIs => &buffer,
+            Forward::Held => &[],
+            Forward::Ehlo(ref reply) => reply.as_bytes(),
+        };
+        client
+            .write_all(sent)
+            .await
+            .map_err(MailcatcherError::smtp)?;
+        buffer.clear();
+        // If a mail has been accepted, keep it then send it to the HTTP side
+        if let Some(mail) = mail {
+            if let Some(ref journal) = journal {
+                // The upstream server is in charge of the mail, so it's not an error
+                if let Err(e) = journal.append(&mail).await {
+                    log::error!("Mail {} not written in the journal: {}", mail.get_id(), e);
+                }
+            }
+            mails_broker.send(mail).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_transaction() {
+        crate::test::log_init();
+
+        let mut capture: Capture = Capture::default();
+
+        // Commands before the DATA acceptance are never captured as content
+        for line in &[
+            "EHLO client",
+            "MAIL FROM:<from@example.org>",
+            "RCPT TO:<to@example.net>",
+            "DATA",
+        ] {
+            capture.client_line(line);
+        }
+        assert!(!capture.receive_data);
+        assert!(capture
+            .server_line("354 Start mail input; end with <CRLF>.<CRLF>")
+            .is_none());
+        assert!(capture.receive_data);
+
+        for line in &["Subject: Relayed", "", "..Dot stuffed", "."] {
+            capture.client_line(line);
+        }
+        // Only kept once the upstream accepts it
+        assert!(capture.server_line("250-Queued").is_none());
+        let mail: Mail = capture.server_line("250 Ok").expect("captured mail");
+
+        assert_eq!(mail.from(), "from@example.org");
+        assert_eq!(mail.to(), &vec!["to@example.net".to_owned()]);
+        assert_eq!(mail.get_subject(), "Relayed");
+        assert_eq!(mail.get_text().expect("mail text"), ".Dot stuffed");
+        assert_eq!(
+            mail.get_client().and_then(|client| client.helo.as_deref()),
+            Some("client")
+        );
+        assert!(!capture.receive_data);
+    }
+
+    #[test]
+    fn capture_rejected_data() {
+        crate::test::log_init();
+
+        let mut capture: Capture = Capture::default();
+
+        capture.client_line("DATA");
+        assert!(capture
+            .server_line("503 Bad sequence of commands")
+            .is_none());
+        assert!(!capture.receive_data);
+
+        // The client gives up, it's a command and not some content
+        capture.client_line("RSET");
+        assert!(!capture.data_requested);
+        assert!(capture.data.is_empty());
+    }
+
+    #[test]
+    fn capture_refused_mail() {
+        crate::test::log_init();
+
+        let mut capture: Capture = Capture::default();
+
+        for line in &[
+            "MAIL FROM:<from@example.org>",
+            "RCPT TO:<to@example.net>",
+            "DATA",
+        ] {
+            capture.client_line(line);
+        }
+        assert!(capture.server_line("354 Go ahead").is_none());
+        for line in &["Subject: Refused", "", "Spam", "."] {
+            capture.client_line(line);
+        }
+        // Refused by the upstream, the mail is not kept
+        assert!(capture.server_line("554 5.7.1 Message rejected").is_none());
+        assert!(capture.pending.is_none());
+        assert!(capture.server_line("250 Ok").is_none());
+    }
+
+    #[test]
+    fn capture_ehlo_reply() {
+        crate::test::log_init();
+
+        let mut capture: Capture = Capture::default();
+
+        // Only the EHLO reply is filtered
+        assert_eq!(capture.forward("220-upstream.test ESMTP"), Forward::AsIs);
+        capture.client_line("EHLO client");
+        for line in &[
+            "250-upstream.test Hello",
+            "250-PIPELINING",
+            "250-starttls",
+            "250-8BITMIME",
+        ] {
+            assert_eq!(capture.forward(line), Forward::Held);
+        }
+        assert_eq!(
+            capture.forward("250 CHUNKING"),
+            Forward::Ehlo(
+                "250-upstream.test Hello\r\n250-PIPELINING\r\n250 8BITMIME\r\n".to_owned()
+            )
+        );
+        assert_eq!(capture.forward("250 Ok"), Forward::AsIs);
+
+        // An error is sent like it is
+        capture.client_line("EHLO client");
+        assert_eq!(
+            capture.forward("502 Not implemented"),
+            Forward::Ehlo("502 Not implemented\r\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn capture_8bit_content() {
+        crate::test::log_init();
+
+        let mut capture: Capture = Capture::default();
+
+        for line in &[
+            "MAIL FROM:<from@example.org>",
+            "RCPT TO:<to@example.net>",
+            "DATA",
+        ] {
+            capture.client_line(line);
+        }
+        assert!(capture.server_line("354 Go ahead").is_none());
+        for line in &[
+            &b"Subject: Caf\xe9\r\n"[..],
+            b"\r\n",
+            "Déjà vu\n".as_bytes(),
+            b".\r\n",
+        ] {
+            capture.client_line(&text_line(line));
+        }
+        let mail: Mail = capture.server_line("250 Ok").expect("captured mail");
+
+        assert_eq!(mail.get_subject(), "Café");
+        assert_eq!(mail.get_text().expect("mail text"), "Déjà vu");
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::VecDeque, fmt, net::IpAddr, sync::Arc};
+
+use async_std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use tide::prelude::Serialize;
+use ulid::Ulid;
+
+use crate::mail::{filter::Filter, Mail};
+
+/// Largest number of incomplete transactions kept, the oldest being forgotten
+const MAX_INCOMPLETE: usize = 100;
+
+/// Way a transaction was cut off in the middle of its content
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cutoff {
+    /// The client closed the connection
+    Closed,
+    /// The connection failed, like when it is reset
+    Failed,
+    /// The client stayed silent for too long
+    Timeout,
+}
+
+impl fmt::Display for Cutoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Self::Closed => "connection closed",
+            Self::Failed => "connection failed",
+            Self::Timeout => "session timed out",
+        })
+    }
+}
+
+/// Transaction cut off before the end of its content, with what was received
+#[derive(Clone, Debug)]
+pub struct Incomplete {
+    /// Way the transaction was cut off
+    pub cutoff: Cutoff,
+    /// Address of the client
+    pub client: Option<IpAddr>,
+    /// Time the transaction was cut off
+    pub at: DateTime<Utc>,
+    /// Mail parsed from the partial content
+    pub mail: Mail,
+}
+
+/// Transactions cut off in the middle of their content, kept apart from the
+/// mails so the flaky clients can be diagnosed, shared with the HTTP side
+#[derive(Clone, Debug, Default)]
+pub struct Quarantine {
+    /// Incomplete transactions, the oldest first
+    incomplete: Arc<Mutex<VecDeque<Incomplete>>>,
+}
+
+impl Quarantine {
+    /// Keep an incomplete transaction, forgetting the oldest one if there are
+    /// too many
+    pub async fn keep(&self, incomplete: Incomplete) {
+        let mut kept = self.incomplete.lock().await;
+        if kept.len() >= MAX_INCOMPLETE {
+            let _oldest: Option<Incomplete> = kept.pop_front();
+        }
+        kept.push_back(incomplete);
+    }
+
+    /// Incomplete transactions whose partial mail matches the filter, the
+    /// newest first
+    pub async fn list(&self, filter: &Filter) -> Vec<Incomplete> {
+        self.incomplete
+            .lock()
+            .await
+            .iter()
+            .rev()
+            .filter(|incomplete| filter.matches(&incomplete.mail))
+            .cloned()
+            .collect()
+    }
+
+    /// Incomplete transaction whose partial mail has the id
+    pub async fn get(&self, id: Ulid) -> Option<Incomplete> {
+        self.incomplete
+            .lock()
+            .await
+            .iter()
+            .find(|incomplete| incomplete.mail.get_id() == id)
+            .cloned()
+    }
+
+    /// Forget the incomplete transactions
+    pub async fn clear(&self) {
+        self.incomplete.lock().await.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn keep_incomplete() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        task::block_on(async {
+            let quarantine: Quarantine = Quarantine::default();
+            let incomplete = |to: &str| Incomplete {
+                cutoff: Cutoff::Closed,
+                client: None,
+                at: Utc::now(),
+                mail: Mail::new("from@example.org", &[to.to_owned()], "Subject: Cut"),
+            };
+            for _ in 0..MAX_INCOMPLETE {
+                quarantine.keep(incomplete("old@example.net")).await;
+            }
+            let newest: Incomplete = incomplete("new@example.net");
+            let id: Ulid = newest.mail.get_id();
+            quarantine.keep(newest).await;
+
+            let all: Vec<Incomplete> = quarantine.list(&Filter::default()).await;
+            assert_eq!(all.len(), MAX_INCOMPLETE);
+            assert_eq!(all.first().map(|newest| newest.mail.get_id()), Some(id));
+
+            let filter: Filter = "to:new@example.net".parse()?;
+            assert_eq!(quarantine.list(&filter).await.len(), 1);
+            assert!(quarantine.get(id).await.is_some());
+            assert!(quarantine.get(Ulid::new()).await.is_none());
+
+            quarantine.clear().await;
+            assert!(quarantine.list(&Filter::default()).await.is_empty());
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_std::{channel::Receiver, sync::Mutex};
+use tide::prelude::Serialize;
+
+use crate::mail::Mail;
+
+/// Period over which the arrivals are counted
+const RATE_PERIOD: Duration = Duration::from_mins(1);
+
+/// What happens to a new mail when the queue of the received mails is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// The session waits for room in the queue before acknowledging the mail
+    Block,
+    /// The mail is refused with a 452 reply, the client can try again later
+    Shed,
+    /// The oldest mail waiting is dropped to make room for the new one
+    DropOldest,
+}
+
+impl Default for Overflow {
+    fn default() -> Self {
+        Self::Block
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "block" => Ok(Self::Block),
+            "shed" => Ok(Self::Shed),
+            "drop-oldest" => Ok(Self::DropOldest),
+            _ => Err(format!(
+                "Unknown overflow {}, expected block, shed or drop-oldest",
+                s
+            )),
+        }
+    }
+}
+
+/// State of the queue of the received mails, given by `/api/stats/queues`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct QueueStats {
+    /// Number of mails waiting for the mail broker
+    pub depth: usize,
+    /// Largest number of mails waiting, without limit if `None`
+    pub capacity: Option<usize>,
+    /// What happens to a new mail when the queue is full
+    pub overflow: Overflow,
+    /// Number of mails received during the last minute
+    pub arrivals_last_minute: usize,
+    /// Number of mails refused as the queue was full
+    pub shed: usize,
+    /// Number of mails dropped to make room for newer ones
+    pub dropped: usize,
+}
+
+/// Queue of the mails received by the SMTP side, waiting for the mail broker,
+/// shared with the HTTP side to see how deep it is
+#[derive(Clone, Debug, Default)]
+pub struct Queue {
+    /// Mails waiting, to count them and to drop the oldest one, unknown if `None`
+    waiting: Option<Receiver<Mail>>,
+    /// What happens to a new mail when the queue is full
+    overflow: Overflow,
+    /// Times of the arrivals of the last minute
+    arrivals: Arc<Mutex<VecDeque<Instant>>>,
+    /// Number of mails refused
+    shed: Arc<AtomicUsize>,
+    /// Number of mails dropped
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Queue {
+    /// Queue of the mails `waiting` for the broker, with the policy to apply
+    /// when it is full
+    pub fn new(waiting: Receiver<Mail>, overflow: Overflow) -> Self {
+        Self {
+            waiting: Some(waiting),
+            overflow,
+            ..Self::default()
+        }
+    }
+
+    /// Check if the queue is full
+    fn is_full(&self) -> bool {
+        self.waiting.as_ref().map_or(false, Receiver::is_full)
+    }
+
+    /// Check if a new mail must be refused, the queue being full and the mails
+    /// over it shed
+    pub fn sheds(&self) -> bool {
+        if self.overflow == Overflow::Shed && self.is_full() {
+            let _ = self.shed.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    /// Make room for a new mail if the queue is full and the oldest mails are
+    /// dropped, returning the mail dropped
+    pub fn make_room(&self) -> Option<Mail> {
+        if self.overflow != Overflow::DropOldest || !self.is_full() {
+            return None;
+        }
+        let dropped: Mail = self.waiting.as_ref()?.try_recv().ok()?;
+        let _ = self.dropped.fetch_add(1, Ordering::Relaxed);
+        log::warn!(
+            "Queue of the received mails full, mail {} dropped",
+            dropped.get_id()
+        );
+        Some(dropped)
+    }
+
+    /// Record the arrival of a new mail in the queue
+    pub async fn arrived(&self) {
+        let mut arrivals = self.arrivals.lock().await;
+        Self::forget_old(&mut arrivals);
+        arrivals.push_back(Instant::now());
+    }
+
+    /// Forget the arrivals older than the period of the rate
+    fn forget_old(arrivals: &mut VecDeque<Instant>) {
+        while arrivals
+            .front()
+            .map_or(false, |time| time.elapsed() >= RATE_PERIOD)
+        {
+            let _ = arrivals.pop_front();
+        }
+    }
+
+    /// Current state of the queue
+    pub async fn stats(&self) -> QueueStats {
+        let mut arrivals = self.arrivals.lock().await;
+        Self::forget_old(&mut arrivals);
+        QueueStats {
+            depth: self.waiting.as_ref().map_or(0, Receiver::len),
+            capacity: self.waiting.as_ref().and_then(Receiver::capacity),
+            overflow: self.overflow,
+            arrivals_last_minute: arrivals.len(),
+            shed: self.shed.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{
+        channel::{bounded, Sender},
+        task,
+    };
+
+    use super::*;
+
+    #[test]
+    fn overflows() {
+        crate::test::log_init();
+
+        assert_eq!("block".parse(), Ok(Overflow::Block));
+        assert_eq!("Shed".parse(), Ok(Overflow::Shed));
+        assert_eq!("drop-oldest".parse(), Ok(Overflow::DropOldest));
+        assert!("drop".parse::<Overflow>().is_err());
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn full_queue() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        task::block_on(async {
+            let (sender, receiver): (Sender<Mail>, Receiver<Mail>) = bounded(1);
+            let shedding: Queue = Queue::new(receiver.clone(), Overflow::Shed);
+            let dropping: Queue = Queue::new(receiver.clone(), Overflow::DropOldest);
+            let blocking: Queue = Queue::new(receiver, Overflow::Block);
+
+            // Room left
+            assert!(!shedding.sheds());
+            assert!(dropping.make_room().is_none());
+
+            let oldest: Mail = Mail::fake();
+            sender.send(oldest.clone()).await?;
+            shedding.arrived().await;
+            assert!(!blocking.sheds());
+            assert!(blocking.make_room().is_none());
+            assert!(shedding.sheds());
+            assert_eq!(
+                dropping.make_room().map(|mail| mail.get_id()),
+                Some(oldest.get_id())
+            );
+
+            assert_eq!(
+                shedding.stats().await,
+                QueueStats {
+                    depth: 0,
+                    capacity: Some(1),
+                    overflow: Overflow::Shed,
+                    arrivals_last_minute: 1,
+                    shed: 1,
+                    dropped: 0,
+                }
+            );
+            assert_eq!(dropping.stats().await.dropped, 1);
+
+            Ok(())
+        })
+    }
+}
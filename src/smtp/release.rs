@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+use async_std::{
+    io::{
+        prelude::{BufReadExt, WriteExt},
+        BufReader, Lines,
+    },
+    net::TcpStream,
+    prelude::FutureExt,
+};
+use futures::StreamExt;
+
+use crate::{
+    encoding::encode_8bit,
+    error::MailcatcherError,
+    mail::{Mail, Type},
+    smtp::{auth::Credentials, MASK},
+};
+
+/// Longest duration of a release, so a server that does not reply fails it
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Name given by the catcher with EHLO
+const CLIENT_NAME: &str = "mailcatcher";
+
+/// SMTP server the caught mails are released to, to really deliver them
+///
+/// The connection is in clear text, as the catcher does not support TLS yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// Address of the server, as `host:port`
+    pub server: String,
+    /// Credentials given with AUTH PLAIN, if the server needs them
+    pub credentials: Option<Credentials>,
+}
+
+/// Client session with the server a mail is released to
+#[derive(Debug)]
+struct Session {
+    /// Connection to the server, to send the commands
+    stream: TcpStream,
+    /// Lines sent by the server
+    lines: Lines<BufReader<TcpStream>>,
+}
+
+impl Session {
+    /// Send a command, and check the code of the reply, that is returned
+    async fn command(&mut self, command: &str, expected: u16) -> crate::Result<String> {
+        if command.starts_with("AUTH ") {
+            log::debug!("C: AUTH PLAIN {}", MASK);
+        } else {
+            log::debug!("C: {}", command.lines().next().unwrap_or_default());
+        }
+        self.send(command.as_bytes(), expected).await
+    }
+
+    /// Send the content of the mail, and check the code of the reply, that is
+    /// returned
+    async fn content(&mut self, content: &[u8], expected: u16) -> crate::Result<String> {
+        log::debug!("C: <{} bytes of content>", content.len());
+        self.send(content, expected).await
+    }
+
+    /// Send some bytes, and check the code of the reply, that is returned
+    async fn send(&mut self, bytes: &[u8], expected: u16) -> crate::Result<String> {
+        self.stream
+            .write_all(bytes)
+            .await
+            .map_err(MailcatcherError::smtp)?;
+        self.reply(expected).await
+    }
+
+    /// Read a reply, that can span several lines, and check its code
+    ///
+    /// When a positive completion is expected, any 2xx code is accepted, like 251
+    /// for a recipient that is forwarded.
+    async fn reply(&mut self, expected: u16) -> crate::Result<String> {
+        loop {
+            let line: String = self
+                .lines
+                .next()
+                .await
+                .ok_or_else(|| MailcatcherError::smtp("Connection closed by the server"))?
+                .map_err(MailcatcherError::smtp)?;
+            log::debug!("S: {}", line);
+            if line.get(3..4) == Some("-") {
+                continue;
+            }
+            let code: Option<u16> = line.get(..3).and_then(|code| code.parse().ok());
+            if !code.map_or(false, |code| is_accepted(code, expected)) {
+                return Err(MailcatcherError::smtp(format!(
+                    "\"{}\" replied, expected {}",
+                    line, expected
+                )));
+            }
+            return Ok(line);
+        }
+    }
+}
+
+/// Deliver a caught mail to a real SMTP server, with its envelope and its raw
+/// content, returning the reply of the server accepting it
+pub async fn release(target: &Target, mail: &Mail) -> crate::Result<String> {
+    deliver(target, mail)
+        .timeout(TIMEOUT)
+        .await
+        .unwrap_or_else(|_| Err(MailcatcherError::smtp("No reply in time")))
+}
+
+/// Run the SMTP session delivering the mail
+async fn deliver(target: &Target, mail: &Mail) -> crate::Result<String> {
+    let stream: TcpStream = TcpStream::connect(&target.server)
+        .await
+        .map_err(MailcatcherError::smtp)?;
+    let mut session: Session = Session {
+        lines: BufReader::new(stream.clone()).lines(),
+        stream,
+    };
+
+    let _greeting: String = session.reply(220).await?;
+    let ehlo: String = format!("EHLO {}\r\n", CLIENT_NAME);
+    if session.command(&ehlo, 250).await.is_err() {
+        let _hello: String = session
+            .command(&format!("HELO {}\r\n", CLIENT_NAME), 250)
+            .await?;
+    }
+    if let Some(ref credentials) = target.credentials {
+        let _auth: String = session
+            .command(&format!("AUTH PLAIN {}\r\n", credentials.plain()), 235)
+            .await?;
+    }
+    let raw: &str = mail.get_data(&Type::Raw).map_or("", String::as_str);
+    // The 8-bit content is sent as it was received
+    let body: &str = if raw.is_ascii() { "" } else { " BODY=8BITMIME" };
+    let _from: String = session
+        .command(&format!("MAIL FROM:<{}>{}\r\n", mail.from(), body), 250)
+        .await?;
+    for to in mail.to() {
+        let _to: String = session
+            .command(&format!("RCPT TO:<{}>\r\n", to), 250)
+            .await?;
+    }
+    let _data: String = session.command("DATA\r\n", 354).await?;
+    let accepted: String = session.content(&encode_8bit(&content(raw)), 250).await?;
+    // The mail is accepted, the server can close the session as it wants
+    if let Err(e) = session.command("QUIT\r\n", 221).await {
+        log::debug!("Release session not closed: {}", e);
+    }
+
+    log::info!(
+        "Mail {} released to {}: {}",
+        mail.get_id(),
+        target.server,
+        accepted
+    );
+    Ok(accepted)
+}
+
+/// Check the code of a reply, any positive completion being accepted when one
+/// is expected
+fn is_accepted(code: u16, expected: u16) -> bool {
+    let positive = |code: &u16| (200..300).contains(code);
+    code == expected || (positive(&code) && positive(&expected))
+}
+
+/// Content sent after DATA, with CRLF line endings, the lines beginning with a
+/// dot doubled, and the final dot
+fn content(data: &str) -> String {
+    let mut content: String = String::with_capacity(data.len().saturating_add(5));
+    for line in data.lines() {
+        if line.starts_with('.') {
+            content.push('.');
+        }
+        content.push_str(line);
+        content.push_str("\r\n");
+    }
+    content.push_str(".\r\n");
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_stuffing() {
+        crate::test::log_init();
+
+        assert_eq!(
+            content("Subject: Dots\r\n\r\n.hidden\n..\r\nend"),
+            "Subject: Dots\r\n\r\n..hidden\r\n...\r\nend\r\n.\r\n"
+        );
+        assert_eq!(content(""), ".\r\n");
+    }
+
+    #[test]
+    fn reply_codes() {
+        crate::test::log_init();
+
+        assert!(is_accepted(250, 250));
+        assert!(is_accepted(251, 250));
+        assert!(is_accepted(235, 235));
+        assert!(!is_accepted(550, 250));
+        assert!(!is_accepted(250, 354));
+        assert!(!is_accepted(334, 235));
+    }
+
+    #[test]
+    fn content_8bit() {
+        crate::test::log_init();
+
+        // Kept in Latin-1 at the reception, sent back as is
+        assert_eq!(
+            encode_8bit(&content(
+                "Subject: Caf\u{e9}\r\n\r\nD\u{e9}j\u{e0} vu \u{20ac}"
+            )),
+            b"Subject: Caf\xe9\r\n\r\nD\xc3\xa9j\xc3\xa0 vu \xe2\x82\xac\r\n.\r\n".to_vec()
+        );
+    }
+}
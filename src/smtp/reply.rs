@@ -0,0 +1,278 @@
+use std::{collections::BTreeMap, iter::Peekable};
+
+use tide::prelude::{Deserialize, Serialize};
+
+/// Custom texts of the replies, replacing the default ones
+pub type Replies = BTreeMap<Reply, String>;
+
+/// Reply of the server whose text can be changed, to mimic the phrasing of a
+/// production provider
+///
+/// A text can span several lines, each one being sent with the code, and the
+/// enhanced status code of RFC 3463 for the default texts. The custom texts are
+/// sent as they are, with their own enhanced status codes if any.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    /// 220, the greeting of the server
+    Greeting,
+    /// 250, to HELO and EHLO, the extensions following it for EHLO
+    Hello,
+    /// 250, to MAIL, RCPT, RSET, NOOP and the chunks of BDAT but the last one
+    Ok,
+    /// 250, once a mail is received, `{id}` being replaced by its id
+    Queued,
+    /// 252, to VRFY, as the addresses are not verified
+    CannotVerify,
+    /// 252, to EXPN, as there is no mailing list
+    CannotExpand,
+    /// 354, to DATA
+    StartData,
+    /// 221, to QUIT
+    Closing,
+    /// 214, to HELP
+    Help,
+    /// 235, once the client is authenticated
+    Authenticated,
+    /// 421, while the service is paused
+    Unavailable,
+    /// 421, to a client silent for too long, or connected for too long
+    Timeout,
+    /// 421, to a client over the limits of the connections
+    TooManyConnections,
+    /// 450, to the first attempt to deliver to a recipient, with `--greylist`
+    Greylisted,
+    /// 451, when the mail cannot be kept
+    LocalError,
+    /// 451, to the transactions on a listener with the `always-tempfail`
+    /// profile
+    TemporaryFailure,
+    /// 452, to the recipients over the limit
+    TooManyRecipients,
+    /// 452, to a mail arriving while the queue of the mails is full
+    QueueFull,
+    /// 500, to a line too long
+    LineTooLong,
+    /// 500, to a command or a mail with a line ended by a bare LF, with
+    /// `--strict-crlf`
+    BareLineFeed,
+    /// 501, to invalid arguments
+    SyntaxError,
+    /// 501, to an invalid or cancelled authentication
+    AuthAborted,
+    /// 502, to an unknown command
+    NotImplemented,
+    /// 503, to a command not allowed here
+    BadSequence,
+    /// 504, to an unknown authentication mechanism
+    UnknownMechanism,
+    /// 535, to invalid credentials
+    InvalidCredentials,
+    /// 550, to a recipient rejected by the rules
+    MailboxUnavailable,
+    /// 552, to a mail with more header fields than `--max-headers`
+    TooManyHeaderFields,
+    /// 552, to a mail with a header field larger than `--max-header-length`
+    HeaderFieldTooLong,
+    /// 552, to a mail with a header section larger than `--max-header-bytes`
+    HeaderTooLarge,
+    /// 554, to a client not allowed to connect
+    AccessDenied,
+    /// 554, instead of the greeting, to a client talking before it with
+    /// `--reject-early-talkers`
+    EarlyTalker,
+}
+
+impl Reply {
+    /// Code of the reply
+    pub const fn code(self) -> u16 {
+        match self {
+            Self::Help => 214,
+            Self::Greeting => 220,
+            Self::Closing => 221,
+            Self::Authenticated => 235,
+            Self::Hello | Self::Ok | Self::Queued => 250,
+            Self::CannotVerify | Self::CannotExpand => 252,
+            Self::StartData => 354,
+            Self::Unavailable | Self::Timeout | Self::TooManyConnections => 421,
+            Self::Greylisted => 450,
+            Self::LocalError | Self::TemporaryFailure => 451,
+            Self::TooManyRecipients | Self::QueueFull => 452,
+            Self::LineTooLong | Self::BareLineFeed => 500,
+            Self::SyntaxError | Self::AuthAborted => 501,
+            Self::NotImplemented => 502,
+            Self::BadSequence => 503,
+            Self::UnknownMechanism => 504,
+            Self::InvalidCredentials => 535,
+            Self::MailboxUnavailable => 550,
+            Self::TooManyHeaderFields | Self::HeaderFieldTooLong | Self::HeaderTooLarge => 552,
+            Self::AccessDenied | Self::EarlyTalker => 554,
+        }
+    }
+
+    /// Enhanced status code of the reply, following RFC 2034, that neither the
+    /// greeting, nor the replies to HELO and EHLO, nor the intermediate ones have
+    pub const fn status(self) -> Option<&'static str> {
+        match self {
+            Self::Greeting | Self::Hello | Self::StartData => None,
+            Self::Ok
+            | Self::Queued
+            | Self::CannotVerify
+            | Self::CannotExpand
+            | Self::Closing
+            | Self::Help => Some("2.0.0"),
+            Self::Authenticated => Some("2.7.0"),
+            Self::Unavailable => Some("4.3.2"),
+            Self::Timeout => Some("4.4.2"),
+            Self::TooManyConnections => Some("4.7.0"),
+            Self::Greylisted => Some("4.7.1"),
+            Self::LocalError | Self::TemporaryFailure => Some("4.3.0"),
+            Self::TooManyRecipients => Some("4.5.3"),
+            Self::QueueFull => Some("4.3.1"),
+            Self::LineTooLong | Self::BareLineFeed | Self::AuthAborted => Some("5.5.2"),
+            Self::SyntaxError | Self::UnknownMechanism => Some("5.5.4"),
+            Self::NotImplemented | Self::BadSequence | Self::EarlyTalker => Some("5.5.1"),
+            Self::InvalidCredentials => Some("5.7.8"),
+            Self::MailboxUnavailable => Some("5.1.1"),
+            Self::TooManyHeaderFields | Self::HeaderFieldTooLong | Self::HeaderTooLarge => {
+                Some("5.3.4")
+            }
+            Self::AccessDenied => Some("5.7.1"),
+        }
+    }
+
+    /// Text of the reply, the custom one if there is one in `replies`, otherwise
+    /// the default one with its enhanced status code on each line
+    pub fn text(self, replies: &Replies, server_name: &str) -> String {
+        if let Some(text) = replies.get(&self) {
+            return text.clone();
+        }
+        let text: String = self.default_text(server_name);
+        match self.status() {
+            Some(status) => text
+                .lines()
+                .map(|line| format!("{} {}", status, line))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            None => text,
+        }
+    }
+
+    /// Default text of the reply
+    fn default_text(self, server_name: &str) -> String {
+        match self {
+            Self::Greeting => format!("{} ESMTP", server_name),
+            Self::Hello => server_name.to_owned(),
+            Self::Ok | Self::Queued => "OK".to_owned(),
+            Self::CannotVerify => {
+                "Cannot VRFY user, but will accept message and attempt delivery".to_owned()
+            }
+            Self::CannotExpand => {
+                "Cannot EXPN list, but will accept message and attempt delivery".to_owned()
+            }
+            Self::StartData => "Start mail input; end with <CRLF>.<CRLF>".to_owned(),
+            Self::Closing => format!("{} Service closing transmission channel", server_name),
+            Self::Help => "Commands supported:\n\
+                HELO EHLO AUTH MAIL RCPT DATA BDAT\n\
+                RSET NOOP QUIT VRFY EXPN HELP\n\
+                End of HELP info"
+                .to_owned(),
+            Self::Authenticated => "Authentication successful".to_owned(),
+            Self::Unavailable => format!(
+                "{} Service not available, closing transmission channel",
+                server_name
+            ),
+            Self::Timeout => format!(
+                "{} Timeout exceeded, closing transmission channel",
+                server_name
+            ),
+            Self::TooManyConnections => format!("{} Too many connections", server_name),
+            Self::Greylisted => "Greylisted, try again later".to_owned(),
+            Self::LocalError => "Requested action aborted: local error in processing".to_owned(),
+            Self::TemporaryFailure => "Temporary failure, try again later".to_owned(),
+            Self::TooManyRecipients => "Too many recipients".to_owned(),
+            Self::QueueFull => "Insufficient system storage".to_owned(),
+            Self::LineTooLong => "Line too long.".to_owned(),
+            Self::BareLineFeed => "Bare <LF> received, lines must end with <CRLF>".to_owned(),
+            Self::SyntaxError => "Syntax error in parameters or arguments".to_owned(),
+            Self::AuthAborted => "Authentication aborted".to_owned(),
+            Self::NotImplemented => "Command not implemented".to_owned(),
+            Self::BadSequence => "Bad sequence of commands".to_owned(),
+            Self::UnknownMechanism => "Unrecognized authentication type".to_owned(),
+            Self::InvalidCredentials => "Authentication credentials invalid".to_owned(),
+            Self::MailboxUnavailable => {
+                "Requested action not taken: mailbox unavailable".to_owned()
+            }
+            Self::TooManyHeaderFields => "Too many header fields".to_owned(),
+            Self::HeaderFieldTooLong => "Header field too long".to_owned(),
+            Self::HeaderTooLarge => "Header section too large".to_owned(),
+            Self::AccessDenied => "Access denied".to_owned(),
+            Self::EarlyTalker => {
+                format!(
+                    "{} Protocol error, talking before the greeting",
+                    server_name
+                )
+            }
+        }
+    }
+
+    /// Reply to send, with the custom text if there is one in `replies`
+    pub fn message(self, replies: &Replies, server_name: &str) -> String {
+        format(self.code(), self.text(replies, server_name).lines())
+    }
+}
+
+/// Format the lines of a reply: the code is followed by a dash on each line
+/// but the last one, where it is followed by a space
+pub fn format<'a, I: Iterator<Item = &'a str>>(code: u16, lines: I) -> String {
+    let mut lines: Peekable<I> = lines.peekable();
+    let code: String = code.to_string();
+    let mut message: String = String::new();
+    while let Some(line) = lines.next() {
+        message.push_str(&code);
+        message.push(if lines.peek().is_some() { '-' } else { ' ' });
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use tide::prelude::json;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn custom_replies() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let replies: Replies = serde_json::from_value(json!({
+            "greeting": "mx.example.com ESMTP ready",
+            "queued": "2.0.0 OK {id}\nqueued for delivery",
+        }))?;
+        assert_eq!(
+            Reply::Greeting.message(&replies, "Catcher"),
+            "220 mx.example.com ESMTP ready\r\n"
+        );
+        assert_eq!(
+            Reply::Queued.message(&replies, "Catcher"),
+            "250-2.0.0 OK {id}\r\n250 queued for delivery\r\n"
+        );
+        assert_eq!(
+            Reply::Closing.message(&replies, "Catcher"),
+            "221 2.0.0 Catcher Service closing transmission channel\r\n"
+        );
+        assert_eq!(
+            Reply::Help.message(&replies, "Catcher"),
+            "214-2.0.0 Commands supported:\r\n\
+             214-2.0.0 HELO EHLO AUTH MAIL RCPT DATA BDAT\r\n\
+             214-2.0.0 RSET NOOP QUIT VRFY EXPN HELP\r\n\
+             214 2.0.0 End of HELP info\r\n"
+        );
+        assert!(serde_json::from_value::<Replies>(json!({"unknown": "Text"})).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+use glob::{MatchOptions, Pattern};
+use regex::{Regex, RegexBuilder};
+use tide::prelude::{Deserialize, Serialize};
+
+/// Options of the glob patterns, the addresses being case insensitive
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// What happens to the recipients matched by a rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// The recipient is accepted, and the mail is caught for it
+    Accept,
+    /// The recipient is refused with a 550 reply, like an unknown mailbox
+    Reject,
+}
+
+/// Pattern matched against the address of a recipient
+#[derive(Clone, Debug)]
+enum Matcher {
+    /// Glob pattern, like `*@bounce.test`, matching the whole address
+    Glob(Pattern),
+    /// Regular expression between slashes, like `/^user[0-9]+@/`, matching a
+    /// part of the address unless it is anchored
+    Regex(Box<Regex>),
+}
+
+/// Rule accepting or rejecting the recipients, given as `action:pattern`, like
+/// `reject:*@bounce.test` or `accept:/^vip\./`
+///
+/// The patterns are case insensitive. The rules are compared by their text.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct RecipientRule {
+    /// What happens to the recipients matched
+    action: Action,
+    /// Pattern, as it was given
+    pattern: String,
+    /// Pattern, ready to match the addresses
+    matcher: Matcher,
+}
+
+impl RecipientRule {
+    /// Action on the recipient, if the rule matches it
+    pub fn action_on(&self, recipient: &str) -> Option<Action> {
+        let matches: bool = match self.matcher {
+            Matcher::Glob(ref glob) => glob.matches_with(recipient, GLOB_OPTIONS),
+            Matcher::Regex(ref regex) => regex.is_match(recipient),
+        };
+        Some(self.action).filter(|_| matches)
+    }
+}
+
+impl PartialEq for RecipientRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.action == other.action && self.pattern == other.pattern
+    }
+}
+
+impl Eq for RecipientRule {}
+
+impl FromStr for RecipientRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (action, pattern): (&str, &str) = s.split_once(':').ok_or_else(|| {
+            format!(
+                "The rule must be \"accept:pattern\" or \"reject:pattern\": {}",
+                s
+            )
+        })?;
+        let action: Action = match action.trim().to_lowercase().as_str() {
+            "accept" => Action::Accept,
+            "reject" => Action::Reject,
+            _ => {
+                return Err(format!(
+                    "Unknown action {}, expected accept or reject",
+                    action
+                ))
+            }
+        };
+        let pattern: &str = pattern.trim();
+        if pattern.is_empty() {
+            return Err(format!("The rule has no pattern: {}", s));
+        }
+
+        let regex: Option<&str> = pattern
+            .strip_prefix('/')
+            .and_then(|regex| regex.strip_suffix('/'));
+        let matcher: Matcher = if let Some(regex) = regex {
+            Matcher::Regex(Box::new(
+                RegexBuilder::new(regex)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| format!("Invalid pattern {}: {}", pattern, e))?,
+            ))
+        } else {
+            Matcher::Glob(
+                Pattern::new(pattern).map_err(|e| format!("Invalid pattern {}: {}", pattern, e))?,
+            )
+        };
+
+        Ok(Self {
+            action,
+            pattern: pattern.to_owned(),
+            matcher,
+        })
+    }
+}
+
+impl fmt::Display for RecipientRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action: &str = match self.action {
+            Action::Accept => "accept",
+            Action::Reject => "reject",
+        };
+        write!(f, "{}:{}", action, self.pattern)
+    }
+}
+
+impl From<RecipientRule> for String {
+    #[inline]
+    fn from(rule: RecipientRule) -> Self {
+        rule.to_string()
+    }
+}
+
+impl TryFrom<String> for RecipientRule {
+    type Error = String;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn rules() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let glob: RecipientRule = "reject:*@Bounce.test".parse()?;
+        assert_eq!(glob.action_on("user@bounce.test"), Some(Action::Reject));
+        assert_eq!(glob.action_on("user@bounce.test.org"), None);
+        assert_eq!(glob.to_string(), "reject:*@Bounce.test");
+
+        let regex: RecipientRule = "Accept: /^vip[0-9]+\\./ ".parse()?;
+        assert_eq!(
+            regex.action_on("VIP12.user@example.com"),
+            Some(Action::Accept)
+        );
+        assert_eq!(regex.action_on("user.vip1.@example.com"), None);
+        assert_eq!(regex.to_string(), "accept:/^vip[0-9]+\\./");
+
+        for invalid in &[
+            "*@bounce.test",
+            "drop:*",
+            "reject:",
+            "reject:/(/",
+            "reject:[a",
+        ] {
+            assert!(invalid.parse::<RecipientRule>().is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+}
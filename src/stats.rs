@@ -0,0 +1,133 @@
+use tide::{http::Method, prelude::Deserialize};
+
+use crate::{
+    error::MailcatcherError,
+    utils::{local_request, Output},
+};
+
+/// Statistics of the instance, as given by the `/api/stats` route, with the
+/// fields printed as text
+#[derive(Debug, Deserialize)]
+struct Stats {
+    /// Number of mails
+    mails: usize,
+    /// Total size of the mails
+    size: usize,
+    /// Delivery latency of the mails
+    latency: Latency,
+    /// Browsers connected by SSE
+    sse: Browsers,
+}
+
+/// Delivery latency of the mails, in milliseconds
+#[derive(Debug, Deserialize)]
+struct Latency {
+    /// Number of mails with a latency
+    count: usize,
+    /// Shortest latency
+    min: Option<i64>,
+    /// Longest latency
+    max: Option<i64>,
+    /// Average latency
+    mean: Option<i64>,
+}
+
+/// Browsers connected by SSE
+#[derive(Debug, Deserialize)]
+struct Browsers {
+    /// Number of browsers connected
+    clients: usize,
+}
+
+/// Print the statistics of the instance listening on the HTTP `port`, with the
+/// `GET /api/stats` route
+///
+/// With the JSON `output`, the document of the route is printed as is.
+#[allow(clippy::print_stdout)]
+pub async fn stats(port: u16, output: Output) -> crate::Result<()> {
+    let document: serde_json::Value = local_request(port, Method::Get, "/api/stats", None)
+        .await?
+        .body_json()
+        .await
+        .map_err(|e| MailcatcherError::http(e.to_string()))?;
+
+    println!("{}", render(document, output)?);
+
+    Ok(())
+}
+
+/// Statistics printed in the `output` format
+fn render(document: serde_json::Value, output: Output) -> crate::Result<String> {
+    match output {
+        Output::Text => serde_json::from_value(document)
+            .map(|stats: Stats| text(&stats))
+            .map_err(MailcatcherError::http),
+        Output::Json => Ok(document.to_string()),
+    }
+}
+
+/// Statistics readable by a human, one per line
+fn text(stats: &Stats) -> String {
+    let latency: String = match (stats.latency.min, stats.latency.mean, stats.latency.max) {
+        (Some(min), Some(mean), Some(max)) => format!(
+            "{} ms min, {} ms mean, {} ms max, over {} mails",
+            min, mean, max, stats.latency.count
+        ),
+        (None, _, _) | (_, None, _) | (_, _, None) => "unknown".to_owned(),
+    };
+    format!(
+        "Mails: {}\nSize: {} bytes\nLatency: {}\nBrowsers: {}",
+        stats.mails, stats.size, latency, stats.sse.clients
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tide::prelude::json;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn rendered_stats() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let document: serde_json::Value = json!({
+            "mails": 2,
+            "size": 300,
+            "latency": {"count": 1, "min": 1500, "max": 1500, "mean": 1500},
+            "content_types": {
+                "text_only": 1,
+                "html_only": 1,
+                "text_and_html": 0,
+                "multipart": 0,
+                "with_attachments": 0,
+            },
+            "sse": {"clients": 0, "connections": []},
+        });
+        assert_eq!(
+            render(document.clone(), Output::Text)?,
+            "Mails: 2\nSize: 300 bytes\n\
+Latency: 1500 ms min, 1500 ms mean, 1500 ms max, over 1 mails\nBrowsers: 0"
+        );
+        // The document of the instance, as is
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&render(document.clone(), Output::Json)?)?,
+            document
+        );
+
+        let empty: serde_json::Value = json!({
+            "mails": 0,
+            "size": 0,
+            "latency": {"count": 0, "min": null, "max": null, "mean": null},
+            "sse": {"clients": 1, "connections": []},
+        });
+        assert_eq!(
+            render(empty, Output::Text)?,
+            "Mails: 0\nSize: 0 bytes\nLatency: unknown\nBrowsers: 1"
+        );
+        assert!(render(json!({"mails": "many"}), Output::Text).is_err());
+
+        Ok(())
+    }
+}
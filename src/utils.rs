@@ -1,10 +1,24 @@
 use core::future::Future;
 use std::{
+    convert::TryFrom,
     fmt, io,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
     time::{Duration, Instant},
 };
 
-use async_std::{net::SocketAddr, task};
+use async_std::{
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    task,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
+use tide::{
+    http::{Body, Method, Request, Response, Url},
+    prelude::{Deserialize, Serialize},
+};
+
+use crate::error::MailcatcherError;
 
 /// Spawn a new async task, waiting it completion,
 /// it display it's status at the end: Success or Error
@@ -24,7 +38,7 @@ where
 async fn log_errors<F, T, E>(task_name: String, fut: F) -> Option<T>
 where
     F: Future<Output = Result<T, E>> + Send,
-    E: std::fmt::Display,
+    E: fmt::Display,
 {
     match fut.await {
         Ok(r) => {
@@ -61,7 +75,7 @@ impl ConnectionInfo {
 
     /// Get connection duration
     pub fn get_duration(&self) -> Duration {
-        Instant::now() - self.connected_at
+        self.connected_at.elapsed()
     }
 }
 
@@ -91,3 +105,528 @@ impl fmt::Display for ConnectionInfo {
         )
     }
 }
+
+/// IP versions of the addresses to listen on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// Both IPv4 and IPv6
+    Any,
+    /// Only IPv4
+    V4,
+    /// Only IPv6
+    V6,
+}
+
+impl AddressFamily {
+    /// Check if the address is of this family
+    pub const fn accepts(self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Any => true,
+            Self::V4 => addr.is_ipv4(),
+            Self::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Self::Any => "IP",
+            Self::V4 => "IPv4",
+            Self::V6 => "IPv6",
+        })
+    }
+}
+
+/// Format of the results printed by the commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Readable by a human
+    Text,
+    /// A JSON document, to be parsed by scripts
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("text") {
+            Ok(Self::Text)
+        } else if s.eq_ignore_ascii_case("json") {
+            Ok(Self::Json)
+        } else {
+            Err(format!("Unknown output {}, expected text or json", s))
+        }
+    }
+}
+
+/// Resolve the local addresses to listen on the `port`, keeping only those of the
+/// `family`
+///
+/// It fails if the family has no address, as nothing could be listened then.
+pub async fn local_addresses(port: u16, family: AddressFamily) -> crate::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = ("localhost", port)
+        .to_socket_addrs()
+        .await
+        .map_err(MailcatcherError::config)?
+        .filter(|addr| family.accepts(addr))
+        .collect();
+    if addrs.is_empty() {
+        return Err(MailcatcherError::config(format!(
+            "No {} address for localhost",
+            family
+        )));
+    }
+
+    Ok(addrs)
+}
+
+/// Address that could not be bound
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BindFailure {
+    /// Address that was tried
+    pub addr: SocketAddr,
+    /// Reason of the failure
+    pub error: String,
+}
+
+impl fmt::Display for BindFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.addr, self.error)
+    }
+}
+
+/// Listeners bound on a port, with the addresses of this port that failed
+#[derive(Debug)]
+pub struct Bound {
+    /// Bound listeners, there is one at least
+    pub listeners: Vec<TcpListener>,
+    /// Addresses of the port that could not be bound
+    pub failures: Vec<BindFailure>,
+}
+
+impl Bound {
+    /// Retrieve the addresses of the bound listeners
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
+}
+
+/// Bind the local addresses of the first port that is available, from `port`
+/// to `port + fallback`
+///
+/// A port is available if one of its addresses at least can be bound, the others
+/// are returned with the listeners, for the caller to decide if it can run
+/// without them. The `name` of the server is used in the logs.
+pub async fn bind_port(
+    name: &str,
+    port: u16,
+    family: AddressFamily,
+    fallback: u16,
+) -> crate::Result<Bound> {
+    for candidate in (0..=fallback).filter_map(|offset| port.checked_add(offset)) {
+        let mut listeners: Vec<TcpListener> = Vec::new();
+        let mut failures: Vec<BindFailure> = Vec::new();
+        // An ephemeral port is chosen by the first address bound, then shared
+        // with the others
+        let mut shared: u16 = candidate;
+        for addr in local_addresses(candidate, family).await? {
+            let addr: SocketAddr = SocketAddr::new(addr.ip(), shared);
+            match TcpListener::bind(addr).await {
+                Ok(listener) => {
+                    if shared == 0 {
+                        shared = listener.local_addr().map_or(0, |local| local.port());
+                    }
+                    listeners.push(listener);
+                }
+                Err(e) => {
+                    log::warn!("{} unable to bind {}: {}", name, addr, e);
+                    failures.push(BindFailure {
+                        addr,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        if !listeners.is_empty() {
+            if candidate != port {
+                log::warn!(
+                    "{} port {} is not available, using {}",
+                    name,
+                    port,
+                    candidate
+                );
+            }
+            return Ok(Bound {
+                listeners,
+                failures,
+            });
+        }
+    }
+
+    Err(MailcatcherError::config(if fallback == 0 {
+        format!("{} port {} is not available", name, port)
+    } else {
+        format!(
+            "{} ports {} to {} are not available",
+            name,
+            port,
+            port.saturating_add(fallback)
+        )
+    }))
+}
+
+/// Send a request to the instance listening on the HTTP `port` of localhost,
+/// failing if its response is not a success
+pub async fn local_request(
+    port: u16,
+    method: Method,
+    path: &str,
+    body: Option<Body>,
+) -> crate::Result<Response> {
+    let url: Url = Url::parse(&format!("http://localhost:{}{}", port, path))
+        .map_err(MailcatcherError::config)?;
+    let mut request: Request = Request::new(method, url);
+    if let Some(body) = body {
+        request.set_body(body);
+    }
+
+    let stream: TcpStream = TcpStream::connect(("localhost", port))
+        .await
+        .map_err(MailcatcherError::http)?;
+    let mut response: Response = async_h1::connect(stream, request)
+        .await
+        .map_err(|e| MailcatcherError::http(e.to_string()))?;
+
+    if !response.status().is_success() {
+        let message: String = response.body_string().await.unwrap_or_default();
+        return Err(MailcatcherError::http(format!(
+            "{}: {}",
+            response.status(),
+            message
+        )));
+    }
+    Ok(response)
+}
+
+/// Timezone used to format the dates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Timezone {
+    /// Timezone from the tz database, like `Europe/Paris`
+    Named(Tz),
+    /// Fixed offset from UTC, like `+02:00`
+    Offset(FixedOffset),
+}
+
+impl Timezone {
+    /// Format the date in this timezone, in RFC 3339 format
+    pub fn format(self, date: DateTime<Utc>) -> String {
+        match self {
+            Self::Named(tz) => date.with_timezone(&tz).to_rfc3339(),
+            Self::Offset(offset) => date.with_timezone(&offset).to_rfc3339(),
+        }
+    }
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Self::Named(Tz::UTC)
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    /// Parse a timezone name, or an offset like `+02:00`, `-0530` or `+01`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utc") || s.eq_ignore_ascii_case("z") {
+            return Ok(Self::default());
+        }
+        if let Ok(tz) = s.parse::<Tz>() {
+            return Ok(Self::Named(tz));
+        }
+
+        // Not a name, so it must be an offset
+        let invalid = || format!("Invalid timezone: {}", s);
+        let (sign, offset): (i32, &str) = if let Some(offset) = s.strip_prefix('+') {
+            (1, offset)
+        } else if let Some(offset) = s.strip_prefix('-') {
+            (-1, offset)
+        } else {
+            return Err(invalid());
+        };
+        let digits: String = offset.replace(':', "");
+        let (hours, minutes): (&str, &str) = match digits.len() {
+            2 => (&digits, "0"),
+            4 => digits.split_at(2),
+            _ => return Err(invalid()),
+        };
+        let hours: i32 = hours.parse().map_err(|_e| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_e| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        hours
+            .checked_mul(3600)
+            .zip(minutes.checked_mul(60))
+            .and_then(|(hours, minutes)| hours.checked_add(minutes))
+            .and_then(|seconds| seconds.checked_mul(sign))
+            .and_then(FixedOffset::east_opt)
+            .map(Self::Offset)
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Named(tz) => f.write_str(tz.name()),
+            Self::Offset(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+impl From<Timezone> for String {
+    #[inline]
+    fn from(timezone: Timezone) -> Self {
+        timezone.to_string()
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(timezone: String) -> Result<Self, Self::Error> {
+        timezone.parse()
+    }
+}
+
+/// Range of IP addresses, like `192.168.0.0/16` or `fd00::/8`
+///
+/// A single address, without the prefix length, is a range of one address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Cidr {
+    /// First address of the range
+    addr: IpAddr,
+    /// Number of leading bits shared by the addresses of the range
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Check if the address is in the range, an IPv4-mapped IPv6 address being
+    /// the IPv4 address
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, unmap(addr)) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => {
+                let mask: u32 = u32::MAX
+                    .checked_shl(32_u32.saturating_sub(u32::from(self.prefix)))
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                let mask: u128 = u128::MAX
+                    .checked_shl(128_u32.saturating_sub(u32::from(self.prefix)))
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(addr) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+/// Convert an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) to its IPv4 address
+fn unmap(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => IpAddr::V4(Ipv4Addr::from(
+                u32::from(high).checked_shl(16).unwrap_or(0) | u32::from(low),
+            )),
+            _ => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parse an address followed by the prefix length, or a single address
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid CIDR: {}", s);
+        let mut parts = s.splitn(2, '/');
+        let addr: &str = parts.next().unwrap_or_default();
+        let prefix: Option<&str> = parts.next();
+        let addr: IpAddr = unmap(addr.trim().parse().map_err(|_e| invalid())?);
+        let max: u8 = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix: u8 = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_e| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl From<Cidr> for String {
+    #[inline]
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(cidr: String) -> Result<Self, Self::Error> {
+        cidr.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn timezone_parsing_and_format() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        let date: DateTime<Utc> = Utc.ymd(2020, 11, 22).and_hms(0, 58, 23);
+
+        assert_eq!(
+            Timezone::default().format(date),
+            "2020-11-22T00:58:23+00:00"
+        );
+        assert_eq!(
+            "utc".parse::<Timezone>()?.format(date),
+            "2020-11-22T00:58:23+00:00"
+        );
+        assert_eq!(
+            "Europe/Paris".parse::<Timezone>()?.format(date),
+            "2020-11-22T01:58:23+01:00"
+        );
+        assert_eq!(
+            "-05:30".parse::<Timezone>()?.format(date),
+            "2020-11-21T19:28:23-05:30"
+        );
+        assert_eq!(
+            "+0200".parse::<Timezone>()?.format(date),
+            "2020-11-22T02:58:23+02:00"
+        );
+        assert_eq!(
+            "+09".parse::<Timezone>()?.format(date),
+            "2020-11-22T09:58:23+09:00"
+        );
+
+        assert!("Mars/Olympus".parse::<Timezone>().is_err());
+        assert!("+25:00".parse::<Timezone>().is_err());
+        assert!("0200".parse::<Timezone>().is_err());
+
+        // Written back as it can be parsed
+        for timezone in &["UTC", "Europe/Paris", "-05:30"] {
+            assert_eq!(timezone.parse::<Timezone>()?.to_string(), *timezone);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn address_family() -> crate::Result<()> {
+        crate::test::log_init();
+
+        let all: Vec<SocketAddr> = task::block_on(local_addresses(1025, AddressFamily::Any))?;
+        for family in &[AddressFamily::V4, AddressFamily::V6] {
+            let expected: Vec<SocketAddr> = all
+                .iter()
+                .copied()
+                .filter(|addr| family.accepts(addr))
+                .collect();
+            match task::block_on(local_addresses(1025, *family)) {
+                Ok(addrs) => assert_eq!(addrs, expected),
+                Err(_) => assert!(expected.is_empty()),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn port_fallback() -> crate::test::Result<()> {
+        crate::test::log_init();
+
+        task::block_on(async {
+            // A port already in use
+            let busy: TcpListener = TcpListener::bind("127.0.0.1:0").await?;
+            let port: u16 = busy.local_addr()?.port();
+
+            let unavailable: crate::Result<Bound> =
+                bind_port("Test", port, AddressFamily::V4, 0).await;
+            assert_eq!(unavailable.map_err(|e| e.exit_code()).err(), Some(78));
+
+            let bound: Bound = bind_port("Test", port, AddressFamily::V4, 5).await?;
+            assert!(!bound.listeners.is_empty());
+            assert!(bound.failures.is_empty());
+            for listener in &bound.listeners {
+                let fallback: u16 = listener.local_addr()?.port();
+                assert!(fallback > port && fallback <= port.saturating_add(5));
+            }
+
+            // An ephemeral port, shared by all the addresses
+            let bound: Bound = bind_port("Test", 0, AddressFamily::Any, 0).await?;
+            let ports: Vec<u16> = bound.addresses().iter().map(SocketAddr::port).collect();
+            assert!(ports.first().map_or(false, |&first| first != 0));
+            assert!(ports.windows(2).all(|pair| pair.first() == pair.last()));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[allow(clippy::panic_in_result_fn)]
+    fn cidr() -> Result<(), String> {
+        let ip = |addr: &str| addr.parse::<IpAddr>().map_err(|e| e.to_string());
+
+        let lan: Cidr = "192.168.0.0/16".parse()?;
+        assert!(lan.contains(ip("192.168.1.20")?));
+        assert!(lan.contains(ip("::ffff:192.168.1.20")?));
+        assert!(!lan.contains(ip("192.169.0.1")?));
+        assert!(!lan.contains(ip("fd00::1")?));
+
+        let ula: Cidr = "fd00::/8".parse()?;
+        assert!(ula.contains(ip("fd12:3456::1")?));
+        assert!(!ula.contains(ip("fe80::1")?));
+        assert!(!ula.contains(ip("10.0.0.1")?));
+
+        let all: Cidr = "0.0.0.0/0".parse()?;
+        assert!(all.contains(ip("8.8.8.8")?));
+        let localhost: Cidr = "::1".parse()?;
+        assert!(localhost.contains(ip("::1")?));
+        assert!(!localhost.contains(ip("0.0.0.1")?));
+        assert_eq!(localhost.to_string(), "::1/128");
+
+        for invalid in &["10.0.0.0/33", "::/129", "localhost/8", "10.0.0.0/x"] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+}